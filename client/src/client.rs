@@ -0,0 +1,565 @@
+//! 客户端：通过连接池执行命令, 类型化的方法与 `KVDB` 的方法一一对应。
+
+use crate::error;
+use crate::pipeline::Pipeline;
+use crate::pool::Pool;
+use crate::resp::Value;
+use dbcore::{DBError, DBOk, Result, ValueType};
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// 客户端的配置
+#[derive(Debug, Clone)]
+pub struct ClientConfig {
+    /// 连接池中最多同时打开的连接数
+    pub pool_size: usize,
+    /// 每个连接使用的数据库编号
+    pub db: usize,
+    /// 建立连接的超时时间
+    pub connect_timeout: Duration,
+    /// 连接失败之后的重试次数
+    pub reconnect_attempts: u32,
+    /// 第 n 次重试之前等待 n 倍的该时间
+    pub reconnect_delay: Duration,
+}
+
+impl Default for ClientConfig {
+    fn default() -> Self {
+        ClientConfig {
+            pool_size: 8,
+            db: 0,
+            connect_timeout: Duration::from_secs(5),
+            reconnect_attempts: 3,
+            reconnect_delay: Duration::from_millis(100),
+        }
+    }
+}
+
+/// memkv 服务的客户端, 可以复制, 复制出的客户端共享同一个连接池
+#[derive(Clone)]
+pub struct Client {
+    pool: Arc<Pool>,
+}
+
+/// internal：由字符串参数组成的命令
+fn command(args: &[&str]) -> Vec<Vec<u8>> {
+    args.iter().map(|arg| arg.as_bytes().to_vec()).collect()
+}
+
+/// internal：在命令之后追加参数
+fn with_args(mut args: Vec<Vec<u8>>, more: &[String]) -> Vec<Vec<u8>> {
+    args.extend(more.iter().map(|arg| arg.as_bytes().to_vec()));
+    args
+}
+
+fn unexpected(value: &Value) -> DBError {
+    DBError::InvalidPayload(format!("unexpected reply {:?}", value))
+}
+
+fn status(value: Value) -> Result<DBOk> {
+    match value {
+        Value::Status(_) => Ok(DBOk::Ok),
+        Value::Nil => Ok(DBOk::Nil),
+        other => Err(unexpected(&other)),
+    }
+}
+
+fn string(value: Value) -> Result<Option<String>> {
+    match value {
+        Value::Nil => Ok(None),
+        Value::Status(text) => Ok(Some(text)),
+        Value::Bulk(bytes) => String::from_utf8(bytes)
+            .map(Some)
+            .map_err(|_| DBError::InvalidPayload(String::from("reply is not valid UTF-8"))),
+        other => Err(unexpected(&other)),
+    }
+}
+
+fn integer(value: Value) -> Result<i64> {
+    match value {
+        Value::Integer(n) => Ok(n),
+        other => Err(unexpected(&other)),
+    }
+}
+
+fn items(value: Value) -> Result<Vec<Value>> {
+    match value {
+        Value::Array(items) => Ok(items),
+        Value::Nil => Ok(Vec::new()),
+        Value::Map(pairs) => Ok(pairs.into_iter().flat_map(|(k, v)| vec![k, v]).collect()),
+        other => Err(unexpected(&other)),
+    }
+}
+
+fn optional_strings(value: Value) -> Result<Vec<Option<String>>> {
+    items(value)?.into_iter().map(string).collect()
+}
+
+fn strings(value: Value) -> Result<Vec<String>> {
+    let values = optional_strings(value)?;
+    Ok(values.into_iter().flatten().collect())
+}
+
+fn pairs(values: Vec<String>) -> Vec<(String, String)> {
+    values
+        .chunks(2)
+        .filter(|pair| pair.len() == 2)
+        .map(|pair| (pair[0].clone(), pair[1].clone()))
+        .collect()
+}
+
+/// internal：SCAN 类命令的回复 [cursor, [item ...]]
+fn scan_reply(value: Value) -> Result<(u64, Vec<String>)> {
+    let mut parts = items(value)?.into_iter();
+    match (parts.next(), parts.next()) {
+        (Some(cursor), Some(values)) => {
+            let cursor = string(cursor)?.and_then(|cursor| cursor.parse().ok());
+            let cursor =
+                cursor.ok_or_else(|| DBError::InvalidPayload(String::from("invalid cursor")))?;
+            Ok((cursor, strings(values)?))
+        }
+        _ => Err(DBError::InvalidPayload(String::from("invalid scan reply"))),
+    }
+}
+
+/// internal：SCAN 类命令的 MATCH 与 COUNT 参数
+fn scan_args(pattern: Option<&str>, count: usize) -> Vec<String> {
+    let mut args = vec![String::from("COUNT"), count.max(1).to_string()];
+    if let Some(pattern) = pattern {
+        args.push(String::from("MATCH"));
+        args.push(String::from(pattern));
+    }
+    args
+}
+
+impl Client {
+    ///
+    /// 连接 addr（`host:port`）上的服务, 建立第一个连接以确认服务可用
+    ///
+    /// 返回值：
+    ///     * 客户端
+    ///     * 无法连接或者无法选择配置的数据库， 返回对应的错误
+    pub async fn connect(addr: &str, config: ClientConfig) -> Result<Client> {
+        let client = Client {
+            pool: Arc::new(Pool::new(String::from(addr), config)),
+        };
+        client.ping().await?;
+        Ok(client)
+    }
+
+    /// 服务的地址
+    pub fn addr(&self) -> &str {
+        self.pool.addr()
+    }
+
+    ///
+    /// internal：在同一个连接上依次执行 commands
+    ///
+    /// 复用的空闲连接在收到任何回复之前失败时（通常是服务端重启或关闭了空闲连接）, 丢弃所有空闲连接,
+    /// 在新的连接上重新执行；已经收到部分回复时不重试, 避免命令被执行两次。
+    ///
+    /// 返回值：
+    ///     * 与 commands 一一对应的结果
+    ///     * 无法连接或读写失败， 返回 Io
+    pub(crate) async fn run(&self, commands: &[Vec<Vec<u8>>]) -> Result<Vec<Result<Value>>> {
+        loop {
+            let mut conn = self.pool.get().await?;
+            match conn.pipeline(commands).await {
+                Ok(replies) => {
+                    let mut results = Vec::with_capacity(replies.len());
+                    for (args, reply) in commands.iter().zip(replies) {
+                        let message = match reply {
+                            Value::Error(message) => message,
+                            value => {
+                                results.push(Ok(value));
+                                continue;
+                            }
+                        };
+                        // 错误回复中没有 key 的实际类型, 需要额外查询
+                        let mut actual = None;
+                        if message.starts_with("WRONGTYPE") {
+                            let (_, key) = error::command_and_key(args);
+                            let query = [command(&["TYPE", &key])];
+                            if let Ok(mut types) = conn.pipeline(&query).await {
+                                actual = types
+                                    .pop()
+                                    .and_then(|t| t.as_str().and_then(ValueType::from_name));
+                            }
+                        }
+                        results.push(Err(error::from_redis_error(args, &message, actual)));
+                    }
+                    return Ok(results);
+                }
+                Err(failure) if conn.reused && !failure.replied => self.pool.clear(),
+                Err(failure) => return Err(failure.error),
+            }
+        }
+    }
+
+    /// internal：执行一条命令
+    async fn call(&self, args: Vec<Vec<u8>>) -> Result<Value> {
+        let mut results = self.run(std::slice::from_ref(&args)).await?;
+        results
+            .pop()
+            .unwrap_or_else(|| Err(DBError::InvalidPayload(String::from("missing reply"))))
+    }
+
+    ///
+    /// 执行任意命令, 用于没有对应方法的命令
+    ///
+    /// 返回值：
+    ///     * 命令的回复
+    ///     * 服务端返回错误时转换为对应的 `DBError`
+    pub async fn execute<I, A>(&self, args: I) -> Result<Value>
+    where
+        I: IntoIterator<Item = A>,
+        A: AsRef<[u8]>,
+    {
+        self.call(args.into_iter().map(|arg| arg.as_ref().to_vec()).collect())
+            .await
+    }
+
+    /// 创建 pipeline, 其中的命令在同一个连接上一次发送
+    pub fn pipeline(&self) -> Pipeline {
+        Pipeline::new(self.clone())
+    }
+
+    /// 检查服务是否可用
+    pub async fn ping(&self) -> Result<()> {
+        self.call(command(&["PING"])).await.map(|_| ())
+    }
+
+    ///
+    /// 参数说明：
+    ///     * not_exists 只有在key不存在时，才插入
+    ///     * already_exists 只有在key已经存在时，才插入
+    ///     * expire key 的生存时间，单位为秒
+    ///
+    /// 返回值：
+    ///     * 只在设置操作成功完成时才返回 OK, 条件不满足时返回 Nil
+    pub async fn set(
+        &self,
+        key: &str,
+        value: &str,
+        not_exists: bool,
+        already_exists: bool,
+        expire: Option<u64>,
+    ) -> Result<DBOk> {
+        let mut args = vec![String::from("SET"), String::from(key), String::from(value)];
+        if not_exists {
+            args.push(String::from("NX"));
+        }
+        if already_exists {
+            args.push(String::from("XX"));
+        }
+        if let Some(seconds) = expire {
+            args.push(String::from("EX"));
+            args.push(seconds.to_string());
+        }
+        status(self.call(with_args(Vec::new(), &args)).await?)
+    }
+
+    /// 等同于 `set(key, value, false, false, None)`
+    pub async fn sets(&self, key: &str, value: &str) -> Result<DBOk> {
+        self.set(key, value, false, false, None).await
+    }
+
+    pub async fn get(&self, key: &str) -> Result<Option<String>> {
+        string(self.call(command(&["GET", key])).await?)
+    }
+
+    /// 返回值：新增的成员数量
+    pub async fn sadd(&self, key: &str, members: Vec<String>) -> Result<usize> {
+        let added = integer(
+            self.call(with_args(command(&["SADD", key]), &members))
+                .await?,
+        )?;
+        Ok(added as usize)
+    }
+
+    ///
+    /// 随机返回 count 个成员, 与 `SRANDMEMBER` 相同, 成员不会被删除
+    ///
+    /// 返回值：key 不存在时返回 None
+    pub async fn srandmember(&self, key: &str, count: usize) -> Result<Option<HashSet<String>>> {
+        let members = strings(
+            self.call(command(&["SRANDMEMBER", key, &count.to_string()]))
+                .await?,
+        )?;
+        if members.is_empty() && !self.exists(key).await? {
+            return Ok(None);
+        }
+        Ok(Some(members.into_iter().collect()))
+    }
+
+    /// 随机删除并返回一个成员, key 不存在时返回 None
+    pub async fn spop(&self, key: &str) -> Result<Option<String>> {
+        string(self.call(command(&["SPOP", key])).await?)
+    }
+
+    /// key 不存在时返回 false
+    pub async fn sismember(&self, key: &str, member: &str) -> Result<bool> {
+        Ok(integer(self.call(command(&["SISMEMBER", key, member])).await?)? == 1)
+    }
+
+    /// 返回值：删除的成员数量
+    pub async fn srem(&self, key: &str, members: Vec<String>) -> Result<usize> {
+        let removed = integer(
+            self.call(with_args(command(&["SREM", key]), &members))
+                .await?,
+        )?;
+        Ok(removed as usize)
+    }
+
+    /// 集合的成员数量, key 不存在时返回 0
+    pub async fn slen(&self, key: &str) -> Result<usize> {
+        Ok(integer(self.call(command(&["SCARD", key])).await?)? as usize)
+    }
+
+    /// key 不存在时返回空集合
+    pub async fn smembers(&self, key: &str) -> Result<HashSet<String>> {
+        let members = strings(self.call(command(&["SMEMBERS", key])).await?)?;
+        Ok(members.into_iter().collect())
+    }
+
+    /// 返回值：field 是新增的返回 1, 否则返回 0
+    pub async fn hset(&self, key: &str, field: &str, value: &str) -> Result<u32> {
+        Ok(integer(self.call(command(&["HSET", key, field, value])).await?)? as u32)
+    }
+
+    pub async fn hget(&self, key: &str, field: &str) -> Result<Option<String>> {
+        string(self.call(command(&["HGET", key, field])).await?)
+    }
+
+    pub async fn hmset(&self, key: &str, pairs: Vec<(String, String)>) -> Result<DBOk> {
+        let args: Vec<String> = pairs
+            .into_iter()
+            .flat_map(|(field, value)| vec![field, value])
+            .collect();
+        status(
+            self.call(with_args(command(&["HMSET", key]), &args))
+                .await?,
+        )
+    }
+
+    /// 返回值：与 fields 一一对应的值, key 不存在时全部为 None
+    pub async fn hmget(&self, key: &str, fields: &[String]) -> Result<Vec<Option<String>>> {
+        optional_strings(
+            self.call(with_args(command(&["HMGET", key]), fields))
+                .await?,
+        )
+    }
+
+    /// key 不存在时返回空列表
+    pub async fn hkeys(&self, key: &str) -> Result<Vec<String>> {
+        strings(self.call(command(&["HKEYS", key])).await?)
+    }
+
+    /// key 不存在时返回空列表
+    pub async fn hvalues(&self, key: &str) -> Result<Vec<String>> {
+        strings(self.call(command(&["HVALS", key])).await?)
+    }
+
+    /// 所有的 field 与值, key 不存在时返回空表
+    pub async fn hgetall(&self, key: &str) -> Result<HashMap<String, String>> {
+        let values = strings(self.call(command(&["HGETALL", key])).await?)?;
+        Ok(pairs(values).into_iter().collect())
+    }
+
+    /// key 不存在时返回 false
+    pub async fn hexists(&self, key: &str, field: &str) -> Result<bool> {
+        Ok(integer(self.call(command(&["HEXISTS", key, field])).await?)? == 1)
+    }
+
+    /// field 的数量, key 不存在时返回 0
+    pub async fn hlen(&self, key: &str) -> Result<usize> {
+        Ok(integer(self.call(command(&["HLEN", key])).await?)? as usize)
+    }
+
+    /// 返回值：删除的 field 数量
+    pub async fn hdel(&self, key: &str, field: &str) -> Result<usize> {
+        Ok(integer(self.call(command(&["HDEL", key, field])).await?)? as usize)
+    }
+
+    /// 返回值：key 存在并设置了生存时间返回 true
+    pub async fn expire(&self, key: &str, seconds: u64) -> Result<bool> {
+        Ok(integer(
+            self.call(command(&["EXPIRE", key, &seconds.to_string()]))
+                .await?,
+        )? == 1)
+    }
+
+    /// 在毫秒时间戳 when 过期
+    pub async fn pexpire_at(&self, key: &str, when: u64) -> Result<bool> {
+        Ok(integer(
+            self.call(command(&["PEXPIREAT", key, &when.to_string()]))
+                .await?,
+        )? == 1)
+    }
+
+    /// 返回值：key 存在并移除了生存时间返回 true
+    pub async fn persist(&self, key: &str) -> Result<bool> {
+        Ok(integer(self.call(command(&["PERSIST", key])).await?)? == 1)
+    }
+
+    /// 剩余生存时间（秒）, key 不存在返回 -2, 没有设置生存时间返回 -1
+    pub async fn ttl(&self, key: &str) -> Result<i64> {
+        integer(self.call(command(&["TTL", key])).await?)
+    }
+
+    /// 剩余生存时间（毫秒）, 返回值与 `ttl()` 相同
+    pub async fn pttl(&self, key: &str) -> Result<i64> {
+        integer(self.call(command(&["PTTL", key])).await?)
+    }
+
+    /// 返回值：删除的 key 的数量
+    pub async fn del(&self, keys: Vec<String>) -> Result<u32> {
+        Ok(integer(self.call(with_args(command(&["DEL"]), &keys)).await?)? as u32)
+    }
+
+    /// 返回值：删除的 key 的数量, value 在服务端后台释放
+    pub async fn unlink(&self, keys: Vec<String>) -> Result<u32> {
+        Ok(integer(self.call(with_args(command(&["UNLINK"]), &keys)).await?)? as u32)
+    }
+
+    /// 返回值：存在的 key 的数量
+    pub async fn touch(&self, keys: Vec<String>) -> Result<u32> {
+        Ok(integer(self.call(with_args(command(&["TOUCH"]), &keys)).await?)? as u32)
+    }
+
+    pub async fn exists(&self, key: &str) -> Result<bool> {
+        Ok(integer(self.call(command(&["EXISTS", key])).await?)? == 1)
+    }
+
+    /// 数据库中 key 的数量
+    pub async fn size(&self) -> Result<usize> {
+        Ok(integer(self.call(command(&["DBSIZE"])).await?)? as usize)
+    }
+
+    /// 返回值：
+    ///     * 重命名成功返回 OK
+    ///     * key 不存在， 返回 KeyNotFound
+    pub async fn rename(&self, key: &str, newkey: &str) -> Result<DBOk> {
+        status(self.call(command(&["RENAME", key, newkey])).await?)
+    }
+
+    /// 返回值：newkey 已经存在时不重命名, 返回 false
+    pub async fn renamenx(&self, key: &str, newkey: &str) -> Result<bool> {
+        Ok(integer(self.call(command(&["RENAMENX", key, newkey])).await?)? == 1)
+    }
+
+    /// 返回值：source 不存在, 或者 destination 已经存在且 replace 为 false 时返回 false
+    pub async fn copy(&self, source: &str, destination: &str, replace: bool) -> Result<bool> {
+        let mut args = command(&["COPY", source, destination]);
+        if replace {
+            args.push(b"REPLACE".to_vec());
+        }
+        Ok(integer(self.call(args).await?)? == 1)
+    }
+
+    /// key 的序列化数据, 可以通过 `restore()` 恢复；key 不存在时返回 None
+    pub async fn dump(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        match self.call(command(&["DUMP", key])).await? {
+            Value::Bulk(payload) => Ok(Some(payload)),
+            Value::Nil => Ok(None),
+            other => Err(unexpected(&other)),
+        }
+    }
+
+    ///
+    /// 参数说明：
+    ///     * ttl 生存时间（毫秒）, None 表示不过期
+    ///     * replace key 已经存在时是否覆盖
+    ///
+    /// 返回值：
+    ///     * 恢复成功返回 OK
+    ///     * key 已经存在且 replace 为 false， 返回 KeyAlreadyExists
+    ///     * payload 不合法， 返回 InvalidPayload
+    pub async fn restore(
+        &self,
+        key: &str,
+        ttl: Option<u64>,
+        payload: &[u8],
+        replace: bool,
+    ) -> Result<DBOk> {
+        let mut args = command(&["RESTORE", key, &ttl.unwrap_or(0).to_string()]);
+        args.push(payload.to_vec());
+        if replace {
+            args.push(b"REPLACE".to_vec());
+        }
+        status(self.call(args).await?)
+    }
+
+    /// 删除数据库中所有的 key
+    pub async fn flush(&self) -> Result<()> {
+        self.call(command(&["FLUSHDB"])).await.map(|_| ())
+    }
+
+    /// 与 pattern 匹配的所有 key
+    pub async fn keys(&self, pattern: &str) -> Result<Vec<String>> {
+        strings(self.call(command(&["KEYS", pattern])).await?)
+    }
+
+    ///
+    /// 增量遍历 key
+    ///
+    /// 返回值：(下一次遍历使用的游标, 本次遍历的 key)；游标为 0 表示遍历结束
+    pub async fn scan(
+        &self,
+        cursor: u64,
+        pattern: Option<&str>,
+        count: usize,
+        value_type: Option<ValueType>,
+    ) -> Result<(u64, Vec<String>)> {
+        let mut args = scan_args(pattern, count);
+        if let Some(value_type) = value_type {
+            args.push(String::from("TYPE"));
+            args.push(String::from(value_type.name()));
+        }
+        let args = with_args(command(&["SCAN", &cursor.to_string()]), &args);
+        scan_reply(self.call(args).await?)
+    }
+
+    /// key 的类型, key 不存在时返回 None
+    pub async fn key_type(&self, key: &str) -> Result<Option<ValueType>> {
+        let value = self.call(command(&["TYPE", key])).await?;
+        Ok(value.as_str().and_then(ValueType::from_name))
+    }
+
+    /// 随机返回一个 key, 数据库为空时返回 None
+    pub async fn randomkey(&self) -> Result<Option<String>> {
+        string(self.call(command(&["RANDOMKEY"])).await?)
+    }
+
+    /// 增量遍历集合的成员, 返回值与 `scan()` 相同
+    pub async fn sscan(
+        &self,
+        key: &str,
+        cursor: u64,
+        pattern: Option<&str>,
+        count: usize,
+    ) -> Result<(u64, Vec<String>)> {
+        let args = command(&["SSCAN", key, &cursor.to_string()]);
+        scan_reply(
+            self.call(with_args(args, &scan_args(pattern, count)))
+                .await?,
+        )
+    }
+
+    /// 增量遍历 hash 的 field 与值, 返回值与 `scan()` 相同
+    pub async fn hscan(
+        &self,
+        key: &str,
+        cursor: u64,
+        pattern: Option<&str>,
+        count: usize,
+    ) -> Result<(u64, Vec<(String, String)>)> {
+        let args = command(&["HSCAN", key, &cursor.to_string()]);
+        let (cursor, values) = scan_reply(
+            self.call(with_args(args, &scan_args(pattern, count)))
+                .await?,
+        )?;
+        Ok((cursor, pairs(values)))
+    }
+}
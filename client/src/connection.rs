@@ -0,0 +1,102 @@
+//! 到服务端的单个连接。
+//!
+//! 一批命令一次写出, 再按顺序读取同样数量的回复（pipelining）；出现 I/O 错误或协议错误之后连接不再可用。
+
+use crate::resp::{self, Value};
+use dbcore::DBError;
+use std::io;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+/// 每次从 socket 读取的最大字节数
+const READ_CHUNK: usize = 16 * 1024;
+
+/// internal：执行一批命令失败的原因
+#[derive(Debug)]
+pub(crate) struct Failure {
+    pub(crate) error: DBError,
+    /// 失败之前是否已经收到了回复；没有收到回复时服务端可能已经关闭了连接, 可以在新的连接上重试
+    pub(crate) replied: bool,
+}
+
+pub(crate) struct Connection {
+    stream: TcpStream,
+    buf: Vec<u8>,
+    /// 出现错误之后连接被丢弃, 不再放回连接池
+    broken: bool,
+}
+
+fn io_error(what: &str, e: io::Error) -> DBError {
+    DBError::Io(format!("{}: {}", what, e))
+}
+
+impl Connection {
+    pub(crate) fn new(stream: TcpStream) -> Connection {
+        Connection {
+            stream,
+            buf: Vec::new(),
+            broken: false,
+        }
+    }
+
+    pub(crate) fn is_broken(&self) -> bool {
+        self.broken
+    }
+
+    ///
+    /// internal：发送 commands 并按顺序读取每条命令的回复
+    ///
+    /// 返回值：
+    ///     * 与 commands 一一对应的回复, 错误回复为 `Value::Error`
+    ///     * 读写失败或回复格式不正确， 返回 Failure, 连接不再可用
+    pub(crate) async fn pipeline(
+        &mut self,
+        commands: &[Vec<Vec<u8>>],
+    ) -> std::result::Result<Vec<Value>, Failure> {
+        let result = self.exchange(commands).await;
+        if result.is_err() {
+            self.broken = true;
+        }
+        result
+    }
+
+    async fn exchange(
+        &mut self,
+        commands: &[Vec<Vec<u8>>],
+    ) -> std::result::Result<Vec<Value>, Failure> {
+        let fail = |error, replied| Failure { error, replied };
+        let mut out = Vec::new();
+        commands
+            .iter()
+            .for_each(|args| resp::encode_command(args, &mut out));
+        if let Err(e) = self.stream.write_all(&out).await {
+            return Err(fail(io_error("failed to send commands", e), false));
+        }
+
+        let mut replies = Vec::with_capacity(commands.len());
+        let mut pos = 0;
+        let mut chunk = vec![0u8; READ_CHUNK];
+        while replies.len() < commands.len() {
+            match resp::parse_value(&self.buf, pos) {
+                Ok(Some((value, end))) => {
+                    replies.push(value);
+                    pos = end;
+                    continue;
+                }
+                Ok(None) => {}
+                Err(e) => return Err(fail(e, true)),
+            }
+            let replied = !replies.is_empty() || !self.buf.is_empty();
+            match self.stream.read(&mut chunk).await {
+                Ok(0) => {
+                    let e = io::Error::new(io::ErrorKind::UnexpectedEof, "connection closed");
+                    return Err(fail(io_error("failed to read replies", e), replied));
+                }
+                Ok(n) => self.buf.extend_from_slice(&chunk[..n]),
+                Err(e) => return Err(fail(io_error("failed to read replies", e), replied)),
+            }
+        }
+        self.buf.drain(..pos);
+        Ok(replies)
+    }
+}
@@ -0,0 +1,135 @@
+//! 把服务端返回的错误字符串转换为 `DBError`, 与 `DBError::to_redis_error()` 相对应。
+//!
+//! 错误字符串中不包含 key 等上下文, 这些信息从产生错误的命令中获取。
+
+use dbcore::{DBError, ValueType};
+
+/// internal：命令操作的 value 类型, 用于 `WrongValueType` 中期望的类型
+pub(crate) fn expected_type(command: &str) -> Option<ValueType> {
+    match command {
+        "get" => Some(ValueType::String),
+        "sadd" | "srem" | "scard" | "smembers" | "sismember" | "spop" | "srandmember" | "sscan" => {
+            Some(ValueType::Set)
+        }
+        "hset" | "hget" | "hmset" | "hmget" | "hdel" | "hexists" | "hlen" | "hkeys" | "hvals"
+        | "hgetall" | "hscan" => Some(ValueType::Hash),
+        _ => None,
+    }
+}
+
+/// internal：命令名称（小写）与第一个参数（通常是 key）
+pub(crate) fn command_and_key(args: &[Vec<u8>]) -> (String, String) {
+    let text = |arg: Option<&Vec<u8>>| {
+        arg.map_or_else(String::new, |arg| String::from_utf8_lossy(arg).into_owned())
+    };
+    (text(args.first()).to_ascii_lowercase(), text(args.get(1)))
+}
+
+///
+/// internal：把命令 args 的错误回复 message 转换为 `DBError`
+///
+/// 参数说明：
+///     * actual 命令执行时 key 的实际类型, 只在 `WRONGTYPE` 错误中使用
+pub(crate) fn from_redis_error(
+    args: &[Vec<u8>],
+    message: &str,
+    actual: Option<ValueType>,
+) -> DBError {
+    let (command, key) = command_and_key(args);
+    let (kind, rest) = message.split_once(' ').unwrap_or((message, ""));
+    let between = |open: char, close: char| {
+        let start = rest.find(open)? + 1;
+        let end = start + rest[start..].find(close)?;
+        Some(String::from(&rest[start..end]))
+    };
+    match kind {
+        "WRONGTYPE" => {
+            let expected = expected_type(&command);
+            let actual = actual.or(expected).unwrap_or(ValueType::String);
+            DBError::WrongValueType {
+                key,
+                expected: expected.unwrap_or(actual),
+                actual,
+            }
+        }
+        "OOM" => match between('(', ')').and_then(|max| max.parse().ok()) {
+            Some(max) => DBError::OutOfKeysSize(max),
+            None => DBError::Io(String::from(message)),
+        },
+        "BUSYKEY" => {
+            // COPY 与 RENAMENX 的目标 key 是第二个参数
+            let target = args.get(2).filter(|_| command != "restore");
+            DBError::KeyAlreadyExists(
+                target.map_or(key, |arg| String::from_utf8_lossy(arg).into_owned()),
+            )
+        }
+        "EXECABORT" => DBError::ExecAbort,
+        "ERR" => from_err(args, rest, key, between),
+        _ => DBError::Io(String::from(message)),
+    }
+}
+
+/// internal：以 ERR 开头的错误, rest 为 ERR 之后的内容
+fn from_err<F>(args: &[Vec<u8>], rest: &str, key: String, between: F) -> DBError
+where
+    F: Fn(char, char) -> Option<String>,
+{
+    if rest == "no such key" {
+        return DBError::KeyNotFound(key);
+    }
+    if rest == "syntax error" || rest.starts_with("Protocol error") {
+        return DBError::Syntax(String::from(rest));
+    }
+    if rest == "value is not an integer or out of range" {
+        // 携带第一个无法解析为整数的参数
+        let arg = args
+            .iter()
+            .skip(2)
+            .map(|arg| String::from_utf8_lossy(arg).into_owned())
+            .find(|arg| arg.parse::<i64>().is_err());
+        return DBError::NotANumber(arg.unwrap_or_else(|| String::from(rest)));
+    }
+    if rest == "DB index is out of range" {
+        // 超出范围的是参数中最大的编号
+        let index = args
+            .iter()
+            .skip(1)
+            .filter_map(|arg| String::from_utf8_lossy(arg).parse::<usize>().ok())
+            .max();
+        return DBError::DBIndexOutOfRange(index.unwrap_or(0));
+    }
+    if rest == "source and destination objects are the same" {
+        return DBError::SameKey(key);
+    }
+    if rest.starts_with("DUMP payload") {
+        return DBError::InvalidPayload(String::from(rest));
+    }
+    if rest.starts_with("wrong number of arguments") {
+        return DBError::WrongArity(between('\'', '\'').unwrap_or_default());
+    }
+    if rest.starts_with("unknown command") {
+        return DBError::UnknownCommand(between('\'', '\'').unwrap_or_default());
+    }
+    if rest == "invalid expire time" {
+        return DBError::OutOfRange(String::from("expire time"));
+    }
+    if let Some(what) = rest.strip_suffix(" is out of range") {
+        return DBError::OutOfRange(String::from(what));
+    }
+    if let Some(what) = rest.strip_suffix(" is not supported") {
+        return DBError::NotSupported(String::from(what));
+    }
+    if let Some(what) = rest.strip_suffix(" already in progress") {
+        return DBError::InProgress(String::from(what));
+    }
+    if rest.starts_with("Error compiling script")
+        || rest.starts_with("Error running script")
+        || rest.starts_with("Script ")
+    {
+        return DBError::Script(String::from(rest));
+    }
+    if rest.contains("MULTI") {
+        return DBError::Transaction(String::from(rest));
+    }
+    DBError::Io(String::from(rest))
+}
@@ -0,0 +1,30 @@
+//! memkv 服务的异步客户端, 基于 tokio。
+//!
+//! 支持：
+//!     * 连接池：`Client` 可以复制并在多个任务中同时使用, 最多同时打开 `ClientConfig::pool_size` 个连接
+//!     * pipelining：参见 `Pipeline`
+//!     * 类型化的方法与 `dbcore::KVDB` 的方法对应, 返回相同的 `Result` / `DBError`
+//!     * 自动重连：服务端重启之后, 失效的连接被丢弃, 命令在新的连接上执行
+//!
+//! 示例：
+//! ```no_run
+//! use memkv_client::{Client, ClientConfig};
+//!
+//! # async fn example() -> dbcore::Result<()> {
+//! let client = Client::connect("127.0.0.1:6379", ClientConfig::default()).await?;
+//! client.sets("name", "memkv").await?;
+//! assert_eq!(Some(String::from("memkv")), client.get("name").await?);
+//! # Ok(())
+//! # }
+//! ```
+
+mod client;
+mod connection;
+mod error;
+mod pipeline;
+mod pool;
+mod resp;
+
+pub use client::{Client, ClientConfig};
+pub use pipeline::Pipeline;
+pub use resp::Value;
@@ -0,0 +1,68 @@
+//! pipeline：多条命令在同一个连接上一次发送, 回复按照命令的顺序返回。
+
+use crate::client::Client;
+use crate::resp::Value;
+use dbcore::Result;
+
+/// 由 `Client::pipeline()` 创建
+///
+/// 示例：
+/// ```no_run
+/// # async fn example(client: memkv_client::Client) -> dbcore::Result<()> {
+/// let replies = client
+///     .pipeline()
+///     .cmd(["SET", "name", "memkv"])
+///     .cmd(["GET", "name"])
+///     .execute()
+///     .await?;
+/// assert_eq!(2, replies.len());
+/// # Ok(())
+/// # }
+/// ```
+pub struct Pipeline {
+    client: Client,
+    commands: Vec<Vec<Vec<u8>>>,
+}
+
+impl Pipeline {
+    pub(crate) fn new(client: Client) -> Pipeline {
+        Pipeline {
+            client,
+            commands: Vec::new(),
+        }
+    }
+
+    /// 追加一条命令
+    pub fn cmd<I, A>(&mut self, args: I) -> &mut Pipeline
+    where
+        I: IntoIterator<Item = A>,
+        A: AsRef<[u8]>,
+    {
+        self.commands
+            .push(args.into_iter().map(|arg| arg.as_ref().to_vec()).collect());
+        self
+    }
+
+    /// 命令的数量
+    pub fn len(&self) -> usize {
+        self.commands.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.commands.is_empty()
+    }
+
+    ///
+    /// 发送所有命令, 之后 pipeline 被清空, 可以继续使用
+    ///
+    /// 返回值：
+    ///     * 与命令一一对应的结果, 执行失败的命令为对应的 `DBError`
+    ///     * 无法连接或读写失败， 返回 Io
+    pub async fn execute(&mut self) -> Result<Vec<Result<Value>>> {
+        if self.commands.is_empty() {
+            return Ok(Vec::new());
+        }
+        let commands = std::mem::take(&mut self.commands);
+        self.client.run(&commands).await
+    }
+}
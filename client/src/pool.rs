@@ -0,0 +1,156 @@
+//! 连接池：最多同时打开 `ClientConfig::pool_size` 个连接, 空闲的连接被复用。
+//!
+//! 新的连接在建立时选择配置的数据库；连接失败时按照配置的次数与间隔重试。
+
+use crate::client::ClientConfig;
+use crate::connection::Connection;
+use crate::error;
+use crate::resp::Value;
+use dbcore::{DBError, Result};
+use std::ops::{Deref, DerefMut};
+use std::sync::{Mutex, MutexGuard};
+use tokio::net::TcpStream;
+use tokio::sync::{Semaphore, SemaphorePermit};
+use tokio::time;
+
+pub(crate) struct Pool {
+    addr: String,
+    config: ClientConfig,
+    idle: Mutex<Vec<Connection>>,
+    permits: Semaphore,
+}
+
+/// internal：从连接池中取出的连接, drop 时可用的连接放回连接池
+pub(crate) struct Pooled<'a> {
+    pool: &'a Pool,
+    conn: Option<Connection>,
+    /// 是否为之前使用过的空闲连接
+    pub(crate) reused: bool,
+    _permit: SemaphorePermit<'a>,
+}
+
+impl Deref for Pooled<'_> {
+    type Target = Connection;
+
+    fn deref(&self) -> &Connection {
+        self.conn
+            .as_ref()
+            .expect("connection is taken only on drop")
+    }
+}
+
+impl DerefMut for Pooled<'_> {
+    fn deref_mut(&mut self) -> &mut Connection {
+        self.conn
+            .as_mut()
+            .expect("connection is taken only on drop")
+    }
+}
+
+impl Drop for Pooled<'_> {
+    fn drop(&mut self) {
+        if let Some(conn) = self.conn.take().filter(|conn| !conn.is_broken()) {
+            self.pool.idle().push(conn);
+        }
+    }
+}
+
+impl Pool {
+    pub(crate) fn new(addr: String, config: ClientConfig) -> Pool {
+        Pool {
+            addr,
+            permits: Semaphore::new(config.pool_size.max(1)),
+            config,
+            idle: Mutex::new(Vec::new()),
+        }
+    }
+
+    pub(crate) fn addr(&self) -> &str {
+        &self.addr
+    }
+
+    fn idle(&self) -> MutexGuard<'_, Vec<Connection>> {
+        self.idle
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    ///
+    /// internal：取出一个空闲连接, 没有空闲连接时建立新的连接；连接数达到上限时等待其他连接放回
+    ///
+    /// 返回值：
+    ///     * 连接
+    ///     * 重试之后仍然无法连接， 返回 Io
+    pub(crate) async fn get(&self) -> Result<Pooled<'_>> {
+        let permit = self
+            .permits
+            .acquire()
+            .await
+            .map_err(|_| DBError::Io(String::from("the connection pool is closed")))?;
+        let idle = self.idle().pop();
+        let reused = idle.is_some();
+        let conn = match idle {
+            Some(conn) => conn,
+            None => self.connect().await?,
+        };
+        Ok(Pooled {
+            pool: self,
+            conn: Some(conn),
+            reused,
+            _permit: permit,
+        })
+    }
+
+    /// internal：丢弃所有空闲连接, 在发现服务端关闭了连接之后调用
+    pub(crate) fn clear(&self) {
+        self.idle().clear();
+    }
+
+    /// internal：建立连接, 失败时重试 reconnect_attempts 次
+    async fn connect(&self) -> Result<Connection> {
+        let mut attempt = 0;
+        loop {
+            match self.open().await {
+                Ok(conn) => return Ok(conn),
+                Err(e) if attempt >= self.config.reconnect_attempts => return Err(e),
+                Err(_) => {
+                    attempt += 1;
+                    time::sleep(self.config.reconnect_delay * attempt).await;
+                }
+            }
+        }
+    }
+
+    /// internal：建立一个连接并选择数据库
+    async fn open(&self) -> Result<Connection> {
+        let failed =
+            |e: std::io::Error| DBError::Io(format!("failed to connect to {}: {}", self.addr, e));
+        let stream = match time::timeout(
+            self.config.connect_timeout,
+            TcpStream::connect(&self.addr),
+        )
+        .await
+        {
+            Ok(stream) => stream.map_err(failed)?,
+            Err(_) => {
+                return Err(DBError::Io(format!(
+                    "failed to connect to {}: timed out",
+                    self.addr
+                )))
+            }
+        };
+        stream.set_nodelay(true).map_err(failed)?;
+        let mut conn = Connection::new(stream);
+        if self.config.db != 0 {
+            let select = vec![b"SELECT".to_vec(), self.config.db.to_string().into_bytes()];
+            let replies = conn
+                .pipeline(std::slice::from_ref(&select))
+                .await
+                .map_err(|failure| failure.error)?;
+            if let Some(Value::Error(message)) = replies.first() {
+                return Err(error::from_redis_error(&select, message, None));
+            }
+        }
+        Ok(conn)
+    }
+}
@@ -0,0 +1,139 @@
+//! 客户端使用的 RESP 编解码：命令编码为 bulk string 数组, 回复解析为 `Value`。
+//!
+//! 客户端默认使用 RESP2；通过 `Client::execute()` 发送 `HELLO 3` 之后收到的 RESP3 类型
+//! （null、map、set 等）同样可以解析。
+
+use dbcore::{DBError, Result};
+use std::str;
+
+/// 服务端的回复
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    /// 简单字符串, 例如 `+OK`
+    Status(String),
+    /// 嵌套在数组中的错误；顶层的错误转换为 `DBError`
+    Error(String),
+    Integer(i64),
+    Bulk(Vec<u8>),
+    /// 不存在的值
+    Nil,
+    Array(Vec<Value>),
+    /// RESP3 的键值对
+    Map(Vec<(Value, Value)>),
+}
+
+impl Value {
+    /// 按照 UTF-8 解析 Status 与 Bulk, 其他类型返回 None
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Value::Status(text) => Some(text),
+            Value::Bulk(bytes) => str::from_utf8(bytes).ok(),
+            _ => None,
+        }
+    }
+}
+
+/// internal：把命令编码为 RESP 数组, 追加到 out
+pub(crate) fn encode_command(args: &[Vec<u8>], out: &mut Vec<u8>) {
+    out.extend_from_slice(format!("*{}\r\n", args.len()).as_bytes());
+    for arg in args {
+        out.extend_from_slice(format!("${}\r\n", arg.len()).as_bytes());
+        out.extend_from_slice(arg);
+        out.extend_from_slice(b"\r\n");
+    }
+}
+
+fn protocol_error(reason: &str) -> DBError {
+    DBError::InvalidPayload(format!("invalid reply: {}", reason))
+}
+
+///
+/// internal：从 buf 的 pos 处解析一个回复
+///
+/// 返回值：
+///     * (回复, 下一个回复的开始位置)
+///     * 数据不完整， 返回 None
+///     * 格式不正确， 返回 InvalidPayload, 之后的数据无法继续解析
+pub(crate) fn parse_value(buf: &[u8], pos: usize) -> Result<Option<(Value, usize)>> {
+    let (line, next) = match read_line(buf, pos)? {
+        Some(line) => line,
+        None => return Ok(None),
+    };
+    let text = str::from_utf8(&line[1..]).map_err(|_| protocol_error("line is not UTF-8"))?;
+    let number = || {
+        text.parse::<i64>()
+            .map_err(|_| protocol_error("invalid length or integer"))
+    };
+    let value = match line[0] {
+        b'+' => Value::Status(String::from(text)),
+        b'-' => Value::Error(String::from(text)),
+        b':' => Value::Integer(number()?),
+        b'_' => Value::Nil,
+        b'$' => match number()? {
+            len if len < 0 => Value::Nil,
+            len => {
+                let end = next + len as usize;
+                if buf.len() < end + 2 {
+                    return Ok(None);
+                }
+                if &buf[end..end + 2] != b"\r\n" {
+                    return Err(protocol_error("bulk string is not terminated by CRLF"));
+                }
+                return Ok(Some((Value::Bulk(buf[next..end].to_vec()), end + 2)));
+            }
+        },
+        b'*' | b'~' => match number()? {
+            len if len < 0 => Value::Nil,
+            len => {
+                let mut items = Vec::with_capacity(len.min(1024) as usize);
+                let mut pos = next;
+                for _ in 0..len {
+                    match parse_value(buf, pos)? {
+                        Some((item, end)) => {
+                            items.push(item);
+                            pos = end;
+                        }
+                        None => return Ok(None),
+                    }
+                }
+                return Ok(Some((Value::Array(items), pos)));
+            }
+        },
+        b'%' => {
+            let mut pairs = Vec::new();
+            let mut pos = next;
+            for _ in 0..number()?.max(0) {
+                let (key, end) = match parse_value(buf, pos)? {
+                    Some(key) => key,
+                    None => return Ok(None),
+                };
+                let (value, end) = match parse_value(buf, end)? {
+                    Some(value) => value,
+                    None => return Ok(None),
+                };
+                pairs.push((key, value));
+                pos = end;
+            }
+            return Ok(Some((Value::Map(pairs), pos)));
+        }
+        prefix => {
+            return Err(DBError::InvalidPayload(format!(
+                "invalid reply: unexpected type '{}'",
+                prefix as char
+            )))
+        }
+    };
+    Ok(Some((value, next)))
+}
+
+/// internal：读取 pos 处以 \r\n 结尾的一行（不含 \r\n）, 以及下一行的开始位置
+fn read_line(buf: &[u8], pos: usize) -> Result<Option<(&[u8], usize)>> {
+    let end = match buf[pos..].iter().position(|b| *b == b'\n') {
+        Some(end) => pos + end,
+        None => return Ok(None),
+    };
+    if end < pos + 2 || buf[end - 1] != b'\r' {
+        return Err(protocol_error("line is not terminated by CRLF"));
+    }
+    Ok(Some((&buf[pos..end - 1], end + 1)))
+}
@@ -0,0 +1,275 @@
+use dbcore::{DBError, DBOk, Databases, ValueType};
+use memkv_client::{Client, ClientConfig, Value};
+use memkv_server::{Server, ServerConfig, ShutdownHandle};
+use std::collections::HashSet;
+use std::net::SocketAddr;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+/// 在后台线程中运行的服务
+struct Running {
+    addr: SocketAddr,
+    handle: ShutdownHandle,
+    thread: JoinHandle<dbcore::Result<()>>,
+}
+
+impl Running {
+    fn start(addr: &str) -> Running {
+        let server = Server::bind(addr, Databases::new(4, None), ServerConfig::default()).unwrap();
+        let addr = server.local_addr().unwrap();
+        let handle = server.shutdown_handle();
+        let thread = thread::spawn(move || server.run());
+        Running {
+            addr,
+            handle,
+            thread,
+        }
+    }
+
+    async fn client(&self, config: ClientConfig) -> Client {
+        Client::connect(&self.addr.to_string(), config)
+            .await
+            .unwrap()
+    }
+
+    fn stop(self) {
+        self.handle.shutdown();
+        self.thread.join().unwrap().unwrap();
+    }
+}
+
+fn strings(values: &[&str]) -> Vec<String> {
+    values.iter().map(|value| String::from(*value)).collect()
+}
+
+#[tokio::test]
+async fn typed_methods_mirror_kvdb() {
+    let server = Running::start("127.0.0.1:0");
+    let client = server.client(ClientConfig::default()).await;
+
+    assert_eq!(DBOk::Ok, client.sets("name", "memkv").await.unwrap());
+    assert_eq!(
+        DBOk::Nil,
+        client.set("name", "x", true, false, None).await.unwrap()
+    );
+    assert_eq!(
+        Some(String::from("memkv")),
+        client.get("name").await.unwrap()
+    );
+    assert_eq!(None, client.get("missing").await.unwrap());
+    assert!(client.expire("name", 100).await.unwrap());
+    assert!((99..=100).contains(&client.ttl("name").await.unwrap()));
+
+    assert_eq!(2, client.sadd("set", strings(&["a", "b"])).await.unwrap());
+    assert!(client.sismember("set", "a").await.unwrap());
+    assert_eq!(2, client.slen("set").await.unwrap());
+    let members: HashSet<String> = strings(&["a", "b"]).into_iter().collect();
+    assert_eq!(members, client.smembers("set").await.unwrap());
+    assert_eq!(None, client.srandmember("nope", 2).await.unwrap());
+
+    assert_eq!(1, client.hset("hash", "f1", "v1").await.unwrap());
+    assert_eq!(
+        DBOk::Ok,
+        client
+            .hmset("hash", vec![(String::from("f2"), String::from("v2"))])
+            .await
+            .unwrap()
+    );
+    assert_eq!(
+        vec![Some(String::from("v1")), None],
+        client.hmget("hash", &strings(&["f1", "f3"])).await.unwrap()
+    );
+    assert_eq!(2, client.hgetall("hash").await.unwrap().len());
+    assert_eq!(
+        Some(ValueType::Hash),
+        client.key_type("hash").await.unwrap()
+    );
+
+    let payload = client.dump("hash").await.unwrap().unwrap();
+    assert_eq!(
+        DBOk::Ok,
+        client.restore("copy", None, &payload, false).await.unwrap()
+    );
+    assert_eq!(
+        Some(String::from("v2")),
+        client.hget("copy", "f2").await.unwrap()
+    );
+    assert_eq!(4, client.size().await.unwrap());
+    assert_eq!(2, client.del(strings(&["copy", "name"])).await.unwrap());
+    server.stop();
+}
+
+#[tokio::test]
+async fn server_errors_become_dberrors() {
+    let server = Running::start("127.0.0.1:0");
+    let client = server.client(ClientConfig::default()).await;
+    client.sets("name", "memkv").await.unwrap();
+    client.sadd("set", strings(&["a"])).await.unwrap();
+
+    assert_eq!(
+        Err(DBError::WrongValueType {
+            key: String::from("name"),
+            expected: ValueType::Hash,
+            actual: ValueType::String,
+        }),
+        client.hget("name", "field").await
+    );
+    assert_eq!(
+        Err(DBError::WrongValueType {
+            key: String::from("set"),
+            expected: ValueType::String,
+            actual: ValueType::Set,
+        }),
+        client.get("set").await
+    );
+    assert_eq!(
+        Err(DBError::KeyNotFound(String::from("nope"))),
+        client.rename("nope", "other").await
+    );
+    let payload = client.dump("name").await.unwrap().unwrap();
+    assert_eq!(
+        Err(DBError::KeyAlreadyExists(String::from("name"))),
+        client.restore("name", None, &payload, false).await
+    );
+    assert_eq!(
+        Err(DBError::NotANumber(String::from("soon"))),
+        client.execute(["EXPIRE", "name", "soon"]).await
+    );
+    assert_eq!(
+        Err(DBError::UnknownCommand(String::from("nosuch"))),
+        client.execute(["NOSUCH"]).await
+    );
+    assert_eq!(
+        Err(DBError::WrongArity(String::from("get"))),
+        client.execute(["GET"]).await
+    );
+    assert_eq!(
+        Err(DBError::DBIndexOutOfRange(9)),
+        client.execute(["MOVE", "name", "9"]).await
+    );
+    // 连接在错误之后仍然可用
+    assert_eq!(
+        Some(String::from("memkv")),
+        client.get("name").await.unwrap()
+    );
+    server.stop();
+}
+
+#[tokio::test]
+async fn pipeline_replies_in_order() {
+    let server = Running::start("127.0.0.1:0");
+    let client = server.client(ClientConfig::default()).await;
+
+    let mut pipeline = client.pipeline();
+    for i in 0..100 {
+        pipeline.cmd(["SET", &format!("key:{}", i), &i.to_string()]);
+    }
+    pipeline
+        .cmd(["GET", "key:42"])
+        .cmd(["HGET", "key:1", "field"])
+        .cmd(["DBSIZE"]);
+    assert_eq!(103, pipeline.len());
+    let mut replies = pipeline.execute().await.unwrap();
+    assert!(pipeline.is_empty());
+    assert_eq!(103, replies.len());
+    assert_eq!(Ok(Value::Integer(100)), replies.pop().unwrap());
+    assert!(matches!(
+        replies.pop().unwrap(),
+        Err(DBError::WrongValueType { .. })
+    ));
+    assert_eq!(Ok(Value::Bulk(b"42".to_vec())), replies.pop().unwrap());
+    assert!(replies
+        .iter()
+        .all(|reply| reply == &Ok(Value::Status(String::from("OK")))));
+    server.stop();
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn pool_limits_connections() {
+    let server = Running::start("127.0.0.1:0");
+    let config = ClientConfig {
+        pool_size: 3,
+        ..ClientConfig::default()
+    };
+    let client = server.client(config).await;
+
+    let mut tasks = Vec::new();
+    for task in 0..16 {
+        let client = client.clone();
+        tasks.push(tokio::spawn(async move {
+            let mut ids = HashSet::new();
+            for i in 0..20 {
+                let key = format!("task:{}:{}", task, i);
+                client.sets(&key, "v").await.unwrap();
+                if let Value::Integer(id) = client.execute(["CLIENT", "ID"]).await.unwrap() {
+                    ids.insert(id);
+                }
+            }
+            ids
+        }));
+    }
+    let mut ids = HashSet::new();
+    for task in tasks {
+        ids.extend(task.await.unwrap());
+    }
+    assert!(ids.len() <= 3, "{:?}", ids);
+    assert_eq!(320, client.size().await.unwrap());
+    server.stop();
+}
+
+#[tokio::test]
+async fn connections_use_the_configured_database() {
+    let server = Running::start("127.0.0.1:0");
+    let default = server.client(ClientConfig::default()).await;
+    let second = server
+        .client(ClientConfig {
+            db: 2,
+            ..ClientConfig::default()
+        })
+        .await;
+    second.sets("key", "in db2").await.unwrap();
+    assert_eq!(None, default.get("key").await.unwrap());
+    assert_eq!(
+        Some(String::from("in db2")),
+        second.get("key").await.unwrap()
+    );
+
+    let result = Client::connect(
+        &server.addr.to_string(),
+        ClientConfig {
+            db: 99,
+            ..ClientConfig::default()
+        },
+    )
+    .await;
+    assert_eq!(Err(DBError::DBIndexOutOfRange(99)), result.map(|_| ()));
+    server.stop();
+}
+
+#[tokio::test]
+async fn reconnects_after_server_restart() {
+    let server = Running::start("127.0.0.1:0");
+    let addr = server.addr;
+    let client = server.client(ClientConfig::default()).await;
+    client.sets("key", "before").await.unwrap();
+    server.stop();
+
+    // 服务停止期间命令失败
+    let config = ClientConfig {
+        reconnect_attempts: 1,
+        reconnect_delay: Duration::from_millis(10),
+        ..ClientConfig::default()
+    };
+    let offline = Client::connect(&addr.to_string(), config).await;
+    assert!(matches!(offline.map(|_| ()), Err(DBError::Io(_))));
+    assert!(matches!(client.get("key").await, Err(DBError::Io(_))));
+
+    let server = Running::start(&addr.to_string());
+    assert_eq!(None, client.get("key").await.unwrap());
+    client.sets("key", "after").await.unwrap();
+    assert_eq!(
+        Some(String::from("after")),
+        client.get("key").await.unwrap()
+    );
+    server.stop();
+}
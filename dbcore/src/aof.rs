@@ -0,0 +1,529 @@
+//! AOF（append only file）持久化, 对应 Redis 的 `appendonly` 以及 `BGREWRITEAOF` 命令。
+//!
+//! 开启 AOF 后, 每个 `KVDB` 把成功执行的修改命令记录下来, 由 `Databases` 写入文件。
+//! 记录的是确定性的命令：`spop` 记录为 `srem`, `expire` 记录为 `pexpireat`,
+//! 因此重放得到的数据与原数据完全一致。自定义类型记录为 `setcustom key 类型名称 十六进制编码`, 参见 `custom`。
+//!
+//! 文件格式与 Redis 相同, 每条命令编码为一个 RESP 数组：
+//!     * `*<参数个数>\r\n`, 之后是每个参数 `$<字节数>\r\n<参数>\r\n`
+//!     * `select <编号>` 切换之后的命令作用的数据库
+//!
+//! 重写（rewrite）在后台线程中把当前数据转换为最少的命令写入临时文件,
+//! 期间新的命令同时写入旧文件和内存中的重写缓冲区, 重写结束后把缓冲区追加到临时文件,
+//! 再原子地替换旧文件。
+//!
+//! 配置了加密密钥时, 每次写入加密为一帧, 参见 `crypto`。
+
+use crate::crypto::{self, EncryptionKey, FrameWriter, LogFile};
+use crate::custom;
+use crate::databases::DBImage;
+use crate::{now_millis, DBError, DBOk, Result, Value, KVDB};
+use std::ffi::OsString;
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::str;
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use crate::snapshot::{io_error, write_file};
+
+/// 重写时每条 `sadd` / `hmset` 命令最多携带的元素个数, 与 Redis 相同
+const REWRITE_ITEMS_PER_CMD: usize = 64;
+
+/// 命令中单个参数的最大长度, 与 Redis 的 proto-max-bulk-len 默认值相同
+const MAX_ARG_LEN: usize = 512 * 1024 * 1024;
+
+/// 写入 AOF 文件后何时调用 fsync 将数据刷新到磁盘
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FsyncPolicy {
+    /// 每次写入之后都刷新, 最安全也最慢
+    Always,
+    /// 由后台线程每秒刷新一次, 宕机时最多丢失一秒的数据
+    EverySec,
+    /// 从不主动刷新, 由操作系统决定
+    No,
+}
+
+impl FsyncPolicy {
+    /// 策略名称, 与 Redis 的 `appendfsync` 配置一致
+    pub fn name(&self) -> &'static str {
+        match self {
+            FsyncPolicy::Always => "always",
+            FsyncPolicy::EverySec => "everysec",
+            FsyncPolicy::No => "no",
+        }
+    }
+
+    /// 根据策略名称（忽略大小写）解析策略, 无法识别时返回 None
+    pub fn from_name(name: &str) -> Option<FsyncPolicy> {
+        match name.to_ascii_lowercase().as_str() {
+            "always" => Some(FsyncPolicy::Always),
+            "everysec" => Some(FsyncPolicy::EverySec),
+            "no" => Some(FsyncPolicy::No),
+            _ => None,
+        }
+    }
+}
+
+/// 启动时重放 AOF 文件的结果
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AofReplay {
+    /// 重放的命令数量
+    pub commands: usize,
+    /// 文件末尾不完整的命令被截掉的字节数, 通常是写入过程中宕机导致的
+    pub truncated: usize,
+}
+
+/// internal：将一条命令按 RESP 数组编码追加到 buf
+pub(crate) fn encode_command<S: AsRef<str>>(buf: &mut Vec<u8>, args: &[S]) {
+    buf.extend_from_slice(format!("*{}\r\n", args.len()).as_bytes());
+    args.iter().for_each(|arg| {
+        let arg = arg.as_ref();
+        buf.extend_from_slice(format!("${}\r\n", arg.len()).as_bytes());
+        buf.extend_from_slice(arg.as_bytes());
+        buf.extend_from_slice(b"\r\n");
+    });
+}
+
+/// internal：重新创建 key 所需的最少命令, 供重写以及 `restore` / `move` 的记录使用
+pub(crate) fn value_commands(key: &str, value: &Value, expire_at: Option<u64>) -> Vec<Vec<String>> {
+    let with_key = |name: &str| vec![String::from(name), String::from(key)];
+    let mut commands = Vec::new();
+    match value {
+        Value::StringValue(_) | Value::CompressedString(_) => {
+            let mut cmd = with_key("set");
+            cmd.extend(value.string().map(|v| v.into_owned()));
+            commands.push(cmd);
+        }
+        Value::SetValue(v) if v.is_empty() => commands.push(with_key("sadd")),
+        Value::SetValue(v) => {
+            let members: Vec<&String> = v.iter().collect();
+            members.chunks(REWRITE_ITEMS_PER_CMD).for_each(|chunk| {
+                let mut cmd = with_key("sadd");
+                cmd.extend(chunk.iter().map(|m| (*m).clone()));
+                commands.push(cmd);
+            });
+        }
+        Value::HashValue(v) if v.is_empty() => commands.push(with_key("hmset")),
+        Value::HashValue(v) => {
+            let pairs: Vec<(&String, &String)> = v.iter().collect();
+            pairs.chunks(REWRITE_ITEMS_PER_CMD).for_each(|chunk| {
+                let mut cmd = with_key("hmset");
+                chunk.iter().for_each(|(field, value)| {
+                    cmd.push((*field).clone());
+                    cmd.push((*value).clone());
+                });
+                commands.push(cmd);
+            });
+        }
+        Value::Custom(v) => {
+            let mut cmd = with_key("setcustom");
+            cmd.push(String::from(v.type_name()));
+            cmd.push(custom::to_hex(&v.encode()));
+            commands.push(cmd);
+        }
+    }
+    if let Some(when) = expire_at {
+        let mut cmd = with_key("pexpireat");
+        cmd.push(when.to_string());
+        commands.push(cmd);
+    }
+    commands
+}
+
+/// internal：把数据库的数据转换为最少的命令, 已经过期的 key 会被跳过
+pub(crate) fn rewrite(images: &[DBImage]) -> Vec<u8> {
+    let now = now_millis();
+    let mut buf = Vec::new();
+    for (index, db, ttl) in images.iter() {
+        encode_command(&mut buf, &[String::from("select"), index.to_string()]);
+        for (key, value) in db.iter() {
+            let expire_at = ttl.get(key).copied();
+            if expire_at.is_some_and(|when| when <= now) {
+                continue;
+            }
+            value_commands(key, value, expire_at)
+                .iter()
+                .for_each(|cmd| encode_command(&mut buf, cmd));
+        }
+    }
+    buf
+}
+
+/// internal：按 RESP 格式依次解析命令
+struct Parser<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn bad_format(&self) -> DBError {
+        DBError::InvalidPayload(format!("bad AOF format at offset {}", self.pos))
+    }
+
+    /// 读取以 prefix 开头、以 \r\n 结尾的数字行；数据不完整时返回 None
+    fn read_number(&mut self, prefix: u8) -> Result<Option<usize>> {
+        let rest = &self.data[self.pos..];
+        let end = match rest.windows(2).position(|w| w == b"\r\n") {
+            Some(end) => end,
+            None if rest.is_empty() || rest[0] == prefix => return Ok(None),
+            None => return Err(self.bad_format()),
+        };
+        if rest[0] != prefix {
+            return Err(self.bad_format());
+        }
+        let number = str::from_utf8(&rest[1..end])
+            .ok()
+            .and_then(|s| s.parse::<usize>().ok())
+            .ok_or_else(|| self.bad_format())?;
+        self.pos += end + 2;
+        Ok(Some(number))
+    }
+
+    /// 读取一条完整的命令；数据不完整时返回 None, 并且不移动读取位置
+    fn read_command(&mut self) -> Result<Option<Vec<String>>> {
+        let start = self.pos;
+        let command = self.try_read_command();
+        if let Ok(None) = command {
+            self.pos = start;
+        }
+        command
+    }
+
+    fn try_read_command(&mut self) -> Result<Option<Vec<String>>> {
+        let count = match self.read_number(b'*')? {
+            Some(count) => count,
+            None => return Ok(None),
+        };
+        let mut args = Vec::new();
+        for _ in 0..count {
+            let len = match self.read_number(b'$')? {
+                Some(len) if len > MAX_ARG_LEN => return Err(self.bad_format()),
+                Some(len) => len,
+                None => return Ok(None),
+            };
+            if self.data.len() - self.pos < len + 2 {
+                return Ok(None);
+            }
+            let bytes = &self.data[self.pos..self.pos + len];
+            if &self.data[self.pos + len..self.pos + len + 2] != b"\r\n" {
+                return Err(self.bad_format());
+            }
+            let arg = str::from_utf8(bytes).map_err(|_| self.bad_format())?;
+            args.push(String::from(arg));
+            self.pos += len + 2;
+        }
+        if args.is_empty() {
+            return Err(self.bad_format());
+        }
+        Ok(Some(args))
+    }
+}
+
+/// internal：解析 AOF 文件的内容
+///
+/// 返回值： (所有完整的命令, 完整命令的总字节数)；末尾不完整的命令会被忽略
+pub(crate) fn parse(data: &[u8]) -> Result<(Vec<Vec<String>>, usize)> {
+    let mut parser = Parser { data, pos: 0 };
+    let mut commands = Vec::new();
+    while parser.pos < data.len() {
+        match parser.read_command()? {
+            Some(command) => commands.push(command),
+            None => break,
+        }
+    }
+    Ok((commands, parser.pos))
+}
+
+fn wrong_arity(name: &str) -> DBError {
+    DBError::InvalidPayload(format!("wrong number of arguments for `{}`", name))
+}
+
+/// internal：在 db 上重放一条记录的命令（`select` / `swapdb` 由 `Databases` 处理）
+pub(crate) fn apply(db: &mut KVDB, args: &[String]) -> Result<DBOk> {
+    let name = args[0].to_ascii_lowercase();
+    let key = args.get(1).ok_or_else(|| wrong_arity(&name));
+    let rest = args.get(2..).unwrap_or(&[]);
+    match (name.as_str(), rest.len()) {
+        ("flushdb", _) if args.len() == 1 => {
+            db.flush();
+            Ok(DBOk::Ok)
+        }
+        ("set", 1) => db.sets(key?, rest[0].clone()),
+        ("sadd", _) => db.sadd(key?, rest.to_vec()).map(|_| DBOk::Ok),
+        ("srem", n) if n > 0 => db.srem(key?, rest.to_vec()).map(|_| DBOk::Ok),
+        ("hset", 2) => db
+            .hset(key?, rest[0].clone(), rest[1].clone())
+            .map(|_| DBOk::Ok),
+        ("hmset", n) if n % 2 == 0 => {
+            let pairs = rest
+                .chunks(2)
+                .map(|pair| (pair[0].clone(), pair[1].clone()))
+                .collect();
+            db.hmset(key?, pairs)
+        }
+        ("hdel", 1) => db.hdel(key?, &rest[0]).map(|_| DBOk::Ok),
+        ("setcustom", 2) => db.set_encoded(key?, &rest[0], &rest[1]),
+        ("pexpireat", 1) => {
+            let when = rest[0]
+                .parse::<u64>()
+                .map_err(|_| DBError::NotANumber(rest[0].clone()))?;
+            db.pexpire_at(key?, when);
+            Ok(DBOk::Ok)
+        }
+        ("persist", 0) => {
+            db.persist(key?);
+            Ok(DBOk::Ok)
+        }
+        ("del", _) if args.len() > 1 => {
+            db.del(args[1..].to_vec());
+            Ok(DBOk::Ok)
+        }
+        ("rename", 1) => db.rename(key?, &rest[0]),
+        ("copy", 2) if rest[1] == "replace" => db.copy(key?, &rest[0], true).map(|_| DBOk::Ok),
+        ("flushdb", _)
+        | ("set", _)
+        | ("srem", _)
+        | ("hset", _)
+        | ("hmset", _)
+        | ("hdel", _)
+        | ("setcustom", _)
+        | ("pexpireat", _)
+        | ("persist", _)
+        | ("del", _)
+        | ("rename", _)
+        | ("copy", _) => Err(wrong_arity(&name)),
+        _ => Err(DBError::InvalidPayload(format!(
+            "unknown command `{}`",
+            name
+        ))),
+    }
+}
+
+/// internal：正在执行的后台重写
+#[derive(Debug)]
+struct Rewrite {
+    // 成功时返回继续追加写入新文件使用的 FrameWriter
+    handle: JoinHandle<Result<Option<FrameWriter>>>,
+
+    // 重写期间新写入的命令, 重写结束后追加到新文件
+    buf: Vec<u8>,
+}
+
+/// internal：打开 AOF 文件的追加写入, 以及 everysec 策略使用的后台刷新线程
+#[derive(Debug)]
+pub(crate) struct AppendOnlyFile {
+    path: PathBuf,
+    file: LogFile,
+    policy: FsyncPolicy,
+
+    // 后台刷新线程, 丢弃 Sender 时线程退出
+    syncer: Option<(Sender<()>, JoinHandle<()>)>,
+
+    // 最近一次写入的 select 的数据库编号, None 表示下一条命令之前必须写入 select
+    selected: Option<usize>,
+
+    // 还没有写入文件的命令
+    pending: Vec<u8>,
+
+    rewrite: Option<Rewrite>,
+}
+
+/// internal：在 path 后追加后缀得到临时文件的路径
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut tmp = OsString::from(path.as_os_str());
+    tmp.push(suffix);
+    PathBuf::from(tmp)
+}
+
+fn spawn_syncer(file: &File, path: &Path) -> Result<(Sender<()>, JoinHandle<()>)> {
+    let file = file.try_clone().map_err(|e| io_error(path, e))?;
+    let (sender, receiver) = mpsc::channel::<()>();
+    let handle = thread::spawn(move || {
+        while let Err(RecvTimeoutError::Timeout) = receiver.recv_timeout(Duration::from_secs(1)) {
+            let _ = file.sync_data();
+        }
+    });
+    Ok((sender, handle))
+}
+
+impl AppendOnlyFile {
+    /// internal：打开（不存在时创建）path 用于追加写入, 加密的文件需要提供继续写入使用的 FrameWriter
+    pub(crate) fn open(
+        path: &Path,
+        policy: FsyncPolicy,
+        frames: Option<FrameWriter>,
+    ) -> Result<Self> {
+        let file = LogFile::open(path, frames)?;
+        let syncer = match policy {
+            FsyncPolicy::EverySec => Some(spawn_syncer(file.file(), path)?),
+            _ => None,
+        };
+        Ok(AppendOnlyFile {
+            path: path.to_path_buf(),
+            file,
+            policy,
+            syncer,
+            selected: None,
+            pending: Vec::new(),
+            rewrite: None,
+        })
+    }
+
+    /// internal：记录数据库 index 上执行的命令, 必要时先记录 select
+    pub(crate) fn feed(&mut self, index: usize, commands: &[Vec<String>]) {
+        if commands.is_empty() {
+            return;
+        }
+        if self.selected != Some(index) {
+            encode_command(
+                &mut self.pending,
+                &[String::from("select"), index.to_string()],
+            );
+            self.selected = Some(index);
+        }
+        commands
+            .iter()
+            .for_each(|cmd| encode_command(&mut self.pending, cmd));
+    }
+
+    /// internal：记录与具体数据库无关的命令, 例如 swapdb
+    pub(crate) fn feed_global(&mut self, command: &[String]) {
+        encode_command(&mut self.pending, command);
+    }
+
+    /// internal：将记录的命令写入文件, 并按照策略刷新到磁盘
+    pub(crate) fn write(&mut self) -> Result<()> {
+        if self.pending.is_empty() {
+            return Ok(());
+        }
+        self.file
+            .append(&self.pending)
+            .map_err(|e| io_error(&self.path, e))?;
+        if self.policy == FsyncPolicy::Always {
+            self.file
+                .file()
+                .sync_data()
+                .map_err(|e| io_error(&self.path, e))?;
+        }
+        if let Some(rewrite) = &mut self.rewrite {
+            rewrite.buf.extend_from_slice(&self.pending);
+        }
+        self.pending.clear();
+        Ok(())
+    }
+
+    pub(crate) fn rewrite_in_progress(&self) -> bool {
+        self.rewrite.is_some()
+    }
+
+    /// internal：在后台线程中把 images 重写为新的 AOF 文件, 调用前应当先调用 `write()`
+    pub(crate) fn start_rewrite(&mut self, images: Vec<DBImage>) -> Result<DBOk> {
+        if self.rewrite.is_some() {
+            return Err(DBError::InProgress(String::from("AOF rewrite")));
+        }
+        let tmp = with_suffix(&self.path, ".rewrite.tmp");
+        let key = self.file.key().cloned();
+        let handle = thread::spawn(move || {
+            let (data, frames) = crypto::seal_log(key.as_ref(), &rewrite(&images));
+            let write = || -> std::io::Result<()> {
+                let mut file = File::create(&tmp)?;
+                file.write_all(&data)?;
+                file.sync_all()
+            };
+            write().map_err(|e| io_error(&tmp, e))?;
+            Ok(frames)
+        });
+        // 重写缓冲区中的第一条命令之前必须有 select
+        self.selected = None;
+        self.rewrite = Some(Rewrite {
+            handle,
+            buf: Vec::new(),
+        });
+        Ok(DBOk::Ok)
+    }
+
+    /// internal：后台重写是否已经结束
+    pub(crate) fn rewrite_finished(&self) -> bool {
+        self.rewrite
+            .as_ref()
+            .is_some_and(|rewrite| rewrite.handle.is_finished())
+    }
+
+    /// internal：等待后台重写结束, 成功时用新文件替换旧文件, 调用前应当先调用 `write()`
+    ///
+    /// 返回值：重写的结果；没有后台重写时返回 None
+    pub(crate) fn finish_rewrite(&mut self) -> Option<Result<DBOk>> {
+        let Rewrite { handle, buf } = self.rewrite.take()?;
+        let tmp = with_suffix(&self.path, ".rewrite.tmp");
+        let res = match handle.join() {
+            Ok(res) => res,
+            Err(_) => Err(DBError::Io(String::from("AOF rewrite thread panicked"))),
+        }
+        .and_then(|frames| {
+            let mut file = LogFile::open(&tmp, frames)?;
+            if !buf.is_empty() {
+                file.append(&buf).map_err(|e| io_error(&tmp, e))?;
+            }
+            file.file().sync_all().map_err(|e| io_error(&tmp, e))?;
+            fs::rename(&tmp, &self.path).map_err(|e| io_error(&self.path, e))?;
+            self.replace_file(file)
+        });
+        if res.is_err() {
+            let _ = fs::remove_file(&tmp);
+        }
+        Some(res.map(|_| DBOk::Ok))
+    }
+
+    ///
+    /// internal：立即把 images 重写为新的 AOF 文件, 使用 key 加密（None 表示不加密）, 用于迁移与轮换密钥
+    /// 调用前应当先调用 `write()`
+    ///
+    /// 返回值：
+    ///     * 重写成功返回 Ok
+    ///     * 后台重写正在执行， 返回 InProgress
+    ///     * 写入文件失败， 返回 Io
+    pub(crate) fn rewrite_now(
+        &mut self,
+        images: &[DBImage],
+        key: Option<&EncryptionKey>,
+    ) -> Result<()> {
+        if self.rewrite.is_some() {
+            return Err(DBError::InProgress(String::from("AOF rewrite")));
+        }
+        let (data, frames) = crypto::seal_log(key, &rewrite(images));
+        write_file(&self.path, &data)?;
+        self.selected = None;
+        let file = LogFile::open(&self.path, frames)?;
+        self.replace_file(file)
+    }
+
+    /// internal：使用替换之后的文件, 并重启后台刷新线程
+    fn replace_file(&mut self, file: LogFile) -> Result<()> {
+        self.stop_syncer();
+        self.file = file;
+        if self.policy == FsyncPolicy::EverySec {
+            self.syncer = Some(spawn_syncer(self.file.file(), &self.path)?);
+        }
+        Ok(())
+    }
+
+    fn stop_syncer(&mut self) {
+        if let Some((sender, handle)) = self.syncer.take() {
+            drop(sender);
+            let _ = handle.join();
+        }
+    }
+}
+
+impl Drop for AppendOnlyFile {
+    fn drop(&mut self) {
+        let _ = self.write();
+        self.stop_syncer();
+        if self.policy != FsyncPolicy::No {
+            let _ = self.file.file().sync_data();
+        }
+    }
+}
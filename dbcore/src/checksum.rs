@@ -0,0 +1,33 @@
+//! 持久化与序列化使用的校验和算法
+
+/// CRC-64/Jones 多项式（反射形式）, 与 Redis 的 DUMP/RDB 校验和一致
+const CRC64_POLY: u64 = 0x95ac_9329_ac4b_c9b5;
+
+const CRC64_TABLE: [u64; 256] = crc64_table();
+
+const fn crc64_table() -> [u64; 256] {
+    let mut table = [0u64; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u64;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ CRC64_POLY
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+}
+
+/// 在 crc 的基础上继续计算 data 的 CRC64, 初始值为 0
+pub(crate) fn crc64(crc: u64, data: &[u8]) -> u64 {
+    data.iter().fold(crc, |crc, byte| {
+        CRC64_TABLE[((crc ^ u64::from(*byte)) & 0xff) as usize] ^ (crc >> 8)
+    })
+}
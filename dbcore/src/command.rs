@@ -0,0 +1,358 @@
+//! 自定义命令：实现 `Command` trait 并注册到 `KVDB` 或 `Databases` 之后,
+//! 可以通过 `KVDB::call()` 执行, 同时在 REPL、网络服务（包括 `MULTI` 与脚本）中可用。
+//!
+//! 每个命令声明：
+//!     * 名称, 不区分大小写；与内置命令同名时内置命令优先
+//!     * 参数个数 arity, 与 Redis 相同：包括命令名称在内, 正数表示固定个数, 负数表示至少 -arity 个
+//!     * 标记 `CommandFlags`, 修改数据的命令必须带有 WRITE, 只读脚本中不能调用这样的命令
+//!     * key 在参数中的位置 `KeySpec`, 参数个数不足以包含第一个 key 时按参数个数错误拒绝
+//!
+//! 带有 WRITE 标记的命令在 `KVDB::transaction()` 中执行, 返回错误时撤销命令的所有修改。
+//! 同一个 `Databases` 中的所有数据库共用一个注册表, 注册表可以复制并在其他线程中使用。
+//!
+//! 示例：
+//! ```
+//! use dbcore::{Command, CommandFlags, CommandReply, KeySpec, Result, KVDB};
+//! use std::sync::Arc;
+//!
+//! /// GETSET key value：写入 value 并返回原来的值
+//! struct GetSet;
+//!
+//! impl Command for GetSet {
+//!     fn name(&self) -> &str {
+//!         "getset"
+//!     }
+//!
+//!     fn arity(&self) -> i32 {
+//!         3
+//!     }
+//!
+//!     fn flags(&self) -> CommandFlags {
+//!         CommandFlags::WRITE
+//!     }
+//!
+//!     fn key_spec(&self) -> KeySpec {
+//!         KeySpec::FIRST
+//!     }
+//!
+//!     fn execute(&self, db: &mut KVDB, args: &[String]) -> Result<CommandReply> {
+//!         let old = db.get(&args[1])?;
+//!         db.sets(&args[1], args[2].clone())?;
+//!         Ok(old.map_or(CommandReply::Nil, CommandReply::Bulk))
+//!     }
+//! }
+//!
+//! let mut db = KVDB::default();
+//! db.register_command(Arc::new(GetSet)).unwrap();
+//! let args = |args: &[&str]| args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>();
+//! assert_eq!(CommandReply::Nil, db.call(&args(&["GETSET", "name", "memkv"])).unwrap());
+//! assert_eq!(
+//!     CommandReply::Bulk(String::from("memkv")),
+//!     db.call(&args(&["getset", "name", "v2"])).unwrap()
+//! );
+//! ```
+
+use crate::{DBError, DBOk, Result, KVDB};
+use std::collections::HashMap;
+use std::fmt;
+use std::ops::BitOr;
+use std::sync::{Arc, RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+/// 命令的标记, 可以用 `|` 组合
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct CommandFlags(u32);
+
+impl CommandFlags {
+    /// 只读的命令
+    pub const NONE: CommandFlags = CommandFlags(0);
+    /// 命令会修改数据
+    pub const WRITE: CommandFlags = CommandFlags(1);
+    /// 不能在脚本中调用
+    pub const NO_SCRIPT: CommandFlags = CommandFlags(1 << 1);
+    /// 不能在 `MULTI` 之后排队
+    pub const NO_MULTI: CommandFlags = CommandFlags(1 << 2);
+
+    /// 是否包含 other 中的所有标记
+    pub fn contains(&self, other: CommandFlags) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+impl BitOr for CommandFlags {
+    type Output = CommandFlags;
+
+    fn bitor(self, other: CommandFlags) -> CommandFlags {
+        CommandFlags(self.0 | other.0)
+    }
+}
+
+///
+/// key 在命令参数中的位置, 与 Redis 的 first key / last key / step 相同,
+/// 参数的下标从命令名称开始计算, 即第一个参数的下标为 1
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeySpec {
+    /// 第一个 key 的下标, 0 表示命令没有 key
+    pub first: usize,
+    /// 最后一个 key 的下标, 负数表示从参数末尾倒数, 例如 -1 表示最后一个参数
+    pub last: i64,
+    /// 相邻两个 key 的下标之差
+    pub step: usize,
+}
+
+impl KeySpec {
+    /// 命令没有 key
+    pub const NONE: KeySpec = KeySpec {
+        first: 0,
+        last: 0,
+        step: 0,
+    };
+    /// 第一个参数是唯一的 key
+    pub const FIRST: KeySpec = KeySpec {
+        first: 1,
+        last: 1,
+        step: 1,
+    };
+
+    /// 参数（包括命令名称）中的 key, 参数个数不足时只返回存在的 key
+    pub fn keys<'a>(&self, args: &'a [String]) -> Vec<&'a String> {
+        if self.first == 0 || self.first >= args.len() {
+            return Vec::new();
+        }
+        let last = if self.last < 0 {
+            args.len() as i64 + self.last
+        } else {
+            self.last.min(args.len() as i64 - 1)
+        };
+        if last < self.first as i64 {
+            return Vec::new();
+        }
+        (self.first..=last as usize)
+            .step_by(self.step.max(1))
+            .map(|i| &args[i])
+            .collect()
+    }
+}
+
+/// 命令的回复, 对应 Redis 协议中的各种回复类型
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CommandReply {
+    /// 状态回复, 例如 OK
+    Status(String),
+    Integer(i64),
+    Bulk(String),
+    /// 不存在的值
+    Nil,
+    Array(Vec<CommandReply>),
+}
+
+impl From<DBOk> for CommandReply {
+    fn from(ok: DBOk) -> CommandReply {
+        match ok {
+            DBOk::Ok => CommandReply::Status(String::from("OK")),
+            DBOk::Nil => CommandReply::Nil,
+        }
+    }
+}
+
+/// 与 REPL 中内置命令的输出格式相同
+impl fmt::Display for CommandReply {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CommandReply::Status(status) => f.write_str(status),
+            CommandReply::Integer(n) => write!(f, "{}", n),
+            CommandReply::Bulk(value) => write!(f, "{:?}", value),
+            CommandReply::Nil => f.write_str("(nil)"),
+            CommandReply::Array(items) if items.is_empty() => f.write_str("(empty array)"),
+            CommandReply::Array(items) => {
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        f.write_str("\n")?;
+                    }
+                    write!(f, "{}) {}", i + 1, item)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+///
+/// 自定义命令。命令在持有数据库独占访问权（`&mut`）时执行, 其间其他操作不会穿插进来；
+/// 实现需要保证 `flags()` 与 `key_spec()` 如实描述命令的行为
+pub trait Command: Send + Sync {
+    /// 命令名称, 注册时统一转换为小写
+    fn name(&self) -> &str;
+
+    /// 参数个数, 包括命令名称在内；正数表示固定个数, 负数表示至少 -arity 个
+    fn arity(&self) -> i32;
+
+    /// 命令的标记, 默认为只读的命令
+    fn flags(&self) -> CommandFlags {
+        CommandFlags::NONE
+    }
+
+    /// key 在参数中的位置, 默认为没有 key
+    fn key_spec(&self) -> KeySpec {
+        KeySpec::NONE
+    }
+
+    ///
+    /// 执行命令, args 包括命令名称在内, 参数个数已经按照 `arity()` 与 `key_spec()` 检查过
+    ///
+    /// 返回值：
+    ///     * 命令的回复
+    ///     * 执行失败， 返回对应的错误；带有 WRITE 标记的命令之前的修改全部撤销
+    fn execute(&self, db: &mut KVDB, args: &[String]) -> Result<CommandReply>;
+}
+
+/// 自定义命令的注册表, 复制得到的注册表与原来的共享同样的命令
+#[derive(Clone, Default)]
+pub struct CommandRegistry {
+    commands: Arc<RwLock<Commands>>,
+}
+
+/// internal：命令名称（小写）到命令的映射
+type Commands = HashMap<String, Arc<dyn Command>>;
+
+impl CommandRegistry {
+    pub fn new() -> Self {
+        CommandRegistry::default()
+    }
+
+    /// internal：读取注册表；持有锁的线程 panic 时注册表仍然可用
+    fn read(&self) -> RwLockReadGuard<'_, Commands> {
+        self.commands.read().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// internal：修改注册表；持有锁的线程 panic 时注册表仍然可用
+    fn write(&self) -> RwLockWriteGuard<'_, Commands> {
+        self.commands.write().unwrap_or_else(|e| e.into_inner())
+    }
+
+    ///
+    /// 注册命令
+    ///
+    /// 返回值：
+    ///     * 注册成功返回 ()
+    ///     * 名称为空或包含空白字符、arity 为 0、`key_spec()` 的 step 为 0， 返回 Syntax
+    ///     * 已经注册了同名的命令， 返回 AlreadyRegistered
+    pub fn register(&self, command: Arc<dyn Command>) -> Result<()> {
+        let name = command.name().to_ascii_lowercase();
+        if name.is_empty() || name.contains(char::is_whitespace) {
+            return Err(DBError::Syntax(format!("invalid command name `{}`", name)));
+        }
+        if command.arity() == 0 {
+            return Err(DBError::Syntax(format!(
+                "arity of `{}` must not be 0",
+                name
+            )));
+        }
+        let spec = command.key_spec();
+        if spec.first > 0 && spec.step == 0 {
+            return Err(DBError::Syntax(format!(
+                "key step of `{}` must not be 0",
+                name
+            )));
+        }
+        let mut commands = self.write();
+        if commands.contains_key(&name) {
+            return Err(DBError::AlreadyRegistered(name));
+        }
+        commands.insert(name, command);
+        Ok(())
+    }
+
+    /// 名称为 name（忽略大小写）的命令
+    pub fn get(&self, name: &str) -> Option<Arc<dyn Command>> {
+        let commands = self.read();
+        commands.get(&name.to_ascii_lowercase()).cloned()
+    }
+
+    /// 是否注册了名称为 name（忽略大小写）的命令
+    pub fn contains(&self, name: &str) -> bool {
+        self.get(name).is_some()
+    }
+
+    /// 所有命令的名称, 按字典序排列
+    pub fn names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.read().keys().cloned().collect();
+        names.sort();
+        names
+    }
+
+    /// 注册的命令数量
+    pub fn len(&self) -> usize {
+        self.read().len()
+    }
+
+    /// 是否没有注册任何命令
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    ///
+    /// 查找 args（包括命令名称）对应的命令并检查参数个数, 用于执行或者排队之前的检查
+    ///
+    /// 返回值：
+    ///     * 对应的命令
+    ///     * 命令不存在， 返回 UnknownCommand
+    ///     * 参数个数不符合 `arity()`, 或者不足以包含第一个 key， 返回 WrongArity
+    pub fn lookup(&self, args: &[String]) -> Result<Arc<dyn Command>> {
+        let name = args.first().map(|name| name.to_ascii_lowercase());
+        let name = name.unwrap_or_default();
+        let command = self
+            .get(&name)
+            .ok_or_else(|| DBError::UnknownCommand(name.clone()))?;
+        let arity = command.arity();
+        let len = args.len() as i64;
+        let matches = if arity > 0 {
+            len == i64::from(arity)
+        } else {
+            len >= -i64::from(arity)
+        };
+        let first = command.key_spec().first;
+        if !matches || (first > 0 && first >= args.len()) {
+            return Err(DBError::WrongArity(name));
+        }
+        Ok(command)
+    }
+}
+
+impl fmt::Debug for CommandRegistry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_set().entries(self.names()).finish()
+    }
+}
+
+impl KVDB {
+    /// 自定义命令的注册表, 属于 `Databases` 的数据库共用 `Databases::commands()`
+    pub fn commands(&self) -> &CommandRegistry {
+        &self.commands
+    }
+
+    /// internal：使用 `Databases` 共用的注册表
+    pub(crate) fn set_commands(&mut self, commands: CommandRegistry) {
+        self.commands = commands;
+    }
+
+    /// 注册自定义命令, 详情查看 `CommandRegistry::register()`
+    pub fn register_command(&mut self, command: Arc<dyn Command>) -> Result<()> {
+        self.commands.register(command)
+    }
+
+    ///
+    /// 执行自定义命令, args 包括命令名称在内；带有 WRITE 标记的命令在 `transaction()` 中执行
+    ///
+    /// 返回值：
+    ///     * 命令的回复
+    ///     * 命令不存在或者参数个数不正确， 返回 `CommandRegistry::lookup()` 的错误
+    ///     * 命令执行失败， 返回命令的错误
+    pub fn call(&mut self, args: &[String]) -> Result<CommandReply> {
+        let command = self.commands.lookup(args)?;
+        if command.flags().contains(CommandFlags::WRITE) {
+            self.transaction(|db| command.execute(db, args))
+        } else {
+            command.execute(self, args)
+        }
+    }
+}
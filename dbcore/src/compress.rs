@@ -0,0 +1,74 @@
+//! 字符串 value 的透明压缩。
+//!
+//! 设置压缩阈值后（`KVDB::set_compression_threshold()`）, 写入的字符串超过阈值时使用 LZ4 压缩保存,
+//! 压缩后没有变小的字符串仍然原样保存；读取时自动解压, 对 `get` / `set` 等命令透明。
+//! 内存统计（`INFO memory`）按照压缩后的大小计算, 并给出压缩的字符串数量与节省的字节数。
+//!
+//! 快照与 `DUMP` 保存压缩后的数据, 加载后仍然是压缩的；AOF 与 WAL 记录的是命令, 保存原始字符串,
+//! 重放时按照当前的阈值重新压缩。
+
+use crate::{DBError, Result};
+
+/// internal：LZ4 压缩的字符串
+#[derive(Debug, Clone)]
+pub(crate) struct Compressed {
+    // 原始字符串的字节数
+    len: usize,
+    data: Vec<u8>,
+}
+
+impl Compressed {
+    /// internal：压缩 value, 压缩后没有变小时返回 None
+    pub(crate) fn compress(value: &str) -> Option<Compressed> {
+        let mut data = lz4_flex::block::compress(value.as_bytes());
+        if data.len() >= value.len() {
+            return None;
+        }
+        data.shrink_to_fit();
+        Some(Compressed {
+            len: value.len(),
+            data,
+        })
+    }
+
+    ///
+    /// internal：从快照或 `DUMP` 中读取的压缩数据, 先校验能否解压为 len 字节的 UTF-8 字符串
+    ///
+    /// 返回值：
+    ///     * 压缩的字符串
+    ///     * 无法解压或者不是 UTF-8， 返回 InvalidPayload
+    pub(crate) fn from_parts(len: usize, data: Vec<u8>) -> Result<Compressed> {
+        let invalid = || DBError::InvalidPayload(String::from("bad compressed string"));
+        // LZ4 的压缩比不超过 255, 避免为伪造的长度分配内存
+        if len > data.len().saturating_mul(255) {
+            return Err(invalid());
+        }
+        let raw = lz4_flex::block::decompress(&data, len).map_err(|_| invalid())?;
+        if raw.len() != len || std::str::from_utf8(&raw).is_err() {
+            return Err(invalid());
+        }
+        Ok(Compressed { len, data })
+    }
+
+    /// internal：原始字符串的字节数
+    pub(crate) fn len(&self) -> usize {
+        self.len
+    }
+
+    /// internal：压缩后的数据
+    pub(crate) fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// internal：解压为原始字符串
+    pub(crate) fn decompress(&self) -> String {
+        let raw = lz4_flex::block::decompress(&self.data, self.len)
+            .expect("compressed strings are validated when created");
+        String::from_utf8(raw).expect("compressed strings are validated when created")
+    }
+
+    /// internal：压缩的数据占用的内存, 单位为字节
+    pub(crate) fn memory_usage(&self) -> usize {
+        self.data.capacity()
+    }
+}
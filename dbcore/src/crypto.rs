@@ -0,0 +1,492 @@
+//! 静态数据加密（encryption at rest）：开启后 dbcore 写入的快照、AOF、WAL 以及归档文件都使用
+//! ChaCha20-Poly1305 认证加密, 没有密钥无法读取内容, 任何修改都会被发现。
+//!
+//! 密钥为 32 字节, 来自密钥文件（32 字节原始数据或 64 个十六进制字符）或环境变量（十六进制）,
+//! 通过 `Databases::set_encryption_key()` 在开启持久化之前设置。
+//!
+//! 加密文件的格式：
+//!     * 文件头为 5 字节魔数 "MKENC" + 1 字节格式版本号 + 1 字节文件类型 + 8 字节密钥指纹
+//!     * 之后是若干帧, 每帧为 4 字节密文长度（小端序） + 12 字节随机 nonce + 密文（末尾 16 字节为认证标签）
+//!     * 每帧的附加认证数据为文件头 + 8 字节帧序号（小端序）, 因此修改文件头、调换或删除中间的帧都会被发现
+//!
+//! 快照整体加密为一帧；AOF 与 WAL 每次写入追加一帧, 内容与未加密时完全相同。
+//! 密钥指纹由密钥派生, 用于区分密钥错误与数据被篡改, 不会泄露密钥。
+//!
+//! 读取日志时, 末尾不完整或无法认证的最后一帧视为写入过程中宕机, 与未加密的日志一样被截掉；
+//! 其他位置的帧无法认证说明文件被篡改, 返回错误。
+//!
+//! 配置了密钥时拒绝读取未加密的文件（空的日志除外）, 以免被替换为伪造的明文文件。
+//! 轮换密钥即用新的密钥重写所有文件, 参见 `Databases::rotate_encryption_key()`；
+//! 迁移未加密的数据同样通过轮换完成：不配置密钥打开数据, 再轮换为新的密钥。
+
+use crate::snapshot::io_error;
+use crate::{DBError, Result};
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::path::Path;
+use zeroize::Zeroize;
+
+const MAGIC: &[u8] = b"MKENC";
+
+/// 当前的加密格式版本号
+const ENCRYPTION_VERSION: u8 = 1;
+
+/// 文件类型：整体加密为一帧的文件（快照）
+const KIND_FILE: u8 = 1;
+/// 文件类型：每次写入追加一帧的日志（AOF、WAL）
+const KIND_LOG: u8 = 2;
+
+const FINGERPRINT_LEN: usize = 8;
+const HEADER_LEN: usize = MAGIC.len() + 2 + FINGERPRINT_LEN;
+const NONCE_LEN: usize = 12;
+
+/// 帧头：4 字节密文长度 + 12 字节 nonce
+const FRAME_HEADER_LEN: usize = 4 + NONCE_LEN;
+
+/// 密钥的字节数
+pub const KEY_LEN: usize = 32;
+
+/// 默认读取十六进制密钥的环境变量
+pub const ENCRYPTION_KEY_ENV: &str = "MEMKV_ENCRYPTION_KEY";
+
+/// 加密密钥, 丢弃时清零；`Debug` 只显示密钥指纹
+#[derive(Clone)]
+pub struct EncryptionKey {
+    key: [u8; KEY_LEN],
+    fingerprint: [u8; FINGERPRINT_LEN],
+}
+
+fn invalid_key() -> DBError {
+    DBError::Encryption(String::from(
+        "the key must be 32 bytes or 64 hexadecimal characters",
+    ))
+}
+
+impl EncryptionKey {
+    /// 使用 32 字节的原始密钥
+    pub fn new(key: [u8; KEY_LEN]) -> EncryptionKey {
+        // 用固定的 nonce 认证空消息, 标签的前 8 字节作为指纹
+        let tag = ChaCha20Poly1305::new(Key::from_slice(&key))
+            .encrypt(
+                Nonce::from_slice(&[0u8; NONCE_LEN]),
+                Payload {
+                    msg: b"",
+                    aad: b"memkv key fingerprint",
+                },
+            )
+            .expect("encrypting an empty message cannot fail");
+        let mut fingerprint = [0u8; FINGERPRINT_LEN];
+        fingerprint.copy_from_slice(&tag[..FINGERPRINT_LEN]);
+        EncryptionKey { key, fingerprint }
+    }
+
+    ///
+    /// 解析 64 个十六进制字符表示的密钥, 忽略首尾的空白
+    ///
+    /// 返回值：
+    ///     * 密钥
+    ///     * 长度或字符不正确， 返回 Encryption
+    pub fn from_hex(text: &str) -> Result<EncryptionKey> {
+        let text = text.trim().as_bytes();
+        if text.len() != KEY_LEN * 2 {
+            return Err(invalid_key());
+        }
+        let mut key = [0u8; KEY_LEN];
+        for (i, pair) in text.chunks(2).enumerate() {
+            let digit = |c: u8| (c as char).to_digit(16).ok_or_else(invalid_key);
+            key[i] = (digit(pair[0])? * 16 + digit(pair[1])?) as u8;
+        }
+        Ok(EncryptionKey::new(key))
+    }
+
+    ///
+    /// 从密钥文件读取密钥, 文件内容为 32 字节原始数据或 64 个十六进制字符
+    ///
+    /// 返回值：
+    ///     * 密钥
+    ///     * 读取文件失败， 返回 Io
+    ///     * 内容不是合法的密钥， 返回 Encryption
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<EncryptionKey> {
+        let path = path.as_ref();
+        let mut data = std::fs::read(path).map_err(|e| io_error(path, e))?;
+        let key = if data.len() == KEY_LEN {
+            let mut key = [0u8; KEY_LEN];
+            key.copy_from_slice(&data);
+            Ok(EncryptionKey::new(key))
+        } else {
+            std::str::from_utf8(&data)
+                .map_err(|_| invalid_key())
+                .and_then(EncryptionKey::from_hex)
+        };
+        data.zeroize();
+        key
+    }
+
+    ///
+    /// 从环境变量 name 读取十六进制密钥, 例如 `ENCRYPTION_KEY_ENV`
+    ///
+    /// 返回值：
+    ///     * 密钥；环境变量不存在或为空时返回 None
+    ///     * 内容不是合法的密钥， 返回 Encryption
+    pub fn from_env(name: &str) -> Result<Option<EncryptionKey>> {
+        match std::env::var(name) {
+            Ok(text) if !text.trim().is_empty() => EncryptionKey::from_hex(&text).map(Some),
+            Ok(_) | Err(std::env::VarError::NotPresent) => Ok(None),
+            Err(std::env::VarError::NotUnicode(_)) => Err(invalid_key()),
+        }
+    }
+
+    /// 密钥指纹的十六进制表示, 可以用于确认使用的是哪个密钥
+    pub fn fingerprint(&self) -> String {
+        self.fingerprint
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect()
+    }
+
+    fn cipher(&self) -> ChaCha20Poly1305 {
+        ChaCha20Poly1305::new(Key::from_slice(&self.key))
+    }
+
+    fn header(&self, kind: u8) -> Vec<u8> {
+        let mut header = MAGIC.to_vec();
+        header.push(ENCRYPTION_VERSION);
+        header.push(kind);
+        header.extend_from_slice(&self.fingerprint);
+        header
+    }
+}
+
+impl fmt::Debug for EncryptionKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("EncryptionKey")
+            .field("fingerprint", &self.fingerprint())
+            .finish()
+    }
+}
+
+impl Drop for EncryptionKey {
+    fn drop(&mut self) {
+        self.key.zeroize();
+    }
+}
+
+/// internal：依次加密一个文件中的帧
+#[derive(Debug)]
+pub(crate) struct FrameWriter {
+    key: EncryptionKey,
+    header: Vec<u8>,
+    frames: u64,
+}
+
+impl FrameWriter {
+    /// internal：之前已经写入了 frames 帧的文件
+    fn new(key: &EncryptionKey, kind: u8, frames: u64) -> FrameWriter {
+        FrameWriter {
+            key: key.clone(),
+            header: key.header(kind),
+            frames,
+        }
+    }
+
+    /// internal：加密的密钥
+    pub(crate) fn key(&self) -> &EncryptionKey {
+        &self.key
+    }
+
+    /// internal：把 plaintext 加密为下一帧
+    pub(crate) fn seal(&mut self, plaintext: &[u8]) -> Vec<u8> {
+        let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let mut aad = self.header.clone();
+        aad.extend_from_slice(&self.frames.to_le_bytes());
+        let ciphertext = self
+            .key
+            .cipher()
+            .encrypt(
+                &nonce,
+                Payload {
+                    msg: plaintext,
+                    aad: &aad,
+                },
+            )
+            .expect("frame is too large to encrypt");
+        self.frames += 1;
+        let mut frame = Vec::with_capacity(FRAME_HEADER_LEN + ciphertext.len());
+        frame.extend_from_slice(&(ciphertext.len() as u32).to_le_bytes());
+        frame.extend_from_slice(&nonce);
+        frame.extend_from_slice(&ciphertext);
+        frame
+    }
+}
+
+/// internal：data 是否为加密文件
+pub(crate) fn is_encrypted(data: &[u8]) -> bool {
+    data.starts_with(MAGIC)
+}
+
+/// internal：data 是否为使用 key 加密的文件
+pub(crate) fn encrypted_with(data: &[u8], key: &EncryptionKey) -> bool {
+    is_encrypted(data)
+        && data.len() >= HEADER_LEN
+        && data[MAGIC.len() + 2..HEADER_LEN] == key.fingerprint
+}
+
+/// internal：配置了密钥但文件未加密
+fn unencrypted(path: &Path) -> DBError {
+    DBError::Encryption(format!(
+        "{}: the file is not encrypted but an encryption key is configured, \
+         open it without a key and rotate to the key to encrypt it",
+        path.display()
+    ))
+}
+
+/// internal：检查加密文件的文件头, 返回解密使用的密钥
+fn check_header<'a>(
+    key: Option<&'a EncryptionKey>,
+    path: &Path,
+    data: &[u8],
+    kind: u8,
+) -> Result<&'a EncryptionKey> {
+    let error = |reason: &str| DBError::Encryption(format!("{}: {}", path.display(), reason));
+    if data.len() < HEADER_LEN {
+        return Err(error("truncated encryption header"));
+    }
+    let version = data[MAGIC.len()];
+    if version > ENCRYPTION_VERSION {
+        return Err(error(&format!(
+            "unsupported encryption version {}",
+            version
+        )));
+    }
+    if data[MAGIC.len() + 1] != kind {
+        return Err(error("unexpected kind of encrypted file"));
+    }
+    match key {
+        None => Err(error(
+            "the file is encrypted but no encryption key is configured",
+        )),
+        Some(key) if !encrypted_with(data, key) => Err(error("wrong encryption key")),
+        Some(key) => Ok(key),
+    }
+}
+
+/// internal：解析 data 中从 pos 开始的一帧
+///
+/// 返回值：(明文, 下一帧的位置)；帧不完整或无法认证时返回 None
+fn open_frame(
+    key: &EncryptionKey,
+    header: &[u8],
+    index: u64,
+    data: &[u8],
+    pos: usize,
+) -> Option<(Vec<u8>, usize)> {
+    let rest = &data[pos..];
+    if rest.len() < FRAME_HEADER_LEN {
+        return None;
+    }
+    let mut len = [0u8; 4];
+    len.copy_from_slice(&rest[..4]);
+    let len = u32::from_le_bytes(len) as usize;
+    if rest.len() - FRAME_HEADER_LEN < len {
+        return None;
+    }
+    let mut aad = header.to_vec();
+    aad.extend_from_slice(&index.to_le_bytes());
+    let plaintext = key
+        .cipher()
+        .decrypt(
+            Nonce::from_slice(&rest[4..FRAME_HEADER_LEN]),
+            Payload {
+                msg: &rest[FRAME_HEADER_LEN..FRAME_HEADER_LEN + len],
+                aad: &aad,
+            },
+        )
+        .ok()?;
+    Some((plaintext, pos + FRAME_HEADER_LEN + len))
+}
+
+fn tampered(path: &Path) -> DBError {
+    DBError::Encryption(format!(
+        "{}: authentication failed, the file is corrupted or has been tampered with",
+        path.display()
+    ))
+}
+
+/// internal：整体加密快照等文件；key 为 None 时原样返回
+pub(crate) fn seal(key: Option<&EncryptionKey>, plaintext: Vec<u8>) -> Vec<u8> {
+    match key {
+        Some(key) => {
+            let mut writer = FrameWriter::new(key, KIND_FILE, 0);
+            let mut data = writer.header.clone();
+            data.extend_from_slice(&writer.seal(&plaintext));
+            data
+        }
+        None => plaintext,
+    }
+}
+
+///
+/// internal：解密 `seal()` 的结果, 未配置密钥时未加密的文件原样返回
+///
+/// 返回值：
+///     * 明文
+///     * 未配置密钥、密钥错误、文件不完整或被篡改， 返回 Encryption
+///     * 配置了密钥但文件未加密， 返回 Encryption
+pub(crate) fn unseal(key: Option<&EncryptionKey>, path: &Path, data: Vec<u8>) -> Result<Vec<u8>> {
+    if !is_encrypted(&data) {
+        return match key {
+            Some(_) => Err(unencrypted(path)),
+            None => Ok(data),
+        };
+    }
+    let key = check_header(key, path, &data, KIND_FILE)?;
+    match open_frame(key, &data[..HEADER_LEN], 0, &data, HEADER_LEN) {
+        Some((plaintext, end)) if end == data.len() => Ok(plaintext),
+        _ => Err(tampered(path)),
+    }
+}
+
+/// internal：把日志的内容加密为只有一帧的新文件, 用于创建与重写日志；key 为 None 时原样返回
+///
+/// 返回值：(文件内容, 继续追加写入使用的 FrameWriter)
+pub(crate) fn seal_log(
+    key: Option<&EncryptionKey>,
+    plaintext: &[u8],
+) -> (Vec<u8>, Option<FrameWriter>) {
+    match key {
+        Some(key) => {
+            let mut writer = FrameWriter::new(key, KIND_LOG, 0);
+            let mut data = writer.header.clone();
+            data.extend_from_slice(&writer.seal(plaintext));
+            (data, Some(writer))
+        }
+        None => (plaintext.to_vec(), None),
+    }
+}
+
+/// internal：解密后的日志
+pub(crate) struct LogData {
+    /// 所有完整且通过认证的帧的明文；未加密的日志即文件内容
+    pub(crate) data: Vec<u8>,
+    /// 日志是否加密
+    pub(crate) encrypted: bool,
+    // 每一帧结束时的明文字节数与文件字节数
+    ends: Vec<(usize, usize)>,
+}
+
+impl LogData {
+    ///
+    /// internal：保留明文的前 len 字节时, 文件应当保留的字节数
+    /// 加密的日志只能按帧截断, 保留结束位置不超过 len 的帧
+    pub(crate) fn file_len(&self, len: usize) -> usize {
+        if !self.encrypted {
+            return len;
+        }
+        self.ends
+            .iter()
+            .take_while(|(plain, _)| *plain <= len)
+            .last()
+            .map_or(HEADER_LEN, |(_, file)| *file)
+    }
+
+    /// internal：截断到 `file_len(len)` 之后继续追加写入使用的 FrameWriter, 未加密时返回 None
+    pub(crate) fn writer(&self, key: Option<&EncryptionKey>, len: usize) -> Option<FrameWriter> {
+        let key = key.filter(|_| self.encrypted)?;
+        let frames = self
+            .ends
+            .iter()
+            .take_while(|(plain, _)| *plain <= len)
+            .count();
+        Some(FrameWriter::new(key, KIND_LOG, frames as u64))
+    }
+}
+
+///
+/// internal：解密日志, 未配置密钥时未加密的日志原样返回；末尾不完整或无法认证的最后一帧被忽略
+///
+/// 返回值：
+///     * 解密后的日志
+///     * 未配置密钥或密钥错误， 返回 Encryption
+///     * 最后一帧之前的帧无法认证（文件被篡改）， 返回 Encryption
+///     * 配置了密钥但日志不为空且未加密， 返回 Encryption
+pub(crate) fn read_log(key: Option<&EncryptionKey>, path: &Path, data: &[u8]) -> Result<LogData> {
+    if !is_encrypted(data) {
+        if key.is_some() && !data.is_empty() {
+            return Err(unencrypted(path));
+        }
+        return Ok(LogData {
+            data: data.to_vec(),
+            encrypted: false,
+            ends: Vec::new(),
+        });
+    }
+    let key = check_header(key, path, data, KIND_LOG)?;
+    let header = &data[..HEADER_LEN];
+    let mut log = LogData {
+        data: Vec::new(),
+        encrypted: true,
+        ends: Vec::new(),
+    };
+    let mut pos = HEADER_LEN;
+    while pos < data.len() {
+        match open_frame(key, header, log.ends.len() as u64, data, pos) {
+            Some((plaintext, end)) => {
+                log.data.extend_from_slice(&plaintext);
+                log.ends.push((log.data.len(), end));
+                pos = end;
+            }
+            None => {
+                // 之后还有完整的帧说明不是宕机造成的, 而是被篡改
+                let mut len = [0u8; 4];
+                if data.len() - pos >= FRAME_HEADER_LEN {
+                    len.copy_from_slice(&data[pos..pos + 4]);
+                }
+                let end = pos + FRAME_HEADER_LEN + u32::from_le_bytes(len) as usize;
+                if data.len() - pos >= FRAME_HEADER_LEN && end < data.len() {
+                    return Err(tampered(path));
+                }
+                break;
+            }
+        }
+    }
+    Ok(log)
+}
+
+/// internal：追加写入的日志文件, 配置了密钥时每次写入加密为一帧
+#[derive(Debug)]
+pub(crate) struct LogFile {
+    file: File,
+    frames: Option<FrameWriter>,
+}
+
+impl LogFile {
+    /// internal：打开（不存在时创建）path 用于追加写入, 加密的日志需要提供之前的帧数对应的 FrameWriter
+    pub(crate) fn open(path: &Path, frames: Option<FrameWriter>) -> Result<LogFile> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .map_err(|e| io_error(path, e))?;
+        Ok(LogFile { file, frames })
+    }
+
+    /// internal：追加写入 data, 加密时写入一帧
+    pub(crate) fn append(&mut self, data: &[u8]) -> io::Result<()> {
+        match &mut self.frames {
+            Some(writer) => self.file.write_all(&writer.seal(data)),
+            None => self.file.write_all(data),
+        }
+    }
+
+    pub(crate) fn file(&self) -> &File {
+        &self.file
+    }
+
+    /// internal：加密使用的密钥, 未加密时返回 None
+    pub(crate) fn key(&self) -> Option<&EncryptionKey> {
+        self.frames.as_ref().map(|writer| writer.key())
+    }
+}
@@ -0,0 +1,302 @@
+//! 自定义的 value 类型：实现 `CustomValue` trait 并通过 `register_type()` 注册之后,
+//! 可以用 `KVDB::set_custom()` / `get_custom()` / `update_custom()` 保存与访问。
+//!
+//! 自定义类型与内置类型一样支持过期时间、`TYPE`、`DEL`、`RENAME`、事务等与类型无关的操作,
+//! 并且可以持久化：
+//!     * `DUMP` 与快照中保存类型名称与 `encode()` 的结果, 加载时通过注册的类型 `decode()`
+//!     * AOF 与 WAL 中记录为 `setcustom key 类型名称 编码结果的十六进制`
+//!     * 导出的 value 为编码结果的十六进制字符串
+//!
+//! 类型在进程范围内注册, 加载包含自定义类型的数据之前必须先注册, 否则加载失败。
+//! 自定义类型的写入产生类别为 d 的键空间事件, 事件名称为类型名称。
+//!
+//! 示例：
+//! ```
+//! use dbcore::{register_type, CustomValue, Result, KVDB};
+//!
+//! /// 计数器：编码为 8 字节小端序整数
+//! #[derive(Debug, Clone, PartialEq)]
+//! struct Counter(i64);
+//!
+//! impl CustomValue for Counter {
+//!     const TYPE_NAME: &'static str = "counter";
+//!
+//!     fn encode(&self) -> Vec<u8> {
+//!         self.0.to_le_bytes().to_vec()
+//!     }
+//!
+//!     fn decode(data: &[u8]) -> Result<Self> {
+//!         let mut bytes = [0u8; 8];
+//!         bytes.copy_from_slice(data);
+//!         Ok(Counter(i64::from_le_bytes(bytes)))
+//!     }
+//! }
+//!
+//! register_type::<Counter>().unwrap();
+//! let mut db = KVDB::default();
+//! let key = String::from("visits");
+//! db.set_custom(&key, Counter(1)).unwrap();
+//! assert_eq!(Some(2), db.update_custom(&key, |c: &mut Counter| { c.0 += 1; c.0 }).unwrap());
+//! assert_eq!(Some(&Counter(2)), db.get_custom::<Counter>(&key).unwrap());
+//! assert_eq!("counter", db.key_type(&key).unwrap().name());
+//! ```
+
+use crate::aof;
+use crate::{wrong_type, DBError, DBOk, EventClass, Result, Value, ValueType, KVDB};
+use std::any::{Any, TypeId};
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{OnceLock, RwLock};
+
+/// 自定义的 value 类型
+pub trait CustomValue: Clone + fmt::Debug + Send + Sync + 'static {
+    /// 类型名称, 即 `TYPE` 命令的返回值；只能包含小写字母、数字、`-` 与 `_`, 不能与内置类型同名
+    const TYPE_NAME: &'static str;
+
+    /// 序列化为字节序列, 用于 `DUMP`、快照、AOF 与导出
+    fn encode(&self) -> Vec<u8>;
+
+    ///
+    /// 从 `encode()` 的结果还原
+    ///
+    /// 返回值：
+    ///     * 还原的 value
+    ///     * 数据不合法， 返回错误（一般为 InvalidPayload）
+    fn decode(data: &[u8]) -> Result<Self>;
+
+    /// 估算占用的内存, 单位为字节；默认为编码结果的长度
+    fn memory_usage(&self) -> usize {
+        self.encode().len()
+    }
+}
+
+/// internal：`Value` 中保存的自定义类型, 对 `CustomValue` 的类型擦除
+pub(crate) trait CustomObject: fmt::Debug + Send + Sync {
+    fn type_name(&self) -> &'static str;
+    fn encode(&self) -> Vec<u8>;
+    fn memory_usage(&self) -> usize;
+    fn clone_object(&self) -> Box<dyn CustomObject>;
+    fn as_any(&self) -> &dyn Any;
+    fn as_any_mut(&mut self) -> &mut dyn Any;
+}
+
+impl<T: CustomValue> CustomObject for T {
+    fn type_name(&self) -> &'static str {
+        T::TYPE_NAME
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        CustomValue::encode(self)
+    }
+
+    fn memory_usage(&self) -> usize {
+        CustomValue::memory_usage(self)
+    }
+
+    fn clone_object(&self) -> Box<dyn CustomObject> {
+        Box::new(self.clone())
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+impl Clone for Box<dyn CustomObject> {
+    fn clone(&self) -> Self {
+        self.clone_object()
+    }
+}
+
+/// internal：注册的类型
+struct RegisteredType {
+    type_id: TypeId,
+    decode: fn(&[u8]) -> Result<Box<dyn CustomObject>>,
+}
+
+/// internal：进程范围内注册的类型, 以类型名称为 key
+fn types() -> &'static RwLock<HashMap<&'static str, RegisteredType>> {
+    static TYPES: OnceLock<RwLock<HashMap<&'static str, RegisteredType>>> = OnceLock::new();
+    TYPES.get_or_init(Default::default)
+}
+
+fn decode_object<T: CustomValue>(data: &[u8]) -> Result<Box<dyn CustomObject>> {
+    T::decode(data).map(|value| Box::new(value) as Box<dyn CustomObject>)
+}
+
+///
+/// 注册自定义类型 T, 同一个类型可以重复注册
+///
+/// 返回值：
+///     * 注册成功返回 ()
+///     * 类型名称为空或包含不允许的字符， 返回 Syntax
+///     * 与内置类型或者其他已经注册的类型同名， 返回 AlreadyRegistered
+pub fn register_type<T: CustomValue>() -> Result<()> {
+    let name = T::TYPE_NAME;
+    let valid = |c: char| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-' || c == '_';
+    if name.is_empty() || !name.chars().all(valid) {
+        return Err(DBError::Syntax(format!("invalid type name `{}`", name)));
+    }
+    if ["string", "set", "hash", "none"].contains(&name) {
+        return Err(DBError::AlreadyRegistered(String::from(name)));
+    }
+    let mut types = types().write().unwrap_or_else(|e| e.into_inner());
+    match types.get(name) {
+        Some(registered) if registered.type_id == TypeId::of::<T>() => Ok(()),
+        Some(_) => Err(DBError::AlreadyRegistered(String::from(name))),
+        None => {
+            types.insert(
+                name,
+                RegisteredType {
+                    type_id: TypeId::of::<T>(),
+                    decode: decode_object::<T>,
+                },
+            );
+            Ok(())
+        }
+    }
+}
+
+/// internal：已经注册的名称为 name 的类型的名称
+pub(crate) fn registered_name(name: &str) -> Option<&'static str> {
+    types()
+        .read()
+        .unwrap_or_else(|e| e.into_inner())
+        .get_key_value(name)
+        .map(|(k, _)| *k)
+}
+
+/// internal：类型 T 是否已经注册
+fn is_registered<T: CustomValue>() -> bool {
+    let types = types().read().unwrap_or_else(|e| e.into_inner());
+    types
+        .get(T::TYPE_NAME)
+        .is_some_and(|registered| registered.type_id == TypeId::of::<T>())
+}
+
+///
+/// internal：按照类型名称还原自定义类型的 value
+///
+/// 返回值：
+///     * 还原的 value
+///     * 类型没有注册， 返回 InvalidPayload
+///     * 数据不合法， 返回类型的 `decode()` 的错误
+pub(crate) fn decode(type_name: &str, data: &[u8]) -> Result<Value> {
+    let decode = types()
+        .read()
+        .unwrap_or_else(|e| e.into_inner())
+        .get(type_name)
+        .map(|registered| registered.decode)
+        .ok_or_else(|| DBError::InvalidPayload(format!("unknown value type `{}`", type_name)))?;
+    decode(data).map(Value::Custom)
+}
+
+/// internal：字节序列的十六进制表示
+pub(crate) fn to_hex(data: &[u8]) -> String {
+    data.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// internal：解析十六进制表示, 不合法时返回 None
+pub(crate) fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) || !hex.is_ascii() {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).ok())
+        .collect()
+}
+
+impl KVDB {
+    ///
+    /// 获取 key 关联的类型为 T 的自定义 value
+    /// 时间复杂度： O(1)
+    ///
+    /// 返回值：
+    ///     * key存在且value类型正确， 返回value
+    ///     * value类型不是 T， 返回WrongValueType
+    ///     * key 不存在，返回None
+    pub fn get_custom<T: CustomValue>(&self, key: &String) -> Result<Option<&T>> {
+        match self.lookup(key) {
+            Some(Value::Custom(v)) if v.as_any().is::<T>() => Ok(v.as_any().downcast_ref()),
+            Some(other) => Err(wrong_type(key, ValueType::Custom(T::TYPE_NAME), other)),
+            None => Ok(None),
+        }
+    }
+
+    ///
+    /// 将自定义 value 关联到 key, 与 `set()` 相同：覆写旧值（无视类型）并清除原有的 TTL
+    /// 时间复杂度： O(1)
+    ///
+    /// 返回值：
+    ///     * 设置成功返回 OK
+    ///     * 类型 T 没有通过 `register_type()` 注册， 返回 NotSupported
+    ///     * key 不存在并且数据库已满， 返回 OutOfKeysSize
+    pub fn set_custom<T: CustomValue>(&mut self, key: &String, value: T) -> Result<DBOk> {
+        if !is_registered::<T>() {
+            return Err(DBError::NotSupported(format!(
+                "unregistered value type `{}`",
+                T::TYPE_NAME
+            )));
+        }
+        self.store_custom(key, Value::Custom(Box::new(value)))
+    }
+
+    ///
+    /// 在原处修改 key 关联的类型为 T 的自定义 value, 保留 key 的 TTL
+    /// 时间复杂度： O(1) + f 的复杂度
+    ///
+    /// 返回值：
+    ///     * key 存在且 value 类型正确， 返回 f 的返回值
+    ///     * value类型不是 T， 返回WrongValueType
+    ///     * key 不存在，返回None, 不会调用 f
+    pub fn update_custom<T, R, F>(&mut self, key: &String, f: F) -> Result<Option<R>>
+    where
+        T: CustomValue,
+        F: FnOnce(&mut T) -> R,
+    {
+        let res = match self.lookup_mut(key) {
+            Some(Value::Custom(v)) if v.as_any().is::<T>() => {
+                f(v.as_any_mut().downcast_mut().expect("custom value type"))
+            }
+            Some(other) => return Err(wrong_type(key, ValueType::Custom(T::TYPE_NAME), other)),
+            None => return Ok(None),
+        };
+        self.modified(key, 1);
+        if let Some(commands) = &mut self.propagated {
+            let expire_at = self.ttl.get(key).copied();
+            commands.extend(aof::value_commands(key, &self.db[key], expire_at));
+        }
+        self.notify(EventClass::CUSTOM, T::TYPE_NAME, key);
+        Ok(Some(res))
+    }
+
+    /// internal：重放 AOF 与 WAL 中的 `setcustom key type hex`
+    pub(crate) fn set_encoded(&mut self, key: &String, type_name: &str, hex: &str) -> Result<DBOk> {
+        let data = from_hex(hex).ok_or_else(|| {
+            DBError::InvalidPayload(format!("`{}` is not a hexadecimal string", hex))
+        })?;
+        let value = decode(type_name, &data)?;
+        self.store_custom(key, value)
+    }
+
+    /// internal：写入自定义类型的 value 并清除 TTL
+    fn store_custom(&mut self, key: &String, value: Value) -> Result<DBOk> {
+        self.save_undo(key);
+        self.expire_if_needed(key);
+        if !self.db.contains_key(key) && !self.make_room() {
+            return Err(self.out_of_keys());
+        }
+        let event = value.value_type().name();
+        if let Some(commands) = &mut self.propagated {
+            commands.extend(aof::value_commands(key, &value, None));
+        }
+        self.insert_with_ttl(key.clone(), value, None);
+        self.notify(EventClass::CUSTOM, event, key);
+        Ok(DBOk::Ok)
+    }
+}
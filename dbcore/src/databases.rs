@@ -0,0 +1,1100 @@
+//! 多个逻辑数据库, 对应 Redis 的 `SELECT` / `SWAPDB` / `FLUSHDB` / `FLUSHALL`。
+//!
+//! 每个数据库都是一个独立的 `KVDB`, 通过从 0 开始的编号访问, 各自拥有 key 数量上限。
+//! 所有数据库一起保存为一个快照文件, 参见 `snapshot`；开启 AOF 时修改命令写入 AOF 文件, 参见 `aof`；
+//! 开启 WAL 时修改命令写入预写日志, 参见 `wal`。
+//! 设置加密密钥后以上所有文件都加密保存, 参见 `crypto`。
+
+use crate::aof::{self, AofReplay, AppendOnlyFile, FsyncPolicy};
+use crate::command::{Command, CommandRegistry};
+use crate::crypto::{self, EncryptionKey};
+use crate::expires::Expires;
+use crate::export::{self, ExportFormat};
+use crate::history::{self, History, HistoryView, RestorePoint};
+use crate::info::{CommandStat, CommandStats, Info, PersistenceInfo, PubSubInfo};
+use crate::notify::{EventClass, KeyspaceListener, Notifications};
+use crate::pubsub::PubSub;
+use crate::rdb::{self, RdbImport};
+use crate::snapshot::{self, SaveRule};
+use crate::wal::{self, Wal, WalRecovery};
+use crate::{
+    now_millis, DBError, DBOk, EvictionPolicy, ImportedKey, Result, Value, ValueType, KVDB,
+};
+use std::collections::HashMap;
+use std::fs::{self, OpenOptions};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// 默认的数据库数量, 与 Redis 相同
+pub const DEFAULT_DATABASES: usize = 16;
+
+/// 后台保存失败后, 自动保存规则至少间隔多久才会再次触发
+const BGSAVE_RETRY_DELAY: Duration = Duration::from_secs(5);
+
+/// internal：正在执行的后台保存
+#[derive(Debug)]
+struct BackgroundSave {
+    handle: JoinHandle<Result<()>>,
+
+    // 开始保存时的修改次数
+    dirty: u64,
+}
+
+/// internal：后台保存或重写时复制的数据库编号、数据与过期时间
+pub(crate) type DBImage = (usize, HashMap<String, Value>, Expires);
+
+#[derive(Debug)]
+pub struct Databases {
+    dbs: Vec<KVDB>,
+
+    // 创建时间, 用于计算运行时长
+    started: Instant,
+
+    // 每个命令的调用统计
+    commands: CommandStats,
+
+    // 不属于单个数据库的修改次数, 例如 swapdb
+    dirty: u64,
+
+    // 下一次主动过期从该编号的数据库开始
+    expire_cursor: usize,
+
+    // 上次保存快照时的修改次数、时间以及对应的 Unix 时间戳（秒）
+    saved_dirty: u64,
+    last_save: Instant,
+    last_save_time: u64,
+
+    // 上次尝试后台保存的时间与结果
+    last_bgsave_try: Option<Instant>,
+    last_bgsave_ok: bool,
+
+    bgsave: Option<BackgroundSave>,
+
+    // 开启 AOF 时打开的文件, 以及上次重写的结果
+    aof: Option<AppendOnlyFile>,
+    last_aof_rewrite_ok: bool,
+
+    // 开启 WAL 时的日志, 以及检查点归档旧日志与快照的目录
+    wal: Option<Wal>,
+    wal_archive: Option<PathBuf>,
+
+    // 快照、AOF、WAL 以及归档文件使用的加密密钥
+    encryption: Option<EncryptionKey>,
+
+    // 发布/订阅的频道, 不属于任何一个数据库
+    pubsub: PubSub,
+
+    // 键空间通知的配置, 所有数据库共用
+    notifications: Arc<Notifications>,
+
+    // 自定义命令的注册表, 所有数据库共用
+    registry: CommandRegistry,
+}
+
+fn unix_seconds() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+impl Databases {
+    /// 新建 count 个数据库, 每个数据库的 key 数量上限均为 key_size
+    pub fn new(count: usize, key_size: Option<usize>) -> Self {
+        Databases::with_key_sizes(vec![key_size; count])
+    }
+
+    /// 按照 key_sizes 依次新建数据库, 每个数据库使用各自的 key 数量上限
+    pub fn with_key_sizes(key_sizes: Vec<Option<usize>>) -> Self {
+        let pubsub = PubSub::new();
+        let notifications = Arc::new(Notifications::new(pubsub.clone()));
+        let registry = CommandRegistry::new();
+        Databases {
+            dbs: key_sizes
+                .into_iter()
+                .enumerate()
+                .map(|(index, key_size)| {
+                    let mut db = KVDB::new(key_size);
+                    db.set_notifier(Some(notifications.notifier(index)));
+                    db.set_commands(registry.clone());
+                    db
+                })
+                .collect(),
+            started: Instant::now(),
+            commands: CommandStats::new(),
+            dirty: 0,
+            expire_cursor: 0,
+            saved_dirty: 0,
+            last_save: Instant::now(),
+            last_save_time: unix_seconds(),
+            last_bgsave_try: None,
+            last_bgsave_ok: true,
+            bgsave: None,
+            aof: None,
+            last_aof_rewrite_ok: true,
+            wal: None,
+            wal_archive: None,
+            encryption: None,
+            pubsub,
+            notifications,
+            registry,
+        }
+    }
+
+    /// 数据库的数量
+    pub fn len(&self) -> usize {
+        self.dbs.len()
+    }
+
+    /// 是否一个数据库都没有
+    pub fn is_empty(&self) -> bool {
+        self.dbs.is_empty()
+    }
+
+    /// 获取编号为 index 的数据库
+    ///
+    /// 返回值：
+    ///     * 对应的数据库
+    ///     * 编号超出范围， 返回 DBIndexOutOfRange
+    pub fn db(&self, index: usize) -> Result<&KVDB> {
+        self.dbs.get(index).ok_or(DBError::DBIndexOutOfRange(index))
+    }
+
+    /// 获取编号为 index 的数据库用于修改, 返回值同 `db()`
+    pub fn db_mut(&mut self, index: usize) -> Result<&mut KVDB> {
+        self.dbs
+            .get_mut(index)
+            .ok_or(DBError::DBIndexOutOfRange(index))
+    }
+
+    /// 发布/订阅的频道注册表, 复制之后可以在其他线程中发布消息或者订阅
+    pub fn pubsub(&self) -> &PubSub {
+        &self.pubsub
+    }
+
+    ///
+    /// 设置启用的键空间事件类别, 详情查看 `notify`；之后产生的事件立即按照新的类别过滤
+    pub fn set_notify_keyspace_events(&mut self, classes: EventClass) {
+        self.notifications.set_classes(classes);
+    }
+
+    /// 启用的键空间事件类别, 默认不启用任何类别
+    pub fn notify_keyspace_events(&self) -> EventClass {
+        self.notifications.classes()
+    }
+
+    ///
+    /// 注册进程内的键空间事件监听者, 启用的类别中的事件都会通知给它, 参见 `KeyspaceListener`
+    ///
+    /// 返回值：用于 `remove_keyspace_listener()` 的编号
+    pub fn add_keyspace_listener(&mut self, listener: Arc<dyn KeyspaceListener>) -> u64 {
+        self.notifications.add_listener(listener)
+    }
+
+    /// 取消注册编号为 id 的监听者
+    ///
+    /// 返回值：取消成功返回 true; 编号不存在返回 false
+    pub fn remove_keyspace_listener(&mut self, id: u64) -> bool {
+        self.notifications.remove_listener(id)
+    }
+
+    /// 所有数据库共用的自定义命令注册表, 参见 `command`
+    pub fn commands(&self) -> &CommandRegistry {
+        &self.registry
+    }
+
+    /// 注册在所有数据库中都可以使用的自定义命令, 详情查看 `CommandRegistry::register()`
+    pub fn register_command(&mut self, command: Arc<dyn Command>) -> Result<()> {
+        self.registry.register(command)
+    }
+
+    /// 设置所有数据库的字符串压缩阈值, 详情查看 `KVDB::set_compression_threshold()`
+    pub fn set_compression_threshold(&mut self, threshold: Option<usize>) {
+        self.dbs
+            .iter_mut()
+            .for_each(|db| db.set_compression_threshold(threshold));
+    }
+
+    /// 设置所有数据库的淘汰策略, 详情查看 `evict`
+    pub fn set_eviction_policy(&mut self, policy: EvictionPolicy) {
+        self.dbs
+            .iter_mut()
+            .for_each(|db| db.set_eviction_policy(policy));
+    }
+
+    /// 遍历所有数据库及其编号
+    pub fn iter(&self) -> impl Iterator<Item = (usize, &KVDB)> {
+        self.dbs.iter().enumerate()
+    }
+
+    ///
+    /// 交换两个数据库中的数据, key 数量上限仍属于原来的编号
+    /// 时间复杂度 O(1)
+    ///
+    /// 返回值：
+    ///     * 交换成功返回 OK
+    ///     * 编号超出范围， 返回 DBIndexOutOfRange
+    pub fn swapdb(&mut self, index1: usize, index2: usize) -> Result<DBOk> {
+        if let Some(index) = [index1, index2].iter().find(|i| **i >= self.dbs.len()) {
+            return Err(DBError::DBIndexOutOfRange(*index));
+        }
+        if index1 != index2 {
+            self.collect_propagated();
+            let max_keys1 = self.dbs[index1].max_keys();
+            let max_keys2 = self.dbs[index2].max_keys();
+            self.dbs.swap(index1, index2);
+            self.dbs[index1].set_max_keys(max_keys1);
+            self.dbs[index2].set_max_keys(max_keys2);
+            for index in [index1, index2].iter() {
+                let notifier = self.notifications.notifier(*index);
+                self.dbs[*index].set_notifier(Some(notifier));
+            }
+            self.dirty += 1;
+            let command = vec![
+                String::from("swapdb"),
+                index1.to_string(),
+                index2.to_string(),
+            ];
+            if let Some(aof) = &mut self.aof {
+                aof.feed_global(&command);
+            }
+            if let Some(wal) = &self.wal {
+                wal.append(0, &[command]);
+            }
+        }
+        Ok(DBOk::Ok)
+    }
+
+    ///
+    /// 将 key 从数据库 from 移动到数据库 to, 详情查看 `KVDB::move_to()`
+    /// 时间复杂度 O(1)
+    ///
+    /// 返回值：
+    ///     * 移动成功返回 true; key 不存在、目标数据库中已经存在同名 key 或 from 与 to 相同时返回 false
+    ///     * 编号超出范围， 返回 DBIndexOutOfRange
+    ///     * 目标数据库已满， 返回 OutOfKeysSize
+    pub fn move_key(&mut self, key: &String, from: usize, to: usize) -> Result<bool> {
+        if let Some(index) = [from, to].iter().find(|i| **i >= self.dbs.len()) {
+            return Err(DBError::DBIndexOutOfRange(*index));
+        }
+        if from == to {
+            return Ok(false);
+        }
+        let (low, high) = self.dbs.split_at_mut(from.max(to));
+        let (source, target) = if from < to {
+            (&mut low[from], &mut high[0])
+        } else {
+            (&mut high[0], &mut low[to])
+        };
+        source.move_to(key, target)
+    }
+
+    ///
+    /// 清空编号为 index 的数据库, lazy 为 true 时旧数据交给后台线程释放
+    ///
+    /// 返回值：
+    ///     * 清空成功返回 OK
+    ///     * 编号超出范围， 返回 DBIndexOutOfRange
+    pub fn flushdb(&mut self, index: usize, lazy: bool) -> Result<DBOk> {
+        let db = self.db_mut(index)?;
+        if lazy {
+            db.flush_async();
+        } else {
+            db.flush();
+        }
+        Ok(DBOk::Ok)
+    }
+
+    ///
+    /// 清空所有数据库, lazy 为 true 时旧数据交给后台线程释放
+    pub fn flushall(&mut self, lazy: bool) {
+        self.dbs.iter_mut().for_each(|db| {
+            if lazy {
+                db.flush_async();
+            } else {
+                db.flush();
+            }
+        });
+    }
+
+    ///
+    /// 删除所有数据库中已经过期的 key
+    ///
+    /// 返回值：被删除的 key 的数量
+    pub fn purge_expired(&mut self) -> usize {
+        self.dbs.iter_mut().map(|db| db.purge_expired()).sum()
+    }
+
+    ///
+    /// 在 budget 时间内依次对各个数据库执行主动过期, 详情查看 `KVDB::active_expire()`；
+    /// 时间用完时下一次从没有处理的数据库继续
+    ///
+    /// 返回值：被删除的 key 的数量
+    pub fn active_expire(&mut self, budget: Duration) -> usize {
+        let deadline = Instant::now() + budget;
+        let mut total = 0;
+        for _ in 0..self.dbs.len() {
+            let index = self.expire_cursor % self.dbs.len();
+            self.expire_cursor = index + 1;
+            total += self.dbs[index].active_expire(deadline);
+            if Instant::now() >= deadline {
+                break;
+            }
+        }
+        total
+    }
+
+    ///
+    /// 记录一次命令调用, 命令名称不区分大小写
+    pub fn record_command(&mut self, name: &str, elapsed: Duration) {
+        let stat = self.commands.entry(name.to_ascii_lowercase()).or_default();
+        stat.calls += 1;
+        stat.total += elapsed;
+    }
+
+    /// internal：复制所有非空数据库的数据, 供后台线程使用
+    fn images(&self) -> Vec<DBImage> {
+        self.iter()
+            .filter(|(_, db)| !db.db.is_empty())
+            .map(|(index, db)| (index, db.db.clone(), db.ttl.clone()))
+            .collect()
+    }
+
+    /// internal：所有数据库的修改次数之和
+    fn total_dirty(&self) -> u64 {
+        self.dbs.iter().map(|db| db.dirty()).sum::<u64>() + self.dirty
+    }
+
+    /// 上次保存快照（或加载快照）以来的修改次数
+    pub fn changes_since_save(&self) -> u64 {
+        self.total_dirty().saturating_sub(self.saved_dirty)
+    }
+
+    /// 上次成功保存快照的时间, 单位为秒的 Unix 时间戳, 即 `LASTSAVE` 的返回值
+    pub fn last_save(&self) -> u64 {
+        self.last_save_time
+    }
+
+    /// 是否正在后台保存快照
+    pub fn bgsave_in_progress(&self) -> bool {
+        self.bgsave.is_some()
+    }
+
+    /// internal：记录一次成功的保存, dirty 为保存时刻的修改次数
+    fn saved(&mut self, dirty: u64) {
+        self.saved_dirty = dirty;
+        self.last_save = Instant::now();
+        self.last_save_time = unix_seconds();
+    }
+
+    ///
+    /// 将所有数据库保存为快照文件, 保存期间阻塞
+    /// 时间复杂度 O(N), N 为所有数据库中key的数量
+    ///
+    /// 返回值：
+    ///     * 保存成功返回 OK
+    ///     * 后台保存正在执行， 返回 InProgress
+    ///     * 写入文件失败， 返回 Io
+    pub fn save<P: AsRef<Path>>(&mut self, path: P) -> Result<DBOk> {
+        self.poll_bgsave();
+        if self.bgsave.is_some() {
+            return Err(DBError::InProgress(String::from("background save")));
+        }
+        let lsn = self.flush_wal();
+        let data = snapshot::encode(
+            self.dbs
+                .iter()
+                .enumerate()
+                .map(|(i, db)| (i, &db.db, &db.ttl)),
+            lsn,
+        );
+        let data = crypto::seal(self.encryption.as_ref(), data);
+        snapshot::write_file(path.as_ref(), &data)?;
+        let dirty = self.total_dirty();
+        self.saved(dirty);
+        Ok(DBOk::Ok)
+    }
+
+    ///
+    /// 在后台线程中将所有数据库保存为快照文件
+    /// 调用时复制一份当前的数据（相当于 Redis 中 fork 出的子进程看到的数据）, 之后的修改不会写入本次快照,
+    /// 通过 `poll_bgsave()` 或 `wait_bgsave()` 获取保存的结果
+    /// 时间复杂度 O(N), N 为所有数据库中key的数量
+    ///
+    /// 返回值：
+    ///     * 开始保存返回 OK
+    ///     * 后台保存正在执行， 返回 InProgress
+    pub fn bgsave<P: AsRef<Path>>(&mut self, path: P) -> Result<DBOk> {
+        self.poll_bgsave();
+        if self.bgsave.is_some() {
+            return Err(DBError::InProgress(String::from("background save")));
+        }
+        let lsn = self.flush_wal();
+        let images = self.images();
+        let path = path.as_ref().to_path_buf();
+        let key = self.encryption.clone();
+        let handle = thread::spawn(move || {
+            let data =
+                snapshot::encode(images.iter().map(|(index, db, ttl)| (*index, db, ttl)), lsn);
+            snapshot::write_file(&path, &crypto::seal(key.as_ref(), data))
+        });
+        self.last_bgsave_try = Some(Instant::now());
+        self.bgsave = Some(BackgroundSave {
+            handle,
+            dirty: self.total_dirty(),
+        });
+        Ok(DBOk::Ok)
+    }
+
+    ///
+    /// 检查后台保存是否已经结束, 不会阻塞
+    ///
+    /// 返回值：本次调用时刚刚结束的后台保存的结果；没有后台保存或尚未结束时返回 None
+    pub fn poll_bgsave(&mut self) -> Option<Result<DBOk>> {
+        match &self.bgsave {
+            Some(bgsave) if bgsave.handle.is_finished() => self.wait_bgsave(),
+            _ => None,
+        }
+    }
+
+    ///
+    /// 等待后台保存结束
+    ///
+    /// 返回值：后台保存的结果；没有后台保存时返回 None
+    pub fn wait_bgsave(&mut self) -> Option<Result<DBOk>> {
+        let bgsave = self.bgsave.take()?;
+        let res = match bgsave.handle.join() {
+            Ok(res) => res,
+            Err(_) => Err(DBError::Io(String::from("background save thread panicked"))),
+        };
+        self.last_bgsave_ok = res.is_ok();
+        if res.is_ok() {
+            self.saved(bgsave.dirty);
+        }
+        Some(res.map(|_| DBOk::Ok))
+    }
+
+    ///
+    /// 是否满足任意一条自动保存规则, 满足时调用者应当执行 `bgsave()`
+    /// 后台保存正在执行, 或者上次后台保存失败且距离上次尝试不足 5 秒时返回 false
+    pub fn should_save(&self, rules: &[SaveRule]) -> bool {
+        if self.bgsave.is_some() {
+            return false;
+        }
+        if let (false, Some(tried)) = (self.last_bgsave_ok, self.last_bgsave_try) {
+            if tried.elapsed() < BGSAVE_RETRY_DELAY {
+                return false;
+            }
+        }
+        let changes = self.changes_since_save();
+        let elapsed = self.last_save.elapsed().as_secs();
+        rules
+            .iter()
+            .any(|rule| changes >= rule.changes && elapsed >= rule.seconds)
+    }
+
+    ///
+    /// 从快照文件加载数据, 替换所有数据库中现有的数据, 已经过期的 key 不会被加载
+    /// 快照不合法时不会修改任何数据
+    /// 时间复杂度 O(N), N 为快照中key的数量
+    ///
+    /// 返回值：
+    ///     * 加载的 key 的数量
+    ///     * 读取文件失败， 返回 Io
+    ///     * 文件格式、版本或校验和不正确， 返回 InvalidPayload
+    ///     * 快照已加密但没有设置密钥、密钥错误或文件被篡改， 返回 Encryption
+    ///     * 设置了密钥但快照未加密， 返回 Encryption
+    ///     * 快照中的数据库编号超出范围， 返回 DBIndexOutOfRange
+    ///     * 某个数据库中的 key 超过了它的数量上限， 返回 OutOfKeysSize
+    pub fn load<P: AsRef<Path>>(&mut self, path: P) -> Result<usize> {
+        let path = path.as_ref();
+        let data = fs::read(path).map_err(|e| snapshot::io_error(path, e))?;
+        let data = crypto::unseal(self.encryption.as_ref(), path, data)?;
+        let (entries, _) = snapshot::decode(&data, now_millis())?;
+
+        let mut counts = vec![0; self.dbs.len()];
+        for entry in entries.iter() {
+            match counts.get_mut(entry.index) {
+                Some(count) => *count += 1,
+                None => return Err(DBError::DBIndexOutOfRange(entry.index)),
+            }
+        }
+        for (db, count) in self.dbs.iter().zip(counts) {
+            match db.max_keys() {
+                Some(max) if count > max => return Err(DBError::OutOfKeysSize(max)),
+                _ => {}
+            }
+        }
+
+        self.dbs.iter_mut().for_each(|db| db.flush());
+        let loaded = entries.len();
+        for entry in entries {
+            self.dbs[entry.index].load_key(entry.key, entry.value, entry.expire_at);
+        }
+        let dirty = self.total_dirty();
+        self.saved(dirty);
+        Ok(loaded)
+    }
+
+    ///
+    /// 从 Redis RDB 文件导入数据, 同名的 key 会被覆盖；memkv 不支持的列表、有序集合会被跳过并在结果中列出
+    /// RDB 不合法时不会修改任何数据
+    /// 时间复杂度 O(N), N 为 RDB 中key的数量
+    ///
+    /// 返回值：
+    ///     * 导入的结果, 包括导入的 key 的数量、已经过期的 key 的数量以及被跳过的 key
+    ///     * 读取文件失败， 返回 Io
+    ///     * 文件格式或校验和不正确， 返回 InvalidPayload
+    ///     * 包含无法解析的类型（stream、module 等）或版本过高， 返回 NotSupported
+    ///     * RDB 中的数据库编号超出范围， 返回 DBIndexOutOfRange
+    ///     * 某个数据库中的 key 超过了它的数量上限， 返回 OutOfKeysSize
+    pub fn import_rdb<P: AsRef<Path>>(&mut self, path: P) -> Result<RdbImport> {
+        let path = path.as_ref();
+        let data = fs::read(path).map_err(|e| snapshot::io_error(path, e))?;
+        let (version, entries) = rdb::parse(&data)?;
+        let (report, values) = rdb::prepare(version, entries);
+        self.import_keys(values)?;
+        Ok(report)
+    }
+
+    ///
+    /// 以 JSON Lines 或 CSV 格式导出所有数据库中的 key, 包括类型、剩余生存时间与 value, 格式参见 `export`
+    /// 时间复杂度 O(N*log(N)), N 为所有数据库中key的数量
+    ///
+    /// 参数说明：
+    ///     * out 导出的数据写入的目标
+    ///     * pattern 只导出匹配该模式的 key
+    ///     * value_type 只导出指定类型的 key
+    ///
+    /// 返回值：
+    ///     * 导出的 key 的数量
+    ///     * 写入失败， 返回 Io
+    pub fn export<W: Write>(
+        &self,
+        out: &mut W,
+        format: ExportFormat,
+        pattern: Option<&str>,
+        value_type: Option<ValueType>,
+    ) -> Result<usize> {
+        let write = |out: &mut W, text: &str| {
+            out.write_all(text.as_bytes())
+                .map_err(|e| DBError::Io(e.to_string()))
+        };
+        write(out, &export::header(format))?;
+        let mut exported = 0;
+        for (index, db) in self.dbs.iter().enumerate() {
+            let (text, count) = db.export_records(index, format, pattern, value_type);
+            write(out, &text)?;
+            exported += count;
+        }
+        out.flush().map_err(|e| DBError::Io(e.to_string()))?;
+        Ok(exported)
+    }
+
+    ///
+    /// 导入 `export()` 导出的 JSON Lines 或 CSV 数据, 同名的 key 会被覆盖
+    /// 数据不合法时不会修改任何数据
+    /// 时间复杂度 O(N), N 为导入的key的数量
+    ///
+    /// 参数说明：
+    ///     * input 导入的数据的来源
+    ///     * pattern 只导入匹配该模式的 key
+    ///     * value_type 只导入指定类型的 key
+    ///
+    /// 返回值：
+    ///     * 导入的 key 的数量
+    ///     * 读取失败， 返回 Io
+    ///     * 格式不正确或者不是 UTF-8 文本， 返回 InvalidPayload, 携带出错的行号
+    ///     * 数据库编号超出范围， 返回 DBIndexOutOfRange
+    ///     * 某个数据库中的 key 超过了它的数量上限， 返回 OutOfKeysSize
+    pub fn import<R: Read>(
+        &mut self,
+        input: &mut R,
+        format: ExportFormat,
+        pattern: Option<&str>,
+        value_type: Option<ValueType>,
+    ) -> Result<usize> {
+        let mut data = Vec::new();
+        input
+            .read_to_end(&mut data)
+            .map_err(|e| DBError::Io(e.to_string()))?;
+        let text = String::from_utf8(data)
+            .map_err(|_| DBError::InvalidPayload(String::from("input is not valid UTF-8")))?;
+        let keys = export::parse(&text, format, pattern, value_type)?;
+        let imported = keys.len();
+        self.import_keys(keys)?;
+        Ok(imported)
+    }
+
+    /// internal：把导入的 key 写入对应的数据库, 同名的 key 会被覆盖；
+    /// 数据库编号超出范围或者 key 的数量超过上限时不会修改任何数据
+    fn import_keys(&mut self, values: Vec<ImportedKey>) -> Result<()> {
+        if let Some((index, _, _, _)) = values.iter().find(|(i, _, _, _)| *i >= self.dbs.len()) {
+            return Err(DBError::DBIndexOutOfRange(*index));
+        }
+        for (index, db) in self.dbs.iter().enumerate() {
+            let keys = values.iter().filter(|(i, _, _, _)| *i == index);
+            db.check_import(keys.map(|(_, key, _, _)| key))?;
+        }
+        for (index, key, value, expire_at) in values {
+            self.dbs[index].import_key(key, value, expire_at);
+        }
+        Ok(())
+    }
+
+    ///
+    /// 开启 AOF：先重放 path 中已有的命令（替换所有数据库中现有的数据）, 之后的修改命令追加到该文件。
+    /// 文件末尾不完整的命令（写入过程中宕机导致）会被截掉, 其余部分不合法时返回错误且不开启 AOF。
+    /// 开启后调用者应当在每条命令之后调用 `flush_aof()`
+    /// 时间复杂度 O(N), N 为文件中命令的数量
+    ///
+    /// 返回值：
+    ///     * 重放的结果, 参见 `AofReplay`
+    ///     * 读写文件失败， 返回 Io
+    ///     * 文件格式不正确或包含无法识别的命令， 返回 InvalidPayload
+    ///     * 文件已加密但没有设置密钥、密钥错误或文件被篡改， 返回 Encryption
+    ///     * 设置了密钥但文件未加密（空的日志除外）， 返回 Encryption
+    ///     * 重放命令失败时返回该命令的错误, 例如 OutOfKeysSize
+    pub fn open_aof<P: AsRef<Path>>(&mut self, path: P, policy: FsyncPolicy) -> Result<AofReplay> {
+        let path = path.as_ref();
+        if self.aof.is_some() || self.wal.is_some() {
+            return Err(DBError::InProgress(String::from("AOF")));
+        }
+        let data = match fs::read(path) {
+            Ok(data) => data,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(snapshot::io_error(path, e)),
+        };
+        let log = crypto::read_log(self.encryption.as_ref(), path, &data)?;
+        let (commands, valid_len) = aof::parse(&log.data)?;
+
+        self.dbs.iter_mut().for_each(|db| db.flush());
+        let mut index = 0;
+        for command in commands.iter() {
+            if command[0].eq_ignore_ascii_case("select") {
+                index = command
+                    .get(1)
+                    .and_then(|i| i.parse::<usize>().ok())
+                    .ok_or_else(|| DBError::InvalidPayload(String::from("bad select")))?;
+                self.db(index)?;
+            } else {
+                self.replay(index, command)?;
+            }
+        }
+        let file_len = log.file_len(valid_len);
+        if file_len < data.len() {
+            OpenOptions::new()
+                .write(true)
+                .open(path)
+                .and_then(|file| file.set_len(file_len as u64))
+                .map_err(|e| snapshot::io_error(path, e))?;
+        }
+
+        let key = self.encryption.as_ref();
+        let mut aof = AppendOnlyFile::open(path, policy, log.writer(key, valid_len))?;
+        if key.is_some() && !log.encrypted {
+            // 空的文件立即用密钥重写, 写入加密的文件头
+            aof.rewrite_now(&self.images(), key)?;
+        }
+        self.aof = Some(aof);
+        self.dbs.iter_mut().for_each(|db| db.set_propagate(true));
+        self.saved_dirty = self.total_dirty();
+        Ok(AofReplay {
+            commands: commands.len(),
+            truncated: data.len() - file_len,
+        })
+    }
+
+    /// 是否开启了 AOF
+    pub fn aof_enabled(&self) -> bool {
+        self.aof.is_some()
+    }
+
+    /// internal：固定所有数据库判断过期时使用的当前时间, None 表示使用系统时间
+    pub(crate) fn set_clock(&mut self, now: Option<u64>) {
+        self.dbs.iter_mut().for_each(|db| db.set_clock(now));
+    }
+
+    /// internal：重放一条 AOF 或 WAL 中的命令, index 为命令作用的数据库
+    pub(crate) fn replay(&mut self, index: usize, command: &[String]) -> Result<()> {
+        if command[0].eq_ignore_ascii_case("swapdb") {
+            let indexes: Vec<usize> = command[1..].iter().filter_map(|i| i.parse().ok()).collect();
+            match indexes.as_slice() {
+                [index1, index2] if command.len() == 3 => self.swapdb(*index1, *index2).map(|_| ()),
+                _ => Err(DBError::InvalidPayload(String::from("bad swapdb"))),
+            }
+        } else {
+            aof::apply(self.db_mut(index)?, command).map(|_| ())
+        }
+    }
+
+    ///
+    /// 开启 WAL：先从 snapshot 加载检查点（文件存在时）, 再重放 path 中 LSN 更大的记录, 替换所有数据库中现有的数据。
+    /// 日志末尾不完整或损坏的记录会被截掉, 恢复的结果总是日志的一个前缀。
+    /// 开启后调用者应当在每条命令之后调用 `commit_wal()`, 或者调用 `flush_wal()` 并在释放数据库之后通过 `wal()` 提交,
+    /// 使多个连接的提交合并为一次 fsync；`checkpoint()` 与 `save()` 把 LSN 写入快照
+    /// 时间复杂度 O(N), N 为快照中key的数量与日志中记录的数量之和
+    ///
+    /// 参数说明：
+    ///     * path 日志文件, 不存在时创建
+    ///     * snapshot 检查点使用的快照文件
+    ///
+    /// 返回值：
+    ///     * 恢复的结果
+    ///     * 已经开启了 AOF 或 WAL， 返回 InProgress
+    ///     * 读写文件失败， 返回 Io
+    ///     * 快照不合法或日志的文件头不正确， 返回 InvalidPayload
+    ///     * 文件已加密但没有设置密钥、密钥错误或文件被篡改， 返回 Encryption
+    ///     * 设置了密钥但文件未加密（空的日志除外）， 返回 Encryption
+    ///     * 快照或日志中的数据库编号超出范围， 返回 DBIndexOutOfRange
+    pub fn open_wal<P: AsRef<Path>, S: AsRef<Path>>(
+        &mut self,
+        path: P,
+        snapshot: S,
+    ) -> Result<WalRecovery> {
+        let (path, snapshot) = (path.as_ref(), snapshot.as_ref());
+        if self.aof.is_some() || self.wal.is_some() {
+            return Err(DBError::InProgress(String::from("WAL")));
+        }
+        let key = self.encryption.clone();
+        let checkpoint = match fs::read(snapshot) {
+            Ok(data) => {
+                let data = crypto::unseal(key.as_ref(), snapshot, data)?;
+                Some(snapshot::decode(&data, 0)?)
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+            Err(e) => return Err(snapshot::io_error(snapshot, e)),
+        };
+        let data = match fs::read(path) {
+            Ok(data) => data,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(snapshot::io_error(path, e)),
+        };
+        let log = crypto::read_log(key.as_ref(), path, &data)?;
+        let (records, valid_len) = wal::read(&log.data)?;
+
+        self.dbs.iter_mut().for_each(|db| db.flush());
+        let mut checkpoint_lsn = 0;
+        if let Some((entries, lsn)) = checkpoint {
+            checkpoint_lsn = lsn.unwrap_or(0);
+            for entry in entries {
+                self.db_mut(entry.index)?;
+                self.dbs[entry.index].load_key(entry.key, entry.value, entry.expire_at);
+            }
+        }
+        // 按照记录写入时的时间重放, 使过期判断与当时相同
+        let (mut replayed, mut last_timestamp) = (0, 0);
+        for record in records.iter().filter(|r| r.lsn > checkpoint_lsn) {
+            self.set_clock(Some(record.timestamp));
+            let replay = self.replay(record.index, &record.command);
+            self.set_clock(None);
+            replay?;
+            replayed += 1;
+            last_timestamp = record.timestamp;
+        }
+        let last_lsn = records.last().map_or(0, |r| r.lsn).max(checkpoint_lsn);
+
+        let file_len = log.file_len(valid_len);
+        let frames = if log.data.is_empty() {
+            wal::create(path, key.as_ref())?
+        } else {
+            if file_len < data.len() {
+                OpenOptions::new()
+                    .write(true)
+                    .open(path)
+                    .and_then(|file| file.set_len(file_len as u64))
+                    .map_err(|e| snapshot::io_error(path, e))?;
+            }
+            log.writer(key.as_ref(), valid_len)
+        };
+        self.wal = Some(Wal::open(path, snapshot, last_lsn + 1, frames)?);
+        self.dbs.iter_mut().for_each(|db| db.set_propagate(true));
+        self.saved_dirty = self.total_dirty();
+        Ok(WalRecovery {
+            checkpoint_lsn,
+            records: replayed,
+            last_lsn,
+            last_timestamp,
+            truncated: data.len() - file_len.min(data.len()),
+        })
+    }
+
+    /// 开启 WAL 时返回日志的句柄, 用于在其他线程中提交
+    pub fn wal(&self) -> Option<&Wal> {
+        self.wal.as_ref()
+    }
+
+    ///
+    /// 把上次调用以来的修改命令追加到 WAL 的缓冲区（不等待落盘）
+    ///
+    /// 返回值：开启 WAL 时返回最后一条记录的 LSN, 调用者需要在回复之前 `commit()` 该 LSN；未开启时返回 None
+    pub fn flush_wal(&mut self) -> Option<u64> {
+        self.collect_propagated();
+        self.wal.as_ref().map(|wal| wal.last_lsn())
+    }
+
+    ///
+    /// 把上次调用以来的修改命令写入 WAL 并等待落盘；未开启 WAL 时什么都不做
+    ///
+    /// 返回值：
+    ///     * 落盘成功返回 OK
+    ///     * 写入文件失败， 返回 Io
+    pub fn commit_wal(&mut self) -> Result<DBOk> {
+        if let (Some(lsn), Some(wal)) = (self.flush_wal(), &self.wal) {
+            wal.commit(lsn)?;
+        }
+        Ok(DBOk::Ok)
+    }
+
+    ///
+    /// 检查点：提交所有记录, 把当前数据连同最后一条记录的 LSN 写入快照, 然后清空日志
+    /// 时间复杂度 O(N), N 为所有数据库中key的数量
+    ///
+    /// 返回值：
+    ///     * 快照包含的最后一条记录的 LSN
+    ///     * 未开启 WAL， 返回 NotSupported
+    ///     * 后台保存正在执行， 返回 InProgress
+    ///     * 读写文件失败， 返回 Io
+    pub fn checkpoint(&mut self) -> Result<u64> {
+        let wal = match &self.wal {
+            Some(wal) => wal.clone(),
+            None => {
+                return Err(DBError::NotSupported(String::from(
+                    "checkpoint without WAL",
+                )))
+            }
+        };
+        let lsn = self.flush_wal().unwrap_or(0);
+        wal.commit(lsn)?;
+        let archive = self.wal_archive.clone();
+        // 归档被清空的日志以及被覆盖的快照（例如开启 WAL 之前就存在的数据）
+        let key = self.encryption.clone();
+        if let Some(dir) = &archive {
+            history::archive_log(dir, &wal, lsn)?;
+            history::archive_snapshot(dir, wal.snapshot(), key.as_ref())?;
+        }
+        self.save(wal.snapshot())?;
+        if let Some(dir) = &archive {
+            history::archive_snapshot(dir, wal.snapshot(), key.as_ref())?;
+        }
+        wal.reset(lsn + 1, key.as_ref())?;
+        Ok(lsn)
+    }
+
+    ///
+    /// 设置检查点归档的目录：之后每次检查点清空日志之前把日志与快照复制到该目录, 用于恢复更早的时间点
+    /// 目录不存在时创建
+    ///
+    /// 返回值：
+    ///     * 设置成功返回 OK
+    ///     * 创建目录失败， 返回 Io
+    pub fn set_wal_archive<P: AsRef<Path>>(&mut self, dir: P) -> Result<DBOk> {
+        let dir = dir.as_ref();
+        fs::create_dir_all(dir).map_err(|e| snapshot::io_error(dir, e))?;
+        self.wal_archive = Some(dir.to_path_buf());
+        Ok(DBOk::Ok)
+    }
+
+    ///
+    /// 时间点恢复：提交所有记录后, 根据检查点快照、WAL 以及归档目录重建 target 时刻的数据,
+    /// 返回与当前数据库并存的只读历史视图, 详情查看 `History::restore()`
+    ///
+    /// 返回值：
+    ///     * 历史视图
+    ///     * 未开启 WAL， 返回 NotSupported
+    ///     * 其余错误同 `History::restore()`
+    pub fn history(&mut self, target: RestorePoint) -> Result<HistoryView> {
+        let history = match &self.wal {
+            Some(wal) => History::new(wal.path(), wal.snapshot()),
+            None => {
+                return Err(DBError::NotSupported(String::from(
+                    "point-in-time restore without WAL",
+                )))
+            }
+        };
+        let history = match &self.wal_archive {
+            Some(dir) => history.with_archive(dir),
+            None => history,
+        };
+        let history = match &self.encryption {
+            Some(key) => history.with_encryption_key(key.clone()),
+            None => history,
+        };
+        self.commit_wal()?;
+        history.restore(target, self.dbs.len())
+    }
+
+    ///
+    /// 设置加密密钥, 之后保存的快照以及开启的 AOF、WAL 都使用该密钥加密, 读取时使用该密钥解密, 参见 `crypto`
+    /// 应当在 `load()`、`open_aof()` 与 `open_wal()` 之前调用；已经开启 AOF 或 WAL 时使用 `rotate_encryption_key()`
+    ///
+    /// 返回值：
+    ///     * 设置成功返回 OK
+    ///     * 已经开启了 AOF 或 WAL， 返回 InProgress
+    pub fn set_encryption_key(&mut self, key: Option<EncryptionKey>) -> Result<DBOk> {
+        if self.aof.is_some() {
+            return Err(DBError::InProgress(String::from("AOF")));
+        }
+        if self.wal.is_some() {
+            return Err(DBError::InProgress(String::from("WAL")));
+        }
+        self.encryption = key;
+        Ok(DBOk::Ok)
+    }
+
+    ///
+    /// 轮换加密密钥：用新的密钥 key 立即重写 AOF、WAL 与检查点快照（通过检查点）以及归档目录中的文件,
+    /// key 为 None 时改为不加密；会先等待正在执行的后台保存与 AOF 重写结束
+    /// 加密未加密的数据：不设置密钥打开 AOF 或 WAL（或加载快照）, 再轮换为新的密钥
+    /// 只使用快照时不会写入任何文件, 调用者需要之后调用 `save()` 用新的密钥保存快照
+    /// 时间复杂度 O(N), N 为所有数据库中key的数量与归档文件的大小之和
+    ///
+    /// 返回值：
+    ///     * 轮换成功返回 OK
+    ///     * 读写文件失败， 返回 Io
+    ///     * 归档文件无法使用当前的密钥解密， 返回 Encryption
+    pub fn rotate_encryption_key(&mut self, key: Option<EncryptionKey>) -> Result<DBOk> {
+        self.wait_bgsave();
+        self.wait_aof_rewrite();
+        if self.aof.is_some() {
+            self.flush_aof()?;
+            let images = self.images();
+            if let Some(aof) = &mut self.aof {
+                aof.rewrite_now(&images, key.as_ref())?;
+            }
+        }
+        match self.wal.clone() {
+            Some(wal) => {
+                // 先用旧的密钥完成检查点与归档, 再用新的密钥重写归档、快照与清空的日志
+                let lsn = self.checkpoint()?;
+                if let Some(dir) = &self.wal_archive {
+                    history::reencrypt_archive(dir, self.encryption.as_ref(), key.as_ref())?;
+                }
+                self.encryption = key;
+                self.save(wal.snapshot())?;
+                wal.reset(lsn + 1, self.encryption.as_ref())?;
+            }
+            None => self.encryption = key,
+        }
+        Ok(DBOk::Ok)
+    }
+
+    /// internal：把各个数据库记录的修改命令交给 AOF 与 WAL
+    fn collect_propagated(&mut self) {
+        for (index, db) in self.dbs.iter_mut().enumerate() {
+            let commands = db.take_propagated();
+            if let Some(aof) = &mut self.aof {
+                aof.feed(index, &commands);
+            }
+            if let Some(wal) = self.wal.as_ref().filter(|_| !commands.is_empty()) {
+                wal.append(index, &commands);
+            }
+        }
+    }
+
+    ///
+    /// 将上次调用以来的修改命令写入 AOF 文件, 并按照 fsync 策略刷新到磁盘；未开启 AOF 时什么都不做
+    ///
+    /// 返回值：
+    ///     * 写入成功返回 OK
+    ///     * 写入文件失败， 返回 Io
+    pub fn flush_aof(&mut self) -> Result<DBOk> {
+        self.collect_propagated();
+        match &mut self.aof {
+            Some(aof) => aof.write().map(|_| DBOk::Ok),
+            None => Ok(DBOk::Ok),
+        }
+    }
+
+    ///
+    /// 在后台线程中重写 AOF 文件：用重建当前数据所需的最少命令替换文件中的历史命令
+    /// 调用时复制一份当前的数据, 重写期间的修改在重写结束后追加到新文件；
+    /// 通过 `poll_aof_rewrite()` 或 `wait_aof_rewrite()` 完成重写
+    /// 时间复杂度 O(N), N 为所有数据库中key的数量
+    ///
+    /// 返回值：
+    ///     * 开始重写返回 OK
+    ///     * 未开启 AOF， 返回 NotSupported
+    ///     * 重写正在执行， 返回 InProgress
+    ///     * 写入文件失败， 返回 Io
+    pub fn bgrewriteaof(&mut self) -> Result<DBOk> {
+        self.poll_aof_rewrite();
+        self.flush_aof()?;
+        let images = self.images();
+        match &mut self.aof {
+            Some(aof) => aof.start_rewrite(images),
+            None => Err(DBError::NotSupported(String::from(
+                "AOF rewrite without appendonly",
+            ))),
+        }
+    }
+
+    ///
+    /// 检查后台重写是否已经结束, 结束时用新文件替换旧文件, 不会阻塞
+    ///
+    /// 返回值：本次调用时刚刚完成的重写的结果；没有重写或尚未结束时返回 None
+    pub fn poll_aof_rewrite(&mut self) -> Option<Result<DBOk>> {
+        match &self.aof {
+            Some(aof) if aof.rewrite_finished() => self.wait_aof_rewrite(),
+            _ => None,
+        }
+    }
+
+    ///
+    /// 等待后台重写结束, 并用新文件替换旧文件
+    ///
+    /// 返回值：重写的结果；没有重写时返回 None
+    pub fn wait_aof_rewrite(&mut self) -> Option<Result<DBOk>> {
+        if let Err(e) = self.flush_aof() {
+            return Some(Err(e));
+        }
+        let res = self.aof.as_mut()?.finish_rewrite()?;
+        self.last_aof_rewrite_ok = res.is_ok();
+        Some(res)
+    }
+
+    ///
+    /// 获取运行时统计信息, 参见 `Info`
+    /// 时间复杂度 O(N), N 为所有数据库中key的数量
+    pub fn info(&self) -> Info {
+        let mut commands: Vec<(String, CommandStat)> = self
+            .commands
+            .iter()
+            .map(|(name, stat)| (name.clone(), *stat))
+            .collect();
+        commands.sort_by(|a, b| a.0.cmp(&b.0));
+        Info {
+            uptime: self.started.elapsed(),
+            databases: self.dbs.len(),
+            keyspace: self
+                .iter()
+                .map(|(index, db)| (index, db.keyspace_info()))
+                .collect(),
+            commands,
+            persistence: PersistenceInfo {
+                changes_since_last_save: self.changes_since_save(),
+                bgsave_in_progress: self.bgsave.is_some(),
+                last_save_time: self.last_save_time,
+                last_bgsave_ok: self.last_bgsave_ok,
+                aof_enabled: self.aof.is_some(),
+                aof_rewrite_in_progress: self
+                    .aof
+                    .as_ref()
+                    .is_some_and(|aof| aof.rewrite_in_progress()),
+                last_aof_rewrite_ok: self.last_aof_rewrite_ok,
+                wal_enabled: self.wal.is_some(),
+                wal_last_lsn: self.wal.as_ref().map_or(0, |wal| wal.last_lsn()),
+                wal_durable_lsn: self.wal.as_ref().map_or(0, |wal| wal.durable_lsn()),
+                encryption_enabled: self.encryption.is_some(),
+            },
+            pubsub: PubSubInfo {
+                channels: self.pubsub.channels(None).len(),
+                patterns: self.pubsub.numpat(),
+                shard_channels: self.pubsub.shard_channels(None).len(),
+            },
+        }
+    }
+}
@@ -0,0 +1,216 @@
+//! value 的二进制编码, 供 `DUMP` / `RESTORE` 使用。
+//!
+//! 编码格式：
+//!     * 长度使用 LEB128 变长整数编码
+//!     * 字符串编码为 长度 + UTF-8 字节
+//!     * value 编码为 类型字节 + 内容, 集合与哈希表的内容为 元素个数 + 各个元素
+//!     * 压缩的字符串（版本 2）的内容为 原始字节数 + 压缩后的字节数 + LZ4 压缩的数据, 参见 `compress`
+//!     * 自定义类型（版本 3）的内容为 类型名称字符串 + 长度 + `CustomValue::encode()` 的结果, 参见 `custom`
+//!     * DUMP 的结果为 value 编码 + 2 字节格式版本号 + 8 字节 CRC64 校验和（均为小端序）
+
+use crate::checksum::crc64;
+use crate::compress::Compressed;
+use crate::custom;
+use crate::{DBError, Result, Value};
+use std::collections::{HashMap, HashSet};
+
+/// 当前的序列化格式版本号, 只能还原不高于该版本的数据
+pub(crate) const DUMP_VERSION: u16 = 3;
+
+const TYPE_STRING: u8 = 0;
+const TYPE_STRING_LZ4: u8 = 1;
+const TYPE_SET: u8 = 2;
+const TYPE_HASH: u8 = 4;
+const TYPE_CUSTOM: u8 = 5;
+
+pub(crate) fn write_len(buf: &mut Vec<u8>, mut len: u64) {
+    loop {
+        let byte = (len & 0x7f) as u8;
+        len >>= 7;
+        if len == 0 {
+            buf.push(byte);
+            return;
+        }
+        buf.push(byte | 0x80);
+    }
+}
+
+pub(crate) fn write_string(buf: &mut Vec<u8>, s: &str) {
+    write_len(buf, s.len() as u64);
+    buf.extend_from_slice(s.as_bytes());
+}
+
+pub(crate) fn write_value(buf: &mut Vec<u8>, value: &Value) {
+    match value {
+        Value::StringValue(v) => {
+            buf.push(TYPE_STRING);
+            write_string(buf, v);
+        }
+        Value::CompressedString(v) => {
+            buf.push(TYPE_STRING_LZ4);
+            write_len(buf, v.len() as u64);
+            write_len(buf, v.data().len() as u64);
+            buf.extend_from_slice(v.data());
+        }
+        Value::SetValue(v) => {
+            buf.push(TYPE_SET);
+            write_len(buf, v.len() as u64);
+            v.iter().for_each(|member| write_string(buf, member));
+        }
+        Value::HashValue(v) => {
+            buf.push(TYPE_HASH);
+            write_len(buf, v.len() as u64);
+            v.iter().for_each(|(field, value)| {
+                write_string(buf, field);
+                write_string(buf, value);
+            });
+        }
+        Value::Custom(v) => {
+            buf.push(TYPE_CUSTOM);
+            write_string(buf, v.type_name());
+            let data = v.encode();
+            write_len(buf, data.len() as u64);
+            buf.extend_from_slice(&data);
+        }
+    }
+}
+
+fn truncated() -> DBError {
+    DBError::InvalidPayload(String::from("unexpected end of data"))
+}
+
+/// internal：按照上面的编码格式从字节序列中依次读取数据, 数据不完整或不合法时返回 InvalidPayload
+pub(crate) struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    pub(crate) fn new(data: &'a [u8]) -> Self {
+        Reader { data, pos: 0 }
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.pos >= self.data.len()
+    }
+
+    pub(crate) fn read_bytes(&mut self, n: usize) -> Result<&'a [u8]> {
+        if self.data.len() - self.pos < n {
+            return Err(truncated());
+        }
+        let bytes = &self.data[self.pos..self.pos + n];
+        self.pos += n;
+        Ok(bytes)
+    }
+
+    pub(crate) fn read_u8(&mut self) -> Result<u8> {
+        self.read_bytes(1).map(|b| b[0])
+    }
+
+    pub(crate) fn read_len(&mut self) -> Result<u64> {
+        let mut len: u64 = 0;
+        let mut shift = 0;
+        loop {
+            let byte = self.read_u8()?;
+            if shift > 63 {
+                return Err(DBError::InvalidPayload(String::from("length overflow")));
+            }
+            len |= u64::from(byte & 0x7f) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(len);
+            }
+            shift += 7;
+        }
+    }
+
+    pub(crate) fn read_string(&mut self) -> Result<String> {
+        let len = self.read_len()?;
+        if len > (self.data.len() - self.pos) as u64 {
+            return Err(truncated());
+        }
+        let bytes = self.read_bytes(len as usize)?;
+        String::from_utf8(bytes.to_vec())
+            .map_err(|_| DBError::InvalidPayload(String::from("string is not valid UTF-8")))
+    }
+
+    pub(crate) fn read_value(&mut self) -> Result<Value> {
+        match self.read_u8()? {
+            TYPE_STRING => Ok(Value::StringValue(self.read_string()?)),
+            TYPE_STRING_LZ4 => {
+                let len = self.read_len()?;
+                let size = self.read_len()?;
+                if size > (self.data.len() - self.pos) as u64 {
+                    return Err(truncated());
+                }
+                let data = self.read_bytes(size as usize)?.to_vec();
+                Compressed::from_parts(len as usize, data).map(Value::CompressedString)
+            }
+            TYPE_SET => {
+                let len = self.read_len()?;
+                let mut set = HashSet::new();
+                for _ in 0..len {
+                    set.insert(self.read_string()?);
+                }
+                Ok(Value::SetValue(set))
+            }
+            TYPE_HASH => {
+                let len = self.read_len()?;
+                let mut hash = HashMap::new();
+                for _ in 0..len {
+                    let field = self.read_string()?;
+                    hash.insert(field, self.read_string()?);
+                }
+                Ok(Value::HashValue(hash))
+            }
+            TYPE_CUSTOM => {
+                let type_name = self.read_string()?;
+                let len = self.read_len()?;
+                if len > (self.data.len() - self.pos) as u64 {
+                    return Err(truncated());
+                }
+                custom::decode(&type_name, self.read_bytes(len as usize)?)
+            }
+            other => Err(DBError::InvalidPayload(format!(
+                "unknown value type {}",
+                other
+            ))),
+        }
+    }
+}
+
+/// internal：序列化单个 value, 见 `KVDB::dump()`
+pub(crate) fn dump_value(value: &Value) -> Vec<u8> {
+    let mut buf = Vec::new();
+    write_value(&mut buf, value);
+    buf.extend_from_slice(&DUMP_VERSION.to_le_bytes());
+    let crc = crc64(0, &buf);
+    buf.extend_from_slice(&crc.to_le_bytes());
+    buf
+}
+
+/// internal：校验并反序列化 `dump_value()` 的结果
+pub(crate) fn restore_value(payload: &[u8]) -> Result<Value> {
+    if payload.len() < 10 {
+        return Err(truncated());
+    }
+    let (body, footer) = payload.split_at(payload.len() - 8);
+    let mut crc = [0u8; 8];
+    crc.copy_from_slice(footer);
+    if crc64(0, body) != u64::from_le_bytes(crc) {
+        return Err(DBError::InvalidPayload(String::from("checksum mismatch")));
+    }
+    let (data, version) = body.split_at(body.len() - 2);
+    let version = u16::from_le_bytes([version[0], version[1]]);
+    if version > DUMP_VERSION {
+        return Err(DBError::InvalidPayload(format!(
+            "unsupported version {}",
+            version
+        )));
+    }
+    let mut reader = Reader::new(data);
+    let value = reader.read_value()?;
+    if !reader.is_empty() {
+        return Err(DBError::InvalidPayload(String::from("trailing bytes")));
+    }
+    Ok(value)
+}
@@ -0,0 +1,144 @@
+//! dbcore 的错误类型。
+//!
+//! 每个错误都携带足够的上下文（key、期望与实际的类型等）用于展示给用户,
+//! 同时可以通过 `DBError::to_redis_error()` 转换为 Redis 协议中的错误字符串。
+
+use crate::ValueType;
+use std::error::Error;
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DBError {
+    /// key 不存在
+    KeyNotFound(String),
+    /// key 对应的 value 类型与操作要求的类型不一致
+    WrongValueType {
+        key: String,
+        expected: ValueType,
+        actual: ValueType,
+    },
+    /// 数据库中 key 的数量已经达到上限, 携带该上限
+    OutOfKeysSize(usize),
+    /// 目标 key 已经存在
+    KeyAlreadyExists(String),
+    /// 源 key 与目标 key 相同, 携带该 key
+    SameKey(String),
+    /// 序列化数据不合法, 携带具体原因
+    InvalidPayload(String),
+    /// 数据库编号超出范围, 携带该编号
+    DBIndexOutOfRange(usize),
+    /// 命令语法错误, 携带具体原因
+    Syntax(String),
+    /// 参数无法解析为数字, 携带原始参数
+    NotANumber(String),
+    /// 参数超出允许的范围, 携带参数名称
+    OutOfRange(String),
+    /// 不支持的命令或功能, 携带其名称
+    NotSupported(String),
+    /// 读写文件失败, 携带文件路径与具体原因
+    Io(String),
+    /// 同类的后台任务正在执行, 携带任务名称
+    InProgress(String),
+    /// 加密文件无法解密（未配置密钥、密钥错误或数据被篡改）或密钥不合法, 携带具体原因
+    Encryption(String),
+    /// 命令的参数个数不正确, 携带命令名称
+    WrongArity(String),
+    /// 无法识别的命令, 携带命令名称
+    UnknownCommand(String),
+    /// 事务命令的使用方式不正确（嵌套的 MULTI、没有 MULTI 的 EXEC 等）, 携带具体原因
+    Transaction(String),
+    /// 事务中有命令排队失败, EXEC 放弃整个事务
+    ExecAbort,
+    /// 脚本编译或执行失败（包括超时与被终止）, 携带具体原因
+    Script(String),
+    /// 同名的自定义命令或类型已经注册, 携带其名称
+    AlreadyRegistered(String),
+}
+
+impl DBError {
+    ///
+    /// 转换为 Redis 协议中的错误字符串, 供网络协议前端使用,
+    /// 字符串以错误类别（ERR、WRONGTYPE 等）开头
+    pub fn to_redis_error(&self) -> String {
+        match self {
+            DBError::KeyNotFound(_) => String::from("ERR no such key"),
+            DBError::WrongValueType { .. } => {
+                String::from("WRONGTYPE Operation against a key holding the wrong kind of value")
+            }
+            DBError::OutOfKeysSize(max) => format!(
+                "OOM command not allowed when the number of keys reached max_keys ({})",
+                max
+            ),
+            DBError::KeyAlreadyExists(_) => String::from("BUSYKEY Target key name already exists."),
+            DBError::SameKey(_) => String::from("ERR source and destination objects are the same"),
+            DBError::InvalidPayload(_) => {
+                String::from("ERR DUMP payload version or checksum are wrong")
+            }
+            DBError::DBIndexOutOfRange(_) => String::from("ERR DB index is out of range"),
+            DBError::Syntax(_) => String::from("ERR syntax error"),
+            DBError::NotANumber(_) => String::from("ERR value is not an integer or out of range"),
+            // 与 Redis 一致, 过期时间超出范围时回复 invalid expire time
+            DBError::OutOfRange(what) if what == "expire time" => {
+                String::from("ERR invalid expire time")
+            }
+            DBError::OutOfRange(what) => format!("ERR {} is out of range", what),
+            DBError::NotSupported(what) => format!("ERR {} is not supported", what),
+            DBError::Io(reason) => format!("ERR {}", reason),
+            DBError::InProgress(what) => format!("ERR {} already in progress", what),
+            DBError::Encryption(reason) => format!("ERR {}", reason),
+            DBError::WrongArity(name) => {
+                format!("ERR wrong number of arguments for '{}' command", name)
+            }
+            DBError::UnknownCommand(name) => format!("ERR unknown command '{}'", name),
+            DBError::Transaction(reason) => format!("ERR {}", reason),
+            DBError::ExecAbort => {
+                String::from("EXECABORT Transaction discarded because of previous errors.")
+            }
+            DBError::Script(reason) => format!("ERR {}", reason),
+            DBError::AlreadyRegistered(name) => format!("ERR {} is already registered", name),
+        }
+    }
+}
+
+impl fmt::Display for DBError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DBError::KeyNotFound(key) => write!(f, "key `{}` not found", key),
+            DBError::WrongValueType {
+                key,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "key `{}` holds a {} value, but a {} value is required",
+                key, actual, expected
+            ),
+            DBError::OutOfKeysSize(max) => {
+                write!(f, "the database is full, it holds at most {} keys", max)
+            }
+            DBError::KeyAlreadyExists(key) => write!(f, "key `{}` already exists", key),
+            DBError::SameKey(key) => write!(f, "source and destination are the same key `{}`", key),
+            DBError::InvalidPayload(reason) => write!(f, "invalid payload: {}", reason),
+            DBError::DBIndexOutOfRange(index) => {
+                write!(f, "database index {} is out of range", index)
+            }
+            DBError::Syntax(reason) => write!(f, "syntax error: {}", reason),
+            DBError::NotANumber(value) => write!(f, "`{}` is not a valid number", value),
+            DBError::OutOfRange(what) => write!(f, "{} is out of range", what),
+            DBError::NotSupported(what) => write!(f, "{} is not supported", what),
+            DBError::Io(reason) => write!(f, "I/O error: {}", reason),
+            DBError::InProgress(what) => write!(f, "{} is already in progress", what),
+            DBError::Encryption(reason) => write!(f, "encryption error: {}", reason),
+            DBError::WrongArity(name) => write!(f, "wrong number of arguments for `{}`", name),
+            DBError::UnknownCommand(name) => write!(f, "unknown command `{}`", name),
+            DBError::Transaction(reason) => write!(f, "transaction error: {}", reason),
+            DBError::ExecAbort => {
+                write!(f, "transaction discarded because of previous errors")
+            }
+            DBError::Script(reason) => write!(f, "script error: {}", reason),
+            DBError::AlreadyRegistered(name) => write!(f, "`{}` is already registered", name),
+        }
+    }
+}
+
+impl Error for DBError {}
@@ -0,0 +1,100 @@
+//! key 的数量达到上限（`KVDB::set_max_keys()`）时的淘汰策略。
+//!
+//! 需要创建新的 key 而数据库已满时, 先删除已经过期的 key；仍然没有空间时按照淘汰策略删除已有的 key,
+//! 被淘汰的 key 计入 `KeyspaceInfo::evicted_keys`, 像 `DEL` 一样记录到 AOF, 并产生 evicted 键空间事件。
+//! 默认策略为 `NoEviction`, 即拒绝写入并返回 OutOfKeysSize。
+
+use crate::{random_u64, EventClass, KVDB};
+
+/// 数据库已满时的淘汰策略
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum EvictionPolicy {
+    /// 不淘汰, 拒绝创建新的 key
+    #[default]
+    NoEviction,
+    /// 淘汰最久没有被访问的 key
+    AllKeysLru,
+    /// 随机淘汰一个 key
+    AllKeysRandom,
+}
+
+impl EvictionPolicy {
+    /// 策略名称, 与 Redis 的 `maxmemory-policy` 配置一致
+    pub fn name(&self) -> &'static str {
+        match self {
+            EvictionPolicy::NoEviction => "noeviction",
+            EvictionPolicy::AllKeysLru => "allkeys-lru",
+            EvictionPolicy::AllKeysRandom => "allkeys-random",
+        }
+    }
+
+    /// 根据策略名称（忽略大小写）解析策略, 无法识别时返回 None
+    pub fn from_name(name: &str) -> Option<EvictionPolicy> {
+        match name.to_ascii_lowercase().as_str() {
+            "noeviction" => Some(EvictionPolicy::NoEviction),
+            "allkeys-lru" => Some(EvictionPolicy::AllKeysLru),
+            "allkeys-random" => Some(EvictionPolicy::AllKeysRandom),
+            _ => None,
+        }
+    }
+}
+
+impl KVDB {
+    /// 设置数据库已满时的淘汰策略
+    pub fn set_eviction_policy(&mut self, policy: EvictionPolicy) {
+        self.eviction = policy;
+    }
+
+    /// 数据库已满时的淘汰策略
+    pub fn eviction_policy(&self) -> EvictionPolicy {
+        self.eviction
+    }
+
+    ///
+    /// internal：为新的 key 腾出空间, 先删除已经过期的 key, 然后按照淘汰策略淘汰已有的 key
+    /// 时间复杂度：数据库未满时 O(1), 否则 O(N), N 为数据库中 key 的数量
+    ///
+    /// 返回值：可以创建新的 key 时返回 true
+    pub(crate) fn make_room(&mut self) -> bool {
+        if self.can_add_key() {
+            return true;
+        }
+        self.purge_expired();
+        while !self.can_add_key() {
+            match self.eviction_candidate() {
+                Some(key) => self.evict(&key),
+                None => return false,
+            }
+        }
+        true
+    }
+
+    /// internal：按照淘汰策略选出下一个被淘汰的 key
+    fn eviction_candidate(&self) -> Option<String> {
+        match self.eviction {
+            EvictionPolicy::NoEviction => None,
+            EvictionPolicy::AllKeysLru => self
+                .db
+                .keys()
+                .min_by_key(|key| self.access.get(*key).map_or(0, |at| at.get()))
+                .cloned(),
+            EvictionPolicy::AllKeysRandom if self.db.is_empty() => None,
+            EvictionPolicy::AllKeysRandom => {
+                let index = (random_u64() % self.db.len() as u64) as usize;
+                self.db.keys().nth(index).cloned()
+            }
+        }
+    }
+
+    /// internal：淘汰 key
+    fn evict(&mut self, key: &String) {
+        self.save_undo(key);
+        self.db.remove(key);
+        self.ttl.remove(key);
+        self.stats.evicted_keys += 1;
+        self.dirty += 1;
+        self.forget(key);
+        self.propagate("del", key, &[]);
+        self.notify(EventClass::EVICTED, "evicted", key);
+    }
+}
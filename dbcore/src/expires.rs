@@ -0,0 +1,174 @@
+//! key 的过期时间表。
+//!
+//! 除了按 key 查找过期时间之外, 还可以 O(1) 地随机抽取一个设置了过期时间的 key,
+//! 供主动过期（`KVDB::active_expire()`）抽样使用：与 Redis 的 activeExpireCycle 相同,
+//! 每轮随机抽查一批 key 并删除其中已经过期的, 而不需要遍历所有设置了过期时间的 key。
+//!
+//! 另外按过期时间记录了 key 的数量, 并维护已经过期但还没有被删除的 key 的数量（`expired()`）,
+//! 使 `KVDB::size()` 不需要遍历过期时间表。
+
+use crate::random_u64;
+use std::cell::Cell;
+use std::collections::{BTreeMap, HashMap};
+use std::mem;
+
+#[derive(Debug, Clone)]
+pub(crate) struct Expires {
+    // key 的过期时间（毫秒时间戳）以及 key 在 keys 中的位置
+    map: HashMap<String, (u64, usize)>,
+    // 设置了过期时间的 key, 用于随机抽取
+    keys: Vec<String>,
+    // 每个过期时间对应的 key 的数量
+    deadlines: BTreeMap<u64, usize>,
+    // 已经统计到的时间, 以及过期时间不晚于该时间的 key 的数量
+    watermark: Cell<u64>,
+    overdue: Cell<usize>,
+    // 晚于 watermark 的最早的过期时间, 没有时为 u64::MAX
+    next_deadline: Cell<u64>,
+}
+
+impl Default for Expires {
+    fn default() -> Self {
+        Expires {
+            map: HashMap::new(),
+            keys: Vec::new(),
+            deadlines: BTreeMap::new(),
+            watermark: Cell::new(0),
+            overdue: Cell::new(0),
+            next_deadline: Cell::new(u64::MAX),
+        }
+    }
+}
+
+impl Expires {
+    pub(crate) fn get(&self, key: &str) -> Option<&u64> {
+        self.map.get(key).map(|(when, _)| when)
+    }
+
+    /// internal：设置 key 的过期时间, 返回原来的过期时间
+    pub(crate) fn insert(&mut self, key: String, when: u64) -> Option<u64> {
+        self.add_deadline(when);
+        if let Some((old, _)) = self.map.get_mut(&key) {
+            let old = mem::replace(old, when);
+            self.remove_deadline(old);
+            return Some(old);
+        }
+        self.map.insert(key.clone(), (when, self.keys.len()));
+        self.keys.push(key);
+        None
+    }
+
+    /// internal：清除 key 的过期时间, 返回原来的过期时间
+    pub(crate) fn remove(&mut self, key: &str) -> Option<u64> {
+        let (when, index) = self.map.remove(key)?;
+        self.keys.swap_remove(index);
+        if let Some(moved) = self.keys.get(index) {
+            if let Some(entry) = self.map.get_mut(moved) {
+                entry.1 = index;
+            }
+        }
+        self.remove_deadline(when);
+        Some(when)
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.map.clear();
+        self.keys.clear();
+        self.deadlines.clear();
+        self.overdue.set(0);
+        self.next_deadline.set(u64::MAX);
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.keys.len()
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+
+    pub(crate) fn iter(&self) -> impl Iterator<Item = (&String, &u64)> {
+        self.map.iter().map(|(key, (when, _))| (key, when))
+    }
+
+    pub(crate) fn keys(&self) -> impl Iterator<Item = &String> {
+        self.keys.iter()
+    }
+
+    /// internal：随机的一个设置了过期时间的 key, 没有时返回 None
+    pub(crate) fn random_key(&self) -> Option<&String> {
+        if self.keys.is_empty() {
+            return None;
+        }
+        self.keys
+            .get((random_u64() % self.keys.len() as u64) as usize)
+    }
+
+    ///
+    /// internal：过期时间不晚于 now 的 key 的数量
+    /// 时间复杂度：now 与上一次调用之间没有 key 过期时 O(1), 否则为 O(K + log N),
+    /// K 为这段时间内不同的过期时间的数量, 每个过期时间只会被统计一次
+    pub(crate) fn expired(&self, now: u64) -> usize {
+        let watermark = self.watermark.get();
+        if now >= watermark && now < self.next_deadline.get() {
+            return self.overdue.get();
+        }
+        let overdue = if now > watermark {
+            let passed: usize = self
+                .deadlines
+                .range(watermark + 1..=now)
+                .map(|(_, n)| n)
+                .sum();
+            self.overdue.get() + passed
+        } else {
+            // 时钟回拨
+            let restored: usize = self
+                .deadlines
+                .range(now + 1..=watermark)
+                .map(|(_, n)| n)
+                .sum();
+            self.overdue.get() - restored
+        };
+        self.overdue.set(overdue);
+        self.watermark.set(now);
+        self.next_deadline.set(self.first_deadline_after(now));
+        overdue
+    }
+
+    /// internal：晚于 time 的最早的过期时间, 没有时返回 u64::MAX
+    fn first_deadline_after(&self, time: u64) -> u64 {
+        if time == u64::MAX {
+            return u64::MAX;
+        }
+        self.deadlines
+            .range(time + 1..)
+            .next()
+            .map_or(u64::MAX, |(when, _)| *when)
+    }
+
+    /// internal：记录一个过期时间为 when 的 key
+    fn add_deadline(&mut self, when: u64) {
+        *self.deadlines.entry(when).or_insert(0) += 1;
+        if when <= self.watermark.get() {
+            self.overdue.set(self.overdue.get() + 1);
+        } else if when < self.next_deadline.get() {
+            self.next_deadline.set(when);
+        }
+    }
+
+    /// internal：移除一个过期时间为 when 的 key
+    fn remove_deadline(&mut self, when: u64) {
+        if let Some(n) = self.deadlines.get_mut(&when) {
+            *n -= 1;
+            if *n == 0 {
+                self.deadlines.remove(&when);
+            }
+        }
+        if when <= self.watermark.get() {
+            self.overdue.set(self.overdue.get() - 1);
+        } else if when == self.next_deadline.get() {
+            self.next_deadline
+                .set(self.first_deadline_after(self.watermark.get()));
+        }
+    }
+}
@@ -0,0 +1,412 @@
+//! 以 JSON Lines 或 CSV 格式导出、导入数据, 用于调试以及与其他数据处理工具交换数据。
+//!
+//! 每个 key 对应一条记录, 包含以下字段：
+//!     * db    key 所在的数据库编号
+//!     * key   key
+//!     * type  value 的类型： string / set / hash 或者自定义类型的名称
+//!     * ttl   剩余的生存时间, 单位为毫秒；-1 表示没有设置过期时间
+//!     * value 字符串为 JSON 字符串, 集合为字符串数组, 哈希表为值为字符串的对象,
+//!             自定义类型为编码结果的十六进制字符串（导入时需要先注册该类型）
+//!
+//! JSON Lines 每行是一个 JSON 对象, 例如：
+//!     {"db":0,"key":"name","type":"string","ttl":-1,"value":"memkv"}
+//!
+//! CSV 第一行为表头 `db,key,type,ttl,value`, 字段按照 RFC 4180 转义；
+//! 字符串的 value 直接保存, 集合与哈希表的 value 保存为上面的 JSON 文本。
+//!
+//! 导出时集合元素与哈希表的域按字典序排列, 保证同样的数据得到同样的输出。
+
+use crate::custom;
+use crate::json::{self, Json};
+use crate::{glob_match, now_millis, DBError, ImportedKey, Result, Value, ValueType, KVDB};
+use std::collections::{HashMap, HashSet};
+use std::fmt::Write;
+
+const CSV_HEADER: [&str; 5] = ["db", "key", "type", "ttl", "value"];
+
+/// 导出、导入的文件格式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportFormat {
+    /// 每行一个 JSON 对象
+    JsonLines,
+    /// 带表头的 CSV
+    Csv,
+}
+
+impl ExportFormat {
+    /// 格式名称, 同时也是常用的文件扩展名
+    pub fn name(&self) -> &'static str {
+        match self {
+            ExportFormat::JsonLines => "jsonl",
+            ExportFormat::Csv => "csv",
+        }
+    }
+
+    /// 根据格式名称（忽略大小写）解析格式, 无法识别时返回 None
+    pub fn from_name(name: &str) -> Option<ExportFormat> {
+        match name.to_ascii_lowercase().as_str() {
+            "jsonl" | "ndjson" => Some(ExportFormat::JsonLines),
+            "csv" => Some(ExportFormat::Csv),
+            _ => None,
+        }
+    }
+}
+
+/// internal：导出的一条记录
+struct Record {
+    db: usize,
+    key: String,
+    ttl: i64,
+    value: Value,
+}
+
+/// internal：输出 value 对应的 JSON
+fn write_json_value(out: &mut String, value: &Value) {
+    match value {
+        Value::StringValue(s) => json::write_string(out, s),
+        Value::CompressedString(s) => json::write_string(out, &s.decompress()),
+        Value::SetValue(set) => {
+            let mut members: Vec<&String> = set.iter().collect();
+            members.sort();
+            out.push('[');
+            for (i, member) in members.into_iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                json::write_string(out, member);
+            }
+            out.push(']');
+        }
+        Value::HashValue(hash) => {
+            let mut fields: Vec<(&String, &String)> = hash.iter().collect();
+            fields.sort();
+            out.push('{');
+            for (i, (field, value)) in fields.into_iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                json::write_string(out, field);
+                out.push(':');
+                json::write_string(out, value);
+            }
+            out.push('}');
+        }
+        Value::Custom(v) => json::write_string(out, &custom::to_hex(&v.encode())),
+    }
+}
+
+/// internal：输出 CSV 字段, 包含逗号、引号或换行时用引号包围
+fn write_csv_field(out: &mut String, field: &str) {
+    if field.contains(&[',', '"', '\n', '\r'][..]) {
+        out.push('"');
+        out.push_str(&field.replace('"', "\"\""));
+        out.push('"');
+    } else {
+        out.push_str(field);
+    }
+}
+
+fn write_csv_record(out: &mut String, fields: &[&str]) {
+    for (i, field) in fields.iter().enumerate() {
+        if i > 0 {
+            out.push(',');
+        }
+        write_csv_field(out, field);
+    }
+    out.push('\n');
+}
+
+/// internal：格式的开头部分, CSV 为表头
+pub(crate) fn header(format: ExportFormat) -> String {
+    let mut out = String::new();
+    if format == ExportFormat::Csv {
+        write_csv_record(&mut out, &CSV_HEADER);
+    }
+    out
+}
+
+/// internal：把一条记录编码为一行（CSV 的字段中可能包含换行）
+fn encode(format: ExportFormat, record: &Record) -> String {
+    let mut out = String::new();
+    let value_type = record.value.value_type().name();
+    match format {
+        ExportFormat::JsonLines => {
+            let _ = write!(out, "{{\"db\":{},\"key\":", record.db);
+            json::write_string(&mut out, &record.key);
+            let _ = write!(
+                out,
+                ",\"type\":\"{}\",\"ttl\":{},\"value\":",
+                value_type, record.ttl
+            );
+            write_json_value(&mut out, &record.value);
+            out.push_str("}\n");
+        }
+        ExportFormat::Csv => {
+            let value = match &record.value {
+                Value::StringValue(_) | Value::CompressedString(_) => {
+                    record.value.string().unwrap_or_default().into_owned()
+                }
+                other => {
+                    let mut json = String::new();
+                    write_json_value(&mut json, other);
+                    json
+                }
+            };
+            let db = record.db.to_string();
+            let ttl = record.ttl.to_string();
+            write_csv_record(&mut out, &[&db, &record.key, value_type, &ttl, &value]);
+        }
+    }
+    out
+}
+
+impl KVDB {
+    /// internal：按照 key 的字典序把符合条件的 key 编码为记录, 已经过期的 key 不会被导出
+    ///
+    /// 返回值：(编码结果, 导出的 key 的数量)
+    pub(crate) fn export_records(
+        &self,
+        index: usize,
+        format: ExportFormat,
+        pattern: Option<&str>,
+        value_type: Option<ValueType>,
+    ) -> (String, usize) {
+        let now = now_millis();
+        let mut keys: Vec<&String> = self
+            .db
+            .iter()
+            .filter(|(key, _)| !self.is_expired(key))
+            .filter(|(_, value)| value_type.is_none_or(|t| t == value.value_type()))
+            .filter(|(key, _)| pattern.is_none_or(|p| glob_match(p, key)))
+            .map(|(key, _)| key)
+            .collect();
+        keys.sort();
+        let mut out = String::new();
+        for key in keys.iter() {
+            let ttl = match self.ttl.get(key) {
+                Some(when) => when.saturating_sub(now).max(1) as i64,
+                None => -1,
+            };
+            let record = Record {
+                db: index,
+                key: (*key).clone(),
+                ttl,
+                value: self.db[*key].clone(),
+            };
+            out.push_str(&encode(format, &record));
+        }
+        (out, keys.len())
+    }
+}
+
+/// internal：解析 CSV 文本, 返回所有记录及其所在的行号
+fn parse_csv(text: &str) -> std::result::Result<Vec<(usize, Vec<String>)>, String> {
+    let mut records = Vec::new();
+    let mut chars = text.chars().peekable();
+    let mut line = 1;
+    while chars.peek().is_some() {
+        let start = line;
+        let mut fields = Vec::new();
+        let mut field = String::new();
+        loop {
+            match chars.next() {
+                Some('"') if field.is_empty() => loop {
+                    match chars.next() {
+                        Some('"') if chars.peek() == Some(&'"') => {
+                            chars.next();
+                            field.push('"');
+                        }
+                        Some('"') => break,
+                        Some(c) => {
+                            if c == '\n' {
+                                line += 1;
+                            }
+                            field.push(c);
+                        }
+                        None => return Err(format!("line {}: unterminated quoted field", start)),
+                    }
+                },
+                Some(',') => fields.push(std::mem::take(&mut field)),
+                Some('\r') if chars.peek() == Some(&'\n') => {}
+                Some('\n') | None => {
+                    line += 1;
+                    fields.push(field);
+                    break;
+                }
+                Some(c) => field.push(c),
+            }
+        }
+        if fields.len() > 1 || !fields[0].is_empty() {
+            records.push((start, fields));
+        }
+    }
+    Ok(records)
+}
+
+/// internal：把 JSON 转换为 value_type 类型的 value
+fn json_to_value(value_type: ValueType, json: Json) -> std::result::Result<Value, String> {
+    let expect_string = |json: Json| match json {
+        Json::String(s) => Ok(s),
+        other => Err(format!("expected string, found {}", other.kind())),
+    };
+    match (value_type, json) {
+        (ValueType::String, json) => expect_string(json).map(Value::StringValue),
+        (ValueType::Set, Json::Array(items)) if !items.is_empty() => {
+            let set = items
+                .into_iter()
+                .map(expect_string)
+                .collect::<std::result::Result<HashSet<String>, String>>()?;
+            Ok(Value::SetValue(set))
+        }
+        (ValueType::Hash, Json::Object(members)) if !members.is_empty() => {
+            let mut hash = HashMap::new();
+            for (field, value) in members {
+                hash.insert(field, expect_string(value)?);
+            }
+            Ok(Value::HashValue(hash))
+        }
+        (ValueType::Set, Json::Array(_)) | (ValueType::Hash, Json::Object(_)) => {
+            Err(format!("{} must not be empty", value_type))
+        }
+        (ValueType::Set, other) => Err(format!("expected array, found {}", other.kind())),
+        (ValueType::Hash, other) => Err(format!("expected object, found {}", other.kind())),
+        (ValueType::Custom(name), json) => {
+            let hex = expect_string(json)?;
+            let data = custom::from_hex(&hex)
+                .ok_or_else(|| format!("`{}` is not a hexadecimal string", hex))?;
+            custom::decode(name, &data).map_err(|e| e.to_string())
+        }
+    }
+}
+
+/// internal：根据各字段的文本构造记录
+fn to_record(
+    db: i64,
+    key: String,
+    value_type: &str,
+    ttl: i64,
+    value: Json,
+) -> std::result::Result<Record, String> {
+    if db < 0 {
+        return Err(format!("invalid db {}", db));
+    }
+    if ttl <= 0 && ttl != -1 {
+        return Err(format!("invalid ttl {}", ttl));
+    }
+    let value_type =
+        ValueType::from_name(value_type).ok_or_else(|| format!("unknown type `{}`", value_type))?;
+    Ok(Record {
+        db: db as usize,
+        key,
+        ttl,
+        value: json_to_value(value_type, value)?,
+    })
+}
+
+fn parse_json_record(line: &str) -> std::result::Result<Record, String> {
+    let members = match Json::parse(line)? {
+        Json::Object(members) => members,
+        other => return Err(format!("expected object, found {}", other.kind())),
+    };
+    let (mut db, mut key, mut value_type, mut ttl, mut value) = (None, None, None, None, None);
+    for (name, json) in members {
+        match (name.as_str(), json) {
+            ("db", Json::Number(n)) => db = Some(n),
+            ("key", Json::String(s)) => key = Some(s),
+            ("type", Json::String(s)) => value_type = Some(s),
+            ("ttl", Json::Number(n)) => ttl = Some(n),
+            ("value", json) => value = Some(json),
+            (name, json) if CSV_HEADER.contains(&name) => {
+                return Err(format!("bad `{}` field: {}", name, json.kind()))
+            }
+            (name, _) => return Err(format!("unknown field `{}`", name)),
+        }
+    }
+    match (key, value_type, value) {
+        (Some(key), Some(value_type), Some(value)) => {
+            to_record(db.unwrap_or(0), key, &value_type, ttl.unwrap_or(-1), value)
+        }
+        _ => Err(String::from("`key`, `type` and `value` are required")),
+    }
+}
+
+fn parse_csv_record(fields: Vec<String>) -> std::result::Result<Record, String> {
+    if fields.len() != CSV_HEADER.len() {
+        return Err(format!(
+            "expected {} fields, found {}",
+            CSV_HEADER.len(),
+            fields.len()
+        ));
+    }
+    let number = |field: &str| {
+        field
+            .parse::<i64>()
+            .map_err(|_| format!("`{}` is not an integer", field))
+    };
+    let mut fields = fields.into_iter();
+    let mut next = || fields.next().unwrap_or_default();
+    let (db, key, value_type, ttl, value) = (next(), next(), next(), next(), next());
+    let json = if ValueType::from_name(&value_type) == Some(ValueType::String) {
+        Json::String(value)
+    } else {
+        Json::parse(&value)?
+    };
+    to_record(number(&db)?, key, &value_type, number(&ttl)?, json)
+}
+
+/// internal：解析 JSON Lines 或 CSV 文本, 返回符合条件的 key
+///
+/// 返回值：
+///     * 需要导入的 key
+///     * 格式不正确， 返回 InvalidPayload, 携带出错的行号
+pub(crate) fn parse(
+    text: &str,
+    format: ExportFormat,
+    pattern: Option<&str>,
+    value_type: Option<ValueType>,
+) -> Result<Vec<ImportedKey>> {
+    let records = match format {
+        ExportFormat::JsonLines => text
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| !line.trim().is_empty())
+            .map(|(i, line)| (i + 1, parse_json_record(line)))
+            .collect::<Vec<_>>(),
+        ExportFormat::Csv => {
+            let mut records = parse_csv(text)
+                .map_err(DBError::InvalidPayload)?
+                .into_iter();
+            match records.next() {
+                Some((_, header)) if header == CSV_HEADER => {}
+                _ => {
+                    return Err(DBError::InvalidPayload(format!(
+                        "line 1: expected CSV header `{}`",
+                        CSV_HEADER.join(",")
+                    )))
+                }
+            }
+            records
+                .map(|(line, fields)| (line, parse_csv_record(fields)))
+                .collect()
+        }
+    };
+
+    let now = now_millis();
+    let mut keys = Vec::new();
+    for (line, record) in records {
+        let record = record
+            .map_err(|reason| DBError::InvalidPayload(format!("line {}: {}", line, reason)))?;
+        if value_type.is_some_and(|t| t != record.value.value_type())
+            || pattern.is_some_and(|p| !glob_match(p, &record.key))
+        {
+            continue;
+        }
+        let expire_at = if record.ttl > 0 {
+            Some(now + record.ttl as u64)
+        } else {
+            None
+        };
+        keys.push((record.db, record.key, record.value, expire_at));
+    }
+    Ok(keys)
+}
@@ -0,0 +1,336 @@
+//! 时间点恢复（point-in-time restore）与只读的历史视图。
+//!
+//! WAL 中的每条记录都带有 LSN 与写入时间, 检查点快照记录了它包含的最后一条记录的 LSN, 参见 `wal`。
+//! 从不晚于目标的最新快照出发, 按照记录写入时的时间依次重放之后的记录, 就得到任意 LSN 或时刻的数据；
+//! 重放与视图都使用固定的时间判断过期, 因此看到的 TTL 与当时相同。
+//!
+//! 检查点会清空日志, 默认只能恢复到最近一次检查点及之后的时间点。
+//! 设置归档目录（`Databases::set_wal_archive()`）后, 每次检查点把被清空的日志与快照保存到该目录：
+//!     * wal-<最后一条记录的 LSN>.log
+//!     * snapshot-<快照的 LSN>.mkdb
+//! LSN 补零到 20 位, 文件名的顺序即 LSN 的顺序。
+//!
+//! 恢复只读取文件, 不会修改日志或快照, 可以在数据库运行期间执行。
+//! 归档文件与日志、快照使用相同的密钥加密, 轮换密钥时一起重写。
+
+use crate::crypto::{self, EncryptionKey};
+use crate::databases::Databases;
+use crate::snapshot::{self, io_error, write_file};
+use crate::wal::{self, Wal, WalRecord};
+use crate::{now_millis, DBError, Result, KVDB};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const SNAPSHOT_PREFIX: &str = "snapshot-";
+const SNAPSHOT_SUFFIX: &str = ".mkdb";
+const WAL_PREFIX: &str = "wal-";
+const WAL_SUFFIX: &str = ".log";
+
+/// 时间点恢复的目标
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RestorePoint {
+    /// 该 LSN 的记录执行之后的数据
+    Lsn(u64),
+    /// 该毫秒时间戳时的数据
+    Time(u64),
+}
+
+/// 历史视图对应的时间点
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RestoredPoint {
+    /// 最后一条重放的记录的 LSN
+    pub lsn: u64,
+    /// 视图判断过期使用的毫秒时间戳
+    pub timestamp: u64,
+    /// 作为起点的快照的 LSN, 没有快照时为 0
+    pub base_lsn: u64,
+    /// 重放的记录数量
+    pub records: usize,
+}
+
+/// 只读的历史视图, 与当前数据库互不影响
+#[derive(Debug)]
+pub struct HistoryView {
+    dbs: Databases,
+    point: RestoredPoint,
+}
+
+impl HistoryView {
+    /// 视图对应的时间点
+    pub fn point(&self) -> RestoredPoint {
+        self.point
+    }
+
+    /// 获取编号为 index 的数据库, 返回值同 `Databases::db()`
+    pub fn db(&self, index: usize) -> Result<&KVDB> {
+        self.dbs.db(index)
+    }
+
+    /// 遍历所有数据库及其编号
+    pub fn iter(&self) -> impl Iterator<Item = (usize, &KVDB)> {
+        self.dbs.iter()
+    }
+
+    /// 转换为可以修改的数据库, 例如保存为快照；转换后使用系统时间判断过期
+    pub fn into_databases(mut self) -> Databases {
+        self.dbs.set_clock(None);
+        self.dbs
+    }
+}
+
+/// 时间点恢复使用的文件：WAL、检查点快照以及可选的归档目录
+#[derive(Debug, Clone)]
+pub struct History {
+    wal: PathBuf,
+    snapshot: PathBuf,
+    archive: Option<PathBuf>,
+    key: Option<EncryptionKey>,
+}
+
+/// internal：归档文件的名称
+fn archived_name(prefix: &str, lsn: u64, suffix: &str) -> String {
+    format!("{}{:020}{}", prefix, lsn, suffix)
+}
+
+/// internal：解析归档文件名称中的 LSN, 不是该类归档文件时返回 None
+fn archived_lsn(name: &str, prefix: &str, suffix: &str) -> Option<u64> {
+    name.strip_prefix(prefix)?
+        .strip_suffix(suffix)?
+        .parse()
+        .ok()
+}
+
+/// internal：读取文件, 文件不存在时返回 None
+fn read_optional(path: &Path) -> Result<Option<Vec<u8>>> {
+    match fs::read(path) {
+        Ok(data) => Ok(Some(data)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(io_error(path, e)),
+    }
+}
+
+/// internal：检查点清空日志之前, 把日志中的记录复制到归档目录
+pub(crate) fn archive_log(dir: &Path, wal: &Wal, lsn: u64) -> Result<()> {
+    if let Some(data) = read_optional(wal.path())? {
+        let log = crypto::read_log(wal.key().as_ref(), wal.path(), &data)?;
+        let (records, valid_len) = wal::read(&log.data)?;
+        if !records.is_empty() {
+            write_file(
+                &dir.join(archived_name(WAL_PREFIX, lsn, WAL_SUFFIX)),
+                &data[..log.file_len(valid_len)],
+            )?;
+        }
+    }
+    Ok(())
+}
+
+/// internal：把使用 key 加密的快照复制到归档目录, 同一 LSN 的快照已经归档时跳过
+pub(crate) fn archive_snapshot(dir: &Path, path: &Path, key: Option<&EncryptionKey>) -> Result<()> {
+    if let Some(data) = read_optional(path)? {
+        let (_, lsn) = snapshot::decode(&crypto::unseal(key, path, data.clone())?, 0)?;
+        let archived = dir.join(archived_name(
+            SNAPSHOT_PREFIX,
+            lsn.unwrap_or(0),
+            SNAPSHOT_SUFFIX,
+        ));
+        if !archived.exists() {
+            write_file(&archived, &data)?;
+        }
+    }
+    Ok(())
+}
+
+///
+/// internal：用新的密钥 key 重写归档目录中的所有文件, key 为 None 时解密；
+/// 已经使用 key 加密的文件会被跳过, 因此中断之后可以重新执行
+pub(crate) fn reencrypt_archive(
+    dir: &Path,
+    old: Option<&EncryptionKey>,
+    key: Option<&EncryptionKey>,
+) -> Result<()> {
+    for entry in fs::read_dir(dir).map_err(|e| io_error(dir, e))? {
+        let path = entry.map_err(|e| io_error(dir, e))?.path();
+        let name = path.file_name().and_then(|n| n.to_str()).unwrap_or("");
+        let is_snapshot = archived_lsn(name, SNAPSHOT_PREFIX, SNAPSHOT_SUFFIX).is_some();
+        if !is_snapshot && archived_lsn(name, WAL_PREFIX, WAL_SUFFIX).is_none() {
+            continue;
+        }
+        let data = fs::read(&path).map_err(|e| io_error(&path, e))?;
+        let done = match key {
+            Some(key) => crypto::encrypted_with(&data, key),
+            None => !crypto::is_encrypted(&data),
+        };
+        if done {
+            continue;
+        }
+        let data = if is_snapshot {
+            crypto::seal(key, crypto::unseal(old, &path, data)?)
+        } else {
+            crypto::seal_log(key, &crypto::read_log(old, &path, &data)?.data).0
+        };
+        write_file(&path, &data)?;
+    }
+    Ok(())
+}
+
+impl History {
+    /// 使用日志文件 wal 与检查点快照 snapshot, 即 `Databases::open_wal()` 的参数
+    pub fn new<P: AsRef<Path>, S: AsRef<Path>>(wal: P, snapshot: S) -> History {
+        History {
+            wal: wal.as_ref().to_path_buf(),
+            snapshot: snapshot.as_ref().to_path_buf(),
+            archive: None,
+            key: None,
+        }
+    }
+
+    /// 同时使用归档目录中的日志与快照, 即 `Databases::set_wal_archive()` 的参数
+    pub fn with_archive<P: AsRef<Path>>(mut self, dir: P) -> History {
+        self.archive = Some(dir.as_ref().to_path_buf());
+        self
+    }
+
+    /// 使用 key 解密加密的日志、快照与归档文件, 即 `Databases::set_encryption_key()` 的参数
+    pub fn with_encryption_key(mut self, key: EncryptionKey) -> History {
+        self.key = Some(key);
+        self
+    }
+
+    /// internal：读取并解密快照, 文件不存在时返回 None
+    fn read_snapshot(&self, path: &Path) -> Result<Option<Vec<u8>>> {
+        match read_optional(path)? {
+            Some(data) => crypto::unseal(self.key.as_ref(), path, data).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    ///
+    /// 重建 target 时刻的数据：从不晚于目标的最新快照出发, 按照写入时的时间重放之后的记录
+    /// 目标为时间时, 重放写入时间不晚于该时间的所有记录
+    /// 时间复杂度 O(N), N 为快照中key的数量与保留的记录数量之和
+    ///
+    /// 参数说明：
+    ///     * target 恢复的目标
+    ///     * databases 视图中数据库的数量
+    ///
+    /// 返回值：
+    ///     * 历史视图
+    ///     * 目标晚于最新的记录或早于保留的最早的记录， 返回 OutOfRange
+    ///     * 归档中缺少需要重放的记录， 返回 InvalidPayload
+    ///     * 读取文件失败， 返回 Io
+    ///     * 文件格式不正确， 返回 InvalidPayload
+    ///     * 文件已加密但没有密钥、密钥错误或文件被篡改， 返回 Encryption
+    ///     * 记录中的数据库编号超出范围， 返回 DBIndexOutOfRange
+    pub fn restore(&self, target: RestorePoint, databases: usize) -> Result<HistoryView> {
+        let mut bases: Vec<(u64, PathBuf)> = Vec::new();
+        let mut segments: Vec<PathBuf> = Vec::new();
+        if let Some(dir) = &self.archive {
+            for entry in fs::read_dir(dir).map_err(|e| io_error(dir, e))? {
+                let path = entry.map_err(|e| io_error(dir, e))?.path();
+                let name = path.file_name().and_then(|n| n.to_str()).unwrap_or("");
+                if let Some(lsn) = archived_lsn(name, SNAPSHOT_PREFIX, SNAPSHOT_SUFFIX) {
+                    bases.push((lsn, path));
+                } else if archived_lsn(name, WAL_PREFIX, WAL_SUFFIX).is_some() {
+                    segments.push(path);
+                }
+            }
+            segments.sort();
+        }
+        segments.push(self.wal.clone());
+
+        // 按照 LSN 合并所有日志, 归档与当前日志可能有重叠的记录
+        let mut records: Vec<WalRecord> = Vec::new();
+        for path in segments.iter() {
+            if let Some(data) = read_optional(path)? {
+                let log = crypto::read_log(self.key.as_ref(), path, &data)?;
+                let last = records.last().map_or(0, |r| r.lsn);
+                records.extend(wal::read(&log.data)?.0.into_iter().filter(|r| r.lsn > last));
+            }
+        }
+        let live = match self.read_snapshot(&self.snapshot)? {
+            Some(data) => {
+                let (entries, lsn) = snapshot::decode(&data, 0)?;
+                Some((lsn.unwrap_or(0), entries))
+            }
+            None => None,
+        };
+
+        let out_of_range = || DBError::OutOfRange(String::from("restore point"));
+        let latest = bases
+            .iter()
+            .map(|(lsn, _)| *lsn)
+            .chain(live.iter().map(|(lsn, _)| *lsn))
+            .chain(records.last().map(|r| r.lsn))
+            .max()
+            .unwrap_or(0);
+        let (lsn, timestamp) = match target {
+            RestorePoint::Lsn(lsn) if lsn > latest => return Err(out_of_range()),
+            RestorePoint::Lsn(lsn) => match records.iter().find(|r| r.lsn == lsn) {
+                Some(record) => (lsn, record.timestamp),
+                None => (lsn, now_millis()),
+            },
+            RestorePoint::Time(time) => {
+                match records.iter().take_while(|r| r.timestamp <= time).last() {
+                    Some(record) => (record.lsn, time),
+                    None => return Err(out_of_range()),
+                }
+            }
+        };
+
+        // 不晚于目标的最新快照, 同一 LSN 时优先使用当前的快照
+        let archived = bases
+            .into_iter()
+            .filter(|(base, _)| *base <= lsn)
+            .max_by_key(|(base, _)| *base);
+        let (base_lsn, entries) = match (live, archived) {
+            (Some((base, entries)), archived)
+                if base <= lsn && archived.as_ref().is_none_or(|(a, _)| *a <= base) =>
+            {
+                (base, entries)
+            }
+            (_, Some((base, path))) => match self.read_snapshot(&path)? {
+                Some(data) => (base, snapshot::decode(&data, 0)?.0),
+                None => return Err(io_error(&path, std::io::ErrorKind::NotFound.into())),
+            },
+            (_, None) => (0, Vec::new()),
+        };
+
+        let replay: Vec<&WalRecord> = records
+            .iter()
+            .filter(|r| r.lsn > base_lsn && r.lsn <= lsn)
+            .collect();
+        let mut expected = base_lsn + 1;
+        for record in replay.iter() {
+            if record.lsn != expected {
+                break;
+            }
+            expected += 1;
+        }
+        if expected <= lsn {
+            return Err(DBError::InvalidPayload(format!(
+                "WAL record {} is missing from the history",
+                expected
+            )));
+        }
+
+        let mut dbs = Databases::new(databases, None);
+        for entry in entries {
+            dbs.db_mut(entry.index)?
+                .insert_with_ttl(entry.key, entry.value, entry.expire_at);
+        }
+        for record in replay.iter() {
+            dbs.set_clock(Some(record.timestamp));
+            dbs.replay(record.index, &record.command)?;
+        }
+        dbs.set_clock(Some(timestamp));
+        Ok(HistoryView {
+            dbs,
+            point: RestoredPoint {
+                lsn,
+                timestamp,
+                base_lsn,
+                records: replay.len(),
+            },
+        })
+    }
+}
@@ -0,0 +1,302 @@
+//! `INFO` 命令使用的运行时统计信息。
+//!
+//! 每个 `KVDB` 记录自己的命中/未命中、过期与淘汰计数；`Databases` 汇总所有数据库,
+//! 并记录命令的调用次数、耗时以及运行时长。
+
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::time::Duration;
+
+/// `INFO` 支持的所有段落名称
+pub const INFO_SECTIONS: [&str; 6] = [
+    "server",
+    "memory",
+    "persistence",
+    "stats",
+    "commandstats",
+    "keyspace",
+];
+
+/// internal：单个数据库的统计计数器。
+/// 命中/未命中在只读的查找中累加, 因此使用 `Cell`
+#[derive(Debug, Default)]
+pub(crate) struct DBStats {
+    pub(crate) hits: Cell<u64>,
+    pub(crate) misses: Cell<u64>,
+    pub(crate) expired_keys: u64,
+    pub(crate) evicted_keys: u64,
+}
+
+impl DBStats {
+    pub(crate) fn hit(&self) {
+        self.hits.set(self.hits.get() + 1);
+    }
+
+    pub(crate) fn miss(&self) {
+        self.misses.set(self.misses.get() + 1);
+    }
+}
+
+/// 单个数据库的 key 统计
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct KeyspaceInfo {
+    /// key 的总数
+    pub keys: usize,
+    /// 设置了生存时间的 key 的数量
+    pub expires: usize,
+    /// 字符串类型的 key 的数量
+    pub strings: usize,
+    /// 集合类型的 key 的数量
+    pub sets: usize,
+    /// 哈希表类型的 key 的数量
+    pub hashes: usize,
+    /// 自定义类型的 key 的数量
+    pub customs: usize,
+    /// 估算的内存占用, 单位为字节
+    pub used_memory: usize,
+    /// 压缩保存的字符串的数量
+    pub compressed_strings: usize,
+    /// 压缩节省的内存, 单位为字节
+    pub compression_saved_bytes: usize,
+    /// 读取 key 时 key 存在的次数
+    pub keyspace_hits: u64,
+    /// 读取 key 时 key 不存在的次数
+    pub keyspace_misses: u64,
+    /// 因过期被删除的 key 的数量
+    pub expired_keys: u64,
+    /// 因内存或 key 数量限制被淘汰的 key 的数量
+    pub evicted_keys: u64,
+}
+
+/// 单个命令的调用统计
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CommandStat {
+    /// 调用次数
+    pub calls: u64,
+    /// 累计耗时
+    pub total: Duration,
+}
+
+impl CommandStat {
+    /// 平均每次调用的耗时, 单位为微秒
+    pub fn usec_per_call(&self) -> f64 {
+        if self.calls == 0 {
+            0.0
+        } else {
+            self.total.as_micros() as f64 / self.calls as f64
+        }
+    }
+}
+
+/// 快照持久化的状态
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PersistenceInfo {
+    /// 上次保存快照以来的修改次数
+    pub changes_since_last_save: u64,
+    /// 是否正在后台保存快照
+    pub bgsave_in_progress: bool,
+    /// 上次成功保存快照的时间, 单位为秒的 Unix 时间戳
+    pub last_save_time: u64,
+    /// 上次后台保存是否成功, 从未执行过时为 true
+    pub last_bgsave_ok: bool,
+    /// 是否开启了 AOF
+    pub aof_enabled: bool,
+    /// 是否正在后台重写 AOF 文件
+    pub aof_rewrite_in_progress: bool,
+    /// 上次 AOF 重写是否成功, 从未执行过时为 true
+    pub last_aof_rewrite_ok: bool,
+    /// 是否开启了 WAL
+    pub wal_enabled: bool,
+    /// WAL 中最后一条记录的 LSN
+    pub wal_last_lsn: u64,
+    /// WAL 中已经写入磁盘的最后一条记录的 LSN
+    pub wal_durable_lsn: u64,
+    /// 是否加密保存快照与日志文件
+    pub encryption_enabled: bool,
+}
+
+/// 发布/订阅的状态
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PubSubInfo {
+    /// 至少有一个订阅者的频道数量
+    pub channels: usize,
+    /// 被订阅的模式数量
+    pub patterns: usize,
+    /// 至少有一个订阅者的分片频道数量
+    pub shard_channels: usize,
+}
+
+/// `INFO` 命令的结果
+#[derive(Debug, Clone, PartialEq)]
+pub struct Info {
+    /// 运行时长
+    pub uptime: Duration,
+    /// 数据库的数量
+    pub databases: usize,
+    /// 每个数据库的编号与 key 统计, 按编号升序
+    pub keyspace: Vec<(usize, KeyspaceInfo)>,
+    /// 每个命令的调用统计, 按命令名称升序
+    pub commands: Vec<(String, CommandStat)>,
+    /// 快照持久化的状态
+    pub persistence: PersistenceInfo,
+    /// 发布/订阅的状态
+    pub pubsub: PubSubInfo,
+}
+
+impl Info {
+    /// 所有数据库估算的内存占用, 单位为字节
+    pub fn used_memory(&self) -> usize {
+        self.keyspace.iter().map(|(_, k)| k.used_memory).sum()
+    }
+
+    /// 所有数据库 key 统计的合计
+    pub fn total(&self) -> KeyspaceInfo {
+        self.keyspace
+            .iter()
+            .fold(KeyspaceInfo::default(), |mut total, (_, k)| {
+                total.keys += k.keys;
+                total.expires += k.expires;
+                total.strings += k.strings;
+                total.sets += k.sets;
+                total.hashes += k.hashes;
+                total.customs += k.customs;
+                total.used_memory += k.used_memory;
+                total.compressed_strings += k.compressed_strings;
+                total.compression_saved_bytes += k.compression_saved_bytes;
+                total.keyspace_hits += k.keyspace_hits;
+                total.keyspace_misses += k.keyspace_misses;
+                total.expired_keys += k.expired_keys;
+                total.evicted_keys += k.evicted_keys;
+                total
+            })
+    }
+
+    /// 所有命令的调用次数之和
+    pub fn total_commands_processed(&self) -> u64 {
+        self.commands.iter().map(|(_, c)| c.calls).sum()
+    }
+
+    ///
+    /// 按 Redis `INFO` 的文本格式输出统计信息
+    ///
+    /// 参数说明：
+    ///     * section 只输出指定段落, 参见 `INFO_SECTIONS`；None 或 "all" 输出所有段落
+    ///
+    /// 返回值：统计信息文本；段落名称不存在时返回空字符串
+    pub fn render(&self, section: Option<&str>) -> String {
+        let section = section.map(|s| s.to_ascii_lowercase());
+        let wanted = |name: &str| match &section {
+            None => true,
+            Some(s) => s == "all" || s == name,
+        };
+        let total = self.total();
+        let mut out = String::new();
+
+        if wanted("server") {
+            out.push_str("# Server\r\n");
+            let _ = write!(out, "uptime_in_seconds:{}\r\n", self.uptime.as_secs());
+            let _ = write!(out, "databases:{}\r\n", self.databases);
+            out.push_str("\r\n");
+        }
+        if wanted("memory") {
+            out.push_str("# Memory\r\n");
+            let _ = write!(out, "used_memory:{}\r\n", total.used_memory);
+            let _ = write!(out, "compressed_strings:{}\r\n", total.compressed_strings);
+            let _ = write!(
+                out,
+                "compression_saved_bytes:{}\r\n",
+                total.compression_saved_bytes
+            );
+            out.push_str("\r\n");
+        }
+        if wanted("persistence") {
+            let p = &self.persistence;
+            out.push_str("# Persistence\r\n");
+            let _ = write!(
+                out,
+                "rdb_changes_since_last_save:{}\r\n",
+                p.changes_since_last_save
+            );
+            let _ = write!(
+                out,
+                "rdb_bgsave_in_progress:{}\r\n",
+                p.bgsave_in_progress as u8
+            );
+            let _ = write!(out, "rdb_last_save_time:{}\r\n", p.last_save_time);
+            let _ = write!(
+                out,
+                "rdb_last_bgsave_status:{}\r\n",
+                if p.last_bgsave_ok { "ok" } else { "err" }
+            );
+            let _ = write!(out, "aof_enabled:{}\r\n", p.aof_enabled as u8);
+            let _ = write!(
+                out,
+                "aof_rewrite_in_progress:{}\r\n",
+                p.aof_rewrite_in_progress as u8
+            );
+            let _ = write!(
+                out,
+                "aof_last_bgrewrite_status:{}\r\n",
+                if p.last_aof_rewrite_ok { "ok" } else { "err" }
+            );
+            let _ = write!(out, "wal_enabled:{}\r\n", p.wal_enabled as u8);
+            let _ = write!(out, "wal_last_lsn:{}\r\n", p.wal_last_lsn);
+            let _ = write!(out, "wal_durable_lsn:{}\r\n", p.wal_durable_lsn);
+            let _ = write!(out, "encryption_enabled:{}\r\n", p.encryption_enabled as u8);
+            out.push_str("\r\n");
+        }
+        if wanted("stats") {
+            out.push_str("# Stats\r\n");
+            let _ = write!(
+                out,
+                "total_commands_processed:{}\r\n",
+                self.total_commands_processed()
+            );
+            let _ = write!(out, "expired_keys:{}\r\n", total.expired_keys);
+            let _ = write!(out, "evicted_keys:{}\r\n", total.evicted_keys);
+            let _ = write!(out, "keyspace_hits:{}\r\n", total.keyspace_hits);
+            let _ = write!(out, "keyspace_misses:{}\r\n", total.keyspace_misses);
+            let _ = write!(out, "pubsub_channels:{}\r\n", self.pubsub.channels);
+            let _ = write!(out, "pubsub_patterns:{}\r\n", self.pubsub.patterns);
+            let _ = write!(
+                out,
+                "pubsub_shardchannels:{}\r\n",
+                self.pubsub.shard_channels
+            );
+            out.push_str("\r\n");
+        }
+        if wanted("commandstats") {
+            out.push_str("# Commandstats\r\n");
+            self.commands.iter().for_each(|(name, c)| {
+                let _ = write!(
+                    out,
+                    "cmdstat_{}:calls={},usec={},usec_per_call={:.2}\r\n",
+                    name,
+                    c.calls,
+                    c.total.as_micros(),
+                    c.usec_per_call()
+                );
+            });
+            out.push_str("\r\n");
+        }
+        if wanted("keyspace") {
+            out.push_str("# Keyspace\r\n");
+            self.keyspace
+                .iter()
+                .filter(|(_, k)| k.keys > 0)
+                .for_each(|(index, k)| {
+                    let _ = write!(
+                        out,
+                        "db{}:keys={},expires={},strings={},sets={},hashes={},customs={}\r\n",
+                        index, k.keys, k.expires, k.strings, k.sets, k.hashes, k.customs
+                    );
+                });
+            out.push_str("\r\n");
+        }
+        out
+    }
+}
+
+/// internal：命令统计表, 由 `Databases::record_command()` 更新
+pub(crate) type CommandStats = HashMap<String, CommandStat>;
@@ -1,11 +1,65 @@
+use std::borrow::Cow;
+use std::cell::Cell;
+use std::collections::hash_map::RandomState;
 use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::hash::{BuildHasher, Hasher};
+use std::mem;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::thread;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
 
-#[derive(Debug, PartialEq, Eq)]
-pub enum DBError {
-    KeyNotFound,
-    WrongValueType,
-    OutOfKeysSize,
-}
+use compress::Compressed;
+use custom::CustomObject;
+use expires::Expires;
+use info::DBStats;
+use notify::Notifier;
+use undo::UndoLog;
+
+mod aof;
+mod checksum;
+mod command;
+mod compress;
+mod crypto;
+mod custom;
+mod databases;
+mod encoding;
+mod error;
+mod evict;
+mod expires;
+mod export;
+mod history;
+mod info;
+mod json;
+mod multi;
+mod notify;
+mod pattern;
+mod pubsub;
+mod rdb;
+mod scan;
+mod snapshot;
+mod undo;
+mod wal;
+
+pub use aof::{AofReplay, FsyncPolicy};
+pub use command::{Command, CommandFlags, CommandRegistry, CommandReply, KeySpec};
+pub use crypto::{EncryptionKey, ENCRYPTION_KEY_ENV, KEY_LEN};
+pub use custom::{register_type, CustomValue};
+pub use databases::{Databases, DEFAULT_DATABASES};
+pub use error::DBError;
+pub use evict::EvictionPolicy;
+pub use export::ExportFormat;
+pub use history::{History, HistoryView, RestorePoint, RestoredPoint};
+pub use info::{CommandStat, Info, KeyspaceInfo, PersistenceInfo, PubSubInfo, INFO_SECTIONS};
+pub use json::Json;
+pub use multi::{Transaction, Versioned};
+pub use notify::{EventClass, KeyspaceEvent, KeyspaceListener};
+pub use pattern::glob_match;
+pub use pubsub::{Message, PubSub, RecvError, Subscriber, DEFAULT_SUBSCRIBER_BUFFER};
+pub use rdb::{RdbImport, SkippedKey, RDB_MAX_VERSION};
+pub use scan::DEFAULT_SCAN_COUNT;
+pub use snapshot::{SaveRule, DEFAULT_SAVE_RULES};
+pub use wal::{Wal, WalRecovery};
 
 #[derive(Debug, PartialEq, Eq)]
 pub enum DBOk {
@@ -13,11 +67,139 @@ pub enum DBOk {
     Nil,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 enum Value {
     StringValue(String),
+    // 超过压缩阈值的字符串, 参见 `compress`
+    CompressedString(Compressed),
     SetValue(HashSet<String>),
     HashValue(HashMap<String, String>),
+    // 通过 `register_type()` 注册的自定义类型, 参见 `custom`
+    Custom(Box<dyn CustomObject>),
+}
+
+/// key 对应的 value 的类型, 即 `TYPE` 命令的返回值
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ValueType {
+    String,
+    Set,
+    Hash,
+    /// 通过 `register_type()` 注册的自定义类型, 携带类型名称
+    Custom(&'static str),
+}
+
+impl ValueType {
+    /// 类型名称, 与 Redis 的 `TYPE` 命令一致
+    pub fn name(&self) -> &'static str {
+        match self {
+            ValueType::String => "string",
+            ValueType::Set => "set",
+            ValueType::Hash => "hash",
+            ValueType::Custom(name) => name,
+        }
+    }
+
+    /// 根据类型名称（忽略大小写）解析类型, 包括已经注册的自定义类型；无法识别时返回 None
+    pub fn from_name(name: &str) -> Option<ValueType> {
+        match name.to_ascii_lowercase().as_str() {
+            "string" => Some(ValueType::String),
+            "set" => Some(ValueType::Set),
+            "hash" => Some(ValueType::Hash),
+            other => custom::registered_name(other).map(ValueType::Custom),
+        }
+    }
+}
+
+impl fmt::Display for ValueType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl Value {
+    fn value_type(&self) -> ValueType {
+        match self {
+            Value::StringValue(_) | Value::CompressedString(_) => ValueType::String,
+            Value::SetValue(_) => ValueType::Set,
+            Value::HashValue(_) => ValueType::Hash,
+            Value::Custom(v) => ValueType::Custom(v.type_name()),
+        }
+    }
+
+    /// internal：集合类型的元素个数, 字符串与自定义类型视为 1 个元素
+    fn elements(&self) -> usize {
+        match self {
+            Value::StringValue(_) | Value::CompressedString(_) | Value::Custom(_) => 1,
+            Value::SetValue(v) => v.len(),
+            Value::HashValue(v) => v.len(),
+        }
+    }
+
+    /// internal：估算 value 占用的内存, 单位为字节
+    fn memory_usage(&self) -> usize {
+        let string_size = |s: &String| mem::size_of::<String>() + s.capacity();
+        mem::size_of::<Value>()
+            + match self {
+                Value::StringValue(v) => v.capacity(),
+                Value::CompressedString(v) => v.memory_usage(),
+                Value::SetValue(v) => v.iter().map(string_size).sum(),
+                Value::HashValue(v) => v
+                    .iter()
+                    .map(|(field, value)| string_size(field) + string_size(value))
+                    .sum(),
+                Value::Custom(v) => v.memory_usage(),
+            }
+    }
+
+    /// internal：字符串类型的内容, 压缩的字符串会被解压；其他类型返回 None
+    fn string(&self) -> Option<Cow<'_, str>> {
+        match self {
+            Value::StringValue(v) => Some(Cow::Borrowed(v)),
+            Value::CompressedString(v) => Some(Cow::Owned(v.decompress())),
+            _ => None,
+        }
+    }
+}
+
+/// internal：导入的 key： (数据库编号, key, value, 过期时间)
+type ImportedKey = (usize, String, Value, Option<u64>);
+
+/// 元素个数超过该值的 value 在 `unlink()` 时交给后台线程释放, 与 Redis 的 LAZYFREE_THRESHOLD 相同
+pub const LAZYFREE_THRESHOLD: usize = 64;
+
+/// internal：当前时间的毫秒时间戳
+fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+/// internal：最近一次分配的 key 版本号, 所有数据库共用, 保证 `SWAPDB`、`MOVE` 之后版本号仍然不会重复
+static LAST_VERSION: AtomicU64 = AtomicU64::new(0);
+
+/// internal：分配一个新的 key 版本号
+fn next_version() -> u64 {
+    LAST_VERSION.fetch_add(1, Ordering::Relaxed) + 1
+}
+
+/// internal：生成一个随机数, 不引入额外依赖,
+/// 利用 `RandomState` 每次构建都会使用不同随机种子的特性
+fn random_u64() -> u64 {
+    let mut hasher = RandomState::new().build_hasher();
+    if let Ok(now) = SystemTime::now().duration_since(UNIX_EPOCH) {
+        hasher.write_u128(now.as_nanos());
+    }
+    hasher.finish()
+}
+
+/// internal：构造类型错误, 携带 key 以及期望与实际的类型
+fn wrong_type(key: &str, expected: ValueType, actual: &Value) -> DBError {
+    DBError::WrongValueType {
+        key: String::from(key),
+        expected,
+        actual: actual.value_type(),
+    }
 }
 
 pub type Result<T> = std::result::Result<T, DBError>;
@@ -25,15 +207,54 @@ pub type Result<T> = std::result::Result<T, DBError>;
 #[derive(Debug)]
 pub struct KVDB {
     db: HashMap<String, Value>,
-    //时间轮
-    ttl: HashMap<String, u64>,
+    // key 的过期时间, 毫秒时间戳
+    ttl: Expires,
 
     // 最多的 keys 数量, None时，无限制
     max_keys: Option<usize>,
+
+    // key 的数量达到上限时的淘汰策略
+    eviction: EvictionPolicy,
+
+    // INFO 使用的统计计数器
+    stats: DBStats,
+
+    // 创建以来数据被修改的次数, 用于判断是否需要保存快照
+    dirty: u64,
+
+    // 开启 AOF 时记录的、还没有写入文件的修改命令；None 表示不记录
+    propagated: Option<Vec<Vec<String>>>,
+
+    // 固定的当前时间（毫秒时间戳）, 用于按记录的时间重放日志以及历史视图；None 表示使用系统时间
+    clock: Option<u64>,
+
+    // 超过该字节数的字符串压缩保存；None 表示不压缩
+    compression: Option<usize>,
+
+    // key 的版本号, key 每次被修改时更新；只保存在内存中
+    versions: HashMap<String, u64>,
+
+    // key 的标记（memcached 的 flags）, 只保存非 0 的标记；value 被覆盖或 key 被删除时清除, 只保存在内存中
+    flags: HashMap<String, u32>,
+
+    // key 最近一次被访问（读写或 TOUCH）的时间, 毫秒时间戳；只读的查找也会更新, 因此使用 `Cell`, 只保存在内存中
+    access: HashMap<String, Cell<u64>>,
+
+    // 产生键空间事件的入口, 只有属于 `Databases` 的数据库才有
+    notifier: Option<Notifier>,
+
+    // `transaction()` 的撤销日志, 每层嵌套的事务一个
+    undo: Vec<UndoLog>,
+
+    // 自定义命令的注册表, 属于 `Databases` 的数据库共用一个
+    commands: CommandRegistry,
 }
 
 pub const DEFAULT_DB_KEY_SIZE: usize = 256;
 
+/// 主动过期每轮抽查的 key 的数量, 与 Redis 相同
+pub const ACTIVE_EXPIRE_SAMPLES: usize = 20;
+
 impl KVDB {
     /// 默认构建KVDB，无限 key size
     pub fn default() -> Self {
@@ -44,8 +265,20 @@ impl KVDB {
     pub fn new(key_size: Option<usize>) -> Self {
         KVDB {
             db: HashMap::new(),
-            ttl: HashMap::new(),
+            ttl: Expires::default(),
             max_keys: key_size,
+            eviction: EvictionPolicy::default(),
+            stats: DBStats::default(),
+            dirty: 0,
+            propagated: None,
+            clock: None,
+            compression: None,
+            versions: HashMap::new(),
+            flags: HashMap::new(),
+            access: HashMap::new(),
+            notifier: None,
+            undo: Vec::new(),
+            commands: CommandRegistry::new(),
         }
     }
 
@@ -62,17 +295,322 @@ impl KVDB {
         }
     }
 
+    /// 修改 key 数量的上限, None 表示无限制；已经存在的 key 不受影响
+    pub fn set_max_keys(&mut self, key_size: Option<usize>) {
+        self.max_keys = key_size;
+    }
+
+    /// key 数量的上限, None 表示无限制
+    pub fn max_keys(&self) -> Option<usize> {
+        self.max_keys
+    }
+
+    /// 设置字符串的压缩阈值：之后写入的超过 threshold 字节的字符串使用 LZ4 压缩保存, None 表示不压缩；
+    /// 已经存在的 value 不受影响, 详情查看 `compress`
+    pub fn set_compression_threshold(&mut self, threshold: Option<usize>) {
+        self.compression = threshold;
+    }
+
+    /// 字符串的压缩阈值, None 表示不压缩
+    pub fn compression_threshold(&self) -> Option<usize> {
+        self.compression
+    }
+
+    /// internal：按照压缩阈值保存字符串
+    fn string_value(&self, value: String) -> Value {
+        match self.compression {
+            Some(threshold) if value.len() > threshold => match Compressed::compress(&value) {
+                Some(compressed) => Value::CompressedString(compressed),
+                None => Value::StringValue(value),
+            },
+            _ => Value::StringValue(value),
+        }
+    }
+
+    /// internal：按照压缩阈值保存从快照、`DUMP` 等处读取的 value
+    fn stored_value(&self, value: Value) -> Value {
+        match value {
+            Value::StringValue(v) => self.string_value(v),
+            other => other,
+        }
+    }
+
+    /// internal：加载快照时写入 key, 字符串按照压缩阈值保存
+    fn load_key(&mut self, key: String, value: Value, expire_at: Option<u64>) {
+        let value = self.stored_value(value);
+        self.insert_with_ttl(key, value, expire_at);
+    }
+
+    /// 创建以来数据被修改的次数, 每个被写入、修改或删除的 key（集合元素、哈希表域）计一次
+    pub fn dirty(&self) -> u64 {
+        self.dirty
+    }
+
+    /// internal：key 被修改了 changes 次（集合元素、哈希表域各计一次）, 为它分配新的版本号
+    fn modified(&mut self, key: &String, changes: u64) {
+        if changes == 0 {
+            return;
+        }
+        self.dirty += changes;
+        let version = next_version();
+        match self.versions.get_mut(key) {
+            Some(v) => *v = version,
+            None => {
+                self.versions.insert(key.clone(), version);
+            }
+        }
+        match self.access.get(key) {
+            Some(at) => at.set(self.now()),
+            None => {
+                self.access.insert(key.clone(), Cell::new(self.now()));
+            }
+        }
+    }
+
+    /// internal：记录 key 被访问, 只更新已经有记录的 key
+    fn accessed(&self, key: &String) {
+        if let Some(at) = self.access.get(key) {
+            at.set(self.now());
+        }
+    }
+
+    /// internal：key 被删除之后清除它的版本号、标记与访问时间
+    fn forget(&mut self, key: &String) {
+        self.versions.remove(key);
+        self.flags.remove(key);
+        self.access.remove(key);
+    }
+
+    ///
+    /// 获取 key 的版本号。key 每次被写入、修改都会得到一个新的版本号,
+    /// 版本号在所有数据库中唯一并且递增, 可以用作 CAS（check-and-set）的令牌。
+    /// 版本号只保存在内存中, 重新加载数据之后所有 key 都会得到新的版本号。
+    /// 时间复杂度 O(1)
+    ///
+    /// 返回值：key 存在返回版本号; 否则返回 None
+    pub fn version(&self, key: &str) -> Option<u64> {
+        if self.is_expired(key) || !self.db.contains_key(key) {
+            return None;
+        }
+        Some(self.versions.get(key).copied().unwrap_or(0))
+    }
+
+    ///
+    /// 获取 key 的标记（memcached 的 flags）, 没有设置过标记的 key 为 0
+    /// 时间复杂度 O(1)
+    ///
+    /// 返回值：key 存在返回标记; 否则返回 None
+    pub fn flags(&self, key: &String) -> Option<u32> {
+        if self.is_expired(key) || !self.db.contains_key(key) {
+            return None;
+        }
+        Some(self.flags.get(key).copied().unwrap_or(0))
+    }
+
+    ///
+    /// 设置 key 的标记。标记随 value 一起保存, value 被覆盖或 key 被删除时清除；
+    /// 标记不会写入快照与日志, 设置标记也不会改变 key 的版本号
+    /// 时间复杂度 O(1)
+    ///
+    /// 返回值：设置成功返回 true; key 不存在返回 false
+    pub fn set_flags(&mut self, key: &String, flags: u32) -> bool {
+        if self.lookup_mut(key).is_none() {
+            return false;
+        }
+        if flags == 0 {
+            self.flags.remove(key);
+        } else {
+            self.flags.insert(key.clone(), flags);
+        }
+        true
+    }
+
+    /// internal：设置产生键空间事件的入口, None 表示不产生事件
+    pub(crate) fn set_notifier(&mut self, notifier: Option<Notifier>) {
+        self.notifier = notifier;
+    }
+
+    /// internal：key 产生了类别为 class 的事件 event
+    fn notify(&self, class: EventClass, event: &'static str, key: &str) {
+        if let Some(notifier) = &self.notifier {
+            notifier.notify(class, event, key);
+        }
+    }
+
+    /// internal：开始（true）或停止（false）记录修改命令
+    pub(crate) fn set_propagate(&mut self, enabled: bool) {
+        self.propagated = if enabled { Some(Vec::new()) } else { None };
+    }
+
+    /// internal：取出记录的修改命令
+    pub(crate) fn take_propagated(&mut self) -> Vec<Vec<String>> {
+        self.propagated.as_mut().map(mem::take).unwrap_or_default()
+    }
+
+    /// internal：固定判断过期时使用的当前时间, None 表示使用系统时间
+    pub(crate) fn set_clock(&mut self, now: Option<u64>) {
+        self.clock = now;
+    }
+
+    /// internal：当前时间的毫秒时间戳
+    fn now(&self) -> u64 {
+        self.clock.unwrap_or_else(now_millis)
+    }
+
+    /// internal：从当前时间开始 ms 毫秒之后的时间戳, 溢出时返回 OutOfRange
+    fn expire_after(&self, ms: u64) -> Result<u64> {
+        self.now()
+            .checked_add(ms)
+            .ok_or_else(|| DBError::OutOfRange(String::from("expire time")))
+    }
+
+    /// internal：从当前时间开始 seconds 秒之后的时间戳, 溢出时返回 OutOfRange
+    fn expire_after_secs(&self, seconds: u64) -> Result<u64> {
+        let ms = seconds
+            .checked_mul(1000)
+            .ok_or_else(|| DBError::OutOfRange(String::from("expire time")))?;
+        self.expire_after(ms)
+    }
+
+    /// internal：是否正在记录修改命令
+    fn propagating(&self) -> bool {
+        self.propagated.is_some()
+    }
+
+    /// internal：记录一条修改命令, 参数为 name key args...
+    fn propagate(&mut self, name: &str, key: &str, args: &[&str]) {
+        if let Some(commands) = &mut self.propagated {
+            let mut command = vec![String::from(name), String::from(key)];
+            command.extend(args.iter().map(|arg| String::from(*arg)));
+            commands.push(command);
+        }
+    }
+
+    /// internal：记录一条参数个数不固定的修改命令, 参数为 name key args...
+    fn propagate_all(&mut self, name: &str, key: &str, args: Vec<String>) {
+        if let Some(commands) = &mut self.propagated {
+            let mut command = vec![String::from(name), String::from(key)];
+            command.extend(args);
+            commands.push(command);
+        }
+    }
+
+    /// internal：数据库已满时返回的错误
+    fn out_of_keys(&self) -> DBError {
+        DBError::OutOfKeysSize(self.max_keys.unwrap_or(0))
+    }
+
+    /// internal：key 是否已经过期（但还没有被删除）
+    fn is_expired(&self, key: &str) -> bool {
+        match self.ttl.get(key) {
+            Some(when) => *when <= self.now(),
+            None => false,
+        }
+    }
+
+    /// internal：惰性删除, 如果 key 已经过期就把它删除
+    ///
+    /// 返回值：key 过期并被删除时返回 true
+    fn expire_if_needed(&mut self, key: &String) -> bool {
+        if self.is_expired(key) {
+            self.save_undo(key);
+            self.db.remove(key);
+            self.ttl.remove(key);
+            self.stats.expired_keys += 1;
+            self.dirty += 1;
+            self.forget(key);
+            self.propagate("del", key, &[]);
+            self.notify(EventClass::EXPIRED, "expired", key);
+            true
+        } else {
+            false
+        }
+    }
+
+    /// internal：只读地查找 key, 已过期的 key 视为不存在
+    fn lookup(&self, key: &String) -> Option<&Value> {
+        let value = if self.is_expired(key) {
+            None
+        } else {
+            self.db.get(key)
+        };
+        if value.is_some() {
+            self.stats.hit();
+            self.accessed(key);
+        } else {
+            self.stats.miss();
+        }
+        value
+    }
+
+    /// internal：查找 key 用于修改, 会先删除已过期的 key
+    fn lookup_mut(&mut self, key: &String) -> Option<&mut Value> {
+        self.save_undo(key);
+        self.expire_if_needed(key);
+        self.accessed(key);
+        self.db.get_mut(key)
+    }
+
+    /// internal：写入 key, 同时设置（Some）或清除（None）它的过期时间
+    fn insert_with_ttl(&mut self, key: String, value: Value, expire_at: Option<u64>) {
+        self.save_undo(&key);
+        self.modified(&key, 1);
+        self.flags.remove(&key);
+        match expire_at {
+            Some(when) => self.ttl.insert(key.clone(), when),
+            None => self.ttl.remove(&key),
+        };
+        self.db.insert(key, value);
+    }
+
+    /// internal：检查导入 keys 之后 key 的数量是否超过上限, 已经存在的 key 会被覆盖而不会增加数量
+    fn check_import<'a, I>(&self, keys: I) -> Result<()>
+    where
+        I: Iterator<Item = &'a String>,
+    {
+        if let Some(max) = self.max_keys {
+            let added: HashSet<&String> = keys.filter(|key| !self.db.contains_key(*key)).collect();
+            if self.db.len() + added.len() > max {
+                return Err(DBError::OutOfKeysSize(max));
+            }
+        }
+        Ok(())
+    }
+
+    /// internal：写入一个导入的 key, 覆盖已有的同名 key
+    fn import_key(&mut self, key: String, value: Value, expire_at: Option<u64>) {
+        if let Some(commands) = &mut self.propagated {
+            commands.push(vec![String::from("del"), key.clone()]);
+            commands.extend(aof::value_commands(&key, &value, expire_at));
+        }
+        self.load_key(key, value, expire_at);
+    }
+
+    /// internal：删除 key 以及它的过期时间
+    fn remove(&mut self, key: &String) -> Option<(Value, Option<u64>)> {
+        self.save_undo(key);
+        let expire_at = self.ttl.remove(key);
+        let removed = self.db.remove(key).map(|value| (value, expire_at));
+        if removed.is_some() {
+            self.dirty += 1;
+            self.forget(key);
+        }
+        removed
+    }
+
     ///将字符串值 value 关联到 key 。
     /// 如果 key 已经持有其他值， SET 就覆写旧值， 无视类型。
-    /// TODO: 当 SET 命令对一个带有生存时间（TTL）的键进行设置之后， 该键原有的 TTL 将被清除。
+    /// 当 SET 命令对一个带有生存时间（TTL）的键进行设置之后， 该键原有的 TTL 将被清除。
     /// 时间复杂度： O(1)
     ///
     /// 参数说明：
     ///     * not_exists 只有在key不存在时，才插入
     ///     * already_exists 只有在key已经存在时，才插入
+    ///     * expire key 的生存时间，单位为秒
     ///
     /// 返回值：
     ///     * 只在设置操作成功完成时才返回 OK
+    ///     * 过期时间超出毫秒时间戳的范围， 返回 OutOfRange
     pub fn set(
         &mut self,
         key: &String,
@@ -81,42 +619,57 @@ impl KVDB {
         already_exists: bool,
         expire: Option<u64>,
     ) -> Result<DBOk> {
+        let expire_at = match expire {
+            Some(e) => Some(self.expire_after_secs(e)?),
+            None => None,
+        };
         let res: Result<DBOk>;
+        let logged = if self.propagating() {
+            Some(value.clone())
+        } else {
+            None
+        };
+        self.save_undo(key);
+        self.expire_if_needed(key);
         match self.db.get(key) {
-            Some(Value::StringValue(_)) => {
+            Some(Value::StringValue(_)) | Some(Value::CompressedString(_)) => {
                 if not_exists {
                     res = Ok(DBOk::Nil);
                 } else {
-                    if self.can_add_key() {
-                        self.db.insert(key.clone(), Value::StringValue(value));
-                        res = Ok(DBOk::Ok);
-                    } else {
-                        res = Err(DBError::OutOfKeysSize)
-                    }
+                    // 覆盖已有的 key 不会增加 key 的数量, 数据库已满时也可以写入
+                    self.db.insert(key.clone(), self.string_value(value));
+                    res = Ok(DBOk::Ok);
                 }
             }
-            Some(_) => res = Err(DBError::WrongValueType),
+            Some(other) => res = Err(wrong_type(key, ValueType::String, other)),
             None => {
                 if already_exists {
                     res = Ok(DBOk::Nil);
                 } else {
-                    if self.can_add_key() {
-                        self.db.insert(key.clone(), Value::StringValue(value));
+                    if self.make_room() {
+                        self.db.insert(key.clone(), self.string_value(value));
                         res = Ok(DBOk::Ok);
                     } else {
-                        res = Err(DBError::OutOfKeysSize)
+                        res = Err(self.out_of_keys())
                     }
                 }
             }
         }
-        match res {
-            Ok(DBOk::Ok) => {
-                if let Some(e) = expire {
-                    self.ttl.insert(key.clone(), e);
-                }
+        if let Ok(DBOk::Ok) = res {
+            self.modified(key, 1);
+            self.flags.remove(key);
+            if let Some(v) = logged {
+                self.propagate("set", key, &[&v]);
             }
-            _ => {}
-        };
+            self.notify(EventClass::STRING, "set", key);
+            if let Some(when) = expire_at {
+                self.ttl.insert(key.clone(), when);
+                self.propagate("pexpireat", key, &[&when.to_string()]);
+                self.notify(EventClass::GENERIC, "expire", key);
+            } else {
+                self.ttl.remove(key);
+            }
+        }
 
         res
     }
@@ -135,9 +688,11 @@ impl KVDB {
     ///     * value类型不是字符串， 返回WrongValueType
     ///     * key 不存在，返回None
     pub fn get(&self, key: &String) -> Result<Option<String>> {
-        match self.db.get(key) {
-            Some(Value::StringValue(v)) => Ok(Some(v.clone())),
-            Some(_) => Err(DBError::WrongValueType),
+        match self.lookup(key) {
+            Some(value) => match value.string() {
+                Some(v) => Ok(Some(v.into_owned())),
+                None => Err(wrong_type(key, ValueType::String, value)),
+            },
             None => Ok(None),
         }
     }
@@ -155,28 +710,42 @@ impl KVDB {
     ///     * 当 key 不是集合类型时，返回一个错误。
     pub fn sadd(&mut self, key: &String, members: Vec<String>) -> Result<usize> {
         let mut counter: usize = 0;
-        match self.db.get_mut(key) {
+        let logged = if self.propagating() {
+            members.clone()
+        } else {
+            Vec::new()
+        };
+        match self.lookup_mut(key) {
             Some(Value::SetValue(v)) => {
                 members.into_iter().for_each(|member| {
                     if v.insert(member) {
                         counter += 1;
                     }
                 });
+                self.modified(key, counter as u64);
+                if counter > 0 {
+                    self.propagate_all("sadd", key, logged);
+                    self.notify(EventClass::SET, "sadd", key);
+                }
 
                 Ok(counter)
             }
-            Some(_) => Err(DBError::WrongValueType),
+            Some(other) => Err(wrong_type(key, ValueType::Set, other)),
             None => {
-                if self.can_add_key() {
+                if self.make_room() {
                     let mut set = HashSet::new();
                     members.into_iter().for_each(|member| {
-                        set.insert(member);
-                        counter += 1;
+                        if set.insert(member) {
+                            counter += 1;
+                        }
                     });
                     self.db.insert(key.clone(), Value::SetValue(set));
+                    self.modified(key, counter as u64);
+                    self.propagate_all("sadd", key, logged);
+                    self.notify(EventClass::SET, "sadd", key);
                     Ok(counter)
                 } else {
-                    Err(DBError::OutOfKeysSize)
+                    Err(self.out_of_keys())
                 }
             }
         }
@@ -192,20 +761,59 @@ impl KVDB {
     ///     * key 对应 value 的类型不是 Set， 则返回 WrongValueType
     ///     * key不存在或空集则返回 None
     pub fn srandmember(&mut self, key: &String, count: usize) -> Result<Option<HashSet<String>>> {
-        match self.db.get_mut(key) {
+        match self.lookup_mut(key) {
             Some(Value::SetValue(v)) => {
                 //WARNNING: rust can only clone and then remove;
                 let res: HashSet<String> = v.clone().into_iter().take(count).collect();
                 res.iter().for_each(|s| {
                     v.remove(s);
                 });
+                self.modified(key, res.len() as u64);
+                if !res.is_empty() {
+                    self.propagate_all("srem", key, res.iter().cloned().collect());
+                    self.notify(EventClass::SET, "spop", key);
+                }
                 Ok(Some(res))
             }
-            Some(_) => Err(DBError::WrongValueType),
+            Some(other) => Err(wrong_type(key, ValueType::Set, other)),
             None => Ok(None),
         }
     }
 
+    ///
+    /// 随机返回集合中的元素, 不会移除元素, 与 Redis 的 `SRANDMEMBER key count` 相同：
+    /// count 为正数时返回最多 count 个不重复的元素; count 为负数时返回 -count 个元素, 元素可能重复
+    /// 时间复杂度 O(N), N 为 set 集合元素个数
+    ///
+    /// 返回值：
+    ///     * 随机的集合元素
+    ///     * key 对应 value 的类型不是 Set， 则返回 WrongValueType
+    ///     * key不存在则返回 None
+    pub fn random_members(&self, key: &String, count: i64) -> Result<Option<Vec<String>>> {
+        let mut members: Vec<&String> = match self.lookup(key) {
+            Some(Value::SetValue(v)) => v.iter().collect(),
+            Some(other) => return Err(wrong_type(key, ValueType::Set, other)),
+            None => return Ok(None),
+        };
+        let random_index = |len: usize| (random_u64() % len as u64) as usize;
+        if count < 0 {
+            if members.is_empty() {
+                return Ok(Some(Vec::new()));
+            }
+            let res = (0..count.unsigned_abs())
+                .map(|_| members[random_index(members.len())].clone())
+                .collect();
+            return Ok(Some(res));
+        }
+        // 只打乱前 count 个位置的 Fisher-Yates 洗牌
+        let count = (count as usize).min(members.len());
+        for i in 0..count {
+            let j = i + random_index(members.len() - i);
+            members.swap(i, j);
+        }
+        Ok(Some(members.into_iter().take(count).cloned().collect()))
+    }
+
     ///
     /// 移除并返回集合中的一个随机元素。
     /// 时间复杂度: O(1)
@@ -215,22 +823,27 @@ impl KVDB {
     ///     * 当 key 不存在或 key 是空集时，返回 None
     ///     * 当key对应的value 不是 Set 时，返回 WrongValueType
     pub fn spop(&mut self, key: &String) -> Result<Option<String>> {
-        match self.db.get_mut(key) {
+        match self.lookup_mut(key) {
             Some(Value::SetValue(v)) => {
                 //WARNNING: rust can only clone and then remove;
                 let res: Option<String> = v.clone().into_iter().take(1).nth(0);
                 res.iter().for_each(|s| {
                     v.remove(s);
                 });
+                self.modified(key, res.iter().count() as u64);
+                if let Some(member) = &res {
+                    self.propagate("srem", key, &[member]);
+                    self.notify(EventClass::SET, "spop", key);
+                }
                 Ok(res)
             }
-            Some(_) => Err(DBError::WrongValueType),
+            Some(other) => Err(wrong_type(key, ValueType::Set, other)),
             None => Ok(None),
         }
     }
 
     pub fn sismember(&self, key: &String, member: &String) -> Result<Option<bool>> {
-        match self.db.get(key) {
+        match self.lookup(key) {
             Some(Value::SetValue(v)) => {
                 if v.contains(member) {
                     Ok(Some(true))
@@ -238,7 +851,7 @@ impl KVDB {
                     Ok(Some(false))
                 }
             }
-            Some(_) => Err(DBError::WrongValueType),
+            Some(other) => Err(wrong_type(key, ValueType::Set, other)),
             None => Ok(None),
         }
     }
@@ -252,7 +865,7 @@ impl KVDB {
     ///     * value类型不是集合类型， 返回DBError::WrongValueType
     ///
     pub fn srem(&mut self, key: &String, members: Vec<String>) -> Result<usize> {
-        match self.db.get_mut(key) {
+        match self.lookup_mut(key) {
             Some(Value::SetValue(v)) => {
                 let mut counter: usize = 0;
                 members.iter().for_each(|member| {
@@ -260,9 +873,14 @@ impl KVDB {
                         counter += 1;
                     }
                 });
+                self.modified(key, counter as u64);
+                if counter > 0 {
+                    self.propagate_all("srem", key, members);
+                    self.notify(EventClass::SET, "srem", key);
+                }
                 Ok(counter)
             }
-            Some(_) => Err(DBError::WrongValueType),
+            Some(other) => Err(wrong_type(key, ValueType::Set, other)),
             None => Ok(0),
         }
     }
@@ -275,9 +893,9 @@ impl KVDB {
     ///     * key不存在，返回DBError::KeyNotFound
     ///     * value类型不是集合类型， 返回DBError::WrongValueType
     pub fn slen(&self, key: &String) -> Result<Option<usize>> {
-        match self.db.get(key) {
+        match self.lookup(key) {
             Some(Value::SetValue(v)) => Ok(Some(v.len())),
-            Some(_) => Err(DBError::WrongValueType),
+            Some(other) => Err(wrong_type(key, ValueType::Set, other)),
             None => Ok(None),
         }
     }
@@ -291,9 +909,9 @@ impl KVDB {
     ///     * value类型不是集合类型， 返回DBError::WrongValueType
     ///
     pub fn smembers(&self, key: &String) -> Result<Option<HashSet<String>>> {
-        match self.db.get(key) {
+        match self.lookup(key) {
             Some(Value::SetValue(v)) => Ok(Some(v.clone())),
-            Some(_) => Err(DBError::WrongValueType),
+            Some(other) => Err(wrong_type(key, ValueType::Set, other)),
             None => Ok(None),
         }
     }
@@ -309,23 +927,35 @@ impl KVDB {
     ///     * 覆盖原field，则返回0；
     ///     * key对应的类型不是HashMap类型，那么返回错误信息
     pub fn hset(&mut self, key: &String, field: String, value: String) -> Result<u32> {
-        match self.db.get_mut(key) {
+        let logged = if self.propagating() {
+            vec![field.clone(), value.clone()]
+        } else {
+            Vec::new()
+        };
+        match self.lookup_mut(key) {
             Some(Value::HashValue(v)) => {
-                if let Some(_) = v.insert(field, value) {
+                let replaced = v.insert(field, value);
+                self.modified(key, 1);
+                self.propagate_all("hset", key, logged);
+                self.notify(EventClass::HASH, "hset", key);
+                if let Some(_) = replaced {
                     Ok(0)
                 } else {
                     Ok(1)
                 }
             }
-            Some(_) => Err(DBError::WrongValueType),
+            Some(other) => Err(wrong_type(key, ValueType::Hash, other)),
             None => {
-                if self.can_add_key() {
+                if self.make_room() {
                     let mut hashmap: HashMap<String, String> = HashMap::new();
                     hashmap.insert(field, value);
                     self.db.insert(key.clone(), Value::HashValue(hashmap));
+                    self.modified(key, 1);
+                    self.propagate_all("hset", key, logged);
+                    self.notify(EventClass::HASH, "hset", key);
                     Ok(1)
                 } else {
-                    Err(DBError::OutOfKeysSize)
+                    Err(self.out_of_keys())
                 }
             }
         }
@@ -340,7 +970,7 @@ impl KVDB {
     ///     * 给定域不存在于哈希表中， 又或者给定的哈希表并不存在， 返回None
     ///     * key对应的类型不是哈希表， 返回 WrongValueType
     pub fn hget(&self, key: &String, field: &String) -> Result<Option<String>> {
-        match self.db.get(key) {
+        match self.lookup(key) {
             Some(Value::HashValue(v)) => {
                 if let Some(value) = v.get(field) {
                     Ok(Some(value.clone()))
@@ -348,7 +978,7 @@ impl KVDB {
                     Ok(None)
                 }
             }
-            Some(_) => Err(DBError::WrongValueType),
+            Some(other) => Err(wrong_type(key, ValueType::Hash, other)),
             None => Ok(None),
         }
     }
@@ -361,24 +991,40 @@ impl KVDB {
     ///     * 如果命令执行成功，返回 OK 。
     ///     * 当 key 不是哈希表(hash)类型时，返回一个错误。
     pub fn hmset(&mut self, key: &String, pairs: Vec<(String, String)>) -> Result<DBOk> {
-        match self.db.get_mut(key) {
+        let logged: Vec<String> = if self.propagating() {
+            pairs
+                .iter()
+                .flat_map(|(field, value)| vec![field.clone(), value.clone()])
+                .collect()
+        } else {
+            Vec::new()
+        };
+        match self.lookup_mut(key) {
             Some(Value::HashValue(v)) => {
+                let changes = pairs.len() as u64;
                 pairs.into_iter().for_each(|(field, value)| {
                     v.insert(field, value);
                 });
+                self.modified(key, changes);
+                self.propagate_all("hmset", key, logged);
+                self.notify(EventClass::HASH, "hset", key);
                 Ok(DBOk::Ok)
             }
-            Some(_) => Err(DBError::WrongValueType),
+            Some(other) => Err(wrong_type(key, ValueType::Hash, other)),
             None => {
-                if self.can_add_key() {
+                if self.make_room() {
                     let mut hashmap: HashMap<String, String> = HashMap::new();
                     pairs.into_iter().for_each(|(field, value)| {
                         hashmap.insert(field, value);
                     });
+                    let changes = hashmap.len() as u64;
                     self.db.insert(key.clone(), Value::HashValue(hashmap));
+                    self.modified(key, changes);
+                    self.propagate_all("hmset", key, logged);
+                    self.notify(EventClass::HASH, "hset", key);
                     Ok(DBOk::Ok)
                 } else {
-                    Err(DBError::OutOfKeysSize)
+                    Err(self.out_of_keys())
                 }
             }
         }
@@ -392,7 +1038,7 @@ impl KVDB {
     ///     * 如果 filed 不存在，返回Option::None
     ///     * 如果 key 不存在，那么返回 DBError::KeyNotFound
     pub fn hmget(&self, key: &String, fields: &Vec<String>) -> Result<Vec<Option<String>>> {
-        match self.db.get(key) {
+        match self.lookup(key) {
             Some(Value::HashValue(v)) => {
                 let values: Vec<Option<String>> = fields
                     .iter()
@@ -400,8 +1046,8 @@ impl KVDB {
                     .collect();
                 Ok(values)
             }
-            Some(_) => Err(DBError::WrongValueType),
-            None => Err(DBError::KeyNotFound),
+            Some(other) => Err(wrong_type(key, ValueType::Hash, other)),
+            None => Err(DBError::KeyNotFound(key.clone())),
         }
     }
 
@@ -414,12 +1060,12 @@ impl KVDB {
     ///     * 当 key 不存在时，返回 None。
     ///     * key对应的类型不是哈希表， 返回 WrongValueType
     pub fn hkeys(&self, key: &String) -> Result<Option<Vec<String>>> {
-        match self.db.get(key) {
+        match self.lookup(key) {
             Some(Value::HashValue(v)) => {
                 let keys: Vec<String> = v.keys().map(|s| s.clone()).collect();
                 Ok(Some(keys))
             }
-            Some(_) => Err(DBError::WrongValueType),
+            Some(other) => Err(wrong_type(key, ValueType::Hash, other)),
             None => Ok(None),
         }
     }
@@ -433,12 +1079,12 @@ impl KVDB {
     ///     * 当 key 不存在时，返回 None。
     ///     * key对应的类型不是哈希表， 返回 WrongValueType
     pub fn hvalues(&self, key: &String) -> Result<Option<Vec<String>>> {
-        match self.db.get(key) {
+        match self.lookup(key) {
             Some(Value::HashValue(v)) => {
                 let values: Vec<String> = v.values().map(|s| s.clone()).collect();
                 Ok(Some(values))
             }
-            Some(_) => Err(DBError::WrongValueType),
+            Some(other) => Err(wrong_type(key, ValueType::Hash, other)),
             None => Ok(None),
         }
     }
@@ -452,7 +1098,7 @@ impl KVDB {
     ///     * 当 key 不存在时，返回 None。
     ///     * key对应的类型不是哈希表， 返回 WrongValueType
     pub fn hexists(&self, key: &String, field: &String) -> Result<Option<bool>> {
-        match self.db.get(key) {
+        match self.lookup(key) {
             Some(Value::HashValue(v)) => {
                 if v.contains_key(field) {
                     Ok(Some(true))
@@ -460,7 +1106,7 @@ impl KVDB {
                     Ok(Some(false))
                 }
             }
-            Some(_) => Err(DBError::WrongValueType),
+            Some(other) => Err(wrong_type(key, ValueType::Hash, other)),
             None => Ok(None),
         }
     }
@@ -474,9 +1120,9 @@ impl KVDB {
     ///     * 当 key 不存在时，返回 0
     ///     * key对应的类型不是哈希表， 返回 WrongValueType
     pub fn hlen(&self, key: &String) -> Result<Option<usize>> {
-        match self.db.get(key) {
+        match self.lookup(key) {
             Some(Value::HashValue(v)) => Ok(Some(v.len())),
-            Some(_) => Err(DBError::WrongValueType),
+            Some(other) => Err(wrong_type(key, ValueType::Hash, other)),
             None => Ok(None),
         }
     }
@@ -491,26 +1137,94 @@ impl KVDB {
     ///     * key对应的类型不是哈希表， 返回 WrongValueType
     ///
     pub fn hdel(&mut self, key: &String, field: &String) -> Result<Option<usize>> {
-        match self.db.get_mut(key) {
+        match self.lookup_mut(key) {
             Some(Value::HashValue(v)) => {
                 if let Some(_) = v.remove(field) {
+                    self.modified(key, 1);
+                    self.propagate("hdel", key, &[field]);
+                    self.notify(EventClass::HASH, "hdel", key);
                     Ok(Some(1))
                 } else {
                     Ok(Some(0))
                 }
             }
-            Some(_) => Err(DBError::WrongValueType),
+            Some(other) => Err(wrong_type(key, ValueType::Hash, other)),
             None => Ok(None),
         }
     }
 
-    /// set key ttl in seconds
-    /// return:
-    ///     -2: key not exists
-    ///     -1: key not set a ttl
-    ///     u32: live seconds of key
-    // pub fn ttl(&mut self, key: &String, seconds: u32)-> i32{
-    // }
+    ///
+    /// 为 key 设置生存时间, 单位为秒, 过期后 key 会被自动删除
+    /// 时间复杂度 O(1)
+    ///
+    /// 返回值：
+    ///     * 设置成功返回 true; key 不存在返回 false
+    ///     * 过期时间超出毫秒时间戳的范围， 返回 OutOfRange
+    pub fn expire(&mut self, key: &String, seconds: u64) -> Result<bool> {
+        let when = self.expire_after_secs(seconds)?;
+        Ok(self.pexpire_at(key, when))
+    }
+
+    ///
+    /// 将 key 的过期时间设置为毫秒时间戳 when
+    /// 时间复杂度 O(1)
+    ///
+    /// 返回值：设置成功返回 true; key 不存在返回 false
+    pub fn pexpire_at(&mut self, key: &String, when: u64) -> bool {
+        if self.lookup_mut(key).is_some() {
+            self.ttl.insert(key.clone(), when);
+            self.modified(key, 1);
+            self.propagate("pexpireat", key, &[&when.to_string()]);
+            self.notify(EventClass::GENERIC, "expire", key);
+            true
+        } else {
+            false
+        }
+    }
+
+    ///
+    /// 移除 key 的生存时间, 使其成为永久的 key
+    /// 时间复杂度 O(1)
+    ///
+    /// 返回值：成功移除返回 true; key 不存在或没有设置生存时间返回 false
+    pub fn persist(&mut self, key: &String) -> bool {
+        if self.lookup_mut(key).is_some() && self.ttl.remove(key).is_some() {
+            self.modified(key, 1);
+            self.propagate("persist", key, &[]);
+            self.notify(EventClass::GENERIC, "persist", key);
+            true
+        } else {
+            false
+        }
+    }
+
+    ///
+    /// 返回 key 的剩余生存时间, 单位为秒
+    /// 时间复杂度 O(1)
+    ///
+    /// 返回值：
+    ///     * -2: key 不存在
+    ///     * -1: key 没有设置生存时间
+    ///     * 其他: key 的剩余生存时间
+    pub fn ttl(&self, key: &String) -> i64 {
+        match self.pttl(key) {
+            ms if ms < 0 => ms,
+            ms => (ms + 500) / 1000,
+        }
+    }
+
+    ///
+    /// 返回 key 的剩余生存时间, 单位为毫秒, 返回值含义同 `ttl()`
+    /// 时间复杂度 O(1)
+    pub fn pttl(&self, key: &String) -> i64 {
+        if self.lookup(key).is_none() {
+            return -2;
+        }
+        match self.ttl.get(key) {
+            Some(when) => when.saturating_sub(self.now()) as i64,
+            None => -1,
+        }
+    }
 
     /// 删除db中的keys
     /// 时间复杂度 O(N), N为输入的key的数量
@@ -520,8 +1234,12 @@ impl KVDB {
     pub fn del(&mut self, keys: Vec<String>) -> u32 {
         let mut counter = 0;
         keys.iter().for_each(|key| {
-            if let Some(_) = self.db.remove(key) {
-                counter += 1
+            if !self.expire_if_needed(key) {
+                if let Some(_) = self.remove(key) {
+                    self.propagate("del", key, &[]);
+                    self.notify(EventClass::GENERIC, "del", key);
+                    counter += 1
+                }
             }
         });
 
@@ -534,17 +1252,483 @@ impl KVDB {
     ///
     /// 返回值：key存在返回 true; 否则返回false
     pub fn exists(&self, key: &String) -> bool {
-        self.db.contains_key(key)
+        self.lookup(key).is_some()
     }
 
     ///
-    /// 获取数据库中 key的数量
-    /// 时间复杂度 O(N), N数据库中的key的数量
-    ///
-    /// TODO: 可以优化为O(1), 添加一个key的计数器。
+    /// 获取数据库中 key的数量, 不包括已经过期但还没有被删除的 key
+    /// 时间复杂度 O(1), 过期时间表维护了已经过期的 key 的数量, 每个过期时间只会被统计一次
     ///
     /// 返回值：数据库中key的数量
     pub fn size(&self) -> usize {
-        self.db.len()
+        self.db.len() - self.ttl.expired(self.now())
+    }
+
+    ///
+    /// 删除所有已经过期的 key
+    /// 时间复杂度 O(N), N 为设置了生存时间的 key 的数量
+    ///
+    /// 返回值：被删除的 key 的数量
+    pub fn purge_expired(&mut self) -> usize {
+        let now = self.now();
+        let expired: Vec<String> = self
+            .ttl
+            .iter()
+            .filter(|(_, when)| **when <= now)
+            .map(|(key, _)| key.clone())
+            .collect();
+        expired.iter().for_each(|key| {
+            self.expire_if_needed(key);
+        });
+        expired.len()
+    }
+
+    ///
+    /// 主动删除已经过期的 key, 与 Redis 的 activeExpireCycle 相同：每轮随机抽查 `ACTIVE_EXPIRE_SAMPLES` 个
+    /// 设置了生存时间的 key 并删除其中已经过期的, 过期的不超过四分之一或者到达 deadline 时停止
+    /// 时间复杂度：每轮 O(1), 至少执行一轮
+    ///
+    /// 返回值：被删除的 key 的数量
+    pub fn active_expire(&mut self, deadline: Instant) -> usize {
+        let mut total = 0;
+        while !self.ttl.is_empty() {
+            let sampled: Vec<String> = if self.ttl.len() <= ACTIVE_EXPIRE_SAMPLES {
+                self.ttl.keys().cloned().collect()
+            } else {
+                (0..ACTIVE_EXPIRE_SAMPLES)
+                    .filter_map(|_| self.ttl.random_key().cloned())
+                    .collect()
+            };
+            let expired = sampled
+                .iter()
+                .filter(|key| self.expire_if_needed(key))
+                .count();
+            total += expired;
+            if expired * 4 <= sampled.len() || Instant::now() >= deadline {
+                break;
+            }
+        }
+        total
+    }
+
+    ///
+    /// 获取数据库的统计信息, 参见 `KeyspaceInfo`
+    /// 时间复杂度 O(N), N数据库中的key的数量
+    pub fn keyspace_info(&self) -> KeyspaceInfo {
+        let mut info = KeyspaceInfo {
+            keys: self.db.len(),
+            expires: self.ttl.len(),
+            keyspace_hits: self.stats.hits.get(),
+            keyspace_misses: self.stats.misses.get(),
+            expired_keys: self.stats.expired_keys,
+            evicted_keys: self.stats.evicted_keys,
+            ..KeyspaceInfo::default()
+        };
+        self.db.iter().for_each(|(key, value)| {
+            match value.value_type() {
+                ValueType::String => info.strings += 1,
+                ValueType::Set => info.sets += 1,
+                ValueType::Hash => info.hashes += 1,
+                ValueType::Custom(_) => info.customs += 1,
+            }
+            info.used_memory += mem::size_of::<String>() + key.capacity() + value.memory_usage();
+            if let Value::CompressedString(v) = value {
+                info.compressed_strings += 1;
+                info.compression_saved_bytes += v.len().saturating_sub(v.memory_usage());
+            }
+        });
+        info.used_memory += self
+            .ttl
+            .keys()
+            .map(|key| mem::size_of::<(String, u64)>() + key.capacity())
+            .sum::<usize>();
+        info
+    }
+
+    ///
+    /// 将 key 改名为 newkey, key 的生存时间随之转移；newkey 已经存在时会被覆盖
+    /// 时间复杂度 O(1)
+    ///
+    /// 返回值：
+    ///     * 改名成功返回 OK
+    ///     * key 不存在， 返回 KeyNotFound
+    pub fn rename(&mut self, key: &String, newkey: &String) -> Result<DBOk> {
+        self.expire_if_needed(key);
+        if key == newkey {
+            return if self.db.contains_key(key) {
+                Ok(DBOk::Ok)
+            } else {
+                Err(DBError::KeyNotFound(key.clone()))
+            };
+        }
+        let flags = self.flags.get(key).copied();
+        match self.remove(key) {
+            Some((value, expire_at)) => {
+                self.insert_with_ttl(newkey.clone(), value, expire_at);
+                if let Some(flags) = flags {
+                    self.flags.insert(newkey.clone(), flags);
+                }
+                self.propagate("rename", key, &[newkey]);
+                self.notify(EventClass::GENERIC, "rename_from", key);
+                self.notify(EventClass::GENERIC, "rename_to", newkey);
+                Ok(DBOk::Ok)
+            }
+            None => Err(DBError::KeyNotFound(key.clone())),
+        }
+    }
+
+    ///
+    /// 当且仅当 newkey 不存在时，将 key 改名为 newkey
+    /// 时间复杂度 O(1)
+    ///
+    /// 返回值：
+    ///     * 改名成功返回 true; newkey 已经存在返回 false
+    ///     * key 不存在， 返回 KeyNotFound
+    pub fn renamenx(&mut self, key: &String, newkey: &String) -> Result<bool> {
+        if self.lookup_mut(key).is_none() {
+            return Err(DBError::KeyNotFound(key.clone()));
+        }
+        if self.lookup_mut(newkey).is_some() {
+            return Ok(false);
+        }
+        self.rename(key, newkey).map(|_| true)
+    }
+
+    ///
+    /// 将 source 的值（连同生存时间）复制到 destination
+    /// 时间复杂度 O(N), N 为 value 中元素的数量
+    ///
+    /// 参数说明：
+    ///     * replace destination 已经存在时是否覆盖
+    ///
+    /// 返回值：
+    ///     * 复制成功返回 true; source 不存在或 destination 已经存在且不覆盖时返回 false
+    ///     * source 与 destination 相同， 返回 SameKey
+    ///     * 需要创建新的 key 但数据库已满， 返回 OutOfKeysSize
+    pub fn copy(&mut self, source: &String, destination: &String, replace: bool) -> Result<bool> {
+        if source == destination {
+            return Err(DBError::SameKey(source.clone()));
+        }
+        let value = match self.lookup_mut(source) {
+            Some(value) => value.clone(),
+            None => return Ok(false),
+        };
+        let expire_at = self.ttl.get(source).copied();
+        if self.lookup_mut(destination).is_some() {
+            if !replace {
+                return Ok(false);
+            }
+        } else if !self.make_room() {
+            return Err(self.out_of_keys());
+        }
+        self.insert_with_ttl(destination.clone(), value, expire_at);
+        self.propagate("copy", source, &[destination, "replace"]);
+        self.notify(EventClass::GENERIC, "copy_to", destination);
+        Ok(true)
+    }
+
+    ///
+    /// 将 key（连同生存时间）移动到另一个数据库 target 中
+    /// 时间复杂度 O(1)
+    ///
+    /// 返回值：
+    ///     * 移动成功返回 true; key 不存在或 target 中已经存在同名 key 时返回 false
+    ///     * target 已满， 返回 OutOfKeysSize
+    pub fn move_to(&mut self, key: &String, target: &mut KVDB) -> Result<bool> {
+        if self.lookup_mut(key).is_none() || target.lookup_mut(key).is_some() {
+            return Ok(false);
+        }
+        if !target.make_room() {
+            return Err(target.out_of_keys());
+        }
+        if let Some((value, expire_at)) = self.remove(key) {
+            self.propagate("del", key, &[]);
+            if let Some(commands) = &mut target.propagated {
+                commands.extend(aof::value_commands(key, &value, expire_at));
+            }
+            target.insert_with_ttl(key.clone(), value, expire_at);
+            self.notify(EventClass::GENERIC, "move_from", key);
+            target.notify(EventClass::GENERIC, "move_to", key);
+        }
+        Ok(true)
+    }
+
+    ///
+    /// 访问一个或多个 key：更新它们最近一次被访问的时间（参见 `idle_time()`）, 同时清理其中已经过期的 key
+    /// 时间复杂度 O(N), N为输入的key的数量
+    ///
+    /// 返回值：存在的 key 的数量
+    pub fn touch(&mut self, keys: Vec<String>) -> u32 {
+        keys.iter()
+            .filter(|key| self.lookup_mut(key).is_some())
+            .count() as u32
+    }
+
+    ///
+    /// 返回 key 自最近一次被访问（读写或 `touch()`）以来空闲的秒数, 与 Redis 的 `OBJECT IDLETIME` 相同；
+    /// 查询本身不算作访问, 访问时间只保存在内存中, 重新加载数据之后从加载时开始计算
+    /// 时间复杂度 O(1)
+    ///
+    /// 返回值：key 存在返回空闲的秒数; 否则返回 None
+    pub fn idle_time(&self, key: &String) -> Option<u64> {
+        if self.is_expired(key) || !self.db.contains_key(key) {
+            return None;
+        }
+        let at = self.access.get(key).map_or(self.now(), |at| at.get());
+        Some(self.now().saturating_sub(at) / 1000)
+    }
+
+    ///
+    /// 与 `del()` 相同, 从数据库中删除 keys, 但元素数量超过 `LAZYFREE_THRESHOLD`
+    /// 的 value 由后台线程释放, 删除大集合时不会阻塞调用方。
+    /// 时间复杂度 O(N), N为输入的key的数量
+    ///
+    /// 返回值：成功删除的key的数量
+    pub fn unlink(&mut self, keys: Vec<String>) -> u32 {
+        let mut counter = 0;
+        let mut lazy_free: Vec<Value> = Vec::new();
+        keys.iter().for_each(|key| {
+            if !self.expire_if_needed(key) {
+                if let Some((value, _)) = self.remove(key) {
+                    self.propagate("del", key, &[]);
+                    self.notify(EventClass::GENERIC, "del", key);
+                    counter += 1;
+                    if value.elements() > LAZYFREE_THRESHOLD {
+                        lazy_free.push(value);
+                    }
+                }
+            }
+        });
+        if !lazy_free.is_empty() {
+            thread::spawn(move || drop(lazy_free));
+        }
+
+        counter
+    }
+
+    ///
+    /// 序列化 key 的值, 结果可以通过 `restore()` 还原到任意 memkv 实例中。
+    /// 序列化结果带有格式版本号和 CRC64 校验和, 不包含生存时间。
+    /// 时间复杂度 O(N), N 为 value 中元素的数量
+    ///
+    /// 返回值：
+    ///     * 序列化后的字节
+    ///     * key 不存在，返回 None
+    pub fn dump(&self, key: &String) -> Option<Vec<u8>> {
+        self.lookup(key).map(encoding::dump_value)
+    }
+
+    ///
+    /// 反序列化 `dump()` 的结果, 并关联到 key
+    /// 时间复杂度 O(N), N 为 value 中元素的数量
+    ///
+    /// 参数说明：
+    ///     * ttl key 的生存时间, 单位为毫秒, None 表示不过期
+    ///     * payload `dump()` 的结果
+    ///     * replace key 已经存在时是否覆盖
+    ///
+    /// 返回值：
+    ///     * 还原成功返回 OK
+    ///     * key 已经存在且不覆盖， 返回 KeyAlreadyExists
+    ///     * payload 的版本号不支持或校验和错误， 返回 InvalidPayload
+    ///     * 需要创建新的 key 但数据库已满， 返回 OutOfKeysSize
+    ///     * 过期时间超出毫秒时间戳的范围， 返回 OutOfRange
+    pub fn restore(
+        &mut self,
+        key: &String,
+        ttl: Option<u64>,
+        payload: &[u8],
+        replace: bool,
+    ) -> Result<DBOk> {
+        let value = encoding::restore_value(payload)?;
+        let expire_at = match ttl {
+            Some(ms) => Some(self.expire_after(ms)?),
+            None => None,
+        };
+        if self.lookup_mut(key).is_some() {
+            if !replace {
+                return Err(DBError::KeyAlreadyExists(key.clone()));
+            }
+        } else if !self.make_room() {
+            return Err(self.out_of_keys());
+        }
+        if let Some(commands) = &mut self.propagated {
+            commands.push(vec![String::from("del"), key.clone()]);
+            commands.extend(aof::value_commands(key, &value, expire_at));
+        }
+        self.load_key(key.clone(), value, expire_at);
+        self.notify(EventClass::GENERIC, "restore", key);
+        Ok(DBOk::Ok)
+    }
+
+    ///
+    /// 清空数据库中的所有 key
+    /// 时间复杂度 O(N), N数据库中的key的数量
+    pub fn flush(&mut self) {
+        self.save_undo_all();
+        self.dirty += self.db.len() as u64;
+        if let Some(commands) = &mut self.propagated {
+            commands.push(vec![String::from("flushdb")]);
+        }
+        self.db.clear();
+        self.ttl.clear();
+        self.versions.clear();
+        self.flags.clear();
+        self.access.clear();
+    }
+
+    ///
+    /// 清空数据库中的所有 key, 旧数据交给后台线程释放
+    /// 时间复杂度 O(1)
+    pub fn flush_async(&mut self) {
+        self.save_undo_all();
+        self.dirty += self.db.len() as u64;
+        if let Some(commands) = &mut self.propagated {
+            commands.push(vec![String::from("flushdb")]);
+        }
+        let db = mem::take(&mut self.db);
+        let ttl = mem::take(&mut self.ttl);
+        let versions = mem::take(&mut self.versions);
+        self.flags.clear();
+        self.access.clear();
+        thread::spawn(move || drop((db, ttl, versions)));
+    }
+
+    ///
+    /// 查找所有符合给定模式 pattern 的 key, 模式语法见 `glob_match()`
+    /// 时间复杂度 O(N), N数据库中的key的数量
+    ///
+    /// 返回值：符合给定模式的 key 列表, 顺序不确定
+    pub fn keys(&self, pattern: &str) -> Vec<String> {
+        self.db
+            .keys()
+            .filter(|key| !self.is_expired(key) && glob_match(pattern, key))
+            .cloned()
+            .collect()
+    }
+
+    ///
+    /// 基于游标迭代数据库中的 key, 游标为 0 时开始一次新的迭代。
+    /// 在整个迭代过程中一直存在的 key 一定会被返回, 即使期间有 key 的增删导致 rehash；
+    /// 迭代过程中新增或删除的 key 可能返回, 也可能不返回。
+    /// 与 Redis 一样, pattern 和 value_type 过滤在取出一批 key 之后进行, 因此某次迭代可能返回空列表。
+    /// 时间复杂度：每次调用 O(N), N数据库中的key的数量
+    ///
+    /// 参数说明：
+    ///     * cursor 上一次迭代返回的游标
+    ///     * pattern 只返回匹配该模式的 key
+    ///     * count 每次迭代检查的 key 的数量, 参见 `DEFAULT_SCAN_COUNT`
+    ///     * value_type 只返回指定类型的 key
+    ///
+    /// 返回值：(下一次迭代的游标, 本次迭代的 key)；返回的游标为 0 时表示迭代结束
+    pub fn scan(
+        &self,
+        cursor: u64,
+        pattern: Option<&str>,
+        count: usize,
+        value_type: Option<ValueType>,
+    ) -> (u64, Vec<String>) {
+        let items = self
+            .db
+            .iter()
+            .filter(|(key, _)| !self.is_expired(key))
+            .map(|(key, value)| (key, (key, value)));
+        let (next, batch) = scan::scan_items(items, cursor, count);
+        let keys = batch
+            .into_iter()
+            .filter(|(_, value)| value_type.is_none_or(|t| t == value.value_type()))
+            .filter(|(key, _)| pattern.is_none_or(|p| glob_match(p, key)))
+            .map(|(key, _)| key.clone())
+            .collect();
+        (next, keys)
+    }
+
+    ///
+    /// 返回 key 所储存的值的类型。
+    /// 时间复杂度 O(1)
+    ///
+    /// 返回值：
+    ///     * key 对应 value 的类型
+    ///     * key 不存在，返回 None
+    pub fn key_type(&self, key: &String) -> Option<ValueType> {
+        self.lookup(key).map(|value| value.value_type())
+    }
+
+    ///
+    /// 从数据库中随机返回一个 key
+    /// 时间复杂度 O(N), N数据库中的key的数量
+    ///
+    /// 返回值：
+    ///     * 随机的一个 key
+    ///     * 数据库为空时，返回 None
+    pub fn randomkey(&self) -> Option<String> {
+        let live: Vec<&String> = self.db.keys().filter(|key| !self.is_expired(key)).collect();
+        if live.is_empty() {
+            return None;
+        }
+        let index = (random_u64() % live.len() as u64) as usize;
+        Some(live[index].clone())
+    }
+
+    ///
+    /// 基于游标迭代集合 key 中的元素, 迭代保证与 `scan()` 相同
+    /// 时间复杂度：每次调用 O(N), N 为集合元素个数
+    ///
+    /// 返回值：
+    ///     * (下一次迭代的游标, 本次迭代的元素)；返回的游标为 0 时表示迭代结束
+    ///     * key 不存在时，返回 (0, [])
+    ///     * value类型不是集合类型， 返回 WrongValueType
+    pub fn sscan(
+        &self,
+        key: &String,
+        cursor: u64,
+        pattern: Option<&str>,
+        count: usize,
+    ) -> Result<(u64, Vec<String>)> {
+        match self.lookup(key) {
+            Some(Value::SetValue(v)) => {
+                let items = v.iter().map(|member| (member, member));
+                let (next, batch) = scan::scan_items(items, cursor, count);
+                let members = batch
+                    .into_iter()
+                    .filter(|member| pattern.is_none_or(|p| glob_match(p, member)))
+                    .cloned()
+                    .collect();
+                Ok((next, members))
+            }
+            Some(other) => Err(wrong_type(key, ValueType::Set, other)),
+            None => Ok((0, Vec::new())),
+        }
+    }
+
+    ///
+    /// 基于游标迭代哈希表 key 中的域值对, 迭代保证与 `scan()` 相同, pattern 只匹配域
+    /// 时间复杂度：每次调用 O(N), N 为哈希表大小
+    ///
+    /// 返回值：
+    ///     * (下一次迭代的游标, 本次迭代的域值对)；返回的游标为 0 时表示迭代结束
+    ///     * key 不存在时，返回 (0, [])
+    ///     * key对应的类型不是哈希表， 返回 WrongValueType
+    pub fn hscan(
+        &self,
+        key: &String,
+        cursor: u64,
+        pattern: Option<&str>,
+        count: usize,
+    ) -> Result<(u64, Vec<(String, String)>)> {
+        match self.lookup(key) {
+            Some(Value::HashValue(v)) => {
+                let items = v.iter().map(|(field, value)| (field, (field, value)));
+                let (next, batch) = scan::scan_items(items, cursor, count);
+                let pairs = batch
+                    .into_iter()
+                    .filter(|(field, _)| pattern.is_none_or(|p| glob_match(p, field)))
+                    .map(|(field, value)| (field.clone(), value.clone()))
+                    .collect();
+                Ok((next, pairs))
+            }
+            Some(other) => Err(wrong_type(key, ValueType::Hash, other)),
+            None => Ok((0, Vec::new())),
+        }
     }
 }
@@ -0,0 +1,217 @@
+//! 事务：`MULTI` / `EXEC` / `DISCARD` 与乐观锁 `WATCH`, 语义与 Redis 一致。
+//!
+//! `Transaction` 只负责事务的状态, 不关心命令的格式：
+//!     * `multi()` 之后 `queue()` 的命令不会立即执行, `exec()` 时按顺序依次执行,
+//!       执行期间持有数据库的独占访问权（`&mut`）, 其他操作不会穿插在事务的命令之间
+//!     * 命令在排队时出错（例如命令不存在、参数个数不正确）时调用 `abort()`, 之后 `exec()` 放弃整个事务
+//!     * 单条命令执行失败不会回滚已经执行的命令, 与 Redis 相同
+//!     * `watch()` 记录 key 当前的版本号（参见 `KVDB::version()`）, `exec()` 时任意被监视的 key 的版本号变化,
+//!       包括被修改、删除以及过期, 则放弃执行并返回 None
+//!
+//! 被监视的 key 不存在时记录为 None, 之后被创建又被删除的情况无法发现。
+//!
+//! 示例：
+//! ```
+//! use dbcore::{Transaction, KVDB};
+//!
+//! let mut db = KVDB::default();
+//! let key = String::from("counter");
+//! db.sets(&key, String::from("1")).unwrap();
+//!
+//! let mut tx: Transaction<String> = Transaction::new();
+//! tx.watch(&db, 0, &key).unwrap();
+//! tx.multi().unwrap();
+//! tx.queue(String::from("2"));
+//! // 在 EXEC 之前被其他客户端修改
+//! db.sets(&key, String::from("10")).unwrap();
+//! let replies = tx.exec(&mut db, |db, value| db.sets(&key, value)).unwrap();
+//! assert_eq!(None, replies);
+//! ```
+
+use crate::{DBError, Databases, Result, KVDB};
+
+/// 可以被 `WATCH` 的数据：`KVDB` 或者 `Databases`
+pub trait Versioned {
+    /// 编号为 db 的数据库中 key 的版本号, key 不存在、已经过期或者数据库不存在时返回 None
+    fn key_version(&self, db: usize, key: &str) -> Option<u64>;
+}
+
+/// 单独的 `KVDB` 只有一个数据库, 忽略数据库编号
+impl Versioned for KVDB {
+    fn key_version(&self, _db: usize, key: &str) -> Option<u64> {
+        self.version(key)
+    }
+}
+
+impl Versioned for Databases {
+    fn key_version(&self, db: usize, key: &str) -> Option<u64> {
+        self.db(db).ok()?.version(key)
+    }
+}
+
+/// 一个客户端的事务状态, C 为排队的命令
+#[derive(Debug)]
+pub struct Transaction<C> {
+    /// 被监视的 (数据库编号, key, 监视时的版本号)
+    watched: Vec<(usize, String, Option<u64>)>,
+    /// `MULTI` 之后排队的命令；None 表示不在事务中
+    queued: Option<Vec<C>>,
+    /// 有命令排队失败, `EXEC` 时放弃事务
+    aborted: bool,
+}
+
+impl<C> Default for Transaction<C> {
+    fn default() -> Self {
+        Transaction::new()
+    }
+}
+
+impl<C> Transaction<C> {
+    pub fn new() -> Transaction<C> {
+        Transaction {
+            watched: Vec::new(),
+            queued: None,
+            aborted: false,
+        }
+    }
+
+    ///
+    /// 监视编号为 db 的数据库中的 key, 同一个 key 被监视多次时以第一次的版本号为准
+    ///
+    /// 返回值：
+    ///     * 成功返回 ()
+    ///     * 已经在事务中， 返回 Transaction
+    pub fn watch<T: Versioned>(&mut self, target: &T, db: usize, key: &String) -> Result<()> {
+        if self.in_multi() {
+            return Err(DBError::Transaction(String::from(
+                "WATCH inside MULTI is not allowed",
+            )));
+        }
+        if !self.watched.iter().any(|(i, k, _)| *i == db && k == key) {
+            self.watched
+                .push((db, key.clone(), target.key_version(db, key)));
+        }
+        Ok(())
+    }
+
+    /// 取消监视所有 key
+    pub fn unwatch(&mut self) {
+        self.watched.clear();
+    }
+
+    /// 被监视的 key 的数量
+    pub fn watched(&self) -> usize {
+        self.watched.len()
+    }
+
+    /// 被监视的 key 是否已经被修改、删除或者过期, 此时 `exec()` 一定会放弃执行
+    pub fn is_dirty<T: Versioned>(&self, target: &T) -> bool {
+        self.watched
+            .iter()
+            .any(|(db, key, version)| target.key_version(*db, key) != *version)
+    }
+
+    ///
+    /// 开始事务
+    ///
+    /// 返回值：
+    ///     * 成功返回 ()
+    ///     * 已经在事务中， 返回 Transaction
+    pub fn multi(&mut self) -> Result<()> {
+        if self.in_multi() {
+            return Err(DBError::Transaction(String::from(
+                "MULTI calls can not be nested",
+            )));
+        }
+        self.queued = Some(Vec::new());
+        self.aborted = false;
+        Ok(())
+    }
+
+    /// 是否在事务中
+    pub fn in_multi(&self) -> bool {
+        self.queued.is_some()
+    }
+
+    /// 排队的命令数量
+    pub fn len(&self) -> usize {
+        self.queued.as_ref().map_or(0, Vec::len)
+    }
+
+    /// 是否没有排队的命令
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// 命令排队, 不在事务中时返回 false
+    pub fn queue(&mut self, command: C) -> bool {
+        match self.queued.as_mut() {
+            Some(queued) => {
+                queued.push(command);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// 有命令排队失败, 之后的 `exec()` 放弃整个事务
+    pub fn abort(&mut self) {
+        if self.in_multi() {
+            self.aborted = true;
+        }
+    }
+
+    ///
+    /// 放弃事务, 丢弃所有排队的命令并取消监视所有 key
+    ///
+    /// 返回值：
+    ///     * 成功返回 ()
+    ///     * 不在事务中， 返回 Transaction
+    pub fn discard(&mut self) -> Result<()> {
+        if self.queued.take().is_none() {
+            return Err(DBError::Transaction(String::from("DISCARD without MULTI")));
+        }
+        self.aborted = false;
+        self.unwatch();
+        Ok(())
+    }
+
+    ///
+    /// 执行事务：被监视的 key 都没有变化时, 按顺序对每条排队的命令调用 run。
+    /// 无论是否执行, 事务都会结束并取消监视所有 key
+    ///
+    /// 参数说明：
+    ///     * target: 执行命令的数据, 执行期间被独占
+    ///     * run: 执行一条命令, 返回它的结果
+    ///
+    /// 返回值：
+    ///     * 所有命令的结果
+    ///     * 被监视的 key 发生了变化， 返回 None
+    ///     * 不在事务中， 返回 Transaction
+    ///     * 有命令排队失败， 返回 ExecAbort
+    pub fn exec<T, R, F>(&mut self, target: &mut T, mut run: F) -> Result<Option<Vec<R>>>
+    where
+        T: Versioned,
+        F: FnMut(&mut T, C) -> R,
+    {
+        let queued = self
+            .queued
+            .take()
+            .ok_or_else(|| DBError::Transaction(String::from("EXEC without MULTI")))?;
+        let dirty = self.is_dirty(target);
+        let aborted = std::mem::replace(&mut self.aborted, false);
+        self.unwatch();
+        if aborted {
+            return Err(DBError::ExecAbort);
+        }
+        if dirty {
+            return Ok(None);
+        }
+        Ok(Some(
+            queued
+                .into_iter()
+                .map(|command| run(target, command))
+                .collect(),
+        ))
+    }
+}
@@ -0,0 +1,260 @@
+//! 键空间通知（keyspace notifications）, 语义与 Redis 的 `notify-keyspace-events` 一致。
+//!
+//! `Databases` 中的 key 被写入、修改、删除、过期或者被淘汰时产生一个事件, 事件的类别被启用时：
+//!     * 启用 K 时发布到 `__keyspace@<db>__:<key>` 频道, 消息为事件名称
+//!     * 启用 E 时发布到 `__keyevent@<db>__:<event>` 频道, 消息为 key
+//!     * 同步调用所有注册的 `KeyspaceListener`, 不需要启用 K 或 E
+//!
+//! 默认不启用任何类别。单独创建的 `KVDB` 不属于任何 `Databases`, 不会产生事件。
+//! 按照淘汰策略（参见 `EvictionPolicy`）淘汰 key 时产生类别为 e 的 evicted 事件。
+//!
+//! 示例：
+//! ```
+//! use dbcore::{Databases, EventClass};
+//!
+//! let mut dbs = Databases::new(1, None);
+//! dbs.set_notify_keyspace_events(EventClass::parse("KEA").unwrap());
+//! let subscriber = dbs.pubsub().subscriber();
+//! subscriber.subscribe("__keyspace@0__:name");
+//! dbs.db_mut(0).unwrap().sets(&String::from("name"), String::from("memkv")).unwrap();
+//! assert_eq!("set", subscriber.recv().unwrap().payload);
+//! ```
+
+use crate::pubsub::PubSub;
+use crate::{DBError, Result};
+use std::fmt;
+use std::ops::BitOr;
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use std::sync::{Arc, RwLock, RwLockReadGuard};
+
+/// 事件的类别, 可以用 `|` 组合, 字符表示与 Redis 的 `notify-keyspace-events` 相同
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct EventClass(u32);
+
+impl EventClass {
+    /// 不启用任何类别
+    pub const NONE: EventClass = EventClass(0);
+    /// K：发布到 `__keyspace@<db>__:<key>` 频道
+    pub const KEYSPACE: EventClass = EventClass(1);
+    /// E：发布到 `__keyevent@<db>__:<event>` 频道
+    pub const KEYEVENT: EventClass = EventClass(1 << 1);
+    /// g：与类型无关的事件, 例如 del、expire、rename_from
+    pub const GENERIC: EventClass = EventClass(1 << 2);
+    /// $：字符串的事件
+    pub const STRING: EventClass = EventClass(1 << 3);
+    /// s：集合的事件
+    pub const SET: EventClass = EventClass(1 << 4);
+    /// h：哈希表的事件
+    pub const HASH: EventClass = EventClass(1 << 5);
+    /// x：key 过期被删除
+    pub const EXPIRED: EventClass = EventClass(1 << 6);
+    /// e：key 被淘汰
+    pub const EVICTED: EventClass = EventClass(1 << 7);
+    /// d：自定义类型的事件, 事件名称为类型名称, 参见 `custom`
+    pub const CUSTOM: EventClass = EventClass(1 << 8);
+    /// A：g$shxed 的组合
+    pub const ALL: EventClass =
+        EventClass((1 << 2) | (1 << 3) | (1 << 4) | (1 << 5) | (1 << 6) | (1 << 7) | (1 << 8));
+
+    /// 各个类别对应的字符
+    const FLAGS: [(char, EventClass); 9] = [
+        ('g', EventClass::GENERIC),
+        ('$', EventClass::STRING),
+        ('s', EventClass::SET),
+        ('h', EventClass::HASH),
+        ('x', EventClass::EXPIRED),
+        ('e', EventClass::EVICTED),
+        ('d', EventClass::CUSTOM),
+        ('K', EventClass::KEYSPACE),
+        ('E', EventClass::KEYEVENT),
+    ];
+
+    ///
+    /// 解析 Redis `notify-keyspace-events` 格式的字符串, 例如 "KEA"、"Kx"；空字符串表示不启用任何类别
+    ///
+    /// 返回值：
+    ///     * 对应的类别
+    ///     * 包含无法识别的字符（包括 Redis 支持但 memkv 没有的 l、z、t 等类别）， 返回 Syntax
+    pub fn parse(flags: &str) -> Result<EventClass> {
+        flags.chars().try_fold(EventClass::NONE, |classes, flag| {
+            let class = match flag {
+                'A' => EventClass::ALL,
+                _ => EventClass::FLAGS
+                    .iter()
+                    .find(|(c, _)| *c == flag)
+                    .map(|(_, class)| *class)
+                    .ok_or_else(|| {
+                        DBError::Syntax(format!("invalid keyspace event class '{}'", flag))
+                    })?,
+            };
+            Ok(classes | class)
+        })
+    }
+
+    /// 是否包含 other 中的所有类别
+    pub fn contains(&self, other: EventClass) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    /// 是否不包含任何类别
+    pub fn is_empty(&self) -> bool {
+        self.0 == 0
+    }
+}
+
+impl BitOr for EventClass {
+    type Output = EventClass;
+
+    fn bitor(self, other: EventClass) -> EventClass {
+        EventClass(self.0 | other.0)
+    }
+}
+
+/// 格式与 `parse()` 的参数相同, 包含所有类型的事件时写作 A
+impl fmt::Display for EventClass {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let all = self.contains(EventClass::ALL);
+        let mut out = String::from(if all { "A" } else { "" });
+        for (flag, class) in EventClass::FLAGS.iter() {
+            if self.contains(*class) && !(all && EventClass::ALL.contains(*class)) {
+                out.push(*flag);
+            }
+        }
+        f.write_str(&out)
+    }
+}
+
+/// 一个键空间事件
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeyspaceEvent {
+    /// 数据库编号
+    pub db: usize,
+    /// 事件所属的类别, 只包含一个类型类别（g、$、s、h、x、e、d）
+    pub class: EventClass,
+    /// 事件名称, 与 Redis 相同, 例如 set、sadd、hset、del、expire、expired
+    pub event: &'static str,
+    pub key: String,
+}
+
+///
+/// 进程内的事件监听者, 例如用于使本地缓存失效。
+/// 事件在修改数据的线程中、持有数据库的访问权时同步通知, 因此实现不能再访问产生事件的数据库,
+/// 耗时的处理应当交给其他线程。
+pub trait KeyspaceListener: Send + Sync {
+    fn on_event(&self, event: &KeyspaceEvent);
+}
+
+impl<F: Fn(&KeyspaceEvent) + Send + Sync> KeyspaceListener for F {
+    fn on_event(&self, event: &KeyspaceEvent) {
+        self(event)
+    }
+}
+
+/// internal：所有数据库共用的通知配置
+pub(crate) struct Notifications {
+    classes: AtomicU32,
+    pubsub: PubSub,
+    listeners: RwLock<Vec<(u64, Arc<dyn KeyspaceListener>)>>,
+    next_id: AtomicU64,
+}
+
+impl fmt::Debug for Notifications {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Notifications")
+            .field("classes", &self.classes())
+            .field("listeners", &self.listeners().len())
+            .finish()
+    }
+}
+
+impl Notifications {
+    pub(crate) fn new(pubsub: PubSub) -> Notifications {
+        Notifications {
+            classes: AtomicU32::new(0),
+            pubsub,
+            listeners: RwLock::new(Vec::new()),
+            next_id: AtomicU64::new(1),
+        }
+    }
+
+    pub(crate) fn classes(&self) -> EventClass {
+        EventClass(self.classes.load(Ordering::Relaxed))
+    }
+
+    pub(crate) fn set_classes(&self, classes: EventClass) {
+        self.classes.store(classes.0, Ordering::Relaxed);
+    }
+
+    fn listeners(&self) -> RwLockReadGuard<'_, Vec<(u64, Arc<dyn KeyspaceListener>)>> {
+        self.listeners
+            .read()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// internal：注册监听者, 返回用于取消注册的编号
+    pub(crate) fn add_listener(&self, listener: Arc<dyn KeyspaceListener>) -> u64 {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        self.listeners
+            .write()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .push((id, listener));
+        id
+    }
+
+    /// internal：取消注册, 编号不存在时返回 false
+    pub(crate) fn remove_listener(&self, id: u64) -> bool {
+        let mut listeners = self
+            .listeners
+            .write()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        let before = listeners.len();
+        listeners.retain(|(listener, _)| *listener != id);
+        listeners.len() != before
+    }
+
+    /// internal：编号为 db 的数据库使用的通知入口
+    pub(crate) fn notifier(self: &Arc<Notifications>, db: usize) -> Notifier {
+        Notifier {
+            shared: self.clone(),
+            db,
+        }
+    }
+}
+
+/// internal：一个数据库产生事件的入口
+#[derive(Debug, Clone)]
+pub(crate) struct Notifier {
+    shared: Arc<Notifications>,
+    db: usize,
+}
+
+impl Notifier {
+    /// internal：产生一个类别为 class 的事件, 类别没有启用时什么都不做
+    pub(crate) fn notify(&self, class: EventClass, event: &'static str, key: &str) {
+        let classes = self.shared.classes();
+        if !classes.contains(class) {
+            return;
+        }
+        if classes.contains(EventClass::KEYSPACE) {
+            let channel = format!("__keyspace@{}__:{}", self.db, key);
+            self.shared.pubsub.publish(&channel, event);
+        }
+        if classes.contains(EventClass::KEYEVENT) {
+            let channel = format!("__keyevent@{}__:{}", self.db, event);
+            self.shared.pubsub.publish(&channel, key);
+        }
+        let listeners = self.shared.listeners();
+        if listeners.is_empty() {
+            return;
+        }
+        let event = KeyspaceEvent {
+            db: self.db,
+            class,
+            event,
+            key: String::from(key),
+        };
+        listeners
+            .iter()
+            .for_each(|(_, listener)| listener.on_event(&event));
+    }
+}
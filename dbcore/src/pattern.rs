@@ -0,0 +1,106 @@
+//! glob 风格的模式匹配, 语义与 Redis 的 `KEYS` / `SCAN MATCH` 一致。
+//!
+//! 支持的语法：
+//!     * `?` 匹配任意单个字符
+//!     * `*` 匹配任意数量（包括零个）的字符
+//!     * `[abc]` 匹配方括号中的任意字符, `[^abc]` 取反, `[a-z]` 匹配区间
+//!     * `\x` 转义, 匹配字符 x 本身
+
+/// 判断字符串 `string` 是否匹配 glob 模式 `pattern`
+///
+/// 返回值：匹配返回 true; 否则返回 false
+pub fn glob_match(pattern: &str, string: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let string: Vec<char> = string.chars().collect();
+    match_chars(&pattern, &string)
+}
+
+/// internal：迭代的通配符匹配, 只记录最近一个 `*` 的位置, 失配时回溯到它并多吞掉一个字符,
+/// 时间复杂度 O(M * N), 不会因为多个 `*` 而指数级回溯
+fn match_chars(pattern: &[char], string: &[char]) -> bool {
+    let mut p = 0;
+    let mut s = 0;
+    // 最近一个 `*` 之后的模式位置, 以及它当前匹配到的字符串位置
+    let mut star: Option<(usize, usize)> = None;
+    while s < string.len() {
+        if p < pattern.len() && pattern[p] == '*' {
+            while p < pattern.len() && pattern[p] == '*' {
+                p += 1;
+            }
+            if p == pattern.len() {
+                return true;
+            }
+            star = Some((p, s));
+            continue;
+        }
+        match match_one(pattern, p, string[s]) {
+            Some(next) => {
+                p = next;
+                s += 1;
+            }
+            None => match star {
+                Some((star_p, star_s)) => {
+                    star = Some((star_p, star_s + 1));
+                    p = star_p;
+                    s = star_s + 1;
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p.min(pattern.len())..].iter().all(|c| *c == '*')
+}
+
+/// internal：用 `p` 处的单个模式元素（`?`、字符集、转义字符或普通字符）匹配字符 c
+///
+/// 返回值：匹配时返回下一个模式元素的位置; 不匹配或模式已经结束返回 None
+fn match_one(pattern: &[char], p: usize, c: char) -> Option<usize> {
+    if p >= pattern.len() {
+        return None;
+    }
+    let (matched, next) = match pattern[p] {
+        '?' => (true, p + 1),
+        '[' => match_class(pattern, p + 1, c),
+        '\\' if p + 1 < pattern.len() => (pattern[p + 1] == c, p + 2),
+        other => (other == c, p + 1),
+    };
+    if matched {
+        Some(next)
+    } else {
+        None
+    }
+}
+
+/// internal：匹配方括号字符集, `start` 指向 `[` 之后的第一个字符
+///
+/// 返回值： (是否匹配, 字符集结束后下一个模式字符的位置)
+fn match_class(pattern: &[char], start: usize, c: char) -> (bool, usize) {
+    let mut p = start;
+    let negate = p < pattern.len() && pattern[p] == '^';
+    if negate {
+        p += 1;
+    }
+    let mut matched = false;
+    while p < pattern.len() && pattern[p] != ']' {
+        if pattern[p] == '\\' && p + 1 < pattern.len() {
+            p += 1;
+            if pattern[p] == c {
+                matched = true;
+            }
+        } else if p + 2 < pattern.len() && pattern[p + 1] == '-' && pattern[p + 2] != ']' {
+            let (mut low, mut high) = (pattern[p], pattern[p + 2]);
+            if low > high {
+                std::mem::swap(&mut low, &mut high);
+            }
+            if low <= c && c <= high {
+                matched = true;
+            }
+            p += 2;
+        } else if pattern[p] == c {
+            matched = true;
+        }
+        p += 1;
+    }
+    // 跳过结尾的 `]`；未闭合的字符集与 Redis 一样视为到模式结尾
+    (matched != negate, p + 1)
+}
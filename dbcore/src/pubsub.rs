@@ -0,0 +1,535 @@
+//! 发布/订阅, 语义与 Redis 的 `PUBLISH` / `SUBSCRIBE` / `PSUBSCRIBE` 以及分片频道（`SPUBLISH` / `SSUBSCRIBE`）一致。
+//!
+//! 消息不会保存：发布时只投递给当前的订阅者。每个订阅者有一个有界的缓冲区,
+//! 缓冲区满（订阅者处理得不够快）时订阅者被断开, 之后不再收到消息, 避免慢速的订阅者占用无限的内存。
+//!
+//! 频道按照名称的哈希分布在多个分片中, 每个分片有各自的锁, 发布到不同频道的消息可以并行投递。
+//! 分片频道与普通频道是独立的命名空间, 也不会匹配模式订阅。
+//!
+//! 示例：
+//! ```
+//! use dbcore::PubSub;
+//!
+//! let pubsub = PubSub::new();
+//! let subscriber = pubsub.subscriber();
+//! subscriber.psubscribe("news.*");
+//! assert_eq!(1, pubsub.publish("news.tech", "hello"));
+//! let message = subscriber.recv().unwrap();
+//! assert_eq!(("news.tech", "hello"), (message.channel.as_str(), message.payload.as_str()));
+//! ```
+
+use crate::pattern::glob_match;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeSet, HashMap};
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::mem;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, SyncSender, TryRecvError, TrySendError};
+use std::sync::{Arc, Mutex, MutexGuard, RwLock, RwLockReadGuard, RwLockWriteGuard};
+use std::time::Duration;
+
+/// 每个订阅者默认最多缓冲的消息数量
+pub const DEFAULT_SUBSCRIBER_BUFFER: usize = 1024;
+
+/// 频道分片的数量
+const SHARDS: usize = 16;
+
+/// 订阅者收到的消息
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Message {
+    /// 匹配的模式, 只有通过模式订阅收到的消息才有
+    pub pattern: Option<String>,
+    /// 发布消息的频道
+    pub channel: String,
+    pub payload: String,
+    /// 是否发布到分片频道
+    pub sharded: bool,
+}
+
+/// 接收消息失败的原因
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecvError {
+    /// 缓冲区已满, 订阅者已经被断开
+    Overflow,
+    /// 订阅者已经关闭
+    Closed,
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// internal：缓冲区溢出时调用的函数
+struct OverflowHandler(Box<dyn FnOnce() + Send>);
+
+impl fmt::Debug for OverflowHandler {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("OverflowHandler")
+    }
+}
+
+/// internal：订阅者的收件箱
+#[derive(Debug)]
+struct Inbox {
+    // 订阅者关闭或者被断开之后为 None
+    sender: Mutex<Option<SyncSender<Message>>>,
+    overflowed: AtomicBool,
+    on_overflow: Mutex<Option<OverflowHandler>>,
+}
+
+impl Inbox {
+    /// internal：投递消息, 缓冲区已满时断开订阅者；返回是否投递成功
+    fn deliver(&self, message: Message) -> bool {
+        let mut sender = lock(&self.sender);
+        let result = match sender.as_ref() {
+            Some(sender) => sender.try_send(message),
+            None => return false,
+        };
+        match result {
+            Ok(()) => true,
+            Err(TrySendError::Full(_)) => {
+                self.overflowed.store(true, Ordering::SeqCst);
+                *sender = None;
+                if let Some(OverflowHandler(handler)) = lock(&self.on_overflow).take() {
+                    handler();
+                }
+                false
+            }
+            Err(TrySendError::Disconnected(_)) => {
+                *sender = None;
+                false
+            }
+        }
+    }
+}
+
+/// internal：频道（或模式）到订阅者编号与收件箱的映射
+type Subscribers = HashMap<String, HashMap<u64, Arc<Inbox>>>;
+
+fn add(subscribers: &mut Subscribers, name: &str, id: u64, inbox: &Arc<Inbox>) {
+    subscribers
+        .entry(String::from(name))
+        .or_default()
+        .insert(id, inbox.clone());
+}
+
+fn remove(subscribers: &mut Subscribers, name: &str, id: u64) {
+    if let Some(inboxes) = subscribers.get_mut(name) {
+        inboxes.remove(&id);
+        if inboxes.is_empty() {
+            subscribers.remove(name);
+        }
+    }
+}
+
+fn names(subscribers: &Subscribers, pattern: Option<&str>) -> Vec<String> {
+    subscribers
+        .keys()
+        .filter(|name| pattern.is_none_or(|pattern| glob_match(pattern, name)))
+        .cloned()
+        .collect()
+}
+
+/// internal：一个分片中的普通频道与分片频道
+#[derive(Debug, Default)]
+struct Shard {
+    channels: Subscribers,
+    shard_channels: Subscribers,
+}
+
+#[derive(Debug)]
+struct Registry {
+    shards: Vec<Mutex<Shard>>,
+    patterns: RwLock<Subscribers>,
+    next_id: AtomicU64,
+    buffer: AtomicUsize,
+}
+
+impl Registry {
+    fn shard(&self, channel: &str) -> MutexGuard<'_, Shard> {
+        let mut hasher = DefaultHasher::new();
+        channel.hash(&mut hasher);
+        lock(&self.shards[hasher.finish() as usize % SHARDS])
+    }
+
+    fn patterns(&self) -> RwLockReadGuard<'_, Subscribers> {
+        self.patterns
+            .read()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn patterns_mut(&self) -> RwLockWriteGuard<'_, Subscribers> {
+        self.patterns
+            .write()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// internal：所有分片中的频道名称
+    fn channels(&self, pattern: Option<&str>, sharded: bool) -> Vec<String> {
+        let mut channels: Vec<String> = self
+            .shards
+            .iter()
+            .flat_map(|shard| {
+                let shard = lock(shard);
+                let subscribers = if sharded {
+                    &shard.shard_channels
+                } else {
+                    &shard.channels
+                };
+                names(subscribers, pattern)
+            })
+            .collect();
+        channels.sort();
+        channels
+    }
+}
+
+/// 发布/订阅的频道注册表；复制得到的 `PubSub` 共享同一组频道
+#[derive(Debug, Clone)]
+pub struct PubSub {
+    registry: Arc<Registry>,
+}
+
+impl Default for PubSub {
+    fn default() -> Self {
+        PubSub::new()
+    }
+}
+
+impl PubSub {
+    /// 新建没有订阅者的注册表, 订阅者的缓冲区大小为 `DEFAULT_SUBSCRIBER_BUFFER`
+    pub fn new() -> PubSub {
+        PubSub {
+            registry: Arc::new(Registry {
+                shards: (0..SHARDS).map(|_| Mutex::new(Shard::default())).collect(),
+                patterns: RwLock::new(HashMap::new()),
+                next_id: AtomicU64::new(1),
+                buffer: AtomicUsize::new(DEFAULT_SUBSCRIBER_BUFFER),
+            }),
+        }
+    }
+
+    /// 设置之后创建的订阅者最多缓冲的消息数量, 至少为 1
+    pub fn set_subscriber_buffer(&self, messages: usize) {
+        self.registry
+            .buffer
+            .store(messages.max(1), Ordering::SeqCst);
+    }
+
+    /// 新建订阅者, 订阅频道或模式之后才会收到消息；drop 时取消所有订阅
+    pub fn subscriber(&self) -> Subscriber {
+        let (sender, receiver) = mpsc::sync_channel(self.registry.buffer.load(Ordering::SeqCst));
+        Subscriber {
+            registry: self.registry.clone(),
+            id: self.registry.next_id.fetch_add(1, Ordering::SeqCst),
+            inbox: Arc::new(Inbox {
+                sender: Mutex::new(Some(sender)),
+                overflowed: AtomicBool::new(false),
+                on_overflow: Mutex::new(None),
+            }),
+            receiver: Mutex::new(receiver),
+            subscriptions: Mutex::new(Subscriptions::default()),
+        }
+    }
+
+    ///
+    /// 把消息发布到频道, 投递给订阅了该频道以及订阅了匹配的模式的订阅者
+    /// 时间复杂度 O(N+M), N 为该频道的订阅者数量, M 为模式订阅的数量
+    ///
+    /// 返回值：收到消息的订阅者数量, 同时订阅了频道与模式的订阅者收到多条消息, 重复计数
+    pub fn publish(&self, channel: &str, payload: &str) -> usize {
+        // 投递期间持有分片的锁, 同一个频道的消息按照发布的顺序到达
+        let shard = self.registry.shard(channel);
+        let mut receivers = 0;
+        if let Some(inboxes) = shard.channels.get(channel) {
+            for inbox in inboxes.values() {
+                let message = Message {
+                    pattern: None,
+                    channel: String::from(channel),
+                    payload: String::from(payload),
+                    sharded: false,
+                };
+                receivers += inbox.deliver(message) as usize;
+            }
+        }
+        for (pattern, inboxes) in self.registry.patterns().iter() {
+            if !glob_match(pattern, channel) {
+                continue;
+            }
+            for inbox in inboxes.values() {
+                let message = Message {
+                    pattern: Some(pattern.clone()),
+                    channel: String::from(channel),
+                    payload: String::from(payload),
+                    sharded: false,
+                };
+                receivers += inbox.deliver(message) as usize;
+            }
+        }
+        receivers
+    }
+
+    ///
+    /// 把消息发布到分片频道, 只投递给通过 `Subscriber::ssubscribe()` 订阅了该频道的订阅者
+    ///
+    /// 返回值：收到消息的订阅者数量
+    pub fn spublish(&self, channel: &str, payload: &str) -> usize {
+        let shard = self.registry.shard(channel);
+        shard.shard_channels.get(channel).map_or(0, |inboxes| {
+            inboxes
+                .values()
+                .filter(|inbox| {
+                    inbox.deliver(Message {
+                        pattern: None,
+                        channel: String::from(channel),
+                        payload: String::from(payload),
+                        sharded: true,
+                    })
+                })
+                .count()
+        })
+    }
+
+    /// 至少有一个订阅者的频道, 按名称排序；pattern 不为 None 时只返回匹配的频道
+    pub fn channels(&self, pattern: Option<&str>) -> Vec<String> {
+        self.registry.channels(pattern, false)
+    }
+
+    /// 至少有一个订阅者的分片频道, 按名称排序；pattern 不为 None 时只返回匹配的频道
+    pub fn shard_channels(&self, pattern: Option<&str>) -> Vec<String> {
+        self.registry.channels(pattern, true)
+    }
+
+    /// 频道的订阅者数量, 不包括模式订阅
+    pub fn numsub(&self, channel: &str) -> usize {
+        self.registry
+            .shard(channel)
+            .channels
+            .get(channel)
+            .map_or(0, |inboxes| inboxes.len())
+    }
+
+    /// 分片频道的订阅者数量
+    pub fn shard_numsub(&self, channel: &str) -> usize {
+        self.registry
+            .shard(channel)
+            .shard_channels
+            .get(channel)
+            .map_or(0, |inboxes| inboxes.len())
+    }
+
+    /// 被订阅的模式的数量, 多个订阅者订阅同一个模式只计算一次
+    pub fn numpat(&self) -> usize {
+        self.registry.patterns().len()
+    }
+}
+
+/// internal：订阅者当前订阅的频道与模式
+#[derive(Debug, Default)]
+struct Subscriptions {
+    channels: BTreeSet<String>,
+    patterns: BTreeSet<String>,
+    shard_channels: BTreeSet<String>,
+}
+
+impl Subscriptions {
+    fn count(&self) -> usize {
+        self.channels.len() + self.patterns.len()
+    }
+}
+
+/// 订阅者：通过有界的缓冲区接收消息, 可以在多个线程之间共享
+#[derive(Debug)]
+pub struct Subscriber {
+    registry: Arc<Registry>,
+    id: u64,
+    inbox: Arc<Inbox>,
+    receiver: Mutex<Receiver<Message>>,
+    subscriptions: Mutex<Subscriptions>,
+}
+
+impl Subscriber {
+    /// 订阅者的编号, 在同一个 `PubSub` 中唯一
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    /// 订阅频道, 重复订阅没有作用；返回订阅的频道与模式的总数
+    pub fn subscribe(&self, channel: &str) -> usize {
+        let mut subscriptions = lock(&self.subscriptions);
+        if subscriptions.channels.insert(String::from(channel)) {
+            let mut shard = self.registry.shard(channel);
+            add(&mut shard.channels, channel, self.id, &self.inbox);
+        }
+        subscriptions.count()
+    }
+
+    /// 取消订阅频道；返回订阅的频道与模式的总数
+    pub fn unsubscribe(&self, channel: &str) -> usize {
+        let mut subscriptions = lock(&self.subscriptions);
+        if subscriptions.channels.remove(channel) {
+            remove(&mut self.registry.shard(channel).channels, channel, self.id);
+        }
+        subscriptions.count()
+    }
+
+    /// 订阅 glob 模式（参见 `glob_match()`）, 重复订阅没有作用；返回订阅的频道与模式的总数
+    pub fn psubscribe(&self, pattern: &str) -> usize {
+        let mut subscriptions = lock(&self.subscriptions);
+        if subscriptions.patterns.insert(String::from(pattern)) {
+            add(
+                &mut self.registry.patterns_mut(),
+                pattern,
+                self.id,
+                &self.inbox,
+            );
+        }
+        subscriptions.count()
+    }
+
+    /// 取消订阅模式；返回订阅的频道与模式的总数
+    pub fn punsubscribe(&self, pattern: &str) -> usize {
+        let mut subscriptions = lock(&self.subscriptions);
+        if subscriptions.patterns.remove(pattern) {
+            remove(&mut self.registry.patterns_mut(), pattern, self.id);
+        }
+        subscriptions.count()
+    }
+
+    /// 订阅分片频道, 重复订阅没有作用；返回订阅的分片频道数量
+    pub fn ssubscribe(&self, channel: &str) -> usize {
+        let mut subscriptions = lock(&self.subscriptions);
+        if subscriptions.shard_channels.insert(String::from(channel)) {
+            let mut shard = self.registry.shard(channel);
+            add(&mut shard.shard_channels, channel, self.id, &self.inbox);
+        }
+        subscriptions.shard_channels.len()
+    }
+
+    /// 取消订阅分片频道；返回订阅的分片频道数量
+    pub fn sunsubscribe(&self, channel: &str) -> usize {
+        let mut subscriptions = lock(&self.subscriptions);
+        if subscriptions.shard_channels.remove(channel) {
+            let mut shard = self.registry.shard(channel);
+            remove(&mut shard.shard_channels, channel, self.id);
+        }
+        subscriptions.shard_channels.len()
+    }
+
+    /// 订阅的频道, 按名称排序
+    pub fn channels(&self) -> Vec<String> {
+        lock(&self.subscriptions).channels.iter().cloned().collect()
+    }
+
+    /// 订阅的模式, 按名称排序
+    pub fn patterns(&self) -> Vec<String> {
+        lock(&self.subscriptions).patterns.iter().cloned().collect()
+    }
+
+    /// 订阅的分片频道, 按名称排序
+    pub fn shard_channels(&self) -> Vec<String> {
+        lock(&self.subscriptions)
+            .shard_channels
+            .iter()
+            .cloned()
+            .collect()
+    }
+
+    /// 是否订阅了任意频道、模式或分片频道
+    pub fn is_subscribed(&self) -> bool {
+        let subscriptions = lock(&self.subscriptions);
+        subscriptions.count() + subscriptions.shard_channels.len() > 0
+    }
+
+    ///
+    /// 设置缓冲区溢出时调用的函数, 例如关闭网络连接；函数在发布消息的线程中调用, 最多调用一次
+    /// 已经溢出时立即调用, 之前设置的函数被替换
+    pub fn on_overflow<F: FnOnce() + Send + 'static>(&self, handler: F) {
+        let mut on_overflow = lock(&self.inbox.on_overflow);
+        if self.inbox.overflowed.load(Ordering::SeqCst) {
+            drop(on_overflow);
+            handler();
+        } else {
+            *on_overflow = Some(OverflowHandler(Box::new(handler)));
+        }
+    }
+
+    fn disconnected(&self) -> RecvError {
+        if self.inbox.overflowed.load(Ordering::SeqCst) {
+            RecvError::Overflow
+        } else {
+            RecvError::Closed
+        }
+    }
+
+    fn check(&self) -> Result<(), RecvError> {
+        match self.disconnected() {
+            RecvError::Overflow => Err(RecvError::Overflow),
+            RecvError::Closed => Ok(()),
+        }
+    }
+
+    ///
+    /// 等待并接收下一条消息
+    ///
+    /// 返回值：
+    ///     * 消息
+    ///     * 缓冲区曾经溢出， 返回 Overflow, 缓冲区中剩余的消息被丢弃
+    ///     * 订阅者已经关闭， 返回 Closed
+    pub fn recv(&self) -> Result<Message, RecvError> {
+        self.check()?;
+        let received = lock(&self.receiver).recv();
+        received.map_err(|_| self.disconnected())
+    }
+
+    /// 接收缓冲区中的下一条消息, 没有消息时立即返回 None；错误同 `recv()`
+    pub fn try_recv(&self) -> Result<Option<Message>, RecvError> {
+        self.check()?;
+        let received = lock(&self.receiver).try_recv();
+        match received {
+            Ok(message) => Ok(Some(message)),
+            Err(TryRecvError::Empty) => Ok(None),
+            Err(TryRecvError::Disconnected) => Err(self.disconnected()),
+        }
+    }
+
+    /// 最多等待 timeout 接收下一条消息, 超时返回 None；错误同 `recv()`
+    pub fn recv_timeout(&self, timeout: Duration) -> Result<Option<Message>, RecvError> {
+        self.check()?;
+        let received = lock(&self.receiver).recv_timeout(timeout);
+        match received {
+            Ok(message) => Ok(Some(message)),
+            Err(RecvTimeoutError::Timeout) => Ok(None),
+            Err(RecvTimeoutError::Disconnected) => Err(self.disconnected()),
+        }
+    }
+
+    /// 取消所有订阅并关闭订阅者, 等待消息的线程收到 `RecvError::Closed`；重复关闭没有作用
+    pub fn close(&self) {
+        let mut subscriptions = lock(&self.subscriptions);
+        for channel in mem::take(&mut subscriptions.channels) {
+            remove(
+                &mut self.registry.shard(&channel).channels,
+                &channel,
+                self.id,
+            );
+        }
+        for pattern in mem::take(&mut subscriptions.patterns) {
+            remove(&mut self.registry.patterns_mut(), &pattern, self.id);
+        }
+        for channel in mem::take(&mut subscriptions.shard_channels) {
+            let mut shard = self.registry.shard(&channel);
+            remove(&mut shard.shard_channels, &channel, self.id);
+        }
+        *lock(&self.inbox.sender) = None;
+    }
+}
+
+impl Drop for Subscriber {
+    fn drop(&mut self) {
+        self.close();
+    }
+}
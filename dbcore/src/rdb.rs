@@ -0,0 +1,644 @@
+//! 读取 Redis 的 RDB 文件, 用于把已有的 Redis 数据迁移到 memkv。
+//!
+//! 支持 RDB 版本 1 到 12（Redis 7.4 之前）：
+//!     * 字符串、列表、集合、有序集合、哈希表, 以及它们的紧凑编码：
+//!       intset、zipmap、ziplist、listpack、quicklist
+//!     * 整数编码与 LZF 压缩的字符串
+//!     * 过期时间（秒与毫秒）、SELECTDB、RESIZEDB、AUX 等操作码
+//!     * 文件末尾的 CRC64 校验和（为 0 时表示未开启校验）
+//!
+//! memkv 没有列表和有序集合类型, 这两类 key 会被完整解析但跳过, 并在导入结果中列出；
+//! key 或 value 不是合法 UTF-8 的 key 同样被跳过。
+//! 无法解析的类型（stream、module 以及带字段过期时间的哈希表）会返回 NotSupported。
+
+use crate::checksum::crc64;
+use crate::encoding::Reader;
+use crate::{now_millis, DBError, ImportedKey, Result, Value, KVDB};
+use std::collections::HashMap;
+
+/// 支持的最高 RDB 版本
+pub const RDB_MAX_VERSION: u32 = 12;
+
+const OP_FUNCTION2: u8 = 0xf5;
+const OP_MODULE_AUX: u8 = 0xf7;
+const OP_IDLE: u8 = 0xf8;
+const OP_FREQ: u8 = 0xf9;
+const OP_AUX: u8 = 0xfa;
+const OP_RESIZEDB: u8 = 0xfb;
+const OP_EXPIRETIME_MS: u8 = 0xfc;
+const OP_EXPIRETIME: u8 = 0xfd;
+const OP_SELECTDB: u8 = 0xfe;
+const OP_EOF: u8 = 0xff;
+
+const TYPE_STRING: u8 = 0;
+const TYPE_LIST: u8 = 1;
+const TYPE_SET: u8 = 2;
+const TYPE_ZSET: u8 = 3;
+const TYPE_HASH: u8 = 4;
+const TYPE_ZSET_2: u8 = 5;
+const TYPE_HASH_ZIPMAP: u8 = 9;
+const TYPE_LIST_ZIPLIST: u8 = 10;
+const TYPE_SET_INTSET: u8 = 11;
+const TYPE_ZSET_ZIPLIST: u8 = 12;
+const TYPE_HASH_ZIPLIST: u8 = 13;
+const TYPE_LIST_QUICKLIST: u8 = 14;
+const TYPE_HASH_LISTPACK: u8 = 16;
+const TYPE_ZSET_LISTPACK: u8 = 17;
+const TYPE_LIST_QUICKLIST_2: u8 = 18;
+const TYPE_SET_LISTPACK: u8 = 20;
+
+const QUICKLIST_NODE_PLAIN: u64 = 1;
+
+/// 被跳过的 key
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SkippedKey {
+    /// key 所在的数据库编号
+    pub db: usize,
+    /// key, 不是合法 UTF-8 时无法识别的字节被替换为 U+FFFD
+    pub key: String,
+    /// 跳过的原因
+    pub reason: String,
+}
+
+/// 导入 RDB 文件的结果
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RdbImport {
+    /// RDB 文件的版本
+    pub version: u32,
+    /// 导入的 key 的数量
+    pub imported: usize,
+    /// 已经过期而没有导入的 key 的数量
+    pub expired: usize,
+    /// memkv 无法保存而被跳过的 key
+    pub skipped: Vec<SkippedKey>,
+}
+
+/// internal：RDB 中未经转换的 value, 哈希表的 field、value 交替存放, 列表与有序集合只记录类型名称
+enum RdbValue {
+    String(Vec<u8>),
+    Set(Vec<Vec<u8>>),
+    Hash(Vec<Vec<u8>>),
+    Unsupported(&'static str),
+}
+
+impl RdbValue {
+    /// 转换为 memkv 的 value, 列表、有序集合以及不是 UTF-8 的内容返回 NotSupported
+    fn into_value(self) -> Result<Value> {
+        let binary = || DBError::NotSupported(String::from("binary value"));
+        let to_utf8 = |bytes: Vec<u8>| String::from_utf8(bytes).map_err(|_| binary());
+        match self {
+            RdbValue::String(bytes) => Ok(Value::StringValue(to_utf8(bytes)?)),
+            RdbValue::Set(members) => Ok(Value::SetValue(
+                members.into_iter().map(to_utf8).collect::<Result<_>>()?,
+            )),
+            RdbValue::Hash(items) => {
+                if items.len() % 2 != 0 {
+                    return Err(invalid("hash has an odd number of elements"));
+                }
+                let mut hash = HashMap::new();
+                let mut items = items.into_iter();
+                while let (Some(field), Some(value)) = (items.next(), items.next()) {
+                    hash.insert(to_utf8(field)?, to_utf8(value)?);
+                }
+                Ok(Value::HashValue(hash))
+            }
+            RdbValue::Unsupported(name) => {
+                Err(DBError::NotSupported(format!("{} value in memkv", name)))
+            }
+        }
+    }
+}
+
+/// internal：RDB 文件中的一个 key
+pub(crate) struct RdbEntry {
+    pub(crate) db: usize,
+    pub(crate) key: Vec<u8>,
+    pub(crate) value: Result<Value>,
+    pub(crate) expire_at: Option<u64>,
+}
+
+fn invalid(reason: &str) -> DBError {
+    DBError::InvalidPayload(format!("RDB {}", reason))
+}
+
+/// internal：RDB 的长度编码, 特殊编码（整数、LZF）由 `Length::Encoded` 表示
+enum Length {
+    Len(u64),
+    Encoded(u8),
+}
+
+struct RdbReader<'a> {
+    reader: Reader<'a>,
+}
+
+impl<'a> RdbReader<'a> {
+    fn read_u8(&mut self) -> Result<u8> {
+        self.reader.read_u8()
+    }
+
+    fn read_bytes(&mut self, n: u64) -> Result<&'a [u8]> {
+        if n > usize::MAX as u64 {
+            return Err(invalid("length overflow"));
+        }
+        self.reader.read_bytes(n as usize)
+    }
+
+    fn read_le(&mut self, n: usize) -> Result<u64> {
+        let bytes = self.read_bytes(n as u64)?;
+        Ok(bytes
+            .iter()
+            .rev()
+            .fold(0u64, |acc, b| (acc << 8) | u64::from(*b)))
+    }
+
+    fn read_be(&mut self, n: usize) -> Result<u64> {
+        let bytes = self.read_bytes(n as u64)?;
+        Ok(bytes.iter().fold(0u64, |acc, b| (acc << 8) | u64::from(*b)))
+    }
+
+    fn read_length_encoding(&mut self) -> Result<Length> {
+        let first = self.read_u8()?;
+        match first >> 6 {
+            0 => Ok(Length::Len(u64::from(first & 0x3f))),
+            1 => {
+                let next = self.read_u8()?;
+                Ok(Length::Len(
+                    (u64::from(first & 0x3f) << 8) | u64::from(next),
+                ))
+            }
+            2 => match first {
+                0x80 => Ok(Length::Len(self.read_be(4)?)),
+                0x81 => Ok(Length::Len(self.read_be(8)?)),
+                _ => Err(invalid("unknown length encoding")),
+            },
+            _ => Ok(Length::Encoded(first & 0x3f)),
+        }
+    }
+
+    fn read_length(&mut self) -> Result<u64> {
+        match self.read_length_encoding()? {
+            Length::Len(len) => Ok(len),
+            Length::Encoded(_) => Err(invalid("unexpected string encoding")),
+        }
+    }
+
+    /// 读取字符串, 整数编码的字符串转换为十进制文本, LZF 压缩的字符串会被解压
+    fn read_string(&mut self) -> Result<Vec<u8>> {
+        match self.read_length_encoding()? {
+            Length::Len(len) => Ok(self.read_bytes(len)?.to_vec()),
+            Length::Encoded(0) => Ok((self.read_le(1)? as i8).to_string().into_bytes()),
+            Length::Encoded(1) => Ok((self.read_le(2)? as i16).to_string().into_bytes()),
+            Length::Encoded(2) => Ok((self.read_le(4)? as i32).to_string().into_bytes()),
+            Length::Encoded(3) => {
+                let compressed_len = self.read_length()?;
+                let len = self.read_length()?;
+                lzf_decompress(self.read_bytes(compressed_len)?, len)
+            }
+            Length::Encoded(_) => Err(invalid("unknown string encoding")),
+        }
+    }
+
+    /// 读取旧版有序集合中以字符串保存的分值
+    fn read_double_string(&mut self) -> Result<f64> {
+        match self.read_u8()? {
+            253 => Ok(f64::NAN),
+            254 => Ok(f64::INFINITY),
+            255 => Ok(f64::NEG_INFINITY),
+            len => std::str::from_utf8(self.read_bytes(u64::from(len))?)
+                .ok()
+                .and_then(|s| s.parse::<f64>().ok())
+                .ok_or_else(|| invalid("bad zset score")),
+        }
+    }
+
+    fn read_value(&mut self, value_type: u8) -> Result<RdbValue> {
+        match value_type {
+            TYPE_STRING => Ok(RdbValue::String(self.read_string()?)),
+            TYPE_LIST => {
+                for _ in 0..self.read_length()? {
+                    self.read_string()?;
+                }
+                Ok(RdbValue::Unsupported("list"))
+            }
+            TYPE_SET => {
+                let mut members = Vec::new();
+                for _ in 0..self.read_length()? {
+                    members.push(self.read_string()?);
+                }
+                Ok(RdbValue::Set(members))
+            }
+            TYPE_ZSET | TYPE_ZSET_2 => {
+                for _ in 0..self.read_length()? {
+                    self.read_string()?;
+                    if value_type == TYPE_ZSET {
+                        self.read_double_string()?;
+                    } else {
+                        self.read_bytes(8)?;
+                    }
+                }
+                Ok(RdbValue::Unsupported("zset"))
+            }
+            TYPE_HASH => {
+                let mut items = Vec::new();
+                for _ in 0..self.read_length()? * 2 {
+                    items.push(self.read_string()?);
+                }
+                Ok(RdbValue::Hash(items))
+            }
+            TYPE_HASH_ZIPMAP => Ok(RdbValue::Hash(zipmap_entries(&self.read_string()?)?)),
+            TYPE_LIST_ZIPLIST => {
+                ziplist_entries(&self.read_string()?)?;
+                Ok(RdbValue::Unsupported("list"))
+            }
+            TYPE_SET_INTSET => Ok(RdbValue::Set(intset_entries(&self.read_string()?)?)),
+            TYPE_ZSET_ZIPLIST | TYPE_ZSET_LISTPACK => {
+                let blob = self.read_string()?;
+                if value_type == TYPE_ZSET_ZIPLIST {
+                    ziplist_entries(&blob)?;
+                } else {
+                    listpack_entries(&blob)?;
+                }
+                Ok(RdbValue::Unsupported("zset"))
+            }
+            TYPE_HASH_ZIPLIST | TYPE_HASH_LISTPACK => {
+                let blob = self.read_string()?;
+                let items = if value_type == TYPE_HASH_ZIPLIST {
+                    ziplist_entries(&blob)?
+                } else {
+                    listpack_entries(&blob)?
+                };
+                Ok(RdbValue::Hash(items))
+            }
+            TYPE_LIST_QUICKLIST => {
+                for _ in 0..self.read_length()? {
+                    ziplist_entries(&self.read_string()?)?;
+                }
+                Ok(RdbValue::Unsupported("list"))
+            }
+            TYPE_LIST_QUICKLIST_2 => {
+                for _ in 0..self.read_length()? {
+                    let container = self.read_length()?;
+                    let blob = self.read_string()?;
+                    if container != QUICKLIST_NODE_PLAIN {
+                        listpack_entries(&blob)?;
+                    }
+                }
+                Ok(RdbValue::Unsupported("list"))
+            }
+            TYPE_SET_LISTPACK => Ok(RdbValue::Set(listpack_entries(&self.read_string()?)?)),
+            6 | 7 => Err(DBError::NotSupported(String::from("RDB module value"))),
+            15 | 19 | 21 => Err(DBError::NotSupported(String::from("RDB stream value"))),
+            22..=25 => Err(DBError::NotSupported(String::from(
+                "RDB hash with field expiration",
+            ))),
+            other => Err(invalid(&format!("unknown value type {}", other))),
+        }
+    }
+}
+
+/// internal：解压 LZF 压缩的数据, len 为解压后的长度
+fn lzf_decompress(input: &[u8], len: u64) -> Result<Vec<u8>> {
+    let bad = || invalid("bad LZF compressed string");
+    let mut out: Vec<u8> = Vec::with_capacity(len.min(1 << 20) as usize);
+    let mut i = 0;
+    while i < input.len() {
+        let ctrl = usize::from(input[i]);
+        i += 1;
+        if ctrl < 32 {
+            let run = ctrl + 1;
+            out.extend_from_slice(input.get(i..i + run).ok_or_else(bad)?);
+            i += run;
+        } else {
+            let mut run = ctrl >> 5;
+            if run == 7 {
+                run += usize::from(*input.get(i).ok_or_else(bad)?);
+                i += 1;
+            }
+            let back = ((ctrl & 0x1f) << 8) + usize::from(*input.get(i).ok_or_else(bad)?) + 1;
+            i += 1;
+            let start = out.len().checked_sub(back).ok_or_else(bad)?;
+            // 引用的区域可能与正在写入的区域重叠, 必须逐字节复制
+            for k in 0..run + 2 {
+                let byte = out[start + k];
+                out.push(byte);
+            }
+        }
+    }
+    if out.len() as u64 != len {
+        return Err(bad());
+    }
+    Ok(out)
+}
+
+/// internal：解析 intset 编码的集合
+fn intset_entries(blob: &[u8]) -> Result<Vec<Vec<u8>>> {
+    let mut reader = RdbReader {
+        reader: Reader::new(blob),
+    };
+    let width = reader.read_le(4)? as usize;
+    if width != 2 && width != 4 && width != 8 {
+        return Err(invalid("bad intset encoding"));
+    }
+    let len = reader.read_le(4)?;
+    let mut members = Vec::new();
+    for _ in 0..len {
+        let raw = reader.read_le(width)?;
+        let value = match width {
+            2 => i64::from(raw as i16),
+            4 => i64::from(raw as i32),
+            _ => raw as i64,
+        };
+        members.push(value.to_string().into_bytes());
+    }
+    Ok(members)
+}
+
+/// internal：解析 zipmap 编码的哈希表, 返回交替的 field、value
+fn zipmap_entries(blob: &[u8]) -> Result<Vec<Vec<u8>>> {
+    let mut reader = RdbReader {
+        reader: Reader::new(blob),
+    };
+    reader.read_u8()?;
+    let mut items = Vec::new();
+    loop {
+        let len = match reader.read_u8()? {
+            0xff => return Ok(items),
+            254 => reader.read_le(4)?,
+            len => u64::from(len),
+        };
+        let free = if items.len() % 2 == 1 {
+            reader.read_u8()?
+        } else {
+            0
+        };
+        items.push(reader.read_bytes(len)?.to_vec());
+        reader.read_bytes(u64::from(free))?;
+    }
+}
+
+/// internal：解析 ziplist, 返回其中的所有元素, 整数元素转换为十进制文本
+fn ziplist_entries(blob: &[u8]) -> Result<Vec<Vec<u8>>> {
+    let mut reader = RdbReader {
+        reader: Reader::new(blob),
+    };
+    reader.read_bytes(10)?;
+    let mut items = Vec::new();
+    loop {
+        let prevlen = reader.read_u8()?;
+        if prevlen == 0xff {
+            return Ok(items);
+        }
+        if prevlen == 254 {
+            reader.read_bytes(4)?;
+        }
+        let encoding = reader.read_u8()?;
+        let item = match encoding >> 6 {
+            0 => reader.read_bytes(u64::from(encoding & 0x3f))?.to_vec(),
+            1 => {
+                let len = (u64::from(encoding & 0x3f) << 8) | u64::from(reader.read_u8()?);
+                reader.read_bytes(len)?.to_vec()
+            }
+            2 => {
+                let len = reader.read_be(4)?;
+                reader.read_bytes(len)?.to_vec()
+            }
+            _ => {
+                let value = match encoding {
+                    0xc0 => i64::from(reader.read_le(2)? as i16),
+                    0xd0 => i64::from(reader.read_le(4)? as i32),
+                    0xe0 => reader.read_le(8)? as i64,
+                    0xf0 => (((reader.read_le(3)? as u32) << 8) as i32 >> 8) as i64,
+                    0xfe => i64::from(reader.read_le(1)? as i8),
+                    0xf1..=0xfd => i64::from(encoding & 0x0f) - 1,
+                    _ => return Err(invalid("bad ziplist entry encoding")),
+                };
+                value.to_string().into_bytes()
+            }
+        };
+        items.push(item);
+    }
+}
+
+/// internal：解析 listpack, 返回其中的所有元素, 整数元素转换为十进制文本
+fn listpack_entries(blob: &[u8]) -> Result<Vec<Vec<u8>>> {
+    let mut reader = RdbReader {
+        reader: Reader::new(blob),
+    };
+    reader.read_bytes(6)?;
+    let mut items = Vec::new();
+    loop {
+        let encoding = reader.read_u8()?;
+        if encoding == 0xff {
+            return Ok(items);
+        }
+        // (元素, 编码与数据的总字节数)
+        let (item, size) = if encoding & 0x80 == 0 {
+            (i64::from(encoding & 0x7f).to_string().into_bytes(), 1)
+        } else if encoding & 0xc0 == 0x80 {
+            let len = u64::from(encoding & 0x3f);
+            (reader.read_bytes(len)?.to_vec(), 1 + len)
+        } else if encoding & 0xe0 == 0xc0 {
+            let raw = (u64::from(encoding & 0x1f) << 8) | u64::from(reader.read_u8()?);
+            let value = ((raw as i16) << 3) >> 3;
+            (value.to_string().into_bytes(), 2)
+        } else if encoding & 0xf0 == 0xe0 {
+            let len = (u64::from(encoding & 0x0f) << 8) | u64::from(reader.read_u8()?);
+            (reader.read_bytes(len)?.to_vec(), 2 + len)
+        } else {
+            match encoding {
+                0xf0 => {
+                    let len = reader.read_le(4)?;
+                    (reader.read_bytes(len)?.to_vec(), 5 + len)
+                }
+                0xf1 => (
+                    i64::from(reader.read_le(2)? as i16)
+                        .to_string()
+                        .into_bytes(),
+                    3,
+                ),
+                0xf2 => {
+                    let value = (((reader.read_le(3)? as u32) << 8) as i32 >> 8) as i64;
+                    (value.to_string().into_bytes(), 4)
+                }
+                0xf3 => (
+                    i64::from(reader.read_le(4)? as i32)
+                        .to_string()
+                        .into_bytes(),
+                    5,
+                ),
+                0xf4 => ((reader.read_le(8)? as i64).to_string().into_bytes(), 9),
+                _ => return Err(invalid("bad listpack entry encoding")),
+            }
+        };
+        // 跳过记录元素长度的 backlen
+        let backlen = match size {
+            0..=127 => 1,
+            128..=16382 => 2,
+            16383..=2_097_150 => 3,
+            2_097_151..=268_435_454 => 4,
+            _ => 5,
+        };
+        reader.read_bytes(backlen)?;
+        items.push(item);
+    }
+}
+
+/// internal：解析 RDB 文件, 按出现的顺序返回所有 key
+///
+/// 返回值： (RDB 版本, 所有 key)；单个 key 的 value 无法在 memkv 中保存时, 该 key 的 value 为错误
+pub(crate) fn parse(data: &[u8]) -> Result<(u32, Vec<RdbEntry>)> {
+    if data.len() < 9 || &data[..5] != b"REDIS" {
+        return Err(invalid("file signature is missing"));
+    }
+    let version = std::str::from_utf8(&data[5..9])
+        .ok()
+        .and_then(|v| v.parse::<u32>().ok())
+        .ok_or_else(|| invalid("version is not a number"))?;
+    if version == 0 || version > RDB_MAX_VERSION {
+        return Err(DBError::NotSupported(format!("RDB version {}", version)));
+    }
+
+    // 版本 5 开始文件末尾是 8 字节的 CRC64 校验和, 在解析之前校验, 保证损坏的文件报告为校验和错误
+    let body = if version >= 5 {
+        if data.len() < 9 + 1 + 8 {
+            return Err(invalid("file is truncated"));
+        }
+        let (body, footer) = data.split_at(data.len() - 8);
+        let mut crc = [0u8; 8];
+        crc.copy_from_slice(footer);
+        let expected = u64::from_le_bytes(crc);
+        if expected != 0 && crc64(0, body) != expected {
+            return Err(invalid("checksum mismatch"));
+        }
+        body
+    } else {
+        data
+    };
+
+    let mut reader = RdbReader {
+        reader: Reader::new(&body[9..]),
+    };
+    let mut entries = Vec::new();
+    let mut db = 0;
+    let mut expire_at = None;
+    loop {
+        match reader.read_u8()? {
+            OP_EOF => break,
+            OP_SELECTDB => db = reader.read_length()? as usize,
+            OP_RESIZEDB => {
+                reader.read_length()?;
+                reader.read_length()?;
+            }
+            OP_AUX => {
+                reader.read_string()?;
+                reader.read_string()?;
+            }
+            OP_EXPIRETIME => expire_at = Some(reader.read_le(4)? * 1000),
+            OP_EXPIRETIME_MS => expire_at = Some(reader.read_le(8)?),
+            OP_FREQ => {
+                reader.read_u8()?;
+            }
+            OP_IDLE => {
+                reader.read_length()?;
+            }
+            OP_FUNCTION2 => {
+                reader.read_string()?;
+            }
+            OP_MODULE_AUX => {
+                return Err(DBError::NotSupported(String::from("RDB module data")));
+            }
+            value_type => {
+                let key = reader.read_string()?;
+                let value = match reader.read_value(value_type) {
+                    Ok(value) => match value.into_value() {
+                        Err(e @ DBError::InvalidPayload(_)) => return Err(e),
+                        value => value,
+                    },
+                    Err(DBError::NotSupported(what)) => {
+                        return Err(DBError::NotSupported(format!(
+                            "{} (key `{}`)",
+                            what,
+                            String::from_utf8_lossy(&key)
+                        )))
+                    }
+                    Err(e) => return Err(e),
+                };
+                entries.push(RdbEntry {
+                    db,
+                    key,
+                    value,
+                    expire_at: expire_at.take(),
+                });
+            }
+        }
+    }
+
+    if !reader.reader.is_empty() {
+        return Err(invalid("trailing bytes after EOF"));
+    }
+    Ok((version, entries))
+}
+
+/// internal：把 `parse()` 的结果整理为导入结果, 同时返回需要写入的 key
+pub(crate) fn prepare(version: u32, entries: Vec<RdbEntry>) -> (RdbImport, Vec<ImportedKey>) {
+    let now = now_millis();
+    let mut report = RdbImport {
+        version,
+        ..RdbImport::default()
+    };
+    let mut values = Vec::new();
+    for entry in entries {
+        if entry.expire_at.is_some_and(|when| when <= now) {
+            report.expired += 1;
+            continue;
+        }
+        let RdbEntry {
+            db,
+            key,
+            value,
+            expire_at,
+        } = entry;
+        let reason = match (String::from_utf8(key), value) {
+            (Ok(key), Ok(value)) => {
+                values.push((db, key, value, expire_at));
+                continue;
+            }
+            (Err(e), _) => SkippedKey {
+                db,
+                key: String::from_utf8_lossy(e.as_bytes()).into_owned(),
+                reason: String::from("key is not valid UTF-8"),
+            },
+            (Ok(key), Err(e)) => SkippedKey {
+                db,
+                key,
+                reason: e.to_string(),
+            },
+        };
+        report.skipped.push(reason);
+    }
+    report.imported = values.len();
+    (report, values)
+}
+
+impl KVDB {
+    ///
+    /// 从 Redis RDB 文件的内容中导入编号为 index 的数据库, 同名的 key 会被覆盖, 其他数据库中的 key 会被忽略
+    /// RDB 不合法时不会修改任何数据
+    /// 时间复杂度 O(N), N 为 RDB 中key的数量
+    ///
+    /// 返回值：
+    ///     * 导入的结果, 包括导入的 key 的数量、已经过期的 key 的数量以及被跳过的 key
+    ///     * 文件格式或校验和不正确， 返回 InvalidPayload
+    ///     * 包含无法解析的类型（stream、module 等）， 返回 NotSupported
+    ///     * key 的数量超过上限， 返回 OutOfKeysSize
+    pub fn import_rdb(&mut self, data: &[u8], index: usize) -> Result<RdbImport> {
+        let (version, entries) = parse(data)?;
+        let entries = entries.into_iter().filter(|e| e.db == index).collect();
+        let (report, values) = prepare(version, entries);
+        self.check_import(values.iter().map(|(_, key, _, _)| key))?;
+        for (_, key, value, expire_at) in values {
+            self.import_key(key, value, expire_at);
+        }
+        Ok(report)
+    }
+}
@@ -0,0 +1,50 @@
+//! SCAN / SSCAN / HSCAN 使用的游标遍历。
+//!
+//! 游标是元素在固定哈希函数（FNV-1a 64位）下的哈希值：每次调用返回哈希值不小于游标的、
+//! 哈希值最小的一批元素, 下一次的游标为这批元素中最大哈希值加一。
+//! 元素的遍历顺序只由元素本身决定, 与 `HashMap` 内部的桶布局无关,
+//! 因此即使遍历过程中发生扩容/缩容（rehash）, 在整个遍历期间一直存在的元素也一定会被返回,
+//! 并且只会返回一次（哈希冲突的元素总是在同一批中返回）。
+//!
+//! 代价是每次调用需要 O(N) 地检查一遍集合, N 为集合元素个数。
+
+const FNV_OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+
+/// 每次遍历默认返回的元素数量, 与 Redis 的默认 COUNT 相同
+pub const DEFAULT_SCAN_COUNT: usize = 10;
+
+/// internal：计算元素在遍历顺序中的位置
+pub(crate) fn scan_hash(key: &str) -> u64 {
+    key.bytes().fold(FNV_OFFSET_BASIS, |hash, byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(FNV_PRIME)
+    })
+}
+
+/// internal：从 `cursor` 开始取出至少 `count` 个元素（哈希冲突时可能多于 count 个）
+///
+/// 返回值： (下一次遍历的游标, 本次遍历到的元素)；游标为 0 时表示遍历结束
+pub(crate) fn scan_items<'a, T, I>(items: I, cursor: u64, count: usize) -> (u64, Vec<T>)
+where
+    I: Iterator<Item = (&'a String, T)>,
+{
+    let count = count.max(1);
+    let mut candidates: Vec<(u64, T)> = items
+        .map(|(key, item)| (scan_hash(key), item))
+        .filter(|(hash, _)| *hash >= cursor)
+        .collect();
+
+    if candidates.len() <= count {
+        return (0, candidates.into_iter().map(|(_, item)| item).collect());
+    }
+
+    let (_, nth, _) = candidates.select_nth_unstable_by_key(count - 1, |(hash, _)| *hash);
+    let threshold = nth.0;
+    let batch: Vec<T> = candidates
+        .into_iter()
+        .filter(|(hash, _)| *hash <= threshold)
+        .map(|(_, item)| item)
+        .collect();
+    // threshold 为 u64::MAX 时加一溢出为 0, 正好表示遍历结束
+    (threshold.wrapping_add(1), batch)
+}
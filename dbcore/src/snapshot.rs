@@ -0,0 +1,212 @@
+//! 快照持久化, 对应 Redis 的 RDB 文件以及 `SAVE` / `BGSAVE` 命令。
+//!
+//! 文件格式：
+//!     * 文件头为 5 字节魔数 "MEMKV" + 2 字节格式版本号（小端序）
+//!     * 可选的 LSN 操作码 + 8 字节 LSN（小端序）, 表示快照包含 WAL 中该 LSN 及之前的所有记录（版本 2）
+//!     * SELECTDB 操作码 + 数据库编号（LEB128）, 之后的 key 都属于该数据库
+//!     * 可选的 EXPIRETIME_MS 操作码 + 8 字节毫秒时间戳（小端序）, 作用于紧随其后的 key
+//!     * KEY 操作码 + key 字符串 + value 编码, 编码方式与 `DUMP` 相同, 参见 `encoding`；
+//!       压缩的字符串保持压缩保存（版本 3）, 可以包含自定义类型（版本 4）
+//!     * EOF 操作码 + 之前所有字节的 8 字节 CRC64 校验和（小端序）
+//!
+//! 文件先写入同目录下的临时文件, 刷新到磁盘后再重命名为目标文件,
+//! 因此保存过程中崩溃不会破坏已有的快照。
+
+use crate::checksum::crc64;
+use crate::encoding::{write_len, write_string, write_value, Reader};
+use crate::expires::Expires;
+use crate::{now_millis, DBError, Result, Value};
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+const MAGIC: &[u8] = b"MEMKV";
+
+/// 当前的快照格式版本号, 只能加载不高于该版本的快照
+pub(crate) const SNAPSHOT_VERSION: u16 = 4;
+
+const OP_LSN: u8 = 0xfb;
+const OP_EXPIRETIME_MS: u8 = 0xfc;
+const OP_KEY: u8 = 0xfd;
+const OP_SELECTDB: u8 = 0xfe;
+const OP_EOF: u8 = 0xff;
+
+/// 默认的自动保存规则, 与 Redis 相同
+pub const DEFAULT_SAVE_RULES: &str = "3600 1 300 100 60 10000";
+
+/// 自动保存规则：距离上次保存超过 seconds 秒, 并且期间至少有 changes 次修改时触发 `BGSAVE`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SaveRule {
+    pub seconds: u64,
+    pub changes: u64,
+}
+
+impl SaveRule {
+    ///
+    /// 解析 Redis `save` 配置格式的规则列表, 例如 "3600 1 300 100"
+    ///
+    /// 参数说明：
+    ///     * rules 由空白分隔的 "秒数 修改次数" 对, 空字符串表示不自动保存
+    ///
+    /// 返回值：
+    ///     * 解析得到的规则
+    ///     * 数字的个数不是偶数， 返回 Syntax
+    ///     * 无法解析为数字， 返回 NotANumber
+    pub fn parse_rules(rules: &str) -> Result<Vec<SaveRule>> {
+        let numbers = rules
+            .split_whitespace()
+            .map(|s| {
+                s.parse::<u64>()
+                    .map_err(|_| DBError::NotANumber(String::from(s)))
+            })
+            .collect::<Result<Vec<u64>>>()?;
+        if numbers.len() % 2 != 0 {
+            return Err(DBError::Syntax(String::from(
+                "save rules must be pairs of seconds and changes",
+            )));
+        }
+        Ok(numbers
+            .chunks(2)
+            .map(|pair| SaveRule {
+                seconds: pair[0],
+                changes: pair[1],
+            })
+            .collect())
+    }
+}
+
+/// internal：快照中的一个 key
+pub(crate) struct Entry {
+    pub(crate) index: usize,
+    pub(crate) key: String,
+    pub(crate) value: Value,
+    pub(crate) expire_at: Option<u64>,
+}
+
+/// internal：将若干个数据库编码为快照, 已经过期的 key 不会被保存；开启 WAL 时 lsn 为最后一条记录的 LSN
+pub(crate) fn encode<'a, I>(dbs: I, lsn: Option<u64>) -> Vec<u8>
+where
+    I: Iterator<Item = (usize, &'a HashMap<String, Value>, &'a Expires)>,
+{
+    let now = now_millis();
+    let mut buf = Vec::new();
+    buf.extend_from_slice(MAGIC);
+    buf.extend_from_slice(&SNAPSHOT_VERSION.to_le_bytes());
+    if let Some(lsn) = lsn {
+        buf.push(OP_LSN);
+        buf.extend_from_slice(&lsn.to_le_bytes());
+    }
+    for (index, db, ttl) in dbs {
+        if db.is_empty() {
+            continue;
+        }
+        buf.push(OP_SELECTDB);
+        write_len(&mut buf, index as u64);
+        for (key, value) in db.iter() {
+            let expire_at = ttl.get(key);
+            if let Some(when) = expire_at {
+                if *when <= now {
+                    continue;
+                }
+                buf.push(OP_EXPIRETIME_MS);
+                buf.extend_from_slice(&when.to_le_bytes());
+            }
+            buf.push(OP_KEY);
+            write_string(&mut buf, key);
+            write_value(&mut buf, value);
+        }
+    }
+    buf.push(OP_EOF);
+    let crc = crc64(0, &buf);
+    buf.extend_from_slice(&crc.to_le_bytes());
+    buf
+}
+
+/// internal：校验并解码 `encode()` 的结果, 过期时间不晚于 now 的 key 会被跳过, now 为 0 时保留所有 key
+///
+/// 返回值：(所有 key, 快照的 LSN)
+pub(crate) fn decode(data: &[u8], now: u64) -> Result<(Vec<Entry>, Option<u64>)> {
+    let header = MAGIC.len() + 2;
+    if data.len() < header + 1 + 8 || &data[..MAGIC.len()] != MAGIC {
+        return Err(DBError::InvalidPayload(String::from(
+            "not a memkv snapshot file",
+        )));
+    }
+    let (body, footer) = data.split_at(data.len() - 8);
+    let mut crc = [0u8; 8];
+    crc.copy_from_slice(footer);
+    if crc64(0, body) != u64::from_le_bytes(crc) {
+        return Err(DBError::InvalidPayload(String::from("checksum mismatch")));
+    }
+    let version = u16::from_le_bytes([body[MAGIC.len()], body[MAGIC.len() + 1]]);
+    if version > SNAPSHOT_VERSION {
+        return Err(DBError::InvalidPayload(format!(
+            "unsupported version {}",
+            version
+        )));
+    }
+
+    let mut reader = Reader::new(&body[header..]);
+    let mut entries = Vec::new();
+    let mut index = 0;
+    let mut expire_at = None;
+    let mut lsn = None;
+    loop {
+        match reader.read_u8()? {
+            OP_SELECTDB => index = reader.read_len()? as usize,
+            OP_EXPIRETIME_MS => {
+                let mut when = [0u8; 8];
+                when.copy_from_slice(reader.read_bytes(8)?);
+                expire_at = Some(u64::from_le_bytes(when));
+            }
+            OP_LSN => {
+                let mut number = [0u8; 8];
+                number.copy_from_slice(reader.read_bytes(8)?);
+                lsn = Some(u64::from_le_bytes(number));
+            }
+            OP_KEY => {
+                let key = reader.read_string()?;
+                let value = reader.read_value()?;
+                match expire_at.take() {
+                    Some(when) if when <= now => {}
+                    expire_at => entries.push(Entry {
+                        index,
+                        key,
+                        value,
+                        expire_at,
+                    }),
+                }
+            }
+            OP_EOF => break,
+            other => return Err(DBError::InvalidPayload(format!("unknown opcode {}", other))),
+        }
+    }
+    if !reader.is_empty() {
+        return Err(DBError::InvalidPayload(String::from("trailing bytes")));
+    }
+    Ok((entries, lsn))
+}
+
+/// internal：将 IO 错误转换为 DBError, 附带出错的文件
+pub(crate) fn io_error(path: &Path, err: std::io::Error) -> DBError {
+    DBError::Io(format!("{}: {}", path.display(), err))
+}
+
+/// internal：先写入临时文件并刷新到磁盘, 再原子地重命名为 path
+pub(crate) fn write_file(path: &Path, data: &[u8]) -> Result<()> {
+    let mut tmp = OsString::from(path.as_os_str());
+    tmp.push(".tmp");
+    let tmp = PathBuf::from(tmp);
+    let write = || -> std::io::Result<()> {
+        let mut file = File::create(&tmp)?;
+        file.write_all(data)?;
+        file.sync_all()
+    };
+    if let Err(e) = write() {
+        let _ = fs::remove_file(&tmp);
+        return Err(io_error(&tmp, e));
+    }
+    fs::rename(&tmp, path).map_err(|e| io_error(path, e))
+}
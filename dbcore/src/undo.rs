@@ -0,0 +1,210 @@
+//! 嵌入式使用的原子事务：`KVDB::transaction()`。
+//!
+//! 事务期间每个 key 第一次被修改（写入、删除、过期、修改生存时间等）之前, 把它原来的状态
+//! （value、过期时间、版本号、标记）记录到撤销日志中；清空数据库时记录整个数据库。
+//! 闭包返回错误时按撤销日志恢复所有被修改的 key, 同时恢复修改次数并丢弃事务期间记录的修改命令（AOF）,
+//! 效果等同于事务中的命令从未执行；闭包成功返回时丢弃撤销日志。
+//!
+//! 事务可以嵌套, 内层事务失败只撤销内层的修改。
+//! 已经产生的键空间事件无法撤销, 监听者可能收到被回滚的修改的事件。
+
+use crate::expires::Expires;
+use crate::{Result, Value, KVDB};
+use std::collections::HashMap;
+use std::mem;
+
+/// internal：key 第一次被修改之前的状态, value 为 None 表示 key 不存在
+#[derive(Debug)]
+struct SavedKey {
+    value: Option<Value>,
+    expire_at: Option<u64>,
+    version: Option<u64>,
+    flags: Option<u32>,
+}
+
+/// internal：事务开始时整个数据库的状态, 数据库在事务中被清空时记录
+#[derive(Debug, Default)]
+struct Image {
+    db: HashMap<String, Value>,
+    ttl: Expires,
+    versions: HashMap<String, u64>,
+    flags: HashMap<String, u32>,
+}
+
+impl Image {
+    /// internal：把 key 恢复为 saved 的状态
+    fn restore(&mut self, key: String, saved: SavedKey) {
+        let SavedKey {
+            value,
+            expire_at,
+            version,
+            flags,
+        } = saved;
+        match value {
+            Some(value) => self.db.insert(key.clone(), value),
+            None => self.db.remove(&key),
+        };
+        match expire_at {
+            Some(when) => self.ttl.insert(key.clone(), when),
+            None => self.ttl.remove(&key),
+        };
+        match version {
+            Some(version) => self.versions.insert(key.clone(), version),
+            None => self.versions.remove(&key),
+        };
+        match flags {
+            Some(flags) => self.flags.insert(key, flags),
+            None => self.flags.remove(&key),
+        };
+    }
+}
+
+/// internal：一层事务的撤销日志
+#[derive(Debug)]
+pub(crate) struct UndoLog {
+    /// 被修改的 key 在事务开始时的状态
+    keys: HashMap<String, SavedKey>,
+    /// 数据库被清空时, 事务开始时整个数据库的状态；之后的修改不需要再记录
+    image: Option<Image>,
+    /// 事务开始时的修改次数
+    dirty: u64,
+    /// 事务开始时已经记录的修改命令数量
+    propagated: usize,
+}
+
+/// internal：把 keys 中记录的状态应用到 image 上, 得到事务开始时的状态
+fn apply(keys: HashMap<String, SavedKey>, mut image: Image) -> Image {
+    keys.into_iter()
+        .for_each(|(key, saved)| image.restore(key, saved));
+    image
+}
+
+impl UndoLog {
+    /// internal：内层事务成功时并入外层事务
+    fn absorb(&mut self, inner: UndoLog) {
+        if self.image.is_some() {
+            return;
+        }
+        match inner.image {
+            // 内层清空过数据库：内层的快照是外层记录的 key 被修改之后的状态
+            Some(image) => self.image = Some(apply(mem::take(&mut self.keys), image)),
+            // 外层已经记录的 key 以外层的状态为准
+            None => {
+                for (key, saved) in inner.keys {
+                    self.keys.entry(key).or_insert(saved);
+                }
+            }
+        }
+    }
+}
+
+impl KVDB {
+    ///
+    /// 原子地执行 f：f 返回错误时, f 对数据库的所有修改都被撤销, 数据库恢复到调用之前的状态。
+    /// f 可以调用 `KVDB` 的任意方法, 包括 `max_keys` 限制在内的错误都可以用 `?` 返回并触发回滚。
+    ///
+    /// 返回值：
+    ///     * f 的返回值
+    ///     * f 返回错误时， 撤销所有修改并返回该错误
+    ///
+    /// 示例：
+    /// ```
+    /// use dbcore::{DBError, KVDB};
+    ///
+    /// let mut db = KVDB::new(Some(2));
+    /// let (from, to) = (String::from("from"), String::from("to"));
+    /// db.sadd(&from, vec![String::from("job")]).unwrap();
+    ///
+    /// let res = db.transaction(|tx| {
+    ///     tx.srem(&from, vec![String::from("job")])?;
+    ///     tx.sadd(&to, vec![String::from("job")])?;
+    ///     tx.sets(&String::from("moved"), String::from("1"))?;
+    ///     Ok(())
+    /// });
+    /// // 第三个 key 超出上限, 之前的修改都被撤销
+    /// assert_eq!(Err(DBError::OutOfKeysSize(2)), res);
+    /// assert_eq!(Ok(Some(true)), db.sismember(&from, &String::from("job")));
+    /// assert!(!db.exists(&to));
+    /// ```
+    pub fn transaction<T, F>(&mut self, f: F) -> Result<T>
+    where
+        F: FnOnce(&mut KVDB) -> Result<T>,
+    {
+        self.undo.push(UndoLog {
+            keys: HashMap::new(),
+            image: None,
+            dirty: self.dirty,
+            propagated: self.propagated.as_ref().map_or(0, Vec::len),
+        });
+        let res = f(self);
+        let log = self.undo.pop().expect("transaction undo log");
+        match &res {
+            Ok(_) => {
+                if let Some(outer) = self.undo.last_mut() {
+                    outer.absorb(log);
+                }
+            }
+            Err(_) => self.rollback(log),
+        }
+        res
+    }
+
+    /// 是否在 `transaction()` 中
+    pub fn in_transaction(&self) -> bool {
+        !self.undo.is_empty()
+    }
+
+    /// internal：key 将被修改, 事务中第一次修改时记录它原来的状态
+    pub(crate) fn save_undo(&mut self, key: &String) {
+        let log = match self.undo.last_mut() {
+            Some(log) if log.image.is_none() && !log.keys.contains_key(key) => log,
+            _ => return,
+        };
+        let saved = SavedKey {
+            value: self.db.get(key).cloned(),
+            expire_at: self.ttl.get(key).copied(),
+            version: self.versions.get(key).copied(),
+            flags: self.flags.get(key).copied(),
+        };
+        log.keys.insert(key.clone(), saved);
+    }
+
+    /// internal：数据库将被清空, 事务中记录整个数据库原来的状态
+    pub(crate) fn save_undo_all(&mut self) {
+        let log = match self.undo.last_mut() {
+            Some(log) if log.image.is_none() => log,
+            _ => return,
+        };
+        let image = Image {
+            db: self.db.clone(),
+            ttl: self.ttl.clone(),
+            versions: self.versions.clone(),
+            flags: self.flags.clone(),
+        };
+        log.image = Some(apply(mem::take(&mut log.keys), image));
+    }
+
+    /// internal：按撤销日志恢复数据库
+    fn rollback(&mut self, log: UndoLog) {
+        self.dirty = log.dirty;
+        if let Some(commands) = &mut self.propagated {
+            commands.truncate(log.propagated);
+        }
+        let image = match log.image {
+            Some(image) => image,
+            None => {
+                let current = Image {
+                    db: mem::take(&mut self.db),
+                    ttl: mem::take(&mut self.ttl),
+                    versions: mem::take(&mut self.versions),
+                    flags: mem::take(&mut self.flags),
+                };
+                apply(log.keys, current)
+            }
+        };
+        self.db = image.db;
+        self.ttl = image.ttl;
+        self.versions = image.versions;
+        self.flags = image.flags;
+    }
+}
@@ -0,0 +1,389 @@
+//! 预写日志（write-ahead log）, 提供比定期快照更强的持久性保证。
+//!
+//! 开启 WAL 后, 每个 `KVDB` 把成功执行的修改命令记录下来（与 AOF 相同, 参见 `aof`）,
+//! 由 `Databases` 编号后追加到日志；调用者在回复客户端之前调用 `Wal::commit()` 等待日志落盘。
+//!
+//! 文件格式：
+//!     * 文件头为 5 字节魔数 "MKWAL" + 2 字节格式版本号（小端序）
+//!     * 之后是若干条记录, 每条记录为 4 字节负载长度 + 8 字节负载的 CRC64 + 负载（均为小端序）
+//!     * 负载为 8 字节 LSN + 8 字节毫秒时间戳 + 数据库编号（LEB128） + 参数个数（LEB128） + 每个参数
+//!
+//! LSN（log sequence number）从 1 开始连续递增。
+//!
+//! 组提交（group commit）：多个线程的记录先追加到内存缓冲区,
+//! 第一个需要落盘的线程成为 leader, 在锁外把整个缓冲区写入文件并 fsync,
+//! 其他线程等待 leader 完成；一次 fsync 覆盖期间所有线程追加的记录。
+//!
+//! 检查点（checkpoint）把当前数据连同最后一条记录的 LSN 写入快照, 然后清空日志。
+//! 恢复时先加载快照, 再重放日志中 LSN 大于快照 LSN 的记录,
+//! 因此即使在写入快照之后、清空日志之前宕机, 也不会重复执行命令。
+//!
+//! 恢复时从头校验每条记录, 遇到第一条不完整（写入过程中宕机）或损坏（长度、校验和、LSN 不正确）
+//! 的记录就停止, 该记录及之后的内容被截掉, 因此恢复的结果总是日志的一个前缀。
+//!
+//! 配置了加密密钥时, 文件头以及每次组提交写入的记录加密为一帧, 参见 `crypto`。
+
+use crate::checksum::crc64;
+use crate::crypto::{self, EncryptionKey, FrameWriter, LogFile};
+use crate::encoding::{write_len, write_string, Reader};
+use crate::snapshot::{io_error, write_file};
+use crate::{now_millis, DBError, Result};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+
+const MAGIC: &[u8] = b"MKWAL";
+
+/// 当前的日志格式版本号
+const WAL_VERSION: u16 = 1;
+
+const HEADER_LEN: usize = MAGIC.len() + 2;
+
+/// 记录头：4 字节负载长度 + 8 字节 CRC64
+const RECORD_HEADER_LEN: usize = 4 + 8;
+
+/// 恢复的结果
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WalRecovery {
+    /// 快照中记录的检查点 LSN, 没有快照时为 0
+    pub checkpoint_lsn: u64,
+    /// 重放的记录数量
+    pub records: usize,
+    /// 恢复之后最后一条记录的 LSN
+    pub last_lsn: u64,
+    /// 重放的最后一条记录的时间戳（毫秒）, 没有重放记录时为 0
+    pub last_timestamp: u64,
+    /// 日志末尾被截掉的字节数
+    pub truncated: usize,
+}
+
+/// internal：日志中的一条记录
+pub(crate) struct WalRecord {
+    pub(crate) lsn: u64,
+    pub(crate) timestamp: u64,
+    pub(crate) index: usize,
+    pub(crate) command: Vec<String>,
+}
+
+/// internal：编码一条记录
+fn encode_record(buf: &mut Vec<u8>, lsn: u64, timestamp: u64, index: usize, command: &[String]) {
+    let mut payload = Vec::new();
+    payload.extend_from_slice(&lsn.to_le_bytes());
+    payload.extend_from_slice(&timestamp.to_le_bytes());
+    write_len(&mut payload, index as u64);
+    write_len(&mut payload, command.len() as u64);
+    command
+        .iter()
+        .for_each(|arg| write_string(&mut payload, arg));
+    buf.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+    buf.extend_from_slice(&crc64(0, &payload).to_le_bytes());
+    buf.extend_from_slice(&payload);
+}
+
+fn decode_payload(payload: &[u8]) -> Result<WalRecord> {
+    let mut reader = Reader::new(payload);
+    let mut number = [0u8; 8];
+    number.copy_from_slice(reader.read_bytes(8)?);
+    let lsn = u64::from_le_bytes(number);
+    number.copy_from_slice(reader.read_bytes(8)?);
+    let timestamp = u64::from_le_bytes(number);
+    let index = reader.read_len()? as usize;
+    let argc = reader.read_len()?;
+    let mut command = Vec::new();
+    for _ in 0..argc {
+        command.push(reader.read_string()?);
+    }
+    if command.is_empty() || !reader.is_empty() {
+        return Err(DBError::InvalidPayload(String::from("bad WAL record")));
+    }
+    Ok(WalRecord {
+        lsn,
+        timestamp,
+        index,
+        command,
+    })
+}
+
+/// internal：校验并解析日志, 在第一条不完整或损坏的记录处停止
+///
+/// 返回值：
+///     * (有效的记录, 有效部分的字节数)；空文件视为没有记录的日志
+///     * 文件头不正确， 返回 InvalidPayload
+pub(crate) fn read(data: &[u8]) -> Result<(Vec<WalRecord>, usize)> {
+    if data.is_empty() {
+        return Ok((Vec::new(), 0));
+    }
+    if data.len() < HEADER_LEN || &data[..MAGIC.len()] != MAGIC {
+        return Err(DBError::InvalidPayload(String::from(
+            "not a memkv WAL file",
+        )));
+    }
+    let version = u16::from_le_bytes([data[MAGIC.len()], data[MAGIC.len() + 1]]);
+    if version > WAL_VERSION {
+        return Err(DBError::InvalidPayload(format!(
+            "unsupported WAL version {}",
+            version
+        )));
+    }
+
+    let mut records: Vec<WalRecord> = Vec::new();
+    let mut pos = HEADER_LEN;
+    while data.len() - pos >= RECORD_HEADER_LEN {
+        let mut len = [0u8; 4];
+        len.copy_from_slice(&data[pos..pos + 4]);
+        let len = u32::from_le_bytes(len) as usize;
+        let mut crc = [0u8; 8];
+        crc.copy_from_slice(&data[pos + 4..pos + RECORD_HEADER_LEN]);
+        let start = pos + RECORD_HEADER_LEN;
+        if data.len() - start < len {
+            break;
+        }
+        let payload = &data[start..start + len];
+        if crc64(0, payload) != u64::from_le_bytes(crc) {
+            break;
+        }
+        let record = match decode_payload(payload) {
+            Ok(record) => record,
+            Err(_) => break,
+        };
+        if records
+            .last()
+            .is_some_and(|last| last.lsn + 1 != record.lsn)
+        {
+            break;
+        }
+        records.push(record);
+        pos = start + len;
+    }
+    Ok((records, pos))
+}
+
+/// internal：创建只有文件头的日志, 已经存在的文件会被原子地替换；key 不为 None 时加密
+///
+/// 返回值：继续追加写入加密的日志使用的 FrameWriter
+pub(crate) fn create(path: &Path, key: Option<&EncryptionKey>) -> Result<Option<FrameWriter>> {
+    let mut header = MAGIC.to_vec();
+    header.extend_from_slice(&WAL_VERSION.to_le_bytes());
+    let (data, frames) = crypto::seal_log(key, &header);
+    write_file(path, &data)?;
+    Ok(frames)
+}
+
+struct State {
+    // leader 写入期间被取走
+    file: Option<LogFile>,
+    // 日志使用的加密密钥
+    key: Option<EncryptionKey>,
+    // 已经追加、尚未写入文件的记录
+    pending: Vec<u8>,
+    next_lsn: u64,
+    durable_lsn: u64,
+    syncing: bool,
+    // 写入失败之后日志可能不完整, 之后的提交都会失败
+    failed: Option<String>,
+    syncs: u64,
+    size: u64,
+}
+
+struct Shared {
+    path: PathBuf,
+    snapshot: PathBuf,
+    state: Mutex<State>,
+    durable: Condvar,
+}
+
+/// 预写日志的句柄, 可以复制到其他线程, 在不持有数据库的情况下等待提交
+#[derive(Clone)]
+pub struct Wal {
+    shared: Arc<Shared>,
+}
+
+impl Wal {
+    /// internal：打开已经恢复完毕的日志, 之后的记录从 next_lsn 开始编号；加密的日志需要提供继续写入使用的 FrameWriter
+    pub(crate) fn open(
+        path: &Path,
+        snapshot: &Path,
+        next_lsn: u64,
+        frames: Option<FrameWriter>,
+    ) -> Result<Wal> {
+        let key = frames.as_ref().map(|writer| writer.key().clone());
+        let file = LogFile::open(path, frames)?;
+        let size = file.file().metadata().map_err(|e| io_error(path, e))?.len();
+        Ok(Wal {
+            shared: Arc::new(Shared {
+                path: path.to_path_buf(),
+                snapshot: snapshot.to_path_buf(),
+                state: Mutex::new(State {
+                    file: Some(file),
+                    key,
+                    pending: Vec::new(),
+                    next_lsn,
+                    durable_lsn: next_lsn - 1,
+                    syncing: false,
+                    failed: None,
+                    syncs: 0,
+                    size,
+                }),
+                durable: Condvar::new(),
+            }),
+        })
+    }
+
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.shared
+            .state
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// 日志文件
+    pub(crate) fn path(&self) -> &Path {
+        &self.shared.path
+    }
+
+    /// 检查点使用的快照文件
+    pub(crate) fn snapshot(&self) -> &Path {
+        &self.shared.snapshot
+    }
+
+    /// internal：为命令分配 LSN 并追加到内存缓冲区
+    ///
+    /// 返回值：最后一条记录的 LSN
+    pub(crate) fn append(&self, index: usize, commands: &[Vec<String>]) -> u64 {
+        let mut state = self.lock();
+        let timestamp = now_millis();
+        for command in commands {
+            let lsn = state.next_lsn;
+            let before = state.pending.len();
+            encode_record(&mut state.pending, lsn, timestamp, index, command);
+            state.size += (state.pending.len() - before) as u64;
+            state.next_lsn += 1;
+        }
+        state.next_lsn - 1
+    }
+
+    ///
+    /// 等待 lsn 及之前的记录写入磁盘, 多个线程同时提交时只需要一次 fsync
+    ///
+    /// 返回值：
+    ///     * 落盘成功返回 Ok
+    ///     * 写入文件失败（包括之前的提交失败）， 返回 Io
+    pub fn commit(&self, lsn: u64) -> Result<()> {
+        let mut state = self.lock();
+        loop {
+            if let Some(reason) = &state.failed {
+                return Err(DBError::Io(reason.clone()));
+            }
+            if state.durable_lsn >= lsn {
+                return Ok(());
+            }
+            if state.syncing {
+                state = self
+                    .shared
+                    .durable
+                    .wait(state)
+                    .unwrap_or_else(|poisoned| poisoned.into_inner());
+                continue;
+            }
+
+            // 成为 leader：在锁外写入期间追加的所有记录
+            state.syncing = true;
+            let batch = std::mem::take(&mut state.pending);
+            let target = state.next_lsn - 1;
+            let mut file = state.file.take();
+            drop(state);
+            let written = match &mut file {
+                Some(file) => file.append(&batch).and_then(|_| file.file().sync_data()),
+                None => Ok(()),
+            };
+            state = self.lock();
+            state.file = file;
+            state.syncing = false;
+            state.syncs += 1;
+            match written {
+                Ok(_) => state.durable_lsn = target,
+                Err(e) => state.failed = Some(io_error(&self.shared.path, e).to_string()),
+            }
+            self.shared.durable.notify_all();
+        }
+    }
+
+    /// 等待所有已经追加的记录写入磁盘, 参见 `commit()`
+    pub fn sync(&self) -> Result<()> {
+        let lsn = self.last_lsn();
+        self.commit(lsn)
+    }
+
+    /// 最后一条记录的 LSN, 没有记录时为 0
+    pub fn last_lsn(&self) -> u64 {
+        self.lock().next_lsn - 1
+    }
+
+    /// 已经写入磁盘的最后一条记录的 LSN
+    pub fn durable_lsn(&self) -> u64 {
+        self.lock().durable_lsn
+    }
+
+    /// 调用 fsync 的次数, 小于提交的次数说明组提交合并了多个提交
+    pub fn syncs(&self) -> u64 {
+        self.lock().syncs
+    }
+
+    /// 日志的字节数, 包括尚未写入文件的记录
+    pub fn size(&self) -> u64 {
+        self.lock().size
+    }
+
+    /// internal：日志使用的加密密钥, 未加密时返回 None
+    pub(crate) fn key(&self) -> Option<EncryptionKey> {
+        self.lock().key.clone()
+    }
+
+    /// internal：检查点之后清空日志, 之后的记录从 next_lsn 开始编号, 新的日志使用 key 加密
+    /// 调用者需要保证所有记录已经提交
+    pub(crate) fn reset(&self, next_lsn: u64, key: Option<&EncryptionKey>) -> Result<()> {
+        let path = &self.shared.path;
+        let mut state = self.lock();
+        while state.syncing {
+            state = self
+                .shared
+                .durable
+                .wait(state)
+                .unwrap_or_else(|poisoned| poisoned.into_inner());
+        }
+        let frames = create(path, key)?;
+        let file = LogFile::open(path, frames)?;
+        state.size = file.file().metadata().map_err(|e| io_error(path, e))?.len();
+        state.file = Some(file);
+        state.key = key.cloned();
+        state.pending.clear();
+        state.next_lsn = next_lsn;
+        state.durable_lsn = next_lsn - 1;
+        Ok(())
+    }
+}
+
+impl fmt::Debug for Wal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Wal")
+            .field("path", &self.shared.path)
+            .field("last_lsn", &self.last_lsn())
+            .field("durable_lsn", &self.durable_lsn())
+            .finish()
+    }
+}
+
+impl Drop for Shared {
+    fn drop(&mut self) {
+        let state = self
+            .state
+            .get_mut()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        if let Some(file) = &mut state.file {
+            if state.failed.is_none() && !state.pending.is_empty() {
+                let _ = file.append(&state.pending);
+                let _ = file.file().sync_data();
+            }
+        }
+    }
+}
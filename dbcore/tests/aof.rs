@@ -0,0 +1,217 @@
+use dbcore::{AofReplay, DBError, DBOk, Databases, FsyncPolicy, ValueType};
+use std::fs;
+use std::path::PathBuf;
+
+/// 每个测试使用独立的 AOF 文件
+fn aof_path(name: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("memkv-{}-{}.aof", name, std::process::id()));
+    let _ = fs::remove_file(&path);
+    path
+}
+
+/// 把所有数据库的内容描述为排好序的文本, 用于比较两个实例的数据是否相同
+fn describe(dbs: &Databases) -> Vec<String> {
+    let mut lines = Vec::new();
+    for (index, db) in dbs.iter() {
+        for key in db.keys("*") {
+            let mut content = match db.key_type(&key).unwrap() {
+                ValueType::String => vec![db.get(&key).unwrap().unwrap()],
+                ValueType::Set => db.smembers(&key).unwrap().unwrap().into_iter().collect(),
+                ValueType::Hash => db
+                    .hkeys(&key)
+                    .unwrap()
+                    .unwrap()
+                    .into_iter()
+                    .map(|f| format!("{}={}", f, db.hget(&key, &f).unwrap().unwrap()))
+                    .collect(),
+                ValueType::Custom(name) => vec![String::from(name)],
+            };
+            content.sort();
+            let volatile = db.ttl(&key) > 0;
+            lines.push(format!("{} {} {:?} {}", index, key, content, volatile));
+        }
+    }
+    lines.sort();
+    lines
+}
+
+fn s(value: &str) -> String {
+    String::from(value)
+}
+
+#[test]
+fn replay_reproduces_every_command() {
+    let path = aof_path("replay");
+    let mut dbs = Databases::new(4, None);
+    assert_eq!(
+        AofReplay {
+            commands: 0,
+            truncated: 0
+        },
+        dbs.open_aof(&path, FsyncPolicy::Always).unwrap()
+    );
+    assert!(dbs.aof_enabled());
+
+    let db = dbs.db_mut(0).unwrap();
+    assert_eq!(Ok(DBOk::Ok), db.sets(&s("string"), s("中文 value")));
+    assert_eq!(
+        Ok(DBOk::Ok),
+        db.set(&s("volatile"), s("v"), false, false, Some(100))
+    );
+    assert_eq!(
+        Ok(4),
+        db.sadd(&s("set"), vec![s("a"), s("b"), s("c"), s("d")])
+    );
+    assert!(db.spop(&s("set")).unwrap().is_some());
+    assert_eq!(1, db.srandmember(&s("set"), 1).unwrap().unwrap().len());
+    let _ = db.srem(&s("set"), vec![s("a"), s("b")]);
+    assert_eq!(Ok(1), db.hset(&s("hash"), s("f1"), s("v1")));
+    let pairs = vec![(s("f2"), s("v2")), (s("f3"), s("v3"))];
+    assert_eq!(Ok(DBOk::Ok), db.hmset(&s("hash"), pairs));
+    assert_eq!(Ok(Some(1)), db.hdel(&s("hash"), &s("f1")));
+    assert_eq!(true, db.expire(&s("hash"), 100).unwrap());
+    assert_eq!(true, db.persist(&s("hash")));
+    assert_eq!(Ok(DBOk::Ok), db.rename(&s("hash"), &s("renamed")));
+    assert_eq!(Ok(true), db.copy(&s("renamed"), &s("copied"), false));
+    let payload = db.dump(&s("renamed")).unwrap();
+    assert_eq!(
+        Ok(DBOk::Ok),
+        db.restore(&s("string"), Some(100_000), &payload, true)
+    );
+    assert_eq!(Ok(DBOk::Ok), db.sets(&s("gone"), s("v")));
+    assert_eq!(1, db.del(vec![s("gone")]));
+    assert_eq!(Ok(true), dbs.move_key(&s("copied"), 0, 2));
+    assert_eq!(Ok(DBOk::Ok), dbs.swapdb(2, 3));
+    let db = dbs.db_mut(1).unwrap();
+    assert_eq!(Ok(DBOk::Ok), db.sets(&s("flushed"), s("v")));
+    assert_eq!(Ok(DBOk::Ok), dbs.flushdb(1, false));
+    assert_eq!(Ok(DBOk::Ok), dbs.flush_aof());
+
+    let expected = describe(&dbs);
+    drop(dbs);
+    let mut replayed = Databases::new(4, None);
+    let replay = replayed.open_aof(&path, FsyncPolicy::No).unwrap();
+    assert!(replay.commands > 20);
+    assert_eq!(0, replay.truncated);
+    assert_eq!(expected, describe(&replayed));
+    assert_eq!(0, replayed.changes_since_save());
+    let _ = fs::remove_file(&path);
+}
+
+#[test]
+fn truncated_tail_is_dropped() {
+    let path = aof_path("truncated");
+    let mut dbs = Databases::new(1, None);
+    dbs.open_aof(&path, FsyncPolicy::No).unwrap();
+    for x in 0..5 {
+        let db = dbs.db_mut(0).unwrap();
+        assert_eq!(Ok(DBOk::Ok), db.sets(&format!("key{}", x), x.to_string()));
+        assert_eq!(Ok(DBOk::Ok), dbs.flush_aof());
+    }
+    drop(dbs);
+    let data = fs::read(&path).unwrap();
+
+    // 在任意字节处截断, 都只会丢失最后一条不完整的命令
+    let mut last_keys = 0;
+    for len in 0..=data.len() {
+        fs::write(&path, &data[..len]).unwrap();
+        let mut dbs = Databases::new(1, None);
+        let replay = dbs.open_aof(&path, FsyncPolicy::No).unwrap();
+        let keys = dbs.db(0).unwrap().size();
+        assert!(keys >= last_keys);
+        last_keys = keys;
+        let kept = fs::metadata(&path).unwrap().len() as usize;
+        assert_eq!(len, kept + replay.truncated);
+        assert_eq!(&data[..kept], &fs::read(&path).unwrap()[..]);
+    }
+    assert_eq!(5, last_keys);
+    let _ = fs::remove_file(&path);
+}
+
+#[test]
+fn corrupted_log_is_rejected() {
+    let path = aof_path("corrupted");
+    let mut dbs = Databases::new(1, None);
+    dbs.open_aof(&path, FsyncPolicy::No).unwrap();
+    assert_eq!(Ok(DBOk::Ok), dbs.db_mut(0).unwrap().sets(&s("a"), s("1")));
+    assert_eq!(Ok(DBOk::Ok), dbs.db_mut(0).unwrap().sets(&s("b"), s("2")));
+    assert_eq!(Ok(DBOk::Ok), dbs.flush_aof());
+    drop(dbs);
+    let data = fs::read(&path).unwrap();
+
+    let mut corrupted = data.clone();
+    let second = data.len() / 2
+        + data[data.len() / 2..]
+            .iter()
+            .position(|b| *b == b'*')
+            .unwrap();
+    corrupted[second] = b'x';
+    fs::write(&path, &corrupted).unwrap();
+    let mut dbs = Databases::new(1, None);
+    assert!(matches!(
+        dbs.open_aof(&path, FsyncPolicy::No),
+        Err(DBError::InvalidPayload(_))
+    ));
+    assert!(!dbs.aof_enabled());
+
+    // 参数长度超出上限
+    fs::write(&path, b"*2\r\n$18446744073709551615\r\nxx\r\n").unwrap();
+    assert!(matches!(
+        dbs.open_aof(&path, FsyncPolicy::No),
+        Err(DBError::InvalidPayload(_))
+    ));
+
+    fs::write(&path, b"*2\r\n$7\r\nunknown\r\n$1\r\na\r\n").unwrap();
+    assert_eq!(
+        Err(DBError::InvalidPayload(s("unknown command `unknown`"))),
+        dbs.open_aof(&path, FsyncPolicy::No)
+    );
+    let _ = fs::remove_file(&path);
+}
+
+#[test]
+fn rewrite_compacts_log() {
+    let path = aof_path("rewrite");
+    let mut dbs = Databases::new(2, None);
+    assert!(matches!(dbs.bgrewriteaof(), Err(DBError::NotSupported(_))));
+    dbs.open_aof(&path, FsyncPolicy::EverySec).unwrap();
+    let db = dbs.db_mut(1).unwrap();
+    for x in 0..200 {
+        assert_eq!(Ok(DBOk::Ok), db.sets(&s("counter"), x.to_string()));
+    }
+    let members: Vec<String> = (0..100).map(|x| x.to_string()).collect();
+    assert_eq!(Ok(100), db.sadd(&s("set"), members));
+    assert_eq!(true, db.expire(&s("set"), 100).unwrap());
+    assert_eq!(Ok(DBOk::Ok), dbs.flush_aof());
+    let before = fs::metadata(&path).unwrap().len();
+
+    assert_eq!(Ok(DBOk::Ok), dbs.bgrewriteaof());
+    // 重写期间的修改会追加到新文件
+    let db = dbs.db_mut(0).unwrap();
+    assert_eq!(Ok(DBOk::Ok), db.sets(&s("during"), s("v")));
+    assert_eq!(Some(Ok(DBOk::Ok)), dbs.wait_aof_rewrite());
+    assert!(fs::metadata(&path).unwrap().len() < before);
+    let db = dbs.db_mut(0).unwrap();
+    assert_eq!(Ok(DBOk::Ok), db.sets(&s("after"), s("v")));
+    assert_eq!(Ok(DBOk::Ok), dbs.flush_aof());
+    assert!(!dbs.info().persistence.aof_rewrite_in_progress);
+
+    let expected = describe(&dbs);
+    drop(dbs);
+    let mut replayed = Databases::new(2, None);
+    replayed.open_aof(&path, FsyncPolicy::No).unwrap();
+    assert_eq!(expected, describe(&replayed));
+    let _ = fs::remove_file(&path);
+}
+
+#[test]
+fn fsync_policy_names() {
+    for policy in [FsyncPolicy::Always, FsyncPolicy::EverySec, FsyncPolicy::No].iter() {
+        assert_eq!(Some(*policy), FsyncPolicy::from_name(policy.name()));
+    }
+    assert_eq!(
+        Some(FsyncPolicy::EverySec),
+        FsyncPolicy::from_name("EVERYSEC")
+    );
+    assert_eq!(None, FsyncPolicy::from_name("sometimes"));
+}
@@ -0,0 +1,448 @@
+use dbcore::{
+    register_type, Command, CommandFlags, CommandReply, CustomValue, DBError, DBOk, Databases,
+    EventClass, ExportFormat, FsyncPolicy, KeySpec, Result, ValueType, KVDB,
+};
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+fn s(value: &str) -> String {
+    String::from(value)
+}
+
+fn args(args: &[&str]) -> Vec<String> {
+    args.iter().map(|arg| s(arg)).collect()
+}
+
+/// 每个测试使用独立的文件
+fn temp_path(name: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("memkv-{}-{}", name, std::process::id()));
+    let _ = fs::remove_file(&path);
+    path
+}
+
+/// STRLEN key：字符串的长度, key 不存在时为 0
+struct StrLen;
+
+impl Command for StrLen {
+    fn name(&self) -> &str {
+        "STRLEN"
+    }
+
+    fn arity(&self) -> i32 {
+        2
+    }
+
+    fn key_spec(&self) -> KeySpec {
+        KeySpec::FIRST
+    }
+
+    fn execute(&self, db: &mut KVDB, args: &[String]) -> Result<CommandReply> {
+        let len = db.get(&args[1])?.map_or(0, |value| value.chars().count());
+        Ok(CommandReply::Integer(len as i64))
+    }
+}
+
+/// MSETNX key value [key value ...]：依次写入, 遇到已经存在的 key 时出错
+struct MSetNx;
+
+impl Command for MSetNx {
+    fn name(&self) -> &str {
+        "msetnx"
+    }
+
+    fn arity(&self) -> i32 {
+        -3
+    }
+
+    fn flags(&self) -> CommandFlags {
+        CommandFlags::WRITE
+    }
+
+    fn key_spec(&self) -> KeySpec {
+        KeySpec {
+            first: 1,
+            last: -2,
+            step: 2,
+        }
+    }
+
+    fn execute(&self, db: &mut KVDB, args: &[String]) -> Result<CommandReply> {
+        if args.len().is_multiple_of(2) {
+            return Err(DBError::WrongArity(s("msetnx")));
+        }
+        for pair in args[1..].chunks(2) {
+            if db.exists(&pair[0]) {
+                return Err(DBError::KeyAlreadyExists(pair[0].clone()));
+            }
+            db.sets(&pair[0], pair[1].clone())?;
+        }
+        Ok(CommandReply::from(DBOk::Ok))
+    }
+}
+
+/// 追加写入的日志, 编码为以换行分隔的文本
+#[derive(Debug, Clone, PartialEq)]
+struct Log(Vec<String>);
+
+impl CustomValue for Log {
+    const TYPE_NAME: &'static str = "log";
+
+    fn encode(&self) -> Vec<u8> {
+        self.0.join("\n").into_bytes()
+    }
+
+    fn decode(data: &[u8]) -> Result<Self> {
+        let text = std::str::from_utf8(data)
+            .map_err(|_| DBError::InvalidPayload(s("log is not valid UTF-8")))?;
+        Ok(Log(text.split('\n').map(String::from).collect()))
+    }
+}
+
+/// LOGAPPEND key entry：追加一条日志, 返回日志的条数
+struct LogAppend;
+
+impl Command for LogAppend {
+    fn name(&self) -> &str {
+        "logappend"
+    }
+
+    fn arity(&self) -> i32 {
+        3
+    }
+
+    fn flags(&self) -> CommandFlags {
+        CommandFlags::WRITE
+    }
+
+    fn key_spec(&self) -> KeySpec {
+        KeySpec::FIRST
+    }
+
+    fn execute(&self, db: &mut KVDB, args: &[String]) -> Result<CommandReply> {
+        let entry = args[2].clone();
+        let appended = db.update_custom(&args[1], |log: &mut Log| {
+            log.0.push(entry.clone());
+            log.0.len()
+        })?;
+        let len = match appended {
+            Some(len) => len,
+            None => {
+                db.set_custom(&args[1], Log(vec![entry]))?;
+                1
+            }
+        };
+        Ok(CommandReply::Integer(len as i64))
+    }
+}
+
+#[test]
+fn registered_commands_are_called_by_name() {
+    let mut db = KVDB::default();
+    assert_eq!(Ok(()), db.register_command(Arc::new(StrLen)));
+    assert_eq!(Ok(()), db.register_command(Arc::new(MSetNx)));
+    assert_eq!(vec![s("msetnx"), s("strlen")], db.commands().names());
+    assert_eq!(
+        Err(DBError::AlreadyRegistered(s("strlen"))),
+        db.register_command(Arc::new(StrLen))
+    );
+
+    db.sets(&s("name"), s("中文")).unwrap();
+    assert_eq!(
+        Ok(CommandReply::Integer(2)),
+        db.call(&args(&["strlen", "name"]))
+    );
+    assert_eq!(
+        Ok(CommandReply::Integer(0)),
+        db.call(&args(&["StrLen", "missing"]))
+    );
+    assert_eq!(
+        Err(DBError::WrongArity(s("strlen"))),
+        db.call(&args(&["strlen", "a", "b"]))
+    );
+    assert_eq!(
+        Err(DBError::UnknownCommand(s("nosuch"))),
+        db.call(&args(&["NOSUCH", "a"]))
+    );
+    assert!(db.call(&args(&["sadd", "a"])).is_err());
+
+    // key 的位置：除了最后一个参数之外每隔一个参数
+    let spec = MSetNx.key_spec();
+    assert_eq!(
+        vec!["a", "b"],
+        spec.keys(&args(&["msetnx", "a", "1", "b", "2"]))
+    );
+    assert!(spec.keys(&args(&["msetnx"])).is_empty());
+    assert_eq!(vec!["k"], KeySpec::FIRST.keys(&args(&["strlen", "k"])));
+    assert!(KeySpec::NONE.keys(&args(&["strlen", "k"])).is_empty());
+}
+
+#[test]
+fn write_commands_are_rolled_back_on_error() {
+    let mut db = KVDB::default();
+    db.register_command(Arc::new(MSetNx)).unwrap();
+    db.sets(&s("b"), s("old")).unwrap();
+
+    assert_eq!(
+        Err(DBError::KeyAlreadyExists(s("b"))),
+        db.call(&args(&["msetnx", "a", "1", "b", "2"]))
+    );
+    assert!(!db.exists(&s("a")));
+    assert_eq!(Ok(Some(s("old"))), db.get(&s("b")));
+
+    assert_eq!(
+        Ok(CommandReply::Status(s("OK"))),
+        db.call(&args(&["msetnx", "a", "1", "c", "3"]))
+    );
+    assert_eq!(Ok(Some(s("3"))), db.get(&s("c")));
+}
+
+#[test]
+fn invalid_commands_are_rejected() {
+    /// 名称或 key 的位置不合法的命令
+    struct Invalid(&'static str, i32, KeySpec);
+
+    impl Command for Invalid {
+        fn name(&self) -> &str {
+            self.0
+        }
+
+        fn arity(&self) -> i32 {
+            self.1
+        }
+
+        fn key_spec(&self) -> KeySpec {
+            self.2
+        }
+
+        fn execute(&self, _db: &mut KVDB, _args: &[String]) -> Result<CommandReply> {
+            Ok(CommandReply::Nil)
+        }
+    }
+
+    let db = KVDB::default();
+    let spec = KeySpec {
+        first: 1,
+        last: 1,
+        step: 0,
+    };
+    for command in [
+        Invalid("", 1, KeySpec::NONE),
+        Invalid("two words", 1, KeySpec::NONE),
+        Invalid("zero", 0, KeySpec::NONE),
+        Invalid("nostep", 2, spec),
+    ] {
+        assert!(matches!(
+            db.commands().register(Arc::new(command)),
+            Err(DBError::Syntax(_))
+        ));
+    }
+    assert!(db.commands().is_empty());
+}
+
+#[test]
+fn databases_share_the_registry() {
+    let mut dbs = Databases::new(2, None);
+    dbs.register_command(Arc::new(StrLen)).unwrap();
+    // 复制的注册表与原来的共享同样的命令
+    dbs.commands().clone().register(Arc::new(MSetNx)).unwrap();
+    assert_eq!(2, dbs.commands().len());
+
+    dbs.db_mut(1).unwrap().sets(&s("name"), s("memkv")).unwrap();
+    assert_eq!(
+        Ok(CommandReply::Integer(5)),
+        dbs.db_mut(1).unwrap().call(&args(&["strlen", "name"]))
+    );
+    dbs.swapdb(0, 1).unwrap();
+    assert!(dbs.db(1).unwrap().commands().contains("MSETNX"));
+    assert_eq!(
+        Ok(CommandReply::Integer(5)),
+        dbs.db_mut(0).unwrap().call(&args(&["strlen", "name"]))
+    );
+    // 单独创建的 KVDB 有自己的注册表
+    assert!(KVDB::default().commands().is_empty());
+}
+
+#[test]
+fn custom_values_are_typed() {
+    register_type::<Log>().unwrap();
+    // 同一个类型可以重复注册, 其他类型不能使用同样的名称
+    register_type::<Log>().unwrap();
+
+    #[derive(Debug, Clone)]
+    struct Other;
+
+    impl CustomValue for Other {
+        const TYPE_NAME: &'static str = "log";
+
+        fn encode(&self) -> Vec<u8> {
+            Vec::new()
+        }
+
+        fn decode(_data: &[u8]) -> Result<Self> {
+            Ok(Other)
+        }
+    }
+
+    #[derive(Debug, Clone)]
+    struct Hash;
+
+    impl CustomValue for Hash {
+        const TYPE_NAME: &'static str = "hash";
+
+        fn encode(&self) -> Vec<u8> {
+            Vec::new()
+        }
+
+        fn decode(_data: &[u8]) -> Result<Self> {
+            Ok(Hash)
+        }
+    }
+
+    assert_eq!(
+        Err(DBError::AlreadyRegistered(s("log"))),
+        register_type::<Other>()
+    );
+    assert_eq!(
+        Err(DBError::AlreadyRegistered(s("hash"))),
+        register_type::<Hash>()
+    );
+
+    let mut db = KVDB::default();
+    let key = s("log");
+    assert!(matches!(
+        db.set_custom(&key, Other),
+        Err(DBError::NotSupported(_))
+    ));
+    assert_eq!(Ok(DBOk::Ok), db.set_custom(&key, Log(vec![s("a")])));
+    assert_eq!(Ok(Some(&Log(vec![s("a")]))), db.get_custom::<Log>(&key));
+    assert_eq!(Some(ValueType::Custom("log")), db.key_type(&key));
+    assert_eq!(Some(ValueType::Custom("log")), ValueType::from_name("LOG"));
+    assert_eq!("log", ValueType::Custom("log").name());
+    assert_eq!(
+        Err(DBError::WrongValueType {
+            key: key.clone(),
+            expected: ValueType::String,
+            actual: ValueType::Custom("log"),
+        }),
+        db.get(&key)
+    );
+
+    db.sets(&s("name"), s("memkv")).unwrap();
+    assert_eq!(
+        Err(DBError::WrongValueType {
+            key: s("name"),
+            expected: ValueType::Custom("log"),
+            actual: ValueType::String,
+        }),
+        db.get_custom::<Log>(&s("name"))
+    );
+
+    // 原处修改保留生存时间, set_custom 清除生存时间
+    assert!(db.expire(&key, 100).unwrap());
+    assert_eq!(
+        Ok(Some(2)),
+        db.update_custom(&key, |log: &mut Log| {
+            log.0.push(s("b"));
+            log.0.len()
+        })
+    );
+    assert!(db.ttl(&key) > 0);
+    assert_eq!(
+        Ok(None),
+        db.update_custom(&s("missing"), |log: &mut Log| log.0.len())
+    );
+    assert_eq!(Ok(DBOk::Ok), db.set_custom(&key, Log(vec![s("c")])));
+    assert_eq!(-1, db.ttl(&key));
+    assert_eq!(1, db.keyspace_info().customs);
+
+    // 与类型无关的操作
+    let dump = db.dump(&key).unwrap();
+    assert_eq!(Ok(DBOk::Ok), db.restore(&s("copy"), None, &dump, false));
+    assert_eq!(
+        Ok(Some(&Log(vec![s("c")]))),
+        db.get_custom::<Log>(&s("copy"))
+    );
+    assert_eq!(Ok(DBOk::Ok), db.rename(&s("copy"), &s("renamed")));
+    assert_eq!(
+        Ok(Some(&Log(vec![s("c")]))),
+        db.get_custom::<Log>(&s("renamed"))
+    );
+}
+
+#[test]
+fn custom_values_are_rolled_back_and_notified() {
+    register_type::<Log>().unwrap();
+    let mut dbs = Databases::new(1, None);
+    dbs.register_command(Arc::new(LogAppend)).unwrap();
+    dbs.set_notify_keyspace_events(EventClass::parse("Kd").unwrap());
+    let subscriber = dbs.pubsub().subscriber();
+    subscriber.subscribe("__keyspace@0__:events");
+
+    let db = dbs.db_mut(0).unwrap();
+    let key = s("events");
+    assert_eq!(
+        Ok(CommandReply::Integer(1)),
+        db.call(&args(&["logappend", "events", "a"]))
+    );
+    assert_eq!("log", subscriber.recv().unwrap().payload);
+    let res: Result<()> = db.transaction(|db| {
+        db.call(&args(&["logappend", "events", "b"]))?;
+        Err(DBError::Syntax(s("abort")))
+    });
+    assert!(res.is_err());
+    assert_eq!(Ok(Some(&Log(vec![s("a")]))), db.get_custom::<Log>(&key));
+}
+
+#[test]
+fn custom_values_are_persisted() {
+    register_type::<Log>().unwrap();
+    let aof = temp_path("custom.aof");
+    let snapshot = temp_path("custom.mkv");
+    let mut dbs = Databases::new(2, None);
+    dbs.register_command(Arc::new(LogAppend)).unwrap();
+    dbs.open_aof(&aof, FsyncPolicy::Always).unwrap();
+
+    let db = dbs.db_mut(1).unwrap();
+    db.call(&args(&["logappend", "events", "中文"])).unwrap();
+    db.call(&args(&["logappend", "events", "b"])).unwrap();
+    assert!(db.expire(&s("events"), 100).unwrap());
+    db.call(&args(&["logappend", "events", "c"])).unwrap();
+    dbs.flush_aof().unwrap();
+    dbs.save(&snapshot).unwrap();
+    let expected = Log(vec![s("中文"), s("b"), s("c")]);
+    let check = |dbs: &Databases| {
+        let db = dbs.db(1).unwrap();
+        assert_eq!(Ok(Some(&expected)), db.get_custom::<Log>(&s("events")));
+        assert!(db.ttl(&s("events")) > 0);
+    };
+
+    let mut replayed = Databases::new(2, None);
+    replayed.open_aof(&aof, FsyncPolicy::No).unwrap();
+    check(&replayed);
+
+    let mut loaded = Databases::new(2, None);
+    assert_eq!(Ok(1), loaded.load(&snapshot));
+    check(&loaded);
+
+    for format in [ExportFormat::JsonLines, ExportFormat::Csv] {
+        let mut out = Vec::new();
+        dbs.export(&mut out, format, None, None).unwrap();
+        let text = String::from_utf8(out).unwrap();
+        assert!(text.contains("log"), "{}", text);
+        let mut imported = Databases::new(2, None);
+        assert_eq!(
+            Ok(1),
+            imported.import(
+                &mut text.as_bytes(),
+                format,
+                None,
+                Some(ValueType::Custom("log"))
+            )
+        );
+        check(&imported);
+    }
+
+    let _ = fs::remove_file(&aof);
+    let _ = fs::remove_file(&snapshot);
+}
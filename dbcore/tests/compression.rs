@@ -0,0 +1,206 @@
+use dbcore::{DBOk, Databases, ExportFormat, FsyncPolicy, KVDB};
+use std::fs;
+use std::path::PathBuf;
+
+/// 每个测试使用独立的文件
+fn path(name: &str) -> PathBuf {
+    let path =
+        std::env::temp_dir().join(format!("memkv-compression-{}-{}", name, std::process::id()));
+    let _ = fs::remove_file(&path);
+    path
+}
+
+fn s(value: &str) -> String {
+    String::from(value)
+}
+
+/// 重复度很高、容易压缩的字符串
+fn compressible(len: usize) -> String {
+    "memkv 压缩测试 ".chars().cycle().take(len).collect()
+}
+
+/// 伪随机的、几乎无法压缩的字符串
+fn incompressible(len: usize) -> String {
+    let mut seed: u32 = 0x2545_f491;
+    (0..len)
+        .map(|_| {
+            seed ^= seed << 13;
+            seed ^= seed >> 17;
+            seed ^= seed << 5;
+            (b'!' + (seed % 94) as u8) as char
+        })
+        .collect()
+}
+
+fn compressed_db(threshold: usize) -> KVDB {
+    let mut db = KVDB::new(None);
+    db.set_compression_threshold(Some(threshold));
+    db
+}
+
+#[test]
+fn compression_is_transparent_to_get_and_set() {
+    let mut db = compressed_db(64);
+    assert_eq!(Some(64), db.compression_threshold());
+    let long = compressible(4096);
+    let noise = incompressible(4096);
+    assert_eq!(Ok(DBOk::Ok), db.sets(&s("long"), long.clone()));
+    assert_eq!(Ok(DBOk::Ok), db.sets(&s("noise"), noise.clone()));
+    assert_eq!(Ok(DBOk::Ok), db.sets(&s("short"), s("short value")));
+    assert_eq!(Ok(Some(long.clone())), db.get(&s("long")));
+    assert_eq!(Ok(Some(noise.clone())), db.get(&s("noise")));
+    assert_eq!(Ok(Some(s("short value"))), db.get(&s("short")));
+
+    let info = db.keyspace_info();
+    assert_eq!(3, info.strings);
+    // 只有超过阈值并且压缩后变小的字符串被压缩
+    assert_eq!(1, info.compressed_strings);
+    assert!(info.compression_saved_bytes > 3000);
+
+    // 覆盖、改名、复制后仍然可以正常读取
+    assert_eq!(
+        Ok(DBOk::Ok),
+        db.set(&s("long"), s("now short"), false, false, None)
+    );
+    assert_eq!(0, db.keyspace_info().compressed_strings);
+    assert_eq!(Ok(DBOk::Ok), db.sets(&s("long"), long.clone()));
+    assert_eq!(Ok(DBOk::Ok), db.rename(&s("long"), &s("renamed")));
+    assert_eq!(Ok(true), db.copy(&s("renamed"), &s("copied"), false));
+    assert_eq!(Ok(Some(long.clone())), db.get(&s("renamed")));
+    assert_eq!(Ok(Some(long)), db.get(&s("copied")));
+    assert_eq!(2, db.keyspace_info().compressed_strings);
+
+    // 关闭压缩后已经压缩的 value 不受影响, 新写入的字符串不再压缩
+    db.set_compression_threshold(None);
+    assert_eq!(Ok(DBOk::Ok), db.sets(&s("plain"), compressible(4096)));
+    assert_eq!(2, db.keyspace_info().compressed_strings);
+    assert_eq!(Ok(Some(compressible(4096))), db.get(&s("plain")));
+}
+
+#[test]
+fn memory_usage_counts_compressed_size() {
+    let mut plain = KVDB::new(None);
+    let mut compressed = compressed_db(128);
+    for i in 0..20 {
+        let key = format!("key:{}", i);
+        assert_eq!(Ok(DBOk::Ok), plain.sets(&key, compressible(8192)));
+        assert_eq!(Ok(DBOk::Ok), compressed.sets(&key, compressible(8192)));
+    }
+    let plain = plain.keyspace_info();
+    let compressed = compressed.keyspace_info();
+    assert_eq!(0, plain.compressed_strings);
+    assert_eq!(0, plain.compression_saved_bytes);
+    assert_eq!(20, compressed.compressed_strings);
+    assert!(compressed.used_memory * 4 < plain.used_memory);
+    assert!(compressed.used_memory + compressed.compression_saved_bytes >= plain.used_memory / 2);
+
+    let mut dbs = Databases::new(2, None);
+    dbs.set_compression_threshold(Some(16));
+    assert_eq!(
+        Ok(DBOk::Ok),
+        dbs.db_mut(1).unwrap().sets(&s("k"), compressible(1024))
+    );
+    let info = dbs.info();
+    assert_eq!(1, info.total().compressed_strings);
+    let rendered = info.render(Some("memory"));
+    assert!(
+        rendered.contains("compressed_strings:1\r\n"),
+        "{}",
+        rendered
+    );
+    assert!(
+        rendered.contains("compression_saved_bytes:"),
+        "{}",
+        rendered
+    );
+}
+
+#[test]
+fn snapshots_and_dump_keep_values_compressed() {
+    let file = path("snapshot.db");
+    let long = compressible(10000);
+    let mut dbs = Databases::new(2, None);
+    dbs.set_compression_threshold(Some(100));
+    let db = dbs.db_mut(1).unwrap();
+    assert_eq!(Ok(DBOk::Ok), db.sets(&s("long"), long.clone()));
+    assert_eq!(
+        Ok(DBOk::Ok),
+        db.set(&s("volatile"), long.clone(), false, false, Some(100))
+    );
+    assert_eq!(Ok(DBOk::Ok), dbs.save(&file));
+    // 快照保存的是压缩后的数据
+    assert!(fs::metadata(&file).unwrap().len() < 2000);
+
+    // 加载时不需要开启压缩, 压缩的 value 原样加载
+    let mut loaded = Databases::new(2, None);
+    assert_eq!(Ok(2), loaded.load(&file));
+    let db = loaded.db(1).unwrap();
+    assert_eq!(Ok(Some(long.clone())), db.get(&s("long")));
+    assert_eq!(Ok(Some(long.clone())), db.get(&s("volatile")));
+    assert!(db.ttl(&s("volatile")) > 0);
+    assert_eq!(2, db.keyspace_info().compressed_strings);
+
+    // 没有压缩的快照在开启压缩后加载, 字符串按照阈值压缩
+    let mut plain = Databases::new(1, None);
+    assert_eq!(
+        Ok(DBOk::Ok),
+        plain.db_mut(0).unwrap().sets(&s("k"), long.clone())
+    );
+    assert_eq!(Ok(DBOk::Ok), plain.save(&file));
+    assert!(fs::metadata(&file).unwrap().len() > 10000);
+    let mut loaded = Databases::new(1, None);
+    loaded.set_compression_threshold(Some(100));
+    assert_eq!(Ok(1), loaded.load(&file));
+    assert_eq!(1, loaded.db(0).unwrap().keyspace_info().compressed_strings);
+    let _ = fs::remove_file(&file);
+
+    // DUMP 的结果同样是压缩的, 可以还原到没有开启压缩的数据库
+    let payload = dbs.db(1).unwrap().dump(&s("long")).unwrap();
+    assert!(payload.len() < 2000);
+    let mut db = KVDB::new(None);
+    assert_eq!(
+        Ok(DBOk::Ok),
+        db.restore(&s("restored"), None, &payload, false)
+    );
+    assert_eq!(Ok(Some(long)), db.get(&s("restored")));
+    assert_eq!(1, db.keyspace_info().compressed_strings);
+
+    // 伪造的压缩数据不能还原
+    let mut forged = payload.clone();
+    let middle = forged.len() / 2;
+    forged[middle] ^= 0xff;
+    assert!(db.restore(&s("forged"), None, &forged, false).is_err());
+    assert!(!db.exists(&s("forged")));
+}
+
+#[test]
+fn logs_and_exports_contain_original_strings() {
+    let file = path("replay.aof");
+    let long = compressible(5000);
+    let mut dbs = Databases::new(1, None);
+    dbs.set_compression_threshold(Some(100));
+    dbs.open_aof(&file, FsyncPolicy::Always).unwrap();
+    assert_eq!(
+        Ok(DBOk::Ok),
+        dbs.db_mut(0).unwrap().sets(&s("long"), long.clone())
+    );
+    assert_eq!(Ok(DBOk::Ok), dbs.flush_aof());
+
+    let mut out = Vec::new();
+    dbs.export(&mut out, ExportFormat::JsonLines, None, None)
+        .unwrap();
+    assert!(String::from_utf8(out).unwrap().contains(&long));
+    drop(dbs);
+
+    // AOF 记录的是原始字符串, 重放时按照当前的阈值重新压缩
+    let data = fs::read_to_string(&file).unwrap();
+    assert!(data.contains(&long));
+    let mut replayed = Databases::new(1, None);
+    replayed.set_compression_threshold(Some(100));
+    replayed.open_aof(&file, FsyncPolicy::Always).unwrap();
+    let db = replayed.db(0).unwrap();
+    assert_eq!(Ok(Some(long)), db.get(&s("long")));
+    assert_eq!(1, db.keyspace_info().compressed_strings);
+    drop(replayed);
+    let _ = fs::remove_file(&file);
+}
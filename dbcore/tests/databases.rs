@@ -0,0 +1,102 @@
+use dbcore::{DBError, DBOk, Databases, DEFAULT_DATABASES};
+use std::time::Duration;
+
+#[test]
+fn databases_are_isolated() {
+    let mut dbs = Databases::new(DEFAULT_DATABASES, None);
+    assert_eq!(16, dbs.len());
+    let key = String::from("key");
+
+    assert_eq!(
+        Ok(DBOk::Ok),
+        dbs.db_mut(0).unwrap().sets(&key, String::from("zero"))
+    );
+    assert_eq!(
+        Ok(DBOk::Ok),
+        dbs.db_mut(1).unwrap().sets(&key, String::from("one"))
+    );
+    assert_eq!(Ok(Some(String::from("zero"))), dbs.db(0).unwrap().get(&key));
+    assert_eq!(Ok(Some(String::from("one"))), dbs.db(1).unwrap().get(&key));
+    assert_eq!(false, dbs.db(2).unwrap().exists(&key));
+
+    assert_eq!(Err(DBError::DBIndexOutOfRange(16)), dbs.db(16).map(|_| ()));
+    assert_eq!(Err(DBError::DBIndexOutOfRange(16)), dbs.swapdb(0, 16));
+}
+
+#[test]
+fn per_database_key_sizes() {
+    let mut dbs = Databases::with_key_sizes(vec![Some(1), None]);
+    let a = String::from("a");
+    let b = String::from("b");
+    let db = dbs.db_mut(0).unwrap();
+    assert_eq!(Ok(DBOk::Ok), db.sets(&a, String::from("1")));
+    assert_eq!(
+        Err(DBError::OutOfKeysSize(1)),
+        db.sets(&b, String::from("2"))
+    );
+    let db = dbs.db_mut(1).unwrap();
+    assert_eq!(Ok(DBOk::Ok), db.sets(&a, String::from("1")));
+    assert_eq!(Ok(DBOk::Ok), db.sets(&b, String::from("2")));
+
+    // 交换数据后, key 数量上限仍属于原来的编号
+    assert_eq!(Ok(DBOk::Ok), dbs.swapdb(0, 1));
+    assert_eq!(Some(1), dbs.db(0).unwrap().max_keys());
+    assert_eq!(2, dbs.db(0).unwrap().size());
+    assert_eq!(None, dbs.db(1).unwrap().max_keys());
+    assert_eq!(1, dbs.db(1).unwrap().size());
+}
+
+#[test]
+fn move_key_between_databases() {
+    let mut dbs = Databases::new(3, None);
+    let key = String::from("key");
+    assert_eq!(
+        Ok(DBOk::Ok),
+        dbs.db_mut(2).unwrap().sets(&key, String::from("v"))
+    );
+
+    assert_eq!(Ok(false), dbs.move_key(&key, 2, 2));
+    assert_eq!(Ok(true), dbs.move_key(&key, 2, 0));
+    assert_eq!(false, dbs.db(2).unwrap().exists(&key));
+    assert_eq!(Ok(Some(String::from("v"))), dbs.db(0).unwrap().get(&key));
+    assert_eq!(Ok(true), dbs.move_key(&key, 0, 1));
+    assert_eq!(Ok(false), dbs.move_key(&key, 0, 1));
+    assert_eq!(Err(DBError::DBIndexOutOfRange(3)), dbs.move_key(&key, 1, 3));
+}
+
+#[test]
+fn flushdb_and_flushall() {
+    let mut dbs = Databases::new(3, None);
+    for index in 0..3 {
+        let db = dbs.db_mut(index).unwrap();
+        for x in 0..100 {
+            assert_eq!(Ok(DBOk::Ok), db.sets(&x.to_string(), x.to_string()));
+        }
+    }
+
+    assert_eq!(Ok(DBOk::Ok), dbs.flushdb(0, false));
+    assert_eq!(Ok(DBOk::Ok), dbs.flushdb(1, true));
+    assert_eq!(0, dbs.db(0).unwrap().size());
+    assert_eq!(0, dbs.db(1).unwrap().size());
+    assert_eq!(100, dbs.db(2).unwrap().size());
+    assert_eq!(Err(DBError::DBIndexOutOfRange(3)), dbs.flushdb(3, false));
+
+    dbs.flushall(true);
+    assert!(dbs.iter().all(|(_, db)| db.size() == 0));
+}
+
+#[test]
+fn active_expire_visits_every_database() {
+    let mut dbs = Databases::new(3, None);
+    for index in 0..3 {
+        let db = dbs.db_mut(index).unwrap();
+        for x in 0..50 {
+            let key = x.to_string();
+            assert_eq!(Ok(DBOk::Ok), db.sets(&key, x.to_string()));
+            assert!(db.pexpire_at(&key, 1));
+        }
+    }
+
+    assert_eq!(150, dbs.active_expire(Duration::from_secs(10)));
+    assert!(dbs.iter().all(|(_, db)| db.keyspace_info().keys == 0));
+}
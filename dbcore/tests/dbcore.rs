@@ -19,7 +19,7 @@ fn keys_size() {
     let key2 = String::from("key2");
     let value2 = String::from("value2");
     assert_eq!(
-        Err(DBError::OutOfKeysSize),
+        Err(DBError::OutOfKeysSize(1)),
         db.set(&key2, value2, false, false, None)
     );
 
@@ -106,7 +106,7 @@ fn set_add_one() {
     let other_key: String = String::from("other_key");
     let other_members: Vec<String> = vec![String::from("a"), String::from("d")];
     assert_eq!(
-        Err(DBError::OutOfKeysSize),
+        Err(DBError::OutOfKeysSize(1)),
         db.sadd(&other_key, other_members.clone())
     );
 
@@ -133,6 +133,35 @@ fn set_randommember() {
     assert_eq!(Ok(Some(set.len() - get_count)), db.slen(&key));
 }
 
+#[test]
+fn set_random_members() {
+    let key: String = String::from("key");
+    let members: Vec<String> = (0..10).map(|i| i.to_string()).collect();
+    let db: KVDB = common::setup_common_one_key_set(&key, &members);
+    let set: HashSet<String> = HashSet::from_iter(members);
+
+    // count 为正数时元素不重复, 并且不会移除元素
+    let res = db.random_members(&key, 3).unwrap().unwrap();
+    assert_eq!(3, HashSet::<&String>::from_iter(res.iter()).len());
+    assert!(res.iter().all(|m| set.contains(m)));
+    assert_eq!(10, db.random_members(&key, 20).unwrap().unwrap().len());
+    assert_eq!(Ok(Some(10)), db.slen(&key));
+
+    // count 为负数时元素可能重复
+    let res = db.random_members(&key, -20).unwrap().unwrap();
+    assert_eq!(20, res.len());
+    assert!(res.iter().all(|m| set.contains(m)));
+
+    // 多次抽取的结果不总是相同
+    let seen: HashSet<String> = (0..100)
+        .flat_map(|_| db.random_members(&key, 1).unwrap().unwrap())
+        .collect();
+    assert!(seen.len() > 1);
+
+    assert_eq!(Ok(None), db.random_members(&String::from("missing"), 1));
+    assert_eq!(Ok(Some(Vec::new())), db.random_members(&key, 0));
+}
+
 #[test]
 #[ignore]
 fn set_add_and_pop() {
@@ -142,7 +171,7 @@ fn set_add_and_pop() {
     let mut db: KVDB = common::setup_common_one_key_set(&key, &members);
 
     let res = db.spop(&key);
-    assert_ne!(Err(DBError::WrongValueType), res);
+    assert!(res.is_ok());
     if let Ok(Some(s)) = res {
         assert_eq!(true, members.contains(&s));
     }
@@ -240,7 +269,7 @@ fn hash_add_out_of_keys_size() {
 
     let other_key: String = String::from("other_key");
     let res = db.hset(&other_key, String::from("field"), String::from("values"));
-    assert_eq!(Err(DBError::OutOfKeysSize), res);
+    assert_eq!(Err(DBError::OutOfKeysSize(1)), res);
 }
 
 #[test]
@@ -0,0 +1,386 @@
+use dbcore::{DBError, DBOk, Databases, EncryptionKey, FsyncPolicy, History, RestorePoint};
+use std::fs;
+use std::path::PathBuf;
+
+/// 每个测试使用独立的文件, name 为文件名后缀
+fn path(test: &str, name: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!(
+        "memkv-encryption-{}-{}-{}",
+        test,
+        std::process::id(),
+        name
+    ));
+    let _ = fs::remove_file(&path);
+    let _ = fs::remove_dir_all(&path);
+    path
+}
+
+fn s(value: &str) -> String {
+    String::from(value)
+}
+
+fn key(byte: u8) -> EncryptionKey {
+    EncryptionKey::new([byte; 32])
+}
+
+fn contains(data: &[u8], text: &str) -> bool {
+    data.windows(text.len()).any(|w| w == text.as_bytes())
+}
+
+/// 错误的类型与原因中的关键字
+fn assert_encryption_error<T: std::fmt::Debug>(res: Result<T, DBError>, reason: &str) {
+    match res {
+        Err(DBError::Encryption(message)) => assert!(message.contains(reason), "{}", message),
+        other => panic!("expect an encryption error, got {:?}", other),
+    }
+}
+
+fn encrypted_dbs(key: Option<EncryptionKey>) -> Databases {
+    let mut dbs = Databases::new(2, None);
+    assert_eq!(Ok(DBOk::Ok), dbs.set_encryption_key(key));
+    dbs
+}
+
+#[test]
+fn keys_from_hex_files_and_env() {
+    let hex = "00112233445566778899aabbccddeeff00112233445566778899aabbccddeeff";
+    let parsed = EncryptionKey::from_hex(&format!("  {}\n", hex)).unwrap();
+    assert_eq!(16, parsed.fingerprint().len());
+    assert_ne!(key(1).fingerprint(), key(2).fingerprint());
+    assert_eq!(key(1).fingerprint(), key(1).fingerprint());
+    assert!(!format!("{:?}", key(0xab)).contains("abab"));
+    assert_eq!(
+        format!(
+            "EncryptionKey {{ fingerprint: \"{}\" }}",
+            key(1).fingerprint()
+        ),
+        format!("{:?}", key(1))
+    );
+
+    for bad in ["", "0011", &hex[1..], &format!("{}zz", &hex[2..])].iter() {
+        assert_encryption_error(EncryptionKey::from_hex(bad), "64 hexadecimal");
+    }
+
+    let file = path("keys", "key");
+    fs::write(&file, hex).unwrap();
+    assert_eq!(
+        parsed.fingerprint(),
+        EncryptionKey::from_file(&file).unwrap().fingerprint()
+    );
+    fs::write(&file, [7u8; 32]).unwrap();
+    assert_eq!(
+        key(7).fingerprint(),
+        EncryptionKey::from_file(&file).unwrap().fingerprint()
+    );
+    fs::write(&file, [7u8; 31]).unwrap();
+    assert_encryption_error(EncryptionKey::from_file(&file), "32 bytes");
+    fs::remove_file(&file).unwrap();
+    assert!(matches!(
+        EncryptionKey::from_file(&file),
+        Err(DBError::Io(_))
+    ));
+
+    std::env::set_var("MEMKV_TEST_ENCRYPTION_KEY", hex);
+    assert_eq!(
+        Some(parsed.fingerprint()),
+        EncryptionKey::from_env("MEMKV_TEST_ENCRYPTION_KEY")
+            .unwrap()
+            .map(|k| k.fingerprint())
+    );
+    assert!(EncryptionKey::from_env("MEMKV_TEST_MISSING_KEY")
+        .unwrap()
+        .is_none());
+}
+
+#[test]
+fn snapshot_is_encrypted_and_authenticated() {
+    let file = path("snapshot", "mkdb");
+    let mut dbs = encrypted_dbs(Some(key(1)));
+    assert_eq!(
+        Ok(DBOk::Ok),
+        dbs.db_mut(1)
+            .unwrap()
+            .sets(&s("secret"), s("plaintext-value"))
+    );
+    assert_eq!(Ok(DBOk::Ok), dbs.save(&file));
+    assert!(dbs
+        .info()
+        .render(Some("persistence"))
+        .contains("encryption_enabled:1\r\n"));
+
+    let data = fs::read(&file).unwrap();
+    assert!(data.starts_with(b"MKENC"));
+    assert!(!contains(&data, "secret") && !contains(&data, "plaintext-value"));
+
+    let mut loaded = encrypted_dbs(Some(key(1)));
+    assert_eq!(Ok(1), loaded.load(&file));
+    assert_eq!(
+        Ok(Some(s("plaintext-value"))),
+        loaded.db(1).unwrap().get(&s("secret"))
+    );
+
+    assert_encryption_error(encrypted_dbs(None).load(&file), "no encryption key");
+    assert_encryption_error(
+        encrypted_dbs(Some(key(2))).load(&file),
+        "wrong encryption key",
+    );
+
+    // 修改任意一个字节都无法通过认证
+    for pos in [20, data.len() / 2, data.len() - 1].iter() {
+        let mut tampered = data.clone();
+        tampered[*pos] ^= 1;
+        fs::write(&file, &tampered).unwrap();
+        assert_encryption_error(encrypted_dbs(Some(key(1))).load(&file), "tampered");
+    }
+    fs::write(&file, &data[..data.len() - 1]).unwrap();
+    assert_encryption_error(encrypted_dbs(Some(key(1))).load(&file), "tampered");
+
+    // 设置密钥后拒绝加载未加密（例如被替换）的快照；迁移时不设置密钥加载, 再设置密钥保存
+    assert_eq!(Ok(DBOk::Ok), Databases::new(2, None).save(&file));
+    assert_encryption_error(encrypted_dbs(Some(key(1))).load(&file), "not encrypted");
+    let mut migrated = encrypted_dbs(None);
+    assert_eq!(Ok(0), migrated.load(&file));
+    assert_eq!(Ok(DBOk::Ok), migrated.set_encryption_key(Some(key(1))));
+    assert_eq!(Ok(DBOk::Ok), migrated.save(&file));
+    assert!(fs::read(&file).unwrap().starts_with(b"MKENC"));
+    assert_eq!(Ok(DBOk::Ok), migrated.bgsave(&file));
+    assert_eq!(Some(Ok(DBOk::Ok)), migrated.wait_bgsave());
+    assert_eq!(Ok(0), encrypted_dbs(Some(key(1))).load(&file));
+    fs::remove_file(&file).unwrap();
+}
+
+#[test]
+fn aof_frames_survive_torn_tail_but_not_tampering() {
+    let file = path("aof", "aof");
+    {
+        let mut dbs = encrypted_dbs(Some(key(1)));
+        dbs.open_aof(&file, FsyncPolicy::Always).unwrap();
+        for i in 0..3 {
+            let db = dbs.db_mut(0).unwrap();
+            assert_eq!(
+                Ok(DBOk::Ok),
+                db.sets(&format!("key{}", i), s("secret-value"))
+            );
+            assert_eq!(Ok(DBOk::Ok), dbs.flush_aof());
+        }
+        assert_eq!(
+            Err(DBError::InProgress(s("AOF"))),
+            dbs.set_encryption_key(None)
+        );
+    }
+    let data = fs::read(&file).unwrap();
+    assert!(!contains(&data, "secret-value") && !contains(&data, "key0"));
+
+    let mut dbs = encrypted_dbs(Some(key(1)));
+    let replay = dbs.open_aof(&file, FsyncPolicy::No).unwrap();
+    assert_eq!((4, 0), (replay.commands, replay.truncated));
+    assert_eq!(3, dbs.db(0).unwrap().size());
+    drop(dbs);
+    assert_encryption_error(
+        encrypted_dbs(None).open_aof(&file, FsyncPolicy::No),
+        "no encryption key",
+    );
+    assert_encryption_error(
+        encrypted_dbs(Some(key(2))).open_aof(&file, FsyncPolicy::No),
+        "wrong encryption key",
+    );
+
+    // 最后一帧不完整时截掉该帧, 之后继续追加的帧仍然可以读取
+    fs::write(&file, &data[..data.len() - 3]).unwrap();
+    let mut dbs = encrypted_dbs(Some(key(1)));
+    let replay = dbs.open_aof(&file, FsyncPolicy::No).unwrap();
+    assert_eq!(2, dbs.db(0).unwrap().size());
+    assert!(replay.truncated > 3);
+    assert_eq!(
+        Ok(DBOk::Ok),
+        dbs.db_mut(0).unwrap().sets(&s("after"), s("v"))
+    );
+    assert_eq!(Ok(DBOk::Ok), dbs.flush_aof());
+    drop(dbs);
+    let mut dbs = encrypted_dbs(Some(key(1)));
+    assert_eq!(0, dbs.open_aof(&file, FsyncPolicy::No).unwrap().truncated);
+    assert_eq!(Ok(Some(s("v"))), dbs.db(0).unwrap().get(&s("after")));
+    drop(dbs);
+
+    // 中间的帧被修改
+    let mut tampered = fs::read(&file).unwrap();
+    tampered[40] ^= 1;
+    fs::write(&file, &tampered).unwrap();
+    assert_encryption_error(
+        encrypted_dbs(Some(key(1))).open_aof(&file, FsyncPolicy::No),
+        "tampered",
+    );
+
+    // 重写之后仍然加密
+    fs::write(&file, &data).unwrap();
+    let mut dbs = encrypted_dbs(Some(key(1)));
+    dbs.open_aof(&file, FsyncPolicy::EverySec).unwrap();
+    assert_eq!(Ok(DBOk::Ok), dbs.bgrewriteaof());
+    assert_eq!(
+        Ok(DBOk::Ok),
+        dbs.db_mut(0).unwrap().sets(&s("during"), s("v"))
+    );
+    assert_eq!(Some(Ok(DBOk::Ok)), dbs.wait_aof_rewrite());
+    assert_eq!(
+        Ok(DBOk::Ok),
+        dbs.db_mut(0).unwrap().sets(&s("rewritten"), s("v"))
+    );
+    assert_eq!(Ok(DBOk::Ok), dbs.flush_aof());
+    drop(dbs);
+    let data = fs::read(&file).unwrap();
+    assert!(data.starts_with(b"MKENC") && !contains(&data, "during"));
+    let mut dbs = encrypted_dbs(Some(key(1)));
+    dbs.open_aof(&file, FsyncPolicy::No).unwrap();
+    assert_eq!(5, dbs.db(0).unwrap().size());
+    fs::remove_file(&file).unwrap();
+}
+
+#[test]
+fn plaintext_logs_are_refused_and_migrated_by_rotation() {
+    let aof = path("migrate", "aof");
+    let mut dbs = Databases::new(1, None);
+    dbs.open_aof(&aof, FsyncPolicy::No).unwrap();
+    assert_eq!(
+        Ok(DBOk::Ok),
+        dbs.db_mut(0).unwrap().sets(&s("key"), s("secret-value"))
+    );
+    assert_eq!(Ok(DBOk::Ok), dbs.flush_aof());
+    drop(dbs);
+    assert!(contains(&fs::read(&aof).unwrap(), "secret-value"));
+
+    // 设置了密钥时未加密的日志无法开启, 文件保持不变
+    let mut dbs = encrypted_dbs(Some(key(1)));
+    assert_encryption_error(dbs.open_aof(&aof, FsyncPolicy::No), "not encrypted");
+    assert!(!dbs.aof_enabled());
+    assert!(contains(&fs::read(&aof).unwrap(), "secret-value"));
+
+    // 不设置密钥开启, 再轮换为新的密钥
+    let mut dbs = encrypted_dbs(None);
+    assert_eq!(2, dbs.open_aof(&aof, FsyncPolicy::No).unwrap().commands);
+    assert_eq!(Ok(DBOk::Ok), dbs.rotate_encryption_key(Some(key(1))));
+    let data = fs::read(&aof).unwrap();
+    assert!(data.starts_with(b"MKENC") && !contains(&data, "secret-value"));
+    drop(dbs);
+    let mut dbs = encrypted_dbs(Some(key(1)));
+    dbs.open_aof(&aof, FsyncPolicy::No).unwrap();
+    assert_eq!(
+        Ok(Some(s("secret-value"))),
+        dbs.db(0).unwrap().get(&s("key"))
+    );
+    drop(dbs);
+
+    let (wal, snapshot) = (path("migrate", "wal"), path("migrate", "mkdb"));
+    let mut dbs = Databases::new(1, None);
+    dbs.open_wal(&wal, &snapshot).unwrap();
+    assert_eq!(
+        Ok(DBOk::Ok),
+        dbs.db_mut(0).unwrap().sets(&s("key"), s("secret-value"))
+    );
+    assert_eq!(Ok(1), dbs.checkpoint());
+    assert_eq!(
+        Ok(DBOk::Ok),
+        dbs.db_mut(0).unwrap().sets(&s("other"), s("secret-value"))
+    );
+    assert_eq!(Ok(DBOk::Ok), dbs.commit_wal());
+    drop(dbs);
+
+    assert_encryption_error(
+        encrypted_dbs(Some(key(1))).open_wal(&wal, &snapshot),
+        "not encrypted",
+    );
+    let mut dbs = encrypted_dbs(None);
+    let recovery = dbs.open_wal(&wal, &snapshot).unwrap();
+    assert_eq!((1, 2), (recovery.records, recovery.last_lsn));
+    assert_eq!(Ok(DBOk::Ok), dbs.rotate_encryption_key(Some(key(1))));
+    drop(dbs);
+    for file in [&wal, &snapshot].iter() {
+        let data = fs::read(file).unwrap();
+        assert!(data.starts_with(b"MKENC") && !contains(&data, "secret-value"));
+    }
+    let mut dbs = encrypted_dbs(Some(key(1)));
+    dbs.open_wal(&wal, &snapshot).unwrap();
+    assert_eq!(
+        Ok(Some(s("secret-value"))),
+        dbs.db(0).unwrap().get(&s("other"))
+    );
+
+    for file in [&aof, &wal, &snapshot].iter() {
+        fs::remove_file(file).unwrap();
+    }
+}
+
+#[test]
+fn wal_history_and_rotation() {
+    let (wal, snapshot, archive) = (
+        path("rotate", "wal"),
+        path("rotate", "mkdb"),
+        path("rotate", "archive"),
+    );
+    let set = |dbs: &mut Databases, value: &str| {
+        let db = dbs.db_mut(0).unwrap();
+        assert_eq!(Ok(DBOk::Ok), db.sets(&s("key"), s(value)));
+        assert_eq!(Ok(DBOk::Ok), dbs.commit_wal());
+    };
+
+    let mut dbs = encrypted_dbs(Some(key(1)));
+    dbs.open_wal(&wal, &snapshot).unwrap();
+    assert_eq!(Ok(DBOk::Ok), dbs.set_wal_archive(&archive));
+    set(&mut dbs, "secret-v1");
+    assert_eq!(Ok(1), dbs.checkpoint());
+    set(&mut dbs, "secret-v2");
+    for entry in fs::read_dir(&archive).unwrap() {
+        let data = fs::read(entry.unwrap().path()).unwrap();
+        assert!(data.starts_with(b"MKENC") && !contains(&data, "secret"));
+    }
+    let view = dbs.history(RestorePoint::Lsn(1)).unwrap();
+    assert_eq!(Ok(Some(s("secret-v1"))), view.db(0).unwrap().get(&s("key")));
+    assert_eq!(
+        Err(DBError::InProgress(s("WAL"))),
+        dbs.set_encryption_key(None)
+    );
+
+    // 轮换之后只能使用新的密钥, 历史仍然完整
+    assert_eq!(Ok(DBOk::Ok), dbs.rotate_encryption_key(Some(key(2))));
+    set(&mut dbs, "secret-v3");
+    let view = dbs.history(RestorePoint::Lsn(1)).unwrap();
+    assert_eq!(Ok(Some(s("secret-v1"))), view.db(0).unwrap().get(&s("key")));
+    drop(dbs);
+
+    assert_encryption_error(
+        encrypted_dbs(Some(key(1))).open_wal(&wal, &snapshot),
+        "wrong encryption key",
+    );
+    let history = History::new(&wal, &snapshot).with_archive(&archive);
+    assert_encryption_error(
+        history.restore(RestorePoint::Lsn(2), 1).map(|v| v.point()),
+        "no encryption key",
+    );
+    let view = history
+        .with_encryption_key(key(2))
+        .restore(RestorePoint::Lsn(2), 1)
+        .unwrap();
+    assert_eq!(Ok(Some(s("secret-v2"))), view.db(0).unwrap().get(&s("key")));
+
+    let mut dbs = encrypted_dbs(Some(key(2)));
+    let recovery = dbs.open_wal(&wal, &snapshot).unwrap();
+    assert_eq!((2, 3), (recovery.checkpoint_lsn, recovery.last_lsn));
+    assert_eq!(Ok(Some(s("secret-v3"))), dbs.db(0).unwrap().get(&s("key")));
+    assert_eq!(Ok(DBOk::Ok), dbs.set_wal_archive(&archive));
+
+    // 轮换为不加密
+    assert_eq!(Ok(DBOk::Ok), dbs.rotate_encryption_key(None));
+    drop(dbs);
+    assert!(contains(&fs::read(&snapshot).unwrap(), "secret-v3"));
+    let mut dbs = Databases::new(1, None);
+    dbs.open_wal(&wal, &snapshot).unwrap();
+    assert_eq!(Ok(Some(s("secret-v3"))), dbs.db(0).unwrap().get(&s("key")));
+    let view = History::new(&wal, &snapshot)
+        .with_archive(&archive)
+        .restore(RestorePoint::Lsn(1), 1)
+        .unwrap();
+    assert_eq!(Ok(Some(s("secret-v1"))), view.db(0).unwrap().get(&s("key")));
+
+    let _ = fs::remove_file(&wal);
+    let _ = fs::remove_file(&snapshot);
+    let _ = fs::remove_dir_all(&archive);
+}
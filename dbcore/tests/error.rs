@@ -0,0 +1,123 @@
+/// 声明测试通用模块
+mod common;
+
+use dbcore::{DBError, DBOk, ValueType, KVDB};
+use std::error::Error;
+
+#[test]
+fn wrong_type_carries_context() {
+    let key = String::from("key");
+    let db: KVDB = common::setup_common_one_key_set(&key, &vec![String::from("m")]);
+
+    let err = db.hget(&key, &String::from("field")).unwrap_err();
+    assert_eq!(
+        DBError::WrongValueType {
+            key: key.clone(),
+            expected: ValueType::Hash,
+            actual: ValueType::Set,
+        },
+        err
+    );
+    assert_eq!(
+        "key `key` holds a set value, but a hash value is required",
+        err.to_string()
+    );
+    assert_eq!(
+        "WRONGTYPE Operation against a key holding the wrong kind of value",
+        err.to_redis_error()
+    );
+    assert!(err.source().is_none());
+
+    let pairs = vec![(String::from("f"), String::from("v"))];
+    let db: KVDB = common::setup_common_one_key_hash(&key, &pairs);
+    assert_eq!(
+        Err(DBError::WrongValueType {
+            key: key.clone(),
+            expected: ValueType::String,
+            actual: ValueType::Hash,
+        }),
+        db.get(&key)
+    );
+}
+
+#[test]
+fn errors_map_to_redis_strings() {
+    let mut db: KVDB = common::setup(Some(1));
+    let key = String::from("key");
+    assert_eq!(Ok(DBOk::Ok), db.sets(&key, String::from("value")));
+    let err = db
+        .sets(&String::from("other"), String::from("v"))
+        .unwrap_err();
+    assert_eq!(DBError::OutOfKeysSize(1), err);
+    assert!(err.to_redis_error().starts_with("OOM "));
+
+    let err = db.rename(&String::from("missing"), &key).unwrap_err();
+    assert_eq!("key `missing` not found", err.to_string());
+    assert_eq!("ERR no such key", err.to_redis_error());
+
+    let cases = vec![
+        (DBError::KeyAlreadyExists(key.clone()), "BUSYKEY "),
+        (
+            DBError::SameKey(String::from("key")),
+            "ERR source and destination objects are the same",
+        ),
+        (
+            DBError::DBIndexOutOfRange(16),
+            "ERR DB index is out of range",
+        ),
+        (
+            DBError::Syntax(String::from("missing value")),
+            "ERR syntax error",
+        ),
+        (
+            DBError::NotANumber(String::from("abc")),
+            "ERR value is not an integer",
+        ),
+        (
+            DBError::OutOfRange(String::from("count")),
+            "ERR count is out of range",
+        ),
+        (
+            DBError::OutOfRange(String::from("expire time")),
+            "ERR invalid expire time",
+        ),
+        (
+            DBError::NotSupported(String::from("LPUSH")),
+            "ERR LPUSH is not supported",
+        ),
+        (
+            DBError::Encryption(String::from("wrong encryption key")),
+            "ERR wrong encryption key",
+        ),
+        (
+            DBError::WrongArity(String::from("get")),
+            "ERR wrong number of arguments for 'get' command",
+        ),
+        (
+            DBError::UnknownCommand(String::from("lpush")),
+            "ERR unknown command 'lpush'",
+        ),
+    ];
+    for (err, prefix) in cases {
+        assert!(err.to_redis_error().starts_with(prefix), "{}", err);
+        assert!(!err.to_string().is_empty());
+    }
+}
+
+#[test]
+fn errors_work_with_question_mark() {
+    fn copy_twice(db: &mut KVDB) -> Result<bool, Box<dyn Error>> {
+        let key = String::from("key");
+        db.sets(&key, String::from("value"))?;
+        Ok(
+            db.copy(&key, &String::from("a"), false)?
+                && db.copy(&key, &String::from("b"), false)?,
+        )
+    }
+    let mut db: KVDB = common::setup(Some(2));
+    let err = copy_twice(&mut db).unwrap_err();
+    assert_eq!(
+        "the database is full, it holds at most 2 keys",
+        err.to_string()
+    );
+}
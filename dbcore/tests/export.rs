@@ -0,0 +1,264 @@
+use dbcore::{DBError, DBOk, Databases, ExportFormat, ValueType};
+use std::collections::HashSet;
+use std::iter::FromIterator;
+
+fn s(value: &str) -> String {
+    String::from(value)
+}
+
+/// 包含每种 value 以及需要转义的字符
+fn fill(dbs: &mut Databases) {
+    let db = dbs.db_mut(0).unwrap();
+    assert_eq!(
+        Ok(DBOk::Ok),
+        db.sets(&s("string"), s("中文 \"quoted\", comma"))
+    );
+    assert_eq!(
+        Ok(DBOk::Ok),
+        db.set(
+            &s("volatile"),
+            s("line1\nline2\r\n\t\u{1}"),
+            false,
+            false,
+            Some(100)
+        )
+    );
+    assert_eq!(Ok(DBOk::Ok), db.sets(&s("empty"), s("")));
+    assert_eq!(
+        Ok(3),
+        db.sadd(&s("set"), vec![s("a"), s("b,c"), s("\"d\"")])
+    );
+    let db = dbs.db_mut(2).unwrap();
+    let pairs = vec![(s("name"), s("memkv")), (s("emoji"), s("😀\\"))];
+    assert_eq!(Ok(DBOk::Ok), db.hmset(&s("hash"), pairs));
+    assert_eq!(Ok(DBOk::Ok), db.sets(&s("key with spaces"), s(" v ")));
+}
+
+fn export(dbs: &Databases, format: ExportFormat) -> String {
+    let mut out = Vec::new();
+    dbs.export(&mut out, format, None, None).unwrap();
+    String::from_utf8(out).unwrap()
+}
+
+fn import(dbs: &mut Databases, format: ExportFormat, data: &str) -> dbcore::Result<usize> {
+    dbs.import(&mut data.as_bytes(), format, None, None)
+}
+
+#[test]
+fn json_lines_format() {
+    let mut dbs = Databases::new(4, None);
+    fill(&mut dbs);
+    let text = export(&dbs, ExportFormat::JsonLines);
+    let lines: Vec<&str> = text.lines().collect();
+    assert_eq!(6, lines.len());
+    assert_eq!(
+        r#"{"db":0,"key":"empty","type":"string","ttl":-1,"value":""}"#,
+        lines[0]
+    );
+    assert_eq!(
+        r#"{"db":0,"key":"set","type":"set","ttl":-1,"value":["\"d\"","a","b,c"]}"#,
+        lines[1]
+    );
+    assert_eq!(
+        r#"{"db":0,"key":"string","type":"string","ttl":-1,"value":"中文 \"quoted\", comma"}"#,
+        lines[2]
+    );
+    assert!(lines[3].starts_with(r#"{"db":0,"key":"volatile","type":"string","ttl":"#));
+    assert!(lines[3].ends_with(r#","value":"line1\nline2\r\n\t\u0001"}"#));
+    assert_eq!(
+        r#"{"db":2,"key":"hash","type":"hash","ttl":-1,"value":{"emoji":"😀\\","name":"memkv"}}"#,
+        lines[4]
+    );
+}
+
+#[test]
+fn round_trip_every_value() {
+    for format in [ExportFormat::JsonLines, ExportFormat::Csv].iter() {
+        let mut dbs = Databases::new(4, None);
+        fill(&mut dbs);
+        let text = export(&dbs, *format);
+
+        let mut imported = Databases::new(4, None);
+        assert_eq!(Ok(6), import(&mut imported, *format, &text));
+
+        let db = imported.db(0).unwrap();
+        assert_eq!(Ok(Some(s("中文 \"quoted\", comma"))), db.get(&s("string")));
+        assert_eq!(
+            Ok(Some(s("line1\nline2\r\n\t\u{1}"))),
+            db.get(&s("volatile"))
+        );
+        let ttl = db.ttl(&s("volatile"));
+        assert!(ttl > 90 && ttl <= 100);
+        assert_eq!(-1, db.ttl(&s("string")));
+        assert_eq!(Ok(Some(s(""))), db.get(&s("empty")));
+        assert_eq!(
+            Ok(Some(HashSet::from_iter(vec![s("a"), s("b,c"), s("\"d\"")]))),
+            db.smembers(&s("set"))
+        );
+        let db = imported.db(2).unwrap();
+        assert_eq!(Ok(Some(s("😀\\"))), db.hget(&s("hash"), &s("emoji")));
+        assert_eq!(Ok(Some(s(" v "))), db.get(&s("key with spaces")));
+
+        // 去掉会随时间变化的过期时间后, 再次导出的结果完全相同
+        for dbs in [&mut dbs, &mut imported].iter_mut() {
+            assert_eq!(true, dbs.db_mut(0).unwrap().persist(&s("volatile")));
+        }
+        assert_eq!(export(&dbs, *format), export(&imported, *format));
+    }
+}
+
+#[test]
+fn filter_by_pattern_and_type() {
+    let mut dbs = Databases::new(4, None);
+    fill(&mut dbs);
+    for format in [ExportFormat::JsonLines, ExportFormat::Csv].iter() {
+        let mut out = Vec::new();
+        assert_eq!(
+            Ok(1),
+            dbs.export(&mut out, *format, Some("s*"), Some(ValueType::String))
+        );
+        let mut out = Vec::new();
+        assert_eq!(
+            Ok(1),
+            dbs.export(&mut out, *format, None, Some(ValueType::Hash))
+        );
+
+        let text = export(&dbs, *format);
+        let mut imported = Databases::new(4, None);
+        assert_eq!(
+            Ok(1),
+            imported.import(
+                &mut text.as_bytes(),
+                *format,
+                Some("*e*"),
+                Some(ValueType::Set)
+            )
+        );
+        assert_eq!(vec![s("set")], imported.db(0).unwrap().keys("*"));
+        assert_eq!(0, imported.db(2).unwrap().size());
+    }
+}
+
+#[test]
+fn import_overwrites_and_is_atomic() {
+    let mut dbs = Databases::new(2, Some(3));
+    assert_eq!(Ok(DBOk::Ok), dbs.db_mut(0).unwrap().sets(&s("a"), s("old")));
+    assert_eq!(
+        Ok(DBOk::Ok),
+        dbs.db_mut(0).unwrap().sets(&s("b"), s("kept"))
+    );
+
+    let data = "{\"db\":0,\"key\":\"a\",\"type\":\"string\",\"ttl\":-1,\"value\":\"new\"}\n\n\
+                {\"key\":\"c\",\"type\":\"set\",\"value\":[\"x\",\"y\"]}\n";
+    assert_eq!(Ok(2), import(&mut dbs, ExportFormat::JsonLines, data));
+    let db = dbs.db(0).unwrap();
+    assert_eq!(Ok(Some(s("new"))), db.get(&s("a")));
+    assert_eq!(Ok(Some(s("kept"))), db.get(&s("b")));
+    assert_eq!(3, db.size());
+
+    let full = "db,key,type,ttl,value\n0,d,string,-1,v\n";
+    assert_eq!(
+        Err(DBError::OutOfKeysSize(3)),
+        import(&mut dbs, ExportFormat::Csv, full)
+    );
+    let out_of_range = "db,key,type,ttl,value\r\n0,a,string,-1,v\r\n5,d,string,-1,v\r\n";
+    assert_eq!(
+        Err(DBError::DBIndexOutOfRange(5)),
+        import(&mut dbs, ExportFormat::Csv, out_of_range)
+    );
+    assert_eq!(
+        Ok(Some(s("new"))),
+        dbs.db(0).unwrap().get(&s("a")),
+        "failed import keeps data"
+    );
+}
+
+#[test]
+fn bad_input_reports_line() {
+    let mut dbs = Databases::new(2, None);
+    let cases = [
+        (
+            ExportFormat::JsonLines,
+            "\n{\"key\":\"a\"",
+            "line 2: expected `,` or `}` in object",
+        ),
+        (
+            ExportFormat::JsonLines,
+            "{\"key\":\"a\",\"type\":\"list\",\"value\":[]}",
+            "line 1: unknown type `list`",
+        ),
+        (
+            ExportFormat::JsonLines,
+            "{\"key\":\"a\",\"type\":\"set\",\"value\":[]}",
+            "line 1: set must not be empty",
+        ),
+        (
+            ExportFormat::JsonLines,
+            "{\"key\":\"a\",\"type\":\"hash\",\"value\":{\"f\":1}}",
+            "line 1: expected string, found number",
+        ),
+        (
+            ExportFormat::JsonLines,
+            "{\"key\":\"a\",\"type\":\"string\",\"ttl\":0,\"value\":\"v\"}",
+            "line 1: invalid ttl 0",
+        ),
+        (
+            ExportFormat::JsonLines,
+            "{\"key\":\"a\",\"type\":\"string\",\"value\":\"v\",\"extra\":1}",
+            "line 1: unknown field `extra`",
+        ),
+        (
+            ExportFormat::Csv,
+            "key,value\n",
+            "line 1: expected CSV header `db,key,type,ttl,value`",
+        ),
+        (
+            ExportFormat::Csv,
+            "db,key,type,ttl,value\n0,\"a\nb\",string,-1,v\n0,b,string\n",
+            "line 4: expected 5 fields, found 3",
+        ),
+        (
+            ExportFormat::Csv,
+            "db,key,type,ttl,value\n0,a,set,-1,[\"x\"\n",
+            "line 2: expected `,` or `]` in array",
+        ),
+        (
+            ExportFormat::Csv,
+            "db,key,type,ttl,value\n0,a,string,-1,\"v\n",
+            "line 2: unterminated quoted field",
+        ),
+    ];
+    for (format, data, reason) in cases.iter() {
+        assert_eq!(
+            Err(DBError::InvalidPayload(s(reason))),
+            import(&mut dbs, *format, data),
+            "{}",
+            data
+        );
+    }
+    assert_eq!(
+        Err(DBError::InvalidPayload(s("input is not valid UTF-8"))),
+        dbs.import(&mut &[0xffu8][..], ExportFormat::JsonLines, None, None)
+    );
+    assert_eq!(0, dbs.db(0).unwrap().size());
+}
+
+#[test]
+fn json_escapes() {
+    let mut dbs = Databases::new(1, None);
+    let data = r#"{"key":"\u4e2d\ud83d\ude00\/","type":"string","value":"a\"b\\c\bd\fe"}"#;
+    assert_eq!(Ok(1), import(&mut dbs, ExportFormat::JsonLines, data));
+    assert_eq!(
+        Ok(Some(s("a\"b\\c\u{8}d\u{c}e"))),
+        dbs.db(0).unwrap().get(&s("中😀/"))
+    );
+}
+
+#[test]
+fn format_names() {
+    for format in [ExportFormat::JsonLines, ExportFormat::Csv].iter() {
+        assert_eq!(Some(*format), ExportFormat::from_name(format.name()));
+    }
+    assert_eq!(Some(ExportFormat::Csv), ExportFormat::from_name("CSV"));
+    assert_eq!(None, ExportFormat::from_name("xml"));
+}
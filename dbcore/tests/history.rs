@@ -0,0 +1,219 @@
+use dbcore::{DBError, DBOk, Databases, History, RestorePoint};
+use std::collections::HashSet;
+use std::fs;
+use std::iter::FromIterator;
+use std::path::PathBuf;
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// 每个测试使用独立的 WAL、快照文件与归档目录
+fn paths(name: &str) -> (PathBuf, PathBuf, PathBuf) {
+    let dir = std::env::temp_dir();
+    let prefix = format!("memkv-history-{}-{}", name, std::process::id());
+    let wal = dir.join(format!("{}.wal", prefix));
+    let snapshot = dir.join(format!("{}.mkdb", prefix));
+    let archive = dir.join(format!("{}-archive", prefix));
+    let _ = fs::remove_file(&wal);
+    let _ = fs::remove_file(&snapshot);
+    let _ = fs::remove_dir_all(&archive);
+    (wal, snapshot, archive)
+}
+
+fn s(value: &str) -> String {
+    String::from(value)
+}
+
+fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_millis() as u64
+}
+
+fn set(dbs: &mut Databases, key: &str, value: &str) -> u64 {
+    assert_eq!(Ok(DBOk::Ok), dbs.db_mut(0).unwrap().sets(&s(key), s(value)));
+    assert_eq!(Ok(DBOk::Ok), dbs.commit_wal());
+    dbs.wal().unwrap().last_lsn()
+}
+
+#[test]
+fn restore_by_lsn_and_time() {
+    let (wal, snapshot, _) = paths("target");
+    let mut dbs = Databases::new(2, None);
+    dbs.open_wal(&wal, &snapshot).unwrap();
+
+    let mut times = Vec::new();
+    for value in ["v1", "v2", "v3"].iter() {
+        thread::sleep(Duration::from_millis(5));
+        assert_eq!(times.len() as u64 + 1, set(&mut dbs, "key", value));
+        thread::sleep(Duration::from_millis(5));
+        times.push(now_millis());
+    }
+    thread::sleep(Duration::from_millis(5));
+    assert_eq!(Ok(DBOk::Ok), dbs.swapdb(0, 1));
+    assert_eq!(Ok(DBOk::Ok), dbs.commit_wal());
+
+    for (i, value) in ["v1", "v2", "v3"].iter().enumerate() {
+        let view = dbs.history(RestorePoint::Lsn(i as u64 + 1)).unwrap();
+        assert_eq!(Ok(Some(s(value))), view.db(0).unwrap().get(&s("key")));
+        let point = view.point();
+        assert_eq!(
+            (i as u64 + 1, 0, i + 1),
+            (point.lsn, point.base_lsn, point.records)
+        );
+
+        let view = dbs.history(RestorePoint::Time(times[i])).unwrap();
+        assert_eq!(Ok(Some(s(value))), view.db(0).unwrap().get(&s("key")));
+        assert_eq!(times[i], view.point().timestamp);
+    }
+    let view = dbs.history(RestorePoint::Time(now_millis())).unwrap();
+    assert_eq!(4, view.point().lsn);
+    assert_eq!(Ok(Some(s("v3"))), view.db(1).unwrap().get(&s("key")));
+    assert_eq!(0, view.db(0).unwrap().size());
+
+    // 历史视图与当前数据库互不影响
+    assert_eq!(Ok(None), dbs.db(0).unwrap().get(&s("key")));
+    assert_eq!(Ok(Some(s("v3"))), dbs.db(1).unwrap().get(&s("key")));
+
+    assert_eq!(
+        Err(DBError::OutOfRange(s("restore point"))),
+        dbs.history(RestorePoint::Lsn(5)).map(|v| v.point())
+    );
+    assert_eq!(
+        Err(DBError::OutOfRange(s("restore point"))),
+        dbs.history(RestorePoint::Time(times[0] - 1000))
+            .map(|v| v.point())
+    );
+    assert_eq!(
+        Err(DBError::NotSupported(s(
+            "point-in-time restore without WAL"
+        ))),
+        Databases::new(1, None)
+            .history(RestorePoint::Lsn(1))
+            .map(|v| v.point())
+    );
+    let _ = fs::remove_file(&wal);
+}
+
+#[test]
+fn replay_uses_the_time_of_each_record() {
+    let (wal, snapshot, _) = paths("clock");
+    let mut dbs = Databases::new(1, None);
+    dbs.open_wal(&wal, &snapshot).unwrap();
+
+    // 300 毫秒后过期的集合, 过期之前继续添加元素
+    let db = dbs.db_mut(0).unwrap();
+    assert_eq!(Ok(1), db.sadd(&s("set"), vec![s("a")]));
+    let payload = db.dump(&s("set")).unwrap();
+    assert_eq!(
+        Ok(DBOk::Ok),
+        db.restore(&s("set"), Some(300), &payload, true)
+    );
+    assert_eq!(Ok(1), db.sadd(&s("set"), vec![s("b")]));
+    assert_eq!(Ok(DBOk::Ok), dbs.commit_wal());
+    let lsn = dbs.wal().unwrap().last_lsn();
+    thread::sleep(Duration::from_millis(350));
+    assert_eq!(Ok(None), dbs.db(0).unwrap().smembers(&s("set")));
+
+    // 视图中的数据与 TTL 与当时相同
+    let view = dbs.history(RestorePoint::Lsn(lsn)).unwrap();
+    let db = view.db(0).unwrap();
+    assert_eq!(
+        Ok(Some(HashSet::from_iter(vec![s("a"), s("b")]))),
+        db.smembers(&s("set"))
+    );
+    assert!(db.pttl(&s("set")) > 0 && db.pttl(&s("set")) <= 300);
+
+    // 转换为普通的数据库之后使用系统时间
+    let restored = view.into_databases();
+    assert_eq!(Ok(None), restored.db(0).unwrap().smembers(&s("set")));
+
+    // 崩溃恢复同样按照记录的时间重放, 恢复之后 key 已经过期
+    drop(dbs);
+    let mut dbs = Databases::new(1, None);
+    assert_eq!(lsn as usize, dbs.open_wal(&wal, &snapshot).unwrap().records);
+    assert_eq!(Ok(None), dbs.db(0).unwrap().smembers(&s("set")));
+    let _ = fs::remove_file(&wal);
+}
+
+#[test]
+fn archive_keeps_history_across_checkpoints() {
+    let (wal, snapshot, archive) = paths("archive");
+
+    // 开启 WAL 之前就存在的数据
+    let mut dbs = Databases::new(1, None);
+    assert_eq!(Ok(DBOk::Ok), dbs.db_mut(0).unwrap().sets(&s("old"), s("v")));
+    assert_eq!(Ok(DBOk::Ok), dbs.save(&snapshot));
+
+    let mut dbs = Databases::new(1, None);
+    dbs.open_wal(&wal, &snapshot).unwrap();
+    assert_eq!(Ok(DBOk::Ok), dbs.set_wal_archive(&archive));
+    set(&mut dbs, "key", "v1");
+    set(&mut dbs, "key", "v2");
+    assert_eq!(Ok(2), dbs.checkpoint());
+    set(&mut dbs, "key", "v3");
+    assert_eq!(Ok(3), dbs.checkpoint());
+    assert_eq!(Ok(3), dbs.checkpoint());
+    set(&mut dbs, "key", "v4");
+
+    let mut archived: Vec<String> = fs::read_dir(&archive)
+        .unwrap()
+        .map(|e| e.unwrap().file_name().into_string().unwrap())
+        .collect();
+    archived.sort();
+    assert_eq!(
+        vec![
+            "snapshot-00000000000000000000.mkdb",
+            "snapshot-00000000000000000002.mkdb",
+            "snapshot-00000000000000000003.mkdb",
+            "wal-00000000000000000002.log",
+            "wal-00000000000000000003.log",
+        ],
+        archived
+    );
+
+    for (lsn, value, base_lsn) in [(1, "v1", 0), (2, "v2", 2), (3, "v3", 3), (4, "v4", 3)].iter() {
+        let view = dbs.history(RestorePoint::Lsn(*lsn)).unwrap();
+        let db = view.db(0).unwrap();
+        assert_eq!(Ok(Some(s(value))), db.get(&s("key")));
+        assert_eq!(Ok(Some(s("v"))), db.get(&s("old")));
+        assert_eq!(*base_lsn, view.point().base_lsn);
+    }
+    let view = dbs.history(RestorePoint::Lsn(0)).unwrap();
+    assert_eq!(vec![s("old")], view.db(0).unwrap().keys("*"));
+    drop(dbs);
+
+    // 不使用归档时只能恢复到最近一次检查点之后
+    let history = History::new(&wal, &snapshot);
+    assert_eq!(
+        Ok(Some(s("v4"))),
+        history
+            .restore(RestorePoint::Lsn(4), 1)
+            .unwrap()
+            .db(0)
+            .unwrap()
+            .get(&s("key"))
+    );
+    assert_eq!(
+        Err(DBError::InvalidPayload(s(
+            "WAL record 1 is missing from the history"
+        ))),
+        history.restore(RestorePoint::Lsn(1), 1).map(|v| v.point())
+    );
+
+    // 恢复只读取文件, 结果可以保存为新的快照
+    let mut restored = history
+        .with_archive(&archive)
+        .restore(RestorePoint::Lsn(1), 1)
+        .unwrap()
+        .into_databases();
+    let output = archive.join("restored.mkdb");
+    assert_eq!(Ok(DBOk::Ok), restored.save(&output));
+    let mut loaded = Databases::new(1, None);
+    assert_eq!(Ok(2), loaded.load(&output));
+    assert_eq!(Ok(Some(s("v1"))), loaded.db(0).unwrap().get(&s("key")));
+
+    let _ = fs::remove_file(&wal);
+    let _ = fs::remove_file(&snapshot);
+    let _ = fs::remove_dir_all(&archive);
+}
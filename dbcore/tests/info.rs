@@ -0,0 +1,139 @@
+use dbcore::{DBOk, Databases, INFO_SECTIONS};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_millis() as u64
+}
+
+#[test]
+fn keyspace_counts_per_type() {
+    let mut dbs = Databases::new(2, None);
+    let db = dbs.db_mut(1).unwrap();
+    assert_eq!(
+        Ok(DBOk::Ok),
+        db.sets(&String::from("s1"), String::from("v"))
+    );
+    assert_eq!(
+        Ok(DBOk::Ok),
+        db.set(
+            &String::from("s2"),
+            String::from("v"),
+            false,
+            false,
+            Some(100)
+        )
+    );
+    assert_eq!(
+        Ok(1),
+        db.sadd(&String::from("set"), vec![String::from("m")])
+    );
+    assert_eq!(
+        Ok(1),
+        db.hset(&String::from("hash"), String::from("f"), String::from("v"))
+    );
+
+    let info = dbs.info();
+    assert_eq!(2, info.databases);
+    assert_eq!(0, info.keyspace[0].1.keys);
+    let keyspace = &info.keyspace[1].1;
+    assert_eq!(4, keyspace.keys);
+    assert_eq!(1, keyspace.expires);
+    assert_eq!(2, keyspace.strings);
+    assert_eq!(1, keyspace.sets);
+    assert_eq!(1, keyspace.hashes);
+    assert!(keyspace.used_memory > 0);
+    assert_eq!(keyspace.used_memory, info.used_memory());
+}
+
+#[test]
+fn hits_misses_and_expired() {
+    let mut dbs = Databases::new(1, None);
+    let db = dbs.db_mut(0).unwrap();
+    let key = String::from("key");
+    assert_eq!(Ok(None), db.get(&key));
+    assert_eq!(Ok(DBOk::Ok), db.sets(&key, String::from("v")));
+    assert_eq!(Ok(Some(String::from("v"))), db.get(&key));
+    assert_eq!(true, db.exists(&key));
+
+    let stale = String::from("stale");
+    assert_eq!(Ok(DBOk::Ok), db.sets(&stale, String::from("v")));
+    assert_eq!(true, db.pexpire_at(&stale, now_millis() - 1));
+    assert_eq!(1, db.size(), "expired keys are not counted");
+    assert_eq!(1, dbs.purge_expired());
+
+    let total = dbs.info().total();
+    assert_eq!(2, total.keyspace_hits);
+    assert_eq!(1, total.keyspace_misses);
+    assert_eq!(1, total.expired_keys);
+    assert_eq!(0, total.evicted_keys);
+    assert_eq!(1, total.keys);
+}
+
+#[test]
+fn size_follows_expiry() {
+    let mut dbs = Databases::new(1, None);
+    let db = dbs.db_mut(0).unwrap();
+    for i in 0..10 {
+        let key = format!("key:{}", i);
+        assert_eq!(Ok(DBOk::Ok), db.sets(&key, String::from("v")));
+    }
+    let now = now_millis();
+    assert_eq!(true, db.pexpire_at(&String::from("key:0"), now - 1));
+    assert_eq!(true, db.pexpire_at(&String::from("key:1"), now + 200));
+    assert_eq!(true, db.pexpire_at(&String::from("key:2"), now + 200));
+    assert_eq!(true, db.pexpire_at(&String::from("key:3"), now + 60_000));
+    assert_eq!(9, db.size());
+    assert_eq!(true, db.persist(&String::from("key:2")));
+    assert_eq!(9, db.size());
+
+    std::thread::sleep(Duration::from_millis(250));
+    assert_eq!(8, db.size());
+    assert_eq!(true, db.pexpire_at(&String::from("key:3"), now - 1));
+    assert_eq!(7, db.size());
+    assert_eq!(3, dbs.purge_expired());
+    assert_eq!(7, dbs.db(0).unwrap().size());
+    assert_eq!(0, dbs.info().total().expires);
+}
+
+#[test]
+fn command_stats() {
+    let mut dbs = Databases::new(1, None);
+    dbs.record_command("GET", Duration::from_micros(10));
+    dbs.record_command("get", Duration::from_micros(30));
+    dbs.record_command("set", Duration::from_micros(5));
+
+    let info = dbs.info();
+    assert_eq!(3, info.total_commands_processed());
+    assert_eq!("get", info.commands[0].0);
+    assert_eq!(2, info.commands[0].1.calls);
+    assert_eq!(20.0, info.commands[0].1.usec_per_call());
+    assert_eq!("set", info.commands[1].0);
+}
+
+#[test]
+fn render_sections() {
+    let mut dbs = Databases::new(2, None);
+    let db = dbs.db_mut(1).unwrap();
+    assert_eq!(
+        Ok(DBOk::Ok),
+        db.sets(&String::from("key"), String::from("v"))
+    );
+    dbs.record_command("set", Duration::from_micros(3));
+    let info = dbs.info();
+
+    let all = info.render(None);
+    for section in INFO_SECTIONS.iter() {
+        assert!(all.to_lowercase().contains(&format!("# {}", section)));
+    }
+    let keyspace = info.render(Some("keyspace"));
+    assert!(keyspace.contains("db1:keys=1,expires=0,strings=1,sets=0,hashes=0"));
+    assert!(!keyspace.contains("db0:"));
+    assert!(!keyspace.contains("# Server"));
+    assert!(info
+        .render(Some("commandstats"))
+        .contains("cmdstat_set:calls=1,usec=3"));
+    assert_eq!("", info.render(Some("nothing")));
+}
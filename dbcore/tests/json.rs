@@ -0,0 +1,29 @@
+use dbcore::Json;
+
+#[test]
+fn parse_and_display_round_trip() {
+    let text = r#"{"key":"name","tags":["a","\"quoted\"","中文"],"ttl":-1,"ok":true,"none":null}"#;
+    let json = Json::parse(text).unwrap();
+    assert_eq!(text, json.to_string());
+    assert_eq!(Some(&Json::Number(-1)), json.get("ttl"));
+    assert_eq!(Some(&Json::Bool(true)), json.get("ok"));
+    assert_eq!(None, json.get("missing"));
+    assert_eq!(
+        Json::String(String::from("\u{1f600}\n")),
+        Json::parse(r#" "\ud83d\ude00\n" "#).unwrap()
+    );
+    assert_eq!(
+        "\"\\u0001\"",
+        Json::String(String::from("\u{1}")).to_string()
+    );
+}
+
+#[test]
+fn invalid_documents_are_rejected() {
+    assert!(Json::parse("").is_err());
+    assert!(Json::parse("{\"a\":1,}").is_err());
+    assert!(Json::parse("[1] 2").is_err());
+    assert!(Json::parse("1.5").is_err());
+    assert!(Json::parse("\"\\ud800\"").is_err());
+    assert_eq!("object", Json::parse("{}").unwrap().kind());
+}
@@ -0,0 +1,386 @@
+/// 声明测试通用模块
+mod common;
+
+use dbcore::{DBError, DBOk, EvictionPolicy, KVDB};
+use std::collections::HashSet;
+use std::iter::FromIterator;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_millis() as u64
+}
+
+#[test]
+fn expire_ttl_and_persist() {
+    let mut db: KVDB = common::setup(None);
+    let key = String::from("key");
+    assert_eq!(-2, db.ttl(&key));
+    assert_eq!(false, db.expire(&key, 10).unwrap());
+
+    assert_eq!(
+        Ok(DBOk::Ok),
+        db.set(&key, String::from("v"), false, false, Some(100))
+    );
+    assert!(db.ttl(&key) > 90 && db.ttl(&key) <= 100);
+    assert_eq!(true, db.persist(&key));
+    assert_eq!(-1, db.ttl(&key));
+    assert_eq!(false, db.persist(&key));
+
+    assert_eq!(true, db.expire(&key, 100).unwrap());
+    assert_eq!(Ok(DBOk::Ok), db.sets(&key, String::from("v2")));
+    assert_eq!(-1, db.ttl(&key), "set without expire clears the ttl");
+}
+
+#[test]
+fn overflowing_expire_times_are_rejected() {
+    let mut db: KVDB = common::setup(None);
+    let key = String::from("key");
+    let out_of_range = DBError::OutOfRange(String::from("expire time"));
+    assert_eq!(
+        Err(out_of_range.clone()),
+        db.set(&key, String::from("v"), false, false, Some(u64::MAX / 100))
+    );
+    assert_eq!(false, db.exists(&key));
+
+    assert_eq!(Ok(DBOk::Ok), db.sets(&key, String::from("v")));
+    assert_eq!(Err(out_of_range.clone()), db.expire(&key, u64::MAX / 100));
+    assert_eq!(-1, db.ttl(&key));
+
+    let payload = db.dump(&key).unwrap();
+    let restored = String::from("restored");
+    assert_eq!(
+        Err(out_of_range),
+        db.restore(&restored, Some(u64::MAX), &payload, false)
+    );
+    assert_eq!(false, db.exists(&restored));
+}
+
+#[test]
+fn expired_keys_are_invisible() {
+    let mut db: KVDB = common::setup(None);
+    let key = String::from("key");
+    assert_eq!(Ok(DBOk::Ok), db.sets(&key, String::from("v")));
+    assert_eq!(true, db.pexpire_at(&key, now_millis() - 1));
+
+    assert_eq!(Ok(None), db.get(&key));
+    assert_eq!(false, db.exists(&key));
+    assert_eq!(-2, db.ttl(&key));
+    assert!(db.keys("*").is_empty());
+    assert_eq!(None, db.randomkey());
+    assert_eq!(0, db.del(vec![key.clone()]));
+    assert_eq!(Ok(1), db.sadd(&key, vec![String::from("m")]));
+}
+
+#[test]
+fn rename_keeps_ttl() {
+    let mut db: KVDB = common::setup(None);
+    let key = String::from("key");
+    let newkey = String::from("newkey");
+    assert_eq!(
+        Err(DBError::KeyNotFound(key.clone())),
+        db.rename(&key, &newkey)
+    );
+
+    assert_eq!(
+        Ok(DBOk::Ok),
+        db.set(&key, String::from("v"), false, false, Some(100))
+    );
+    assert_eq!(Ok(DBOk::Ok), db.sets(&newkey, String::from("old")));
+    assert_eq!(Ok(DBOk::Ok), db.rename(&key, &newkey));
+    assert_eq!(false, db.exists(&key));
+    assert_eq!(Ok(Some(String::from("v"))), db.get(&newkey));
+    assert!(db.ttl(&newkey) > 90);
+    assert_eq!(-2, db.ttl(&key));
+}
+
+#[test]
+fn rename_in_full_database() {
+    let key = String::from("key");
+    let pairs: Vec<(String, String)> = vec![(String::from("f"), String::from("v"))];
+    let mut db: KVDB = common::setup_common_one_key_hash(&key, &pairs);
+
+    let newkey = String::from("newkey");
+    assert_eq!(Ok(DBOk::Ok), db.rename(&key, &newkey));
+    assert_eq!(Ok(Some(String::from("v"))), db.hget(&newkey, &pairs[0].0));
+    assert_eq!(1, db.size());
+}
+
+#[test]
+fn renamenx_only_when_new_key_missing() {
+    let mut db: KVDB = common::setup(None);
+    let a = String::from("a");
+    let b = String::from("b");
+    assert_eq!(Err(DBError::KeyNotFound(a.clone())), db.renamenx(&a, &b));
+    assert_eq!(Ok(DBOk::Ok), db.sets(&a, String::from("1")));
+    assert_eq!(Ok(DBOk::Ok), db.sets(&b, String::from("2")));
+    assert_eq!(Ok(false), db.renamenx(&a, &b));
+    assert_eq!(Ok(Some(String::from("2"))), db.get(&b));
+
+    assert_eq!(1, db.del(vec![b.clone()]));
+    assert_eq!(Ok(true), db.renamenx(&a, &b));
+    assert_eq!(Ok(Some(String::from("1"))), db.get(&b));
+}
+
+#[test]
+fn copy_with_and_without_replace() {
+    let key = String::from("key");
+    let members: Vec<String> = vec![String::from("a"), String::from("b")];
+    let mut db: KVDB = common::setup_common_one_key_set(&key, &members);
+    let dest = String::from("dest");
+    assert_eq!(Err(DBError::OutOfKeysSize(1)), db.copy(&key, &dest, false));
+
+    let mut db: KVDB = common::setup(None);
+    assert_eq!(Ok(2), db.sadd(&key, members.clone()));
+    assert_eq!(true, db.expire(&key, 100).unwrap());
+    assert_eq!(Ok(false), db.copy(&String::from("missing"), &dest, false));
+    assert_eq!(Err(DBError::SameKey(key.clone())), db.copy(&key, &key, true));
+    assert_eq!(Ok(true), db.copy(&key, &dest, false));
+    assert!(db.ttl(&dest) > 90);
+
+    let set: HashSet<String> = HashSet::from_iter(members);
+    assert_eq!(Ok(Some(set.clone())), db.smembers(&dest));
+    // 复制得到的是独立的 value
+    assert_eq!(Ok(1), db.srem(&dest, vec![String::from("a")]));
+    assert_eq!(Ok(Some(set)), db.smembers(&key));
+
+    assert_eq!(1, db.del(vec![dest.clone()]));
+    assert_eq!(Ok(DBOk::Ok), db.sets(&dest, String::from("v")));
+    assert_eq!(Ok(false), db.copy(&key, &dest, false));
+    assert_eq!(Ok(true), db.copy(&key, &dest, true));
+    assert_eq!(Ok(Some(2)), db.slen(&dest));
+}
+
+#[test]
+fn move_between_databases() {
+    let mut src: KVDB = common::setup(None);
+    let mut dst: KVDB = common::setup(Some(1));
+    let key = String::from("key");
+    assert_eq!(Ok(false), src.move_to(&key, &mut dst));
+
+    assert_eq!(
+        Ok(DBOk::Ok),
+        src.set(&key, String::from("v"), false, false, Some(100))
+    );
+    assert_eq!(Ok(true), src.move_to(&key, &mut dst));
+    assert_eq!(false, src.exists(&key));
+    assert_eq!(Ok(Some(String::from("v"))), dst.get(&key));
+    assert!(dst.ttl(&key) > 90);
+
+    assert_eq!(Ok(DBOk::Ok), src.sets(&key, String::from("again")));
+    assert_eq!(Ok(false), src.move_to(&key, &mut dst));
+    let other = String::from("other");
+    assert_eq!(Ok(DBOk::Ok), src.sets(&other, String::from("v")));
+    assert_eq!(
+        Err(DBError::OutOfKeysSize(1)),
+        src.move_to(&other, &mut dst)
+    );
+}
+
+#[test]
+fn touch_and_unlink() {
+    let mut db: KVDB = common::setup(None);
+    let small = String::from("small");
+    let big = String::from("big");
+    assert_eq!(Ok(DBOk::Ok), db.sets(&small, String::from("v")));
+    let members: Vec<String> = (0..1000).map(|x| x.to_string()).collect();
+    assert_eq!(Ok(1000), db.sadd(&big, members));
+
+    // 访问时间只在读写或 touch 时更新, 查询空闲时间本身不算访问
+    assert_eq!(None, db.idle_time(&String::from("missing")));
+    thread::sleep(Duration::from_millis(1100));
+    assert_eq!(Some(1), db.idle_time(&small));
+    assert_eq!(Some(1), db.idle_time(&big));
+    assert_eq!(Ok(Some(String::from("v"))), db.get(&small));
+    assert_eq!(Some(0), db.idle_time(&small));
+
+    let keys = vec![small.clone(), big.clone(), String::from("missing")];
+    assert_eq!(2, db.touch(keys.clone()));
+    assert_eq!(Some(0), db.idle_time(&big));
+    assert_eq!(2, db.unlink(keys));
+    assert_eq!(0, db.size());
+    assert_eq!(false, db.exists(&big));
+}
+
+#[test]
+fn dump_and_restore_every_type() {
+    let mut db: KVDB = common::setup(None);
+    let string = String::from("string");
+    let set = String::from("set");
+    let hash = String::from("hash");
+    assert_eq!(Ok(DBOk::Ok), db.sets(&string, String::from("中文 value")));
+    assert_eq!(
+        Ok(2),
+        db.sadd(&set, vec![String::from("a"), String::from("b")])
+    );
+    let pairs = vec![(String::from("f"), String::from("v"))];
+    assert_eq!(Ok(DBOk::Ok), db.hmset(&hash, pairs));
+    assert_eq!(None, db.dump(&String::from("missing")));
+
+    let mut other: KVDB = common::setup(None);
+    for key in [&string, &set, &hash].iter() {
+        let payload = db.dump(key).unwrap();
+        assert_eq!(Ok(DBOk::Ok), other.restore(key, None, &payload, false));
+        assert_eq!(db.key_type(key), other.key_type(key));
+    }
+    assert_eq!(db.get(&string), other.get(&string));
+    assert_eq!(db.smembers(&set), other.smembers(&set));
+    assert_eq!(db.hkeys(&hash), other.hkeys(&hash));
+}
+
+#[test]
+fn restore_rejects_bad_payloads() {
+    let mut db: KVDB = common::setup(None);
+    let key = String::from("key");
+    assert_eq!(Ok(DBOk::Ok), db.sets(&key, String::from("value")));
+    let payload = db.dump(&key).unwrap();
+
+    assert_eq!(
+        Err(DBError::KeyAlreadyExists(key.clone())),
+        db.restore(&key, None, &payload, false)
+    );
+    assert_eq!(
+        Ok(DBOk::Ok),
+        db.restore(&key, Some(100_000), &payload, true)
+    );
+    assert!(db.ttl(&key) > 90);
+
+    let restored = String::from("restored");
+    let mut corrupted = payload.clone();
+    corrupted[2] ^= 0xff;
+    let checksum = DBError::InvalidPayload(String::from("checksum mismatch"));
+    assert_eq!(
+        Err(checksum.clone()),
+        db.restore(&restored, None, &corrupted, false)
+    );
+    assert_eq!(
+        Err(checksum),
+        db.restore(&restored, None, &payload[..payload.len() - 1], false)
+    );
+    let err = db.restore(&restored, None, &[], false).unwrap_err();
+    assert!(matches!(err, DBError::InvalidPayload(_)));
+    assert_eq!(
+        "ERR DUMP payload version or checksum are wrong",
+        err.to_redis_error()
+    );
+    assert_eq!(false, db.exists(&restored));
+}
+
+#[test]
+fn versions_change_on_every_write() {
+    let mut db: KVDB = common::setup(None);
+    let key = String::from("key");
+    assert_eq!(None, db.version(&key));
+    assert_eq!(false, db.set_flags(&key, 7));
+
+    db.sets(&key, String::from("v")).unwrap();
+    let created = db.version(&key).unwrap();
+    assert_eq!(Some(0), db.flags(&key));
+    assert_eq!(true, db.set_flags(&key, 7));
+    assert_eq!(Some(7), db.flags(&key));
+    // 读取与设置标记不会改变版本号
+    db.get(&key).unwrap();
+    assert_eq!(Some(created), db.version(&key));
+
+    assert!(db.expire(&key, 100).unwrap());
+    let expired = db.version(&key).unwrap();
+    assert!(expired > created);
+    // 改名时版本号更新, 标记随之转移
+    let other = String::from("other");
+    db.rename(&key, &other).unwrap();
+    assert_eq!(None, db.version(&key));
+    assert!(db.version(&other).unwrap() > expired);
+    assert_eq!(Some(7), db.flags(&other));
+    // 覆盖 value 时清除标记
+    db.sets(&other, String::from("w")).unwrap();
+    assert_eq!(Some(0), db.flags(&other));
+
+    let set = String::from("set");
+    db.sadd(&set, vec![String::from("a")]).unwrap();
+    let added = db.version(&set).unwrap();
+    db.sadd(&set, vec![String::from("a")]).unwrap();
+    assert_eq!(Some(added), db.version(&set));
+    db.srem(&set, vec![String::from("a")]).unwrap();
+    assert!(db.version(&set).unwrap() > added);
+    db.del(vec![set.clone()]);
+    assert_eq!(None, db.version(&set));
+
+    db.flush();
+    assert_eq!(None, db.version(&other));
+}
+
+#[test]
+fn eviction_when_database_is_full() {
+    let mut db: KVDB = common::setup(Some(2));
+    let (a, b, c) = (String::from("a"), String::from("b"), String::from("c"));
+    db.sets(&a, String::from("v")).unwrap();
+    thread::sleep(Duration::from_millis(5));
+    db.sets(&b, String::from("v")).unwrap();
+    // 默认不淘汰, 但是可以覆盖已有的 key
+    assert_eq!(
+        Err(DBError::OutOfKeysSize(2)),
+        db.sets(&c, String::from("v"))
+    );
+    assert_eq!(Ok(DBOk::Ok), db.sets(&b, String::from("w")));
+    assert_eq!(Ok(Some(String::from("w"))), db.get(&b));
+    assert_eq!(0, db.keyspace_info().evicted_keys);
+
+    db.set_eviction_policy(EvictionPolicy::AllKeysLru);
+    thread::sleep(Duration::from_millis(5));
+    db.get(&a).unwrap();
+    assert_eq!(Ok(DBOk::Ok), db.sets(&c, String::from("v")));
+    assert!(db.exists(&a));
+    assert!(!db.exists(&b));
+    assert_eq!(1, db.keyspace_info().evicted_keys);
+    // 覆盖已有的 key 不会淘汰其他 key
+    assert_eq!(Ok(DBOk::Ok), db.sets(&c, String::from("w")));
+    assert_eq!(2, db.size());
+    assert_eq!(1, db.keyspace_info().evicted_keys);
+
+    // 先删除已经过期的 key
+    assert!(db.pexpire_at(&a, now_millis() - 1));
+    assert_eq!(1, db.size());
+    db.sadd(&b, vec![String::from("m")]).unwrap();
+    assert!(db.exists(&c));
+    assert_eq!(1, db.keyspace_info().evicted_keys);
+
+    db.set_eviction_policy(EvictionPolicy::AllKeysRandom);
+    db.hset(&a, String::from("f"), String::from("v")).unwrap();
+    assert!(db.exists(&a));
+    assert_eq!(2, db.size());
+    assert_eq!(2, db.keyspace_info().evicted_keys);
+
+    assert_eq!(
+        Some(EvictionPolicy::AllKeysLru),
+        EvictionPolicy::from_name("ALLKEYS-LRU")
+    );
+    assert_eq!("noeviction", EvictionPolicy::NoEviction.name());
+}
+
+#[test]
+fn active_expire_samples_keys_with_ttl() {
+    let mut db: KVDB = common::setup(None);
+    for i in 0..1000 {
+        let key = format!("stale:{}", i);
+        db.sets(&key, String::from("v")).unwrap();
+        assert!(db.pexpire_at(&key, now_millis() - 1));
+    }
+    for i in 0..5 {
+        let key = format!("live:{}", i);
+        db.sets(&key, String::from("v")).unwrap();
+        assert!(db.expire(&key, 100).unwrap());
+        db.sets(&format!("persistent:{}", i), String::from("v"))
+            .unwrap();
+    }
+
+    let deadline = Instant::now() + Duration::from_secs(10);
+    assert_eq!(1000, db.active_expire(deadline));
+    assert_eq!(10, db.size());
+    assert_eq!(5, db.keyspace_info().expires);
+    assert_eq!(1000, db.keyspace_info().expired_keys);
+    // 没有过期的 key 时什么也不做
+    assert_eq!(0, db.active_expire(Instant::now()));
+}
@@ -0,0 +1,192 @@
+/// 声明测试通用模块
+mod common;
+
+use dbcore::{glob_match, DBError, ValueType, KVDB};
+use std::collections::HashSet;
+
+fn fill_strings(db: &mut KVDB, prefix: &str, range: std::ops::Range<u32>) {
+    for x in range {
+        let key = format!("{}{}", prefix, x);
+        assert!(db.sets(&key, x.to_string()).is_ok());
+    }
+}
+
+#[test]
+fn glob_patterns() {
+    assert!(glob_match("*", "anything"));
+    assert!(glob_match("h?llo", "hello"));
+    assert!(!glob_match("h?llo", "hllo"));
+    assert!(glob_match("h*llo", "heeeello"));
+    assert!(glob_match("h[ae]llo", "hallo"));
+    assert!(!glob_match("h[ae]llo", "hillo"));
+    assert!(glob_match("h[^e]llo", "hallo"));
+    assert!(!glob_match("h[^e]llo", "hello"));
+    assert!(glob_match("h[a-b]llo", "hbllo"));
+    assert!(glob_match("h[b-a]llo", "hallo"));
+    assert!(glob_match("h\\*llo", "h*llo"));
+    assert!(!glob_match("h\\*llo", "hello"));
+    assert!(glob_match("user:*:name", "user:42:name"));
+    assert!(!glob_match("user:*:name", "user:42:age"));
+}
+
+#[test]
+fn glob_patterns_do_not_backtrack_exponentially() {
+    let key = "a".repeat(10_000);
+    assert!(!glob_match("*a*a*a*a*a*a*a*a*a*a*b", &key));
+    assert!(glob_match("*a*a*a*a*a*a*a*a*a*a*", &key));
+    assert!(glob_match("*a*?*[a-c]*a", &key));
+    assert!(!glob_match("a*", ""));
+    assert!(glob_match("**", ""));
+}
+
+#[test]
+fn keys_with_pattern() {
+    let mut db: KVDB = common::setup(None);
+    fill_strings(&mut db, "user:", 0..5);
+    fill_strings(&mut db, "order:", 0..3);
+
+    let mut keys = db.keys("user:*");
+    keys.sort();
+    assert_eq!(vec!["user:0", "user:1", "user:2", "user:3", "user:4"], keys);
+    assert_eq!(8, db.keys("*").len());
+    assert!(db.keys("nothing*").is_empty());
+}
+
+#[test]
+fn scan_returns_every_key_once() {
+    let mut db: KVDB = common::setup(None);
+    fill_strings(&mut db, "key:", 0..100);
+
+    let mut seen: Vec<String> = Vec::new();
+    let mut cursor = 0;
+    loop {
+        let (next, keys) = db.scan(cursor, None, 7, None);
+        seen.extend(keys);
+        if next == 0 {
+            break;
+        }
+        cursor = next;
+    }
+    let unique: HashSet<String> = seen.iter().cloned().collect();
+    assert_eq!(100, seen.len());
+    assert_eq!(100, unique.len());
+}
+
+#[test]
+fn scan_survives_rehash() {
+    let mut db: KVDB = common::setup(None);
+    fill_strings(&mut db, "stable:", 0..50);
+    fill_strings(&mut db, "gone:", 0..50);
+
+    let mut seen: HashSet<String> = HashSet::new();
+    let mut cursor = 0;
+    let mut round = 0;
+    loop {
+        let (next, keys) = db.scan(cursor, None, 5, None);
+        seen.extend(keys);
+        // 迭代过程中大量增删 key, 触发 HashMap 扩容与缩容
+        if round < 3 {
+            fill_strings(&mut db, &format!("new{}:", round), 0..200);
+        } else if round == 3 {
+            db.del(db.keys("gone:*"));
+            db.del(db.keys("new*"));
+        }
+        round += 1;
+        if next == 0 {
+            break;
+        }
+        cursor = next;
+    }
+    for x in 0..50 {
+        assert!(seen.contains(&format!("stable:{}", x)));
+    }
+}
+
+#[test]
+fn scan_match_and_type() {
+    let mut db: KVDB = common::setup(None);
+    fill_strings(&mut db, "str:", 0..10);
+    assert_eq!(
+        Ok(2),
+        db.sadd(
+            &String::from("set:a"),
+            vec![String::from("a"), String::from("b")]
+        )
+    );
+    assert_eq!(
+        Ok(1),
+        db.hset(
+            &String::from("hash:a"),
+            String::from("f"),
+            String::from("v")
+        )
+    );
+
+    let (next, keys) = db.scan(0, Some("str:*"), 1000, None);
+    assert_eq!(0, next);
+    assert_eq!(10, keys.len());
+
+    let (_, keys) = db.scan(0, None, 1000, Some(ValueType::Set));
+    assert_eq!(vec![String::from("set:a")], keys);
+
+    let (_, keys) = db.scan(0, Some("str:*"), 1000, Some(ValueType::Hash));
+    assert!(keys.is_empty());
+}
+
+#[test]
+fn type_and_randomkey() {
+    let mut db: KVDB = common::setup(None);
+    assert_eq!(None, db.randomkey());
+    let key = String::from("key");
+    assert_eq!(None, db.key_type(&key));
+
+    assert!(db.sets(&key, String::from("value")).is_ok());
+    assert_eq!(Some(ValueType::String), db.key_type(&key));
+    assert_eq!("string", ValueType::String.name());
+    assert_eq!(Some(ValueType::Hash), ValueType::from_name("HASH"));
+    assert_eq!(Some(key), db.randomkey());
+
+    fill_strings(&mut db, "other:", 0..10);
+    let random = db.randomkey().unwrap();
+    assert!(db.exists(&random));
+}
+
+#[test]
+fn sscan_and_hscan() {
+    let key = String::from("key");
+    let members: Vec<String> = (0..30).map(|x| format!("m{}", x)).collect();
+    let db: KVDB = common::setup_common_one_key_set(&key, &members);
+
+    let mut seen: HashSet<String> = HashSet::new();
+    let mut cursor = 0;
+    loop {
+        let (next, batch) = db.sscan(&key, cursor, None, 4).unwrap();
+        seen.extend(batch);
+        if next == 0 {
+            break;
+        }
+        cursor = next;
+    }
+    assert_eq!(30, seen.len());
+    assert_eq!(
+        Ok((0, Vec::new())),
+        db.sscan(&String::from("missing"), 0, None, 10)
+    );
+    assert_eq!(
+        Err(DBError::WrongValueType {
+            key: key.clone(),
+            expected: ValueType::Hash,
+            actual: ValueType::Set,
+        }),
+        db.hscan(&key, 0, None, 10)
+    );
+
+    let pairs: Vec<(String, String)> = vec![
+        (String::from("name"), String::from("memkv")),
+        (String::from("lang"), String::from("rust")),
+    ];
+    let db: KVDB = common::setup_common_one_key_hash(&key, &pairs);
+    let (next, batch) = db.hscan(&key, 0, Some("n*"), 10).unwrap();
+    assert_eq!(0, next);
+    assert_eq!(vec![(String::from("name"), String::from("memkv"))], batch);
+}
@@ -0,0 +1,131 @@
+use dbcore::{DBError, DBOk, Databases, Transaction, KVDB};
+
+fn s(value: &str) -> String {
+    String::from(value)
+}
+
+/// 事务中排队的命令：(数据库编号, key, 要追加的集合元素)
+type Command = (usize, String, String);
+
+fn sadd(dbs: &mut Databases, (db, key, member): Command) -> dbcore::Result<usize> {
+    dbs.db_mut(db)?.sadd(&key, vec![member])
+}
+
+#[test]
+fn queued_commands_run_on_exec() {
+    let mut dbs = Databases::new(2, None);
+    let mut tx: Transaction<Command> = Transaction::new();
+    assert!(!tx.in_multi());
+    assert!(!tx.queue((0, s("tags"), s("a"))));
+
+    tx.multi().unwrap();
+    assert!(matches!(tx.multi(), Err(DBError::Transaction(_))));
+    assert!(tx.queue((0, s("tags"), s("a"))));
+    assert!(tx.queue((0, s("tags"), s("a"))));
+    assert!(tx.queue((1, s("tags"), s("b"))));
+    assert_eq!(3, tx.len());
+    // 排队期间不执行
+    assert!(!dbs.db(0).unwrap().exists(&s("tags")));
+
+    assert_eq!(Ok(Some(vec![Ok(1), Ok(0), Ok(1)])), tx.exec(&mut dbs, sadd));
+    assert!(!tx.in_multi());
+    assert!(tx.is_empty());
+    assert!(matches!(
+        tx.exec(&mut dbs, sadd),
+        Err(DBError::Transaction(_))
+    ));
+    assert!(matches!(tx.discard(), Err(DBError::Transaction(_))));
+
+    // 丢弃排队的命令
+    tx.multi().unwrap();
+    tx.queue((0, s("tags"), s("c")));
+    tx.discard().unwrap();
+    assert!(!tx.in_multi());
+    assert_eq!(Ok(Some(1)), dbs.db(0).unwrap().slen(&s("tags")));
+}
+
+#[test]
+fn queueing_errors_abort_the_transaction() {
+    let mut dbs = Databases::new(1, None);
+    let mut tx: Transaction<Command> = Transaction::new();
+    tx.multi().unwrap();
+    tx.queue((0, s("tags"), s("a")));
+    tx.abort();
+    assert_eq!(Err(DBError::ExecAbort), tx.exec(&mut dbs, sadd));
+    assert!(!dbs.db(0).unwrap().exists(&s("tags")));
+
+    // 下一个事务不受影响
+    tx.multi().unwrap();
+    tx.queue((0, s("tags"), s("a")));
+    assert_eq!(Ok(Some(vec![Ok(1)])), tx.exec(&mut dbs, sadd));
+}
+
+#[test]
+fn watched_keys_abort_exec_when_modified() {
+    let mut dbs = Databases::new(2, None);
+    dbs.db_mut(0).unwrap().sets(&s("balance"), s("10")).unwrap();
+    let mut tx: Transaction<Command> = Transaction::new();
+
+    // 没有变化时正常执行
+    tx.watch(&dbs, 0, &s("balance")).unwrap();
+    tx.multi().unwrap();
+    assert!(matches!(
+        tx.watch(&dbs, 0, &s("balance")),
+        Err(DBError::Transaction(_))
+    ));
+    tx.queue((0, s("log"), s("a")));
+    assert_eq!(Ok(Some(vec![Ok(1)])), tx.exec(&mut dbs, sadd));
+    assert_eq!(0, tx.watched());
+
+    // 被修改之后放弃执行
+    tx.watch(&dbs, 0, &s("balance")).unwrap();
+    dbs.db_mut(0).unwrap().sets(&s("balance"), s("5")).unwrap();
+    assert!(tx.is_dirty(&dbs));
+    tx.multi().unwrap();
+    tx.queue((0, s("log"), s("b")));
+    assert_eq!(Ok(None), tx.exec(&mut dbs, sadd));
+    assert_eq!(Ok(Some(1)), dbs.db(0).unwrap().slen(&s("log")));
+
+    // 不存在的 key 被创建, 其他数据库中的同名 key 不受影响
+    tx.watch(&dbs, 1, &s("lock")).unwrap();
+    tx.watch(&dbs, 0, &s("missing")).unwrap();
+    dbs.db_mut(0).unwrap().sets(&s("lock"), s("1")).unwrap();
+    assert!(!tx.is_dirty(&dbs));
+    dbs.db_mut(1).unwrap().sets(&s("lock"), s("1")).unwrap();
+    assert!(tx.is_dirty(&dbs));
+    tx.unwatch();
+    assert!(!tx.is_dirty(&dbs));
+
+    // 交换数据库之后版本号不同
+    tx.watch(&dbs, 0, &s("lock")).unwrap();
+    dbs.swapdb(0, 1).unwrap();
+    assert!(tx.is_dirty(&dbs));
+    tx.multi().unwrap();
+    tx.discard().unwrap();
+    assert_eq!(0, tx.watched());
+}
+
+#[test]
+fn expired_watched_keys_abort_exec() {
+    let mut db = KVDB::default();
+    db.sets(&s("session"), s("token")).unwrap();
+    let mut tx: Transaction<String> = Transaction::new();
+    tx.watch(&db, 0, &s("session")).unwrap();
+    // 过期的 key 即使还没有被删除也视为被修改
+    db.pexpire_at(&s("session"), 1);
+    tx.multi().unwrap();
+    tx.queue(s("renewed"));
+    assert_eq!(
+        Ok(None),
+        tx.exec(&mut db, |db, value| db.sets(&s("session"), value))
+    );
+
+    // 单独的 KVDB 上执行
+    tx.watch(&db, 0, &s("session")).unwrap();
+    tx.multi().unwrap();
+    tx.queue(s("renewed"));
+    assert_eq!(
+        Ok(Some(vec![Ok(DBOk::Ok)])),
+        tx.exec(&mut db, |db, value| db.sets(&s("session"), value))
+    );
+}
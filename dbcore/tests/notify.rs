@@ -0,0 +1,236 @@
+use dbcore::{DBError, Databases, EventClass, EvictionPolicy, KeyspaceEvent, Subscriber};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+fn s(value: &str) -> String {
+    String::from(value)
+}
+
+/// 监听到的事件： (数据库编号, 事件名称, key)
+type Events = Mutex<Vec<(usize, String, String)>>;
+
+/// 注册收集事件的监听者, 返回收集的事件与监听者的编号
+fn record(dbs: &mut Databases) -> (Arc<Events>, u64) {
+    let events = Arc::new(Mutex::new(Vec::new()));
+    let recorded = events.clone();
+    let id = dbs.add_keyspace_listener(Arc::new(move |event: &KeyspaceEvent| {
+        recorded
+            .lock()
+            .unwrap()
+            .push((event.db, s(event.event), event.key.clone()));
+    }));
+    (events, id)
+}
+
+fn take(events: &Events) -> Vec<(usize, String, String)> {
+    std::mem::take(&mut *events.lock().unwrap())
+}
+
+fn event(db: usize, name: &str, key: &str) -> (usize, String, String) {
+    (db, s(name), s(key))
+}
+
+/// 缓冲区中所有消息的 (频道, 内容)
+fn drain(subscriber: &Subscriber) -> Vec<(String, String)> {
+    let mut messages = Vec::new();
+    while let Ok(Some(message)) = subscriber.try_recv() {
+        messages.push((message.channel, message.payload));
+    }
+    messages
+}
+
+#[test]
+fn parse_event_classes() {
+    let all = EventClass::parse("KEA").unwrap();
+    assert!(all.contains(EventClass::KEYSPACE | EventClass::SET | EventClass::EXPIRED));
+    assert_eq!("AKE", all.to_string());
+    assert_eq!(
+        EventClass::GENERIC | EventClass::STRING | EventClass::KEYSPACE,
+        EventClass::parse("K$g").unwrap()
+    );
+    assert_eq!("g$K", EventClass::parse("K$g").unwrap().to_string());
+    assert!(EventClass::parse("").unwrap().is_empty());
+    assert!(matches!(EventClass::parse("Kl"), Err(DBError::Syntax(_))));
+}
+
+#[test]
+fn events_are_published_to_keyspace_and_keyevent_channels() {
+    let mut dbs = Databases::new(2, None);
+    assert!(dbs.notify_keyspace_events().is_empty());
+    dbs.set_notify_keyspace_events(EventClass::parse("KEA").unwrap());
+    let subscriber = dbs.pubsub().subscriber();
+    subscriber.psubscribe("__keyspace@1__:*");
+    subscriber.subscribe("__keyevent@1__:del");
+
+    let db = dbs.db_mut(1).unwrap();
+    db.sets(&s("name"), s("memkv")).unwrap();
+    db.sadd(&s("tags"), vec![s("a"), s("b")]).unwrap();
+    db.hset(&s("user"), s("id"), s("1")).unwrap();
+    assert_eq!(2, db.del(vec![s("name"), s("user"), s("missing")]));
+    assert_eq!(
+        vec![
+            (s("__keyspace@1__:name"), s("set")),
+            (s("__keyspace@1__:tags"), s("sadd")),
+            (s("__keyspace@1__:user"), s("hset")),
+            (s("__keyspace@1__:name"), s("del")),
+            (s("__keyevent@1__:del"), s("name")),
+            (s("__keyspace@1__:user"), s("del")),
+            (s("__keyevent@1__:del"), s("user")),
+        ],
+        drain(&subscriber)
+    );
+
+    // 其他数据库的事件发布到其他频道
+    dbs.db_mut(0).unwrap().sets(&s("name"), s("memkv")).unwrap();
+    assert!(drain(&subscriber).is_empty());
+
+    // 只启用 K 时不发布到 keyevent 频道
+    dbs.set_notify_keyspace_events(EventClass::parse("Kg").unwrap());
+    dbs.db_mut(1).unwrap().del(vec![s("tags")]);
+    assert_eq!(
+        vec![(s("__keyspace@1__:tags"), s("del"))],
+        drain(&subscriber)
+    );
+}
+
+#[test]
+fn listeners_receive_enabled_classes() {
+    let mut dbs = Databases::new(1, None);
+    let (events, id) = record(&mut dbs);
+    let key = s("k");
+
+    // 默认不启用任何类别
+    dbs.db_mut(0).unwrap().sets(&key, s("v")).unwrap();
+    assert!(take(&events).is_empty());
+
+    // 监听者不需要启用 K 或 E
+    dbs.set_notify_keyspace_events(EventClass::SET | EventClass::HASH);
+    let db = dbs.db_mut(0).unwrap();
+    db.sets(&key, s("v")).unwrap();
+    db.sadd(&s("set"), vec![s("a"), s("b"), s("c")]).unwrap();
+    db.sadd(&s("set"), vec![s("a")]).unwrap();
+    db.srem(&s("set"), vec![s("a")]).unwrap();
+    db.spop(&s("set")).unwrap();
+    db.hmset(&s("hash"), vec![(s("f"), s("1")), (s("g"), s("2"))])
+        .unwrap();
+    db.hdel(&s("hash"), &s("f")).unwrap();
+    db.hdel(&s("hash"), &s("missing")).unwrap();
+    assert_eq!(
+        vec![
+            event(0, "sadd", "set"),
+            event(0, "srem", "set"),
+            event(0, "spop", "set"),
+            event(0, "hset", "hash"),
+            event(0, "hdel", "hash"),
+        ],
+        take(&events)
+    );
+
+    assert!(dbs.remove_keyspace_listener(id));
+    assert!(!dbs.remove_keyspace_listener(id));
+    dbs.db_mut(0)
+        .unwrap()
+        .sadd(&s("set"), vec![s("z")])
+        .unwrap();
+    assert!(take(&events).is_empty());
+}
+
+#[test]
+fn generic_events() {
+    let mut dbs = Databases::new(2, None);
+    dbs.set_notify_keyspace_events(EventClass::parse("A").unwrap());
+    let (events, _) = record(&mut dbs);
+
+    let db = dbs.db_mut(0).unwrap();
+    db.set(&s("a"), s("1"), false, false, Some(100)).unwrap();
+    db.persist(&s("a"));
+    db.expire(&s("a"), 100).unwrap();
+    db.rename(&s("a"), &s("b")).unwrap();
+    db.copy(&s("b"), &s("c"), false).unwrap();
+    let payload = db.dump(&s("c")).unwrap();
+    db.restore(&s("d"), None, &payload, false).unwrap();
+    assert_eq!(1, db.unlink(vec![s("d")]));
+    assert_eq!(Ok(true), dbs.move_key(&s("c"), 0, 1));
+    assert_eq!(
+        vec![
+            event(0, "set", "a"),
+            event(0, "expire", "a"),
+            event(0, "persist", "a"),
+            event(0, "expire", "a"),
+            event(0, "rename_from", "a"),
+            event(0, "rename_to", "b"),
+            event(0, "copy_to", "c"),
+            event(0, "restore", "d"),
+            event(0, "del", "d"),
+            event(0, "move_from", "c"),
+            event(1, "move_to", "c"),
+        ],
+        take(&events)
+    );
+
+    // 交换之后事件使用新的编号
+    dbs.swapdb(0, 1).unwrap();
+    dbs.db_mut(0).unwrap().del(vec![s("c")]);
+    dbs.db_mut(1).unwrap().del(vec![s("b")]);
+    assert_eq!(
+        vec![event(0, "del", "c"), event(1, "del", "b")],
+        take(&events)
+    );
+}
+
+#[test]
+fn expired_keys_are_notified() {
+    let mut dbs = Databases::new(1, None);
+    dbs.set_notify_keyspace_events(EventClass::parse("Ex").unwrap());
+    let subscriber = dbs.pubsub().subscriber();
+    subscriber.subscribe("__keyevent@0__:expired");
+    let (events, _) = record(&mut dbs);
+
+    let db = dbs.db_mut(0).unwrap();
+    for key in ["a", "b", "c"].iter() {
+        db.sets(&s(key), s("v")).unwrap();
+    }
+    db.pexpire_at(&s("a"), 1);
+    db.pexpire_at(&s("b"), 1);
+    // 修改时删除已经过期的 key
+    assert_eq!(1, db.sadd(&s("b"), vec![s("m")]).unwrap());
+    assert_eq!(vec![event(0, "expired", "b")], take(&events));
+    // 定期删除已经过期的 key
+    assert_eq!(1, db.purge_expired());
+    assert_eq!(vec![event(0, "expired", "a")], take(&events));
+    // 删除已经过期的 key 只产生 expired 事件
+    db.pexpire_at(&s("c"), 1);
+    assert_eq!(0, db.del(vec![s("c")]));
+    assert_eq!(vec![event(0, "expired", "c")], take(&events));
+    assert_eq!(
+        vec![
+            (s("__keyevent@0__:expired"), s("b")),
+            (s("__keyevent@0__:expired"), s("a")),
+            (s("__keyevent@0__:expired"), s("c")),
+        ],
+        drain(&subscriber)
+    );
+}
+
+#[test]
+fn evicted_keys_are_notified() {
+    let mut dbs = Databases::with_key_sizes(vec![Some(2)]);
+    dbs.set_eviction_policy(EvictionPolicy::AllKeysLru);
+    dbs.set_notify_keyspace_events(EventClass::parse("Ee").unwrap());
+    let subscriber = dbs.pubsub().subscriber();
+    subscriber.subscribe("__keyevent@0__:evicted");
+    let (events, _) = record(&mut dbs);
+
+    let db = dbs.db_mut(0).unwrap();
+    db.sets(&s("a"), s("v")).unwrap();
+    thread::sleep(Duration::from_millis(5));
+    db.sets(&s("b"), s("v")).unwrap();
+    thread::sleep(Duration::from_millis(5));
+    db.sets(&s("c"), s("v")).unwrap();
+    assert_eq!(vec![event(0, "evicted", "a")], take(&events));
+    assert_eq!(
+        vec![(s("__keyevent@0__:evicted"), s("a"))],
+        drain(&subscriber)
+    );
+}
@@ -0,0 +1,194 @@
+use dbcore::{Databases, Message, PubSub, RecvError};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc};
+use std::thread;
+use std::time::Duration;
+
+fn message(pattern: Option<&str>, channel: &str, payload: &str, sharded: bool) -> Message {
+    Message {
+        pattern: pattern.map(String::from),
+        channel: String::from(channel),
+        payload: String::from(payload),
+        sharded,
+    }
+}
+
+#[test]
+fn channels_and_patterns() {
+    let pubsub = PubSub::new();
+    let subscriber = pubsub.subscriber();
+    assert_eq!(1, subscriber.subscribe("news.tech"));
+    assert_eq!(1, subscriber.subscribe("news.tech"));
+    assert_eq!(2, subscriber.psubscribe("news.*"));
+    assert_eq!(0, pubsub.publish("sports", "goal"));
+
+    // 同时匹配频道与模式时收到两条消息
+    assert_eq!(2, pubsub.publish("news.tech", "rust"));
+    assert_eq!(1, pubsub.publish("news.art", "paint"));
+    assert_eq!(
+        Ok(message(None, "news.tech", "rust", false)),
+        subscriber.recv()
+    );
+    assert_eq!(
+        Ok(message(Some("news.*"), "news.tech", "rust", false)),
+        subscriber.recv()
+    );
+    assert_eq!(
+        Ok(Some(message(Some("news.*"), "news.art", "paint", false))),
+        subscriber.try_recv()
+    );
+    assert_eq!(Ok(None), subscriber.try_recv());
+
+    assert_eq!(1, subscriber.unsubscribe("news.tech"));
+    assert_eq!(0, subscriber.punsubscribe("news.*"));
+    assert!(!subscriber.is_subscribed());
+    assert_eq!(0, pubsub.publish("news.tech", "rust"));
+}
+
+#[test]
+fn sharded_channels_are_a_separate_namespace() {
+    let pubsub = PubSub::new();
+    let subscriber = pubsub.subscriber();
+    assert_eq!(1, subscriber.ssubscribe("orders"));
+    assert_eq!(1, subscriber.psubscribe("*"));
+
+    assert_eq!(1, pubsub.spublish("orders", "created"));
+    assert_eq!(
+        Ok(message(None, "orders", "created", true)),
+        subscriber.recv()
+    );
+    // 普通频道的消息只匹配模式订阅, 分片频道的消息不匹配模式
+    assert_eq!(1, pubsub.publish("orders", "plain"));
+    assert_eq!(
+        Ok(message(Some("*"), "orders", "plain", false)),
+        subscriber.recv()
+    );
+    assert_eq!(0, pubsub.spublish("payments", "created"));
+
+    assert_eq!(vec!["orders"], pubsub.shard_channels(None));
+    assert!(pubsub.channels(None).is_empty());
+    assert_eq!(1, pubsub.shard_numsub("orders"));
+    assert_eq!(0, pubsub.numsub("orders"));
+    assert_eq!(0, subscriber.sunsubscribe("orders"));
+}
+
+#[test]
+fn introspection() {
+    let pubsub = PubSub::new();
+    let first = pubsub.subscriber();
+    let second = pubsub.subscriber();
+    assert_ne!(first.id(), second.id());
+    for channel in ["a.1", "a.2", "b.1"].iter() {
+        first.subscribe(channel);
+    }
+    second.subscribe("a.1");
+    first.psubscribe("a.*");
+    second.psubscribe("a.*");
+    second.psubscribe("b.*");
+
+    assert_eq!(vec!["a.1", "a.2", "b.1"], pubsub.channels(None));
+    assert_eq!(vec!["a.1", "a.2"], pubsub.channels(Some("a.*")));
+    assert_eq!(2, pubsub.numsub("a.1"));
+    assert_eq!(1, pubsub.numsub("b.1"));
+    assert_eq!(0, pubsub.numsub("c.1"));
+    assert_eq!(2, pubsub.numpat());
+    assert_eq!(vec!["a.1", "a.2", "b.1"], first.channels());
+    assert_eq!(vec!["a.*", "b.*"], second.patterns());
+
+    // drop 时取消所有订阅
+    drop(first);
+    assert_eq!(vec!["a.1"], pubsub.channels(None));
+    assert_eq!(2, pubsub.numpat());
+    second.close();
+    assert!(pubsub.channels(None).is_empty());
+    assert_eq!(0, pubsub.numpat());
+}
+
+#[test]
+fn slow_subscribers_are_disconnected() {
+    let pubsub = PubSub::new();
+    pubsub.set_subscriber_buffer(2);
+    let slow = pubsub.subscriber();
+    let fast = pubsub.subscriber();
+    slow.subscribe("events");
+    fast.subscribe("events");
+    let overflowed = Arc::new(AtomicBool::new(false));
+    let flag = overflowed.clone();
+    slow.on_overflow(move || flag.store(true, Ordering::SeqCst));
+
+    assert_eq!(2, pubsub.publish("events", "1"));
+    assert_eq!(Ok(message(None, "events", "1", false)), fast.recv());
+    assert_eq!(2, pubsub.publish("events", "2"));
+    assert_eq!(Ok(message(None, "events", "2", false)), fast.recv());
+    assert!(!overflowed.load(Ordering::SeqCst));
+
+    // 第三条消息超出 slow 的缓冲区
+    assert_eq!(1, pubsub.publish("events", "3"));
+    assert!(overflowed.load(Ordering::SeqCst));
+    assert_eq!(Err(RecvError::Overflow), slow.recv());
+    assert_eq!(Err(RecvError::Overflow), slow.try_recv());
+    assert_eq!(1, pubsub.publish("events", "4"));
+    assert_eq!(Ok(message(None, "events", "3", false)), fast.recv());
+
+    // 已经溢出时立即调用
+    let late = Arc::new(AtomicBool::new(false));
+    let flag = late.clone();
+    slow.on_overflow(move || flag.store(true, Ordering::SeqCst));
+    assert!(late.load(Ordering::SeqCst));
+}
+
+#[test]
+fn subscribers_receive_in_other_threads() {
+    let pubsub = PubSub::new();
+    let subscriber = Arc::new(pubsub.subscriber());
+    subscriber.subscribe("jobs");
+    let receiver = subscriber.clone();
+    let (done, received) = mpsc::channel();
+    let worker = thread::spawn(move || {
+        let mut payloads = Vec::new();
+        loop {
+            match receiver.recv() {
+                Ok(message) => payloads.push(message.payload),
+                Err(e) => return (payloads, e),
+            }
+            if payloads.len() == 100 {
+                done.send(()).unwrap();
+            }
+        }
+    });
+    let publisher = pubsub.clone();
+    thread::spawn(move || {
+        for i in 0..100 {
+            assert_eq!(1, publisher.publish("jobs", &i.to_string()));
+        }
+    })
+    .join()
+    .unwrap();
+    received.recv_timeout(Duration::from_secs(10)).unwrap();
+
+    // 关闭之后等待消息的线程收到 Closed
+    subscriber.close();
+    let (payloads, error) = worker.join().unwrap();
+    assert_eq!(RecvError::Closed, error);
+    let expected: Vec<String> = (0..100).map(|i| i.to_string()).collect();
+    assert_eq!(expected, payloads);
+    assert_eq!(0, pubsub.publish("jobs", "late"));
+}
+
+#[test]
+fn info_counts_subscriptions() {
+    let dbs = Databases::new(1, None);
+    let subscriber = dbs.pubsub().subscriber();
+    subscriber.subscribe("a");
+    subscriber.subscribe("b");
+    subscriber.psubscribe("c*");
+    subscriber.ssubscribe("d");
+    let info = dbs.info();
+    assert_eq!(2, info.pubsub.channels);
+    assert_eq!(1, info.pubsub.patterns);
+    assert_eq!(1, info.pubsub.shard_channels);
+    let stats = info.render(Some("stats"));
+    assert!(stats.contains("pubsub_channels:2\r\n"), "{}", stats);
+    assert!(stats.contains("pubsub_patterns:1\r\n"), "{}", stats);
+    assert!(stats.contains("pubsub_shardchannels:1\r\n"), "{}", stats);
+}
@@ -0,0 +1,513 @@
+use dbcore::{DBError, DBOk, Databases, SkippedKey, KVDB};
+use std::collections::HashSet;
+use std::convert::TryFrom;
+use std::fs;
+use std::iter::FromIterator;
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_millis() as u64
+}
+
+/// 每个测试使用独立的 RDB 文件
+fn rdb_path(name: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("memkv-{}-{}.rdb", name, std::process::id()));
+    let _ = fs::remove_file(&path);
+    path
+}
+
+fn s(value: &str) -> String {
+    String::from(value)
+}
+
+/// Redis 使用的 CRC64 (Jones 多项式, 反射)
+fn crc64(data: &[u8]) -> u64 {
+    let mut crc = 0u64;
+    for byte in data {
+        crc ^= u64::from(*byte);
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ 0x95ac_9329_ac4b_c9b5
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    crc
+}
+
+/// 按照 Redis 的格式手工构造 RDB 文件
+struct Rdb {
+    buf: Vec<u8>,
+}
+
+/// ziplist / listpack 中的元素
+enum Item<'a> {
+    Str(&'a str),
+    Int(i64),
+}
+
+impl Rdb {
+    fn new(version: u32) -> Self {
+        Rdb {
+            buf: format!("REDIS{:04}", version).into_bytes(),
+        }
+    }
+
+    fn len(&mut self, len: u64) -> &mut Self {
+        if len < 64 {
+            self.buf.push(len as u8);
+        } else if len < 16384 {
+            self.buf.push(0x40 | (len >> 8) as u8);
+            self.buf.push(len as u8);
+        } else {
+            self.buf.push(0x80);
+            self.buf.extend_from_slice(&(len as u32).to_be_bytes());
+        }
+        self
+    }
+
+    fn bytes(&mut self, bytes: &[u8]) -> &mut Self {
+        self.len(bytes.len() as u64);
+        self.buf.extend_from_slice(bytes);
+        self
+    }
+
+    fn raw(&mut self, bytes: &[u8]) -> &mut Self {
+        self.buf.extend_from_slice(bytes);
+        self
+    }
+
+    fn str(&mut self, s: &str) -> &mut Self {
+        self.bytes(s.as_bytes())
+    }
+
+    fn aux(&mut self, key: &str, value: &str) -> &mut Self {
+        self.raw(&[0xfa]).str(key).str(value)
+    }
+
+    fn select(&mut self, db: u64) -> &mut Self {
+        self.raw(&[0xfe]).len(db).raw(&[0xfb]).len(1).len(0)
+    }
+
+    fn expire_ms(&mut self, when: u64) -> &mut Self {
+        self.raw(&[0xfc]).raw(&when.to_le_bytes())
+    }
+
+    fn expire_secs(&mut self, when: u32) -> &mut Self {
+        self.raw(&[0xfd]).raw(&when.to_le_bytes())
+    }
+
+    fn key(&mut self, value_type: u8, key: &str) -> &mut Self {
+        self.raw(&[value_type]).str(key)
+    }
+
+    fn finish(&mut self, checksum: bool) -> Vec<u8> {
+        self.buf.push(0xff);
+        let crc = if checksum { crc64(&self.buf) } else { 0 };
+        self.buf.extend_from_slice(&crc.to_le_bytes());
+        self.buf.clone()
+    }
+}
+
+fn ziplist(items: &[Item]) -> Vec<u8> {
+    let mut entries = Vec::new();
+    let mut prevlen = 0;
+    for item in items {
+        let mut entry = vec![prevlen as u8];
+        match item {
+            Item::Str(s) => {
+                entry.push(s.len() as u8);
+                entry.extend_from_slice(s.as_bytes());
+            }
+            Item::Int(n) if (0..=12).contains(n) => entry.push(0xf1 + *n as u8),
+            Item::Int(n) if i8::try_from(*n).is_ok() => entry.extend(&[0xfe, *n as u8]),
+            Item::Int(n) if i16::try_from(*n).is_ok() => {
+                entry.push(0xc0);
+                entry.extend_from_slice(&(*n as i16).to_le_bytes());
+            }
+            Item::Int(n) if (-(1 << 23)..(1 << 23)).contains(n) => {
+                entry.push(0xf0);
+                entry.extend_from_slice(&(*n as i32).to_le_bytes()[..3]);
+            }
+            Item::Int(n) if i32::try_from(*n).is_ok() => {
+                entry.push(0xd0);
+                entry.extend_from_slice(&(*n as i32).to_le_bytes());
+            }
+            Item::Int(n) => {
+                entry.push(0xe0);
+                entry.extend_from_slice(&n.to_le_bytes());
+            }
+        }
+        prevlen = entry.len();
+        entries.extend(entry);
+    }
+    let mut blob = Vec::new();
+    blob.extend_from_slice(&((11 + entries.len()) as u32).to_le_bytes());
+    blob.extend_from_slice(&0u32.to_le_bytes());
+    blob.extend_from_slice(&(items.len() as u16).to_le_bytes());
+    blob.extend(entries);
+    blob.push(0xff);
+    blob
+}
+
+fn listpack(items: &[Item]) -> Vec<u8> {
+    let mut entries = Vec::new();
+    for item in items {
+        let mut entry = Vec::new();
+        match item {
+            Item::Str(s) if s.len() < 64 => {
+                entry.push(0x80 | s.len() as u8);
+                entry.extend_from_slice(s.as_bytes());
+            }
+            Item::Str(s) => {
+                entry.push(0xe0 | (s.len() >> 8) as u8);
+                entry.push(s.len() as u8);
+                entry.extend_from_slice(s.as_bytes());
+            }
+            Item::Int(n) if (0..128).contains(n) => entry.push(*n as u8),
+            Item::Int(n) if (-4096..4096).contains(n) => {
+                let raw = (*n as u16) & 0x1fff;
+                entry.push(0xc0 | (raw >> 8) as u8);
+                entry.push(raw as u8);
+            }
+            Item::Int(n) if i16::try_from(*n).is_ok() => {
+                entry.push(0xf1);
+                entry.extend_from_slice(&(*n as i16).to_le_bytes());
+            }
+            Item::Int(n) if (-(1 << 23)..(1 << 23)).contains(n) => {
+                entry.push(0xf2);
+                entry.extend_from_slice(&(*n as i32).to_le_bytes()[..3]);
+            }
+            Item::Int(n) if i32::try_from(*n).is_ok() => {
+                entry.push(0xf3);
+                entry.extend_from_slice(&(*n as i32).to_le_bytes());
+            }
+            Item::Int(n) => {
+                entry.push(0xf4);
+                entry.extend_from_slice(&n.to_le_bytes());
+            }
+        }
+        // backlen 从后往前读, 这里只需要占用正确的字节数
+        let backlen = if entry.len() < 128 { 1 } else { 2 };
+        entry.resize(entry.len() + backlen, 0);
+        entries.extend(entry);
+    }
+    let mut blob = Vec::new();
+    blob.extend_from_slice(&((7 + entries.len()) as u32).to_le_bytes());
+    blob.extend_from_slice(&(items.len() as u16).to_le_bytes());
+    blob.extend(entries);
+    blob.push(0xff);
+    blob
+}
+
+fn intset(width: u32, members: &[i64]) -> Vec<u8> {
+    let mut blob = Vec::new();
+    blob.extend_from_slice(&width.to_le_bytes());
+    blob.extend_from_slice(&(members.len() as u32).to_le_bytes());
+    for member in members {
+        blob.extend_from_slice(&member.to_le_bytes()[..width as usize]);
+    }
+    blob
+}
+
+fn set_of(members: &[&str]) -> Option<HashSet<String>> {
+    Some(HashSet::from_iter(members.iter().map(|m| s(m))))
+}
+
+/// 包含所有支持的类型与编码的 RDB 文件
+fn every_encoding() -> Vec<u8> {
+    let mut rdb = Rdb::new(11);
+    rdb.aux("redis-ver", "7.2.4").aux("redis-bits", "64");
+    rdb.select(0);
+    rdb.key(0, "string").str("中文 value");
+    rdb.key(0, "int8").raw(&[0xc0, 0x85]);
+    rdb.key(0, "int16").raw(&[0xc1]).raw(&1000i16.to_le_bytes());
+    rdb.key(0, "int32")
+        .raw(&[0xc2])
+        .raw(&(-70000i32).to_le_bytes());
+    // "abcabcabcabc" = 字面量 "abc" + 回溯 3 字节复制 9 字节
+    rdb.key(0, "lzf")
+        .raw(&[0xc3])
+        .len(7)
+        .len(12)
+        .raw(&[0x02, b'a', b'b', b'c', 0xe0, 0x00, 0x02]);
+    rdb.key(2, "set").len(2).str("a").str("b");
+    rdb.key(11, "intset").bytes(&intset(2, &[-3, 7]));
+    rdb.key(11, "intset64").bytes(&intset(8, &[1 << 40]));
+    rdb.key(20, "set-listpack").bytes(&listpack(&[
+        Item::Str("x"),
+        Item::Int(5),
+        Item::Int(-1000),
+        Item::Int(30000),
+        Item::Int(-5_000_000),
+        Item::Int(1 << 31),
+        Item::Int(-(1 << 40)),
+        Item::Str(&"y".repeat(100)),
+    ]));
+    rdb.key(4, "hash")
+        .len(2)
+        .str("f1")
+        .str("v1")
+        .str("f2")
+        .str("");
+    rdb.key(13, "hash-ziplist").bytes(&ziplist(&[
+        Item::Str("name"),
+        Item::Str("memkv"),
+        Item::Str("small"),
+        Item::Int(12),
+        Item::Str("i8"),
+        Item::Int(-100),
+        Item::Str("i16"),
+        Item::Int(1000),
+        Item::Str("i24"),
+        Item::Int(-100_000),
+        Item::Str("i32"),
+        Item::Int(100_000_000),
+        Item::Str("i64"),
+        Item::Int(1 << 40),
+    ]));
+    rdb.key(16, "hash-listpack")
+        .bytes(&listpack(&[Item::Str("f"), Item::Int(42)]));
+    // zipmap: zmlen, (len key len free value)*, 0xff
+    rdb.key(9, "zipmap")
+        .bytes(&[1, 1, b'k', 2, 1, b'v', b'w', 0, 0xff]);
+    rdb.expire_ms(now_millis() + 100_000)
+        .key(0, "volatile")
+        .str("v");
+    rdb.expire_secs(1000).key(0, "expired").str("v");
+    rdb.key(1, "list").len(2).str("a").str("b");
+    rdb.key(10, "list-ziplist")
+        .bytes(&ziplist(&[Item::Str("a"), Item::Int(1)]));
+    rdb.key(14, "quicklist")
+        .len(1)
+        .bytes(&ziplist(&[Item::Str("a")]));
+    rdb.key(18, "quicklist2")
+        .len(2)
+        .len(2)
+        .bytes(&listpack(&[Item::Str("a"), Item::Int(1)]))
+        .len(1)
+        .str("plain");
+    rdb.key(3, "zset")
+        .len(3)
+        .str("a")
+        .raw(&[3])
+        .raw(b"1.5")
+        .str("b")
+        .raw(&[254])
+        .str("c")
+        .raw(&[253]);
+    rdb.key(5, "zset2")
+        .len(1)
+        .str("a")
+        .raw(&1.5f64.to_le_bytes());
+    rdb.key(12, "zset-ziplist")
+        .bytes(&ziplist(&[Item::Str("a"), Item::Int(1)]));
+    rdb.key(17, "zset-listpack")
+        .bytes(&listpack(&[Item::Str("a"), Item::Int(1)]));
+    rdb.key(2, "binary-member").len(2).str("a").bytes(&[0xff]);
+    rdb.raw(&[0xf8]).len(10).raw(&[0xf9, 3]);
+    rdb.raw(&[0]).bytes(&[0xc3, 0x28]).str("binary key");
+    rdb.select(3);
+    rdb.key(0, "string").str("db3");
+    rdb.finish(true)
+}
+
+#[test]
+fn import_every_encoding() {
+    let path = rdb_path("every-encoding");
+    fs::write(&path, every_encoding()).unwrap();
+    let mut dbs = Databases::new(4, None);
+    assert_eq!(
+        Ok(DBOk::Ok),
+        dbs.db_mut(0).unwrap().sets(&s("string"), s("old"))
+    );
+    assert_eq!(
+        Ok(DBOk::Ok),
+        dbs.db_mut(0).unwrap().sets(&s("untouched"), s("v"))
+    );
+
+    let report = dbs.import_rdb(&path).unwrap();
+    assert_eq!(11, report.version);
+    assert_eq!(15, report.imported);
+    assert_eq!(1, report.expired);
+    let skipped: Vec<(String, String)> = report
+        .skipped
+        .iter()
+        .map(|k| (k.key.clone(), k.reason.clone()))
+        .collect();
+    let mut expected_skipped = vec![];
+    for (key, kind) in [
+        ("list", "list"),
+        ("list-ziplist", "list"),
+        ("quicklist", "list"),
+        ("quicklist2", "list"),
+        ("zset", "zset"),
+        ("zset2", "zset"),
+        ("zset-ziplist", "zset"),
+        ("zset-listpack", "zset"),
+    ]
+    .iter()
+    {
+        expected_skipped.push((s(key), format!("{} value in memkv is not supported", kind)));
+    }
+    expected_skipped.push((s("binary-member"), s("binary value is not supported")));
+    expected_skipped.push((s("\u{fffd}("), s("key is not valid UTF-8")));
+    assert_eq!(expected_skipped, skipped);
+
+    let db = dbs.db(0).unwrap();
+    assert_eq!(Ok(Some(s("中文 value"))), db.get(&s("string")));
+    assert_eq!(Ok(Some(s("v"))), db.get(&s("untouched")));
+    assert_eq!(Ok(Some(s("-123"))), db.get(&s("int8")));
+    assert_eq!(Ok(Some(s("1000"))), db.get(&s("int16")));
+    assert_eq!(Ok(Some(s("-70000"))), db.get(&s("int32")));
+    assert_eq!(Ok(Some(s("abcabcabcabc"))), db.get(&s("lzf")));
+    assert_eq!(Ok(set_of(&["a", "b"])), db.smembers(&s("set")));
+    assert_eq!(Ok(set_of(&["-3", "7"])), db.smembers(&s("intset")));
+    assert_eq!(Ok(set_of(&["1099511627776"])), db.smembers(&s("intset64")));
+    let long = "y".repeat(100);
+    assert_eq!(
+        Ok(set_of(&[
+            "x",
+            "5",
+            "-1000",
+            "30000",
+            "-5000000",
+            "2147483648",
+            "-1099511627776",
+            &long
+        ])),
+        db.smembers(&s("set-listpack"))
+    );
+    assert_eq!(Ok(Some(s(""))), db.hget(&s("hash"), &s("f2")));
+    for (field, value) in [
+        ("name", "memkv"),
+        ("small", "12"),
+        ("i8", "-100"),
+        ("i16", "1000"),
+        ("i24", "-100000"),
+        ("i32", "100000000"),
+        ("i64", "1099511627776"),
+    ]
+    .iter()
+    {
+        assert_eq!(Ok(Some(s(value))), db.hget(&s("hash-ziplist"), &s(field)));
+    }
+    assert_eq!(Ok(Some(s("42"))), db.hget(&s("hash-listpack"), &s("f")));
+    assert_eq!(Ok(Some(s("vw"))), db.hget(&s("zipmap"), &s("k")));
+    let ttl = db.ttl(&s("volatile"));
+    assert!(ttl > 90 && ttl <= 100);
+    assert_eq!(-1, db.ttl(&s("string")));
+    assert_eq!(false, db.exists(&s("expired")));
+    assert_eq!(false, db.exists(&s("list")));
+    assert_eq!(Ok(Some(s("db3"))), dbs.db(3).unwrap().get(&s("string")));
+    let _ = fs::remove_file(&path);
+}
+
+#[test]
+fn import_into_single_db() {
+    let data = every_encoding();
+    let mut db = KVDB::new(None);
+    let report = db.import_rdb(&data, 3).unwrap();
+    assert_eq!(1, report.imported);
+    assert_eq!(0, report.expired);
+    assert_eq!(Vec::<SkippedKey>::new(), report.skipped);
+    assert_eq!(Ok(Some(s("db3"))), db.get(&s("string")));
+    assert_eq!(1, db.size());
+
+    let mut small = KVDB::new(Some(3));
+    assert_eq!(Err(DBError::OutOfKeysSize(3)), small.import_rdb(&data, 0));
+    assert_eq!(0, small.size(), "failed import keeps data");
+}
+
+#[test]
+fn checksum_is_verified() {
+    let mut data = every_encoding();
+    let mut db = KVDB::new(None);
+    let middle = data.len() / 2;
+    data[middle] ^= 0xff;
+    assert_eq!(
+        Err(DBError::InvalidPayload(s("RDB checksum mismatch"))),
+        db.import_rdb(&data, 0)
+    );
+
+    let mut rdb = Rdb::new(11);
+    rdb.key(0, "a").str("1");
+    let mut data = rdb.finish(true);
+    let last = data.len() - 1;
+    data[last] ^= 1;
+    assert_eq!(
+        Err(DBError::InvalidPayload(s("RDB checksum mismatch"))),
+        db.import_rdb(&data, 0)
+    );
+
+    // 校验和为 0 表示保存时关闭了校验, 版本 5 之前没有校验和
+    let mut rdb = Rdb::new(11);
+    rdb.key(0, "a").str("1");
+    assert_eq!(1, db.import_rdb(&rdb.finish(false), 0).unwrap().imported);
+    let mut data = Rdb::new(4);
+    data.key(0, "b").str("2").raw(&[0xff]);
+    assert_eq!(1, db.import_rdb(&data.buf, 0).unwrap().imported);
+    assert_eq!(Ok(Some(s("2"))), db.get(&s("b")));
+
+    data.buf.pop();
+    assert!(matches!(
+        db.import_rdb(&data.buf, 0),
+        Err(DBError::InvalidPayload(_))
+    ));
+    assert!(matches!(
+        db.import_rdb(b"not an rdb file", 0),
+        Err(DBError::InvalidPayload(_))
+    ));
+}
+
+#[test]
+fn unsupported_types_are_reported() {
+    let mut db = KVDB::new(None);
+    let mut rdb = Rdb::new(11);
+    rdb.key(0, "a").str("1");
+    rdb.key(15, "events").str("stream data");
+    assert_eq!(
+        Err(DBError::NotSupported(s("RDB stream value (key `events`)"))),
+        db.import_rdb(&rdb.finish(true), 0)
+    );
+    assert_eq!(0, db.size());
+
+    let mut rdb = Rdb::new(11);
+    rdb.key(7, "module").str("data");
+    assert!(matches!(
+        db.import_rdb(&rdb.finish(true), 0),
+        Err(DBError::NotSupported(_))
+    ));
+    assert_eq!(
+        Err(DBError::NotSupported(s("RDB version 13"))),
+        db.import_rdb(&Rdb::new(13).finish(true), 0)
+    );
+    let mut rdb = Rdb::new(11);
+    rdb.key(99, "unknown").str("data");
+    assert_eq!(
+        Err(DBError::InvalidPayload(s("RDB unknown value type 99"))),
+        db.import_rdb(&rdb.finish(true), 0)
+    );
+}
+
+#[test]
+fn import_checks_databases() {
+    let path = rdb_path("checks");
+    fs::write(&path, every_encoding()).unwrap();
+    let mut fewer = Databases::new(2, None);
+    assert_eq!(Err(DBError::DBIndexOutOfRange(3)), fewer.import_rdb(&path));
+    let mut small = Databases::new(4, Some(10));
+    assert_eq!(Ok(DBOk::Ok), small.db_mut(3).unwrap().sets(&s("k"), s("v")));
+    assert_eq!(Err(DBError::OutOfKeysSize(10)), small.import_rdb(&path));
+    assert_eq!(1, small.db(3).unwrap().size(), "failed import keeps data");
+    assert!(matches!(
+        small.import_rdb(rdb_path("missing")),
+        Err(DBError::Io(_))
+    ));
+    let _ = fs::remove_file(&path);
+}
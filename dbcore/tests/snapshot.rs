@@ -0,0 +1,206 @@
+use dbcore::{DBError, DBOk, Databases, SaveRule};
+use std::collections::HashSet;
+use std::fs;
+use std::iter::FromIterator;
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_millis() as u64
+}
+
+/// 每个测试使用独立的快照文件
+fn snapshot_path(name: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("memkv-{}-{}.mkv", name, std::process::id()));
+    let _ = fs::remove_file(&path);
+    path
+}
+
+fn fill(dbs: &mut Databases) {
+    let db = dbs.db_mut(0).unwrap();
+    assert_eq!(
+        Ok(DBOk::Ok),
+        db.sets(&String::from("string"), String::from("中文 value"))
+    );
+    assert_eq!(
+        Ok(DBOk::Ok),
+        db.set(
+            &String::from("volatile"),
+            String::from("v"),
+            false,
+            false,
+            Some(100)
+        )
+    );
+    assert_eq!(
+        Ok(2),
+        db.sadd(
+            &String::from("set"),
+            vec![String::from("a"), String::from("b")]
+        )
+    );
+    let db = dbs.db_mut(3).unwrap();
+    let pairs = vec![
+        (String::from("name"), String::from("memkv")),
+        (String::from("empty"), String::new()),
+    ];
+    assert_eq!(Ok(DBOk::Ok), db.hmset(&String::from("hash"), pairs));
+}
+
+#[test]
+fn save_and_load_every_type() {
+    let path = snapshot_path("every-type");
+    let mut dbs = Databases::new(4, None);
+    fill(&mut dbs);
+    assert!(dbs.changes_since_save() > 0);
+    assert_eq!(Ok(DBOk::Ok), dbs.save(&path));
+    assert_eq!(0, dbs.changes_since_save());
+
+    let mut loaded = Databases::new(4, None);
+    assert_eq!(
+        Ok(DBOk::Ok),
+        loaded
+            .db_mut(1)
+            .unwrap()
+            .sets(&String::from("stale"), String::from("v"))
+    );
+    assert_eq!(Ok(4), loaded.load(&path));
+    assert_eq!(0, loaded.changes_since_save());
+
+    let db = loaded.db(0).unwrap();
+    assert_eq!(
+        Ok(Some(String::from("中文 value"))),
+        db.get(&String::from("string"))
+    );
+    assert_eq!(-1, db.ttl(&String::from("string")));
+    let ttl = db.ttl(&String::from("volatile"));
+    assert!(ttl > 90 && ttl <= 100);
+    assert_eq!(
+        Ok(Some(HashSet::from_iter(vec![
+            String::from("a"),
+            String::from("b")
+        ]))),
+        db.smembers(&String::from("set"))
+    );
+    assert_eq!(
+        0,
+        loaded.db(1).unwrap().size(),
+        "load replaces existing data"
+    );
+    assert_eq!(
+        Ok(Some(String::new())),
+        loaded
+            .db(3)
+            .unwrap()
+            .hget(&String::from("hash"), &String::from("empty"))
+    );
+    let _ = fs::remove_file(&path);
+}
+
+#[test]
+fn expired_keys_are_not_loaded() {
+    let path = snapshot_path("expired");
+    let mut dbs = Databases::new(1, None);
+    let db = dbs.db_mut(0).unwrap();
+    let key = String::from("key");
+    assert_eq!(Ok(DBOk::Ok), db.sets(&key, String::from("v")));
+    assert_eq!(true, db.pexpire_at(&key, now_millis() + 50));
+    assert_eq!(Ok(DBOk::Ok), dbs.save(&path));
+
+    std::thread::sleep(std::time::Duration::from_millis(100));
+    let mut loaded = Databases::new(1, None);
+    assert_eq!(Ok(0), loaded.load(&path));
+    let _ = fs::remove_file(&path);
+}
+
+#[test]
+fn bgsave_uses_data_at_start() {
+    let path = snapshot_path("bgsave");
+    let mut dbs = Databases::new(4, None);
+    fill(&mut dbs);
+    assert_eq!(Ok(DBOk::Ok), dbs.bgsave(&path));
+    assert_eq!(
+        Ok(DBOk::Ok),
+        dbs.db_mut(0)
+            .unwrap()
+            .sets(&String::from("later"), String::from("v"))
+    );
+    assert_eq!(Some(Ok(DBOk::Ok)), dbs.wait_bgsave());
+    assert_eq!(None, dbs.wait_bgsave());
+    assert_eq!(1, dbs.changes_since_save());
+    assert!(dbs.info().persistence.last_bgsave_ok);
+
+    let mut loaded = Databases::new(4, None);
+    assert_eq!(Ok(4), loaded.load(&path));
+    assert_eq!(false, loaded.db(0).unwrap().exists(&String::from("later")));
+    let _ = fs::remove_file(&path);
+}
+
+#[test]
+fn load_rejects_bad_files() {
+    let path = snapshot_path("bad");
+    let mut dbs = Databases::new(4, None);
+    let err = dbs.load(&path).unwrap_err();
+    assert!(matches!(err, DBError::Io(_)));
+
+    fill(&mut dbs);
+    assert_eq!(Ok(DBOk::Ok), dbs.save(&path));
+    let data = fs::read(&path).unwrap();
+    let checksum = DBError::InvalidPayload(String::from("checksum mismatch"));
+    for offset in [7, data.len() / 2, data.len() - 1].iter() {
+        let mut corrupted = data.clone();
+        corrupted[*offset] ^= 0xff;
+        fs::write(&path, &corrupted).unwrap();
+        assert_eq!(Err(checksum.clone()), dbs.load(&path));
+    }
+    fs::write(&path, &data[..data.len() - 1]).unwrap();
+    assert_eq!(Err(checksum), dbs.load(&path));
+    fs::write(&path, b"not a snapshot").unwrap();
+    assert!(matches!(dbs.load(&path), Err(DBError::InvalidPayload(_))));
+    assert_eq!(3, dbs.db(0).unwrap().size(), "failed load keeps data");
+
+    fs::write(&path, &data).unwrap();
+    let mut fewer = Databases::new(2, None);
+    assert_eq!(Err(DBError::DBIndexOutOfRange(3)), fewer.load(&path));
+    let mut small = Databases::new(4, Some(2));
+    assert_eq!(Err(DBError::OutOfKeysSize(2)), small.load(&path));
+    let _ = fs::remove_file(&path);
+}
+
+#[test]
+fn save_rules() {
+    assert_eq!(
+        Ok(vec![
+            SaveRule {
+                seconds: 3600,
+                changes: 1
+            },
+            SaveRule {
+                seconds: 0,
+                changes: 2
+            },
+        ]),
+        SaveRule::parse_rules("3600 1  0 2")
+    );
+    assert_eq!(Ok(vec![]), SaveRule::parse_rules(""));
+    assert!(matches!(
+        SaveRule::parse_rules("3600"),
+        Err(DBError::Syntax(_))
+    ));
+    assert_eq!(
+        Err(DBError::NotANumber(String::from("x"))),
+        SaveRule::parse_rules("3600 x")
+    );
+
+    let rules = SaveRule::parse_rules("3600 1 0 2").unwrap();
+    let mut dbs = Databases::new(2, None);
+    assert_eq!(false, dbs.should_save(&rules));
+    let db = dbs.db_mut(0).unwrap();
+    assert_eq!(Ok(DBOk::Ok), db.sets(&String::from("a"), String::from("1")));
+    assert_eq!(false, dbs.should_save(&rules));
+    assert_eq!(Ok(DBOk::Ok), dbs.swapdb(0, 1));
+    assert_eq!(true, dbs.should_save(&rules));
+}
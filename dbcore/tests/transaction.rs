@@ -0,0 +1,158 @@
+use dbcore::{DBError, DBOk, KVDB};
+
+fn s(value: &str) -> String {
+    String::from(value)
+}
+
+fn aborted() -> DBError {
+    DBError::Syntax(s("aborted"))
+}
+
+#[test]
+fn changes_are_kept_when_the_closure_succeeds() {
+    let mut db = KVDB::default();
+    db.sadd(&s("from"), vec![s("job")]).unwrap();
+    assert!(!db.in_transaction());
+
+    let moved = db.transaction(|tx| {
+        assert!(tx.in_transaction());
+        let removed = tx.srem(&s("from"), vec![s("job")])?;
+        tx.sadd(&s("to"), vec![s("job")])?;
+        tx.sets(&s("counter"), s("1"))?;
+        Ok(removed)
+    });
+    assert_eq!(Ok(1), moved);
+    assert!(!db.in_transaction());
+    assert_eq!(Ok(Some(true)), db.sismember(&s("to"), &s("job")));
+    assert_eq!(Ok(Some(s("1"))), db.get(&s("counter")));
+}
+
+#[test]
+fn all_changes_are_rolled_back_on_error() {
+    let mut db = KVDB::default();
+    db.sets(&s("name"), s("memkv")).unwrap();
+    db.hmset(&s("user"), vec![(s("id"), s("1"))]).unwrap();
+    db.sadd(&s("tags"), vec![s("a"), s("b")]).unwrap();
+    db.expire(&s("tags"), 100).unwrap();
+    db.set_flags(&s("name"), 7);
+    let versions: Vec<Option<u64>> = ["name", "user", "tags"]
+        .iter()
+        .map(|key| db.version(&s(key)))
+        .collect();
+    let dirty = db.dirty();
+
+    let res: dbcore::Result<()> = db.transaction(|tx| {
+        tx.sets(&s("name"), s("changed"))?;
+        tx.hset(&s("user"), s("id"), s("2"))?;
+        tx.hdel(&s("user"), &s("id"))?;
+        tx.spop(&s("tags"))?;
+        tx.persist(&s("tags"));
+        tx.rename(&s("name"), &s("renamed"))?;
+        tx.sets(&s("created"), s("1"))?;
+        tx.del(vec![s("user")]);
+        Err(aborted())
+    });
+    assert_eq!(Err(aborted()), res);
+
+    assert_eq!(Ok(Some(s("memkv"))), db.get(&s("name")));
+    assert_eq!(Some(7), db.flags(&s("name")));
+    assert_eq!(Ok(Some(s("1"))), db.hget(&s("user"), &s("id")));
+    assert_eq!(Ok(Some(2)), db.slen(&s("tags")));
+    assert!(db.ttl(&s("tags")) > 0);
+    assert!(!db.exists(&s("renamed")));
+    assert!(!db.exists(&s("created")));
+    assert_eq!(3, db.size());
+    assert_eq!(dirty, db.dirty());
+    // 版本号也被恢复, WATCH 不会把回滚的事务视为修改
+    let restored: Vec<Option<u64>> = ["name", "user", "tags"]
+        .iter()
+        .map(|key| db.version(&s(key)))
+        .collect();
+    assert_eq!(versions, restored);
+}
+
+#[test]
+fn max_keys_errors_roll_back_the_transaction() {
+    let mut db = KVDB::new(Some(2));
+    db.sets(&s("a"), s("1")).unwrap();
+    let res = db.transaction(|tx| {
+        tx.sets(&s("a"), s("2"))?;
+        tx.sets(&s("b"), s("2"))?;
+        tx.sets(&s("c"), s("2"))
+    });
+    assert_eq!(Err(DBError::OutOfKeysSize(2)), res);
+    assert_eq!(Ok(Some(s("1"))), db.get(&s("a")));
+    assert!(!db.exists(&s("b")));
+
+    // 事务中删除 key 之后可以写入新的 key
+    let res = db.transaction(|tx| {
+        tx.del(vec![s("a")]);
+        tx.sets(&s("b"), s("2"))?;
+        tx.sets(&s("c"), s("2"))
+    });
+    assert_eq!(Ok(DBOk::Ok), res);
+    assert_eq!(vec![s("b"), s("c")], {
+        let mut keys = db.keys("*");
+        keys.sort();
+        keys
+    });
+}
+
+#[test]
+fn flush_and_expiry_are_rolled_back() {
+    let mut db = KVDB::default();
+    db.sets(&s("a"), s("1")).unwrap();
+    db.sets(&s("b"), s("1")).unwrap();
+    db.pexpire_at(&s("b"), 1);
+
+    let res: dbcore::Result<()> = db.transaction(|tx| {
+        tx.sets(&s("a"), s("2"))?;
+        // 惰性删除已经过期的 key
+        assert_eq!(1, tx.purge_expired());
+        tx.flush();
+        tx.sets(&s("c"), s("3"))?;
+        tx.flush_async();
+        Err(aborted())
+    });
+    assert!(res.is_err());
+    assert_eq!(Ok(Some(s("1"))), db.get(&s("a")));
+    assert!(!db.exists(&s("b")));
+    assert!(!db.exists(&s("c")));
+    assert_eq!(2, db.keyspace_info().keys);
+}
+
+#[test]
+fn nested_transactions() {
+    let mut db = KVDB::default();
+    let res = db.transaction(|tx| {
+        tx.sets(&s("outer"), s("1"))?;
+        // 内层失败只撤销内层的修改
+        let inner: dbcore::Result<()> = tx.transaction(|tx| {
+            tx.sets(&s("outer"), s("2"))?;
+            tx.sets(&s("inner"), s("1"))?;
+            Err(aborted())
+        });
+        assert!(inner.is_err());
+        assert_eq!(Ok(Some(s("1"))), tx.get(&s("outer")));
+        assert!(!tx.exists(&s("inner")));
+
+        // 内层成功之后, 外层失败时一起撤销
+        tx.transaction(|tx| {
+            tx.sets(&s("inner"), s("2"))?;
+            tx.flush();
+            tx.sets(&s("after"), s("1"))
+        })?;
+        assert!(tx.exists(&s("after")));
+        Err::<(), _>(aborted())
+    });
+    assert!(res.is_err());
+    assert_eq!(0, db.size());
+
+    db.sets(&s("kept"), s("1")).unwrap();
+    db.transaction(|tx| {
+        tx.transaction(|tx| tx.sets(&s("inner"), s("1")))?;
+        tx.sets(&s("outer"), s("1"))
+    })
+    .unwrap();
+    assert_eq!(3, db.size());
+}
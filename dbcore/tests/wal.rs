@@ -0,0 +1,346 @@
+use dbcore::{DBError, DBOk, Databases, ValueType, WalRecovery};
+use std::fs;
+use std::path::PathBuf;
+use std::sync::{Arc, Barrier, Mutex};
+use std::thread;
+
+/// 文件头："MKWAL" + 2 字节版本号
+const HEADER_LEN: usize = 7;
+
+/// 每个测试使用独立的 WAL 与快照文件
+fn paths(name: &str) -> (PathBuf, PathBuf) {
+    let dir = std::env::temp_dir();
+    let wal = dir.join(format!("memkv-{}-{}.wal", name, std::process::id()));
+    let snapshot = dir.join(format!("memkv-{}-{}.mkdb", name, std::process::id()));
+    let _ = fs::remove_file(&wal);
+    let _ = fs::remove_file(&snapshot);
+    (wal, snapshot)
+}
+
+/// 把所有数据库的内容描述为排好序的文本, 用于比较两个实例的数据是否相同
+fn describe(dbs: &Databases) -> Vec<String> {
+    let mut lines = Vec::new();
+    for (index, db) in dbs.iter() {
+        for key in db.keys("*") {
+            let mut content = match db.key_type(&key).unwrap() {
+                ValueType::String => vec![db.get(&key).unwrap().unwrap()],
+                ValueType::Set => db.smembers(&key).unwrap().unwrap().into_iter().collect(),
+                ValueType::Hash => db
+                    .hkeys(&key)
+                    .unwrap()
+                    .unwrap()
+                    .into_iter()
+                    .map(|f| format!("{}={}", f, db.hget(&key, &f).unwrap().unwrap()))
+                    .collect(),
+                ValueType::Custom(name) => vec![String::from(name)],
+            };
+            content.sort();
+            let volatile = db.ttl(&key) > 0;
+            lines.push(format!("{} {} {:?} {}", index, key, content, volatile));
+        }
+    }
+    lines.sort();
+    lines
+}
+
+fn s(value: &str) -> String {
+    String::from(value)
+}
+
+fn recover(wal: &PathBuf, snapshot: &PathBuf) -> (Databases, dbcore::Result<WalRecovery>) {
+    let mut dbs = Databases::new(4, None);
+    let recovery = dbs.open_wal(wal, snapshot);
+    (dbs, recovery)
+}
+
+#[test]
+fn replay_reproduces_every_command() {
+    let (wal, snapshot) = paths("replay");
+    let (mut dbs, recovery) = recover(&wal, &snapshot);
+    assert_eq!(
+        Ok(WalRecovery {
+            checkpoint_lsn: 0,
+            records: 0,
+            last_lsn: 0,
+            last_timestamp: 0,
+            truncated: 0
+        }),
+        recovery
+    );
+
+    let db = dbs.db_mut(0).unwrap();
+    assert_eq!(Ok(DBOk::Ok), db.sets(&s("string"), s("中文 value")));
+    assert_eq!(
+        Ok(DBOk::Ok),
+        db.set(&s("volatile"), s("v"), false, false, Some(100))
+    );
+    assert_eq!(Ok(3), db.sadd(&s("set"), vec![s("a"), s("b"), s("c")]));
+    assert!(db.spop(&s("set")).unwrap().is_some());
+    let pairs = vec![(s("f1"), s("v1")), (s("f2"), s("v2"))];
+    assert_eq!(Ok(DBOk::Ok), db.hmset(&s("hash"), pairs));
+    assert_eq!(Ok(Some(1)), db.hdel(&s("hash"), &s("f1")));
+    assert_eq!(Ok(DBOk::Ok), db.rename(&s("hash"), &s("renamed")));
+    assert_eq!(Ok(DBOk::Ok), db.sets(&s("gone"), s("v")));
+    assert_eq!(1, db.del(vec![s("gone")]));
+    assert_eq!(Ok(true), dbs.move_key(&s("string"), 0, 1));
+    assert_eq!(Ok(DBOk::Ok), dbs.swapdb(1, 2));
+    assert_eq!(Ok(DBOk::Ok), dbs.db_mut(3).unwrap().sets(&s("k"), s("v")));
+    assert_eq!(Ok(DBOk::Ok), dbs.flushdb(3, false));
+    assert_eq!(Ok(DBOk::Ok), dbs.commit_wal());
+
+    let handle = dbs.wal().unwrap();
+    let last_lsn = handle.last_lsn();
+    assert_eq!(last_lsn, handle.durable_lsn());
+    let expected = describe(&dbs);
+    drop(dbs);
+
+    let (dbs, recovery) = recover(&wal, &snapshot);
+    let recovery = recovery.unwrap();
+    assert_eq!(last_lsn, recovery.last_lsn);
+    assert_eq!(last_lsn as usize, recovery.records);
+    assert!(recovery.last_timestamp > 0);
+    assert_eq!(0, recovery.truncated);
+    assert_eq!(expected, describe(&dbs));
+    assert_eq!(
+        Ok(Some(s("中文 value"))),
+        dbs.db(2).unwrap().get(&s("string"))
+    );
+
+    let _ = fs::remove_file(&wal);
+}
+
+#[test]
+fn recovery_at_every_byte_offset() {
+    let (wal, snapshot) = paths("offsets");
+    let (mut dbs, recovery) = recover(&wal, &snapshot);
+    assert!(recovery.is_ok());
+
+    // 每次提交恰好一条记录, 记录下每个记录边界处的数据
+    let mut boundaries = vec![(HEADER_LEN, describe(&dbs))];
+    let steps: Vec<fn(&mut Databases)> = vec![
+        |dbs| {
+            let _ = dbs.db_mut(0).unwrap().sets(&s("a"), s("1"));
+        },
+        |dbs| {
+            let _ = dbs.db_mut(0).unwrap().sadd(&s("set"), vec![s("x"), s("y")]);
+        },
+        |dbs| {
+            let _ = dbs.db_mut(1).unwrap().hset(&s("hash"), s("f"), s("中文"));
+        },
+        |dbs| {
+            let _ = dbs.db_mut(0).unwrap().expire(&s("a"), 100);
+        },
+        |dbs| {
+            let _ = dbs.swapdb(0, 1);
+        },
+        |dbs| {
+            let _ = dbs.db_mut(1).unwrap().del(vec![s("set")]);
+        },
+        |dbs| {
+            let _ = dbs.db_mut(2).unwrap().sets(&s("b"), s("a longer value"));
+        },
+    ];
+    for (i, step) in steps.iter().enumerate() {
+        step(&mut dbs);
+        assert_eq!(Ok(DBOk::Ok), dbs.commit_wal());
+        assert_eq!(i as u64 + 1, dbs.wal().unwrap().last_lsn());
+        boundaries.push((dbs.wal().unwrap().size() as usize, describe(&dbs)));
+    }
+    drop(dbs);
+    let data = fs::read(&wal).unwrap();
+    assert_eq!(boundaries.last().unwrap().0, data.len());
+
+    let (damaged, damaged_snapshot) = paths("offsets-damaged");
+    let expected_at = |offset: usize| {
+        boundaries
+            .iter()
+            .rev()
+            .find(|(boundary, _)| *boundary <= offset)
+            .unwrap()
+    };
+
+    // 在每个字节处截断：恢复到截断处之前最后一条完整的记录
+    for offset in HEADER_LEN..=data.len() {
+        fs::write(&damaged, &data[..offset]).unwrap();
+        let (dbs, recovery) = recover(&damaged, &damaged_snapshot);
+        let recovery = recovery.unwrap();
+        let (boundary, expected) = expected_at(offset);
+        assert_eq!(*expected, describe(&dbs), "truncated at {}", offset);
+        assert_eq!(offset - boundary, recovery.truncated);
+        drop(dbs);
+        assert_eq!(*boundary as u64, fs::metadata(&damaged).unwrap().len());
+    }
+
+    // 损坏每个字节：恢复到损坏的记录之前, 损坏的记录及之后的内容被截掉
+    for offset in HEADER_LEN..data.len() {
+        let mut corrupted = data.clone();
+        corrupted[offset] ^= 0xff;
+        fs::write(&damaged, &corrupted).unwrap();
+        let (dbs, recovery) = recover(&damaged, &damaged_snapshot);
+        let recovery = recovery.unwrap();
+        let (boundary, expected) = expected_at(offset);
+        assert_eq!(*expected, describe(&dbs), "corrupted at {}", offset);
+        assert_eq!(data.len() - boundary, recovery.truncated);
+        drop(dbs);
+        assert_eq!(*boundary as u64, fs::metadata(&damaged).unwrap().len());
+    }
+
+    // 文件头不完整或损坏时拒绝恢复, 也不修改文件
+    let mut bad_headers: Vec<Vec<u8>> = (1..HEADER_LEN).map(|n| data[..n].to_vec()).collect();
+    for offset in 0..HEADER_LEN {
+        let mut corrupted = data.clone();
+        corrupted[offset] ^= 0xff;
+        bad_headers.push(corrupted);
+    }
+    for bad in bad_headers.iter() {
+        fs::write(&damaged, bad).unwrap();
+        let (dbs, recovery) = recover(&damaged, &damaged_snapshot);
+        assert!(
+            matches!(recovery, Err(DBError::InvalidPayload(_))),
+            "{:?}",
+            &bad[..HEADER_LEN.min(bad.len())]
+        );
+        assert!(dbs.wal().is_none());
+        assert_eq!(bad.len() as u64, fs::metadata(&damaged).unwrap().len());
+    }
+
+    // 空文件视为没有记录的日志
+    fs::write(&damaged, b"").unwrap();
+    let (dbs, recovery) = recover(&damaged, &damaged_snapshot);
+    assert_eq!(0, recovery.unwrap().records);
+    drop(dbs);
+    assert_eq!(HEADER_LEN as u64, fs::metadata(&damaged).unwrap().len());
+
+    let _ = fs::remove_file(&wal);
+    let _ = fs::remove_file(&damaged);
+}
+
+#[test]
+fn checkpoint_keeps_only_the_tail() {
+    let (wal, snapshot) = paths("checkpoint");
+    let (mut dbs, _) = recover(&wal, &snapshot);
+    assert_eq!(Ok(DBOk::Ok), dbs.db_mut(0).unwrap().sets(&s("a"), s("1")));
+    assert_eq!(Ok(DBOk::Ok), dbs.db_mut(1).unwrap().sets(&s("b"), s("2")));
+    assert_eq!(Ok(DBOk::Ok), dbs.commit_wal());
+    let before_checkpoint = fs::read(&wal).unwrap();
+
+    assert_eq!(Ok(2), dbs.checkpoint());
+    assert_eq!(HEADER_LEN as u64, dbs.wal().unwrap().size());
+    assert_eq!(0, dbs.changes_since_save());
+    assert_eq!(Ok(1), dbs.db_mut(0).unwrap().sadd(&s("set"), vec![s("x")]));
+    assert_eq!(Ok(DBOk::Ok), dbs.commit_wal());
+    assert_eq!(3, dbs.wal().unwrap().last_lsn());
+    let expected = describe(&dbs);
+    drop(dbs);
+
+    let (mut dbs, recovery) = recover(&wal, &snapshot);
+    let recovery = recovery.unwrap();
+    assert_eq!(
+        (2, 1, 3),
+        (recovery.checkpoint_lsn, recovery.records, recovery.last_lsn)
+    );
+    assert_eq!(expected, describe(&dbs));
+
+    // 之后的记录继续编号
+    assert_eq!(Ok(DBOk::Ok), dbs.db_mut(0).unwrap().sets(&s("c"), s("3")));
+    assert_eq!(Ok(DBOk::Ok), dbs.commit_wal());
+    assert_eq!(4, dbs.wal().unwrap().last_lsn());
+    drop(dbs);
+
+    // 写入快照之后、清空日志之前宕机：日志中的记录都已包含在快照中, 不会重复执行
+    let (mut dbs, _) = recover(&wal, &snapshot);
+    assert_eq!(Ok(4), dbs.checkpoint());
+    let checkpointed = describe(&dbs);
+    drop(dbs);
+    fs::write(&wal, &before_checkpoint).unwrap();
+    let (dbs, recovery) = recover(&wal, &snapshot);
+    let recovery = recovery.unwrap();
+    assert_eq!(
+        (4, 0, 4),
+        (recovery.checkpoint_lsn, recovery.records, recovery.last_lsn)
+    );
+    assert_eq!(checkpointed, describe(&dbs));
+
+    assert_eq!(
+        Err(DBError::NotSupported(s("checkpoint without WAL"))),
+        Databases::new(1, None).checkpoint()
+    );
+    let _ = fs::remove_file(&wal);
+    let _ = fs::remove_file(&snapshot);
+}
+
+#[test]
+fn group_commit_batches_writers() {
+    let (wal, snapshot) = paths("group");
+    let (mut dbs, _) = recover(&wal, &snapshot);
+
+    // 多条记录只需要一次 fsync
+    for i in 0..5 {
+        let _ = dbs.db_mut(0).unwrap().sets(&i.to_string(), s("v"));
+    }
+    let lsn = dbs.flush_wal().unwrap();
+    assert_eq!(5, lsn);
+    assert_eq!(0, dbs.wal().unwrap().durable_lsn());
+    let handle = dbs.wal().unwrap().clone();
+    assert_eq!(Ok(()), handle.commit(lsn));
+    assert_eq!(Ok(()), handle.commit(3));
+    assert_eq!((1, 5), (handle.syncs(), handle.durable_lsn()));
+
+    // 所有线程的记录都已经追加之后再同时提交, 第一个提交的线程一次 fsync 覆盖所有记录
+    let dbs = Arc::new(Mutex::new(dbs));
+    let barrier = Arc::new(Barrier::new(8));
+    let threads: Vec<_> = (0..8)
+        .map(|t| {
+            let dbs = Arc::clone(&dbs);
+            let barrier = Arc::clone(&barrier);
+            thread::spawn(move || {
+                let (lsn, wal) = {
+                    let mut dbs = dbs.lock().unwrap();
+                    let _ = dbs.db_mut(2).unwrap().sets(&t.to_string(), s("v"));
+                    (dbs.flush_wal().unwrap(), dbs.wal().unwrap().clone())
+                };
+                barrier.wait();
+                assert_eq!(Ok(()), wal.commit(lsn));
+            })
+        })
+        .collect();
+    threads.into_iter().for_each(|t| t.join().unwrap());
+    assert_eq!((2, 13), (handle.syncs(), handle.durable_lsn()));
+
+    // 多个线程并发写入, 在不持有数据库的情况下等待各自的记录落盘
+    let threads: Vec<_> = (0..8)
+        .map(|t| {
+            let dbs = Arc::clone(&dbs);
+            thread::spawn(move || {
+                for i in 0..20 {
+                    let lsn = {
+                        let mut dbs = dbs.lock().unwrap();
+                        let key = format!("{}-{}", t, i);
+                        let _ = dbs.db_mut(1).unwrap().sets(&key, s("v"));
+                        dbs.flush_wal().unwrap()
+                    };
+                    let wal = dbs.lock().unwrap().wal().unwrap().clone();
+                    assert_eq!(Ok(()), wal.commit(lsn));
+                    assert!(wal.durable_lsn() >= lsn);
+                }
+            })
+        })
+        .collect();
+    threads.into_iter().for_each(|t| t.join().unwrap());
+    assert_eq!(173, handle.durable_lsn());
+
+    let dbs = Arc::try_unwrap(dbs).unwrap().into_inner().unwrap();
+    let info = dbs.info().render(Some("persistence"));
+    assert!(info.contains("wal_enabled:1\r\n"));
+    assert!(info.contains("wal_last_lsn:173\r\n"));
+    assert!(info.contains("wal_durable_lsn:173\r\n"));
+    let expected = describe(&dbs);
+    drop(dbs);
+    drop(handle);
+
+    let (dbs, recovery) = recover(&wal, &snapshot);
+    assert_eq!(173, recovery.unwrap().records);
+    assert_eq!(expected, describe(&dbs));
+    assert_eq!(160, dbs.db(1).unwrap().size());
+    let _ = fs::remove_file(&wal);
+}
@@ -0,0 +1,561 @@
+//! 操作数据库的命令：把 RESP 请求的参数转换为 `KVDB` / `Databases` 的方法调用, 并把结果转换为回复。
+//!
+//! 命令名称、参数与回复与 Redis 的同名命令一致；连接级别的命令（`PING`、`HELLO` 等）
+//! 不需要访问数据库, 由 `connection` 处理。
+//! 不是内置命令的名称交给注册的自定义命令（参见 `dbcore::Command`）, 与内置命令同名的自定义命令不会被执行。
+
+use crate::connection::Session;
+use crate::resp::Reply;
+use crate::ServerConfig;
+use dbcore::{DBError, DBOk, Databases, Result, ValueType, DEFAULT_SCAN_COUNT, KVDB};
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// 数据库命令及其参数个数（包括命令名称）, 负数 -N 表示至少 N 个
+const COMMANDS: &[(&str, i32)] = &[
+    ("get", 2),
+    ("set", -3),
+    ("del", -2),
+    ("unlink", -2),
+    ("exists", -2),
+    ("touch", -2),
+    ("object", 3),
+    ("expire", 3),
+    ("pexpire", 3),
+    ("expireat", 3),
+    ("pexpireat", 3),
+    ("persist", 2),
+    ("ttl", 2),
+    ("pttl", 2),
+    ("type", 2),
+    ("keys", 2),
+    ("scan", -2),
+    ("randomkey", 1),
+    ("dbsize", 1),
+    ("rename", 3),
+    ("renamenx", 3),
+    ("copy", -3),
+    ("move", 3),
+    ("dump", 2),
+    ("restore", -4),
+    ("sadd", -3),
+    ("srem", -3),
+    ("scard", 2),
+    ("smembers", 2),
+    ("sismember", 3),
+    ("spop", -2),
+    ("srandmember", -2),
+    ("sscan", -3),
+    ("hset", -4),
+    ("hget", 3),
+    ("hmset", -4),
+    ("hmget", -3),
+    ("hdel", -3),
+    ("hexists", 3),
+    ("hlen", 2),
+    ("hkeys", 2),
+    ("hvals", 2),
+    ("hgetall", 2),
+    ("hscan", -3),
+    ("select", 2),
+    ("swapdb", 3),
+    ("flushdb", -1),
+    ("flushall", -1),
+    ("info", -1),
+    ("save", 1),
+    ("bgsave", 1),
+    ("lastsave", 1),
+    ("bgrewriteaof", 1),
+    ("checkpoint", 1),
+];
+
+/// 访问多个数据库或者整个服务的命令, 其他命令只访问当前数据库
+const SERVER_COMMANDS: &[&str] = &[
+    "select",
+    "swapdb",
+    "move",
+    "flushdb",
+    "flushall",
+    "info",
+    "save",
+    "bgsave",
+    "lastsave",
+    "bgrewriteaof",
+    "checkpoint",
+];
+
+/// 修改数据库的命令
+const WRITE_COMMANDS: &[&str] = &[
+    "set",
+    "del",
+    "unlink",
+    "expire",
+    "pexpire",
+    "expireat",
+    "pexpireat",
+    "persist",
+    "rename",
+    "renamenx",
+    "copy",
+    "move",
+    "restore",
+    "sadd",
+    "srem",
+    "spop",
+    "hset",
+    "hmset",
+    "hdel",
+    "swapdb",
+    "flushdb",
+    "flushall",
+];
+
+/// internal：name（小写）是否为数据库命令
+pub(crate) fn is_command(name: &str) -> bool {
+    COMMANDS.iter().any(|(command, _)| *command == name)
+}
+
+/// internal：name（小写）是否为修改数据库的命令
+pub(crate) fn is_write(name: &str) -> bool {
+    WRITE_COMMANDS.contains(&name)
+}
+
+/// internal：数据库命令的数量
+pub(crate) fn count() -> usize {
+    COMMANDS.len()
+}
+
+/// internal：检查参数个数
+pub(crate) fn check_arity(name: &str, args: usize) -> Result<()> {
+    match COMMANDS.iter().find(|(command, _)| *command == name) {
+        Some((_, arity)) if *arity >= 0 && args == *arity as usize => Ok(()),
+        Some((_, arity)) if *arity < 0 && args >= arity.unsigned_abs() as usize => Ok(()),
+        Some(_) => Err(DBError::WrongArity(String::from(name))),
+        None => Err(DBError::UnknownCommand(String::from(name))),
+    }
+}
+
+/// internal：参数转换为字符串, 数据库只保存 UTF-8 字符串
+pub(crate) fn text(arg: &[u8]) -> Result<String> {
+    String::from_utf8(arg.to_vec())
+        .map_err(|_| DBError::Syntax(String::from("argument is not valid UTF-8")))
+}
+
+pub(crate) fn texts(args: &[Vec<u8>]) -> Result<Vec<String>> {
+    args.iter().map(|arg| text(arg)).collect()
+}
+
+fn number<T: FromStr>(arg: &[u8]) -> Result<T> {
+    let arg = text(arg)?;
+    arg.parse().map_err(|_| DBError::NotANumber(arg))
+}
+
+fn integer(value: usize) -> Reply {
+    Reply::Integer(value as i64)
+}
+
+fn boolean(value: bool) -> Reply {
+    Reply::Integer(value as i64)
+}
+
+fn status(result: Result<DBOk>) -> Result<Reply> {
+    result.map(|ok| match ok {
+        DBOk::Ok => Reply::ok(),
+        DBOk::Nil => Reply::Nil,
+    })
+}
+
+/// 当前时间的毫秒时间戳
+fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+/// internal：过期时间超出毫秒时间戳的范围, 回复为 `ERR invalid expire time`
+fn invalid_expire_time() -> DBError {
+    DBError::OutOfRange(String::from("expire time"))
+}
+
+/// internal：从现在开始 ms 毫秒之后的时间戳
+fn expire_after(ms: u64) -> Result<u64> {
+    now_millis().checked_add(ms).ok_or_else(invalid_expire_time)
+}
+
+/// internal：scan 类命令的可选参数 [MATCH pattern] [COUNT n] [TYPE t]
+struct ScanOpts {
+    pattern: Option<String>,
+    count: usize,
+    value_type: Option<ValueType>,
+}
+
+fn scan_opts(args: &[Vec<u8>], allow_type: bool) -> Result<ScanOpts> {
+    let mut opts = ScanOpts {
+        pattern: None,
+        count: DEFAULT_SCAN_COUNT,
+        value_type: None,
+    };
+    if !args.len().is_multiple_of(2) {
+        return Err(DBError::Syntax(String::from("missing option value")));
+    }
+    for pair in args.chunks(2) {
+        match text(&pair[0])?.to_ascii_lowercase().as_str() {
+            "match" => opts.pattern = Some(text(&pair[1])?),
+            "count" => match number::<usize>(&pair[1])? {
+                0 => return Err(DBError::Syntax(String::from("COUNT must be positive"))),
+                n => opts.count = n,
+            },
+            "type" if allow_type => {
+                let name = text(&pair[1])?;
+                opts.value_type = Some(
+                    ValueType::from_name(&name)
+                        .ok_or_else(|| DBError::Syntax(format!("unknown type `{}`", name)))?,
+                );
+            }
+            option => return Err(DBError::Syntax(format!("unknown option `{}`", option))),
+        }
+    }
+    Ok(opts)
+}
+
+fn scan_reply(cursor: u64, items: Vec<String>) -> Reply {
+    Reply::Array(vec![Reply::bulk(cursor.to_string()), Reply::strings(items)])
+}
+
+/// internal：SET key value [NX|XX] [EX seconds|PX milliseconds]
+fn set(db: &mut KVDB, args: &[Vec<u8>]) -> Result<Reply> {
+    let (key, value) = (text(&args[1])?, text(&args[2])?);
+    let (mut not_exists, mut already_exists) = (false, false);
+    let mut ttl_ms: Option<u64> = None;
+    let mut options = args[3..].iter();
+    while let Some(option) = options.next() {
+        match text(option)?.to_ascii_lowercase().as_str() {
+            "nx" if !already_exists => not_exists = true,
+            "xx" if !not_exists => already_exists = true,
+            unit @ ("ex" | "px") if ttl_ms.is_none() => {
+                let arg = options
+                    .next()
+                    .ok_or_else(|| DBError::Syntax(String::from("missing expire time")))?;
+                // 与 Redis 一致, 生存时间必须为正数
+                let ttl = match number::<i64>(arg) {
+                    Ok(ttl) if ttl <= 0 => return Err(invalid_expire_time()),
+                    Ok(ttl) => ttl as u64,
+                    Err(_) => number::<u64>(arg)?,
+                };
+                let ttl = if unit == "ex" {
+                    ttl.checked_mul(1000)
+                } else {
+                    Some(ttl)
+                };
+                ttl_ms = Some(ttl.ok_or_else(invalid_expire_time)?);
+            }
+            option => return Err(DBError::Syntax(format!("unexpected option `{}`", option))),
+        }
+    }
+    // 整秒的生存时间直接交给 set(), 否则先写入再设置毫秒精度的过期时间；
+    // 写入之前计算过期时间, 溢出时不修改数据库
+    let expire_at = ttl_ms.map(expire_after).transpose()?;
+    let seconds = ttl_ms.filter(|ms| ms % 1000 == 0).map(|ms| ms / 1000);
+    let res = db.set(&key, value, not_exists, already_exists, seconds)?;
+    if let (DBOk::Ok, Some(when), None) = (&res, expire_at, seconds) {
+        db.pexpire_at(&key, when);
+    }
+    status(Ok(res))
+}
+
+/// internal：SPOP key [count] 与 SRANDMEMBER key [count]；SRANDMEMBER 的 count 为负数时元素可能重复
+fn random_members(db: &mut KVDB, args: &[Vec<u8>], pop: bool) -> Result<Reply> {
+    let key = text(&args[1])?;
+    if args.len() > 3 {
+        return Err(DBError::Syntax(String::from("too many arguments")));
+    }
+    let members: Vec<String> = if pop {
+        let count = args.get(2).map(|arg| number::<usize>(arg)).transpose()?;
+        db.srandmember(&key, count.unwrap_or(1))?
+            .map_or_else(Vec::new, |members| members.into_iter().collect())
+    } else {
+        let count = args.get(2).map(|arg| number::<i64>(arg)).transpose()?;
+        db.random_members(&key, count.unwrap_or(1))?
+            .unwrap_or_default()
+    };
+    Ok(match args.len() {
+        3 => Reply::strings(members),
+        _ => Reply::optional(members.into_iter().next()),
+    })
+}
+
+/// internal：COPY source destination [REPLACE]
+fn copy(db: &mut KVDB, args: &[Vec<u8>]) -> Result<Reply> {
+    let replace = match args.get(3).map(|arg| text(arg)).transpose()? {
+        None => false,
+        Some(option) if option.eq_ignore_ascii_case("replace") && args.len() == 4 => true,
+        Some(option) => return Err(DBError::Syntax(format!("unexpected option `{}`", option))),
+    };
+    db.copy(&text(&args[1])?, &text(&args[2])?, replace)
+        .map(boolean)
+}
+
+/// internal：RESTORE key ttl payload [REPLACE], payload 是 `DUMP` 返回的二进制数据
+fn restore(db: &mut KVDB, args: &[Vec<u8>]) -> Result<Reply> {
+    let key = text(&args[1])?;
+    let ttl = match number::<u64>(&args[2])? {
+        0 => None,
+        ms => Some(ms),
+    };
+    let replace = match args.get(4).map(|arg| text(arg)).transpose()? {
+        None => false,
+        Some(option) if option.eq_ignore_ascii_case("replace") && args.len() == 5 => true,
+        Some(option) => return Err(DBError::Syntax(format!("unexpected option `{}`", option))),
+    };
+    status(db.restore(&key, ttl, &args[3], replace))
+}
+
+/// internal：OBJECT IDLETIME key, 只支持 IDLETIME
+fn object(db: &KVDB, args: &[Vec<u8>]) -> Result<Reply> {
+    let sub = text(&args[1])?.to_ascii_lowercase();
+    match sub.as_str() {
+        "idletime" => Ok(db
+            .idle_time(&text(&args[2])?)
+            .map_or(Reply::Nil, |idle| Reply::Integer(idle as i64))),
+        _ => Err(DBError::NotSupported(format!(
+            "OBJECT {}",
+            sub.to_uppercase()
+        ))),
+    }
+}
+
+/// internal：在 KVDB 上执行读写单个数据库的命令
+fn execute_db(db: &mut KVDB, name: &str, args: &[Vec<u8>]) -> Result<Reply> {
+    let key = || text(&args[1]);
+    match name {
+        "get" => db.get(&key()?).map(Reply::optional),
+        "set" => set(db, args),
+        "del" => Ok(integer(db.del(texts(&args[1..])?) as usize)),
+        "unlink" => Ok(integer(db.unlink(texts(&args[1..])?) as usize)),
+        "touch" => Ok(integer(db.touch(texts(&args[1..])?) as usize)),
+        "object" => object(db, args),
+        "exists" => {
+            let keys = texts(&args[1..])?;
+            Ok(integer(keys.iter().filter(|key| db.exists(key)).count()))
+        }
+        "expire" => Ok(boolean(db.expire(&key()?, number(&args[2])?)?)),
+        "pexpire" => {
+            let when = expire_after(number(&args[2])?)?;
+            Ok(boolean(db.pexpire_at(&key()?, when)))
+        }
+        "expireat" => {
+            let seconds: u64 = number(&args[2])?;
+            let when = seconds.checked_mul(1000).ok_or_else(invalid_expire_time)?;
+            Ok(boolean(db.pexpire_at(&key()?, when)))
+        }
+        "pexpireat" => Ok(boolean(db.pexpire_at(&key()?, number(&args[2])?))),
+        "persist" => Ok(boolean(db.persist(&key()?))),
+        "ttl" => Ok(Reply::Integer(db.ttl(&key()?))),
+        "pttl" => Ok(Reply::Integer(db.pttl(&key()?))),
+        "type" => Ok(Reply::Status(String::from(
+            db.key_type(&key()?).map_or("none", |t| t.name()),
+        ))),
+        "keys" => Ok(Reply::strings(db.keys(&text(&args[1])?))),
+        "scan" => {
+            let cursor = number(&args[1])?;
+            let opts = scan_opts(&args[2..], true)?;
+            let (next, keys) =
+                db.scan(cursor, opts.pattern.as_deref(), opts.count, opts.value_type);
+            Ok(scan_reply(next, keys))
+        }
+        "randomkey" => Ok(Reply::optional(db.randomkey())),
+        "dbsize" => Ok(integer(db.size())),
+        "rename" => status(db.rename(&key()?, &text(&args[2])?)),
+        "renamenx" => db.renamenx(&key()?, &text(&args[2])?).map(boolean),
+        "copy" => copy(db, args),
+        "dump" => Ok(db.dump(&key()?).map_or(Reply::Nil, Reply::Bulk)),
+        "restore" => restore(db, args),
+        "sadd" => db.sadd(&key()?, texts(&args[2..])?).map(integer),
+        "srem" => db.srem(&key()?, texts(&args[2..])?).map(integer),
+        "scard" => db.slen(&key()?).map(|len| integer(len.unwrap_or(0))),
+        "smembers" => db
+            .smembers(&key()?)
+            .map(|members| Reply::Set(members.into_iter().flatten().map(Reply::bulk).collect())),
+        "sismember" => db
+            .sismember(&key()?, &text(&args[2])?)
+            .map(|found| boolean(found.unwrap_or(false))),
+        "spop" => random_members(db, args, true),
+        "srandmember" => random_members(db, args, false),
+        "sscan" => {
+            let cursor = number(&args[2])?;
+            let opts = scan_opts(&args[3..], false)?;
+            let (next, members) = db.sscan(&key()?, cursor, opts.pattern.as_deref(), opts.count)?;
+            Ok(scan_reply(next, members))
+        }
+        "hset" | "hmset" => {
+            if !args.len().is_multiple_of(2) {
+                return Err(DBError::WrongArity(String::from(name)));
+            }
+            let key = key()?;
+            let pairs = texts(&args[2..])?;
+            if name == "hmset" {
+                let pairs = pairs
+                    .chunks(2)
+                    .map(|pair| (pair[0].clone(), pair[1].clone()))
+                    .collect();
+                return status(db.hmset(&key, pairs));
+            }
+            let mut added = 0;
+            for pair in pairs.chunks(2) {
+                added += db.hset(&key, pair[0].clone(), pair[1].clone())?;
+            }
+            Ok(integer(added as usize))
+        }
+        "hget" => db.hget(&key()?, &text(&args[2])?).map(Reply::optional),
+        "hmget" => {
+            let fields = texts(&args[2..])?;
+            match db.hmget(&key()?, &fields) {
+                Ok(values) => Ok(Reply::Array(
+                    values.into_iter().map(Reply::optional).collect(),
+                )),
+                Err(DBError::KeyNotFound(_)) => Ok(Reply::Array(vec![Reply::Nil; fields.len()])),
+                Err(e) => Err(e),
+            }
+        }
+        "hdel" => {
+            let key = key()?;
+            let mut deleted = 0;
+            for field in texts(&args[2..])? {
+                deleted += db.hdel(&key, &field)?.unwrap_or(0);
+            }
+            Ok(integer(deleted))
+        }
+        "hexists" => db
+            .hexists(&key()?, &text(&args[2])?)
+            .map(|found| boolean(found.unwrap_or(false))),
+        "hlen" => db.hlen(&key()?).map(|len| integer(len.unwrap_or(0))),
+        "hkeys" => db
+            .hkeys(&key()?)
+            .map(|fields| Reply::strings(fields.unwrap_or_default())),
+        "hvals" => db
+            .hvalues(&key()?)
+            .map(|values| Reply::strings(values.unwrap_or_default())),
+        "hgetall" => {
+            let (_, pairs) = db.hscan(&key()?, 0, None, usize::MAX)?;
+            Ok(Reply::Map(
+                pairs
+                    .into_iter()
+                    .map(|(field, value)| (Reply::bulk(field), Reply::bulk(value)))
+                    .collect(),
+            ))
+        }
+        "hscan" => {
+            let cursor = number(&args[2])?;
+            let opts = scan_opts(&args[3..], false)?;
+            let (next, pairs) = db.hscan(&key()?, cursor, opts.pattern.as_deref(), opts.count)?;
+            let items = pairs
+                .into_iter()
+                .flat_map(|(field, value)| vec![field, value])
+                .collect();
+            Ok(scan_reply(next, items))
+        }
+        _ => Err(DBError::UnknownCommand(String::from(name))),
+    }
+}
+
+///
+/// internal：在单个 KVDB 上执行一条命令（包括自定义命令）, 供脚本使用；name 为小写的命令名称
+///
+/// 返回值：
+///     * 命令的回复
+///     * 访问多个数据库或者整个服务的命令返回 `DBError::NotSupported`
+///     * 命令不存在、参数个数或格式不正确、执行失败， 返回对应的错误
+pub(crate) fn execute_key_command(db: &mut KVDB, name: &str, args: &[Vec<u8>]) -> Result<Reply> {
+    if !is_command(name) {
+        return execute_custom(db, args);
+    }
+    check_arity(name, args.len())?;
+    if SERVER_COMMANDS.contains(&name) {
+        return Err(DBError::NotSupported(format!(
+            "{} from scripts",
+            name.to_uppercase()
+        )));
+    }
+    execute_db(db, name, args)
+}
+
+///
+/// internal：执行一条注册的自定义命令, 参见 `KVDB::call()`
+///
+/// 返回值：
+///     * 命令的回复
+///     * 没有注册该命令， 返回 UnknownCommand
+///     * 参数个数不正确或者执行失败， 返回对应的错误
+pub(crate) fn execute_custom(db: &mut KVDB, args: &[Vec<u8>]) -> Result<Reply> {
+    db.call(&texts(args)?).map(Reply::custom)
+}
+
+/// internal：FLUSHDB / FLUSHALL 的 [ASYNC|SYNC] 参数
+fn lazy_flush(args: &[Vec<u8>]) -> Result<bool> {
+    match args.get(1).map(|arg| text(arg)).transpose()? {
+        None => Ok(false),
+        Some(mode) if args.len() == 2 && mode.eq_ignore_ascii_case("async") => Ok(true),
+        Some(mode) if args.len() == 2 && mode.eq_ignore_ascii_case("sync") => Ok(false),
+        Some(mode) => Err(DBError::Syntax(format!("unexpected option `{}`", mode))),
+    }
+}
+
+///
+/// internal：执行一条数据库命令, name 为小写的命令名称
+///
+/// 返回值：
+///     * 命令的回复
+///     * 命令不存在、参数个数或格式不正确、执行失败， 返回对应的错误
+pub(crate) fn execute(
+    dbs: &mut Databases,
+    config: &ServerConfig,
+    session: &mut Session,
+    name: &str,
+    args: &[Vec<u8>],
+) -> Result<Reply> {
+    if !is_command(name) {
+        return execute_custom(dbs.db_mut(session.db)?, args);
+    }
+    check_arity(name, args.len())?;
+    let no_dbfile = || DBError::NotSupported(format!("{} without a dbfile", name.to_uppercase()));
+    match name {
+        "select" => {
+            let index = number(&args[1])?;
+            dbs.db(index)?;
+            session.db = index;
+            Ok(Reply::ok())
+        }
+        "swapdb" => status(dbs.swapdb(number(&args[1])?, number(&args[2])?)),
+        "move" => dbs
+            .move_key(&text(&args[1])?, session.db, number(&args[2])?)
+            .map(boolean),
+        "flushdb" => status(dbs.flushdb(session.db, lazy_flush(args)?)),
+        "flushall" => {
+            dbs.flushall(lazy_flush(args)?);
+            Ok(Reply::ok())
+        }
+        "info" => {
+            let section = args.get(1).map(|arg| text(arg)).transpose()?;
+            Ok(Reply::bulk(dbs.info().render(section.as_deref())))
+        }
+        "save" => match &config.dbfile {
+            Some(path) => status(dbs.save(path)),
+            None => Err(no_dbfile()),
+        },
+        "bgsave" => match &config.dbfile {
+            Some(path) => dbs
+                .bgsave(path)
+                .map(|_| Reply::Status(String::from("Background saving started"))),
+            None => Err(no_dbfile()),
+        },
+        "lastsave" => Ok(Reply::Integer(dbs.last_save() as i64)),
+        "bgrewriteaof" => dbs.bgrewriteaof().map(|_| {
+            Reply::Status(String::from(
+                "Background append only file rewriting started",
+            ))
+        }),
+        "checkpoint" => dbs.checkpoint().map(|lsn| Reply::Integer(lsn as i64)),
+        _ => execute_db(dbs.db_mut(session.db)?, name, args),
+    }
+}
@@ -0,0 +1,475 @@
+//! 单个客户端连接的处理。
+//!
+//! 每次从 socket 读取数据之后, 依次执行缓冲区中所有完整的命令（pipelining）, 回复按顺序写回；
+//! 不完整的命令跨多次读取继续解析；还没有执行的数据超过 `ServerConfig::query_buffer_limit` 时关闭连接。
+//! 开启 WAL 时, 一批命令只需要等待一次落盘, 并且在数据库锁之外等待, 其他连接可以继续执行命令。
+//!
+//! 订阅了频道的连接由另一个线程推送消息, 参见 `pubsub`；两个线程通过 `Output` 共享连接的写端。
+
+use crate::command::{self, text};
+use crate::multi;
+use crate::pubsub;
+use crate::resp::{CommandParser, Protocol, Reply};
+use crate::script;
+use crate::server::{Shared, ShutdownMode};
+use crate::stream::Stream;
+use dbcore::{DBError, Result, Subscriber, Transaction, Wal};
+use std::io::{ErrorKind, Read, Write};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread::JoinHandle;
+use std::time::Instant;
+
+/// 连接级别的命令, 不需要访问数据库
+const CONNECTION_COMMANDS: &[&str] = &[
+    "ping", "echo", "quit", "hello", "auth", "client", "command", "shutdown",
+];
+
+/// internal：name（小写）是否为连接级别的命令
+pub(crate) fn is_command(name: &str) -> bool {
+    CONNECTION_COMMANDS.contains(&name)
+}
+
+/// 每次从 socket 读取的最大字节数
+const READ_CHUNK: usize = 16 * 1024;
+
+/// 连接的状态
+#[derive(Debug)]
+pub(crate) struct Session {
+    pub(crate) id: u64,
+    /// 当前选择的数据库编号
+    pub(crate) db: usize,
+    pub(crate) protocol: Protocol,
+    /// `CLIENT SETNAME` 设置的名称
+    pub(crate) name: Option<String>,
+    /// 写完回复之后关闭连接
+    pub(crate) closing: bool,
+    /// 第一次订阅时创建的订阅者
+    pub(crate) subscriber: Option<Arc<Subscriber>>,
+    /// 把订阅的消息推送给客户端的线程
+    pub(crate) forwarder: Option<JoinHandle<()>>,
+    /// `MULTI` / `WATCH` 的状态, 排队的命令为原始参数
+    pub(crate) transaction: Transaction<Vec<Vec<u8>>>,
+}
+
+impl Session {
+    pub(crate) fn new(id: u64) -> Session {
+        Session {
+            id,
+            db: 0,
+            protocol: Protocol::Resp2,
+            name: None,
+            closing: false,
+            subscriber: None,
+            forwarder: None,
+            transaction: Transaction::new(),
+        }
+    }
+
+    /// internal：是否订阅了任意频道、模式或分片频道
+    pub(crate) fn subscribed(&self) -> bool {
+        self.subscriber
+            .as_ref()
+            .is_some_and(|subscriber| subscriber.is_subscribed())
+    }
+}
+
+/// internal：连接的写端, 由处理命令的线程与推送消息的线程共享
+#[derive(Debug)]
+pub(crate) struct Output {
+    pub(crate) stream: Stream,
+    /// 连接当前使用的协议, 推送消息时使用
+    pub(crate) protocol: Protocol,
+}
+
+impl Output {
+    pub(crate) fn lock(output: &Mutex<Output>) -> MutexGuard<'_, Output> {
+        output
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+/// internal：一条命令的回复（订阅命令对每个频道各有一条回复）, 以及它写入 WAL 的最后一条记录的 LSN
+struct Pending {
+    replies: Vec<Reply>,
+    protocol: Protocol,
+    lsn: Option<u64>,
+}
+
+///
+/// internal：处理连接直到客户端断开、发送 `QUIT`、出现协议错误或者服务关闭
+pub(crate) fn serve(shared: &Shared, stream: Stream, id: u64) {
+    let output = match stream.try_clone() {
+        Ok(writer) => Arc::new(Mutex::new(Output {
+            stream: writer,
+            protocol: Protocol::Resp2,
+        })),
+        Err(_) => return,
+    };
+    let mut session = Session::new(id);
+    process(shared, stream, &output, &mut session);
+    pubsub::close(&mut session);
+}
+
+/// internal：读取并执行命令, 直到连接需要关闭
+fn process(
+    shared: &Shared,
+    mut stream: Stream,
+    output: &Arc<Mutex<Output>>,
+    session: &mut Session,
+) {
+    let mut buf: Vec<u8> = Vec::new();
+    let mut chunk = vec![0u8; READ_CHUNK];
+    let mut parser = CommandParser::default();
+    let limit = shared.config().query_buffer_limit();
+    loop {
+        let eof = match stream.read(&mut chunk) {
+            Ok(0) => true,
+            Ok(n) => {
+                buf.extend_from_slice(&chunk[..n]);
+                false
+            }
+            Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(_) => return,
+        };
+
+        // 执行命令与写回回复期间持有写端的锁, 订阅的确认先于之后发布的消息写回
+        let mut writer = Output::lock(output);
+        let mut pending = Vec::new();
+        let mut wal = None;
+        let mut pos = 0;
+        while !session.closing {
+            match parser.parse(&buf[pos..]) {
+                Ok((Some(args), used)) => {
+                    pos += used;
+                    if !args.is_empty() {
+                        pending.push(execute(shared, session, output, &args, &mut wal));
+                    }
+                }
+                Ok((None, used)) => {
+                    pos += used;
+                    break;
+                }
+                Err(e) => {
+                    let reason = match e {
+                        DBError::Syntax(reason) => reason,
+                        other => other.to_string(),
+                    };
+                    pending.push(Pending {
+                        replies: vec![Reply::Error(format!("ERR Protocol error: {}", reason))],
+                        protocol: session.protocol,
+                        lsn: None,
+                    });
+                    session.closing = true;
+                }
+            }
+        }
+        buf.drain(..pos);
+        // 与 Redis 的 client-query-buffer-limit 相同, 不完整的命令超过上限时关闭连接
+        if buf.len() + parser.buffered() > limit {
+            session.closing = true;
+        }
+
+        if let Err(e) = commit(wal, &pending) {
+            pending
+                .iter_mut()
+                .filter(|p| p.lsn.is_some())
+                .for_each(|p| p.replies = vec![Reply::error(&e)]);
+        }
+        let mut out = Vec::new();
+        for p in &pending {
+            p.replies
+                .iter()
+                .for_each(|reply| reply.encode(p.protocol, &mut out));
+        }
+        writer.protocol = session.protocol;
+        if !out.is_empty() && writer.stream.write_all(&out).is_err() {
+            return;
+        }
+        drop(writer);
+        if eof || session.closing {
+            return;
+        }
+    }
+}
+
+/// internal：等待这批命令写入 WAL 的记录落盘
+fn commit(wal: Option<Wal>, pending: &[Pending]) -> Result<()> {
+    match (wal, pending.iter().filter_map(|p| p.lsn).max()) {
+        (Some(wal), Some(lsn)) => wal.commit(lsn),
+        _ => Ok(()),
+    }
+}
+
+/// internal：执行一条命令, 修改了数据的命令记录 WAL 的句柄与 LSN
+fn execute(
+    shared: &Shared,
+    session: &mut Session,
+    output: &Arc<Mutex<Output>>,
+    args: &[Vec<u8>],
+    wal: &mut Option<Wal>,
+) -> Pending {
+    let name = String::from_utf8_lossy(&args[0]).to_ascii_lowercase();
+    let done = |replies: Vec<Reply>, session: &Session| Pending {
+        replies,
+        protocol: session.protocol,
+        lsn: None,
+    };
+    // RESP2 的连接在订阅期间只能接收推送的消息, 回复无法与消息区分
+    if session.protocol == Protocol::Resp2
+        && session.subscribed()
+        && !pubsub::allowed_while_subscribed(&name)
+    {
+        let error = format!(
+            "ERR Can't execute '{}': only (P|S)SUBSCRIBE / (P|S)UNSUBSCRIBE / PING / QUIT are allowed in this context",
+            name
+        );
+        return done(vec![Reply::Error(error)], session);
+    }
+    if session.transaction.in_multi() && !multi::is_command(&name) && name != "quit" {
+        let reply = multi::queue(session, shared.commands(), &name, args);
+        return done(vec![reply], session);
+    }
+    if is_command(&name) {
+        let replies = match execute_connection(shared, session, &name, args) {
+            Ok(reply) => reply.into_iter().collect(),
+            Err(e) => vec![Reply::error(&e)],
+        };
+        return done(replies, session);
+    }
+    if pubsub::is_command(&name) {
+        let replies = pubsub::execute(shared, session, output, &name, args)
+            .unwrap_or_else(|e| vec![Reply::error(&e)]);
+        return done(replies, session);
+    }
+
+    // 不锁定数据库, 其他连接执行脚本期间也可以执行 SCRIPT KILL
+    if name == "script" {
+        let reply =
+            script::execute_script(shared.scripts(), args).unwrap_or_else(|e| Reply::error(&e));
+        return done(vec![reply], session);
+    }
+
+    let start = Instant::now();
+    let mut dbs = shared.lock();
+    let before = dbs.wal().map(|wal| wal.last_lsn());
+    let mut reply = if multi::is_command(&name) {
+        multi::execute(&mut dbs, shared.config(), session, &name, args)
+    } else if script::is_command(&name) {
+        let scripts = shared.scripts();
+        script::execute(&mut dbs, shared.config(), scripts, session, &name, args)
+    } else {
+        command::execute(&mut dbs, shared.config(), session, &name, args)
+    }
+    .unwrap_or_else(|e| Reply::error(&e));
+    if let Err(e) = dbs.flush_aof() {
+        reply = Reply::error(&e);
+    }
+    let lsn = dbs.flush_wal().filter(|lsn| Some(*lsn) != before);
+    if lsn.is_some() && wal.is_none() {
+        *wal = dbs.wal().cloned();
+    }
+    if command::is_command(&name)
+        || multi::is_command(&name)
+        || script::is_command(&name)
+        || shared.commands().contains(&name)
+    {
+        dbs.record_command(&name, start.elapsed());
+    }
+    Pending {
+        replies: vec![reply],
+        protocol: session.protocol,
+        lsn,
+    }
+}
+
+/// internal：HELLO 的回复
+fn hello_reply(session: &Session) -> Reply {
+    let field = |name: &str| Reply::bulk(name);
+    Reply::Map(vec![
+        (field("server"), Reply::bulk("memkv")),
+        (field("version"), Reply::bulk(env!("CARGO_PKG_VERSION"))),
+        (field("proto"), Reply::Integer(session.protocol.version())),
+        (field("id"), Reply::Integer(session.id as i64)),
+        (field("mode"), Reply::bulk("standalone")),
+        (field("role"), Reply::bulk("master")),
+        (field("modules"), Reply::Array(Vec::new())),
+    ])
+}
+
+/// internal：没有配置密码, 默认用户可以使用任意密码登录
+fn authenticated(user: &[u8]) -> bool {
+    user == b"default"
+}
+
+/// internal：HELLO [protover [AUTH username password] [SETNAME clientname]]
+fn hello(session: &mut Session, args: &[Vec<u8>]) -> Result<Reply> {
+    let protocol = match args.get(1).map(|arg| text(arg)).transpose()?.as_deref() {
+        None => session.protocol,
+        Some("2") => Protocol::Resp2,
+        Some("3") => Protocol::Resp3,
+        Some(version) if version.parse::<i64>().is_ok() => {
+            return Ok(Reply::Error(String::from(
+                "NOPROTO unsupported protocol version",
+            )))
+        }
+        Some(version) => return Err(DBError::NotANumber(String::from(version))),
+    };
+    let mut name = None;
+    let mut options = args.iter().skip(2);
+    while let Some(option) = options.next() {
+        match text(option)?.to_ascii_lowercase().as_str() {
+            "auth" => match (options.next(), options.next()) {
+                (Some(user), Some(_)) => {
+                    if !authenticated(user) {
+                        return Ok(Reply::Error(String::from(
+                            "WRONGPASS invalid username-password pair or user is disabled.",
+                        )));
+                    }
+                }
+                _ => {
+                    return Err(DBError::Syntax(String::from(
+                        "AUTH needs a user and a password",
+                    )))
+                }
+            },
+            "setname" => match options.next() {
+                Some(value) => name = Some(client_name(value)?),
+                None => return Err(DBError::Syntax(String::from("SETNAME needs a name"))),
+            },
+            option => return Err(DBError::Syntax(format!("unexpected option `{}`", option))),
+        }
+    }
+    session.protocol = protocol;
+    if name.is_some() {
+        session.name = name;
+    }
+    Ok(hello_reply(session))
+}
+
+///
+/// internal：COMMAND GETKEYS command [arg ...], 按照自定义命令声明的 `KeySpec` 返回参数中的 key
+///
+/// 返回值：
+///     * 参数中的 key
+///     * 内置命令， 返回 NotSupported
+///     * 命令不存在或者参数个数不正确， 返回对应的错误
+fn getkeys(shared: &Shared, args: &[Vec<u8>]) -> Result<Reply> {
+    let args = command::texts(args)?;
+    let name = args[0].to_ascii_lowercase();
+    if command::is_command(&name)
+        || multi::is_command(&name)
+        || script::is_command(&name)
+        || pubsub::is_command(&name)
+        || is_command(&name)
+    {
+        return Err(DBError::NotSupported(String::from(
+            "COMMAND GETKEYS for built-in commands",
+        )));
+    }
+    let custom = shared.commands().lookup(&args)?;
+    Ok(Reply::strings(
+        custom.key_spec().keys(&args).into_iter().cloned(),
+    ))
+}
+
+/// internal：客户端名称不能包含空白
+fn client_name(arg: &[u8]) -> Result<String> {
+    let name = text(arg)?;
+    if name.chars().any(|c| c.is_whitespace()) {
+        return Err(DBError::Syntax(String::from(
+            "client names cannot contain spaces",
+        )));
+    }
+    Ok(name)
+}
+
+/// internal：CLIENT ID / SETNAME / GETNAME / SETINFO
+fn client(session: &mut Session, args: &[Vec<u8>]) -> Result<Reply> {
+    let sub = text(&args[1])?.to_ascii_lowercase();
+    match (sub.as_str(), args.len()) {
+        ("id", 2) => Ok(Reply::Integer(session.id as i64)),
+        ("getname", 2) => Ok(Reply::optional(session.name.clone())),
+        ("setname", 3) => {
+            let name = client_name(&args[2])?;
+            session.name = if name.is_empty() { None } else { Some(name) };
+            Ok(Reply::ok())
+        }
+        // 客户端库上报的名称与版本, 只需要回复 OK
+        ("setinfo", 4) => Ok(Reply::ok()),
+        ("id", _) | ("getname", _) | ("setname", _) | ("setinfo", _) => {
+            Err(DBError::WrongArity(format!("client|{}", sub)))
+        }
+        _ => Err(DBError::NotSupported(format!(
+            "CLIENT {}",
+            sub.to_uppercase()
+        ))),
+    }
+}
+
+///
+/// internal：执行连接级别的命令
+///
+/// 返回值：命令的回复；`SHUTDOWN` 成功时没有回复, 连接随后关闭
+fn execute_connection(
+    shared: &Shared,
+    session: &mut Session,
+    name: &str,
+    args: &[Vec<u8>],
+) -> Result<Option<Reply>> {
+    let arity = || DBError::WrongArity(String::from(name));
+    let reply = match (name, args.len()) {
+        // 订阅期间 RESP2 连接的 PING 回复与推送的消息格式相同
+        ("ping", 1) | ("ping", 2)
+            if session.protocol == Protocol::Resp2 && session.subscribed() =>
+        {
+            Reply::Array(vec![
+                Reply::bulk("pong"),
+                Reply::Bulk(args.get(1).cloned().unwrap_or_default()),
+            ])
+        }
+        ("ping", 1) => Reply::Status(String::from("PONG")),
+        ("ping", 2) | ("echo", 2) => Reply::Bulk(args[1].clone()),
+        ("quit", _) => {
+            session.closing = true;
+            Reply::ok()
+        }
+        ("hello", _) => hello(session, args)?,
+        ("auth", 2) | ("auth", 3) => Reply::Error(String::from(
+            "ERR AUTH <password> called without any password configured for the default user",
+        )),
+        ("client", n) if n >= 2 => client(session, args)?,
+        ("command", 1) => Reply::Array(Vec::new()),
+        ("command", _) => match text(&args[1])?.to_ascii_lowercase().as_str() {
+            "count" => Reply::Integer(
+                (command::count()
+                    + multi::count()
+                    + script::count()
+                    + pubsub::count()
+                    + CONNECTION_COMMANDS.len()
+                    + shared.commands().len()) as i64,
+            ),
+            "getkeys" if args.len() > 2 => getkeys(shared, &args[2..])?,
+            "docs" => Reply::Map(Vec::new()),
+            _ => Reply::Array(Vec::new()),
+        },
+        ("shutdown", 1) | ("shutdown", 2) => {
+            let mode = match args.get(1).map(|arg| text(arg)).transpose()? {
+                None => ShutdownMode::Default,
+                Some(mode) if mode.eq_ignore_ascii_case("save") => ShutdownMode::Save,
+                Some(mode) if mode.eq_ignore_ascii_case("nosave") => ShutdownMode::NoSave,
+                Some(mode) => return Err(DBError::Syntax(format!("unexpected option `{}`", mode))),
+            };
+            if mode == ShutdownMode::Save && shared.config().dbfile.is_none() {
+                return Err(DBError::NotSupported(String::from(
+                    "SHUTDOWN SAVE without a dbfile",
+                )));
+            }
+            shared.shutdown_handle().request(mode);
+            session.closing = true;
+            return Ok(None);
+        }
+        _ => return Err(arity()),
+    };
+    Ok(Some(reply))
+}
@@ -0,0 +1,691 @@
+//! HTTP/JSON 接口, 供不能使用 RESP 的工具（shell 脚本、浏览器中的页面）访问数据。
+//!
+//! 资源：
+//!     * `/keys/{key}`：字符串；GET 读取, PUT 写入 `{"value": "...", "ttl": 秒}`（ttl 可选）, DELETE 删除任意类型的 key
+//!     * `/sets/{key}/members`：集合的所有成员；GET 读取, PUT 添加 `{"members": [...]}`, DELETE 删除集合
+//!     * `/sets/{key}/members/{member}`：单个成员；GET 检查是否存在, PUT 添加, DELETE 删除
+//!     * `/hashes/{key}`：哈希表的所有域；GET 读取, PUT 写入 `{"fields": {...}}`, DELETE 删除哈希表
+//!     * `/hashes/{key}/{field}`：单个域；GET 读取, PUT 写入 `{"value": "..."}`, DELETE 删除
+//!
+//! 路径中的 key、成员与域按照 URL 编码解码；查询参数 `db` 选择数据库, 默认为 0。
+//!
+//! 错误的状态码由 `DBError` 决定, 参见 `status_code()`, 响应体为 `{"error": "..."}`。
+//!
+//! 每个资源的 ETag 由资源的值计算（不包含生存时间）：
+//!     * GET 的 `If-None-Match` 与当前 ETag 相同时返回 304
+//!     * PUT / DELETE 的 `If-Match` 与当前 ETag 不同（`*` 表示资源必须存在）、
+//!       或者 `If-None-Match` 与当前 ETag 相同（`*` 表示资源必须不存在）时返回 412, 不修改数据
+//!
+//! 条件检查与修改在同一次数据库加锁中完成, 不会被其他连接的修改打断。
+
+use crate::server::Shared;
+use crate::stream::Stream;
+use dbcore::{DBError, Json, Result, ValueType, Wal, KVDB};
+use std::fmt::Write as _;
+use std::io::{BufRead, BufReader, ErrorKind, Read, Write};
+
+/// 请求行与所有请求头的最大长度
+const MAX_HEADER_LEN: usize = 64 * 1024;
+
+/// 请求体的最大长度
+const MAX_BODY_LEN: usize = 16 * 1024 * 1024;
+
+/// 所有资源支持的方法, 用于 405 响应的 Allow 头
+const ALLOWED_METHODS: &str = "GET, HEAD, PUT, DELETE";
+
+/// internal：解析得到的请求
+struct Request {
+    method: String,
+    path: String,
+    query: String,
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+    keep_alive: bool,
+}
+
+impl Request {
+    /// 名为 name 的请求头（忽略大小写）
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(header, _)| header.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// 查询参数 name 的值
+    fn param(&self, name: &str) -> Option<String> {
+        self.query
+            .split('&')
+            .filter_map(|pair| pair.split_once('=').or(Some((pair, ""))))
+            .find(|(param, _)| decode(param).as_deref() == Some(name))
+            .and_then(|(_, value)| decode(value))
+    }
+
+    /// 请求体解析为 JSON
+    fn json(&self) -> Result<Json> {
+        let text = std::str::from_utf8(&self.body)
+            .map_err(|_| DBError::Syntax(String::from("request body is not valid UTF-8")))?;
+        Json::parse(text).map_err(|reason| DBError::Syntax(format!("invalid JSON: {}", reason)))
+    }
+}
+
+/// internal：响应
+struct Response {
+    status: u16,
+    headers: Vec<(&'static str, String)>,
+    body: Option<Json>,
+}
+
+impl Response {
+    fn new(status: u16, body: Option<Json>) -> Response {
+        Response {
+            status,
+            headers: Vec::new(),
+            body,
+        }
+    }
+
+    fn with_etag(mut self, etag: Option<String>) -> Response {
+        if let Some(etag) = etag {
+            self.headers.push(("ETag", etag));
+        }
+        self
+    }
+
+    fn error(status: u16, message: String) -> Response {
+        Response::new(status, Some(object(vec![("error", Json::String(message))])))
+    }
+}
+
+///
+/// 错误对应的 HTTP 状态码
+///
+/// 返回值：
+///     * key 不存在、数据库编号超出范围， 返回 404
+///     * 类型不匹配、目标已存在、后台任务正在执行、重复注册， 返回 409
+///     * 参数不合法、源与目标是同一个 key， 返回 400
+///     * key 的数量达到上限， 返回 507
+///     * 不支持的功能， 返回 501
+///     * 其他错误返回 500
+pub(crate) fn status_code(err: &DBError) -> u16 {
+    match err {
+        DBError::KeyNotFound(_) | DBError::DBIndexOutOfRange(_) => 404,
+        DBError::WrongValueType { .. }
+        | DBError::KeyAlreadyExists(_)
+        | DBError::InProgress(_)
+        | DBError::AlreadyRegistered(_) => 409,
+        DBError::InvalidPayload(_)
+        | DBError::SameKey(_)
+        | DBError::Syntax(_)
+        | DBError::NotANumber(_)
+        | DBError::OutOfRange(_)
+        | DBError::WrongArity(_)
+        | DBError::UnknownCommand(_)
+        | DBError::Transaction(_)
+        | DBError::ExecAbort
+        | DBError::Script(_) => 400,
+        DBError::OutOfKeysSize(_) => 507,
+        DBError::NotSupported(_) => 501,
+        DBError::Io(_) | DBError::Encryption(_) => 500,
+    }
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        201 => "Created",
+        204 => "No Content",
+        304 => "Not Modified",
+        400 => "Bad Request",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        409 => "Conflict",
+        411 => "Length Required",
+        412 => "Precondition Failed",
+        413 => "Payload Too Large",
+        431 => "Request Header Fields Too Large",
+        501 => "Not Implemented",
+        507 => "Insufficient Storage",
+        _ => "Internal Server Error",
+    }
+}
+
+fn object(members: Vec<(&str, Json)>) -> Json {
+    Json::Object(
+        members
+            .into_iter()
+            .map(|(name, value)| (String::from(name), value))
+            .collect(),
+    )
+}
+
+fn strings(values: Vec<String>) -> Json {
+    Json::Array(values.into_iter().map(Json::String).collect())
+}
+
+/// internal：URL 解码, `+` 保持不变；`%` 之后不是两个十六进制数字等编码不合法的情况返回 None
+fn decode(text: &str) -> Option<String> {
+    let bytes = text.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let hex = bytes.get(i + 1..i + 3)?;
+            if !hex.iter().all(u8::is_ascii_hexdigit) {
+                return None;
+            }
+            out.push(u8::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok()?);
+            i += 3;
+        } else {
+            out.push(bytes[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8(out).ok()
+}
+
+/// internal：由资源的值计算 ETag（FNV-1a）
+fn etag(value: &Json) -> String {
+    let hash = value
+        .to_string()
+        .bytes()
+        .fold(0xcbf2_9ce4_8422_2325u64, |hash, byte| {
+            (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+        });
+    format!("\"{:016x}\"", hash)
+}
+
+/// internal：If-Match / If-None-Match 的值是否与当前 ETag 匹配
+fn matches(header: &str, current: Option<&String>) -> bool {
+    match current {
+        None => false,
+        Some(current) => header
+            .split(',')
+            .map(|tag| tag.trim().trim_start_matches("W/"))
+            .any(|tag| tag == "*" || tag == current),
+    }
+}
+
+/// internal：检查条件请求, 条件不满足时返回对应的响应
+fn precondition(req: &Request, current: Option<&String>) -> Option<Response> {
+    let read = req.method == "GET" || req.method == "HEAD";
+    if let Some(header) = req.header("If-Match") {
+        if !read && !matches(header, current) {
+            return Some(Response::error(412, String::from("precondition failed")));
+        }
+    }
+    if let Some(header) = req.header("If-None-Match") {
+        if matches(header, current) {
+            if read {
+                return Some(Response::new(304, None).with_etag(current.cloned()));
+            }
+            return Some(Response::error(412, String::from("precondition failed")));
+        }
+    }
+    None
+}
+
+/// internal：路径对应的资源
+enum Resource {
+    Key(String),
+    Members(String),
+    Member(String, String),
+    Hash(String),
+    Field(String, String),
+}
+
+impl Resource {
+    fn parse(path: &str) -> Option<Resource> {
+        let segments: Option<Vec<String>> = path
+            .trim_start_matches('/')
+            .split('/')
+            .map(decode)
+            .collect();
+        let mut segments = segments?.into_iter();
+        let resource = match (
+            segments.next()?.as_str(),
+            segments.next(),
+            segments.next(),
+            segments.next(),
+        ) {
+            ("keys", Some(key), None, _) => Resource::Key(key),
+            ("sets", Some(key), Some(members), None) if members == "members" => {
+                Resource::Members(key)
+            }
+            ("sets", Some(key), Some(members), Some(member)) if members == "members" => {
+                Resource::Member(key, member)
+            }
+            ("hashes", Some(key), None, _) => Resource::Hash(key),
+            ("hashes", Some(key), Some(field), None) => Resource::Field(key, field),
+            _ => return None,
+        };
+        match segments.next() {
+            Some(_) => None,
+            None if resource.key().is_empty() => None,
+            None => Some(resource),
+        }
+    }
+
+    fn key(&self) -> &str {
+        match self {
+            Resource::Key(key)
+            | Resource::Members(key)
+            | Resource::Member(key, _)
+            | Resource::Hash(key)
+            | Resource::Field(key, _) => key,
+        }
+    }
+
+    /// 资源当前的值, 不存在时返回 None
+    fn current(&self, db: &KVDB) -> Result<Option<Json>> {
+        Ok(match self {
+            Resource::Key(key) => db.get(&String::from(key))?.map(Json::String),
+            Resource::Members(key) => db.smembers(&String::from(key))?.map(|members| {
+                let mut members: Vec<String> = members.into_iter().collect();
+                members.sort();
+                strings(members)
+            }),
+            Resource::Member(key, member) => db
+                .sismember(&String::from(key), member)?
+                .filter(|found| *found)
+                .map(|_| Json::String(member.clone())),
+            Resource::Hash(key) => {
+                let key = String::from(key);
+                match db.hlen(&key)? {
+                    None => None,
+                    Some(_) => {
+                        let (_, mut pairs) = db.hscan(&key, 0, None, usize::MAX)?;
+                        pairs.sort();
+                        Some(Json::Object(
+                            pairs
+                                .into_iter()
+                                .map(|(field, value)| (field, Json::String(value)))
+                                .collect(),
+                        ))
+                    }
+                }
+            }
+            Resource::Field(key, field) => db.hget(&String::from(key), field)?.map(Json::String),
+        })
+    }
+
+    /// GET 的响应体
+    fn representation(&self, value: Json) -> Json {
+        let key = Json::String(String::from(self.key()));
+        match self {
+            Resource::Key(_) => object(vec![("key", key), ("value", value)]),
+            Resource::Members(_) => object(vec![("key", key), ("members", value)]),
+            Resource::Member(_, _) => object(vec![("key", key), ("member", value)]),
+            Resource::Hash(_) => object(vec![("key", key), ("fields", value)]),
+            Resource::Field(_, field) => object(vec![
+                ("key", key),
+                ("field", Json::String(field.clone())),
+                ("value", value),
+            ]),
+        }
+    }
+
+    /// 资源不存在时的响应
+    fn not_found(&self) -> Response {
+        let message = match self {
+            Resource::Member(key, member) => format!("member `{}` not found in `{}`", member, key),
+            Resource::Field(key, field) => format!("field `{}` not found in `{}`", field, key),
+            resource => DBError::KeyNotFound(String::from(resource.key())).to_string(),
+        };
+        Response::error(404, message)
+    }
+}
+
+/// internal：JSON 字符串成员
+fn string_member(body: &Json, name: &str) -> Result<String> {
+    match body.get(name) {
+        Some(Json::String(s)) => Ok(s.clone()),
+        Some(other) => Err(DBError::Syntax(format!(
+            "`{}` must be a string, found {}",
+            name,
+            other.kind()
+        ))),
+        None => Err(DBError::Syntax(format!("`{}` is required", name))),
+    }
+}
+
+/// internal：PUT /keys/{key} 的可选生存时间（秒）
+fn ttl_member(body: &Json) -> Result<Option<u64>> {
+    match body.get("ttl") {
+        None | Some(Json::Null) => Ok(None),
+        Some(Json::Number(ttl)) if *ttl > 0 => Ok(Some(*ttl as u64)),
+        Some(Json::Number(_)) => Err(DBError::OutOfRange(String::from("ttl"))),
+        Some(other) => Err(DBError::Syntax(format!(
+            "`ttl` must be a number, found {}",
+            other.kind()
+        ))),
+    }
+}
+
+/// internal：执行 PUT, existed 为资源在修改之前是否存在
+fn put(db: &mut KVDB, resource: &Resource, req: &Request, existed: bool) -> Result<Response> {
+    let created = |created: bool| if created { 201 } else { 200 };
+    Ok(match resource {
+        Resource::Key(key) => {
+            let body = req.json()?;
+            let value = string_member(&body, "value")?;
+            let ttl = ttl_member(&body)?;
+            db.set(&String::from(key), value, false, false, ttl)?;
+            Response::new(created(!existed), None)
+        }
+        Resource::Members(key) => {
+            let members = match req.json()?.get("members") {
+                Some(Json::Array(items)) if !items.is_empty() => items
+                    .iter()
+                    .map(|item| match item {
+                        Json::String(s) => Ok(s.clone()),
+                        other => Err(DBError::Syntax(format!(
+                            "members must be strings, found {}",
+                            other.kind()
+                        ))),
+                    })
+                    .collect::<Result<Vec<String>>>()?,
+                _ => {
+                    return Err(DBError::Syntax(String::from(
+                        "`members` must be a non-empty array",
+                    )))
+                }
+            };
+            let added = db.sadd(&String::from(key), members)?;
+            let mut response = Response::new(created(!existed), None);
+            response.body = Some(object(vec![("added", Json::Number(added as i64))]));
+            response
+        }
+        Resource::Member(key, member) => {
+            let added = db.sadd(&String::from(key), vec![member.clone()])?;
+            Response::new(created(added > 0), None)
+        }
+        Resource::Hash(key) => {
+            let pairs = match req.json()?.get("fields") {
+                Some(Json::Object(members)) if !members.is_empty() => members
+                    .iter()
+                    .map(|(field, value)| match value {
+                        Json::String(s) => Ok((field.clone(), s.clone())),
+                        other => Err(DBError::Syntax(format!(
+                            "field values must be strings, found {}",
+                            other.kind()
+                        ))),
+                    })
+                    .collect::<Result<Vec<(String, String)>>>()?,
+                _ => {
+                    return Err(DBError::Syntax(String::from(
+                        "`fields` must be a non-empty object",
+                    )))
+                }
+            };
+            db.hmset(&String::from(key), pairs)?;
+            Response::new(created(!existed), None)
+        }
+        Resource::Field(key, field) => {
+            let value = string_member(&req.json()?, "value")?;
+            let added = db.hset(&String::from(key), field.clone(), value)?;
+            Response::new(created(added > 0), None)
+        }
+    })
+}
+
+/// internal：执行 DELETE, 返回资源是否存在
+fn delete(db: &mut KVDB, resource: &Resource) -> Result<bool> {
+    Ok(match resource {
+        Resource::Key(key) => db.del(vec![String::from(key)]) > 0,
+        Resource::Members(key) | Resource::Hash(key) => {
+            let key = String::from(key);
+            let expected = match resource {
+                Resource::Members(_) => ValueType::Set,
+                _ => ValueType::Hash,
+            };
+            match db.key_type(&key) {
+                None => false,
+                Some(actual) if actual == expected => db.del(vec![key]) > 0,
+                Some(actual) => {
+                    return Err(DBError::WrongValueType {
+                        key,
+                        expected,
+                        actual,
+                    })
+                }
+            }
+        }
+        Resource::Member(key, member) => db.srem(&String::from(key), vec![member.clone()])? > 0,
+        Resource::Field(key, field) => db.hdel(&String::from(key), field)?.unwrap_or(0) > 0,
+    })
+}
+
+/// internal：在数据库上处理请求
+fn handle(db: &mut KVDB, req: &Request) -> Result<Response> {
+    let resource = match Resource::parse(&req.path) {
+        Some(resource) => resource,
+        None => {
+            return Ok(Response::error(
+                404,
+                format!("no resource at `{}`", req.path),
+            ))
+        }
+    };
+    let method = req.method.as_str();
+    if !matches!(method, "GET" | "HEAD" | "PUT" | "DELETE") {
+        let mut response = Response::error(405, format!("method {} is not allowed", method));
+        response
+            .headers
+            .push(("Allow", String::from(ALLOWED_METHODS)));
+        return Ok(response);
+    }
+    // 写入字符串、删除 key 不要求原来的类型, 只在需要比较 ETag 时读取原来的值
+    let conditional = req.header("If-Match").is_some() || req.header("If-None-Match").is_some();
+    let current = match (&resource, method) {
+        (Resource::Key(key), "PUT") | (Resource::Key(key), "DELETE") if !conditional => {
+            db.exists(&String::from(key)).then_some(Json::Null)
+        }
+        _ => resource.current(db)?,
+    };
+    let current_etag = current.as_ref().map(etag);
+    if let Some(response) = precondition(req, current_etag.as_ref()) {
+        return Ok(response);
+    }
+
+    match method {
+        "GET" | "HEAD" => Ok(match current {
+            Some(value) => {
+                let mut body = resource.representation(value);
+                if let (Resource::Key(key), Json::Object(members)) = (&resource, &mut body) {
+                    members.push((String::from("ttl"), Json::Number(db.ttl(key))));
+                }
+                Response::new(200, Some(body)).with_etag(current_etag)
+            }
+            None => resource.not_found(),
+        }),
+        "PUT" => {
+            let response = put(db, &resource, req, current.is_some())?;
+            let updated = resource.current(db)?;
+            Ok(response.with_etag(updated.as_ref().map(etag)))
+        }
+        _ => Ok(match delete(db, &resource)? {
+            true => Response::new(204, None),
+            false => resource.not_found(),
+        }),
+    }
+}
+
+/// internal：读取一个请求；客户端在请求之间关闭连接时返回 None, 请求不合法时返回错误响应
+fn read_request<R: BufRead>(reader: &mut R) -> std::result::Result<Option<Request>, Response> {
+    let mut head = Vec::new();
+    let mut lines = Vec::new();
+    loop {
+        let mut line = Vec::new();
+        let limit = (MAX_HEADER_LEN - head.len().min(MAX_HEADER_LEN) + 1) as u64;
+        match reader.by_ref().take(limit).read_until(b'\n', &mut line) {
+            Ok(0) => return Ok(None),
+            Ok(_) => {}
+            Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(_) => return Ok(None),
+        }
+        head.extend_from_slice(&line);
+        if !line.ends_with(b"\n") {
+            return Err(Response::error(
+                431,
+                String::from("request header is too large"),
+            ));
+        }
+        let text = String::from_utf8_lossy(&line)
+            .trim_end_matches(['\r', '\n'])
+            .to_string();
+        if text.is_empty() {
+            if lines.is_empty() {
+                // 请求之间多余的空行
+                continue;
+            }
+            break;
+        }
+        lines.push(text);
+    }
+
+    let bad_request = |message: &str| Response::error(400, String::from(message));
+    let mut request_line = lines[0].split_whitespace();
+    let (method, target, version) = match (
+        request_line.next(),
+        request_line.next(),
+        request_line.next(),
+    ) {
+        (Some(method), Some(target), Some(version)) if version.starts_with("HTTP/1.") => {
+            (method, target, version)
+        }
+        _ => return Err(bad_request("malformed request line")),
+    };
+    let mut headers = Vec::new();
+    for line in &lines[1..] {
+        match line.split_once(':') {
+            Some((name, value)) => {
+                headers.push((String::from(name.trim()), String::from(value.trim())))
+            }
+            None => return Err(bad_request("malformed header")),
+        }
+    }
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+    let mut req = Request {
+        method: method.to_ascii_uppercase(),
+        path: String::from(path),
+        query: String::from(query),
+        headers,
+        body: Vec::new(),
+        keep_alive: version != "HTTP/1.0",
+    };
+    if let Some(connection) = req.header("Connection") {
+        if connection.eq_ignore_ascii_case("close") {
+            req.keep_alive = false;
+        } else if connection.eq_ignore_ascii_case("keep-alive") {
+            req.keep_alive = true;
+        }
+    }
+    if req.header("Transfer-Encoding").is_some() {
+        return Err(Response::error(
+            411,
+            String::from("chunked requests are not supported"),
+        ));
+    }
+    let length = match req.header("Content-Length").map(str::parse::<usize>) {
+        None => 0,
+        Some(Ok(length)) => length,
+        Some(Err(_)) => return Err(bad_request("invalid Content-Length")),
+    };
+    if length > MAX_BODY_LEN {
+        return Err(Response::error(
+            413,
+            String::from("request body is too large"),
+        ));
+    }
+    req.body = vec![0u8; length];
+    if reader.read_exact(&mut req.body).is_err() {
+        return Ok(None);
+    }
+    Ok(Some(req))
+}
+
+/// internal：编码响应
+fn encode(response: &Response, head_only: bool, keep_alive: bool) -> Vec<u8> {
+    let body = response
+        .body
+        .as_ref()
+        .map(|body| format!("{}\n", body))
+        .unwrap_or_default();
+    let mut out = format!(
+        "HTTP/1.1 {} {}\r\n",
+        response.status,
+        reason(response.status)
+    );
+    for (name, value) in &response.headers {
+        let _ = write!(out, "{}: {}\r\n", name, value);
+    }
+    if response.status != 204 && response.status != 304 {
+        out.push_str("Content-Type: application/json\r\n");
+        let _ = write!(out, "Content-Length: {}\r\n", body.len());
+    }
+    let connection = if keep_alive { "keep-alive" } else { "close" };
+    let _ = write!(out, "Connection: {}\r\n\r\n", connection);
+    let mut out = out.into_bytes();
+    if !head_only {
+        out.extend_from_slice(body.as_bytes());
+    }
+    out
+}
+
+/// internal：在锁中处理请求, 修改了数据时返回 WAL 的句柄与 LSN
+fn execute(shared: &Shared, req: &Request) -> (Response, Option<(Wal, u64)>) {
+    let index = match req.param("db").map(|db| db.parse::<usize>()) {
+        None => 0,
+        Some(Ok(index)) => index,
+        Some(Err(_)) => {
+            let e = DBError::NotANumber(req.param("db").unwrap_or_default());
+            return (Response::error(400, e.to_string()), None);
+        }
+    };
+    let mut dbs = shared.lock();
+    let before = dbs.wal().map(|wal| wal.last_lsn());
+    let mut response = match dbs.db_mut(index).and_then(|db| handle(db, req)) {
+        Ok(response) => response,
+        Err(e) => Response::error(status_code(&e), e.to_string()),
+    };
+    if let Err(e) = dbs.flush_aof() {
+        response = Response::error(status_code(&e), e.to_string());
+    }
+    let lsn = dbs.flush_wal().filter(|lsn| Some(*lsn) != before);
+    let pending = lsn.and_then(|lsn| dbs.wal().map(|wal| (wal.clone(), lsn)));
+    (response, pending)
+}
+
+///
+/// internal：处理连接直到客户端断开、请求 `Connection: close`、请求不合法或者服务关闭
+pub(crate) fn serve(shared: &Shared, stream: Stream, _id: u64) {
+    let mut writer = match stream.try_clone() {
+        Ok(writer) => writer,
+        Err(_) => return,
+    };
+    let mut reader = BufReader::new(stream);
+    loop {
+        let (response, head_only, keep_alive) = match read_request(&mut reader) {
+            Ok(None) => return,
+            Ok(Some(req)) => {
+                let (mut response, pending) = execute(shared, &req);
+                // 写入 WAL 的修改落盘之后才返回响应
+                if let Some((wal, lsn)) = pending {
+                    if let Err(e) = wal.commit(lsn) {
+                        response = Response::error(status_code(&e), e.to_string());
+                    }
+                }
+                (response, req.method == "HEAD", req.keep_alive)
+            }
+            Err(response) => (response, false, false),
+        };
+        if writer
+            .write_all(&encode(&response, head_only, keep_alive))
+            .is_err()
+            || !keep_alive
+        {
+            return;
+        }
+    }
+}
@@ -0,0 +1,46 @@
+//! memkv 的网络服务：使用与 Redis 相同的 RESP2 / RESP3 协议访问 `dbcore` 中的数据库,
+//! `redis-cli` 以及现有的 Redis 客户端库可以直接连接。
+//!
+//! 支持：
+//!     * pipelining：客户端可以连续发送多条命令, 回复按照命令的顺序返回
+//!     * 通过 `HELLO` 协商 RESP3, 之后 map、set、null 使用 RESP3 的类型编码
+//!     * 事务：`MULTI` / `EXEC` / `DISCARD` 以及乐观锁 `WATCH`
+//!     * 脚本：`EVAL` / `EVALSHA` 在服务端原子地执行 Rhai 脚本, 脚本按照 SHA1 缓存, 可以限制执行时间或通过 `SCRIPT KILL` 终止
+//!     * 自定义命令：在 `Server::bind()` 之前通过 `Databases::register_command()` 注册, 可以在事务与脚本中使用,
+//!       `COMMAND GETKEYS` 按照命令声明的 `KeySpec` 返回 key
+//!     * 发布/订阅：`PUBLISH` / `SUBSCRIBE` / `PSUBSCRIBE` / `SSUBSCRIBE` / `PUBSUB`, 消息由服务端推送
+//!     * HTTP/JSON 接口：通过 `Server::listen_http()` 开启, 以 REST 资源的形式读写字符串、集合与哈希表
+//!     * Unix socket：通过 `Server::listen_unix()` 开启, 可以按照对端进程的 uid / gid 限制访问
+//!     * TLS：通过 `Server::enable_tls()` 开启, 可以要求客户端证书, 证书文件被修改之后自动重新加载
+//!     * memcached 协议：通过 `Server::listen_memcached()` 开启, 供使用 memcached 客户端的服务读写字符串
+//!     * 优雅关闭：执行完已经收到的命令, 把数据写入磁盘之后退出, 参见 `Server::run()`
+//!
+//! 示例：
+//! ```no_run
+//! use dbcore::Databases;
+//! use memkv_server::{Server, ServerConfig};
+//!
+//! let server = Server::bind("127.0.0.1:6379", Databases::new(16, None), ServerConfig::default()).unwrap();
+//! server.shutdown_handle().shutdown_on_signals().unwrap();
+//! server.run().unwrap();
+//! ```
+
+mod command;
+mod connection;
+mod http;
+mod memcached;
+mod multi;
+mod pubsub;
+mod resp;
+mod script;
+mod server;
+mod stream;
+mod tls;
+#[cfg(unix)]
+mod unix;
+
+pub use resp::{Protocol, Reply};
+pub use server::{Server, ServerConfig, ShutdownHandle, DEFAULT_QUERY_BUFFER_LIMIT};
+pub use tls::{TlsConfig, TlsHandle};
+#[cfg(unix)]
+pub use unix::{AccessRule, PeerCredentials, UnixSocketConfig};
@@ -0,0 +1,1100 @@
+//! memcached 协议, 供使用 memcached 客户端的旧服务访问数据。
+//!
+//! 同一个端口同时支持文本协议与二进制协议：连接的第一个字节为 0x80 时整个连接使用二进制协议, 否则使用文本协议。
+//!
+//! 支持的命令：get / gets、set / add / replace / append / prepend / cas、incr / decr、delete、touch、
+//! flush_all、stats、version、verbosity、quit；二进制协议还支持 noop, 以及各个命令的 quiet 版本。
+//!
+//! 数据保存在 0 号数据库的字符串中, 与 RESP 接口共享：
+//!     * CAS 令牌为 key 的版本号（`KVDB::version()`）, key 被任何接口修改之后都会变化
+//!     * flags 通过 `KVDB::set_flags()` 与 value 一起保存；flags 只保存在内存中, 重启之后为 0
+//!     * 数据库只保存 UTF-8 字符串, 不是 UTF-8 的 value 会被拒绝
+//!     * 不是字符串的 key（集合、哈希表）对 get 不可见, 写入时返回错误
+//!     * exptime 与 memcached 相同：0 表示不过期, 不超过 30 天时为相对时间（秒）, 否则为 Unix 时间戳（秒）,
+//!       负数表示立即过期
+//!     * append / prepend / incr / decr 保留原有的 flags 与生存时间
+
+use crate::http::status_code;
+use crate::server::Shared;
+use crate::stream::Stream;
+use dbcore::{DBError, Result, ValueType, KVDB};
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// 使用的数据库编号, memcached 没有多个数据库
+const DB_INDEX: usize = 0;
+
+/// key 的最大长度, 与 memcached 相同
+const MAX_KEY_LEN: usize = 250;
+
+/// value 的最大长度, 与 memcached 的默认配置（-I 1m）相同
+const MAX_ITEM_SIZE: usize = 1024 * 1024;
+
+/// 文本协议命令行的最大长度
+const MAX_LINE_LEN: usize = 64 * 1024;
+
+/// 不超过该秒数的 exptime 为相对时间, 否则为 Unix 时间戳
+const RELATIVE_EXPTIME_LIMIT: i64 = 60 * 60 * 24 * 30;
+
+/// exptime 的最大值, 更大的 Unix 时间戳转换为毫秒时会溢出
+const MAX_EXPTIME: i64 = (u64::MAX / 1000) as i64;
+
+/// 二进制协议请求与响应的 magic
+const REQUEST_MAGIC: u8 = 0x80;
+const RESPONSE_MAGIC: u8 = 0x81;
+
+/// 二进制协议的头部长度
+const HEADER_LEN: usize = 24;
+
+const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// internal：一条 memcached 数据
+struct Item {
+    value: String,
+    flags: u32,
+    cas: u64,
+}
+
+/// internal：存储命令的方式
+#[derive(Clone, Copy)]
+enum Mode {
+    Set,
+    /// key 不存在时才写入
+    Add,
+    /// key 存在时才写入
+    Replace,
+    Append,
+    Prepend,
+    /// key 的 CAS 令牌与参数相同时才写入
+    Cas(u64),
+}
+
+/// internal：写入与删除的结果
+enum Outcome {
+    /// 执行成功, 参数为新的 CAS 令牌
+    Done(u64),
+    /// 不满足 add / replace / append / prepend 的条件
+    NotStored,
+    /// CAS 令牌不一致
+    Exists,
+    NotFound,
+}
+
+/// internal：incr / decr 的结果
+enum Counter {
+    /// 新的值与新的 CAS 令牌
+    Value(u64, u64),
+    NotFound,
+    NonNumeric,
+}
+
+/// internal：当前时间的毫秒时间戳
+fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+/// internal：exptime 对应的过期时间（毫秒时间戳）, 0 表示不过期（None）；exptime 不能超过 MAX_EXPTIME
+fn expire_at(exptime: i64) -> Option<u64> {
+    match exptime {
+        0 => None,
+        e if e < 0 => Some(0),
+        e if e <= RELATIVE_EXPTIME_LIMIT => Some(now_millis() + e as u64 * 1000),
+        e => Some(e as u64 * 1000),
+    }
+}
+
+/// internal：读取 key, key 不是字符串时视为不存在
+fn item(db: &KVDB, key: &String) -> Option<Item> {
+    let value = db.get(key).ok()??;
+    Some(Item {
+        value,
+        flags: db.flags(key).unwrap_or(0),
+        cas: db.version(key).unwrap_or(0),
+    })
+}
+
+/// internal：写入 key 并设置 flags 与过期时间
+fn write(
+    db: &mut KVDB,
+    key: &String,
+    value: String,
+    flags: u32,
+    expire_at: Option<u64>,
+) -> Result<u64> {
+    db.set(key, value, false, false, None)?;
+    db.set_flags(key, flags);
+    if let Some(when) = expire_at {
+        db.pexpire_at(key, when);
+    }
+    Ok(db.version(key).unwrap_or(0))
+}
+
+/// internal：替换 key 的值, 保留原有的 flags 与生存时间
+fn rewrite(db: &mut KVDB, key: &String, value: String) -> Result<u64> {
+    let flags = db.flags(key).unwrap_or(0);
+    let expire_at = match db.pttl(key) {
+        ms if ms >= 0 => Some(now_millis() + ms as u64),
+        _ => None,
+    };
+    write(db, key, value, flags, expire_at)
+}
+
+/// internal：执行 set / add / replace / append / prepend / cas
+fn store(
+    db: &mut KVDB,
+    mode: Mode,
+    key: &String,
+    value: String,
+    flags: u32,
+    exptime: i64,
+) -> Result<Outcome> {
+    let current = db.version(key);
+    match (mode, current) {
+        (Mode::Add, Some(_)) => return Ok(Outcome::NotStored),
+        (Mode::Replace, None) | (Mode::Append, None) | (Mode::Prepend, None) => {
+            return Ok(Outcome::NotStored)
+        }
+        (Mode::Cas(_), None) => return Ok(Outcome::NotFound),
+        (Mode::Cas(cas), Some(version)) if cas != version => return Ok(Outcome::Exists),
+        _ => {}
+    }
+    let cas = match mode {
+        Mode::Append | Mode::Prepend => {
+            let old = db.get(key)?.unwrap_or_default();
+            let value = match mode {
+                Mode::Append => old + value.as_str(),
+                _ => value + old.as_str(),
+            };
+            rewrite(db, key, value)?
+        }
+        _ => write(db, key, value, flags, expire_at(exptime))?,
+    };
+    Ok(Outcome::Done(cas))
+}
+
+/// internal：字符串 key 的 CAS 令牌；key 不存在或不是字符串时返回 None, 视为 memcached 中不存在的 item
+fn string_version(db: &KVDB, key: &String) -> Option<u64> {
+    match db.key_type(key) {
+        Some(ValueType::String) => db.version(key),
+        _ => None,
+    }
+}
+
+/// internal：删除 key, cas 不为 0 时只有 CAS 令牌一致才删除
+fn delete(db: &mut KVDB, key: &String, cas: u64) -> Outcome {
+    match string_version(db, key) {
+        None => Outcome::NotFound,
+        Some(version) if cas != 0 && cas != version => Outcome::Exists,
+        Some(_) => {
+            db.del(vec![key.clone()]);
+            Outcome::Done(0)
+        }
+    }
+}
+
+///
+/// internal：执行 incr / decr：incr 超过 u64 的范围时回绕, decr 最小为 0
+///
+/// 参数说明：
+///     * initial key 不存在时写入的初始值与 exptime, None 表示不写入（文本协议）
+fn counter(
+    db: &mut KVDB,
+    key: &String,
+    incr: bool,
+    delta: u64,
+    initial: Option<(u64, i64)>,
+) -> Result<Counter> {
+    let current = match db.get(key) {
+        Ok(current) => current,
+        Err(_) => return Ok(Counter::NonNumeric),
+    };
+    let value = match (current, initial) {
+        (None, None) => return Ok(Counter::NotFound),
+        (None, Some((initial, exptime))) => {
+            let cas = write(db, key, initial.to_string(), 0, expire_at(exptime))?;
+            return Ok(Counter::Value(initial, cas));
+        }
+        (Some(current), _) => match current.parse::<u64>() {
+            Ok(value) => value,
+            Err(_) => return Ok(Counter::NonNumeric),
+        },
+    };
+    let value = if incr {
+        value.wrapping_add(delta)
+    } else {
+        value.saturating_sub(delta)
+    };
+    let cas = rewrite(db, key, value.to_string())?;
+    Ok(Counter::Value(value, cas))
+}
+
+/// internal：修改 key 的过期时间
+fn touch(db: &mut KVDB, key: &String, exptime: i64) -> bool {
+    if string_version(db, key).is_none() {
+        return false;
+    }
+    match expire_at(exptime) {
+        Some(when) => db.pexpire_at(key, when),
+        None => {
+            db.persist(key);
+            true
+        }
+    }
+}
+
+/// internal：stats 的内容
+fn stats(shared: &Shared) -> Vec<(&'static str, String)> {
+    let dbs = shared.lock();
+    let uptime = dbs.info().uptime.as_secs();
+    let keyspace = dbs
+        .db(DB_INDEX)
+        .map(|db| db.keyspace_info())
+        .unwrap_or_default();
+    vec![
+        ("pid", std::process::id().to_string()),
+        ("uptime", uptime.to_string()),
+        ("time", (now_millis() / 1000).to_string()),
+        ("version", String::from(VERSION)),
+        (
+            "pointer_size",
+            (std::mem::size_of::<usize>() * 8).to_string(),
+        ),
+        ("curr_connections", shared.connections().to_string()),
+        ("total_connections", shared.total_connections().to_string()),
+        ("curr_items", keyspace.keys.to_string()),
+        ("bytes", keyspace.used_memory.to_string()),
+        ("get_hits", keyspace.keyspace_hits.to_string()),
+        ("get_misses", keyspace.keyspace_misses.to_string()),
+        ("evictions", keyspace.evicted_keys.to_string()),
+    ]
+}
+
+/// internal：在锁中操作 0 号数据库, 修改了数据时等待写入 WAL 的记录落盘之后返回
+fn with_db<T, F>(shared: &Shared, f: F) -> Result<T>
+where
+    F: FnOnce(&mut KVDB) -> Result<T>,
+{
+    let (result, pending) = {
+        let mut dbs = shared.lock();
+        let before = dbs.wal().map(|wal| wal.last_lsn());
+        let result = dbs.db_mut(DB_INDEX).and_then(f);
+        let flushed = dbs.flush_aof();
+        let lsn = dbs.flush_wal().filter(|lsn| Some(*lsn) != before);
+        let pending = lsn.and_then(|lsn| dbs.wal().map(|wal| (wal.clone(), lsn)));
+        (flushed.and(result), pending)
+    };
+    if let Some((wal, lsn)) = pending {
+        wal.commit(lsn)?;
+    }
+    result
+}
+
+/// internal：检查并转换 key
+fn key(bytes: &[u8]) -> std::result::Result<String, &'static str> {
+    if bytes.is_empty() || bytes.len() > MAX_KEY_LEN {
+        return Err("invalid key length");
+    }
+    if bytes.iter().any(|b| b.is_ascii_control()) {
+        return Err("key contains control characters");
+    }
+    String::from_utf8(bytes.to_vec()).map_err(|_| "key is not valid UTF-8")
+}
+
+///
+/// internal：处理连接直到客户端断开、发送 quit、协议错误或者服务关闭
+pub(crate) fn serve(shared: &Shared, stream: Stream, _id: u64) {
+    let writer = match stream.try_clone() {
+        Ok(writer) => writer,
+        Err(_) => return,
+    };
+    let mut reader = BufReader::new(stream);
+    let binary = match reader.fill_buf() {
+        Ok([]) => return,
+        Ok(buf) => buf[0] == REQUEST_MAGIC,
+        Err(_) => return,
+    };
+    if binary {
+        serve_binary(shared, reader, writer);
+    } else {
+        serve_text(shared, reader, writer);
+    }
+}
+
+/// internal：连续收到的命令的回复一起写出, 直到读缓冲区中没有更多的命令
+fn flush(reader: &BufReader<Stream>, writer: &mut Stream, out: &mut Vec<u8>) -> bool {
+    if !reader.buffer().is_empty() {
+        return true;
+    }
+    let ok = writer.write_all(out).is_ok();
+    out.clear();
+    ok
+}
+
+/// internal：文本协议一行的读取结果
+enum Line {
+    Command(Vec<u8>),
+    TooLong,
+    Eof,
+}
+
+fn read_line(reader: &mut BufReader<Stream>) -> io::Result<Line> {
+    let mut line = Vec::new();
+    reader
+        .by_ref()
+        .take(MAX_LINE_LEN as u64 + 1)
+        .read_until(b'\n', &mut line)?;
+    if line.last() != Some(&b'\n') {
+        return Ok(if line.len() > MAX_LINE_LEN {
+            Line::TooLong
+        } else {
+            Line::Eof
+        });
+    }
+    line.pop();
+    if line.last() == Some(&b'\r') {
+        line.pop();
+    }
+    Ok(Line::Command(line))
+}
+
+fn serve_text(shared: &Shared, mut reader: BufReader<Stream>, mut writer: Stream) {
+    let mut out = Vec::new();
+    loop {
+        let line = match read_line(&mut reader) {
+            Ok(Line::Command(line)) => line,
+            Ok(Line::TooLong) => {
+                let _ = writer.write_all(b"CLIENT_ERROR line too long\r\n");
+                return;
+            }
+            Ok(Line::Eof) | Err(_) => {
+                let _ = writer.write_all(&out);
+                return;
+            }
+        };
+        let tokens: Vec<&[u8]> = line
+            .split(|b| *b == b' ' || *b == b'\t')
+            .filter(|token| !token.is_empty())
+            .collect();
+        if tokens.is_empty() {
+            out.extend_from_slice(b"ERROR\r\n");
+        } else if !execute_text(shared, &mut reader, &tokens, &mut out) {
+            let _ = writer.write_all(&out);
+            return;
+        }
+        if !flush(&reader, &mut writer, &mut out) {
+            return;
+        }
+    }
+}
+
+/// internal：解析文本协议中的数字参数
+fn number<T: std::str::FromStr>(token: &[u8]) -> Option<T> {
+    std::str::from_utf8(token).ok()?.parse().ok()
+}
+
+/// internal：解析文本协议的 exptime, 不是整数或者超过 MAX_EXPTIME 时返回 None
+fn exptime(token: &[u8]) -> Option<i64> {
+    number::<i64>(token).filter(|exptime| *exptime <= MAX_EXPTIME)
+}
+
+/// internal：命令的最后一个参数是否为 noreply
+fn noreply(tokens: &[&[u8]], expected: usize) -> Option<bool> {
+    match tokens.len() {
+        n if n == expected => Some(false),
+        n if n == expected + 1 && tokens[expected] == b"noreply" => Some(true),
+        _ => None,
+    }
+}
+
+fn reply(out: &mut Vec<u8>, noreply: bool, line: &str) {
+    if !noreply {
+        out.extend_from_slice(line.as_bytes());
+        out.extend_from_slice(b"\r\n");
+    }
+}
+
+fn server_error(e: &DBError) -> String {
+    format!("SERVER_ERROR {}", e)
+}
+
+///
+/// internal：执行一条文本协议命令, 回复写入 out
+///
+/// 返回值：连接需要关闭时（quit、I/O 错误）返回 false
+fn execute_text(
+    shared: &Shared,
+    reader: &mut BufReader<Stream>,
+    tokens: &[&[u8]],
+    out: &mut Vec<u8>,
+) -> bool {
+    let name = String::from_utf8_lossy(tokens[0]).to_ascii_lowercase();
+    let bad_format = "CLIENT_ERROR bad command line format";
+    match name.as_str() {
+        "get" | "gets" => {
+            if tokens.len() < 2 {
+                reply(out, false, "ERROR");
+                return true;
+            }
+            let keys: std::result::Result<Vec<String>, _> =
+                tokens[1..].iter().map(|token| key(token)).collect();
+            let keys = match keys {
+                Ok(keys) => keys,
+                Err(reason) => {
+                    reply(out, false, &format!("CLIENT_ERROR {}", reason));
+                    return true;
+                }
+            };
+            let items = with_db(shared, |db| {
+                Ok(keys
+                    .iter()
+                    .map(|key| item(db, key))
+                    .collect::<Vec<Option<Item>>>())
+            });
+            match items {
+                Ok(items) => {
+                    for (key, item) in keys.iter().zip(items) {
+                        if let Some(item) = item {
+                            let mut header =
+                                format!("VALUE {} {} {}", key, item.flags, item.value.len());
+                            if name == "gets" {
+                                header.push_str(&format!(" {}", item.cas));
+                            }
+                            reply(out, false, &header);
+                            reply(out, false, &item.value);
+                        }
+                    }
+                    reply(out, false, "END");
+                }
+                Err(e) => reply(out, false, &server_error(&e)),
+            }
+        }
+        "set" | "add" | "replace" | "append" | "prepend" | "cas" => {
+            let expected = if name == "cas" { 6 } else { 5 };
+            let noreply = match noreply(tokens, expected) {
+                Some(noreply) => noreply,
+                None => {
+                    reply(out, false, "ERROR");
+                    return true;
+                }
+            };
+            let bytes: usize = match number(tokens[4]) {
+                Some(bytes) => bytes,
+                None => {
+                    reply(out, false, bad_format);
+                    return true;
+                }
+            };
+            let data = match read_data(reader, bytes) {
+                Ok(Ok(data)) => data,
+                Ok(Err(message)) => {
+                    reply(out, noreply, message);
+                    return true;
+                }
+                Err(_) => return false,
+            };
+            let key = match key(tokens[1]) {
+                Ok(key) => key,
+                Err(reason) => {
+                    reply(out, noreply, &format!("CLIENT_ERROR {}", reason));
+                    return true;
+                }
+            };
+            let (flags, exptime) = match (number::<u32>(tokens[2]), exptime(tokens[3])) {
+                (Some(flags), Some(exptime)) => (flags, exptime),
+                _ => {
+                    reply(out, noreply, bad_format);
+                    return true;
+                }
+            };
+            let mode = match name.as_str() {
+                "set" => Mode::Set,
+                "add" => Mode::Add,
+                "replace" => Mode::Replace,
+                "append" => Mode::Append,
+                "prepend" => Mode::Prepend,
+                _ => match number(tokens[5]) {
+                    Some(cas) => Mode::Cas(cas),
+                    None => {
+                        reply(out, noreply, bad_format);
+                        return true;
+                    }
+                },
+            };
+            let value = match String::from_utf8(data) {
+                Ok(value) => value,
+                Err(_) => {
+                    reply(out, noreply, "SERVER_ERROR value is not valid UTF-8");
+                    return true;
+                }
+            };
+            let line = match with_db(shared, |db| store(db, mode, &key, value, flags, exptime)) {
+                Ok(Outcome::Done(_)) => String::from("STORED"),
+                Ok(Outcome::NotStored) => String::from("NOT_STORED"),
+                Ok(Outcome::Exists) => String::from("EXISTS"),
+                Ok(Outcome::NotFound) => String::from("NOT_FOUND"),
+                Err(e) => server_error(&e),
+            };
+            reply(out, noreply, &line);
+        }
+        "incr" | "decr" => {
+            let noreply = match noreply(tokens, 3) {
+                Some(noreply) => noreply,
+                None => {
+                    reply(out, false, "ERROR");
+                    return true;
+                }
+            };
+            let key = match key(tokens[1]) {
+                Ok(key) => key,
+                Err(reason) => {
+                    reply(out, noreply, &format!("CLIENT_ERROR {}", reason));
+                    return true;
+                }
+            };
+            let delta: u64 = match number(tokens[2]) {
+                Some(delta) => delta,
+                None => {
+                    reply(out, noreply, "CLIENT_ERROR invalid numeric delta argument");
+                    return true;
+                }
+            };
+            let incr = name == "incr";
+            let line = match with_db(shared, |db| counter(db, &key, incr, delta, None)) {
+                Ok(Counter::Value(value, _)) => value.to_string(),
+                Ok(Counter::NotFound) => String::from("NOT_FOUND"),
+                Ok(Counter::NonNumeric) => {
+                    String::from("CLIENT_ERROR cannot increment or decrement non-numeric value")
+                }
+                Err(e) => server_error(&e),
+            };
+            reply(out, noreply, &line);
+        }
+        "delete" => {
+            // 兼容旧版本客户端发送的 `delete <key> 0`
+            let tokens = match tokens {
+                [name, key, zero, rest @ ..] if *zero == b"0" => {
+                    [&[*name, *key][..], rest].concat()
+                }
+                _ => tokens.to_vec(),
+            };
+            let noreply = match noreply(&tokens, 2) {
+                Some(noreply) => noreply,
+                None => {
+                    reply(out, false, "ERROR");
+                    return true;
+                }
+            };
+            let line = match key(tokens[1]) {
+                Ok(key) => match with_db(shared, |db| Ok(delete(db, &key, 0))) {
+                    Ok(Outcome::Done(_)) => String::from("DELETED"),
+                    Ok(_) => String::from("NOT_FOUND"),
+                    Err(e) => server_error(&e),
+                },
+                Err(reason) => format!("CLIENT_ERROR {}", reason),
+            };
+            reply(out, noreply, &line);
+        }
+        "touch" => {
+            let noreply = match noreply(tokens, 3) {
+                Some(noreply) => noreply,
+                None => {
+                    reply(out, false, "ERROR");
+                    return true;
+                }
+            };
+            let line = match (key(tokens[1]), exptime(tokens[2])) {
+                (Ok(key), Some(exptime)) => {
+                    match with_db(shared, |db| Ok(touch(db, &key, exptime))) {
+                        Ok(true) => String::from("TOUCHED"),
+                        Ok(false) => String::from("NOT_FOUND"),
+                        Err(e) => server_error(&e),
+                    }
+                }
+                (Err(reason), _) => format!("CLIENT_ERROR {}", reason),
+                (_, None) => String::from(bad_format),
+            };
+            reply(out, noreply, &line);
+        }
+        "flush_all" => {
+            let noreply = tokens.last() == Some(&&b"noreply"[..]);
+            let args = &tokens[1..tokens.len() - noreply as usize];
+            let line = match args {
+                [] => None,
+                [delay] => match number::<i64>(delay) {
+                    Some(0) => None,
+                    Some(_) => Some("CLIENT_ERROR delayed flush_all is not supported"),
+                    None => Some(bad_format),
+                },
+                _ => Some("ERROR"),
+            };
+            let line = match line {
+                Some(line) => String::from(line),
+                None => match with_db(shared, |db| {
+                    db.flush();
+                    Ok(())
+                }) {
+                    Ok(()) => String::from("OK"),
+                    Err(e) => server_error(&e),
+                },
+            };
+            reply(out, noreply, &line);
+        }
+        "stats" => {
+            if tokens.len() > 1 {
+                reply(out, false, "ERROR");
+                return true;
+            }
+            for (name, value) in stats(shared) {
+                reply(out, false, &format!("STAT {} {}", name, value));
+            }
+            reply(out, false, "END");
+        }
+        "version" => reply(out, false, &format!("VERSION {}", VERSION)),
+        "verbosity" => match noreply(tokens, 2) {
+            Some(noreply) => reply(out, noreply, "OK"),
+            None => reply(out, false, "ERROR"),
+        },
+        "quit" => return false,
+        _ => reply(out, false, "ERROR"),
+    }
+    true
+}
+
+///
+/// internal：读取存储命令的数据块以及结尾的 `\r\n`
+///
+/// 返回值：
+///     * 数据
+///     * 数据过长或者结尾不是 `\r\n` 时返回错误回复, 数据已经被读取并丢弃
+///     * 读取失败返回 I/O 错误
+fn read_data(
+    reader: &mut BufReader<Stream>,
+    bytes: usize,
+) -> io::Result<std::result::Result<Vec<u8>, &'static str>> {
+    if bytes > MAX_ITEM_SIZE {
+        let len = bytes as u64 + 2;
+        if io::copy(&mut reader.by_ref().take(len), &mut io::sink())? < len {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        return Ok(Err("SERVER_ERROR object too large for cache"));
+    }
+    let mut data = vec![0u8; bytes + 2];
+    reader.read_exact(&mut data)?;
+    if !data.ends_with(b"\r\n") {
+        // 丢弃这一行剩余的内容, 避免把数据当作命令执行
+        if data.last() != Some(&b'\n') {
+            reader.read_until(b'\n', &mut Vec::new())?;
+        }
+        return Ok(Err("CLIENT_ERROR bad data chunk"));
+    }
+    data.truncate(bytes);
+    Ok(Ok(data))
+}
+
+/// 二进制协议的命令
+const OP_GET: u8 = 0x00;
+const OP_SET: u8 = 0x01;
+const OP_ADD: u8 = 0x02;
+const OP_REPLACE: u8 = 0x03;
+const OP_DELETE: u8 = 0x04;
+const OP_INCREMENT: u8 = 0x05;
+const OP_DECREMENT: u8 = 0x06;
+const OP_QUIT: u8 = 0x07;
+const OP_FLUSH: u8 = 0x08;
+const OP_GETQ: u8 = 0x09;
+const OP_NOOP: u8 = 0x0a;
+const OP_VERSION: u8 = 0x0b;
+const OP_GETK: u8 = 0x0c;
+const OP_GETKQ: u8 = 0x0d;
+const OP_APPEND: u8 = 0x0e;
+const OP_PREPEND: u8 = 0x0f;
+const OP_STAT: u8 = 0x10;
+const OP_SETQ: u8 = 0x11;
+const OP_ADDQ: u8 = 0x12;
+const OP_REPLACEQ: u8 = 0x13;
+const OP_DELETEQ: u8 = 0x14;
+const OP_INCREMENTQ: u8 = 0x15;
+const OP_DECREMENTQ: u8 = 0x16;
+const OP_QUITQ: u8 = 0x17;
+const OP_FLUSHQ: u8 = 0x18;
+const OP_APPENDQ: u8 = 0x19;
+const OP_PREPENDQ: u8 = 0x1a;
+const OP_VERBOSITY: u8 = 0x1b;
+const OP_TOUCH: u8 = 0x1c;
+
+/// 二进制协议的响应状态
+const STATUS_OK: u16 = 0x0000;
+const STATUS_KEY_NOT_FOUND: u16 = 0x0001;
+const STATUS_KEY_EXISTS: u16 = 0x0002;
+const STATUS_VALUE_TOO_LARGE: u16 = 0x0003;
+const STATUS_INVALID_ARGUMENTS: u16 = 0x0004;
+const STATUS_NOT_STORED: u16 = 0x0005;
+const STATUS_NON_NUMERIC: u16 = 0x0006;
+const STATUS_UNKNOWN_COMMAND: u16 = 0x0081;
+const STATUS_OUT_OF_MEMORY: u16 = 0x0082;
+const STATUS_NOT_SUPPORTED: u16 = 0x0083;
+const STATUS_INTERNAL_ERROR: u16 = 0x0084;
+
+/// internal：二进制协议的请求
+struct Request {
+    opcode: u8,
+    opaque: u32,
+    cas: u64,
+    extras: Vec<u8>,
+    key: Vec<u8>,
+    value: Vec<u8>,
+}
+
+impl Request {
+    /// 命令的 quiet 版本成功时不回复（get 的 quiet 版本未命中时不回复）
+    fn quiet(&self) -> bool {
+        matches!(
+            self.opcode,
+            OP_GETQ
+                | OP_GETKQ
+                | OP_SETQ
+                | OP_ADDQ
+                | OP_REPLACEQ
+                | OP_DELETEQ
+                | OP_INCREMENTQ
+                | OP_DECREMENTQ
+                | OP_QUITQ
+                | OP_FLUSHQ
+                | OP_APPENDQ
+                | OP_PREPENDQ
+        )
+    }
+
+    fn u32_at(&self, pos: usize) -> u32 {
+        let mut bytes = [0u8; 4];
+        bytes.copy_from_slice(&self.extras[pos..pos + 4]);
+        u32::from_be_bytes(bytes)
+    }
+
+    fn u64_at(&self, pos: usize) -> u64 {
+        let mut bytes = [0u8; 8];
+        bytes.copy_from_slice(&self.extras[pos..pos + 8]);
+        u64::from_be_bytes(bytes)
+    }
+}
+
+/// internal：二进制协议的响应
+struct Response {
+    status: u16,
+    cas: u64,
+    extras: Vec<u8>,
+    key: Vec<u8>,
+    value: Vec<u8>,
+}
+
+impl Response {
+    fn new(status: u16) -> Response {
+        Response {
+            status,
+            cas: 0,
+            extras: Vec::new(),
+            key: Vec::new(),
+            value: Vec::new(),
+        }
+    }
+
+    fn ok(cas: u64) -> Response {
+        Response {
+            cas,
+            ..Response::new(STATUS_OK)
+        }
+    }
+
+    /// 错误响应, 响应体为错误信息
+    fn error(status: u16, message: &str) -> Response {
+        Response {
+            value: message.as_bytes().to_vec(),
+            ..Response::new(status)
+        }
+    }
+
+    fn from_error(e: &DBError) -> Response {
+        let status = match status_code(e) {
+            507 => STATUS_OUT_OF_MEMORY,
+            _ => STATUS_INTERNAL_ERROR,
+        };
+        Response::error(status, &e.to_string())
+    }
+
+    fn encode(&self, opcode: u8, opaque: u32, out: &mut Vec<u8>) {
+        let body = self.extras.len() + self.key.len() + self.value.len();
+        out.push(RESPONSE_MAGIC);
+        out.push(opcode);
+        out.extend_from_slice(&(self.key.len() as u16).to_be_bytes());
+        out.push(self.extras.len() as u8);
+        out.push(0);
+        out.extend_from_slice(&self.status.to_be_bytes());
+        out.extend_from_slice(&(body as u32).to_be_bytes());
+        out.extend_from_slice(&opaque.to_be_bytes());
+        out.extend_from_slice(&self.cas.to_be_bytes());
+        out.extend_from_slice(&self.extras);
+        out.extend_from_slice(&self.key);
+        out.extend_from_slice(&self.value);
+    }
+}
+
+///
+/// internal：读取一个二进制协议的请求
+///
+/// 返回值：
+///     * 请求; 连接关闭时返回 None
+///     * 请求格式不正确时返回错误响应, 之后连接被关闭
+fn read_request(
+    reader: &mut BufReader<Stream>,
+) -> io::Result<std::result::Result<Option<Request>, Response>> {
+    let mut header = [0u8; HEADER_LEN];
+    match reader.read_exact(&mut header) {
+        Ok(()) => {}
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(Ok(None)),
+        Err(e) => return Err(e),
+    }
+    if header[0] != REQUEST_MAGIC {
+        return Ok(Err(Response::error(
+            STATUS_INVALID_ARGUMENTS,
+            "invalid magic",
+        )));
+    }
+    let key_len = u16::from_be_bytes([header[2], header[3]]) as usize;
+    let extras_len = header[4] as usize;
+    let body_len = u32::from_be_bytes([header[8], header[9], header[10], header[11]]) as usize;
+    if body_len > MAX_ITEM_SIZE + MAX_KEY_LEN + u8::MAX as usize {
+        return Ok(Err(Response::error(STATUS_VALUE_TOO_LARGE, "too large")));
+    }
+    if key_len + extras_len > body_len {
+        return Ok(Err(Response::error(
+            STATUS_INVALID_ARGUMENTS,
+            "invalid body length",
+        )));
+    }
+    let mut body = vec![0u8; body_len];
+    reader.read_exact(&mut body)?;
+    let value = body.split_off(extras_len + key_len);
+    let key = body.split_off(extras_len);
+    let mut opaque = [0u8; 4];
+    opaque.copy_from_slice(&header[12..16]);
+    let mut cas = [0u8; 8];
+    cas.copy_from_slice(&header[16..24]);
+    Ok(Ok(Some(Request {
+        opcode: header[1],
+        opaque: u32::from_be_bytes(opaque),
+        cas: u64::from_be_bytes(cas),
+        extras: body,
+        key,
+        value,
+    })))
+}
+
+fn serve_binary(shared: &Shared, mut reader: BufReader<Stream>, mut writer: Stream) {
+    let mut out = Vec::new();
+    loop {
+        let req = match read_request(&mut reader) {
+            Ok(Ok(Some(req))) => req,
+            Ok(Ok(None)) | Err(_) => {
+                let _ = writer.write_all(&out);
+                return;
+            }
+            Ok(Err(response)) => {
+                response.encode(0, 0, &mut out);
+                let _ = writer.write_all(&out);
+                return;
+            }
+        };
+        let responses = execute_binary(shared, &req);
+        let quit = matches!(req.opcode, OP_QUIT | OP_QUITQ);
+        for response in responses {
+            response.encode(req.opcode, req.opaque, &mut out);
+        }
+        if quit {
+            let _ = writer.write_all(&out);
+            return;
+        }
+        if !flush(&reader, &mut writer, &mut out) {
+            return;
+        }
+    }
+}
+
+/// internal：执行一个二进制协议的请求, 返回需要发送的响应（quiet 命令可能没有响应）
+fn execute_binary(shared: &Shared, req: &Request) -> Vec<Response> {
+    let response = match req.opcode {
+        OP_NOOP | OP_VERBOSITY => Response::ok(0),
+        OP_VERSION => Response {
+            value: VERSION.as_bytes().to_vec(),
+            ..Response::ok(0)
+        },
+        OP_QUIT | OP_QUITQ => Response::ok(0),
+        OP_STAT => {
+            if !req.key.is_empty() {
+                return vec![Response::error(STATUS_KEY_NOT_FOUND, "Not found")];
+            }
+            let mut responses: Vec<Response> = stats(shared)
+                .into_iter()
+                .map(|(name, value)| Response {
+                    key: name.as_bytes().to_vec(),
+                    value: value.into_bytes(),
+                    ..Response::ok(0)
+                })
+                .collect();
+            responses.push(Response::ok(0));
+            return responses;
+        }
+        OP_FLUSH | OP_FLUSHQ => match req.extras.len() {
+            0 => flush_binary(shared),
+            4 if req.u32_at(0) == 0 => flush_binary(shared),
+            4 => Response::error(STATUS_NOT_SUPPORTED, "delayed flush is not supported"),
+            _ => Response::error(STATUS_INVALID_ARGUMENTS, "Invalid arguments"),
+        },
+        _ => {
+            let key = match key(&req.key) {
+                Ok(key) => key,
+                Err(reason) => return vec![unknown_or_invalid(req, reason)],
+            };
+            match execute_key(shared, req, &key) {
+                Ok(response) => response,
+                Err(e) => Response::from_error(&e),
+            }
+        }
+    };
+    let quiet = req.quiet()
+        && match req.opcode {
+            OP_GETQ | OP_GETKQ => response.status == STATUS_KEY_NOT_FOUND,
+            _ => response.status == STATUS_OK,
+        };
+    if quiet {
+        Vec::new()
+    } else {
+        vec![response]
+    }
+}
+
+fn flush_binary(shared: &Shared) -> Response {
+    match with_db(shared, |db| {
+        db.flush();
+        Ok(())
+    }) {
+        Ok(()) => Response::ok(0),
+        Err(e) => Response::from_error(&e),
+    }
+}
+
+/// internal：key 不合法时的响应, 未知的命令优先返回 UNKNOWN_COMMAND
+fn unknown_or_invalid(req: &Request, reason: &str) -> Response {
+    if req.opcode > OP_TOUCH {
+        Response::error(STATUS_UNKNOWN_COMMAND, "Unknown command")
+    } else {
+        Response::error(STATUS_INVALID_ARGUMENTS, reason)
+    }
+}
+
+/// internal：执行需要 key 的二进制协议命令
+fn execute_key(shared: &Shared, req: &Request, key: &String) -> Result<Response> {
+    let invalid = || Response::error(STATUS_INVALID_ARGUMENTS, "Invalid arguments");
+    Ok(match req.opcode {
+        OP_GET | OP_GETQ | OP_GETK | OP_GETKQ => match with_db(shared, |db| Ok(item(db, key)))? {
+            Some(item) => Response {
+                cas: item.cas,
+                extras: item.flags.to_be_bytes().to_vec(),
+                key: match req.opcode {
+                    OP_GETK | OP_GETKQ => req.key.clone(),
+                    _ => Vec::new(),
+                },
+                value: item.value.into_bytes(),
+                ..Response::new(STATUS_OK)
+            },
+            None => Response {
+                key: match req.opcode {
+                    OP_GETK | OP_GETKQ => req.key.clone(),
+                    _ => Vec::new(),
+                },
+                ..Response::error(STATUS_KEY_NOT_FOUND, "Not found")
+            },
+        },
+        OP_SET | OP_SETQ | OP_ADD | OP_ADDQ | OP_REPLACE | OP_REPLACEQ | OP_APPEND | OP_APPENDQ
+        | OP_PREPEND | OP_PREPENDQ => {
+            let (flags, exptime) = match (req.opcode, req.extras.len()) {
+                (OP_APPEND, 0) | (OP_APPENDQ, 0) | (OP_PREPEND, 0) | (OP_PREPENDQ, 0) => (0, 0),
+                (OP_APPEND, _) | (OP_APPENDQ, _) | (OP_PREPEND, _) | (OP_PREPENDQ, _) => {
+                    return Ok(invalid())
+                }
+                (_, 8) => (req.u32_at(0), i64::from(req.u32_at(4))),
+                _ => return Ok(invalid()),
+            };
+            let mode = match req.opcode {
+                _ if req.cas != 0 => Mode::Cas(req.cas),
+                OP_SET | OP_SETQ => Mode::Set,
+                OP_ADD | OP_ADDQ => Mode::Add,
+                OP_REPLACE | OP_REPLACEQ => Mode::Replace,
+                OP_APPEND | OP_APPENDQ => Mode::Append,
+                _ => Mode::Prepend,
+            };
+            if req.value.len() > MAX_ITEM_SIZE {
+                return Ok(Response::error(STATUS_VALUE_TOO_LARGE, "Too large"));
+            }
+            let value = match String::from_utf8(req.value.clone()) {
+                Ok(value) => value,
+                Err(_) => {
+                    return Ok(Response::error(
+                        STATUS_INVALID_ARGUMENTS,
+                        "value is not valid UTF-8",
+                    ))
+                }
+            };
+            match with_db(shared, |db| store(db, mode, key, value, flags, exptime))? {
+                Outcome::Done(cas) => Response::ok(cas),
+                Outcome::NotStored => match mode {
+                    Mode::Add => Response::error(STATUS_KEY_EXISTS, "Data exists for key"),
+                    Mode::Replace => Response::error(STATUS_KEY_NOT_FOUND, "Not found"),
+                    _ => Response::error(STATUS_NOT_STORED, "Not stored"),
+                },
+                Outcome::Exists => Response::error(STATUS_KEY_EXISTS, "Data exists for key"),
+                Outcome::NotFound => Response::error(STATUS_KEY_NOT_FOUND, "Not found"),
+            }
+        }
+        OP_DELETE | OP_DELETEQ => match with_db(shared, |db| Ok(delete(db, key, req.cas)))? {
+            Outcome::Done(_) => Response::ok(0),
+            Outcome::Exists => Response::error(STATUS_KEY_EXISTS, "Data exists for key"),
+            _ => Response::error(STATUS_KEY_NOT_FOUND, "Not found"),
+        },
+        OP_INCREMENT | OP_INCREMENTQ | OP_DECREMENT | OP_DECREMENTQ => {
+            if req.extras.len() != 20 {
+                return Ok(invalid());
+            }
+            let delta = req.u64_at(0);
+            let initial = match req.u32_at(16) {
+                u32::MAX => None,
+                exptime => Some((req.u64_at(8), i64::from(exptime))),
+            };
+            let incr = matches!(req.opcode, OP_INCREMENT | OP_INCREMENTQ);
+            match with_db(shared, |db| counter(db, key, incr, delta, initial))? {
+                Counter::Value(value, cas) => Response {
+                    value: value.to_be_bytes().to_vec(),
+                    ..Response::ok(cas)
+                },
+                Counter::NotFound => Response::error(STATUS_KEY_NOT_FOUND, "Not found"),
+                Counter::NonNumeric => Response::error(
+                    STATUS_NON_NUMERIC,
+                    "Non-numeric server-side value for incr or decr",
+                ),
+            }
+        }
+        OP_TOUCH => {
+            if req.extras.len() != 4 {
+                return Ok(invalid());
+            }
+            let exptime = i64::from(req.u32_at(0));
+            match with_db(shared, |db| Ok(touch(db, key, exptime)))? {
+                true => Response::ok(0),
+                false => Response::error(STATUS_KEY_NOT_FOUND, "Not found"),
+            }
+        }
+        _ => Response::error(STATUS_UNKNOWN_COMMAND, "Unknown command"),
+    })
+}
@@ -0,0 +1,125 @@
+//! 事务命令：`MULTI` / `EXEC` / `DISCARD` / `WATCH` / `UNWATCH`, 事务的状态参见 `dbcore::Transaction`。
+//!
+//! `MULTI` 之后的数据库命令只检查命令名称与参数个数, 回复 `+QUEUED`；
+//! `EXEC` 在同一次持有数据库锁期间依次执行所有排队的命令, 其他连接的命令不会穿插其中。
+//! 连接级别的命令、发布/订阅命令、脚本命令以及带有 NO_MULTI 标记的自定义命令不能在事务中排队。
+
+use crate::command::{self, text};
+use crate::connection::{self, Session};
+use crate::pubsub;
+use crate::resp::Reply;
+use crate::script;
+use crate::ServerConfig;
+use dbcore::{CommandFlags, CommandRegistry, DBError, Databases, Result};
+use std::time::Instant;
+
+/// 事务命令及其参数个数（包括命令名称）, 负数 -N 表示至少 N 个
+const COMMANDS: &[(&str, i32)] = &[
+    ("multi", 1),
+    ("exec", 1),
+    ("discard", 1),
+    ("watch", -2),
+    ("unwatch", 1),
+];
+
+/// internal：name（小写）是否为事务命令
+pub(crate) fn is_command(name: &str) -> bool {
+    COMMANDS.iter().any(|(command, _)| *command == name)
+}
+
+/// internal：事务命令的数量
+pub(crate) fn count() -> usize {
+    COMMANDS.len()
+}
+
+fn check_arity(name: &str, args: usize) -> Result<()> {
+    match COMMANDS.iter().find(|(command, _)| *command == name) {
+        Some((_, arity)) if *arity >= 0 && args == *arity as usize => Ok(()),
+        Some((_, arity)) if *arity < 0 && args >= arity.unsigned_abs() as usize => Ok(()),
+        Some(_) => Err(DBError::WrongArity(String::from(name))),
+        None => Err(DBError::UnknownCommand(String::from(name))),
+    }
+}
+
+///
+/// internal：事务中的命令排队
+///
+/// 返回值：
+///     * 排队成功返回 `+QUEUED`
+///     * 命令不存在、参数个数不正确或者不能在事务中执行时返回错误, 之后的 `EXEC` 放弃整个事务
+pub(crate) fn queue(
+    session: &mut Session,
+    commands: &CommandRegistry,
+    name: &str,
+    args: &[Vec<u8>],
+) -> Reply {
+    let not_allowed =
+        || DBError::Transaction(format!("Command '{}' is not allowed inside MULTI", name));
+    let checked = if command::is_command(name) {
+        command::check_arity(name, args.len())
+    } else if connection::is_command(name) || pubsub::is_command(name) || script::is_command(name) {
+        Err(not_allowed())
+    } else {
+        let custom = command::texts(args).and_then(|args| commands.lookup(&args));
+        match custom {
+            Ok(custom) if custom.flags().contains(CommandFlags::NO_MULTI) => Err(not_allowed()),
+            Ok(_) => Ok(()),
+            Err(e) => Err(e),
+        }
+    };
+    match checked {
+        Ok(()) => {
+            session.transaction.queue(args.to_vec());
+            Reply::Status(String::from("QUEUED"))
+        }
+        Err(e) => {
+            session.transaction.abort();
+            Reply::error(&e)
+        }
+    }
+}
+
+///
+/// internal：执行一条事务命令, name 为小写的命令名称
+///
+/// 返回值：
+///     * 命令的回复；`EXEC` 返回所有排队命令的回复, 被监视的 key 发生变化时返回 nil
+///     * 参数个数不正确、使用方式不正确， 返回对应的错误
+pub(crate) fn execute(
+    dbs: &mut Databases,
+    config: &ServerConfig,
+    session: &mut Session,
+    name: &str,
+    args: &[Vec<u8>],
+) -> Result<Reply> {
+    check_arity(name, args.len())?;
+    match name {
+        "multi" => session.transaction.multi().map(|_| Reply::ok()),
+        "discard" => session.transaction.discard().map(|_| Reply::ok()),
+        "watch" => {
+            for arg in &args[1..] {
+                session.transaction.watch(&*dbs, session.db, &text(arg)?)?;
+            }
+            Ok(Reply::ok())
+        }
+        "unwatch" => {
+            session.transaction.unwatch();
+            Ok(Reply::ok())
+        }
+        "exec" => {
+            // 执行期间需要同时修改事务与连接的状态（SELECT 等）
+            let mut transaction = std::mem::take(&mut session.transaction);
+            let replies = transaction.exec(dbs, |dbs, args| {
+                let start = Instant::now();
+                let name = String::from_utf8_lossy(&args[0]).to_ascii_lowercase();
+                let reply = command::execute(dbs, config, session, &name, &args)
+                    .unwrap_or_else(|e| Reply::error(&e));
+                dbs.record_command(&name, start.elapsed());
+                reply
+            });
+            session.transaction = transaction;
+            Ok(replies?.map_or(Reply::NilArray, Reply::Array))
+        }
+        _ => Err(DBError::UnknownCommand(String::from(name))),
+    }
+}
@@ -0,0 +1,267 @@
+//! 发布/订阅命令：`SUBSCRIBE` / `PSUBSCRIBE` / `SSUBSCRIBE` 及对应的取消订阅命令, `PUBLISH`、`SPUBLISH` 与 `PUBSUB`。
+//!
+//! 连接第一次订阅时创建 `dbcore::Subscriber`, 并启动一个线程把收到的消息推送给客户端。
+//! 订阅者的缓冲区溢出（客户端读取得太慢）时连接被关闭。
+
+use crate::command::{text, texts};
+use crate::connection::{Output, Session};
+use crate::resp::Reply;
+use crate::server::Shared;
+use dbcore::{DBError, Message, RecvError, Result, Subscriber};
+use std::io::Write;
+use std::net::Shutdown;
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+/// 发布/订阅命令及其参数个数（包括命令名称）, 负数 -N 表示至少 N 个
+const COMMANDS: &[(&str, i32)] = &[
+    ("subscribe", -2),
+    ("unsubscribe", -1),
+    ("psubscribe", -2),
+    ("punsubscribe", -1),
+    ("ssubscribe", -2),
+    ("sunsubscribe", -1),
+    ("publish", 3),
+    ("spublish", 3),
+    ("pubsub", -2),
+];
+
+/// 推送消息时最多合并写回的消息数量
+const MAX_BATCH: usize = 64;
+
+/// internal：name（小写）是否为发布/订阅命令
+pub(crate) fn is_command(name: &str) -> bool {
+    COMMANDS.iter().any(|(command, _)| *command == name)
+}
+
+/// internal：发布/订阅命令的数量
+pub(crate) fn count() -> usize {
+    COMMANDS.len()
+}
+
+/// internal：RESP2 的连接在订阅期间可以执行的命令
+pub(crate) fn allowed_while_subscribed(name: &str) -> bool {
+    matches!(
+        name,
+        "subscribe"
+            | "unsubscribe"
+            | "psubscribe"
+            | "punsubscribe"
+            | "ssubscribe"
+            | "sunsubscribe"
+            | "ping"
+            | "quit"
+    )
+}
+
+fn check_arity(name: &str, args: usize) -> Result<()> {
+    match COMMANDS.iter().find(|(command, _)| *command == name) {
+        Some((_, arity)) if *arity >= 0 && args == *arity as usize => Ok(()),
+        Some((_, arity)) if *arity < 0 && args >= arity.unsigned_abs() as usize => Ok(()),
+        Some(_) => Err(DBError::WrongArity(String::from(name))),
+        None => Err(DBError::UnknownCommand(String::from(name))),
+    }
+}
+
+/// internal：推送给客户端的消息
+fn message_reply(message: &Message) -> Reply {
+    let mut items = match (&message.pattern, message.sharded) {
+        (Some(pattern), _) => vec![Reply::bulk("pmessage"), Reply::bulk(pattern.as_str())],
+        (None, true) => vec![Reply::bulk("smessage")],
+        (None, false) => vec![Reply::bulk("message")],
+    };
+    items.push(Reply::bulk(message.channel.as_str()));
+    items.push(Reply::bulk(message.payload.as_str()));
+    Reply::Push(items)
+}
+
+/// internal：订阅与取消订阅的确认, count 为之后订阅的数量
+fn confirmation(kind: &str, target: Option<&String>, count: usize) -> Reply {
+    Reply::Push(vec![
+        Reply::bulk(kind),
+        Reply::optional(target.cloned()),
+        Reply::Integer(count as i64),
+    ])
+}
+
+/// internal：把订阅者收到的消息写回客户端, 直到订阅者关闭；缓冲区溢出时关闭连接
+fn forward(subscriber: &Subscriber, output: &Mutex<Output>) {
+    // 溢出时立即关闭连接, 即使这个线程正阻塞在写入上
+    if let Ok(stream) = Output::lock(output).stream.try_clone() {
+        subscriber.on_overflow(move || {
+            let _ = stream.shutdown(Shutdown::Both);
+        });
+    }
+    loop {
+        let mut messages = match subscriber.recv() {
+            Ok(message) => vec![message],
+            Err(RecvError::Overflow) => {
+                let _ = Output::lock(output).stream.shutdown(Shutdown::Both);
+                return;
+            }
+            Err(RecvError::Closed) => return,
+        };
+        while messages.len() < MAX_BATCH {
+            match subscriber.try_recv() {
+                Ok(Some(message)) => messages.push(message),
+                _ => break,
+            }
+        }
+        let mut output = Output::lock(output);
+        let mut out = Vec::new();
+        for message in &messages {
+            message_reply(message).encode(output.protocol, &mut out);
+        }
+        if output.stream.write_all(&out).is_err() {
+            return;
+        }
+    }
+}
+
+/// internal：连接的订阅者, 第一次调用时创建订阅者并启动推送消息的线程
+fn subscriber(
+    shared: &Shared,
+    session: &mut Session,
+    output: &Arc<Mutex<Output>>,
+) -> Result<Arc<Subscriber>> {
+    if let Some(subscriber) = &session.subscriber {
+        return Ok(subscriber.clone());
+    }
+    let subscriber = Arc::new(shared.pubsub().subscriber());
+    let (forwarded, output) = (subscriber.clone(), output.clone());
+    let forwarder = thread::Builder::new()
+        .name(format!("memkv-pubsub-{}", session.id))
+        .spawn(move || forward(&forwarded, &output))
+        .map_err(|e| DBError::Io(format!("failed to start the subscriber: {}", e)))?;
+    session.subscriber = Some(subscriber.clone());
+    session.forwarder = Some(forwarder);
+    Ok(subscriber)
+}
+
+/// internal：关闭连接的订阅者, 等待推送消息的线程退出
+pub(crate) fn close(session: &mut Session) {
+    if let Some(subscriber) = session.subscriber.take() {
+        subscriber.close();
+    }
+    if let Some(forwarder) = session.forwarder.take() {
+        let _ = forwarder.join();
+    }
+}
+
+/// internal：UNSUBSCRIBE / PUNSUBSCRIBE / SUNSUBSCRIBE [name ...], 没有参数时取消所有订阅
+fn unsubscribe(session: &Session, name: &str, args: &[Vec<u8>]) -> Result<Vec<Reply>> {
+    let mut targets = texts(&args[1..])?;
+    let subscriber = match &session.subscriber {
+        Some(subscriber) => subscriber,
+        None if targets.is_empty() => return Ok(vec![confirmation(name, None, 0)]),
+        None => {
+            return Ok(targets
+                .iter()
+                .map(|target| confirmation(name, Some(target), 0))
+                .collect())
+        }
+    };
+    let count = || match name {
+        "sunsubscribe" => subscriber.shard_channels().len(),
+        _ => subscriber.channels().len() + subscriber.patterns().len(),
+    };
+    if targets.is_empty() {
+        targets = match name {
+            "unsubscribe" => subscriber.channels(),
+            "punsubscribe" => subscriber.patterns(),
+            _ => subscriber.shard_channels(),
+        };
+        if targets.is_empty() {
+            return Ok(vec![confirmation(name, None, count())]);
+        }
+    }
+    Ok(targets
+        .iter()
+        .map(|target| {
+            let count = match name {
+                "unsubscribe" => subscriber.unsubscribe(target),
+                "punsubscribe" => subscriber.punsubscribe(target),
+                _ => subscriber.sunsubscribe(target),
+            };
+            confirmation(name, Some(target), count)
+        })
+        .collect())
+}
+
+/// internal：PUBSUB CHANNELS / NUMSUB / NUMPAT / SHARDCHANNELS / SHARDNUMSUB
+fn introspect(shared: &Shared, args: &[Vec<u8>]) -> Result<Reply> {
+    let pubsub = shared.pubsub();
+    let sub = text(&args[1])?.to_ascii_lowercase();
+    let pattern = args.get(2).map(|arg| text(arg)).transpose()?;
+    match (sub.as_str(), args.len()) {
+        ("channels", 2) | ("channels", 3) => {
+            Ok(Reply::strings(pubsub.channels(pattern.as_deref())))
+        }
+        ("shardchannels", 2) | ("shardchannels", 3) => {
+            Ok(Reply::strings(pubsub.shard_channels(pattern.as_deref())))
+        }
+        ("numsub", _) | ("shardnumsub", _) => Ok(Reply::Map(
+            texts(&args[2..])?
+                .into_iter()
+                .map(|channel| {
+                    let count = match sub.as_str() {
+                        "numsub" => pubsub.numsub(&channel),
+                        _ => pubsub.shard_numsub(&channel),
+                    };
+                    (Reply::bulk(channel), Reply::Integer(count as i64))
+                })
+                .collect(),
+        )),
+        ("numpat", 2) => Ok(Reply::Integer(pubsub.numpat() as i64)),
+        ("channels", _) | ("shardchannels", _) | ("numpat", _) => {
+            Err(DBError::WrongArity(format!("pubsub|{}", sub)))
+        }
+        _ => Err(DBError::NotSupported(format!(
+            "PUBSUB {}",
+            sub.to_uppercase()
+        ))),
+    }
+}
+
+///
+/// internal：执行一条发布/订阅命令, name 为小写的命令名称
+///
+/// 返回值：
+///     * 命令的回复；订阅与取消订阅命令对每个频道各回复一条确认
+///     * 参数个数或格式不正确， 返回对应的错误
+pub(crate) fn execute(
+    shared: &Shared,
+    session: &mut Session,
+    output: &Arc<Mutex<Output>>,
+    name: &str,
+    args: &[Vec<u8>],
+) -> Result<Vec<Reply>> {
+    check_arity(name, args.len())?;
+    match name {
+        "publish" | "spublish" => {
+            let (channel, payload) = (text(&args[1])?, text(&args[2])?);
+            let receivers = match name {
+                "publish" => shared.pubsub().publish(&channel, &payload),
+                _ => shared.pubsub().spublish(&channel, &payload),
+            };
+            Ok(vec![Reply::Integer(receivers as i64)])
+        }
+        "subscribe" | "psubscribe" | "ssubscribe" => {
+            let targets = texts(&args[1..])?;
+            let subscriber = subscriber(shared, session, output)?;
+            Ok(targets
+                .iter()
+                .map(|target| {
+                    let count = match name {
+                        "subscribe" => subscriber.subscribe(target),
+                        "psubscribe" => subscriber.psubscribe(target),
+                        _ => subscriber.ssubscribe(target),
+                    };
+                    confirmation(name, Some(target), count)
+                })
+                .collect())
+        }
+        "unsubscribe" | "punsubscribe" | "sunsubscribe" => unsubscribe(session, name, args),
+        _ => introspect(shared, args).map(|reply| vec![reply]),
+    }
+}
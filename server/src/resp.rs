@@ -0,0 +1,291 @@
+//! Redis 序列化协议（RESP）的编解码。
+//!
+//! 请求为 bulk string 组成的数组（`*N\r\n$len\r\narg\r\n...`）, 也支持 telnet 使用的内联命令
+//! （一行以空白分隔的参数）；回复按照连接协商的协议版本编码, RESP3 中的 map、set 与 null
+//! 在 RESP2 中分别退化为扁平的数组、数组与 `$-1`。
+
+use dbcore::{CommandReply, DBError, Result};
+use std::mem;
+use std::str;
+
+/// 单个 bulk string 的最大长度, 与 Redis 的 proto-max-bulk-len 默认值相同
+const MAX_BULK_LEN: usize = 512 * 1024 * 1024;
+
+/// 单条命令的最大参数个数
+const MAX_ARGS: usize = 1024 * 1024;
+
+/// 内联命令的最大长度
+const MAX_INLINE_LEN: usize = 64 * 1024;
+
+/// 协议版本, 通过 `HELLO` 命令协商, 默认为 RESP2
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Protocol {
+    Resp2,
+    Resp3,
+}
+
+impl Protocol {
+    /// 协议的版本号
+    pub fn version(&self) -> i64 {
+        match self {
+            Protocol::Resp2 => 2,
+            Protocol::Resp3 => 3,
+        }
+    }
+}
+
+/// 命令的回复
+#[derive(Debug, Clone, PartialEq)]
+pub enum Reply {
+    /// 简单字符串, 例如 `+OK`
+    Status(String),
+    /// 错误, 以错误类别（ERR、WRONGTYPE 等）开头
+    Error(String),
+    Integer(i64),
+    Bulk(Vec<u8>),
+    /// 不存在的值
+    Nil,
+    /// 不存在的数组, 例如被放弃的事务, RESP2 中编码为 `*-1`
+    NilArray,
+    Array(Vec<Reply>),
+    /// 无序的集合, RESP2 中编码为数组
+    Set(Vec<Reply>),
+    /// 键值对, RESP2 中编码为扁平的数组
+    Map(Vec<(Reply, Reply)>),
+    /// 服务端推送的数据, 例如发布/订阅的消息, RESP2 中编码为数组
+    Push(Vec<Reply>),
+}
+
+impl Reply {
+    /// `+OK`
+    pub fn ok() -> Reply {
+        Reply::Status(String::from("OK"))
+    }
+
+    /// 字符串的 bulk string
+    pub fn bulk<S: Into<String>>(value: S) -> Reply {
+        Reply::Bulk(value.into().into_bytes())
+    }
+
+    /// 字符串存在时为 bulk string, 否则为 nil
+    pub fn optional(value: Option<String>) -> Reply {
+        value.map_or(Reply::Nil, Reply::bulk)
+    }
+
+    /// 字符串列表的数组
+    pub fn strings<I: IntoIterator<Item = String>>(values: I) -> Reply {
+        Reply::Array(values.into_iter().map(Reply::bulk).collect())
+    }
+
+    /// dbcore 的错误, 参见 `DBError::to_redis_error()`
+    pub fn error(err: &DBError) -> Reply {
+        Reply::Error(err.to_redis_error())
+    }
+
+    /// 是否为错误
+    pub fn is_error(&self) -> bool {
+        matches!(self, Reply::Error(_))
+    }
+
+    /// 自定义命令的回复, 参见 `dbcore::Command`
+    pub fn custom(reply: CommandReply) -> Reply {
+        match reply {
+            CommandReply::Status(text) => Reply::Status(text),
+            CommandReply::Integer(n) => Reply::Integer(n),
+            CommandReply::Bulk(value) => Reply::bulk(value),
+            CommandReply::Nil => Reply::Nil,
+            CommandReply::Array(items) => {
+                Reply::Array(items.into_iter().map(Reply::custom).collect())
+            }
+        }
+    }
+
+    /// 按照协议版本 protocol 编码, 追加到 out
+    pub fn encode(&self, protocol: Protocol, out: &mut Vec<u8>) {
+        match self {
+            Reply::Status(text) => line(out, b'+', &single_line(text)),
+            Reply::Error(text) => line(out, b'-', &single_line(text)),
+            Reply::Integer(n) => line(out, b':', &n.to_string()),
+            Reply::Bulk(bytes) => {
+                line(out, b'$', &bytes.len().to_string());
+                out.extend_from_slice(bytes);
+                out.extend_from_slice(b"\r\n");
+            }
+            Reply::Nil => match protocol {
+                Protocol::Resp2 => out.extend_from_slice(b"$-1\r\n"),
+                Protocol::Resp3 => out.extend_from_slice(b"_\r\n"),
+            },
+            Reply::NilArray => match protocol {
+                Protocol::Resp2 => out.extend_from_slice(b"*-1\r\n"),
+                Protocol::Resp3 => out.extend_from_slice(b"_\r\n"),
+            },
+            Reply::Array(items) => {
+                line(out, b'*', &items.len().to_string());
+                items.iter().for_each(|item| item.encode(protocol, out));
+            }
+            Reply::Set(items) | Reply::Push(items) => {
+                let prefix = match (protocol, self) {
+                    (Protocol::Resp2, _) => b'*',
+                    (Protocol::Resp3, Reply::Set(_)) => b'~',
+                    (Protocol::Resp3, _) => b'>',
+                };
+                line(out, prefix, &items.len().to_string());
+                items.iter().for_each(|item| item.encode(protocol, out));
+            }
+            Reply::Map(pairs) => {
+                match protocol {
+                    Protocol::Resp2 => line(out, b'*', &(pairs.len() * 2).to_string()),
+                    Protocol::Resp3 => line(out, b'%', &pairs.len().to_string()),
+                }
+                for (key, value) in pairs {
+                    key.encode(protocol, out);
+                    value.encode(protocol, out);
+                }
+            }
+        }
+    }
+}
+
+/// internal：写入以 prefix 开头的一行
+fn line(out: &mut Vec<u8>, prefix: u8, text: &str) {
+    out.push(prefix);
+    out.extend_from_slice(text.as_bytes());
+    out.extend_from_slice(b"\r\n");
+}
+
+/// internal：简单字符串与错误中不能出现换行
+fn single_line(text: &str) -> String {
+    text.replace(['\r', '\n'], " ")
+}
+
+fn protocol_error(reason: &str) -> DBError {
+    DBError::Syntax(String::from(reason))
+}
+
+/// internal：可以跨多次读取继续解析的命令解析器。
+/// multibulk 命令的数据不完整时保存已经解析的参数, 下次从中断处继续, 不会重新解析已经读取的数据
+#[derive(Debug, Default)]
+pub(crate) struct CommandParser {
+    // 正在解析的 multibulk 命令的参数个数
+    count: Option<usize>,
+    // 已经解析的参数
+    args: Vec<Vec<u8>>,
+    // 已经读取了 `$len` 行的下一个参数的长度
+    bulk: Option<usize>,
+}
+
+impl CommandParser {
+    /// internal：已经解析但命令还不完整的参数占用的字节数
+    pub(crate) fn buffered(&self) -> usize {
+        self.args.iter().map(|arg| arg.len()).sum()
+    }
+
+    ///
+    /// internal：从 buf 的开头继续解析一条命令, buf 以上次调用之后剩余的数据开头
+    ///
+    /// 返回值：
+    ///     * (命令的参数, 占用的字节数)；空行解析为没有参数的命令
+    ///     * 数据不完整， 返回 (None, 已经解析的参数占用的字节数), 这些字节不需要再次传入
+    ///     * 格式不正确或超出长度限制， 返回 Syntax, 之后的数据无法继续解析
+    pub(crate) fn parse(&mut self, buf: &[u8]) -> Result<(Option<Vec<Vec<u8>>>, usize)> {
+        let mut pos = 0;
+        let count = match self.count {
+            Some(count) => count,
+            None => {
+                if buf.is_empty() {
+                    return Ok((None, 0));
+                }
+                if buf[0] != b'*' {
+                    return Ok(match parse_inline(buf)? {
+                        Some((args, used)) => (Some(args), used),
+                        None => (None, 0),
+                    });
+                }
+                let (count, start) = match read_number(buf, 0, "invalid multibulk length")? {
+                    Some(header) => header,
+                    None => return Ok((None, 0)),
+                };
+                if count > MAX_ARGS as i64 {
+                    return Err(protocol_error("invalid multibulk length"));
+                }
+                let count = count.max(0) as usize;
+                self.args = Vec::with_capacity(count.min(1024));
+                self.count = Some(count);
+                pos = start;
+                count
+            }
+        };
+        while self.args.len() < count {
+            let len = match self.bulk {
+                Some(len) => len,
+                None => {
+                    if pos >= buf.len() {
+                        return Ok((None, pos));
+                    }
+                    if buf[pos] != b'$' {
+                        return Err(DBError::Syntax(format!(
+                            "expected '$', got '{}'",
+                            buf[pos] as char
+                        )));
+                    }
+                    let (len, start) = match read_number(buf, pos, "invalid bulk length")? {
+                        Some(header) => header,
+                        None => return Ok((None, pos)),
+                    };
+                    if len < 0 || len as usize > MAX_BULK_LEN {
+                        return Err(protocol_error("invalid bulk length"));
+                    }
+                    self.bulk = Some(len as usize);
+                    pos = start;
+                    len as usize
+                }
+            };
+            let end = pos + len;
+            if buf.len() < end + 2 {
+                return Ok((None, pos));
+            }
+            if &buf[end..end + 2] != b"\r\n" {
+                return Err(protocol_error("bulk string is not terminated by CRLF"));
+            }
+            self.args.push(buf[pos..end].to_vec());
+            self.bulk = None;
+            pos = end + 2;
+        }
+        self.count = None;
+        Ok((Some(mem::take(&mut self.args)), pos))
+    }
+}
+
+/// internal：读取 pos 处以类型字节开头、以 \r\n 结尾的数字
+///
+/// 返回值：(数字, 下一行的开始位置)；数据不完整时返回 None
+fn read_number(buf: &[u8], pos: usize, reason: &str) -> Result<Option<(i64, usize)>> {
+    let end = match buf[pos..].iter().position(|b| *b == b'\n') {
+        Some(end) => pos + end,
+        None if buf.len() - pos > MAX_INLINE_LEN => return Err(protocol_error(reason)),
+        None => return Ok(None),
+    };
+    if end == pos || buf[end - 1] != b'\r' {
+        return Err(protocol_error(reason));
+    }
+    str::from_utf8(&buf[pos + 1..end - 1])
+        .ok()
+        .and_then(|s| s.parse::<i64>().ok())
+        .map(|n| Some((n, end + 1)))
+        .ok_or_else(|| protocol_error(reason))
+}
+
+/// internal：解析以 \n 或 \r\n 结尾的内联命令
+fn parse_inline(buf: &[u8]) -> Result<Option<(Vec<Vec<u8>>, usize)>> {
+    let end = match buf.iter().position(|b| *b == b'\n') {
+        Some(end) => end,
+        None if buf.len() > MAX_INLINE_LEN => return Err(protocol_error("too big inline request")),
+        None => return Ok(None),
+    };
+    let args = buf[..end]
+        .split(|b| b.is_ascii_whitespace())
+        .filter(|word| !word.is_empty())
+        .map(|word| word.to_vec())
+        .collect();
+    Ok(Some((args, end + 1)))
+}
@@ -0,0 +1,456 @@
+//! 服务端脚本：`EVAL` / `EVALSHA` / `EVAL_RO` / `EVALSHA_RO` 与 `SCRIPT`, 脚本语言为嵌入的 Rhai。
+//!
+//! 脚本中可以使用：
+//!     * `KEYS` 与 `ARGV`：命令中的 key 与其他参数, 都是字符串数组
+//!     * `cmd(name, args...)`：在当前数据库上执行一条命令, 返回它的回复；命令出错时脚本出错,
+//!       可以用 Rhai 的 `try` / `catch` 捕获。与 Redis 的 `redis.call` 对应, `call` 是 Rhai 的保留字
+//!     * `pcmd(name, args...)`：与 `cmd` 相同, 但命令出错时返回 `#{err: "..."}` 而不是使脚本出错
+//!
+//! 回复与 Rhai 的值的转换：整数对应 INT, 字符串与状态回复对应字符串, nil 对应 `()`, 数组对应数组,
+//! map 对应 object map；脚本的返回值按相反的方向转换, 其中 true 为 1、false 为 nil,
+//! 包含 `err` / `ok` 字段的 object map 分别为错误回复与状态回复。
+//!
+//! 脚本执行期间持有数据库的锁, 其他连接的命令不会穿插其中；脚本出错、超时或者被 `SCRIPT KILL` 终止时,
+//! 已经执行的修改全部回滚（参见 `KVDB::transaction()`）。脚本只能访问当前数据库,
+//! `SELECT`、`FLUSHALL` 等访问多个数据库或整个服务的命令不能在脚本中执行。
+//! 注册的自定义命令同样可以执行, 带有 NO_SCRIPT 标记的除外, 只读脚本中不能执行带有 WRITE 标记的命令。
+//! 脚本按照 SHA1 缓存在服务中, 所有连接共用, 可以通过 `EVALSHA` 执行。
+
+use crate::command::{self, text};
+use crate::connection::Session;
+use crate::resp::Reply;
+use crate::ServerConfig;
+use dbcore::{CommandFlags, DBError, Databases, Result, KVDB};
+use rhai::packages::{Package, StandardPackage};
+use rhai::{
+    Array, Dynamic, Engine, EvalAltResult, Map, NativeCallContext, Position, Scope, Shared, AST,
+};
+use std::any::TypeId;
+use std::collections::HashMap;
+use std::mem;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+/// 脚本命令及其参数个数（包括命令名称）, 负数 -N 表示至少 N 个
+const COMMANDS: &[(&str, i32)] = &[
+    ("eval", -3),
+    ("evalsha", -3),
+    ("eval_ro", -3),
+    ("evalsha_ro", -3),
+    ("script", -2),
+];
+
+/// `cmd` / `pcmd` 最多接受的参数个数（包括命令名称）
+const MAX_CALL_ARGS: usize = 32;
+
+/// 每执行多少个操作检查一次是否超时
+const TIME_CHECK_INTERVAL: u64 = 256;
+
+/// 脚本中字符串的最大长度, 与 bulk string 的最大长度相同
+const MAX_STRING_SIZE: usize = 512 * 1024 * 1024;
+
+/// 脚本中数组与 object map 的最大元素个数
+const MAX_COLLECTION_SIZE: usize = 1024 * 1024;
+
+/// 脚本中函数调用的最大嵌套层数
+const MAX_CALL_LEVELS: usize = 64;
+
+/// 表达式的最大嵌套层数（全局与函数内）
+const MAX_EXPR_DEPTH: usize = 64;
+
+/// 一次执行最多执行的操作数, 没有设置时间上限时也不会无限执行
+const MAX_OPERATIONS: u64 = 1_000_000_000;
+
+/// internal：创建设置了资源上限的 `Engine`, 编译与执行脚本都使用它
+fn limited_engine() -> Engine {
+    let mut engine = Engine::new_raw();
+    engine
+        .set_max_string_size(MAX_STRING_SIZE)
+        .set_max_array_size(MAX_COLLECTION_SIZE)
+        .set_max_map_size(MAX_COLLECTION_SIZE)
+        .set_max_call_levels(MAX_CALL_LEVELS)
+        .set_max_expr_depths(MAX_EXPR_DEPTH, MAX_EXPR_DEPTH)
+        .set_max_operations(MAX_OPERATIONS);
+    engine
+}
+
+/// internal：name（小写）是否为脚本命令
+pub(crate) fn is_command(name: &str) -> bool {
+    COMMANDS.iter().any(|(command, _)| *command == name)
+}
+
+/// internal：脚本命令的数量
+pub(crate) fn count() -> usize {
+    COMMANDS.len()
+}
+
+fn check_arity(name: &str, args: usize) -> Result<()> {
+    match COMMANDS.iter().find(|(command, _)| *command == name) {
+        Some((_, arity)) if *arity >= 0 && args == *arity as usize => Ok(()),
+        Some((_, arity)) if *arity < 0 && args >= arity.unsigned_abs() as usize => Ok(()),
+        Some(_) => Err(DBError::WrongArity(String::from(name))),
+        None => Err(DBError::UnknownCommand(String::from(name))),
+    }
+}
+
+/// internal：脚本的 SHA1, 40 个小写的十六进制字符
+fn sha1(source: &str) -> String {
+    sha1_smol::Sha1::from(source).digest().to_string()
+}
+
+/// internal：服务中所有连接共用的脚本缓存以及正在执行的脚本
+pub(crate) struct Scripts {
+    cache: Mutex<HashMap<String, Arc<AST>>>,
+    /// 正在执行的脚本的终止标记
+    running: Mutex<Option<Arc<AtomicBool>>>,
+    /// Rhai 的标准库, 每次执行脚本时创建的 `Engine` 共用
+    library: Shared<rhai::Module>,
+}
+
+impl std::fmt::Debug for Scripts {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Scripts")
+            .field("cached", &lock(&self.cache).len())
+            .field("running", &lock(&self.running).is_some())
+            .finish()
+    }
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+impl Scripts {
+    pub(crate) fn new() -> Scripts {
+        Scripts {
+            cache: Mutex::new(HashMap::new()),
+            running: Mutex::new(None),
+            library: StandardPackage::new().as_shared_module(),
+        }
+    }
+
+    /// internal：编译并缓存脚本, 返回它的 SHA1
+    fn load(&self, source: &str) -> Result<(String, Arc<AST>)> {
+        let sha = sha1(source);
+        if let Some(ast) = lock(&self.cache).get(&sha) {
+            return Ok((sha, ast.clone()));
+        }
+        let ast = limited_engine()
+            .compile(source)
+            .map_err(|e| DBError::Script(format!("Error compiling script: {}", e)))?;
+        let ast = Arc::new(ast);
+        lock(&self.cache).insert(sha.clone(), ast.clone());
+        Ok((sha, ast))
+    }
+
+    fn get(&self, sha: &str) -> Option<Arc<AST>> {
+        lock(&self.cache).get(&sha.to_ascii_lowercase()).cloned()
+    }
+
+    /// internal：终止正在执行的脚本, 没有正在执行的脚本时返回 false
+    fn kill(&self) -> bool {
+        match lock(&self.running).as_ref() {
+            Some(killed) => {
+                killed.store(true, Ordering::SeqCst);
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+///
+/// internal：执行 `SCRIPT LOAD / EXISTS / FLUSH / KILL`, 不需要锁定数据库,
+/// 因此可以在其他连接执行脚本期间终止它
+pub(crate) fn execute_script(scripts: &Scripts, args: &[Vec<u8>]) -> Result<Reply> {
+    check_arity("script", args.len())?;
+    let sub = text(&args[1])?.to_ascii_lowercase();
+    match (sub.as_str(), args.len()) {
+        ("load", 3) => scripts
+            .load(&text(&args[2])?)
+            .map(|(sha, _)| Reply::bulk(sha)),
+        ("exists", n) if n >= 3 => {
+            let mut found = Vec::new();
+            for arg in &args[2..] {
+                let exists = scripts.get(&text(arg)?).is_some();
+                found.push(Reply::Integer(exists as i64));
+            }
+            Ok(Reply::Array(found))
+        }
+        ("flush", 2) | ("flush", 3) => {
+            match args.get(2).map(|arg| text(arg)).transpose()? {
+                None => {}
+                Some(mode)
+                    if mode.eq_ignore_ascii_case("async") || mode.eq_ignore_ascii_case("sync") => {}
+                Some(mode) => return Err(DBError::Syntax(format!("unexpected option `{}`", mode))),
+            }
+            lock(&scripts.cache).clear();
+            Ok(Reply::ok())
+        }
+        ("kill", 2) => Ok(if scripts.kill() {
+            Reply::ok()
+        } else {
+            Reply::Error(String::from("NOTBUSY No scripts in execution right now."))
+        }),
+        ("load", _) | ("exists", _) | ("flush", _) | ("kill", _) => {
+            Err(DBError::WrongArity(format!("script|{}", sub)))
+        }
+        _ => Err(DBError::NotSupported(format!(
+            "SCRIPT {}",
+            sub.to_uppercase()
+        ))),
+    }
+}
+
+///
+/// internal：执行 `EVAL` / `EVALSHA` 及其只读版本, name 为小写的命令名称
+///
+/// 返回值：
+///     * 脚本的返回值
+///     * 脚本不存在时返回 NOSCRIPT 错误回复
+///     * 参数不正确、脚本编译或执行失败、超时或者被终止， 返回对应的错误
+pub(crate) fn execute(
+    dbs: &mut Databases,
+    config: &ServerConfig,
+    scripts: &Scripts,
+    session: &Session,
+    name: &str,
+    args: &[Vec<u8>],
+) -> Result<Reply> {
+    check_arity(name, args.len())?;
+    let ast = match name {
+        "eval" | "eval_ro" => scripts.load(&text(&args[1])?)?.1,
+        _ => match scripts.get(&text(&args[1])?) {
+            Some(ast) => ast,
+            None => {
+                return Ok(Reply::Error(String::from(
+                    "NOSCRIPT No matching script. Please use EVAL.",
+                )))
+            }
+        },
+    };
+    let numkeys: usize = text(&args[2])?
+        .parse()
+        .map_err(|_| DBError::NotANumber(String::from_utf8_lossy(&args[2]).into_owned()))?;
+    if numkeys > args.len() - 3 {
+        return Err(DBError::Syntax(String::from(
+            "Number of keys can't be greater than number of args",
+        )));
+    }
+    let keys = command::texts(&args[3..3 + numkeys])?;
+    let argv = command::texts(&args[3 + numkeys..])?;
+    let read_only = name.ends_with("_ro");
+
+    let killed = Arc::new(AtomicBool::new(false));
+    *lock(&scripts.running) = Some(killed.clone());
+    let db = dbs.db_mut(session.db)?;
+    let res = db.transaction(|tx| {
+        let run = Run {
+            scripts,
+            ast: &ast,
+            keys,
+            argv,
+            read_only,
+            killed,
+            limit: config.script_time_limit,
+        };
+        run.eval(tx)
+    });
+    *lock(&scripts.running) = None;
+    res
+}
+
+/// internal：一次脚本的执行
+struct Run<'a> {
+    scripts: &'a Scripts,
+    ast: &'a AST,
+    keys: Vec<String>,
+    argv: Vec<String>,
+    read_only: bool,
+    killed: Arc<AtomicBool>,
+    limit: Option<Duration>,
+}
+
+/// internal：终止脚本的原因, 通过 `on_progress` 传给 `ErrorTerminated`
+const KILLED: &str = "killed";
+const TIMED_OUT: &str = "timeout";
+
+impl Run<'_> {
+    ///
+    /// internal：在 db 上执行脚本。`cmd` 注册为 Rhai 的函数, 要求 `'static`,
+    /// 因此执行期间把 db 的内容移入共享的 `Mutex`, 执行结束（包括 panic）之后移回
+    fn eval(self, db: &mut KVDB) -> Result<Reply> {
+        let shared = Arc::new(Mutex::new(mem::replace(db, KVDB::default())));
+        let engine = self.engine(&shared);
+        let (ast, limit) = (self.ast, self.limit);
+        let mut scope = Scope::new();
+        scope.push_constant("KEYS", strings(self.keys));
+        scope.push_constant("ARGV", strings(self.argv));
+        let outcome = panic::catch_unwind(AssertUnwindSafe(|| {
+            engine.eval_ast_with_scope::<Dynamic>(&mut scope, ast)
+        }));
+        drop(engine);
+        *db = mem::replace(&mut *lock(&shared), KVDB::default());
+        match outcome {
+            Ok(Ok(value)) => Ok(to_reply(value)),
+            Ok(Err(e)) => Err(match *e {
+                EvalAltResult::ErrorTerminated(reason, _) if reason.to_string() == KILLED => {
+                    DBError::Script(String::from("Script killed by user with SCRIPT KILL"))
+                }
+                EvalAltResult::ErrorTerminated(..) => DBError::Script(format!(
+                    "Script exceeded the time limit of {} ms",
+                    limit.unwrap_or_default().as_millis()
+                )),
+                e => DBError::Script(format!("Error running script: {}", e)),
+            }),
+            Err(panicked) => panic::resume_unwind(panicked),
+        }
+    }
+
+    /// internal：创建执行脚本的 `Engine`, 注册 `cmd` / `pcmd` 并设置终止条件
+    fn engine(&self, db: &Arc<Mutex<KVDB>>) -> Engine {
+        let mut engine = limited_engine();
+        engine.register_global_module(self.scripts.library.clone());
+        engine.on_print(|_| {});
+        engine.on_debug(|_, _, _| {});
+
+        let killed = self.killed.clone();
+        let deadline = self.limit.map(|limit| Instant::now() + limit);
+        engine.on_progress(move |ops| {
+            if killed.load(Ordering::Relaxed) {
+                return Some(Dynamic::from(KILLED));
+            }
+            match deadline {
+                Some(deadline) if ops % TIME_CHECK_INTERVAL == 0 && Instant::now() >= deadline => {
+                    Some(Dynamic::from(TIMED_OUT))
+                }
+                _ => None,
+            }
+        });
+
+        for (name, protected) in [("cmd", false), ("pcmd", true)].iter() {
+            for count in 1..=MAX_CALL_ARGS {
+                let (db, read_only, protected) = (db.clone(), self.read_only, *protected);
+                engine.register_raw_fn(
+                    *name,
+                    vec![TypeId::of::<Dynamic>(); count],
+                    move |_: NativeCallContext, args: &mut [&mut Dynamic]| {
+                        let reply = match call(&mut lock(&db), read_only, args) {
+                            Ok(Reply::Error(e)) => Err(e),
+                            Ok(reply) => Ok(reply),
+                            Err(e) => Err(e.to_redis_error()),
+                        };
+                        match reply {
+                            Ok(reply) => Ok(to_dynamic(reply)),
+                            Err(e) if protected => {
+                                let mut map = Map::new();
+                                map.insert("err".into(), Dynamic::from(e));
+                                Ok(Dynamic::from_map(map))
+                            }
+                            Err(e) => Err(Box::new(EvalAltResult::ErrorRuntime(
+                                Dynamic::from(e),
+                                Position::NONE,
+                            ))),
+                        }
+                    },
+                );
+            }
+        }
+        engine
+    }
+}
+
+/// internal：脚本通过 `cmd` / `pcmd` 执行一条命令
+fn call(db: &mut KVDB, read_only: bool, args: &mut [&mut Dynamic]) -> Result<Reply> {
+    let mut command = Vec::with_capacity(args.len());
+    for arg in args.iter() {
+        if arg.is_unit() || arg.is_array() || arg.is_map() {
+            return Err(DBError::Script(String::from(
+                "Command arguments must be strings or integers",
+            )));
+        }
+        command.push(arg.to_string().into_bytes());
+    }
+    let name = String::from_utf8_lossy(&command[0]).to_ascii_lowercase();
+    let write = if command::is_command(&name) {
+        command::is_write(&name)
+    } else {
+        let custom = db
+            .commands()
+            .get(&name)
+            .ok_or_else(|| DBError::UnknownCommand(name.clone()))?;
+        if custom.flags().contains(CommandFlags::NO_SCRIPT) {
+            return Err(DBError::Script(String::from(
+                "This command is not allowed from scripts",
+            )));
+        }
+        custom.flags().contains(CommandFlags::WRITE)
+    };
+    if read_only && write {
+        return Err(DBError::Script(String::from(
+            "Write commands are not allowed from read-only scripts",
+        )));
+    }
+    command::execute_key_command(db, &name, &command)
+}
+
+fn strings(values: Vec<String>) -> Array {
+    values.into_iter().map(Dynamic::from).collect()
+}
+
+/// internal：命令的回复转换为 Rhai 的值, 错误回复由调用者处理
+fn to_dynamic(reply: Reply) -> Dynamic {
+    match reply {
+        Reply::Status(text) | Reply::Error(text) => Dynamic::from(text),
+        Reply::Integer(n) => Dynamic::from(n),
+        Reply::Bulk(bytes) => Dynamic::from(String::from_utf8_lossy(&bytes).into_owned()),
+        Reply::Nil | Reply::NilArray => Dynamic::UNIT,
+        Reply::Array(items) | Reply::Set(items) | Reply::Push(items) => {
+            Dynamic::from_array(items.into_iter().map(to_dynamic).collect())
+        }
+        Reply::Map(pairs) => Dynamic::from_map(
+            pairs
+                .into_iter()
+                .map(|(key, value)| (to_dynamic(key).to_string().into(), to_dynamic(value)))
+                .collect(),
+        ),
+    }
+}
+
+/// internal：脚本的返回值转换为回复
+fn to_reply(value: Dynamic) -> Reply {
+    if value.is_unit() {
+        return Reply::Nil;
+    }
+    if let Ok(n) = value.as_int() {
+        return Reply::Integer(n);
+    }
+    if let Ok(flag) = value.as_bool() {
+        return if flag { Reply::Integer(1) } else { Reply::Nil };
+    }
+    if let Ok(n) = value.as_float() {
+        return Reply::Integer(n as i64);
+    }
+    if value.is_array() {
+        let items = value.into_array().unwrap_or_default();
+        return Reply::Array(items.into_iter().map(to_reply).collect());
+    }
+    if let Some(map) = value.clone().try_cast::<Map>() {
+        if let Some(err) = map.get("err") {
+            return Reply::Error(err.to_string());
+        }
+        if let Some(ok) = map.get("ok") {
+            return Reply::Status(ok.to_string());
+        }
+        return Reply::Map(
+            map.into_iter()
+                .map(|(key, value)| (Reply::bulk(key.as_str()), to_reply(value)))
+                .collect(),
+        );
+    }
+    Reply::bulk(value.to_string())
+}
@@ -0,0 +1,492 @@
+//! 监听 TCP 端口, 为每个连接启动一个线程, 所有连接共享同一个 `Databases`。
+//!
+//! 监听线程同时负责定期任务（与 Redis 的 serverCron 类似）：删除过期的 key、检查后台保存与 AOF 重写的结果、
+//! WAL 超过大小时执行检查点、满足自动保存规则时执行 `BGSAVE`。
+//!
+//! 通过 `Server::enable_tls()` 开启 TLS 之后, 所有 TCP 端口只接受 TLS 连接, 监听线程同时检查证书文件是否被修改。
+//!
+//! 关闭服务（`ShutdownHandle::shutdown()`、`SHUTDOWN` 命令或者 SIGINT / SIGTERM）时：
+//!     * 停止接受新的连接
+//!     * 关闭所有连接的读端, 已经读取的命令执行完毕并写回回复之后连接关闭
+//!     * 等待后台保存与 AOF 重写结束, 把 AOF 与 WAL 写入磁盘, 按照自动保存规则保存快照
+
+use crate::script::Scripts;
+use crate::stream::{Listener, Stream};
+use crate::tls::{Tls, TlsConfig, TlsHandle};
+#[cfg(unix)]
+use crate::unix::{UnixSocket, UnixSocketConfig};
+use crate::{connection, http, memcached};
+use dbcore::{CommandRegistry, DBError, Databases, PubSub, Result, SaveRule};
+use std::collections::HashMap;
+use std::io::ErrorKind;
+use std::net::{Shutdown, SocketAddr, TcpListener, ToSocketAddrs};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, AtomicU8, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, TryLockError};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+/// 没有新连接时检查关闭请求的间隔
+const ACCEPT_INTERVAL: Duration = Duration::from_millis(20);
+
+/// 定期任务的执行间隔
+const CRON_INTERVAL: Duration = Duration::from_millis(100);
+
+/// 每次定期任务中主动过期的时间预算, 与 Redis 相同为执行间隔的四分之一
+const ACTIVE_EXPIRE_BUDGET: Duration = Duration::from_millis(25);
+
+/// 关闭服务时等待回复写出的最长时间, 避免不读取回复的客户端阻塞关闭
+const SHUTDOWN_WRITE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// 查询缓冲区默认的上限, 与 Redis 相同
+pub const DEFAULT_QUERY_BUFFER_LIMIT: usize = 1024 * 1024 * 1024;
+
+/// 服务的配置
+#[derive(Debug, Clone, Default)]
+pub struct ServerConfig {
+    /// 快照文件, 用于 `SAVE` / `BGSAVE`、自动保存以及关闭时保存, None 表示不保存快照
+    pub dbfile: Option<PathBuf>,
+    /// 自动保存规则, 需要同时配置 dbfile
+    pub save_rules: Vec<SaveRule>,
+    /// WAL 超过该字节数时自动执行检查点, 0 表示不自动执行
+    pub wal_checkpoint_size: u64,
+    /// 脚本执行的最长时间, 超时的脚本被终止并回滚, None 表示不限制
+    pub script_time_limit: Option<Duration>,
+    /// 单个连接的查询缓冲区（已经读取但还没有执行的数据）的上限, 超过时关闭连接,
+    /// 与 Redis 的 client-query-buffer-limit 相同；0 表示使用 `DEFAULT_QUERY_BUFFER_LIMIT`
+    pub query_buffer_limit: usize,
+}
+
+impl ServerConfig {
+    /// internal：实际使用的查询缓冲区上限
+    pub(crate) fn query_buffer_limit(&self) -> usize {
+        match self.query_buffer_limit {
+            0 => DEFAULT_QUERY_BUFFER_LIMIT,
+            limit => limit,
+        }
+    }
+}
+
+/// internal：关闭服务的方式, 与 `SHUTDOWN [SAVE|NOSAVE]` 对应
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum ShutdownMode {
+    /// 按照自动保存规则决定是否保存快照
+    Default,
+    Save,
+    NoSave,
+}
+
+const RUNNING: u8 = 0;
+
+impl ShutdownMode {
+    fn code(self) -> u8 {
+        match self {
+            ShutdownMode::Default => 1,
+            ShutdownMode::Save => 2,
+            ShutdownMode::NoSave => 3,
+        }
+    }
+
+    fn from_code(code: u8) -> Option<ShutdownMode> {
+        match code {
+            1 => Some(ShutdownMode::Default),
+            2 => Some(ShutdownMode::Save),
+            3 => Some(ShutdownMode::NoSave),
+            _ => None,
+        }
+    }
+}
+
+/// 用于在其他线程或信号处理函数中关闭服务, 可以复制
+#[derive(Debug, Clone)]
+pub struct ShutdownHandle {
+    state: Arc<AtomicU8>,
+}
+
+impl ShutdownHandle {
+    /// 请求关闭服务, `Server::run()` 在所有连接关闭、数据保存之后返回；重复请求没有作用
+    pub fn shutdown(&self) {
+        self.request(ShutdownMode::Default);
+    }
+
+    /// 是否已经请求关闭服务
+    pub fn is_shutdown(&self) -> bool {
+        self.mode().is_some()
+    }
+
+    /// internal：以 mode 请求关闭, 只有第一次请求生效
+    pub(crate) fn request(&self, mode: ShutdownMode) {
+        let _ =
+            self.state
+                .compare_exchange(RUNNING, mode.code(), Ordering::SeqCst, Ordering::SeqCst);
+    }
+
+    fn mode(&self) -> Option<ShutdownMode> {
+        ShutdownMode::from_code(self.state.load(Ordering::SeqCst))
+    }
+
+    ///
+    /// 收到 SIGINT 或 SIGTERM 时关闭服务
+    ///
+    /// 返回值：
+    ///     * 注册成功返回 Ok
+    ///     * 无法注册信号处理函数， 返回 Io
+    #[cfg(unix)]
+    pub fn shutdown_on_signals(&self) -> Result<()> {
+        for signal in [libc::SIGINT, libc::SIGTERM].iter() {
+            let state = self.state.clone();
+            // 信号处理函数中只做原子操作, 是异步信号安全的
+            let registered = unsafe {
+                signal_hook_registry::register(*signal, move || {
+                    let _ = state.compare_exchange(
+                        RUNNING,
+                        ShutdownMode::Default.code(),
+                        Ordering::SeqCst,
+                        Ordering::SeqCst,
+                    );
+                })
+            };
+            registered.map_err(|e| DBError::Io(format!("failed to handle signals: {}", e)))?;
+        }
+        Ok(())
+    }
+}
+
+/// internal：所有连接共享的状态
+pub(crate) struct Shared {
+    dbs: Mutex<Databases>,
+    // 与 dbs 中的注册表相同, 发布/订阅不需要锁定数据库
+    pubsub: PubSub,
+    config: ServerConfig,
+    shutdown: ShutdownHandle,
+    next_id: AtomicU64,
+    // 每个连接的 socket 的副本, 用于关闭服务时关闭连接
+    clients: Mutex<HashMap<u64, Stream>>,
+    // 脚本缓存, 执行 `SCRIPT` 命令不需要锁定数据库
+    scripts: Scripts,
+    // 与 dbs 中的自定义命令注册表相同, 事务排队与 `COMMAND` 不需要锁定数据库
+    commands: CommandRegistry,
+}
+
+impl Shared {
+    /// internal：锁定数据库；持有锁的线程 panic 时数据仍然可用
+    pub(crate) fn lock(&self) -> MutexGuard<'_, Databases> {
+        self.dbs
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// internal：不等待地锁定数据库, 其他线程（例如正在执行的脚本）持有锁时返回 None
+    fn try_lock(&self) -> Option<MutexGuard<'_, Databases>> {
+        match self.dbs.try_lock() {
+            Ok(dbs) => Some(dbs),
+            Err(TryLockError::Poisoned(poisoned)) => Some(poisoned.into_inner()),
+            Err(TryLockError::WouldBlock) => None,
+        }
+    }
+
+    pub(crate) fn pubsub(&self) -> &PubSub {
+        &self.pubsub
+    }
+
+    pub(crate) fn config(&self) -> &ServerConfig {
+        &self.config
+    }
+
+    pub(crate) fn scripts(&self) -> &Scripts {
+        &self.scripts
+    }
+
+    pub(crate) fn commands(&self) -> &CommandRegistry {
+        &self.commands
+    }
+
+    pub(crate) fn shutdown_handle(&self) -> &ShutdownHandle {
+        &self.shutdown
+    }
+
+    /// internal：当前的连接数
+    pub(crate) fn connections(&self) -> usize {
+        self.clients().len()
+    }
+
+    /// internal：启动以来接受的连接数
+    pub(crate) fn total_connections(&self) -> u64 {
+        self.next_id.load(Ordering::SeqCst) - 1
+    }
+
+    fn clients(&self) -> MutexGuard<'_, HashMap<u64, Stream>> {
+        self.clients
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+/// internal：处理一个连接直到连接关闭
+type Handler = fn(&Shared, Stream, u64);
+
+/// 使用 RESP 协议的 TCP 服务, 可以同时通过 Unix socket 提供服务, 以及提供 HTTP/JSON 接口与 memcached 协议
+pub struct Server {
+    listener: TcpListener,
+    // 其他监听的 socket 及其处理函数
+    frontends: Vec<(Listener, Handler)>,
+    tls: Option<Arc<Tls>>,
+    shared: Arc<Shared>,
+}
+
+fn io_error(what: &str, e: std::io::Error) -> DBError {
+    DBError::Io(format!("{}: {}", what, e))
+}
+
+impl Server {
+    ///
+    /// 监听 addr, 使用 dbs 中已经恢复的数据提供服务；调用 `run()` 之后才开始接受连接
+    ///
+    /// 返回值：
+    ///     * 服务
+    ///     * 无法监听 addr， 返回 Io
+    pub fn bind<A: ToSocketAddrs>(addr: A, dbs: Databases, config: ServerConfig) -> Result<Server> {
+        Ok(Server {
+            listener: listen(addr)?,
+            frontends: Vec::new(),
+            tls: None,
+            shared: Arc::new(Shared {
+                pubsub: dbs.pubsub().clone(),
+                commands: dbs.commands().clone(),
+                dbs: Mutex::new(dbs),
+                config,
+                shutdown: ShutdownHandle {
+                    state: Arc::new(AtomicU8::new(RUNNING)),
+                },
+                next_id: AtomicU64::new(1),
+                clients: Mutex::new(HashMap::new()),
+                scripts: Scripts::new(),
+            }),
+        })
+    }
+
+    /// 实际监听的地址, 监听端口 0 时用于获取系统分配的端口
+    pub fn local_addr(&self) -> Result<SocketAddr> {
+        self.listener
+            .local_addr()
+            .map_err(|e| io_error("failed to get the local address", e))
+    }
+
+    ///
+    /// 在 addr 上同时提供 HTTP/JSON 接口：`/keys/{key}`、`/sets/{key}/members[/{member}]`、
+    /// `/hashes/{key}[/{field}]`, 支持 GET / PUT / DELETE 以及基于 ETag 的条件请求
+    ///
+    /// 返回值：
+    ///     * 实际监听的地址
+    ///     * 无法监听 addr， 返回 Io
+    pub fn listen_http<A: ToSocketAddrs>(&mut self, addr: A) -> Result<SocketAddr> {
+        let listener = listen(addr)?;
+        let local = listener
+            .local_addr()
+            .map_err(|e| io_error("failed to get the local address", e))?;
+        self.frontends.push((Listener::Tcp(listener), http::serve));
+        Ok(local)
+    }
+
+    ///
+    /// 在 addr 上同时提供 memcached 协议（文本协议与二进制协议）, 读写 0 号数据库中的字符串
+    ///
+    /// 返回值：
+    ///     * 实际监听的地址
+    ///     * 无法监听 addr， 返回 Io
+    pub fn listen_memcached<A: ToSocketAddrs>(&mut self, addr: A) -> Result<SocketAddr> {
+        let listener = listen(addr)?;
+        let local = listener
+            .local_addr()
+            .map_err(|e| io_error("failed to get the local address", e))?;
+        self.frontends
+            .push((Listener::Tcp(listener), memcached::serve));
+        Ok(local)
+    }
+
+    ///
+    /// 在 Unix socket 上同时提供 RESP 协议, 参见 `UnixSocketConfig`；
+    /// 上次异常退出留下的 socket 文件会被删除, 服务关闭时删除 socket 文件
+    ///
+    /// 返回值：
+    ///     * 监听成功返回 Ok
+    ///     * 已经有服务在监听、路径被其他文件占用或者无法监听， 返回 Io
+    #[cfg(unix)]
+    pub fn listen_unix(&mut self, config: UnixSocketConfig) -> Result<()> {
+        let socket = UnixSocket::bind(config)?;
+        self.frontends
+            .push((Listener::Unix(socket), connection::serve));
+        Ok(())
+    }
+
+    ///
+    /// 开启 TLS：RESP、HTTP 与 memcached 端口只接受 TLS 连接, Unix socket 不受影响；
+    /// 配置了 `client_ca` 时客户端必须提供由其签发的证书。证书文件被修改之后自动重新加载
+    ///
+    /// 返回值：
+    ///     * 用于立即重新加载证书的句柄
+    ///     * 无法读取文件， 返回 Io；证书或私钥不合法， 返回 InvalidPayload
+    pub fn enable_tls(&mut self, config: TlsConfig) -> Result<TlsHandle> {
+        let tls = Arc::new(Tls::load(config)?);
+        let handle = tls.handle();
+        self.tls = Some(tls);
+        Ok(handle)
+    }
+
+    /// 用于关闭服务的句柄
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        self.shared.shutdown.clone()
+    }
+
+    ///
+    /// 接受连接并处理命令, 直到服务被关闭
+    ///
+    /// 返回值：
+    ///     * 所有连接关闭、数据写入磁盘之后返回 Ok
+    ///     * 关闭时写入 AOF、WAL 或快照失败， 返回对应的错误
+    pub fn run(self) -> Result<()> {
+        let shared = self.shared;
+        let mut connections: Vec<JoinHandle<()>> = Vec::new();
+        let mut last_cron = Instant::now();
+        let tls = self.tls;
+        let listeners: Vec<(Listener, Handler)> =
+            std::iter::once((Listener::Tcp(self.listener), connection::serve as Handler))
+                .chain(self.frontends)
+                .map(|(listener, handler)| match (listener, &tls) {
+                    (Listener::Tcp(listener), Some(tls)) => {
+                        (Listener::Tls(listener, tls.clone()), handler)
+                    }
+                    (listener, _) => (listener, handler),
+                })
+                .collect();
+        while !shared.shutdown.is_shutdown() {
+            let mut idle = true;
+            for (listener, handler) in &listeners {
+                match listener.accept() {
+                    Ok(None) => idle = false,
+                    Ok(Some(stream)) => {
+                        idle = false;
+                        connections.retain(|handle| !handle.is_finished());
+                        match spawn(&shared, stream, *handler) {
+                            Ok(handle) => connections.push(handle),
+                            Err(e) => eprintln!("failed to accept a connection: {}", e),
+                        }
+                    }
+                    Err(e) if e.kind() == ErrorKind::WouldBlock => {}
+                    Err(e) if e.kind() == ErrorKind::Interrupted => idle = false,
+                    Err(e) => eprintln!("failed to accept a connection: {}", e),
+                }
+            }
+            if idle {
+                thread::sleep(ACCEPT_INTERVAL);
+            }
+            // 数据库被占用时（例如脚本正在执行）跳过这次定期任务, 不阻塞接受连接
+            if last_cron.elapsed() >= CRON_INTERVAL && cron(&shared) {
+                if let Some(Err(e)) = tls.as_ref().and_then(|tls| tls.reload_if_changed()) {
+                    eprintln!("failed to reload TLS certificates: {}", e);
+                }
+                last_cron = Instant::now();
+            }
+        }
+
+        drop(listeners);
+        for stream in shared.clients().values() {
+            let _ = stream.set_write_timeout(Some(SHUTDOWN_WRITE_TIMEOUT));
+            let _ = stream.shutdown(Shutdown::Read);
+        }
+        for handle in connections {
+            let _ = handle.join();
+        }
+        finish(&shared)
+    }
+}
+
+/// internal：监听 addr, 使用非阻塞的 accept 以便检查关闭请求
+fn listen<A: ToSocketAddrs>(addr: A) -> Result<TcpListener> {
+    let listener = TcpListener::bind(addr).map_err(|e| io_error("failed to listen", e))?;
+    listener
+        .set_nonblocking(true)
+        .map_err(|e| io_error("failed to listen", e))?;
+    Ok(listener)
+}
+
+/// internal：为连接启动处理线程
+fn spawn(
+    shared: &Arc<Shared>,
+    stream: Stream,
+    handler: Handler,
+) -> std::io::Result<JoinHandle<()>> {
+    stream.prepare()?;
+    let id = shared.next_id.fetch_add(1, Ordering::SeqCst);
+    shared.clients().insert(id, stream.try_clone()?);
+    let thread_shared = shared.clone();
+    let spawned = thread::Builder::new()
+        .name(format!("memkv-client-{}", id))
+        .spawn(move || {
+            handler(&thread_shared, stream, id);
+            thread_shared.clients().remove(&id);
+        });
+    if spawned.is_err() {
+        shared.clients().remove(&id);
+    }
+    spawned
+}
+
+/// internal：定期任务
+///
+/// 返回值：数据库被其他线程占用而没有执行时返回 false
+fn cron(shared: &Shared) -> bool {
+    let mut dbs = match shared.try_lock() {
+        Some(dbs) => dbs,
+        None => return false,
+    };
+    dbs.active_expire(ACTIVE_EXPIRE_BUDGET);
+    if let Err(e) = dbs.flush_aof() {
+        eprintln!("failed to write AOF: {}", e);
+    }
+    // 过期删除的记录由下一次提交一起写入磁盘
+    dbs.flush_wal();
+    if let Some(Err(e)) = dbs.poll_aof_rewrite() {
+        eprintln!("background AOF rewriting failed: {}", e);
+    }
+    if let Some(Err(e)) = dbs.poll_bgsave() {
+        eprintln!("background saving failed: {}", e);
+    }
+    let checkpoint_size = shared.config.wal_checkpoint_size;
+    if dbs
+        .wal()
+        .is_some_and(|wal| checkpoint_size > 0 && wal.size() > checkpoint_size)
+    {
+        if let Err(e) = dbs.checkpoint() {
+            eprintln!("WAL checkpoint failed: {}", e);
+        }
+    }
+    if let Some(path) = &shared.config.dbfile {
+        if dbs.should_save(&shared.config.save_rules) {
+            let _ = dbs.bgsave(path);
+        }
+    }
+    true
+}
+
+/// internal：所有连接关闭之后保存数据
+fn finish(shared: &Shared) -> Result<()> {
+    let mut dbs = shared.lock();
+    if let Some(Err(e)) = dbs.wait_bgsave() {
+        eprintln!("background saving failed: {}", e);
+    }
+    if let Some(Err(e)) = dbs.wait_aof_rewrite() {
+        eprintln!("background AOF rewriting failed: {}", e);
+    }
+    dbs.flush_aof()?;
+    dbs.commit_wal()?;
+    let save = match shared.shutdown.mode() {
+        Some(ShutdownMode::Save) => true,
+        Some(ShutdownMode::NoSave) => false,
+        _ => !shared.config.save_rules.is_empty() && dbs.changes_since_save() > 0,
+    };
+    if let (true, Some(path)) = (save, &shared.config.dbfile) {
+        dbs.save(path)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,126 @@
+//! 服务监听的 socket 与客户端连接：TCP、TLS 或者 Unix domain socket。
+
+use crate::tls::{Tls, TlsStream};
+#[cfg(unix)]
+use crate::unix::UnixSocket;
+use std::io::{self, Read, Write};
+use std::net::{Shutdown, TcpListener, TcpStream};
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// internal：客户端连接
+#[derive(Debug)]
+pub(crate) enum Stream {
+    Tcp(TcpStream),
+    Tls(TlsStream),
+    #[cfg(unix)]
+    Unix(UnixStream),
+}
+
+impl Stream {
+    pub(crate) fn try_clone(&self) -> io::Result<Stream> {
+        match self {
+            Stream::Tcp(stream) => stream.try_clone().map(Stream::Tcp),
+            Stream::Tls(stream) => stream.try_clone().map(Stream::Tls),
+            #[cfg(unix)]
+            Stream::Unix(stream) => stream.try_clone().map(Stream::Unix),
+        }
+    }
+
+    pub(crate) fn shutdown(&self, how: Shutdown) -> io::Result<()> {
+        match self {
+            Stream::Tcp(stream) => stream.shutdown(how),
+            Stream::Tls(stream) => stream.socket().shutdown(how),
+            #[cfg(unix)]
+            Stream::Unix(stream) => stream.shutdown(how),
+        }
+    }
+
+    pub(crate) fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        match self {
+            Stream::Tcp(stream) => stream.set_write_timeout(timeout),
+            Stream::Tls(stream) => stream.socket().set_write_timeout(timeout),
+            #[cfg(unix)]
+            Stream::Unix(stream) => stream.set_write_timeout(timeout),
+        }
+    }
+
+    /// internal：切换为阻塞模式, TCP 连接同时关闭 Nagle 算法
+    pub(crate) fn prepare(&self) -> io::Result<()> {
+        match self {
+            Stream::Tcp(stream) => prepare_tcp(stream),
+            Stream::Tls(stream) => prepare_tcp(stream.socket()),
+            #[cfg(unix)]
+            Stream::Unix(stream) => stream.set_nonblocking(false),
+        }
+    }
+}
+
+fn prepare_tcp(stream: &TcpStream) -> io::Result<()> {
+    stream.set_nonblocking(false)?;
+    stream.set_nodelay(true)
+}
+
+impl Read for Stream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Stream::Tcp(stream) => stream.read(buf),
+            Stream::Tls(stream) => stream.read(buf),
+            #[cfg(unix)]
+            Stream::Unix(stream) => stream.read(buf),
+        }
+    }
+}
+
+impl Write for Stream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Stream::Tcp(stream) => stream.write(buf),
+            Stream::Tls(stream) => stream.write(buf),
+            #[cfg(unix)]
+            Stream::Unix(stream) => stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Stream::Tcp(stream) => stream.flush(),
+            Stream::Tls(stream) => stream.flush(),
+            #[cfg(unix)]
+            Stream::Unix(stream) => stream.flush(),
+        }
+    }
+}
+
+/// internal：非阻塞的监听 socket
+pub(crate) enum Listener {
+    Tcp(TcpListener),
+    /// 接受的连接使用 TLS 加密
+    Tls(TcpListener, Arc<Tls>),
+    #[cfg(unix)]
+    Unix(UnixSocket),
+}
+
+impl Listener {
+    ///
+    /// internal：接受一个连接
+    ///
+    /// 返回值：
+    ///     * 新的连接; 连接被访问规则拒绝时返回 None
+    ///     * 没有等待的连接时返回 WouldBlock
+    pub(crate) fn accept(&self) -> io::Result<Option<Stream>> {
+        match self {
+            Listener::Tcp(listener) => listener
+                .accept()
+                .map(|(stream, _)| Some(Stream::Tcp(stream))),
+            Listener::Tls(listener, tls) => {
+                let (stream, _) = listener.accept()?;
+                tls.accept(stream).map(|stream| Some(Stream::Tls(stream)))
+            }
+            #[cfg(unix)]
+            Listener::Unix(socket) => socket.accept().map(|stream| stream.map(Stream::Unix)),
+        }
+    }
+}
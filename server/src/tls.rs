@@ -0,0 +1,299 @@
+//! TLS：TCP 端口（RESP、HTTP、memcached）上的连接使用 rustls 加密, 可以要求客户端提供证书（mutual TLS）。
+//!
+//! 证书与私钥从 PEM 文件读取。文件被修改之后（例如证书续期）自动重新加载, 也可以通过 `TlsHandle::reload()`
+//! 立即重新加载；新的证书只用于之后建立的连接, 加载失败时继续使用原来的证书。
+//!
+//! 连接读取数据时不持有 TLS 会话的锁等待 socket, 因此其他线程可以同时向同一个连接写入。
+
+use dbcore::{DBError, Result};
+use rustls::server::AllowAnyAuthenticatedClient;
+use rustls::{Certificate, PrivateKey, RootCertStore, ServerConnection};
+use std::fmt;
+use std::fs;
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::TcpStream;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard, RwLock};
+use std::time::SystemTime;
+
+/// 每次从 socket 读取的最大字节数, 不超过一个 TLS 记录
+const READ_CHUNK: usize = 16 * 1024;
+
+/// TLS 的配置
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TlsConfig {
+    /// 证书链（PEM）, 第一个证书是服务的证书
+    pub cert: PathBuf,
+    /// 私钥（PEM）, 支持 PKCS#8、PKCS#1（RSA）与 SEC1（EC）格式
+    pub key: PathBuf,
+    /// 签发客户端证书的 CA（PEM）；设置之后客户端必须提供由其中的 CA 签发的证书
+    pub client_ca: Option<PathBuf>,
+}
+
+impl TlsConfig {
+    /// 使用 cert 与 key, 不验证客户端证书
+    pub fn new<P: Into<PathBuf>, Q: Into<PathBuf>>(cert: P, key: Q) -> TlsConfig {
+        TlsConfig {
+            cert: cert.into(),
+            key: key.into(),
+            client_ca: None,
+        }
+    }
+
+    fn files(&self) -> impl Iterator<Item = &PathBuf> {
+        std::iter::once(&self.cert)
+            .chain(std::iter::once(&self.key))
+            .chain(self.client_ca.iter())
+    }
+}
+
+/// 用于在其他线程中重新加载证书, 可以复制
+#[derive(Debug, Clone)]
+pub struct TlsHandle {
+    tls: Arc<Tls>,
+}
+
+impl TlsHandle {
+    ///
+    /// 立即重新读取证书、私钥与客户端 CA, 之后建立的连接使用新的证书
+    ///
+    /// 返回值：
+    ///     * 加载成功返回 Ok
+    ///     * 无法读取文件， 返回 Io；证书或私钥不合法， 返回 InvalidPayload；失败时继续使用原来的证书
+    pub fn reload(&self) -> Result<()> {
+        self.tls.reload()
+    }
+}
+
+/// internal：当前使用的 TLS 配置
+pub(crate) struct Tls {
+    config: TlsConfig,
+    current: RwLock<Arc<rustls::ServerConfig>>,
+    // 上次加载时各个文件的修改时间, 用于发现文件的变化
+    stamps: Mutex<Vec<Option<SystemTime>>>,
+}
+
+impl fmt::Debug for Tls {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Tls").field("config", &self.config).finish()
+    }
+}
+
+impl Tls {
+    ///
+    /// internal：读取 config 中的证书与私钥
+    ///
+    /// 返回值：
+    ///     * TLS 配置
+    ///     * 无法读取文件， 返回 Io；证书或私钥不合法， 返回 InvalidPayload
+    pub(crate) fn load(config: TlsConfig) -> Result<Tls> {
+        let stamps = stamps(&config);
+        let current = build(&config)?;
+        Ok(Tls {
+            config,
+            current: RwLock::new(current),
+            stamps: Mutex::new(stamps),
+        })
+    }
+
+    pub(crate) fn handle(self: &Arc<Tls>) -> TlsHandle {
+        TlsHandle { tls: self.clone() }
+    }
+
+    fn reload(&self) -> Result<()> {
+        let stamps = stamps(&self.config);
+        let current = build(&self.config)?;
+        *self
+            .current
+            .write()
+            .unwrap_or_else(|poisoned| poisoned.into_inner()) = current;
+        *self.stamps() = stamps;
+        Ok(())
+    }
+
+    ///
+    /// internal：文件的修改时间变化之后重新加载；加载失败之后直到文件再次变化才会重试
+    ///
+    /// 返回值：
+    ///     * 文件没有变化时返回 None
+    ///     * 否则返回重新加载的结果
+    pub(crate) fn reload_if_changed(&self) -> Option<Result<()>> {
+        let stamps = stamps(&self.config);
+        {
+            let mut last = self.stamps();
+            if *last == stamps {
+                return None;
+            }
+            *last = stamps;
+        }
+        Some(self.reload())
+    }
+
+    /// internal：为新的连接创建 TLS 会话, 握手在第一次读写时进行
+    pub(crate) fn accept(&self, socket: TcpStream) -> io::Result<TlsStream> {
+        let config = self
+            .current
+            .read()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .clone();
+        let session = ServerConnection::new(config).map_err(io::Error::other)?;
+        Ok(TlsStream {
+            socket,
+            session: Arc::new(Mutex::new(session)),
+        })
+    }
+
+    fn stamps(&self) -> MutexGuard<'_, Vec<Option<SystemTime>>> {
+        self.stamps
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+fn stamps(config: &TlsConfig) -> Vec<Option<SystemTime>> {
+    config
+        .files()
+        .map(|path| fs::metadata(path).and_then(|meta| meta.modified()).ok())
+        .collect()
+}
+
+fn read_pem(path: &Path) -> Result<Vec<pem::Pem>> {
+    let data = fs::read(path)
+        .map_err(|e| DBError::Io(format!("failed to read {}: {}", path.display(), e)))?;
+    pem::parse_many(&data)
+        .map_err(|e| DBError::InvalidPayload(format!("{}: {}", path.display(), e)))
+}
+
+fn certificates(path: &Path) -> Result<Vec<Certificate>> {
+    let certs: Vec<Certificate> = read_pem(path)?
+        .into_iter()
+        .filter(|pem| pem.tag() == "CERTIFICATE")
+        .map(|pem| Certificate(pem.into_contents()))
+        .collect();
+    if certs.is_empty() {
+        return Err(DBError::InvalidPayload(format!(
+            "{}: no certificate found",
+            path.display()
+        )));
+    }
+    Ok(certs)
+}
+
+fn private_key(path: &Path) -> Result<PrivateKey> {
+    read_pem(path)?
+        .into_iter()
+        .find(|pem| {
+            matches!(
+                pem.tag(),
+                "PRIVATE KEY" | "RSA PRIVATE KEY" | "EC PRIVATE KEY"
+            )
+        })
+        .map(|pem| PrivateKey(pem.into_contents()))
+        .ok_or_else(|| DBError::InvalidPayload(format!("{}: no private key found", path.display())))
+}
+
+/// internal：根据 config 生成 rustls 的服务端配置
+fn build(config: &TlsConfig) -> Result<Arc<rustls::ServerConfig>> {
+    let builder = rustls::ServerConfig::builder().with_safe_defaults();
+    let builder = match &config.client_ca {
+        Some(path) => {
+            let mut roots = RootCertStore::empty();
+            for cert in certificates(path)? {
+                roots
+                    .add(&cert)
+                    .map_err(|e| DBError::InvalidPayload(format!("{}: {}", path.display(), e)))?;
+            }
+            builder.with_client_cert_verifier(AllowAnyAuthenticatedClient::new(roots).boxed())
+        }
+        None => builder.with_no_client_auth(),
+    };
+    let server = builder
+        .with_single_cert(certificates(&config.cert)?, private_key(&config.key)?)
+        .map_err(|e| DBError::InvalidPayload(format!("{}: {}", config.key.display(), e)))?;
+    Ok(Arc::new(server))
+}
+
+/// internal：TLS 连接；副本共享同一个 TLS 会话
+#[derive(Debug)]
+pub(crate) struct TlsStream {
+    socket: TcpStream,
+    session: Arc<Mutex<ServerConnection>>,
+}
+
+impl TlsStream {
+    pub(crate) fn try_clone(&self) -> io::Result<TlsStream> {
+        Ok(TlsStream {
+            socket: self.socket.try_clone()?,
+            session: self.session.clone(),
+        })
+    }
+
+    /// internal：底层的 TCP 连接
+    pub(crate) fn socket(&self) -> &TcpStream {
+        &self.socket
+    }
+
+    fn session(&self) -> MutexGuard<'_, ServerConnection> {
+        self.session
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// internal：把会话中等待发送的 TLS 记录写入 socket
+    fn send(&self, session: &mut ServerConnection) -> io::Result<()> {
+        while session.wants_write() {
+            session.write_tls(&mut &self.socket)?;
+        }
+        Ok(())
+    }
+}
+
+impl Read for TlsStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut incoming = [0u8; READ_CHUNK];
+        loop {
+            {
+                let mut session = self.session();
+                match session.reader().read(buf) {
+                    // 读取到 0 字节表示对端发送了 close_notify
+                    Ok(n) => return Ok(n),
+                    Err(e) if e.kind() == ErrorKind::WouldBlock => {}
+                    Err(e) => return Err(e),
+                }
+                self.send(&mut session)?;
+            }
+            let n = (&self.socket).read(&mut incoming)?;
+            if n == 0 {
+                return Ok(0);
+            }
+            let mut session = self.session();
+            let mut data = &incoming[..n];
+            while !data.is_empty() {
+                if session.read_tls(&mut data)? == 0 {
+                    break;
+                }
+                if let Err(e) = session.process_new_packets() {
+                    // 尽量把 alert 发送给对端
+                    let _ = self.send(&mut session);
+                    return Err(io::Error::new(ErrorKind::InvalidData, e));
+                }
+            }
+            self.send(&mut session)?;
+        }
+    }
+}
+
+impl Write for TlsStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut session = self.session();
+        let n = session.writer().write(buf)?;
+        self.send(&mut session)?;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        let mut session = self.session();
+        session.writer().flush()?;
+        self.send(&mut session)
+    }
+}
@@ -0,0 +1,240 @@
+//! Unix domain socket：同一台机器上的进程（例如 sidecar）不经过 TCP 访问服务。
+//!
+//! 访问控制分为两层：
+//!     * socket 文件的权限（`UnixSocketConfig::mode`）决定哪些用户可以连接
+//!     * 访问规则（`AccessRule`）按照对端进程的凭据（uid / gid）决定是否接受连接,
+//!       被拒绝的连接收到 `-NOPERM` 错误之后关闭
+//!
+//! 启动时 socket 文件已经存在：没有服务在监听（上次异常退出留下的文件）时删除它, 否则返回错误；
+//! 服务关闭时删除 socket 文件。
+
+use dbcore::{DBError, Result};
+use std::fs::{self, Permissions};
+use std::io::{self, Write};
+use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+use std::os::unix::io::AsRawFd;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// Unix socket 的配置
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnixSocketConfig {
+    /// socket 文件的路径
+    pub path: PathBuf,
+    /// socket 文件的权限位, 例如 0o770；None 表示由进程的 umask 决定
+    pub mode: Option<u32>,
+    /// 对端进程满足任意一条规则时接受连接, 为空时接受所有能够打开 socket 文件的进程
+    pub access: Vec<AccessRule>,
+}
+
+impl UnixSocketConfig {
+    /// 监听 path, 使用默认权限并且不限制对端进程
+    pub fn new<P: Into<PathBuf>>(path: P) -> UnixSocketConfig {
+        UnixSocketConfig {
+            path: path.into(),
+            mode: None,
+            access: Vec::new(),
+        }
+    }
+
+    /// 是否接受凭据为 peer 的对端进程
+    pub fn allows(&self, peer: &PeerCredentials) -> bool {
+        self.access.is_empty() || self.access.iter().any(|rule| rule.allows(peer))
+    }
+}
+
+/// 对端进程的凭据, 由操作系统在连接建立时记录
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PeerCredentials {
+    /// 进程 ID, 部分平台不提供
+    pub pid: Option<i32>,
+    /// 有效用户 ID
+    pub uid: u32,
+    /// 有效组 ID
+    pub gid: u32,
+}
+
+impl PeerCredentials {
+    ///
+    /// 获取 Unix socket 连接对端进程的凭据
+    ///
+    /// 返回值：
+    ///     * 对端进程的凭据
+    ///     * 系统调用失败时返回 I/O 错误
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    pub fn of(stream: &UnixStream) -> io::Result<PeerCredentials> {
+        let mut cred = libc::ucred {
+            pid: 0,
+            uid: 0,
+            gid: 0,
+        };
+        let mut len = std::mem::size_of::<libc::ucred>() as libc::socklen_t;
+        // cred 与 len 在调用期间有效, 长度与 ucred 一致
+        let rc = unsafe {
+            libc::getsockopt(
+                stream.as_raw_fd(),
+                libc::SOL_SOCKET,
+                libc::SO_PEERCRED,
+                &mut cred as *mut libc::ucred as *mut libc::c_void,
+                &mut len,
+            )
+        };
+        if rc != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(PeerCredentials {
+            pid: Some(cred.pid),
+            uid: cred.uid,
+            gid: cred.gid,
+        })
+    }
+
+    ///
+    /// 获取 Unix socket 连接对端进程的凭据
+    ///
+    /// 返回值：
+    ///     * 对端进程的凭据, 不包含进程 ID
+    ///     * 系统调用失败时返回 I/O 错误
+    #[cfg(not(any(target_os = "linux", target_os = "android")))]
+    pub fn of(stream: &UnixStream) -> io::Result<PeerCredentials> {
+        let mut uid: libc::uid_t = 0;
+        let mut gid: libc::gid_t = 0;
+        let rc = unsafe { libc::getpeereid(stream.as_raw_fd(), &mut uid, &mut gid) };
+        if rc != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(PeerCredentials {
+            pid: None,
+            uid,
+            gid,
+        })
+    }
+}
+
+/// 按照对端进程的凭据接受连接的规则
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AccessRule {
+    /// 有效用户 ID 为指定值
+    Uid(u32),
+    /// 有效组 ID 为指定值
+    Gid(u32),
+    /// 与服务进程的有效用户相同
+    SameUser,
+}
+
+impl AccessRule {
+    /// 凭据为 peer 的对端进程是否满足规则
+    pub fn allows(&self, peer: &PeerCredentials) -> bool {
+        match self {
+            AccessRule::Uid(uid) => peer.uid == *uid,
+            AccessRule::Gid(gid) => peer.gid == *gid,
+            AccessRule::SameUser => peer.uid == unsafe { libc::geteuid() },
+        }
+    }
+}
+
+impl FromStr for AccessRule {
+    type Err = DBError;
+
+    ///
+    /// 解析规则：`uid:<n>`、`gid:<n>` 或者 `same-user`
+    ///
+    /// 返回值：
+    ///     * 规则
+    ///     * 格式不正确， 返回 Syntax
+    fn from_str(s: &str) -> Result<AccessRule> {
+        let invalid = || DBError::Syntax(format!("invalid access rule `{}`", s));
+        match s.split_once(':') {
+            Some(("uid", id)) => id.parse().map(AccessRule::Uid).map_err(|_| invalid()),
+            Some(("gid", id)) => id.parse().map(AccessRule::Gid).map_err(|_| invalid()),
+            None if s == "same-user" => Ok(AccessRule::SameUser),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+/// internal：监听中的 Unix socket, drop 时删除 socket 文件
+#[derive(Debug)]
+pub(crate) struct UnixSocket {
+    listener: UnixListener,
+    config: UnixSocketConfig,
+}
+
+fn io_error(what: &str, path: &Path, e: io::Error) -> DBError {
+    DBError::Io(format!("{} {}: {}", what, path.display(), e))
+}
+
+impl UnixSocket {
+    ///
+    /// internal：监听 config.path, 清理上次异常退出留下的 socket 文件并设置文件权限
+    ///
+    /// 返回值：
+    ///     * 非阻塞的监听 socket
+    ///     * 已经有服务在监听、路径被其他文件占用或者无法监听， 返回 Io
+    pub(crate) fn bind(config: UnixSocketConfig) -> Result<UnixSocket> {
+        let path = &config.path;
+        match fs::symlink_metadata(path) {
+            Ok(meta) if meta.file_type().is_socket() => {
+                if UnixStream::connect(path).is_ok() {
+                    return Err(DBError::Io(format!(
+                        "failed to listen on {}: another server is listening",
+                        path.display()
+                    )));
+                }
+                fs::remove_file(path)
+                    .map_err(|e| io_error("failed to remove the stale socket", path, e))?;
+            }
+            Ok(_) => {
+                return Err(DBError::Io(format!(
+                    "failed to listen on {}: the path exists and is not a socket",
+                    path.display()
+                )))
+            }
+            Err(_) => {}
+        }
+        let listener =
+            UnixListener::bind(path).map_err(|e| io_error("failed to listen on", path, e))?;
+        // 之后出现错误时 drop 会删除刚刚创建的 socket 文件
+        let socket = UnixSocket { listener, config };
+        let path = &socket.config.path;
+        socket
+            .listener
+            .set_nonblocking(true)
+            .map_err(|e| io_error("failed to listen on", path, e))?;
+        if let Some(mode) = socket.config.mode {
+            fs::set_permissions(path, Permissions::from_mode(mode))
+                .map_err(|e| io_error("failed to set the permissions of", path, e))?;
+        }
+        Ok(socket)
+    }
+
+    ///
+    /// internal：接受一个连接, 对端进程不满足访问规则时回复错误并关闭连接
+    ///
+    /// 返回值：
+    ///     * 新的连接; 连接被拒绝时返回 None
+    ///     * 没有等待的连接时返回 WouldBlock
+    pub(crate) fn accept(&self) -> io::Result<Option<UnixStream>> {
+        let (mut stream, _) = self.listener.accept()?;
+        if self.config.access.is_empty() {
+            return Ok(Some(stream));
+        }
+        match PeerCredentials::of(&stream) {
+            Ok(peer) if self.config.allows(&peer) => Ok(Some(stream)),
+            _ => {
+                stream.set_nonblocking(false)?;
+                let _ = stream.write_all(
+                    b"-NOPERM this user has no permissions to access the Unix socket\r\n",
+                );
+                Ok(None)
+            }
+        }
+    }
+}
+
+impl Drop for UnixSocket {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.config.path);
+    }
+}
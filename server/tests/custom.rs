@@ -0,0 +1,308 @@
+use dbcore::{
+    register_type, Command, CommandFlags, CommandReply, CustomValue, DBError, Databases, KeySpec,
+    Result, KVDB,
+};
+use memkv_server::{Server, ServerConfig, ShutdownHandle};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+/// 在后台线程中运行的服务
+struct Running {
+    addr: SocketAddr,
+    handle: ShutdownHandle,
+    thread: JoinHandle<dbcore::Result<()>>,
+}
+
+impl Running {
+    fn start(dbs: Databases) -> Running {
+        let server = Server::bind("127.0.0.1:0", dbs, ServerConfig::default()).unwrap();
+        let addr = server.local_addr().unwrap();
+        let handle = server.shutdown_handle();
+        let thread = thread::spawn(move || server.run());
+        Running {
+            addr,
+            handle,
+            thread,
+        }
+    }
+
+    fn connect(&self) -> Client {
+        let stream = TcpStream::connect(self.addr).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(10)))
+            .unwrap();
+        Client {
+            reader: BufReader::new(stream.try_clone().unwrap()),
+            stream,
+        }
+    }
+
+    fn stop(self) {
+        self.handle.shutdown();
+        self.thread.join().unwrap().unwrap();
+    }
+}
+
+struct Client {
+    stream: TcpStream,
+    reader: BufReader<TcpStream>,
+}
+
+impl Client {
+    /// 发送一条命令并读取回复的原始文本
+    fn call(&mut self, args: &[&str]) -> String {
+        let mut out = format!("*{}\r\n", args.len()).into_bytes();
+        for arg in args {
+            out.extend_from_slice(format!("${}\r\n{}\r\n", arg.len(), arg).as_bytes());
+        }
+        self.stream.write_all(&out).unwrap();
+        self.read_reply()
+    }
+
+    /// 读取一个完整的回复, 返回原始文本
+    fn read_reply(&mut self) -> String {
+        let mut line = String::new();
+        assert!(
+            self.reader.read_line(&mut line).unwrap() > 0,
+            "connection closed"
+        );
+        let count = line[1..line.len() - 2].parse::<i64>().unwrap_or(0);
+        match line.as_bytes()[0] {
+            b'$' if count >= 0 => {
+                let mut data = vec![0u8; count as usize + 2];
+                self.reader.read_exact(&mut data).unwrap();
+                line.push_str(&String::from_utf8_lossy(&data));
+            }
+            b'*' | b'~' => (0..count).for_each(|_| line.push_str(&self.read_reply())),
+            b'%' => (0..count * 2).for_each(|_| line.push_str(&self.read_reply())),
+            _ => {}
+        }
+        line
+    }
+}
+
+fn bulk(value: &str) -> String {
+    format!("${}\r\n{}\r\n", value.len(), value)
+}
+
+const QUEUED: &str = "+QUEUED\r\n";
+
+/// 追加写入的日志, 编码为以换行分隔的文本
+#[derive(Debug, Clone, PartialEq)]
+struct Log(Vec<String>);
+
+impl CustomValue for Log {
+    const TYPE_NAME: &'static str = "log";
+
+    fn encode(&self) -> Vec<u8> {
+        self.0.join("\n").into_bytes()
+    }
+
+    fn decode(data: &[u8]) -> Result<Self> {
+        let text = std::str::from_utf8(data)
+            .map_err(|_| DBError::InvalidPayload(String::from("log is not valid UTF-8")))?;
+        Ok(Log(text.split('\n').map(String::from).collect()))
+    }
+}
+
+/// LOGAPPEND key entry：追加一条日志, 返回日志的条数
+struct LogAppend;
+
+impl Command for LogAppend {
+    fn name(&self) -> &str {
+        "logappend"
+    }
+
+    fn arity(&self) -> i32 {
+        3
+    }
+
+    fn flags(&self) -> CommandFlags {
+        CommandFlags::WRITE
+    }
+
+    fn key_spec(&self) -> KeySpec {
+        KeySpec::FIRST
+    }
+
+    fn execute(&self, db: &mut KVDB, args: &[String]) -> Result<CommandReply> {
+        let entry = args[2].clone();
+        let appended = db.update_custom(&args[1], |log: &mut Log| {
+            log.0.push(entry.clone());
+            log.0.len()
+        })?;
+        let len = match appended {
+            Some(len) => len,
+            None => {
+                db.set_custom(&args[1], Log(vec![entry]))?;
+                1
+            }
+        };
+        Ok(CommandReply::Integer(len as i64))
+    }
+}
+
+/// LOGRANGE key：所有日志
+struct LogRange;
+
+impl Command for LogRange {
+    fn name(&self) -> &str {
+        "logrange"
+    }
+
+    fn arity(&self) -> i32 {
+        2
+    }
+
+    fn key_spec(&self) -> KeySpec {
+        KeySpec::FIRST
+    }
+
+    fn execute(&self, db: &mut KVDB, args: &[String]) -> Result<CommandReply> {
+        let entries = db.get_custom::<Log>(&args[1])?.map_or(Vec::new(), |log| {
+            log.0.iter().cloned().map(CommandReply::Bulk).collect()
+        });
+        Ok(CommandReply::Array(entries))
+    }
+}
+
+/// 名称为 name、不能在事务与脚本中使用的命令
+struct Restricted(&'static str);
+
+impl Command for Restricted {
+    fn name(&self) -> &str {
+        self.0
+    }
+
+    fn arity(&self) -> i32 {
+        -1
+    }
+
+    fn flags(&self) -> CommandFlags {
+        CommandFlags::NO_MULTI | CommandFlags::NO_SCRIPT
+    }
+
+    fn execute(&self, _db: &mut KVDB, _args: &[String]) -> Result<CommandReply> {
+        Ok(CommandReply::Status(String::from("custom")))
+    }
+}
+
+fn start() -> Running {
+    register_type::<Log>().unwrap();
+    let mut dbs = Databases::new(2, None);
+    dbs.register_command(Arc::new(LogAppend)).unwrap();
+    dbs.register_command(Arc::new(LogRange)).unwrap();
+    dbs.register_command(Arc::new(Restricted("restricted")))
+        .unwrap();
+    // 与内置命令同名, 不会被执行
+    dbs.register_command(Arc::new(Restricted("get"))).unwrap();
+    Running::start(dbs)
+}
+
+#[test]
+fn custom_commands_and_values() {
+    let server = start();
+    let mut client = server.connect();
+
+    assert_eq!(":1\r\n", client.call(&["LOGAPPEND", "events", "a"]));
+    assert_eq!(":2\r\n", client.call(&["logappend", "events", "b"]));
+    assert_eq!("+log\r\n", client.call(&["TYPE", "events"]));
+    assert_eq!(
+        format!("*2\r\n{}{}", bulk("a"), bulk("b")),
+        client.call(&["LOGRANGE", "events"])
+    );
+    assert_eq!("*0\r\n", client.call(&["LOGRANGE", "missing"]));
+    assert_eq!(
+        "-ERR wrong number of arguments for 'logappend' command\r\n",
+        client.call(&["LOGAPPEND", "events"])
+    );
+    assert_eq!("+custom\r\n", client.call(&["RESTRICTED", "x"]));
+
+    // 内置命令优先
+    client.call(&["SET", "name", "memkv"]);
+    assert_eq!(bulk("memkv"), client.call(&["GET", "name"]));
+    assert!(client
+        .call(&["LOGAPPEND", "name", "x"])
+        .starts_with("-WRONGTYPE"));
+    assert!(client.call(&["GET", "events"]).starts_with("-WRONGTYPE"));
+
+    // 自定义命令同样有统计, 并且只作用于当前数据库
+    let info = client.call(&["INFO", "commandstats"]);
+    assert!(info.contains("cmdstat_logappend:calls="), "{}", info);
+    client.call(&["SELECT", "1"]);
+    assert_eq!("*0\r\n", client.call(&["LOGRANGE", "events"]));
+
+    assert_eq!(
+        format!("*1\r\n{}", bulk("events")),
+        client.call(&["COMMAND", "GETKEYS", "LOGAPPEND", "events", "x"])
+    );
+    assert_eq!(
+        "-ERR COMMAND GETKEYS for built-in commands is not supported\r\n",
+        client.call(&["COMMAND", "GETKEYS", "GET", "name"])
+    );
+
+    server.stop();
+}
+
+#[test]
+fn custom_commands_in_transactions_and_scripts() {
+    let server = start();
+    let mut client = server.connect();
+
+    assert_eq!("+OK\r\n", client.call(&["MULTI"]));
+    assert_eq!(QUEUED, client.call(&["LOGAPPEND", "events", "a"]));
+    assert_eq!(QUEUED, client.call(&["LOGRANGE", "events"]));
+    assert_eq!(
+        format!("*2\r\n:1\r\n*1\r\n{}", bulk("a")),
+        client.call(&["EXEC"])
+    );
+    client.call(&["MULTI"]);
+    assert_eq!(
+        "-ERR Command 'restricted' is not allowed inside MULTI\r\n",
+        client.call(&["RESTRICTED"])
+    );
+    assert_eq!(
+        "-ERR wrong number of arguments for 'logrange' command\r\n",
+        client.call(&["LOGRANGE"])
+    );
+    assert!(client.call(&["EXEC"]).starts_with("-EXECABORT"));
+
+    assert_eq!(
+        ":2\r\n",
+        client.call(&[
+            "EVAL",
+            r#"cmd("LOGAPPEND", KEYS[0], ARGV[0])"#,
+            "1",
+            "events",
+            "b"
+        ])
+    );
+    assert_eq!(
+        ":2\r\n",
+        client.call(&["EVAL_RO", r#"cmd("logrange", "events").len()"#, "0"])
+    );
+    assert_eq!(
+        bulk("ERR Write commands are not allowed from read-only scripts"),
+        client.call(&["EVAL_RO", r#"pcmd("LOGAPPEND", "events", "c").err"#, "0"])
+    );
+    assert_eq!(
+        bulk("ERR This command is not allowed from scripts"),
+        client.call(&["EVAL", r#"pcmd("RESTRICTED").err"#, "0"])
+    );
+    // 脚本出错时回滚自定义命令的修改
+    let reply = client.call(&[
+        "EVAL",
+        r#"cmd("LOGAPPEND", "events", "c"); cmd("SADD", "events", "x")"#,
+        "0",
+    ]);
+    assert!(reply.contains("WRONGTYPE"), "{}", reply);
+    assert_eq!(
+        format!("*2\r\n{}{}", bulk("a"), bulk("b")),
+        client.call(&["LOGRANGE", "events"])
+    );
+
+    server.stop();
+}
@@ -0,0 +1,391 @@
+use dbcore::{Databases, Json};
+use memkv_server::{Server, ServerConfig, ShutdownHandle};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+/// 在后台线程中运行的服务, 同时提供 RESP 与 HTTP 接口
+struct Running {
+    addr: SocketAddr,
+    http: SocketAddr,
+    handle: ShutdownHandle,
+    thread: JoinHandle<dbcore::Result<()>>,
+}
+
+impl Running {
+    fn start() -> Running {
+        let mut server = Server::bind(
+            "127.0.0.1:0",
+            Databases::new(4, None),
+            ServerConfig::default(),
+        )
+        .unwrap();
+        let http = server.listen_http("127.0.0.1:0").unwrap();
+        let addr = server.local_addr().unwrap();
+        let handle = server.shutdown_handle();
+        let thread = thread::spawn(move || server.run());
+        Running {
+            addr,
+            http,
+            handle,
+            thread,
+        }
+    }
+
+    fn connect(&self) -> Client {
+        let stream = TcpStream::connect(self.http).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(10)))
+            .unwrap();
+        Client {
+            reader: BufReader::new(stream.try_clone().unwrap()),
+            stream,
+        }
+    }
+
+    fn stop(self) {
+        self.handle.shutdown();
+        self.thread.join().unwrap().unwrap();
+    }
+}
+
+struct Client {
+    stream: TcpStream,
+    reader: BufReader<TcpStream>,
+}
+
+/// 响应：状态码、响应头与解析后的响应体
+struct Response {
+    status: u16,
+    headers: Vec<(String, String)>,
+    body: Option<Json>,
+}
+
+impl Response {
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(header, _)| header.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    fn etag(&self) -> String {
+        String::from(self.header("ETag").expect("missing ETag"))
+    }
+
+    fn field(&self, name: &str) -> Option<&Json> {
+        self.body.as_ref().and_then(|body| body.get(name))
+    }
+}
+
+impl Client {
+    fn request(
+        &mut self,
+        method: &str,
+        path: &str,
+        headers: &[(&str, &str)],
+        body: &str,
+    ) -> Response {
+        let mut request = format!("{} {} HTTP/1.1\r\nHost: localhost\r\n", method, path);
+        for (name, value) in headers {
+            request.push_str(&format!("{}: {}\r\n", name, value));
+        }
+        if !body.is_empty() {
+            request.push_str(&format!("Content-Length: {}\r\n", body.len()));
+        }
+        request.push_str("\r\n");
+        request.push_str(body);
+        self.stream.write_all(request.as_bytes()).unwrap();
+        self.read_response(method == "HEAD")
+    }
+
+    fn read_response(&mut self, head_only: bool) -> Response {
+        let mut line = String::new();
+        self.reader.read_line(&mut line).unwrap();
+        let status = line.split_whitespace().nth(1).unwrap().parse().unwrap();
+        let mut headers = Vec::new();
+        loop {
+            let mut line = String::new();
+            self.reader.read_line(&mut line).unwrap();
+            let line = line.trim_end();
+            if line.is_empty() {
+                break;
+            }
+            let (name, value) = line.split_once(':').unwrap();
+            headers.push((String::from(name), String::from(value.trim())));
+        }
+        let mut response = Response {
+            status,
+            headers,
+            body: None,
+        };
+        let length: usize = response
+            .header("Content-Length")
+            .map_or(0, |length| length.parse().unwrap());
+        if length > 0 && !head_only {
+            let mut body = vec![0u8; length];
+            self.reader.read_exact(&mut body).unwrap();
+            response.body = Some(Json::parse(std::str::from_utf8(&body).unwrap()).unwrap());
+        }
+        response
+    }
+
+    fn get(&mut self, path: &str) -> Response {
+        self.request("GET", path, &[], "")
+    }
+
+    fn put(&mut self, path: &str, body: &str) -> Response {
+        self.request("PUT", path, &[], body)
+    }
+
+    fn delete(&mut self, path: &str) -> Response {
+        self.request("DELETE", path, &[], "")
+    }
+}
+
+fn string(value: &str) -> Json {
+    Json::String(String::from(value))
+}
+
+fn strings(values: &[&str]) -> Json {
+    Json::Array(values.iter().map(|value| string(value)).collect())
+}
+
+#[test]
+fn strings_are_read_written_and_deleted() {
+    let server = Running::start();
+    let mut client = server.connect();
+
+    let created = client.put("/keys/name", r#"{"value": "memkv"}"#);
+    assert_eq!(201, created.status);
+    let etag = created.etag();
+    let read = client.get("/keys/name");
+    assert_eq!(200, read.status);
+    assert_eq!(etag, read.etag());
+    assert_eq!(Some(&string("memkv")), read.field("value"));
+    assert_eq!(Some(&Json::Number(-1)), read.field("ttl"));
+
+    let replaced = client.put("/keys/name", r#"{"value": "other", "ttl": 60}"#);
+    assert_eq!(200, replaced.status);
+    assert_ne!(etag, replaced.etag());
+    let ttl = client.get("/keys/name").field("ttl").cloned();
+    assert!(matches!(ttl, Some(Json::Number(59..=60))), "{:?}", ttl);
+
+    let head = client.request("HEAD", "/keys/name", &[], "");
+    assert_eq!(200, head.status);
+    assert!(head.body.is_none());
+
+    // key 中的特殊字符使用 URL 编码
+    assert_eq!(
+        201,
+        client.put("/keys/a%2Fb%20c", r#"{"value": "1"}"#).status
+    );
+    assert_eq!(
+        Some(&string("a/b c")),
+        client.get("/keys/a%2Fb%20c").field("key")
+    );
+    // `%` 之后必须是两个十六进制数字
+    assert_eq!(404, client.put("/keys/a%+fb", r#"{"value": "1"}"#).status);
+    assert_eq!(404, client.put("/keys/a%2", r#"{"value": "1"}"#).status);
+
+    assert_eq!(204, client.delete("/keys/name").status);
+    let missing = client.get("/keys/name");
+    assert_eq!(404, missing.status);
+    assert_eq!(
+        Some(&string("key `name` not found")),
+        missing.field("error")
+    );
+    assert_eq!(404, client.delete("/keys/name").status);
+    server.stop();
+}
+
+#[test]
+fn sets_and_hashes() {
+    let server = Running::start();
+    let mut client = server.connect();
+
+    let added = client.put("/sets/tags/members", r#"{"members": ["b", "a", "b"]}"#);
+    assert_eq!(201, added.status);
+    assert_eq!(201, client.put("/sets/tags/members/c", "").status);
+    assert_eq!(200, client.put("/sets/tags/members/c", "").status);
+    assert_eq!(
+        Some(&strings(&["a", "b", "c"])),
+        client.get("/sets/tags/members").field("members")
+    );
+    assert_eq!(200, client.get("/sets/tags/members/a").status);
+    assert_eq!(404, client.get("/sets/tags/members/z").status);
+    assert_eq!(204, client.delete("/sets/tags/members/a").status);
+    assert_eq!(404, client.delete("/sets/tags/members/a").status);
+
+    assert_eq!(
+        201,
+        client
+            .put("/hashes/user/name", r#"{"value": "ann"}"#)
+            .status
+    );
+    assert_eq!(
+        200,
+        client
+            .put("/hashes/user/name", r#"{"value": "bob"}"#)
+            .status
+    );
+    assert_eq!(
+        200,
+        client
+            .put("/hashes/user", r#"{"fields": {"age": "42"}}"#)
+            .status
+    );
+    assert_eq!(
+        Some(&string("bob")),
+        client.get("/hashes/user/name").field("value")
+    );
+    let fields = client.get("/hashes/user").field("fields").cloned().unwrap();
+    assert_eq!(Some(&string("42")), fields.get("age"));
+    assert_eq!(204, client.delete("/hashes/user/age").status);
+    assert_eq!(404, client.get("/hashes/user/age").status);
+
+    // 类型不匹配
+    assert_eq!(409, client.get("/keys/tags").status);
+    assert_eq!(409, client.get("/hashes/tags").status);
+    assert_eq!(409, client.delete("/sets/user/members").status);
+    assert_eq!(409, client.delete("/hashes/tags").status);
+    assert_eq!(200, client.get("/sets/tags/members").status);
+    assert_eq!(204, client.delete("/sets/tags/members").status);
+    assert_eq!(204, client.delete("/keys/user").status);
+    server.stop();
+}
+
+#[test]
+fn conditional_requests_use_etags() {
+    let server = Running::start();
+    let mut client = server.connect();
+
+    let create_only = [("If-None-Match", "*")];
+    assert_eq!(
+        201,
+        client
+            .request("PUT", "/keys/counter", &create_only, r#"{"value": "1"}"#)
+            .status
+    );
+    assert_eq!(
+        412,
+        client
+            .request("PUT", "/keys/counter", &create_only, r#"{"value": "2"}"#)
+            .status
+    );
+    let etag = client.get("/keys/counter").etag();
+    let cached = client.request("GET", "/keys/counter", &[("If-None-Match", &etag)], "");
+    assert_eq!(304, cached.status);
+    assert_eq!(etag, cached.etag());
+
+    // 基于旧值的更新被拒绝, 数据不变
+    let stale = [("If-Match", "\"0000000000000000\"")];
+    assert_eq!(
+        412,
+        client
+            .request("PUT", "/keys/counter", &stale, r#"{"value": "3"}"#)
+            .status
+    );
+    assert_eq!(
+        412,
+        client.request("DELETE", "/keys/counter", &stale, "").status
+    );
+    assert_eq!(
+        Some(&string("1")),
+        client.get("/keys/counter").field("value")
+    );
+
+    let current = [("If-Match", etag.as_str())];
+    let updated = client.request("PUT", "/keys/counter", &current, r#"{"value": "2"}"#);
+    assert_eq!(200, updated.status);
+    assert_ne!(etag, updated.etag());
+    assert_eq!(
+        412,
+        client
+            .request("PUT", "/keys/counter", &current, r#"{"value": "3"}"#)
+            .status
+    );
+    let current = [("If-Match", updated.header("ETag").unwrap())];
+    assert_eq!(
+        204,
+        client
+            .request("DELETE", "/keys/counter", &current, "")
+            .status
+    );
+    assert_eq!(
+        412,
+        client
+            .request(
+                "PUT",
+                "/keys/counter",
+                &[("If-Match", "*")],
+                r#"{"value": "1"}"#
+            )
+            .status
+    );
+
+    // 集合的 ETag 与成员的顺序无关
+    client.put("/sets/s/members", r#"{"members": ["x", "y"]}"#);
+    let before = client.get("/sets/s/members").etag();
+    client.delete("/sets/s/members/x");
+    client.put("/sets/s/members/x", "");
+    assert_eq!(before, client.get("/sets/s/members").etag());
+    server.stop();
+}
+
+#[test]
+fn errors_map_to_status_codes() {
+    let server = Running::start();
+    let mut client = server.connect();
+
+    let bad = client.put("/keys/k", "{not json");
+    assert_eq!(400, bad.status);
+    assert!(matches!(bad.field("error"), Some(Json::String(e)) if e.contains("invalid JSON")));
+    assert_eq!(400, client.put("/keys/k", r#"{"value": 1}"#).status);
+    assert_eq!(
+        400,
+        client.put("/keys/k", r#"{"value": "v", "ttl": 0}"#).status
+    );
+    assert_eq!(
+        400,
+        client.put("/sets/s/members", r#"{"members": []}"#).status
+    );
+    assert_eq!(404, client.get("/keys/k?db=99").status);
+    assert_eq!(400, client.get("/keys/k?db=x").status);
+    assert_eq!(404, client.get("/nothing/here").status);
+    assert_eq!(404, client.get("/keys/").status);
+
+    let post = client.request("POST", "/keys/k", &[], "");
+    assert_eq!(405, post.status);
+    assert_eq!(Some("GET, HEAD, PUT, DELETE"), post.header("Allow"));
+    server.stop();
+}
+
+#[test]
+fn gateway_shares_data_with_resp() {
+    let server = Running::start();
+    let mut client = server.connect();
+    assert_eq!(
+        201,
+        client.put("/keys/shared?db=2", r#"{"value": "v"}"#).status
+    );
+    assert_eq!(404, client.get("/keys/shared").status);
+
+    let mut resp = TcpStream::connect(server.addr).unwrap();
+    resp.write_all(b"SELECT 2\r\nGET shared\r\n").unwrap();
+    let mut reader = BufReader::new(resp);
+    let mut reply = String::new();
+    for _ in 0..3 {
+        reader.read_line(&mut reply).unwrap();
+    }
+    assert_eq!("+OK\r\n$1\r\nv\r\n", reply);
+
+    // Connection: close 之后服务端关闭连接
+    let closed = client.request("GET", "/keys/shared?db=2", &[("Connection", "close")], "");
+    assert_eq!(200, closed.status);
+    assert_eq!(Some("close"), closed.header("Connection"));
+    let mut rest = Vec::new();
+    assert_eq!(0, client.reader.read_to_end(&mut rest).unwrap());
+    server.stop();
+}
@@ -0,0 +1,496 @@
+use dbcore::Databases;
+use memkv_server::{Server, ServerConfig, ShutdownHandle};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+/// 在后台线程中运行的服务, 同时提供 RESP 与 memcached 协议
+struct Running {
+    addr: SocketAddr,
+    memcached: SocketAddr,
+    handle: ShutdownHandle,
+    thread: JoinHandle<dbcore::Result<()>>,
+}
+
+impl Running {
+    fn start() -> Running {
+        let mut server = Server::bind(
+            "127.0.0.1:0",
+            Databases::new(4, None),
+            ServerConfig::default(),
+        )
+        .unwrap();
+        let memcached = server.listen_memcached("127.0.0.1:0").unwrap();
+        let addr = server.local_addr().unwrap();
+        let handle = server.shutdown_handle();
+        let thread = thread::spawn(move || server.run());
+        Running {
+            addr,
+            memcached,
+            handle,
+            thread,
+        }
+    }
+
+    fn connect(&self) -> Client {
+        let stream = TcpStream::connect(self.memcached).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(10)))
+            .unwrap();
+        Client {
+            reader: BufReader::new(stream.try_clone().unwrap()),
+            stream,
+        }
+    }
+
+    /// 通过 RESP 协议执行一条 inline 命令, 返回回复的第一行
+    fn resp(&self, command: &str) -> String {
+        let mut stream = TcpStream::connect(self.addr).unwrap();
+        stream
+            .write_all(format!("{}\r\n", command).as_bytes())
+            .unwrap();
+        let mut line = String::new();
+        BufReader::new(stream).read_line(&mut line).unwrap();
+        String::from(line.trim_end())
+    }
+
+    fn stop(self) {
+        self.handle.shutdown();
+        self.thread.join().unwrap().unwrap();
+    }
+}
+
+struct Client {
+    stream: TcpStream,
+    reader: BufReader<TcpStream>,
+}
+
+impl Client {
+    fn send(&mut self, data: &str) {
+        self.stream.write_all(data.as_bytes()).unwrap();
+    }
+
+    fn line(&mut self) -> String {
+        let mut line = String::new();
+        self.reader.read_line(&mut line).unwrap();
+        assert!(line.ends_with("\r\n"), "{:?}", line);
+        line.truncate(line.len() - 2);
+        line
+    }
+
+    /// 发送一条命令并读取一行回复
+    fn call(&mut self, command: &str) -> String {
+        self.send(&format!("{}\r\n", command));
+        self.line()
+    }
+
+    /// 发送存储命令与数据, 读取一行回复
+    fn store(&mut self, command: &str, data: &str) -> String {
+        self.send(&format!("{}\r\n{}\r\n", command, data));
+        self.line()
+    }
+
+    /// 执行 get / gets, 返回所有的 VALUE 行与数据, 直到 END
+    fn get(&mut self, command: &str) -> Vec<(String, String)> {
+        self.send(&format!("{}\r\n", command));
+        let mut values = Vec::new();
+        loop {
+            let header = self.line();
+            if header == "END" {
+                return values;
+            }
+            let data = self.line();
+            values.push((header, data));
+        }
+    }
+
+    /// gets 返回的 CAS 令牌
+    fn cas(&mut self, key: &str) -> u64 {
+        let values = self.get(&format!("gets {}", key));
+        values[0].0.rsplit(' ').next().unwrap().parse().unwrap()
+    }
+}
+
+#[test]
+fn storage_commands() {
+    let server = Running::start();
+    let mut client = server.connect();
+
+    assert_eq!("STORED", client.store("set name 42 0 5", "memkv"));
+    assert_eq!(
+        vec![(String::from("VALUE name 42 5"), String::from("memkv"))],
+        client.get("get name missing")
+    );
+    assert_eq!("NOT_STORED", client.store("add name 0 0 1", "x"));
+    assert_eq!("STORED", client.store("add other 7 0 1", "x"));
+    assert_eq!("NOT_STORED", client.store("replace nope 0 0 1", "x"));
+    assert_eq!("STORED", client.store("replace other 8 0 2", "yy"));
+
+    // append / prepend 忽略参数中的 flags, 保留原有的 flags
+    assert_eq!("STORED", client.store("append name 0 0 2", "-1"));
+    assert_eq!("STORED", client.store("prepend name 0 0 3", "db:"));
+    assert_eq!("NOT_STORED", client.store("append nope 0 0 1", "x"));
+    assert_eq!(
+        vec![
+            (String::from("VALUE name 42 10"), String::from("db:memkv-1")),
+            (String::from("VALUE other 8 2"), String::from("yy")),
+        ],
+        client.get("get name other")
+    );
+
+    // noreply 不回复, 之后的命令仍然按顺序回复
+    client.send("set quiet 0 0 1 noreply\r\nq\r\n");
+    assert_eq!(
+        vec![(String::from("VALUE quiet 0 1"), String::from("q"))],
+        client.get("get quiet")
+    );
+
+    // 数据中可以包含 \r\n
+    assert_eq!("STORED", client.store("set multi 0 0 4", "a\r\nb"));
+    assert_eq!("$4", server.resp("GET multi"));
+    server.stop();
+}
+
+#[test]
+fn cas_tokens_follow_modifications() {
+    let server = Running::start();
+    let mut client = server.connect();
+
+    assert_eq!("NOT_FOUND", client.store("cas key 0 0 1 1", "x"));
+    assert_eq!("STORED", client.store("set key 3 0 1", "a"));
+    let cas = client.cas("key");
+    assert_eq!(cas, client.cas("key"));
+    assert_eq!(
+        "EXISTS",
+        client.store(&format!("cas key 0 0 1 {}", cas + 1000), "b")
+    );
+    assert_eq!(
+        "STORED",
+        client.store(&format!("cas key 5 0 1 {}", cas), "b")
+    );
+    let updated = client.cas("key");
+    assert_ne!(cas, updated);
+    assert_eq!(
+        "EXISTS",
+        client.store(&format!("cas key 0 0 1 {}", cas), "c")
+    );
+
+    // 通过 RESP 修改之后 CAS 令牌失效, 覆盖 value 同时清除 flags
+    assert_eq!("+OK", server.resp("SET key resp"));
+    assert_eq!(
+        "EXISTS",
+        client.store(&format!("cas key 0 0 1 {}", updated), "d")
+    );
+    let values = client.get("gets key");
+    assert!(values[0].0.starts_with("VALUE key 0 4 "), "{:?}", values);
+    assert_eq!("resp", values[0].1);
+
+    // 其他类型的 key 对 get 不可见
+    assert_eq!(":1", server.resp("SADD set member"));
+    assert!(client.get("get set").is_empty());
+    assert!(client
+        .store("set set 0 0 1", "x")
+        .starts_with("SERVER_ERROR"));
+    server.stop();
+}
+
+#[test]
+fn counters_delete_touch_and_flush() {
+    let server = Running::start();
+    let mut client = server.connect();
+
+    assert_eq!("NOT_FOUND", client.call("incr n 1"));
+    assert_eq!("STORED", client.store("set n 9 100 2", "10"));
+    assert_eq!("15", client.call("incr n 5"));
+    assert_eq!("0", client.call("decr n 100"));
+    assert_eq!(
+        "18446744073709551615",
+        client.call("incr n 18446744073709551615")
+    );
+    assert_eq!("0", client.call("incr n 1"));
+    assert_eq!(
+        "CLIENT_ERROR invalid numeric delta argument",
+        client.call("incr n x")
+    );
+    // incr 保留 flags 与生存时间
+    assert_eq!("VALUE n 9 1", client.get("get n")[0].0);
+    let ttl: i64 = server.resp("TTL n")[1..].parse().unwrap();
+    assert!((99..=100).contains(&ttl), "{}", ttl);
+    assert_eq!("STORED", client.store("set s 0 0 3", "abc"));
+    assert_eq!(
+        "CLIENT_ERROR cannot increment or decrement non-numeric value",
+        client.call("incr s 1")
+    );
+
+    assert_eq!("TOUCHED", client.call("touch s 1000"));
+    let ttl: i64 = server.resp("TTL s")[1..].parse().unwrap();
+    assert!((999..=1000).contains(&ttl), "{}", ttl);
+    assert_eq!("TOUCHED", client.call("touch s 0"));
+    assert_eq!(":-1", server.resp("TTL s"));
+    assert_eq!("NOT_FOUND", client.call("touch nope 10"));
+
+    // 负数的 exptime 表示立即过期
+    assert_eq!("STORED", client.store("set gone 0 -1 1", "x"));
+    assert!(client.get("get gone").is_empty());
+
+    assert_eq!("DELETED", client.call("delete s"));
+    assert_eq!("NOT_FOUND", client.call("delete s"));
+
+    // 其他类型的 key 不能被 touch 或 delete
+    assert_eq!(":1", server.resp("HSET hash f v"));
+    assert_eq!("NOT_FOUND", client.call("touch hash 10"));
+    assert_eq!(":-1", server.resp("TTL hash"));
+    assert_eq!("NOT_FOUND", client.call("delete hash"));
+    assert_eq!(":1", server.resp("EXISTS hash"));
+    assert_eq!(
+        "CLIENT_ERROR delayed flush_all is not supported",
+        client.call("flush_all 10")
+    );
+    assert_eq!("OK", client.call("flush_all"));
+    assert_eq!(":0", server.resp("DBSIZE"));
+    server.stop();
+}
+
+#[test]
+fn protocol_errors_and_stats() {
+    let server = Running::start();
+    let mut client = server.connect();
+
+    assert_eq!("ERROR", client.call("bogus"));
+    assert_eq!("ERROR", client.call("get"));
+    assert_eq!("ERROR", client.call("set k 0 0"));
+    assert_eq!(
+        "CLIENT_ERROR bad command line format",
+        client.call("set k 0 0 x")
+    );
+    assert_eq!(
+        "CLIENT_ERROR invalid key length",
+        client.call(&format!("get {}", "k".repeat(251)))
+    );
+    assert_eq!(
+        "CLIENT_ERROR bad data chunk",
+        client.store("set k 0 0 1", "xy")
+    );
+    // 转换为毫秒时间戳会溢出的 exptime
+    assert_eq!(
+        "CLIENT_ERROR bad command line format",
+        client.store("set k 0 9223372036854775807 1", "v")
+    );
+    assert_eq!(
+        "CLIENT_ERROR bad command line format",
+        client.call("touch k 9223372036854775807")
+    );
+    client.send(&format!("set big 0 0 {}\r\n", 2 * 1024 * 1024));
+    client.send(&"x".repeat(2 * 1024 * 1024));
+    assert_eq!("SERVER_ERROR object too large for cache", client.call(""));
+    assert!(client.get("get k big").is_empty());
+
+    assert!(client.call("version").starts_with("VERSION "));
+    assert_eq!("STORED", client.store("set k 0 0 1", "v"));
+    client.get("get k nope");
+    client.send("stats\r\n");
+    let mut stats = Vec::new();
+    loop {
+        let line = client.line();
+        if line == "END" {
+            break;
+        }
+        stats.push(line);
+    }
+    assert!(
+        stats.contains(&String::from("STAT curr_items 1")),
+        "{:?}",
+        stats
+    );
+    assert!(stats.iter().any(|stat| stat.starts_with("STAT get_hits ")));
+    assert!(stats.contains(&String::from("STAT curr_connections 1")));
+
+    client.send("quit\r\n");
+    let mut rest = Vec::new();
+    assert_eq!(0, client.reader.read_to_end(&mut rest).unwrap());
+    server.stop();
+}
+
+/// 二进制协议的响应：(opcode, status, opaque, cas, extras, key, value)
+type Binary = (u8, u16, u32, u64, Vec<u8>, Vec<u8>, Vec<u8>);
+
+fn request(opcode: u8, opaque: u32, cas: u64, extras: &[u8], key: &str, value: &str) -> Vec<u8> {
+    let mut out = vec![0x80, opcode];
+    out.extend_from_slice(&(key.len() as u16).to_be_bytes());
+    out.push(extras.len() as u8);
+    out.extend_from_slice(&[0, 0, 0]);
+    out.extend_from_slice(&((extras.len() + key.len() + value.len()) as u32).to_be_bytes());
+    out.extend_from_slice(&opaque.to_be_bytes());
+    out.extend_from_slice(&cas.to_be_bytes());
+    out.extend_from_slice(extras);
+    out.extend_from_slice(key.as_bytes());
+    out.extend_from_slice(value.as_bytes());
+    out
+}
+
+fn response(client: &mut Client) -> Binary {
+    let mut header = [0u8; 24];
+    client.reader.read_exact(&mut header).unwrap();
+    assert_eq!(0x81, header[0]);
+    let key_len = u16::from_be_bytes([header[2], header[3]]) as usize;
+    let extras_len = header[4] as usize;
+    let body_len = u32::from_be_bytes([header[8], header[9], header[10], header[11]]) as usize;
+    let mut body = vec![0u8; body_len];
+    client.reader.read_exact(&mut body).unwrap();
+    let value = body.split_off(extras_len + key_len);
+    let key = body.split_off(extras_len);
+    let mut cas = [0u8; 8];
+    cas.copy_from_slice(&header[16..24]);
+    (
+        header[1],
+        u16::from_be_bytes([header[6], header[7]]),
+        u32::from_be_bytes([header[12], header[13], header[14], header[15]]),
+        u64::from_be_bytes(cas),
+        body,
+        key,
+        value,
+    )
+}
+
+fn set_extras(flags: u32, exptime: u32) -> Vec<u8> {
+    [flags.to_be_bytes(), exptime.to_be_bytes()].concat()
+}
+
+#[test]
+fn binary_protocol() {
+    let server = Running::start();
+    let mut client = server.connect();
+    let call = |client: &mut Client, req: Vec<u8>| {
+        client.stream.write_all(&req).unwrap();
+        response(client)
+    };
+
+    let (opcode, status, opaque, cas, ..) = call(
+        &mut client,
+        request(0x01, 7, 0, &set_extras(5, 0), "key", "value"),
+    );
+    assert_eq!((0x01, 0, 7), (opcode, status, opaque));
+    assert_ne!(0, cas);
+    let (_, status, _, got_cas, extras, key, value) =
+        call(&mut client, request(0x0c, 1, 0, &[], "key", ""));
+    assert_eq!(0, status);
+    assert_eq!(cas, got_cas);
+    assert_eq!(5u32.to_be_bytes().to_vec(), extras);
+    assert_eq!((b"key".to_vec(), b"value".to_vec()), (key, value));
+
+    // CAS 不一致时返回 KEY_EXISTS, add 已经存在的 key 同样返回 KEY_EXISTS
+    let stale = call(
+        &mut client,
+        request(0x01, 2, cas + 1000, &set_extras(0, 0), "key", "x"),
+    );
+    assert_eq!(0x0002, stale.1);
+    assert_eq!(
+        0x0002,
+        call(
+            &mut client,
+            request(0x02, 3, 0, &set_extras(0, 0), "key", "x")
+        )
+        .1
+    );
+    let updated = call(
+        &mut client,
+        request(0x01, 4, cas, &set_extras(0, 0), "key", "v2"),
+    );
+    assert_eq!(0, updated.1);
+    assert_ne!(cas, updated.3);
+    assert_eq!(0, call(&mut client, request(0x0e, 5, 0, &[], "key", "!")).1);
+    assert_eq!(
+        b"v2!".to_vec(),
+        call(&mut client, request(0x00, 6, 0, &[], "key", "")).6
+    );
+
+    // incr：key 不存在时写入初始值, exptime 为 0xffffffff 时不写入
+    let counter = |delta: u64, initial: u64, exptime: u32| {
+        [
+            &delta.to_be_bytes()[..],
+            &initial.to_be_bytes()[..],
+            &exptime.to_be_bytes()[..],
+        ]
+        .concat()
+    };
+    let missing = call(
+        &mut client,
+        request(0x05, 8, 0, &counter(1, 5, u32::MAX), "n", ""),
+    );
+    assert_eq!(0x0001, missing.1);
+    let created = call(&mut client, request(0x05, 9, 0, &counter(1, 5, 0), "n", ""));
+    assert_eq!((0, 5u64.to_be_bytes().to_vec()), (created.1, created.6));
+    let incremented = call(
+        &mut client,
+        request(0x05, 10, 0, &counter(3, 5, 0), "n", ""),
+    );
+    assert_eq!(8u64.to_be_bytes().to_vec(), incremented.6);
+    assert_eq!(
+        0x0006,
+        call(
+            &mut client,
+            request(0x06, 11, 0, &counter(1, 0, 0), "key", "")
+        )
+        .1
+    );
+    assert_eq!("$1", server.resp("GET n"));
+
+    // quiet 命令成功时不回复, noop 的回复说明之前的命令都已经执行
+    let mut batch = request(0x11, 12, 0, &set_extras(0, 0), "q", "quiet");
+    batch.extend(request(0x09, 13, 0, &[], "missing", ""));
+    batch.extend(request(0x0d, 14, 0, &[], "q", ""));
+    batch.extend(request(0x0a, 15, 0, &[], "", ""));
+    client.stream.write_all(&batch).unwrap();
+    let hit = response(&mut client);
+    assert_eq!(
+        (0x0d, 14, b"q".to_vec(), b"quiet".to_vec()),
+        (hit.0, hit.2, hit.5, hit.6)
+    );
+    assert_eq!((0x0a, 15), {
+        let noop = response(&mut client);
+        (noop.0, noop.2)
+    });
+
+    let deleted = call(&mut client, request(0x04, 16, 0, &[], "q", ""));
+    assert_eq!(0, deleted.1);
+    assert_eq!(
+        0x0001,
+        call(&mut client, request(0x04, 17, 0, &[], "q", "")).1
+    );
+    assert_eq!(
+        0,
+        call(
+            &mut client,
+            request(0x1c, 18, 0, &60u32.to_be_bytes(), "key", "")
+        )
+        .1
+    );
+    assert_eq!(":60", server.resp("TTL key"));
+    assert_eq!(
+        0x0081,
+        call(&mut client, request(0x30, 19, 0, &[], "", "")).1
+    );
+    assert!(!call(&mut client, request(0x0b, 20, 0, &[], "", ""))
+        .6
+        .is_empty());
+
+    // stat 以 key 为空的响应结束
+    client
+        .stream
+        .write_all(&request(0x10, 21, 0, &[], "", ""))
+        .unwrap();
+    let mut stats = Vec::new();
+    loop {
+        let stat = response(&mut client);
+        if stat.5.is_empty() {
+            break;
+        }
+        stats.push(String::from_utf8(stat.5).unwrap());
+    }
+    assert!(stats.contains(&String::from("curr_items")));
+
+    assert_eq!(0, call(&mut client, request(0x08, 22, 0, &[], "", "")).1);
+    assert_eq!(":0", server.resp("DBSIZE"));
+    server.stop();
+}
@@ -0,0 +1,194 @@
+use dbcore::Databases;
+use memkv_server::{Server, ServerConfig, ShutdownHandle};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+/// 在后台线程中运行的服务
+struct Running {
+    addr: SocketAddr,
+    handle: ShutdownHandle,
+    thread: JoinHandle<dbcore::Result<()>>,
+}
+
+impl Running {
+    fn start(dbs: Databases) -> Running {
+        let server = Server::bind("127.0.0.1:0", dbs, ServerConfig::default()).unwrap();
+        let addr = server.local_addr().unwrap();
+        let handle = server.shutdown_handle();
+        let thread = thread::spawn(move || server.run());
+        Running {
+            addr,
+            handle,
+            thread,
+        }
+    }
+
+    fn connect(&self) -> Client {
+        let stream = TcpStream::connect(self.addr).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(10)))
+            .unwrap();
+        Client {
+            reader: BufReader::new(stream.try_clone().unwrap()),
+            stream,
+        }
+    }
+
+    fn stop(self) {
+        self.handle.shutdown();
+        self.thread.join().unwrap().unwrap();
+    }
+}
+
+struct Client {
+    stream: TcpStream,
+    reader: BufReader<TcpStream>,
+}
+
+impl Client {
+    /// 发送一条命令并读取回复的原始文本
+    fn call(&mut self, args: &[&str]) -> String {
+        let mut out = format!("*{}\r\n", args.len()).into_bytes();
+        for arg in args {
+            out.extend_from_slice(format!("${}\r\n{}\r\n", arg.len(), arg).as_bytes());
+        }
+        self.stream.write_all(&out).unwrap();
+        self.read_reply()
+    }
+
+    /// 读取一个完整的回复, 返回原始文本
+    fn read_reply(&mut self) -> String {
+        let mut line = String::new();
+        assert!(
+            self.reader.read_line(&mut line).unwrap() > 0,
+            "connection closed"
+        );
+        let count = line[1..line.len() - 2].parse::<i64>().unwrap_or(0);
+        match line.as_bytes()[0] {
+            b'$' if count >= 0 => {
+                let mut data = vec![0u8; count as usize + 2];
+                self.reader.read_exact(&mut data).unwrap();
+                line.push_str(&String::from_utf8_lossy(&data));
+            }
+            b'*' | b'~' => (0..count).for_each(|_| line.push_str(&self.read_reply())),
+            b'%' => (0..count * 2).for_each(|_| line.push_str(&self.read_reply())),
+            _ => {}
+        }
+        line
+    }
+}
+
+fn bulk(value: &str) -> String {
+    format!("${}\r\n{}\r\n", value.len(), value)
+}
+
+const QUEUED: &str = "+QUEUED\r\n";
+
+#[test]
+fn multi_exec_and_discard() {
+    let server = Running::start(Databases::new(2, None));
+    let mut client = server.connect();
+
+    assert_eq!("+OK\r\n", client.call(&["MULTI"]));
+    assert_eq!(
+        "-ERR MULTI calls can not be nested\r\n",
+        client.call(&["MULTI"])
+    );
+    assert_eq!(QUEUED, client.call(&["SADD", "src", "a", "b"]));
+    assert_eq!(QUEUED, client.call(&["GET", "src"]));
+    assert_eq!(QUEUED, client.call(&["SELECT", "1"]));
+    assert_eq!(QUEUED, client.call(&["SET", "counter", "1"]));
+    assert_eq!(
+        "*4\r\n:2\r\n-WRONGTYPE Operation against a key holding the wrong kind of value\r\n+OK\r\n+OK\r\n",
+        client.call(&["EXEC"])
+    );
+    // SELECT 在事务之后仍然有效
+    assert_eq!(bulk("1"), client.call(&["GET", "counter"]));
+
+    assert_eq!("+OK\r\n", client.call(&["MULTI"]));
+    assert_eq!(QUEUED, client.call(&["DEL", "counter"]));
+    assert_eq!("+OK\r\n", client.call(&["DISCARD"]));
+    assert_eq!(bulk("1"), client.call(&["GET", "counter"]));
+    assert_eq!("-ERR EXEC without MULTI\r\n", client.call(&["EXEC"]));
+    assert_eq!("-ERR DISCARD without MULTI\r\n", client.call(&["DISCARD"]));
+
+    server.stop();
+}
+
+#[test]
+fn queueing_errors_abort_exec() {
+    let server = Running::start(Databases::new(1, None));
+    let mut client = server.connect();
+
+    assert_eq!("+OK\r\n", client.call(&["MULTI"]));
+    assert_eq!(QUEUED, client.call(&["SET", "name", "memkv"]));
+    assert_eq!(
+        "-ERR unknown command 'nosuch'\r\n",
+        client.call(&["NOSUCH", "name"])
+    );
+    assert_eq!(
+        "-ERR wrong number of arguments for 'get' command\r\n",
+        client.call(&["GET"])
+    );
+    assert_eq!(
+        "-ERR Command 'subscribe' is not allowed inside MULTI\r\n",
+        client.call(&["SUBSCRIBE", "news"])
+    );
+    assert_eq!(
+        "-EXECABORT Transaction discarded because of previous errors.\r\n",
+        client.call(&["EXEC"])
+    );
+    assert_eq!("$-1\r\n", client.call(&["GET", "name"]));
+
+    server.stop();
+}
+
+#[test]
+fn watch_aborts_exec_when_keys_change() {
+    let server = Running::start(Databases::new(1, None));
+    let mut client = server.connect();
+    let mut other = server.connect();
+    client.call(&["SET", "balance", "10"]);
+
+    // 其他连接修改了被监视的 key
+    assert_eq!("+OK\r\n", client.call(&["WATCH", "balance", "missing"]));
+    assert_eq!("+OK\r\n", client.call(&["MULTI"]));
+    assert_eq!(
+        "-ERR WATCH inside MULTI is not allowed\r\n",
+        client.call(&["WATCH", "balance"])
+    );
+    assert_eq!(QUEUED, client.call(&["SET", "balance", "20"]));
+    assert_eq!("+OK\r\n", other.call(&["SET", "balance", "5"]));
+    assert_eq!("*-1\r\n", client.call(&["EXEC"]));
+    assert_eq!(bulk("5"), client.call(&["GET", "balance"]));
+
+    // EXEC 之后取消监视
+    assert_eq!("+OK\r\n", client.call(&["MULTI"]));
+    assert_eq!(QUEUED, client.call(&["SET", "balance", "20"]));
+    assert_eq!("*1\r\n+OK\r\n", client.call(&["EXEC"]));
+
+    // UNWATCH 之后的修改不影响事务
+    client.call(&["WATCH", "balance"]);
+    assert_eq!("+OK\r\n", client.call(&["UNWATCH"]));
+    other.call(&["SET", "balance", "1"]);
+    client.call(&["MULTI"]);
+    client.call(&["GET", "balance"]);
+    assert_eq!(format!("*1\r\n{}", bulk("1")), client.call(&["EXEC"]));
+
+    // 过期也视为修改
+    client.call(&["WATCH", "balance"]);
+    other.call(&["PEXPIRE", "balance", "1"]);
+    thread::sleep(Duration::from_millis(10));
+    client.call(&["MULTI"]);
+    client.call(&["SET", "balance", "30"]);
+    assert_eq!("*-1\r\n", client.call(&["EXEC"]));
+    client.call(&["HELLO", "3"]);
+    client.call(&["WATCH", "balance"]);
+    other.call(&["SET", "balance", "2"]);
+    client.call(&["MULTI"]);
+    assert_eq!("_\r\n", client.call(&["EXEC"]));
+
+    server.stop();
+}
@@ -0,0 +1,299 @@
+use dbcore::{Databases, EventClass};
+use memkv_server::{Server, ServerConfig, ShutdownHandle};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+/// 在后台线程中运行的服务
+struct Running {
+    addr: SocketAddr,
+    handle: ShutdownHandle,
+    thread: JoinHandle<dbcore::Result<()>>,
+}
+
+impl Running {
+    fn start(dbs: Databases) -> Running {
+        let server = Server::bind("127.0.0.1:0", dbs, ServerConfig::default()).unwrap();
+        let addr = server.local_addr().unwrap();
+        let handle = server.shutdown_handle();
+        let thread = thread::spawn(move || server.run());
+        Running {
+            addr,
+            handle,
+            thread,
+        }
+    }
+
+    fn connect(&self) -> Client {
+        let stream = TcpStream::connect(self.addr).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(10)))
+            .unwrap();
+        Client {
+            reader: BufReader::new(stream.try_clone().unwrap()),
+            stream,
+        }
+    }
+
+    fn stop(self) {
+        self.handle.shutdown();
+        self.thread.join().unwrap().unwrap();
+    }
+}
+
+struct Client {
+    stream: TcpStream,
+    reader: BufReader<TcpStream>,
+}
+
+/// 编码为 RESP 数组
+fn encode(args: &[&str]) -> Vec<u8> {
+    let mut out = format!("*{}\r\n", args.len()).into_bytes();
+    for arg in args {
+        out.extend_from_slice(format!("${}\r\n{}\r\n", arg.len(), arg).as_bytes());
+    }
+    out
+}
+
+impl Client {
+    /// 发送一条命令但不读取回复
+    fn send(&mut self, args: &[&str]) {
+        self.stream.write_all(&encode(args)).unwrap();
+    }
+
+    /// 发送一条命令并读取回复的原始文本
+    fn call(&mut self, args: &[&str]) -> String {
+        self.send(args);
+        self.read_reply()
+    }
+
+    /// 读取一个完整的回复, 返回原始文本
+    fn read_reply(&mut self) -> String {
+        let mut line = String::new();
+        assert!(
+            self.reader.read_line(&mut line).unwrap() > 0,
+            "connection closed"
+        );
+        let count = line[1..line.len() - 2].parse::<i64>().unwrap_or(0);
+        match line.as_bytes()[0] {
+            b'$' if count >= 0 => {
+                let mut data = vec![0u8; count as usize + 2];
+                self.reader.read_exact(&mut data).unwrap();
+                line.push_str(&String::from_utf8_lossy(&data));
+            }
+            b'*' | b'>' => (0..count).for_each(|_| line.push_str(&self.read_reply())),
+            b'%' => (0..count * 2).for_each(|_| line.push_str(&self.read_reply())),
+            _ => {}
+        }
+        line
+    }
+
+    /// 服务端是否已经关闭连接
+    fn closed(&mut self) -> bool {
+        let mut buf = [0u8; 1];
+        matches!(self.reader.read(&mut buf), Ok(0) | Err(_))
+    }
+}
+
+fn bulk(value: &str) -> String {
+    format!("${}\r\n{}\r\n", value.len(), value)
+}
+
+/// RESP2 的订阅确认或消息
+fn push(kind: char, items: &[&str], count: Option<i64>) -> String {
+    let len = items.len() + count.map_or(0, |_| 1);
+    let mut out = format!("{}{}\r\n", kind, len);
+    for item in items {
+        out.push_str(&bulk(item));
+    }
+    if let Some(count) = count {
+        out.push_str(&format!(":{}\r\n", count));
+    }
+    out
+}
+
+#[test]
+fn subscribe_and_publish() {
+    let server = Running::start(Databases::new(1, None));
+    let mut subscriber = server.connect();
+    let mut publisher = server.connect();
+
+    subscriber.send(&["SUBSCRIBE", "news", "alerts"]);
+    assert_eq!(
+        push('*', &["subscribe", "news"], Some(1)),
+        subscriber.read_reply()
+    );
+    assert_eq!(
+        push('*', &["subscribe", "alerts"], Some(2)),
+        subscriber.read_reply()
+    );
+    assert_eq!(
+        push('*', &["psubscribe", "news.*"], Some(3)),
+        subscriber.call(&["PSUBSCRIBE", "news.*"])
+    );
+
+    assert_eq!(":1\r\n", publisher.call(&["PUBLISH", "news", "hello"]));
+    assert_eq!(":1\r\n", publisher.call(&["PUBLISH", "news.tech", "rust"]));
+    assert_eq!(":0\r\n", publisher.call(&["PUBLISH", "sports", "goal"]));
+    assert_eq!(
+        push('*', &["message", "news", "hello"], None),
+        subscriber.read_reply()
+    );
+    assert_eq!(
+        push('*', &["pmessage", "news.*", "news.tech", "rust"], None),
+        subscriber.read_reply()
+    );
+
+    // 订阅期间只能执行订阅相关的命令
+    assert_eq!(
+        "-ERR Can't execute 'get': only (P|S)SUBSCRIBE / (P|S)UNSUBSCRIBE / PING / QUIT are allowed in this context\r\n",
+        subscriber.call(&["GET", "name"])
+    );
+    assert_eq!(push('*', &["pong", ""], None), subscriber.call(&["PING"]));
+
+    assert_eq!(
+        format!("*4\r\n{}:1\r\n{}:0\r\n", bulk("news"), bulk("sports")),
+        publisher.call(&["PUBSUB", "NUMSUB", "news", "sports"])
+    );
+    assert_eq!(
+        format!("*2\r\n{}{}", bulk("alerts"), bulk("news")),
+        publisher.call(&["PUBSUB", "CHANNELS"])
+    );
+    assert_eq!(":1\r\n", publisher.call(&["PUBSUB", "NUMPAT"]));
+
+    // 没有参数时取消所有订阅, 之后可以执行其他命令
+    subscriber.send(&["UNSUBSCRIBE"]);
+    assert_eq!(
+        push('*', &["unsubscribe", "alerts"], Some(2)),
+        subscriber.read_reply()
+    );
+    assert_eq!(
+        push('*', &["unsubscribe", "news"], Some(1)),
+        subscriber.read_reply()
+    );
+    assert_eq!(
+        push('*', &["punsubscribe", "news.*"], Some(0)),
+        subscriber.call(&["PUNSUBSCRIBE"])
+    );
+    assert_eq!("$-1\r\n", subscriber.call(&["GET", "name"]));
+    assert_eq!(":0\r\n", publisher.call(&["PUBLISH", "news", "hello"]));
+
+    server.stop();
+}
+
+#[test]
+fn resp3_clients_receive_push_messages() {
+    let server = Running::start(Databases::new(1, None));
+    let mut subscriber = server.connect();
+    let mut publisher = server.connect();
+    subscriber.call(&["HELLO", "3"]);
+
+    assert_eq!(
+        push('>', &["ssubscribe", "orders"], Some(1)),
+        subscriber.call(&["SSUBSCRIBE", "orders"])
+    );
+    // RESP3 的连接在订阅期间可以执行其他命令
+    assert_eq!("+OK\r\n", subscriber.call(&["SET", "name", "memkv"]));
+    assert_eq!(":0\r\n", publisher.call(&["PUBLISH", "orders", "plain"]));
+    assert_eq!(":1\r\n", publisher.call(&["SPUBLISH", "orders", "created"]));
+    assert_eq!(
+        push('>', &["smessage", "orders", "created"], None),
+        subscriber.read_reply()
+    );
+    assert_eq!(
+        format!("%1\r\n{}:1\r\n", bulk("orders")),
+        subscriber.call(&["PUBSUB", "SHARDNUMSUB", "orders"])
+    );
+
+    // 连接关闭之后取消所有订阅
+    drop(subscriber);
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while publisher.call(&["SPUBLISH", "orders", "created"]) != ":0\r\n" {
+        assert!(Instant::now() < deadline, "subscriptions were not removed");
+        thread::sleep(Duration::from_millis(20));
+    }
+
+    server.stop();
+}
+
+#[test]
+fn slow_subscribers_are_disconnected() {
+    let dbs = Databases::new(1, None);
+    dbs.pubsub().set_subscriber_buffer(4);
+    let server = Running::start(dbs);
+    let mut subscriber = server.connect();
+    let mut publisher = server.connect();
+    subscriber.call(&["SUBSCRIBE", "firehose"]);
+
+    // subscriber 不读取消息, socket 的缓冲区被填满之后消息积压在订阅者的缓冲区中
+    let payload = "x".repeat(64 * 1024);
+    let deadline = Instant::now() + Duration::from_secs(30);
+    while publisher.call(&["PUBLISH", "firehose", &payload]) != ":0\r\n" {
+        assert!(Instant::now() < deadline, "subscriber was not disconnected");
+    }
+    let mut discarded = Vec::new();
+    let _ = subscriber.reader.read_to_end(&mut discarded);
+    assert!(subscriber.closed());
+    // 连接线程退出之后取消订阅
+    while publisher.call(&["PUBSUB", "NUMSUB", "firehose"])
+        != format!("*2\r\n{}:0\r\n", bulk("firehose"))
+    {
+        assert!(Instant::now() < deadline, "subscriptions were not removed");
+        thread::sleep(Duration::from_millis(20));
+    }
+
+    server.stop();
+}
+
+#[test]
+fn keyspace_notifications() {
+    let mut dbs = Databases::new(1, None);
+    dbs.set_notify_keyspace_events(EventClass::parse("KEA").unwrap());
+    let server = Running::start(dbs);
+    let mut subscriber = server.connect();
+    let mut client = server.connect();
+    subscriber.call(&["PSUBSCRIBE", "__keyspace@0__:*"]);
+
+    assert_eq!("+OK\r\n", client.call(&["SET", "name", "memkv"]));
+    assert_eq!(":1\r\n", client.call(&["SADD", "tags", "a"]));
+    assert_eq!(":1\r\n", client.call(&["DEL", "name"]));
+    for (key, event) in [("name", "set"), ("tags", "sadd"), ("name", "del")].iter() {
+        let channel = format!("__keyspace@0__:{}", key);
+        assert_eq!(
+            push(
+                '*',
+                &["pmessage", "__keyspace@0__:*", &channel, event],
+                None
+            ),
+            subscriber.read_reply()
+        );
+    }
+
+    // 过期的 key 被后台删除时产生 expired 事件
+    subscriber.call(&["SUBSCRIBE", "__keyevent@0__:expired"]);
+    assert_eq!(":1\r\n", client.call(&["PEXPIRE", "tags", "10"]));
+    let channel = "__keyspace@0__:tags";
+    assert_eq!(
+        push(
+            '*',
+            &["pmessage", "__keyspace@0__:*", channel, "expire"],
+            None
+        ),
+        subscriber.read_reply()
+    );
+    assert_eq!(
+        push(
+            '*',
+            &["pmessage", "__keyspace@0__:*", channel, "expired"],
+            None
+        ),
+        subscriber.read_reply()
+    );
+    assert_eq!(
+        push('*', &["message", "__keyevent@0__:expired", "tags"], None),
+        subscriber.read_reply()
+    );
+
+    server.stop();
+}
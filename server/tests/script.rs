@@ -0,0 +1,316 @@
+use dbcore::Databases;
+use memkv_server::{Server, ServerConfig, ShutdownHandle};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+/// 在后台线程中运行的服务
+struct Running {
+    addr: SocketAddr,
+    handle: ShutdownHandle,
+    thread: JoinHandle<dbcore::Result<()>>,
+}
+
+impl Running {
+    fn start(config: ServerConfig) -> Running {
+        let server = Server::bind("127.0.0.1:0", Databases::new(2, None), config).unwrap();
+        let addr = server.local_addr().unwrap();
+        let handle = server.shutdown_handle();
+        let thread = thread::spawn(move || server.run());
+        Running {
+            addr,
+            handle,
+            thread,
+        }
+    }
+
+    fn connect(&self) -> Client {
+        let stream = TcpStream::connect(self.addr).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(10)))
+            .unwrap();
+        Client {
+            reader: BufReader::new(stream.try_clone().unwrap()),
+            stream,
+        }
+    }
+
+    fn stop(self) {
+        self.handle.shutdown();
+        self.thread.join().unwrap().unwrap();
+    }
+}
+
+struct Client {
+    stream: TcpStream,
+    reader: BufReader<TcpStream>,
+}
+
+impl Client {
+    /// 发送一条命令, 不读取回复
+    fn send(&mut self, args: &[&str]) {
+        let mut out = format!("*{}\r\n", args.len()).into_bytes();
+        for arg in args {
+            out.extend_from_slice(format!("${}\r\n{}\r\n", arg.len(), arg).as_bytes());
+        }
+        self.stream.write_all(&out).unwrap();
+    }
+
+    /// 发送一条命令并读取回复的原始文本
+    fn call(&mut self, args: &[&str]) -> String {
+        self.send(args);
+        self.read_reply()
+    }
+
+    /// 读取一个完整的回复, 返回原始文本
+    fn read_reply(&mut self) -> String {
+        let mut line = String::new();
+        assert!(
+            self.reader.read_line(&mut line).unwrap() > 0,
+            "connection closed"
+        );
+        let count = line[1..line.len() - 2].parse::<i64>().unwrap_or(0);
+        match line.as_bytes()[0] {
+            b'$' if count >= 0 => {
+                let mut data = vec![0u8; count as usize + 2];
+                self.reader.read_exact(&mut data).unwrap();
+                line.push_str(&String::from_utf8_lossy(&data));
+            }
+            b'*' | b'~' => (0..count).for_each(|_| line.push_str(&self.read_reply())),
+            b'%' => (0..count * 2).for_each(|_| line.push_str(&self.read_reply())),
+            _ => {}
+        }
+        line
+    }
+}
+
+fn bulk(value: &str) -> String {
+    format!("${}\r\n{}\r\n", value.len(), value)
+}
+
+const SET_AND_GET: &str = r#"
+    cmd("SET", KEYS[0], ARGV[0]);
+    cmd("get", KEYS[0])
+"#;
+
+#[test]
+fn eval_and_evalsha() {
+    let server = Running::start(ServerConfig::default());
+    let mut client = server.connect();
+
+    assert_eq!(
+        bulk("memkv"),
+        client.call(&["EVAL", SET_AND_GET, "1", "name", "memkv"])
+    );
+    assert_eq!(bulk("memkv"), client.call(&["GET", "name"]));
+    // 回复与返回值的转换
+    assert_eq!(":3\r\n", client.call(&["EVAL", "1 + 2", "0"]));
+    assert_eq!(
+        format!("*3\r\n:1\r\n$-1\r\n{}", bulk("b")),
+        client.call(&["EVAL", r#"[true, false, ARGV[1]]"#, "0", "a", "b"])
+    );
+    assert_eq!(
+        ":2\r\n",
+        client.call(&[
+            "EVAL",
+            r#"cmd("SADD", "tags", "a", "b"); cmd("SMEMBERS", "tags").len()"#,
+            "0"
+        ])
+    );
+    assert_eq!(
+        "$-1\r\n",
+        client.call(&["EVAL", r#"cmd("GET", "missing")"#, "0"])
+    );
+    assert_eq!("+DONE\r\n", client.call(&["EVAL", r#"#{ok: "DONE"}"#, "0"]));
+    // numkeys 大于参数个数
+    assert_eq!(
+        "-ERR syntax error\r\n",
+        client.call(&["EVAL", "1", "2", "only"])
+    );
+
+    // 脚本按照 SHA1 缓存
+    let loaded = client.call(&["SCRIPT", "LOAD", SET_AND_GET]);
+    let sha = loaded.lines().nth(1).unwrap().to_string();
+    assert_eq!(40, sha.len());
+    assert_eq!(
+        bulk("v2"),
+        client.call(&["EVALSHA", &sha.to_uppercase(), "1", "name", "v2"])
+    );
+    assert_eq!(
+        "*2\r\n:1\r\n:0\r\n",
+        client.call(&["SCRIPT", "EXISTS", &sha, "0000"])
+    );
+    assert_eq!("+OK\r\n", client.call(&["SCRIPT", "FLUSH", "ASYNC"]));
+    assert_eq!(
+        "-NOSCRIPT No matching script. Please use EVAL.\r\n",
+        client.call(&["EVALSHA", &sha, "1", "name", "v3"])
+    );
+    // EVAL 同样缓存脚本
+    client.call(&["EVAL", SET_AND_GET, "1", "name", "v4"]);
+    assert_eq!("*1\r\n:1\r\n", client.call(&["SCRIPT", "EXISTS", &sha]));
+
+    // 脚本在当前数据库上执行, 不能切换数据库
+    client.call(&["SELECT", "1"]);
+    assert_eq!(
+        "$-1\r\n",
+        client.call(&["EVAL", r#"cmd("GET", "name")"#, "0"])
+    );
+    assert_eq!(
+        "-ERR SELECT from scripts is not supported\r\n",
+        client.call(&["EVAL", r#"pcmd("SELECT", 0)"#, "0"])
+    );
+    client.call(&["MULTI"]);
+    assert_eq!(
+        "-ERR Command 'eval' is not allowed inside MULTI\r\n",
+        client.call(&["EVAL", "1", "0"])
+    );
+    client.call(&["DISCARD"]);
+
+    server.stop();
+}
+
+#[test]
+fn read_only_scripts_and_errors() {
+    let server = Running::start(ServerConfig::default());
+    let mut client = server.connect();
+    client.call(&["SET", "name", "memkv"]);
+
+    assert_eq!(
+        bulk("memkv"),
+        client.call(&["EVAL_RO", r#"cmd("GET", "name")"#, "0"])
+    );
+    let reply = client.call(&["EVAL_RO", r#"cmd("DEL", "name")"#, "0"]);
+    assert!(reply.starts_with("-ERR Error running script"), "{}", reply);
+    assert!(reply.contains("Write commands are not allowed from read-only scripts"));
+    assert_eq!(
+        bulk("ERR Write commands are not allowed from read-only scripts"),
+        client.call(&["EVAL_RO", r#"pcmd("DEL", "name").err"#, "0"])
+    );
+    assert_eq!(bulk("memkv"), client.call(&["GET", "name"]));
+
+    // 出错的脚本之前的修改全部回滚
+    let reply = client.call(&[
+        "EVAL",
+        r#"cmd("SET", "created", "1"); cmd("DEL", "name"); cmd("SADD", "created", "x")"#,
+        "0",
+    ]);
+    assert!(reply.contains("WRONGTYPE"), "{}", reply);
+    assert_eq!("$-1\r\n", client.call(&["GET", "created"]));
+    assert_eq!(bulk("memkv"), client.call(&["GET", "name"]));
+    // 捕获错误之后脚本继续执行
+    assert_eq!(
+        bulk("caught"),
+        client.call(&[
+            "EVAL",
+            r#"try { cmd("SADD", "name", "x") } catch (e) { return "caught"; }"#,
+            "0"
+        ])
+    );
+
+    let reply = client.call(&["EVAL", "let = ;", "0"]);
+    assert!(
+        reply.starts_with("-ERR Error compiling script"),
+        "{}",
+        reply
+    );
+    assert!(client
+        .call(&["EVAL", r#"cmd("NOSUCH")"#, "0"])
+        .contains("unknown command 'nosuch'"));
+
+    server.stop();
+}
+
+/// 写入一个 key 之后进入死循环的脚本
+const BUSY_LOOP: &str = r#"cmd("SET", "partial", "1"); let n = 0; loop { n += 1; }"#;
+
+#[test]
+fn scripts_exceeding_the_time_limit_are_rolled_back() {
+    let config = ServerConfig {
+        script_time_limit: Some(Duration::from_millis(100)),
+        ..ServerConfig::default()
+    };
+    let server = Running::start(config);
+    let mut client = server.connect();
+
+    assert_eq!(
+        "-ERR Script exceeded the time limit of 100 ms\r\n",
+        client.call(&["EVAL", BUSY_LOOP, "0"])
+    );
+    assert_eq!("$-1\r\n", client.call(&["GET", "partial"]));
+    assert_eq!(":1\r\n", client.call(&["EVAL", "1", "0"]));
+
+    server.stop();
+}
+
+#[test]
+fn script_kill_stops_a_running_script() {
+    let server = Running::start(ServerConfig::default());
+    let mut client = server.connect();
+    let mut other = server.connect();
+
+    assert_eq!(
+        "-NOTBUSY No scripts in execution right now.\r\n",
+        other.call(&["SCRIPT", "KILL"])
+    );
+    client.send(&["EVAL", BUSY_LOOP, "0"]);
+    // 脚本执行期间持有数据库的锁, SCRIPT KILL 不需要锁
+    loop {
+        match other.call(&["SCRIPT", "KILL"]).as_str() {
+            "+OK\r\n" => break,
+            _ => thread::sleep(Duration::from_millis(10)),
+        }
+    }
+    assert_eq!(
+        "-ERR Script killed by user with SCRIPT KILL\r\n",
+        client.read_reply()
+    );
+    assert_eq!("$-1\r\n", other.call(&["GET", "partial"]));
+    assert_eq!(":0\r\n", client.call(&["DBSIZE"]));
+
+    server.stop();
+}
+
+#[test]
+fn connections_are_accepted_while_a_script_runs() {
+    let server = Running::start(ServerConfig::default());
+    let mut client = server.connect();
+    client.send(&["EVAL", BUSY_LOOP, "0"]);
+    // 等待脚本开始执行并经过几次定期任务, 之后建立的连接仍然会被接受
+    thread::sleep(Duration::from_millis(300));
+    let mut other = server.connect();
+    loop {
+        match other.call(&["SCRIPT", "KILL"]).as_str() {
+            "+OK\r\n" => break,
+            _ => thread::sleep(Duration::from_millis(10)),
+        }
+    }
+    assert_eq!(
+        "-ERR Script killed by user with SCRIPT KILL\r\n",
+        client.read_reply()
+    );
+    assert_eq!("+PONG\r\n", other.call(&["PING"]));
+
+    server.stop();
+}
+
+#[test]
+fn scripts_are_resource_limited() {
+    let server = Running::start(ServerConfig::default());
+    let mut client = server.connect();
+
+    let reply = client.call(&["EVAL", "fn f(n) { f(n + 1) } f(0)", "0"]);
+    assert!(reply.starts_with("-ERR Error running script"), "{}", reply);
+    let reply = client.call(&["EVAL", "let a = [1]; loop { a += a; }", "0"]);
+    assert!(reply.starts_with("-ERR Error running script"), "{}", reply);
+    let nested = format!("{}1{}", "(".repeat(200), ")".repeat(200));
+    let reply = client.call(&["EVAL", &nested, "0"]);
+    assert!(
+        reply.starts_with("-ERR Error compiling script"),
+        "{}",
+        reply
+    );
+    assert_eq!(":1\r\n", client.call(&["EVAL", "1", "0"]));
+
+    server.stop();
+}
@@ -0,0 +1,474 @@
+use dbcore::{Databases, SaveRule};
+use memkv_server::{Server, ServerConfig, ShutdownHandle};
+use std::fs;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::path::PathBuf;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+/// 每个测试使用独立的文件
+fn path(test: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("memkv-server-{}-{}", test, std::process::id()));
+    let _ = fs::remove_file(&path);
+    path
+}
+
+/// 在后台线程中运行的服务
+struct Running {
+    addr: SocketAddr,
+    handle: ShutdownHandle,
+    thread: JoinHandle<dbcore::Result<()>>,
+}
+
+impl Running {
+    fn start(dbs: Databases, config: ServerConfig) -> Running {
+        let server = Server::bind("127.0.0.1:0", dbs, config).unwrap();
+        let addr = server.local_addr().unwrap();
+        let handle = server.shutdown_handle();
+        let thread = thread::spawn(move || server.run());
+        Running {
+            addr,
+            handle,
+            thread,
+        }
+    }
+
+    fn connect(&self) -> Client {
+        let stream = TcpStream::connect(self.addr).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(10)))
+            .unwrap();
+        Client {
+            reader: BufReader::new(stream.try_clone().unwrap()),
+            stream,
+        }
+    }
+
+    fn stop(self) -> dbcore::Result<()> {
+        self.handle.shutdown();
+        self.thread.join().unwrap()
+    }
+}
+
+struct Client {
+    stream: TcpStream,
+    reader: BufReader<TcpStream>,
+}
+
+/// 编码为 RESP 数组
+fn encode(args: &[&[u8]]) -> Vec<u8> {
+    let mut out = format!("*{}\r\n", args.len()).into_bytes();
+    for arg in args {
+        out.extend_from_slice(format!("${}\r\n", arg.len()).as_bytes());
+        out.extend_from_slice(arg);
+        out.extend_from_slice(b"\r\n");
+    }
+    out
+}
+
+impl Client {
+    /// 发送一条命令并读取回复的原始文本
+    fn call(&mut self, args: &[&str]) -> String {
+        let args: Vec<&[u8]> = args.iter().map(|arg| arg.as_bytes()).collect();
+        self.stream.write_all(&encode(&args)).unwrap();
+        self.read_reply()
+    }
+
+    /// 读取一个完整的回复, 返回原始文本
+    fn read_reply(&mut self) -> String {
+        let mut line = String::new();
+        assert!(
+            self.reader.read_line(&mut line).unwrap() > 0,
+            "connection closed"
+        );
+        let count = line[1..line.len() - 2].parse::<i64>().unwrap_or(0);
+        match line.as_bytes()[0] {
+            b'$' if count >= 0 => {
+                let mut data = vec![0u8; count as usize + 2];
+                self.reader.read_exact(&mut data).unwrap();
+                line.push_str(&String::from_utf8_lossy(&data));
+            }
+            b'*' | b'~' => (0..count).for_each(|_| line.push_str(&self.read_reply())),
+            b'%' => (0..count * 2).for_each(|_| line.push_str(&self.read_reply())),
+            _ => {}
+        }
+        line
+    }
+
+    /// 服务端是否已经关闭连接
+    fn closed(&mut self) -> bool {
+        let mut buf = [0u8; 1];
+        matches!(self.reader.read(&mut buf), Ok(0) | Err(_))
+    }
+}
+
+fn bulk(value: &str) -> String {
+    format!("${}\r\n{}\r\n", value.len(), value)
+}
+
+#[test]
+fn strings_sets_and_hashes() {
+    let server = Running::start(Databases::new(2, None), ServerConfig::default());
+    let mut client = server.connect();
+    assert_eq!("+PONG\r\n", client.call(&["PING"]));
+    assert_eq!(bulk("hi"), client.call(&["ping", "hi"]));
+    assert_eq!(bulk("中文"), client.call(&["ECHO", "中文"]));
+
+    assert_eq!("+OK\r\n", client.call(&["SET", "name", "memkv"]));
+    assert_eq!(bulk("memkv"), client.call(&["GET", "name"]));
+    assert_eq!("$-1\r\n", client.call(&["GET", "missing"]));
+    assert_eq!("$-1\r\n", client.call(&["SET", "name", "x", "NX"]));
+    assert_eq!("$-1\r\n", client.call(&["SET", "other", "x", "XX"]));
+    assert_eq!(
+        "+OK\r\n",
+        client.call(&["SET", "volatile", "v", "PX", "99500"])
+    );
+    assert!(client.call(&["PTTL", "volatile"]).starts_with(":99"));
+    assert_eq!(":-1\r\n", client.call(&["TTL", "name"]));
+    assert_eq!(":1\r\n", client.call(&["EXPIRE", "name", "100"]));
+    assert_eq!(":1\r\n", client.call(&["PERSIST", "name"]));
+    assert_eq!(
+        ":2\r\n",
+        client.call(&["EXISTS", "name", "volatile", "missing"])
+    );
+    assert_eq!("+string\r\n", client.call(&["TYPE", "name"]));
+
+    assert_eq!(":2\r\n", client.call(&["SADD", "set", "a", "b", "a"]));
+    assert_eq!(":2\r\n", client.call(&["SCARD", "set"]));
+    assert_eq!(":1\r\n", client.call(&["SISMEMBER", "set", "a"]));
+    assert_eq!(":0\r\n", client.call(&["SCARD", "missing"]));
+    let members = client.call(&["SMEMBERS", "set"]);
+    assert!(members.starts_with("*2\r\n") && members.contains(&bulk("b")));
+    let random = client.call(&["SRANDMEMBER", "set"]);
+    assert!(random == bulk("a") || random == bulk("b"));
+    assert!(client
+        .call(&["SRANDMEMBER", "set", "-5"])
+        .starts_with("*5\r\n"));
+    assert!(client
+        .call(&["SRANDMEMBER", "set", "5"])
+        .starts_with("*2\r\n"));
+    assert_eq!("*0\r\n", client.call(&["SRANDMEMBER", "missing", "-5"]));
+    assert_eq!("$-1\r\n", client.call(&["SRANDMEMBER", "missing"]));
+    assert_eq!(":2\r\n", client.call(&["SCARD", "set"]));
+
+    assert_eq!(
+        ":2\r\n",
+        client.call(&["HSET", "hash", "f1", "v1", "f2", "v2"])
+    );
+    assert_eq!(":0\r\n", client.call(&["HSET", "hash", "f1", "new"]));
+    assert_eq!(bulk("new"), client.call(&["HGET", "hash", "f1"]));
+    assert_eq!(
+        format!("*2\r\n{}$-1\r\n", bulk("v2")),
+        client.call(&["HMGET", "hash", "f2", "nope"])
+    );
+    assert_eq!(
+        "*2\r\n$-1\r\n$-1\r\n",
+        client.call(&["HMGET", "missing", "a", "b"])
+    );
+    assert!(client.call(&["HGETALL", "hash"]).starts_with("*4\r\n"));
+    assert_eq!(":1\r\n", client.call(&["HDEL", "hash", "f1", "nope"]));
+    assert_eq!(":1\r\n", client.call(&["HLEN", "hash"]));
+
+    // 错误使用与 Redis 相同的前缀
+    assert_eq!(
+        "-WRONGTYPE Operation against a key holding the wrong kind of value\r\n",
+        client.call(&["GET", "set"])
+    );
+    assert_eq!(
+        "-ERR wrong number of arguments for 'get' command\r\n",
+        client.call(&["GET"])
+    );
+    assert_eq!(
+        "-ERR unknown command 'lpush'\r\n",
+        client.call(&["LPUSH", "list", "a"])
+    );
+    assert_eq!(
+        "-ERR value is not an integer or out of range\r\n",
+        client.call(&["EXPIRE", "name", "soon"])
+    );
+    assert_eq!(
+        "-ERR no such key\r\n",
+        client.call(&["RENAME", "nope", "x"])
+    );
+    assert_eq!(
+        "-ERR source and destination objects are the same\r\n",
+        client.call(&["COPY", "name", "name"])
+    );
+    assert_eq!(":0\r\n", client.call(&["OBJECT", "IDLETIME", "name"]));
+    assert_eq!("$-1\r\n", client.call(&["OBJECT", "IDLETIME", "nope"]));
+
+    let info = client.call(&["INFO", "commandstats"]);
+    assert!(info.contains("cmdstat_set:calls=4"), "{}", info);
+    assert_eq!("+OK\r\n", client.call(&["QUIT"]));
+    assert!(client.closed());
+    server.stop().unwrap();
+}
+
+#[test]
+fn overflowing_expire_times_are_rejected() {
+    let server = Running::start(Databases::new(1, None), ServerConfig::default());
+    let mut client = server.connect();
+    let invalid = "-ERR invalid expire time\r\n";
+    assert_eq!(
+        invalid,
+        client.call(&["SET", "name", "v", "EX", "18446744073709551"])
+    );
+    assert_eq!(
+        invalid,
+        client.call(&["SET", "name", "v", "PX", "18446744073709551615"])
+    );
+    assert_eq!(invalid, client.call(&["SET", "name", "v", "EX", "0"]));
+    assert_eq!(invalid, client.call(&["SET", "name", "v", "PX", "0"]));
+    assert_eq!(invalid, client.call(&["SET", "name", "v", "EX", "-1"]));
+    assert_eq!(invalid, client.call(&["SET", "name", "v", "PX", "-1000"]));
+    assert_eq!(":0\r\n", client.call(&["EXISTS", "name"]));
+
+    assert_eq!("+OK\r\n", client.call(&["SET", "name", "v"]));
+    assert_eq!(
+        invalid,
+        client.call(&["EXPIRE", "name", "18446744073709551"])
+    );
+    assert_eq!(
+        invalid,
+        client.call(&["PEXPIRE", "name", "18446744073709551615"])
+    );
+    assert_eq!(
+        invalid,
+        client.call(&["EXPIREAT", "name", "18446744073709552"])
+    );
+    assert_eq!(":-1\r\n", client.call(&["TTL", "name"]));
+    server.stop().unwrap();
+}
+
+#[test]
+fn databases_are_selected_per_connection() {
+    let server = Running::start(Databases::new(4, None), ServerConfig::default());
+    let mut first = server.connect();
+    let mut second = server.connect();
+    assert_eq!("+OK\r\n", first.call(&["SELECT", "2"]));
+    assert_eq!("+OK\r\n", first.call(&["SET", "key", "in db2"]));
+    assert_eq!("$-1\r\n", second.call(&["GET", "key"]));
+    assert_eq!(
+        "-ERR DB index is out of range\r\n",
+        second.call(&["SELECT", "4"])
+    );
+    assert_eq!(":1\r\n", first.call(&["MOVE", "key", "0"]));
+    assert_eq!(bulk("in db2"), second.call(&["GET", "key"]));
+    assert_eq!(":1\r\n", second.call(&["DBSIZE"]));
+
+    // DUMP 的结果是二进制数据, 可以原样传给 RESTORE
+    second
+        .stream
+        .write_all(&encode(&[b"DUMP", b"key"]))
+        .unwrap();
+    let mut line = String::new();
+    second.reader.read_line(&mut line).unwrap();
+    let len: usize = line[1..line.len() - 2].parse().unwrap();
+    let mut payload = vec![0u8; len + 2];
+    second.reader.read_exact(&mut payload).unwrap();
+    payload.truncate(len);
+    let request = encode(&[b"RESTORE", b"copy", b"0", &payload]);
+    second.stream.write_all(&request).unwrap();
+    assert_eq!("+OK\r\n", second.read_reply());
+    assert_eq!(bulk("in db2"), second.call(&["GET", "copy"]));
+    server.stop().unwrap();
+}
+
+#[test]
+fn pipelined_and_inline_commands() {
+    let server = Running::start(Databases::new(1, None), ServerConfig::default());
+    let mut client = server.connect();
+    let mut batch = Vec::new();
+    for i in 0..1000 {
+        let (key, value) = (format!("key:{}", i), format!("value:{}", i));
+        batch.extend(encode(&[b"SET", key.as_bytes(), value.as_bytes()]));
+        batch.extend(encode(&[b"GET", key.as_bytes()]));
+    }
+    client.stream.write_all(&batch).unwrap();
+    for i in 0..1000 {
+        assert_eq!("+OK\r\n", client.read_reply());
+        assert_eq!(bulk(&format!("value:{}", i)), client.read_reply());
+    }
+
+    // 内联命令与跨多次写入的命令
+    client
+        .stream
+        .write_all(b"PING\r\nEXISTS key:1 key:2\n")
+        .unwrap();
+    assert_eq!("+PONG\r\n", client.read_reply());
+    assert_eq!(":2\r\n", client.read_reply());
+    let request = encode(&[b"GET", b"key:7"]);
+    for byte in request {
+        client.stream.write_all(&[byte]).unwrap();
+    }
+    assert_eq!(bulk("value:7"), client.read_reply());
+
+    // 协议错误之后连接关闭
+    client.stream.write_all(b"*1\r\n+PING\r\n").unwrap();
+    assert_eq!(
+        "-ERR Protocol error: expected '$', got '+'\r\n",
+        client.read_reply()
+    );
+    assert!(client.closed());
+    server.stop().unwrap();
+}
+
+#[test]
+fn query_buffer_limit_closes_the_connection() {
+    let config = ServerConfig {
+        query_buffer_limit: 64 * 1024,
+        ..ServerConfig::default()
+    };
+    let server = Running::start(Databases::new(1, None), config);
+    let mut client = server.connect();
+    // 跨多次写入的大参数在上限之内可以正常执行
+    let value = "v".repeat(60 * 1024);
+    let request = encode(&[b"SET", b"big", value.as_bytes()]);
+    for piece in request.chunks(1000) {
+        client.stream.write_all(piece).unwrap();
+    }
+    assert_eq!("+OK\r\n", client.read_reply());
+    assert_eq!(bulk(&value), client.call(&["GET", "big"]));
+
+    // 不完整的命令超过上限时关闭连接
+    let request = encode(&[b"SET", b"huge", "v".repeat(1024 * 1024).as_bytes()]);
+    let _ = client.stream.write_all(&request[..128 * 1024]);
+    assert!(client.closed());
+    server.stop().unwrap();
+}
+
+#[test]
+fn hello_negotiates_resp3() {
+    let mut dbs = Databases::new(1, None);
+    let db = dbs.db_mut(0).unwrap();
+    assert_eq!(
+        Ok(1),
+        db.sadd(&String::from("set"), vec![String::from("m")])
+    );
+    assert_eq!(
+        Ok(1),
+        db.hset(&String::from("hash"), String::from("f"), String::from("v"))
+    );
+    let server = Running::start(dbs, ServerConfig::default());
+    let mut client = server.connect();
+
+    let hello = client.call(&["HELLO"]);
+    assert!(hello.starts_with("*14\r\n"), "{}", hello);
+    assert!(hello.contains(&format!("{}:2\r\n", bulk("proto"))));
+    assert_eq!(
+        "-NOPROTO unsupported protocol version\r\n",
+        client.call(&["HELLO", "4"])
+    );
+
+    let hello = client.call(&["HELLO", "3", "AUTH", "default", "any", "SETNAME", "tester"]);
+    assert!(hello.starts_with("%7\r\n"), "{}", hello);
+    assert!(hello.contains(&format!("{}:3\r\n", bulk("proto"))));
+    assert!(hello.contains(&bulk("memkv")));
+    assert_eq!(bulk("tester"), client.call(&["CLIENT", "GETNAME"]));
+    assert_eq!("_\r\n", client.call(&["GET", "missing"]));
+    assert_eq!(
+        format!("%1\r\n{}{}", bulk("f"), bulk("v")),
+        client.call(&["HGETALL", "hash"])
+    );
+    assert_eq!(
+        format!("~1\r\n{}", bulk("m")),
+        client.call(&["SMEMBERS", "set"])
+    );
+    assert!(client
+        .call(&["HELLO", "3", "AUTH", "admin", "secret"])
+        .starts_with("-WRONGPASS"));
+
+    // 回到 RESP2
+    assert!(client.call(&["HELLO", "2"]).starts_with("*14\r\n"));
+    assert_eq!(
+        format!("*2\r\n{}{}", bulk("f"), bulk("v")),
+        client.call(&["HGETALL", "hash"])
+    );
+    assert_eq!("$-1\r\n", client.call(&["GET", "missing"]));
+    server.stop().unwrap();
+}
+
+#[test]
+fn graceful_shutdown_saves_data() {
+    let dbfile = path("shutdown.db");
+    let config = ServerConfig {
+        dbfile: Some(dbfile.clone()),
+        save_rules: SaveRule::parse_rules("3600 1").unwrap(),
+        wal_checkpoint_size: 0,
+        script_time_limit: None,
+        query_buffer_limit: 0,
+    };
+    let server = Running::start(Databases::new(1, None), config.clone());
+    let mut client = server.connect();
+    assert_eq!("+OK\r\n", client.call(&["SET", "key", "value"]));
+
+    // 关闭服务时已经收到的命令仍然会执行并回复
+    client
+        .stream
+        .write_all(&encode(&[b"SET", b"last", b"write"]))
+        .unwrap();
+    assert_eq!("+OK\r\n", client.read_reply());
+    let addr = server.addr;
+    server.stop().unwrap();
+    assert!(client.closed());
+    assert!(TcpStream::connect(addr).is_err());
+
+    let mut loaded = Databases::new(1, None);
+    assert_eq!(Ok(2), loaded.load(&dbfile));
+    assert_eq!(
+        Ok(Some(String::from("write"))),
+        loaded.db(0).unwrap().get(&String::from("last"))
+    );
+
+    // SHUTDOWN NOSAVE 不保存快照
+    let server = Running::start(loaded, config);
+    let mut client = server.connect();
+    assert_eq!(":1\r\n", client.call(&["DEL", "key"]));
+    client
+        .stream
+        .write_all(&encode(&[b"SHUTDOWN", b"NOSAVE"]))
+        .unwrap();
+    assert!(client.closed());
+    server.thread.join().unwrap().unwrap();
+    let mut loaded = Databases::new(1, None);
+    assert_eq!(Ok(2), loaded.load(&dbfile));
+    let _ = fs::remove_file(&dbfile);
+}
+
+#[test]
+fn writes_are_durable_before_replies_with_wal() {
+    let (wal, snapshot) = (path("durable.wal"), path("durable.db"));
+    let (copy, copy_snapshot) = (path("durable-copy.wal"), path("durable-copy.db"));
+    let mut dbs = Databases::new(1, None);
+    dbs.open_wal(&wal, &snapshot).unwrap();
+    let server = Running::start(dbs, ServerConfig::default());
+
+    let clients: Vec<_> = (0..4)
+        .map(|n| {
+            let mut client = server.connect();
+            thread::spawn(move || {
+                for i in 0..50 {
+                    let key = format!("key:{}:{}", n, i);
+                    assert_eq!("+OK\r\n", client.call(&["SET", &key, "v"]));
+                }
+            })
+        })
+        .collect();
+    clients.into_iter().for_each(|c| c.join().unwrap());
+
+    // 回复之前记录已经落盘, 服务仍在运行时复制的日志包含所有写入
+    fs::copy(&wal, &copy).unwrap();
+    let mut recovered = Databases::new(1, None);
+    let recovery = recovered.open_wal(&copy, &copy_snapshot).unwrap();
+    assert_eq!(200, recovery.records);
+    assert_eq!(200, recovered.db(0).unwrap().size());
+    drop(recovered);
+
+    let mut client = server.connect();
+    assert_eq!(":200\r\n", client.call(&["DBSIZE"]));
+    server.stop().unwrap();
+    for file in &[wal, snapshot, copy, copy_snapshot] {
+        let _ = fs::remove_file(file);
+    }
+}
@@ -0,0 +1,251 @@
+use dbcore::{DBError, Databases};
+use memkv_server::{Server, ServerConfig, ShutdownHandle, TlsConfig, TlsHandle};
+use rcgen::{BasicConstraints, Certificate, CertificateParams, IsCa};
+use rustls::{ClientConfig, ClientConnection, RootCertStore, StreamOwned};
+use std::convert::TryInto;
+use std::fs;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+/// 测试使用的临时目录, drop 时删除
+struct TempDir(PathBuf);
+
+impl TempDir {
+    fn new(name: &str) -> TempDir {
+        let dir = std::env::temp_dir().join(format!("memkv-tls-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        TempDir(dir)
+    }
+
+    fn file(&self, name: &str) -> PathBuf {
+        self.0.join(name)
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+/// 本地生成的 CA
+struct Authority(Certificate);
+
+/// CA 签发的证书与私钥
+struct Issued {
+    cert: Certificate,
+    der: Vec<u8>,
+    pem: String,
+}
+
+impl Authority {
+    fn new() -> Authority {
+        let mut params = CertificateParams::new(Vec::new());
+        params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        Authority(Certificate::from_params(params).unwrap())
+    }
+
+    fn der(&self) -> Vec<u8> {
+        self.0.serialize_der().unwrap()
+    }
+
+    fn pem(&self) -> String {
+        self.0.serialize_pem().unwrap()
+    }
+
+    fn issue(&self, name: &str) -> Issued {
+        let cert =
+            Certificate::from_params(CertificateParams::new(vec![name.to_string()])).unwrap();
+        Issued {
+            der: cert.serialize_der_with_signer(&self.0).unwrap(),
+            pem: cert.serialize_pem_with_signer(&self.0).unwrap(),
+            cert,
+        }
+    }
+}
+
+impl Issued {
+    /// 把证书与私钥写入 dir, 返回对应的配置
+    fn install(&self, dir: &TempDir) -> TlsConfig {
+        let config = TlsConfig::new(dir.file("server.crt"), dir.file("server.key"));
+        fs::write(&config.cert, &self.pem).unwrap();
+        fs::write(&config.key, self.cert.serialize_private_key_pem()).unwrap();
+        config
+    }
+}
+
+/// 在后台线程中运行的 TLS 服务
+struct Running {
+    addr: SocketAddr,
+    memcached: SocketAddr,
+    tls: TlsHandle,
+    handle: ShutdownHandle,
+    thread: JoinHandle<dbcore::Result<()>>,
+}
+
+impl Running {
+    fn start(config: TlsConfig) -> Running {
+        let mut server = Server::bind(
+            "127.0.0.1:0",
+            Databases::new(4, None),
+            ServerConfig::default(),
+        )
+        .unwrap();
+        let memcached = server.listen_memcached("127.0.0.1:0").unwrap();
+        let tls = server.enable_tls(config).unwrap();
+        let addr = server.local_addr().unwrap();
+        let handle = server.shutdown_handle();
+        let thread = thread::spawn(move || server.run());
+        Running {
+            addr,
+            memcached,
+            tls,
+            handle,
+            thread,
+        }
+    }
+
+    fn stop(self) {
+        self.handle.shutdown();
+        self.thread.join().unwrap().unwrap();
+    }
+}
+
+/// 信任 ca, 可以提供客户端证书的 TLS 客户端
+fn client(ca: &Authority, identity: Option<&Issued>) -> Arc<ClientConfig> {
+    let mut roots = RootCertStore::empty();
+    roots.add(&rustls::Certificate(ca.der())).unwrap();
+    let builder = ClientConfig::builder()
+        .with_safe_defaults()
+        .with_root_certificates(roots);
+    let config = match identity {
+        Some(issued) => builder
+            .with_client_auth_cert(
+                vec![rustls::Certificate(issued.der.clone())],
+                rustls::PrivateKey(issued.cert.serialize_private_key_der()),
+            )
+            .unwrap(),
+        None => builder.with_no_client_auth(),
+    };
+    Arc::new(config)
+}
+
+/// 通过 TLS 发送 inline 命令, 返回每条命令回复的第一行
+fn call(addr: SocketAddr, config: Arc<ClientConfig>, commands: &[&str]) -> io::Result<Vec<String>> {
+    let socket = TcpStream::connect(addr)?;
+    socket.set_read_timeout(Some(Duration::from_secs(10)))?;
+    let session =
+        ClientConnection::new(config, "localhost".try_into().unwrap()).map_err(io::Error::other)?;
+    let mut stream = StreamOwned::new(session, socket);
+    for command in commands {
+        stream.write_all(format!("{}\r\n", command).as_bytes())?;
+    }
+    let mut reader = BufReader::new(stream);
+    let mut replies = Vec::new();
+    for _ in commands {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        replies.push(String::from(line.trim_end()));
+    }
+    Ok(replies)
+}
+
+#[test]
+fn serves_resp_and_memcached_over_tls() {
+    let dir = TempDir::new("serve");
+    let ca = Authority::new();
+    let server = Running::start(ca.issue("localhost").install(&dir));
+
+    assert_eq!(
+        vec!["+OK", "$5"],
+        call(
+            server.addr,
+            client(&ca, None),
+            &["SET name memkv", "GET name"]
+        )
+        .unwrap()
+    );
+    let version = call(server.memcached, client(&ca, None), &["version"]).unwrap();
+    assert!(version[0].starts_with("VERSION "), "{:?}", version);
+
+    // 证书不是由信任的 CA 签发时客户端拒绝连接
+    assert!(call(server.addr, client(&Authority::new(), None), &["PING"]).is_err());
+
+    // 不使用 TLS 的客户端收不到回复
+    let mut plain = TcpStream::connect(server.addr).unwrap();
+    plain
+        .set_read_timeout(Some(Duration::from_secs(10)))
+        .unwrap();
+    let _ = plain.write_all(b"PING\r\n");
+    let mut line = String::new();
+    let _ = BufReader::new(plain).read_line(&mut line);
+    assert!(!line.contains("PONG"), "{:?}", line);
+
+    server.stop();
+}
+
+#[test]
+fn mutual_tls_verifies_client_certificates() {
+    let dir = TempDir::new("mutual");
+    let ca = Authority::new();
+    let clients = Authority::new();
+    let config = TlsConfig {
+        client_ca: Some(dir.file("clients.crt")),
+        ..ca.issue("localhost").install(&dir)
+    };
+    fs::write(dir.file("clients.crt"), clients.pem()).unwrap();
+    let server = Running::start(config);
+
+    let trusted = clients.issue("sidecar");
+    assert_eq!(
+        vec!["+PONG"],
+        call(server.addr, client(&ca, Some(&trusted)), &["PING"]).unwrap()
+    );
+    assert!(call(server.addr, client(&ca, None), &["PING"]).is_err());
+    let untrusted = Authority::new().issue("sidecar");
+    assert!(call(server.addr, client(&ca, Some(&untrusted)), &["PING"]).is_err());
+
+    server.stop();
+}
+
+#[test]
+fn certificates_are_reloaded() {
+    let dir = TempDir::new("reload");
+    let first = Authority::new();
+    let config = first.issue("localhost").install(&dir);
+    let server = Running::start(config.clone());
+    assert!(call(server.addr, client(&first, None), &["PING"]).is_ok());
+
+    // 手动重新加载
+    let second = Authority::new();
+    second.issue("localhost").install(&dir);
+    server.tls.reload().unwrap();
+    assert!(call(server.addr, client(&second, None), &["PING"]).is_ok());
+    assert!(call(server.addr, client(&first, None), &["PING"]).is_err());
+
+    // 加载失败时继续使用原来的证书
+    fs::write(&config.cert, "not a certificate").unwrap();
+    assert!(matches!(
+        server.tls.reload(),
+        Err(DBError::InvalidPayload(_))
+    ));
+    assert!(call(server.addr, client(&second, None), &["PING"]).is_ok());
+
+    // 文件被修改之后自动重新加载
+    let third = Authority::new();
+    third.issue("localhost").install(&dir);
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while call(server.addr, client(&third, None), &["PING"]).is_err() {
+        assert!(Instant::now() < deadline, "certificates were not reloaded");
+        thread::sleep(Duration::from_millis(50));
+    }
+
+    server.stop();
+}
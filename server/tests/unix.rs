@@ -0,0 +1,195 @@
+#![cfg(unix)]
+
+use dbcore::{DBError, Databases};
+use memkv_server::{
+    AccessRule, PeerCredentials, Server, ServerConfig, ShutdownHandle, UnixSocketConfig,
+};
+use std::fs;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::thread::{self, JoinHandle};
+
+/// 测试使用的临时目录, drop 时删除
+struct TempDir(PathBuf);
+
+impl TempDir {
+    fn new(name: &str) -> TempDir {
+        let dir = std::env::temp_dir().join(format!("memkv-unix-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        TempDir(dir)
+    }
+
+    fn socket(&self) -> PathBuf {
+        self.0.join("memkv.sock")
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+/// 在后台线程中运行的服务, 同时监听 Unix socket
+struct Running {
+    handle: ShutdownHandle,
+    thread: JoinHandle<dbcore::Result<()>>,
+}
+
+impl Running {
+    fn start(config: UnixSocketConfig) -> dbcore::Result<Running> {
+        let mut server = Server::bind(
+            "127.0.0.1:0",
+            Databases::new(4, None),
+            ServerConfig::default(),
+        )?;
+        server.listen_unix(config)?;
+        let handle = server.shutdown_handle();
+        let thread = thread::spawn(move || server.run());
+        Ok(Running { handle, thread })
+    }
+
+    fn stop(self) {
+        self.handle.shutdown();
+        self.thread.join().unwrap().unwrap();
+    }
+}
+
+/// 发送 inline 命令, 返回每条命令回复的第一行
+fn call(path: &Path, commands: &[&str]) -> Vec<String> {
+    let mut stream = UnixStream::connect(path).unwrap();
+    for command in commands {
+        stream
+            .write_all(format!("{}\r\n", command).as_bytes())
+            .unwrap();
+    }
+    let mut reader = BufReader::new(stream);
+    commands
+        .iter()
+        .map(|_| {
+            let mut line = String::new();
+            reader.read_line(&mut line).unwrap();
+            String::from(line.trim_end())
+        })
+        .collect()
+}
+
+/// 当前进程的凭据
+fn own_credentials() -> PeerCredentials {
+    let (a, _b) = UnixStream::pair().unwrap();
+    PeerCredentials::of(&a).unwrap()
+}
+
+#[test]
+fn serves_resp_over_unix_socket() {
+    let dir = TempDir::new("serve");
+    let path = dir.socket();
+    let config = UnixSocketConfig {
+        mode: Some(0o600),
+        ..UnixSocketConfig::new(&path)
+    };
+    let server = Running::start(config).unwrap();
+
+    let mode = fs::metadata(&path).unwrap().permissions().mode();
+    assert_eq!(0o600, mode & 0o777);
+    assert_eq!(
+        vec!["+OK", "$5"],
+        call(&path, &["SET name memkv", "GET name"])
+    );
+
+    // 服务关闭之后删除 socket 文件
+    server.stop();
+    assert!(!path.exists());
+}
+
+#[test]
+fn stale_sockets_are_replaced() {
+    let dir = TempDir::new("stale");
+    let path = dir.socket();
+    // 异常退出的进程留下的 socket 文件：没有进程在监听
+    drop(UnixListener::bind(&path).unwrap());
+    assert!(path.exists());
+    let server = Running::start(UnixSocketConfig::new(&path)).unwrap();
+    assert_eq!(vec!["+PONG"], call(&path, &["PING"]));
+
+    // 已经有服务在监听时不会删除它的 socket
+    let second = Running::start(UnixSocketConfig::new(&path));
+    assert!(
+        matches!(&second, Err(DBError::Io(e)) if e.contains("another server is listening")),
+        "{:?}",
+        second.map(|_| ())
+    );
+    assert_eq!(vec!["+PONG"], call(&path, &["PING"]));
+    server.stop();
+
+    // 不是 socket 的文件不会被删除
+    fs::write(&path, "data").unwrap();
+    let occupied = Running::start(UnixSocketConfig::new(&path));
+    assert!(matches!(occupied, Err(DBError::Io(_))));
+    assert_eq!("data", fs::read_to_string(&path).unwrap());
+}
+
+#[test]
+fn access_rules_check_peer_credentials() {
+    let me = own_credentials();
+    let allowed = [
+        AccessRule::Uid(me.uid),
+        AccessRule::Gid(me.gid),
+        AccessRule::SameUser,
+    ];
+    for rule in allowed.iter() {
+        let dir = TempDir::new("allowed");
+        let path = dir.socket();
+        let config = UnixSocketConfig {
+            access: vec![AccessRule::Uid(me.uid.wrapping_add(1)), *rule],
+            ..UnixSocketConfig::new(&path)
+        };
+        let server = Running::start(config).unwrap();
+        assert_eq!(vec!["+PONG"], call(&path, &["PING"]), "{:?}", rule);
+        server.stop();
+    }
+
+    let dir = TempDir::new("denied");
+    let path = dir.socket();
+    let config = UnixSocketConfig {
+        access: vec![
+            AccessRule::Uid(me.uid.wrapping_add(1)),
+            AccessRule::Gid(me.gid.wrapping_add(1)),
+        ],
+        ..UnixSocketConfig::new(&path)
+    };
+    let server = Running::start(config).unwrap();
+    let mut stream = UnixStream::connect(&path).unwrap();
+    let mut reply = String::new();
+    stream.read_to_string(&mut reply).unwrap();
+    assert!(reply.starts_with("-NOPERM"), "{:?}", reply);
+    server.stop();
+}
+
+#[test]
+fn access_rules_are_parsed() {
+    assert_eq!(Ok(AccessRule::Uid(1000)), "uid:1000".parse());
+    assert_eq!(Ok(AccessRule::Gid(0)), "gid:0".parse());
+    assert_eq!(Ok(AccessRule::SameUser), "same-user".parse());
+    assert!(matches!(
+        "uid:me".parse::<AccessRule>(),
+        Err(DBError::Syntax(_))
+    ));
+    assert!("user:1".parse::<AccessRule>().is_err());
+
+    let peer = PeerCredentials {
+        pid: None,
+        uid: 1000,
+        gid: 100,
+    };
+    assert!(UnixSocketConfig::new("/tmp/x.sock").allows(&peer));
+    let config = UnixSocketConfig {
+        access: vec![AccessRule::Gid(100)],
+        ..UnixSocketConfig::new("/tmp/x.sock")
+    };
+    assert!(config.allows(&peer));
+    assert!(!config.allows(&PeerCredentials { gid: 101, ..peer }));
+}
@@ -52,6 +52,7 @@ fn cmd_hints() -> HashSet<String> {
     set.insert(String::from("srem key member [member ...]"));
     set.insert(String::from("slen key"));
     set.insert(String::from("smembers key"));
+    set.insert(String::from("sscan key cursor [match pattern] [count n]"));
 
     set.insert(String::from("hget key field"));
     set.insert(String::from("hset key field value"));
@@ -62,16 +63,69 @@ fn cmd_hints() -> HashSet<String> {
     set.insert(String::from("hexists key field"));
     set.insert(String::from("hlen key"));
     set.insert(String::from("hdel key field"));
+    set.insert(String::from("hscan key cursor [match pattern] [count n]"));
 
     set.insert(String::from("del key [key ...]"));
     set.insert(String::from("exists key"));
     set.insert(String::from("size"));
+    set.insert(String::from("keys pattern"));
+    set.insert(String::from(
+        "scan cursor [match pattern] [count n] [type string|set|hash]",
+    ));
+    set.insert(String::from("type key"));
+    set.insert(String::from("randomkey"));
+    set.insert(String::from("expire key seconds"));
+    set.insert(String::from("ttl key"));
+    set.insert(String::from("persist key"));
+    set.insert(String::from("rename key newkey"));
+    set.insert(String::from("renamenx key newkey"));
+    set.insert(String::from("copy source destination [replace]"));
+    set.insert(String::from("touch key [key ...]"));
+    set.insert(String::from("idletime key"));
+    set.insert(String::from("unlink key [key ...]"));
+    set.insert(String::from("dump key"));
+    set.insert(String::from("restore key ttl_ms hex_payload [replace]"));
+
+    set.insert(String::from("select index"));
+    set.insert(String::from("swapdb index1 index2"));
+    set.insert(String::from("move key db"));
+    set.insert(String::from("flushdb [async]"));
+    set.insert(String::from("flushall [async]"));
+    set.insert(String::from(
+        "info [server|memory|persistence|stats|commandstats|keyspace]",
+    ));
+    set.insert(String::from("save"));
+    set.insert(String::from("bgsave"));
+    set.insert(String::from("lastsave"));
+    set.insert(String::from("bgrewriteaof"));
+    set.insert(String::from("checkpoint"));
+    set.insert(String::from("history lsn n|time unix_ms|ago 1h"));
+    set.insert(String::from("history close"));
+    set.insert(String::from("history get key"));
+    set.insert(String::from("importrdb /path/to/dump.rdb"));
+    set.insert(String::from(
+        "export jsonl|csv /path/to/file [match pattern] [type string|set|hash]",
+    ));
+    set.insert(String::from(
+        "import jsonl|csv /path/to/file [match pattern] [type string|set|hash]",
+    ));
 
     set
 }
 
-pub fn cmd_repl() -> Editor<CmdHelper> {
-    let hint = CmdHelper { hints: cmd_hints() };
+/// 是否为 REPL 的内置命令, 自定义命令与内置命令同名时内置命令优先
+pub fn is_builtin(name: &str) -> bool {
+    cmd_hints()
+        .iter()
+        .filter_map(|hint| hint.split_whitespace().next())
+        .any(|builtin| builtin.eq_ignore_ascii_case(name))
+}
+
+/// REPL 的编辑器, custom 为注册的自定义命令的名称, 同样出现在提示与帮助信息中
+pub fn cmd_repl(custom: Vec<String>) -> Editor<CmdHelper> {
+    let mut hints = cmd_hints();
+    hints.extend(custom.into_iter().map(|name| format!("{} [arg ...]", name)));
+    let hint = CmdHelper { hints };
     let mut rl = Editor::new();
     rl.set_helper(Some(hint));
     rl
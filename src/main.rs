@@ -1,8 +1,18 @@
 use clap::Clap;
-use dbcore::{DBError, Result, KVDB};
+use dbcore::{
+    DBError, Databases, EncryptionKey, EventClass, EvictionPolicy, ExportFormat, FsyncPolicy,
+    History, HistoryView, RestorePoint, Result, SaveRule, ValueType, DEFAULT_SCAN_COUNT,
+    ENCRYPTION_KEY_ENV, KVDB,
+};
+use memkv_server::{AccessRule, Server, ServerConfig, TlsConfig, UnixSocketConfig};
 use rustyline::error::ReadlineError;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 mod cmd;
+mod plugins;
 use cmd::CmdHelper;
 
 /// 内存 key-value 数据库 kvbd 使用说明, 使用 --help 现实详细帮助信息
@@ -13,9 +23,187 @@ pub struct BootstrapOpts {
     #[clap(short = "s", long = "key_size", default_value = "256")]
     keys: usize,
 
+    /// 配置逻辑数据库的数量， 默认是16
+    #[clap(short = "d", long = "databases", default_value = "16")]
+    databases: usize,
+
+    /// 快照文件的路径, 启动时从该文件加载数据, save / bgsave 保存到该文件
+    #[clap(short = "f", long = "dbfile")]
+    dbfile: Option<String>,
+
+    /// 自动保存规则, "秒数 修改次数" 对, 空字符串表示不自动保存
+    #[clap(long = "save", default_value = "3600 1 300 100 60 10000")]
+    save: String,
+
+    /// AOF 文件的路径, 指定后开启 AOF, 启动时从该文件（而不是快照文件）恢复数据
+    #[clap(long = "aof")]
+    aof: Option<String>,
+
+    /// AOF 的 fsync 策略： always / everysec / no
+    #[clap(long = "appendfsync", default_value = "everysec")]
+    appendfsync: String,
+
+    /// key 的数量达到上限时的淘汰策略： noeviction / allkeys-lru / allkeys-random
+    #[clap(long = "maxkeys-policy", default_value = "noeviction")]
+    maxkeys_policy: String,
+
+    /// WAL 文件的路径, 指定后开启预写日志, 每个修改命令落盘之后才返回；需要同时指定 --dbfile 作为检查点快照
+    #[clap(long = "wal")]
+    wal: Option<String>,
+
+    /// WAL 归档目录, 检查点把被清空的日志与快照保存到该目录, 用于恢复更早的时间点
+    #[clap(long = "wal-archive")]
+    wal_archive: Option<String>,
+
+    /// WAL 超过该字节数时自动执行检查点, 0 表示不自动执行
+    #[clap(long = "wal-checkpoint-size", default_value = "67108864")]
+    wal_checkpoint_size: u64,
+
+    /// 加密密钥文件（32 字节或 64 个十六进制字符）, 指定后快照、AOF、WAL 都加密保存；
+    /// 未指定时读取环境变量 MEMKV_ENCRYPTION_KEY 中的十六进制密钥
+    #[clap(long = "encryption-key-file")]
+    encryption_key_file: Option<String>,
+
+    /// 字符串超过该字节数时使用 LZ4 压缩保存, 0 表示不压缩
+    #[clap(long = "compress-threshold", default_value = "0")]
+    compress_threshold: usize,
+
     /// 输出信息的详细程度，可多次使用
     #[clap(short = "v", long = "verbose", parse(from_occurrences))]
     verbose: i32,
+
+    /// 不进入交互模式, 执行子命令后退出
+    #[clap(subcommand)]
+    command: Option<Command>,
+}
+
+/// 非交互模式的子命令
+#[derive(Clap)]
+pub enum Command {
+    /// 从 --dbfile 或 --aof 恢复数据, 导出后退出
+    Export(TransferOpts),
+    /// 导入数据, 保存到 --dbfile 或 --aof 后退出
+    Import(TransferOpts),
+    /// 根据 --wal、--dbfile 与 --wal-archive 重建指定时间点的数据, 保存为新的快照文件后退出
+    Restore(RestoreOpts),
+    /// 用新的密钥重写 --dbfile、--aof、--wal 以及 --wal-archive 中的文件后退出；
+    /// 加密未加密的文件时不要指定当前的密钥（--encryption-key-file 与 MEMKV_ENCRYPTION_KEY）
+    RotateKey(RotateKeyOpts),
+    /// 以服务模式运行, 通过 RESP 协议（与 Redis 相同）提供服务, 收到 SIGINT / SIGTERM 或 SHUTDOWN 命令后保存数据并退出
+    Serve(ServeOpts),
+}
+
+/// serve 的参数
+#[derive(Clap)]
+pub struct ServeOpts {
+    /// 监听的地址
+    #[clap(long = "bind", default_value = "127.0.0.1")]
+    bind: String,
+
+    /// 监听的端口
+    #[clap(short = "p", long = "port", default_value = "6379")]
+    port: u16,
+
+    /// 在该端口上同时提供 HTTP/JSON 接口
+    #[clap(long = "http-port")]
+    http_port: Option<u16>,
+
+    /// 在该端口上同时提供 memcached 协议
+    #[clap(long = "memcached-port")]
+    memcached_port: Option<u16>,
+
+    /// 同时在该路径的 Unix socket 上提供服务
+    #[clap(long = "unix-socket")]
+    unix_socket: Option<String>,
+
+    /// Unix socket 文件的权限（八进制）, 例如 770
+    #[clap(long = "unix-socket-mode")]
+    unix_socket_mode: Option<String>,
+
+    /// 接受 Unix socket 连接的规则, 以空格分隔：uid:<n>、gid:<n>、same-user, 满足任意一条即可
+    #[clap(long = "unix-socket-allow")]
+    unix_socket_allow: Option<String>,
+
+    /// TLS 证书链（PEM）, 设置之后所有 TCP 端口只接受 TLS 连接, 需要同时设置 --tls-key
+    #[clap(long = "tls-cert")]
+    tls_cert: Option<String>,
+
+    /// TLS 私钥（PEM）
+    #[clap(long = "tls-key")]
+    tls_key: Option<String>,
+
+    /// 签发客户端证书的 CA（PEM）, 设置之后客户端必须提供证书
+    #[clap(long = "tls-client-ca")]
+    tls_client_ca: Option<String>,
+
+    /// 每个订阅者最多积压的消息数量, 超出时断开连接
+    #[clap(long = "pubsub-buffer", default_value = "1024")]
+    pubsub_buffer: usize,
+
+    /// 启用的键空间通知类别, 格式与 Redis 的 notify-keyspace-events 相同, 例如 KEA
+    #[clap(long = "notify-keyspace-events", default_value = "")]
+    notify_keyspace_events: String,
+
+    /// 脚本执行的最长时间（毫秒）, 超时的脚本被终止并回滚, 0 表示不限制
+    #[clap(long = "script-time-limit", default_value = "5000")]
+    script_time_limit: u64,
+
+    /// 单个连接的查询缓冲区的上限（字节）, 超过时关闭连接, 0 表示使用默认的 1GB
+    #[clap(long = "client-query-buffer-limit", default_value = "0")]
+    client_query_buffer_limit: usize,
+}
+
+/// 读取新密钥的环境变量
+const NEW_ENCRYPTION_KEY_ENV: &str = "MEMKV_NEW_ENCRYPTION_KEY";
+
+/// rotate-key 的参数, 新的密钥来自 --new-key-file 或环境变量 MEMKV_NEW_ENCRYPTION_KEY
+#[derive(Clap)]
+pub struct RotateKeyOpts {
+    /// 新的密钥文件
+    #[clap(long = "new-key-file")]
+    new_key_file: Option<String>,
+
+    /// 不使用新的密钥, 把文件改为不加密保存
+    #[clap(long = "decrypt")]
+    decrypt: bool,
+}
+
+/// restore 的参数, --lsn / --time / --ago 三选一
+#[derive(Clap)]
+pub struct RestoreOpts {
+    /// 恢复到该 LSN 的记录执行之后
+    #[clap(long = "lsn")]
+    lsn: Option<u64>,
+
+    /// 恢复到该毫秒时间戳
+    #[clap(long = "time")]
+    time: Option<u64>,
+
+    /// 恢复到多久之前, 例如 90s、15m、1h、2d
+    #[clap(long = "ago")]
+    ago: Option<String>,
+
+    /// 保存恢复结果的快照文件, 之后可以通过 --dbfile 加载或导出
+    output: String,
+}
+
+/// export / import 的参数
+#[derive(Clap)]
+pub struct TransferOpts {
+    /// 文件格式： jsonl / csv
+    #[clap(long = "format", default_value = "jsonl")]
+    format: String,
+
+    /// 只处理匹配该模式的 key
+    #[clap(long = "match")]
+    pattern: Option<String>,
+
+    /// 只处理指定类型的 key： string / set / hash
+    #[clap(long = "type")]
+    value_type: Option<String>,
+
+    /// 文件路径, "-" 表示标准输出（export）或标准输入（import）
+    file: String,
 }
 
 fn print_result<T>(res: Result<T>)
@@ -27,7 +215,7 @@ where
             println!("{:?}", s);
         }
         Err(e) => {
-            println!("{:?}", e);
+            println!("{}", e);
         }
     }
 }
@@ -44,7 +232,7 @@ where
             println!("(empty or not found)");
         }
         Err(e) => {
-            println!("{:?}", e);
+            println!("{}", e);
         }
     }
 }
@@ -55,48 +243,198 @@ fn parse_bool(s: &str) -> Result<bool> {
     } else if s == "false" {
         Ok(false)
     } else {
-        Err(DBError::WrongValueType)
+        Err(DBError::Syntax(format!("`{}` is not true or false", s)))
+    }
+}
+
+/// scan 类命令的可选参数： [match pattern] [count n] [type t]
+struct ScanOpts {
+    pattern: Option<String>,
+    count: usize,
+    value_type: Option<ValueType>,
+}
+
+fn parse_scan_opts(words: &[&str]) -> Option<ScanOpts> {
+    let mut opts = ScanOpts {
+        pattern: None,
+        count: DEFAULT_SCAN_COUNT,
+        value_type: None,
+    };
+    if !words.len().is_multiple_of(2) {
+        return None;
+    }
+    for pair in words.chunks(2) {
+        match pair[0] {
+            "match" => opts.pattern = Some(String::from(pair[1])),
+            "count" => match usize::from_str_radix(pair[1], 10) {
+                Ok(n) if n > 0 => opts.count = n,
+                _ => return None,
+            },
+            "type" => opts.value_type = Some(ValueType::from_name(pair[1])?),
+            _ => return None,
+        }
+    }
+    Some(opts)
+}
+
+fn print_scan<T>(res: Result<(u64, Vec<T>)>)
+where
+    T: std::fmt::Debug,
+{
+    match res {
+        Ok((cursor, items)) => {
+            println!("cursor: {}", cursor);
+            items.iter().for_each(|item| println!("    {:?}", item));
+        }
+        Err(e) => {
+            println!("{}", e);
+        }
     }
 }
 
+/// scan cursor [match pattern] [count n] [type t]
+/// sscan key cursor [match pattern] [count n]
+/// hscan key cursor [match pattern] [count n]
+fn process_scan(db: &KVDB, words: &[&str]) {
+    let (key, args) = match words[0] {
+        "scan" => (None, &words[1..]),
+        _ if words.len() > 1 => (Some(String::from(words[1])), &words[2..]),
+        _ => (None, &words[words.len()..]),
+    };
+    let cursor = args.first().and_then(|c| u64::from_str_radix(c, 10).ok());
+    let opts = args.get(1..).and_then(parse_scan_opts);
+    if let (Some(cursor), Some(opts)) = (cursor, opts) {
+        let pattern = opts.pattern.as_deref();
+        match (words[0], key) {
+            ("scan", None) => {
+                print_scan(Ok(db.scan(cursor, pattern, opts.count, opts.value_type)));
+                return;
+            }
+            ("sscan", Some(key)) if opts.value_type.is_none() => {
+                print_scan(db.sscan(&key, cursor, pattern, opts.count));
+                return;
+            }
+            ("hscan", Some(key)) if opts.value_type.is_none() => {
+                print_scan(db.hscan(&key, cursor, pattern, opts.count));
+                return;
+            }
+            _ => {}
+        }
+    }
+    println!("input error, please check with `help` command!");
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn from_hex(s: &str) -> Option<Vec<u8>> {
+    if !s.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| s.get(i..i + 2).and_then(|b| u8::from_str_radix(b, 16).ok()))
+        .collect()
+}
+
+/// 执行注册的自定义命令, 参见 `plugins`；与内置命令同名的自定义命令不会被执行
+///
+/// 返回值：输入是自定义命令时返回 true
+fn process_custom(db: &mut KVDB, input: &str) -> bool {
+    let args: Vec<String> = input.split_whitespace().map(String::from).collect();
+    match args.first() {
+        Some(name) if !cmd::is_builtin(name) && db.commands().contains(name) => {
+            print!("memkv: ");
+            match db.call(&args) {
+                Ok(reply) => println!("{}", reply),
+                Err(e) => println!("{}", e),
+            }
+            true
+        }
+        _ => false,
+    }
+}
+
+/// 处理只读的命令, 也用于历史视图
+///
+/// 返回值：输入是只读命令时返回 true
+fn process_read(db: &KVDB, words: &[&str]) -> bool {
+    if let Some(&"scan") | Some(&"sscan") | Some(&"hscan") = words.first() {
+        process_scan(db, words);
+        return true;
+    }
+    match (words.first(), words.len()) {
+        (Some(&"size"), 1) => println!("{}", db.size()),
+        (Some(&"randomkey"), 1) => print_option_result(Ok(db.randomkey())),
+        (Some(&"keys"), 2) => println!("{:?}", db.keys(words[1])),
+        (Some(&"hmget"), n) if n > 2 => {
+            let fields: Vec<String> = words[2..].iter().map(|s| String::from(*s)).collect();
+            print_result(db.hmget(&String::from(words[1]), &fields));
+        }
+        (Some(name), 2) => {
+            let key = String::from(words[1]);
+            match *name {
+                "get" => print_option_result(db.get(&key)),
+                "slen" => print_option_result(db.slen(&key)),
+                "smembers" => print_option_result(db.smembers(&key)),
+                "hkeys" => print_option_result(db.hkeys(&key)),
+                "hvalues" => print_option_result(db.hvalues(&key)),
+                "hlen" => print_option_result(db.hlen(&key)),
+                "exists" => println!("{}", db.exists(&key)),
+                "ttl" => println!("{}", db.ttl(&key)),
+                "idletime" => print_option_result(Ok(db.idle_time(&key))),
+                "dump" => print_option_result(Ok(db.dump(&key).map(|p| to_hex(&p)))),
+                "type" => match db.key_type(&key) {
+                    Some(t) => println!("{}", t),
+                    None => println!("none"),
+                },
+                _ => return false,
+            }
+        }
+        (Some(name), 3) => {
+            let (key, arg) = (String::from(words[1]), String::from(words[2]));
+            match *name {
+                "sismember" => print_result(db.sismember(&key, &arg)),
+                "hget" => print_result(db.hget(&key, &arg)),
+                "hexists" => print_result(db.hexists(&key, &arg)),
+                _ => return false,
+            }
+        }
+        _ => return false,
+    }
+    true
+}
+
 fn process(db: &mut KVDB, input: &String) {
     print!("memkv: ");
     // let unknow_operation = "unknown operation!";
     let words: Vec<&str> = input.trim().split_whitespace().collect();
+    if process_read(db, &words) {
+        return;
+    }
+    if let Some(&"touch") | Some(&"unlink") = words.first() {
+        let keys: Vec<String> = words[1..].iter().map(|s| String::from(*s)).collect();
+        if keys.is_empty() {
+            println!("input error, please check with `help` command!");
+        } else if words[0] == "touch" {
+            println!("{}", db.touch(keys));
+        } else {
+            println!("{}", db.unlink(keys));
+        }
+        return;
+    }
     match words.len() {
         0 => {}
-        1 => match words[0] {
-            "size" => {
-                println!("{}", db.size());
-            }
-            _ => {
-                println!("unknown command or missing params!");
-            }
-        },
+        1 => {
+            println!("unknown command or missing params!");
+        }
         2 => match words[0] {
-            "get" => {
-                print_option_result(db.get(&String::from(words[1])));
-            }
             "spop" => {
                 print_option_result(db.spop(&String::from(words[1])));
             }
-            "slen" => {
-                print_option_result(db.slen(&String::from(words[1])));
-            }
-            "smembers" => {
-                print_option_result(db.smembers(&String::from(words[1])));
-            }
-            "hkeys" => {
-                print_option_result(db.hkeys(&String::from(words[1])));
-            }
-            "hvalues" => {
-                print_option_result(db.hvalues(&String::from(words[1])));
-            }
-            "hlen" => {
-                print_option_result(db.hlen(&String::from(words[1])));
-            }
-            "exists" => {
-                println!("{}", db.exists(&String::from(words[1])));
+            "persist" => {
+                println!("{}", db.persist(&String::from(words[1])));
             }
             _ => {
                 println!("unknown command or missing params");
@@ -118,17 +456,25 @@ fn process(db: &mut KVDB, input: &String) {
                         println!("{} is not a number", arg);
                     }
                 },
-                "sismember" => {
-                    print_result(db.sismember(&key, &arg));
+                "hdel" => {
+                    print_result(db.hdel(&key, &arg));
                 }
-                "hget" => {
-                    print_result(db.hget(&key, &arg));
+                "expire" => match u64::from_str_radix(words[2], 10) {
+                    Ok(seconds) => {
+                        print_result(db.expire(&key, seconds));
+                    }
+                    Err(_) => {
+                        println!("{} is not a number", arg);
+                    }
+                },
+                "rename" => {
+                    print_result(db.rename(&key, &arg));
                 }
-                "hexists" => {
-                    print_result(db.hexists(&key, &arg));
+                "renamenx" => {
+                    print_result(db.renamenx(&key, &arg));
                 }
-                "hdel" => {
-                    print_result(db.hdel(&key, &arg));
+                "copy" => {
+                    print_result(db.copy(&key, &arg, false));
                 }
                 _ => {
                     println!("unknow command!");
@@ -207,18 +553,27 @@ fn process(db: &mut KVDB, input: &String) {
                         println!("input error, please check with `help` command!");
                     }
                 }
-                "hmget" => {
-                    if words.len() > 2 {
-                        let fields: Vec<String> = words[2..]
-                            .to_vec()
-                            .iter()
-                            .map(|s| String::from(*s))
-                            .collect();
-                        print_result(db.hmget(&key, &fields));
+                "copy" => {
+                    if words.len() == 4 && words[3] == "replace" {
+                        print_result(db.copy(&key, &String::from(words[2]), true));
                     } else {
                         println!("input error, please check with `help` command!");
                     }
                 }
+                "restore" => {
+                    let ttl = u64::from_str_radix(words[2], 10).ok();
+                    let payload = words.get(3).and_then(|p| from_hex(p));
+                    let replace = words.len() == 5 && words[4] == "replace";
+                    match (ttl, payload) {
+                        (Some(ttl), Some(payload)) if words.len() == 4 || replace => {
+                            let ttl = if ttl == 0 { None } else { Some(ttl) };
+                            print_result(db.restore(&key, ttl, &payload, replace));
+                        }
+                        _ => {
+                            println!("input error, please check with `help` command!");
+                        }
+                    }
+                }
                 "del" => {
                     if words.len() > 1 {
                         let keys: Vec<String> = words[1..]
@@ -230,15 +585,716 @@ fn process(db: &mut KVDB, input: &String) {
                     }
                 }
                 _ => {
-                    println!("unknow command or missing params", );
+                    println!("unknow command or missing params",);
                 }
             }
         }
     }
 }
 
+/// 当前时间的毫秒时间戳
+fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+/// 解析带单位的时长, 例如 500ms、90s、15m、1h、2d
+///
+/// 返回值：毫秒数, 格式不正确时返回 None
+fn parse_duration(s: &str) -> Option<u64> {
+    let split = s.find(|c: char| !c.is_ascii_digit())?;
+    let number: u64 = s[..split].parse().ok()?;
+    let unit = match &s[split..] {
+        "ms" => 1,
+        "s" => 1000,
+        "m" => 60 * 1000,
+        "h" => 60 * 60 * 1000,
+        "d" => 24 * 60 * 60 * 1000,
+        _ => return None,
+    };
+    number.checked_mul(unit)
+}
+
+/// 时间点恢复的目标：lsn / time 毫秒时间戳 / ago 时长
+fn parse_restore_point(kind: &str, arg: &str) -> std::result::Result<RestorePoint, String> {
+    let number = || {
+        u64::from_str_radix(arg, 10).map_err(|_| DBError::NotANumber(String::from(arg)).to_string())
+    };
+    match kind {
+        "lsn" => number().map(RestorePoint::Lsn),
+        "time" => number().map(RestorePoint::Time),
+        "ago" => match parse_duration(arg) {
+            Some(ms) => Ok(RestorePoint::Time(now_millis().saturating_sub(ms))),
+            None => Err(format!(
+                "invalid duration `{}`, expect e.g. 90s, 15m, 1h",
+                arg
+            )),
+        },
+        _ => Err(String::from(
+            "input error, please check with `help` command!",
+        )),
+    }
+}
+
+/// 处理历史视图的命令：
+///     * history lsn|time|ago <参数> 打开（替换）只读的历史视图
+///     * history close 关闭历史视图
+///     * history <只读命令> 在历史视图中当前选择的数据库上执行只读命令
+///
+/// 返回值：输入是 history 命令时返回 true
+fn process_history(
+    dbs: &mut Databases,
+    view: &mut Option<HistoryView>,
+    current: usize,
+    input: &String,
+) -> bool {
+    let words: Vec<&str> = input.trim().split_whitespace().collect();
+    if words.first() != Some(&"history") {
+        return false;
+    }
+    print!("memkv: ");
+    match (words.get(1), words.len()) {
+        (Some(&"lsn"), 3) | (Some(&"time"), 3) | (Some(&"ago"), 3) => {
+            match parse_restore_point(words[1], words[2]) {
+                Ok(target) => match dbs.history(target) {
+                    Ok(opened) => {
+                        let point = opened.point();
+                        println!(
+                            "opened read-only view at LSN {} (time {} ms, {} records replayed after LSN {})",
+                            point.lsn, point.timestamp, point.records, point.base_lsn
+                        );
+                        *view = Some(opened);
+                    }
+                    Err(e) => println!("{}", e),
+                },
+                Err(e) => println!("{}", e),
+            }
+        }
+        (Some(&"close"), 2) => {
+            *view = None;
+            println!("Ok");
+        }
+        (None, _) => match view {
+            Some(view) => println!(
+                "viewing LSN {} (time {} ms)",
+                view.point().lsn,
+                view.point().timestamp
+            ),
+            None => println!("no history view"),
+        },
+        (Some(_), _) => match view.as_ref().map(|view| view.db(current)) {
+            Some(Ok(db)) => {
+                if !process_read(db, &words[1..]) {
+                    println!("history view only supports read-only commands");
+                }
+            }
+            Some(Err(e)) => println!("{}", e),
+            None => println!("no history view, open one with `history lsn|time|ago`"),
+        },
+    }
+    true
+}
+
+/// 按照参数导出（export 为 true）或导入数据
+///
+/// 返回值：导出或导入的 key 的数量, 失败时返回原因
+fn transfer(
+    dbs: &mut Databases,
+    export: bool,
+    opts: &TransferOpts,
+) -> std::result::Result<usize, String> {
+    let format = ExportFormat::from_name(&opts.format)
+        .ok_or_else(|| format!("unknown format `{}`, expect jsonl or csv", opts.format))?;
+    let value_type = match opts.value_type.as_deref() {
+        Some(name) => {
+            Some(ValueType::from_name(name).ok_or_else(|| format!("unknown type `{}`", name))?)
+        }
+        None => None,
+    };
+    let pattern = opts.pattern.as_deref();
+    let open_error = |e: io::Error| format!("{}: {}", opts.file, e);
+    let result = match (export, opts.file.as_str()) {
+        (true, "-") => dbs.export(&mut io::stdout().lock(), format, pattern, value_type),
+        (true, path) => {
+            let mut out = BufWriter::new(File::create(path).map_err(open_error)?);
+            dbs.export(&mut out, format, pattern, value_type)
+        }
+        (false, "-") => dbs.import(&mut io::stdin().lock(), format, pattern, value_type),
+        (false, path) => {
+            let mut input = BufReader::new(File::open(path).map_err(open_error)?);
+            dbs.import(&mut input, format, pattern, value_type)
+        }
+    };
+    result.map_err(|e| e.to_string())
+}
+
+/// 处理数据库级别的命令： select / swapdb / move / flushdb / flushall / info / save / bgsave / lastsave / bgrewriteaof / checkpoint / importrdb / export / import
+///
+/// 返回值：输入是数据库级别的命令时返回 true
+fn process_databases(
+    dbs: &mut Databases,
+    current: &mut usize,
+    dbfile: Option<&str>,
+    input: &String,
+) -> bool {
+    let words: Vec<&str> = input.trim().split_whitespace().collect();
+    let parse_index =
+        |s: &str| usize::from_str_radix(s, 10).map_err(|_| DBError::NotANumber(String::from(s)));
+    match (words.first(), words.len()) {
+        (Some(&"select"), 2) => {
+            print!("memkv: ");
+            match parse_index(words[1]).and_then(|index| dbs.db(index).map(|_| index)) {
+                Ok(index) => {
+                    *current = index;
+                    println!("Ok");
+                }
+                Err(e) => println!("{}", e),
+            }
+        }
+        (Some(&"swapdb"), 3) => {
+            print!("memkv: ");
+            match (parse_index(words[1]), parse_index(words[2])) {
+                (Ok(index1), Ok(index2)) => print_result(dbs.swapdb(index1, index2)),
+                (Err(e), _) | (_, Err(e)) => println!("{}", e),
+            }
+        }
+        (Some(&"move"), 3) => {
+            print!("memkv: ");
+            match parse_index(words[2]) {
+                Ok(to) => print_result(dbs.move_key(&String::from(words[1]), *current, to)),
+                Err(e) => println!("{}", e),
+            }
+        }
+        (Some(&"flushdb"), 1) | (Some(&"flushdb"), 2) => {
+            print!("memkv: ");
+            match words.get(1) {
+                None => print_result(dbs.flushdb(*current, false)),
+                Some(&"async") => print_result(dbs.flushdb(*current, true)),
+                Some(_) => println!("input error, please check with `help` command!"),
+            }
+        }
+        (Some(&"info"), 1) | (Some(&"info"), 2) => {
+            print!("memkv: ");
+            let section = words.get(1).copied();
+            match dbs.info().render(section) {
+                ref text if text.is_empty() => println!("unknown info section!"),
+                text => println!("\n{}", text.trim_end()),
+            }
+        }
+        (Some(&"flushall"), 1) | (Some(&"flushall"), 2) => {
+            print!("memkv: ");
+            match words.get(1) {
+                None => {
+                    dbs.flushall(false);
+                    println!("Ok");
+                }
+                Some(&"async") => {
+                    dbs.flushall(true);
+                    println!("Ok");
+                }
+                Some(_) => println!("input error, please check with `help` command!"),
+            }
+        }
+        (Some(&"save"), 1) | (Some(&"bgsave"), 1) => {
+            print!("memkv: ");
+            match dbfile {
+                Some(path) if words[0] == "save" => print_result(dbs.save(path)),
+                Some(path) => match dbs.bgsave(path) {
+                    Ok(_) => println!("Background saving started"),
+                    Err(e) => println!("{}", e),
+                },
+                None => println!("no dbfile configured, please start memkv with --dbfile"),
+            }
+        }
+        (Some(&"lastsave"), 1) => {
+            print!("memkv: ");
+            println!("{}", dbs.last_save());
+        }
+        (Some(&"bgrewriteaof"), 1) => {
+            print!("memkv: ");
+            match dbs.bgrewriteaof() {
+                Ok(_) => println!("Background append only file rewriting started"),
+                Err(e) => println!("{}", e),
+            }
+        }
+        (Some(&"checkpoint"), 1) => {
+            print!("memkv: ");
+            match dbs.checkpoint() {
+                Ok(lsn) => println!("checkpoint at LSN {}", lsn),
+                Err(e) => println!("{}", e),
+            }
+        }
+        (Some(&"export"), 3..=7) | (Some(&"import"), 3..=7) => {
+            print!("memkv: ");
+            let mut opts = TransferOpts {
+                format: String::from(words[1]),
+                pattern: None,
+                value_type: None,
+                file: String::from(words[2]),
+            };
+            for pair in words[3..].chunks(2) {
+                match pair {
+                    ["match", pattern] => opts.pattern = Some(String::from(*pattern)),
+                    ["type", name] => opts.value_type = Some(String::from(*name)),
+                    _ => {
+                        println!("input error, please check with `help` command!");
+                        return true;
+                    }
+                }
+            }
+            let export = words[0] == "export";
+            match transfer(dbs, export, &opts) {
+                Ok(n) if export => println!("exported {} keys to {}", n, opts.file),
+                Ok(n) => println!("imported {} keys from {}", n, opts.file),
+                Err(e) => println!("{}", e),
+            }
+        }
+        (Some(&"importrdb"), 2) => {
+            print!("memkv: ");
+            match dbs.import_rdb(words[1]) {
+                Ok(report) => {
+                    println!(
+                        "imported {} keys from RDB version {} ({} expired, {} skipped)",
+                        report.imported,
+                        report.version,
+                        report.expired,
+                        report.skipped.len()
+                    );
+                    for skipped in report.skipped {
+                        println!(
+                            "  skipped db{} `{}`: {}",
+                            skipped.db, skipped.key, skipped.reason
+                        );
+                    }
+                }
+                Err(e) => println!("{}", e),
+            }
+        }
+        _ => return false,
+    }
+    true
+}
+
+/// 读取 --encryption-key-file 或环境变量中的密钥, 都没有指定时返回 None
+fn encryption_key(opts: &BootstrapOpts) -> Result<Option<EncryptionKey>> {
+    match opts.encryption_key_file.as_deref() {
+        Some(path) => EncryptionKey::from_file(path).map(Some),
+        None => EncryptionKey::from_env(ENCRYPTION_KEY_ENV),
+    }
+}
+
+/// 按照启动参数创建数据库并恢复数据：开启 AOF 时重放 AOF 文件, 开启 WAL 时加载检查点快照并重放日志,
+/// 否则加载存在的快照文件
+/// 交互模式下信息输出到标准输出, 否则输出到标准错误, 以免与导出到标准输出的数据混在一起
+///
+/// 返回值：失败时输出原因并返回 None
+fn open_databases(opts: &BootstrapOpts, interactive: bool) -> Option<Databases> {
+    let report = |message: String| {
+        if interactive {
+            println!("{}", message);
+        } else {
+            eprintln!("{}", message);
+        }
+    };
+    let fsync = match FsyncPolicy::from_name(&opts.appendfsync) {
+        Some(policy) => policy,
+        None => {
+            report(String::from(
+                "invalid --appendfsync option, expect always, everysec or no",
+            ));
+            return None;
+        }
+    };
+    let eviction = match EvictionPolicy::from_name(&opts.maxkeys_policy) {
+        Some(policy) => policy,
+        None => {
+            report(String::from(
+                "invalid --maxkeys-policy option, expect noeviction, allkeys-lru or allkeys-random",
+            ));
+            return None;
+        }
+    };
+    let mut dbs = Databases::new(opts.databases.max(1), Some(opts.keys));
+    dbs.set_eviction_policy(eviction);
+    if let Err(e) = plugins::register(&mut dbs) {
+        report(format!("failed to register plugins: {}", e));
+        return None;
+    }
+    if opts.compress_threshold > 0 {
+        dbs.set_compression_threshold(Some(opts.compress_threshold));
+    }
+    if let Err(e) = encryption_key(opts).and_then(|key| dbs.set_encryption_key(key)) {
+        report(format!("failed to read the encryption key: {}", e));
+        return None;
+    }
+    if let Some(path) = opts.wal.as_deref() {
+        let snapshot = match (opts.dbfile.as_deref(), opts.aof.is_some()) {
+            (Some(snapshot), false) => snapshot,
+            (None, _) => {
+                report(String::from("--wal needs --dbfile for checkpoints"));
+                return None;
+            }
+            (_, true) => {
+                report(String::from("--wal can not be used together with --aof"));
+                return None;
+            }
+        };
+        let opened = dbs
+            .open_wal(path, snapshot)
+            .and_then(|recovery| match &opts.wal_archive {
+                Some(dir) => dbs.set_wal_archive(dir).map(|_| recovery),
+                None => Ok(recovery),
+            });
+        match opened {
+            Ok(recovery) => {
+                report(format!(
+                    "replayed {} records from {} after checkpoint LSN {}",
+                    recovery.records, path, recovery.checkpoint_lsn
+                ));
+                if recovery.truncated > 0 {
+                    report(format!(
+                        "!!! truncated {} bytes of incomplete or corrupted records at the end of {}",
+                        recovery.truncated, path
+                    ));
+                }
+            }
+            Err(e) => {
+                report(format!("failed to recover {}: {}", path, e));
+                return None;
+            }
+        }
+    } else if let Some(path) = opts.aof.as_deref() {
+        match dbs.open_aof(path, fsync) {
+            Ok(replay) => {
+                report(format!(
+                    "replayed {} commands from {}",
+                    replay.commands, path
+                ));
+                if replay.truncated > 0 {
+                    report(format!(
+                        "!!! truncated {} bytes of incomplete command at the end of {}",
+                        replay.truncated, path
+                    ));
+                }
+            }
+            Err(e) => {
+                report(format!("failed to load {}: {}", path, e));
+                return None;
+            }
+        }
+    } else if let Some(path) = opts.dbfile.as_deref().filter(|p| Path::new(p).exists()) {
+        match dbs.load(path) {
+            Ok(keys) => report(format!("loaded {} keys from {}", keys, path)),
+            Err(e) => {
+                report(format!("failed to load {}: {}", path, e));
+                return None;
+            }
+        }
+    }
+    Some(dbs)
+}
+
+/// 执行非交互模式的子命令
+///
+/// 返回值：进程的退出码
+fn run_command(opts: &BootstrapOpts, command: &Command) -> i32 {
+    let (export, transfer_opts) = match command {
+        Command::Export(transfer_opts) => (true, transfer_opts),
+        Command::Import(transfer_opts) => (false, transfer_opts),
+        Command::Restore(restore_opts) => return restore(opts, restore_opts),
+        Command::RotateKey(rotate_opts) => return rotate_key(opts, rotate_opts),
+        Command::Serve(serve_opts) => return serve(opts, serve_opts),
+    };
+    if !export && opts.aof.is_none() && opts.wal.is_none() && opts.dbfile.is_none() {
+        eprintln!("import needs --dbfile or --aof to keep the imported data");
+        return 1;
+    }
+    let mut dbs = match open_databases(opts, false) {
+        Some(dbs) => dbs,
+        None => return 1,
+    };
+    let count = match transfer(&mut dbs, export, transfer_opts) {
+        Ok(count) => count,
+        Err(e) => {
+            eprintln!("{}", e);
+            return 1;
+        }
+    };
+    if export {
+        eprintln!("exported {} keys", count);
+        return 0;
+    }
+    eprintln!("imported {} keys", count);
+    let persisted = match opts.dbfile.as_deref() {
+        _ if dbs.wal().is_some() => dbs.commit_wal(),
+        Some(path) if !dbs.aof_enabled() => dbs.save(path),
+        _ => dbs.flush_aof(),
+    };
+    if let Err(e) = persisted {
+        eprintln!("failed to save imported data: {}", e);
+        return 1;
+    }
+    0
+}
+
+/// 服务模式：恢复数据之后监听 TCP 端口, 直到收到关闭请求
+///
+/// 返回值：进程的退出码
+fn serve(opts: &BootstrapOpts, serve_opts: &ServeOpts) -> i32 {
+    let save_rules = match SaveRule::parse_rules(&opts.save) {
+        Ok(rules) => rules,
+        Err(e) => {
+            eprintln!("invalid --save option: {}", e);
+            return 1;
+        }
+    };
+    let notify_keyspace_events = match EventClass::parse(&serve_opts.notify_keyspace_events) {
+        Ok(classes) => classes,
+        Err(e) => {
+            eprintln!("invalid --notify-keyspace-events option: {}", e);
+            return 1;
+        }
+    };
+    let mut dbs = match open_databases(opts, false) {
+        Some(dbs) => dbs,
+        None => return 1,
+    };
+    dbs.pubsub().set_subscriber_buffer(serve_opts.pubsub_buffer);
+    dbs.set_notify_keyspace_events(notify_keyspace_events);
+    let config = ServerConfig {
+        dbfile: opts.dbfile.as_ref().map(PathBuf::from),
+        save_rules,
+        wal_checkpoint_size: opts.wal_checkpoint_size,
+        script_time_limit: Some(serve_opts.script_time_limit)
+            .filter(|&limit| limit > 0)
+            .map(Duration::from_millis),
+        query_buffer_limit: serve_opts.client_query_buffer_limit,
+    };
+    let addr = (serve_opts.bind.as_str(), serve_opts.port);
+    let mut server = match Server::bind(addr, dbs, config) {
+        Ok(server) => server,
+        Err(e) => {
+            eprintln!("{}", e);
+            return 1;
+        }
+    };
+    if let Some(port) = serve_opts.http_port {
+        match server.listen_http((serve_opts.bind.as_str(), port)) {
+            Ok(addr) => eprintln!("HTTP gateway is listening on {}", addr),
+            Err(e) => {
+                eprintln!("{}", e);
+                return 1;
+            }
+        }
+    }
+    if let Some(port) = serve_opts.memcached_port {
+        match server.listen_memcached((serve_opts.bind.as_str(), port)) {
+            Ok(addr) => eprintln!("memcached protocol is listening on {}", addr),
+            Err(e) => {
+                eprintln!("{}", e);
+                return 1;
+            }
+        }
+    }
+    match (&serve_opts.tls_cert, &serve_opts.tls_key) {
+        (Some(cert), Some(key)) => {
+            let config = TlsConfig {
+                client_ca: serve_opts.tls_client_ca.as_ref().map(PathBuf::from),
+                ..TlsConfig::new(cert, key)
+            };
+            if let Err(e) = server.enable_tls(config) {
+                eprintln!("{}", e);
+                return 1;
+            }
+            eprintln!("TLS is enabled");
+        }
+        (None, None) if serve_opts.tls_client_ca.is_none() => {}
+        _ => {
+            eprintln!("TLS needs both --tls-cert and --tls-key");
+            return 1;
+        }
+    }
+    if let Some(path) = &serve_opts.unix_socket {
+        let config = match unix_socket_config(path, serve_opts) {
+            Ok(config) => config,
+            Err(e) => {
+                eprintln!("{}", e);
+                return 1;
+            }
+        };
+        match server.listen_unix(config) {
+            Ok(()) => eprintln!("memkv is listening on the Unix socket {}", path),
+            Err(e) => {
+                eprintln!("{}", e);
+                return 1;
+            }
+        }
+    }
+    if let Err(e) = server.shutdown_handle().shutdown_on_signals() {
+        eprintln!("{}", e);
+        return 1;
+    }
+    if let Ok(addr) = server.local_addr() {
+        eprintln!("memkv is ready to accept connections on {}", addr);
+    }
+    match server.run() {
+        Ok(_) => {
+            eprintln!("memkv is now ready to exit, bye bye...");
+            0
+        }
+        Err(e) => {
+            eprintln!("failed to save data before exiting: {}", e);
+            1
+        }
+    }
+}
+
+/// 根据 --unix-socket-mode 与 --unix-socket-allow 生成 Unix socket 的配置
+///
+/// 返回值：
+///     * Unix socket 的配置
+///     * 权限不是八进制数或者规则格式不正确， 返回 Syntax
+fn unix_socket_config(path: &str, serve_opts: &ServeOpts) -> Result<UnixSocketConfig> {
+    let mut config = UnixSocketConfig::new(path);
+    if let Some(mode) = &serve_opts.unix_socket_mode {
+        let mode = u32::from_str_radix(mode, 8)
+            .ok()
+            .filter(|mode| *mode <= 0o777)
+            .ok_or_else(|| DBError::Syntax(format!("invalid --unix-socket-mode `{}`", mode)))?;
+        config.mode = Some(mode);
+    }
+    if let Some(rules) = &serve_opts.unix_socket_allow {
+        config.access = rules
+            .split_whitespace()
+            .map(|rule| rule.parse::<AccessRule>())
+            .collect::<Result<Vec<AccessRule>>>()?;
+    }
+    Ok(config)
+}
+
+/// 时间点恢复：只读取日志、快照与归档, 不会修改它们, 可以在数据库运行期间执行
+///
+/// 返回值：进程的退出码
+fn restore(opts: &BootstrapOpts, restore_opts: &RestoreOpts) -> i32 {
+    let (wal, dbfile) = match (opts.wal.as_deref(), opts.dbfile.as_deref()) {
+        (Some(wal), Some(dbfile)) => (wal, dbfile),
+        _ => {
+            eprintln!("restore needs --wal and --dbfile");
+            return 1;
+        }
+    };
+    if restore_opts.output == dbfile {
+        eprintln!("restore output must not overwrite --dbfile");
+        return 1;
+    }
+    let target = match (
+        restore_opts.lsn,
+        restore_opts.time,
+        restore_opts.ago.as_deref(),
+    ) {
+        (Some(lsn), None, None) => Ok(RestorePoint::Lsn(lsn)),
+        (None, Some(time), None) => Ok(RestorePoint::Time(time)),
+        (None, None, Some(ago)) => parse_restore_point("ago", ago),
+        _ => Err(String::from(
+            "restore needs exactly one of --lsn, --time and --ago",
+        )),
+    };
+    let mut history = History::new(wal, dbfile);
+    if let Some(dir) = opts.wal_archive.as_deref() {
+        history = history.with_archive(dir);
+    }
+    match encryption_key(opts) {
+        Ok(Some(key)) => history = history.with_encryption_key(key),
+        Ok(None) => {}
+        Err(e) => {
+            eprintln!("failed to read the encryption key: {}", e);
+            return 1;
+        }
+    }
+    let restored = target.and_then(|target| {
+        let view = history
+            .restore(target, opts.databases.max(1))
+            .map_err(|e| e.to_string())?;
+        let point = view.point();
+        view.into_databases()
+            .save(&restore_opts.output)
+            .map(|_| point)
+            .map_err(|e| e.to_string())
+    });
+    match restored {
+        Ok(point) => {
+            eprintln!(
+                "restored LSN {} (time {} ms, {} records replayed after LSN {}) to {}",
+                point.lsn, point.timestamp, point.records, point.base_lsn, restore_opts.output
+            );
+            0
+        }
+        Err(e) => {
+            eprintln!("{}", e);
+            1
+        }
+    }
+}
+
+/// 轮换密钥：使用当前的密钥恢复数据, 再用新的密钥重写所有文件；只使用快照时重新保存 --dbfile
+///
+/// 返回值：进程的退出码
+fn rotate_key(opts: &BootstrapOpts, rotate_opts: &RotateKeyOpts) -> i32 {
+    let key = match (rotate_opts.new_key_file.as_deref(), rotate_opts.decrypt) {
+        (Some(path), false) => EncryptionKey::from_file(path).map(Some),
+        (None, false) => EncryptionKey::from_env(NEW_ENCRYPTION_KEY_ENV).and_then(|key| {
+            key.map(Some).ok_or_else(|| {
+                DBError::Encryption(String::from(
+                    "rotate-key needs --new-key-file, MEMKV_NEW_ENCRYPTION_KEY or --decrypt",
+                ))
+            })
+        }),
+        (None, true) => Ok(None),
+        (Some(_), true) => Err(DBError::Encryption(String::from(
+            "--new-key-file can not be used together with --decrypt",
+        ))),
+    };
+    let key = match key {
+        Ok(key) => key,
+        Err(e) => {
+            eprintln!("{}", e);
+            return 1;
+        }
+    };
+    let mut dbs = match open_databases(opts, false) {
+        Some(dbs) => dbs,
+        None => return 1,
+    };
+    let fingerprint = key.as_ref().map(|key| key.fingerprint());
+    let rotated = dbs
+        .rotate_encryption_key(key)
+        .and_then(|ok| match opts.dbfile.as_deref() {
+            Some(path) if dbs.wal().is_none() => dbs.save(path),
+            _ => Ok(ok),
+        });
+    match (rotated, fingerprint) {
+        (Ok(_), Some(fingerprint)) => {
+            eprintln!("re-encrypted all files with key {}", fingerprint);
+            0
+        }
+        (Ok(_), None) => {
+            eprintln!("decrypted all files");
+            0
+        }
+        (Err(e), _) => {
+            eprintln!("failed to rotate the encryption key: {}", e);
+            1
+        }
+    }
+}
+
 fn main() {
     let bootstrap_opts: BootstrapOpts = BootstrapOpts::parse();
+    if let Some(command) = &bootstrap_opts.command {
+        std::process::exit(run_command(&bootstrap_opts, command));
+    }
     println!("#    # #    # #    #           ");
     println!("##  ## #   #  #    #           Welcome to use memkv!");
     println!("# ## # ####   #    #           ");
@@ -251,14 +1307,65 @@ fn main() {
         "#    # #    #   ##                * verbose   = {}",
         bootstrap_opts.verbose
     );
+    println!(
+        "                                  * databases = {}",
+        bootstrap_opts.databases
+    );
+    println!(
+        "                                  * dbfile    = {}",
+        bootstrap_opts.dbfile.as_deref().unwrap_or("(none)")
+    );
+    println!(
+        "                                  * aof       = {}",
+        bootstrap_opts.aof.as_deref().unwrap_or("(none)")
+    );
+    println!(
+        "                                  * wal       = {}",
+        bootstrap_opts.wal.as_deref().unwrap_or("(none)")
+    );
+    println!(
+        "                                  * encrypted = {}",
+        if bootstrap_opts.encryption_key_file.is_some()
+            || std::env::var_os(ENCRYPTION_KEY_ENV).is_some()
+        {
+            "yes"
+        } else {
+            "no"
+        }
+    );
+    println!(
+        "                                  * compress  = {}",
+        match bootstrap_opts.compress_threshold {
+            0 => String::from("off"),
+            threshold => format!("> {} bytes", threshold),
+        }
+    );
     println!("\n\n\nfor more help information, please input \"help\"\n");
 
-    let mut db: KVDB = KVDB::new(Some(bootstrap_opts.keys));
-    let mut rl = cmd::cmd_repl();
+    let save_rules = match SaveRule::parse_rules(&bootstrap_opts.save) {
+        Ok(rules) => rules,
+        Err(e) => {
+            println!("invalid --save option: {}", e);
+            return;
+        }
+    };
+    let dbfile = bootstrap_opts.dbfile.as_deref();
+    let mut dbs = match open_databases(&bootstrap_opts, true) {
+        Some(dbs) => dbs,
+        None => return,
+    };
+    let mut current: usize = 0;
+    let mut view: Option<HistoryView> = None;
+    let mut rl = cmd::cmd_repl(dbs.commands().names());
 
     loop {
-        match rl.readline("> "){
-            Ok(input)=> {
+        let prompt = if current == 0 {
+            String::from("> ")
+        } else {
+            format!("[{}]> ", current)
+        };
+        match rl.readline(&prompt) {
+            Ok(input) => {
                 rl.add_history_entry(input.clone());
                 match input.as_str() {
                     "help" => {
@@ -266,22 +1373,120 @@ fn main() {
                         helper.print_help();
                     }
                     _ => {
-                        process(&mut db, &input);
+                        let start = Instant::now();
+                        if !process_history(&mut dbs, &mut view, current, &input)
+                            && !process_databases(&mut dbs, &mut current, dbfile, &input)
+                            && !process_custom(dbs.db_mut(current).unwrap(), &input)
+                        {
+                            process(dbs.db_mut(current).unwrap(), &input);
+                        }
+                        if let Err(e) = dbs.flush_aof() {
+                            println!("failed to write AOF: {}", e);
+                        }
+                        if let Err(e) = dbs.commit_wal() {
+                            println!("failed to write WAL: {}", e);
+                        }
+                        if let Some(name) = input.split_whitespace().next() {
+                            dbs.record_command(name, start.elapsed());
+                        }
+                        if let Some(Err(e)) = dbs.poll_aof_rewrite() {
+                            println!("background AOF rewriting failed: {}", e);
+                        }
+                        if let Some(Err(e)) = dbs.poll_bgsave() {
+                            println!("background saving failed: {}", e);
+                        }
+                        let checkpoint_size = bootstrap_opts.wal_checkpoint_size;
+                        if dbs
+                            .wal()
+                            .is_some_and(|wal| checkpoint_size > 0 && wal.size() > checkpoint_size)
+                        {
+                            if let Err(e) = dbs.checkpoint() {
+                                println!("WAL checkpoint failed: {}", e);
+                            }
+                        }
+                        if let Some(path) = dbfile {
+                            if dbs.should_save(&save_rules) {
+                                let _ = dbs.bgsave(path);
+                            }
+                        }
                     }
                 }
-            },
+            }
             Err(ReadlineError::Interrupted) => {
                 println!("CTRL-C");
-                break
-            },
+                break;
+            }
             Err(ReadlineError::Eof) => {
                 println!("CTRL-D");
-                break
-            },
+                break;
+            }
             Err(err) => {
                 println!("Error: {:?}", err);
-                break
+                break;
             }
         }
     }
+
+    // 退出前等待后台保存与 AOF 重写结束, 配置了自动保存规则时保存最新的数据
+    if let Some(Err(e)) = dbs.wait_bgsave() {
+        println!("background saving failed: {}", e);
+    }
+    if let Some(Err(e)) = dbs.wait_aof_rewrite() {
+        println!("background AOF rewriting failed: {}", e);
+    }
+    if let Some(path) = dbfile {
+        if !save_rules.is_empty() && dbs.changes_since_save() > 0 {
+            match dbs.save(path) {
+                Ok(_) => println!("saved to {}", path),
+                Err(e) => println!("failed to save {}: {}", path, e),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dbcore::{Command, CommandFlags, CommandReply, DBOk, KeySpec};
+    use std::sync::Arc;
+
+    /// MARK key：把 key 的值设置为 "1"
+    struct Mark;
+
+    impl Command for Mark {
+        fn name(&self) -> &str {
+            "mark"
+        }
+
+        fn arity(&self) -> i32 {
+            2
+        }
+
+        fn flags(&self) -> CommandFlags {
+            CommandFlags::WRITE
+        }
+
+        fn key_spec(&self) -> KeySpec {
+            KeySpec::FIRST
+        }
+
+        fn execute(&self, db: &mut KVDB, args: &[String]) -> Result<CommandReply> {
+            db.sets(&args[1], String::from("1"))?;
+            Ok(CommandReply::from(DBOk::Ok))
+        }
+    }
+
+    #[test]
+    fn registered_commands_are_dispatched() {
+        let mut dbs = Databases::new(1, None);
+        plugins::register(&mut dbs).unwrap();
+        dbs.register_command(Arc::new(Mark)).unwrap();
+        let db = dbs.db_mut(0).unwrap();
+
+        assert!(process_custom(db, "MARK key"));
+        assert_eq!(Ok(Some(String::from("1"))), db.get(&String::from("key")));
+        // 内置命令与未注册的命令不由 process_custom 处理
+        assert!(!process_custom(db, "get key"));
+        assert!(!process_custom(db, "unmark key"));
+    }
 }
@@ -0,0 +1,16 @@
+//! 编译进 memkv 的插件：在这里注册的自定义命令（`dbcore::Command`）与自定义 value 类型
+//! （`dbcore::CustomValue`）在 REPL 与 `serve` 中都可以使用。
+//!
+//! `register()` 在打开数据库之后、恢复快照、AOF 或 WAL 之前调用, 保存了自定义类型的数据可以正常加载。
+
+use dbcore::{Databases, Result};
+
+///
+/// 注册所有插件, 例如 `dbs.register_command(Arc::new(MyCommand))?` 与 `dbcore::register_type::<MyValue>()?`
+///
+/// 返回值：
+///     * 注册成功返回 ()
+///     * 注册失败（例如同名的命令或类型）， 返回对应的错误, memkv 不会启动
+pub fn register(_dbs: &mut Databases) -> Result<()> {
+    Ok(())
+}